@@ -0,0 +1,48 @@
+/* C API of the agent runtime, built as libagent_ffi from the agent-ffi crate.
+ *
+ * Strings passed in are NUL terminated UTF-8 and stay owned by the caller. Strings returned by
+ * agent_poll_event and agent_result are owned by the caller and must be released with
+ * agent_string_free. A handle must only be used by one thread at a time.
+ */
+#ifndef AGENT_FFI_H
+#define AGENT_FFI_H
+
+#ifdef __cplusplus
+extern "C" {
+#endif
+
+typedef struct AgentHandle AgentHandle;
+
+/* Creates an agent from a JSON config such as
+ *   {"model": "gpt-4.1", "provider": "openai", "system_prompt": "...", "tools": ["calc", "web_fetch"]}
+ * or, to run the research orchestrator with its sub-agents,
+ *   {"model": "gpt-4.1", "research": true, "log_dir": "./agent_logs"}
+ * Returns NULL on error, agent_last_error describes it. */
+AgentHandle *agent_create(const char *config_json);
+
+/* Starts the task in the background and returns 0, or -1 if a task is already running. */
+int agent_run(AgentHandle *agent, const char *task);
+
+/* Returns the next event of the running task as a JSON object, or NULL if there is none yet. */
+char *agent_poll_event(AgentHandle *agent);
+
+/* Returns NULL while the task is running, then {"ok": true, "result": "..."} or
+ * {"ok": false, "error": "..."}. */
+char *agent_result(AgentHandle *agent);
+
+/* Cancels the running task, agent_result then reports it as cancelled. */
+void agent_cancel(AgentHandle *agent);
+
+/* Cancels the running task and releases the agent. */
+void agent_free(AgentHandle *agent);
+
+void agent_string_free(char *s);
+
+/* The error of the last call on this thread that failed, valid until the next failing call. */
+const char *agent_last_error(void);
+
+#ifdef __cplusplus
+}
+#endif
+
+#endif
@@ -0,0 +1,498 @@
+use agent::llm::{self, Message, OpenAICompatible};
+use agent::{AgentBuilder, Error, FinalAnswer, Result, callbacks, tools};
+use research_core::{Config, OrchestratorBuilder};
+use std::cell::RefCell;
+use std::collections::VecDeque;
+use std::ffi::{CStr, CString, c_char, c_int};
+use std::io::{Read, Seek, SeekFrom};
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use tokio_util::sync::CancellationToken;
+
+#[derive(serde::Deserialize)]
+struct AgentConfig {
+    model: String,
+    #[serde(default = "default_provider")]
+    provider: String,
+    #[serde(default)]
+    system_prompt: Option<String>,
+    #[serde(default = "default_name")]
+    name: String,
+    // built-in tools of a single agent: calc, memory, outline, and web_fetch
+    #[serde(default)]
+    tools: Vec<String>,
+    // run the research orchestrator with its sub-agents instead of a single agent
+    #[serde(default)]
+    research: bool,
+    #[serde(default = "default_log_dir")]
+    log_dir: PathBuf,
+    #[serde(default = "default_language")]
+    language: String,
+    #[serde(default)]
+    verify: bool,
+}
+
+fn default_provider() -> String {
+    "openai".to_string()
+}
+
+fn default_name() -> String {
+    "agent".to_string()
+}
+
+fn default_log_dir() -> PathBuf {
+    PathBuf::from("./agent_logs")
+}
+
+fn default_language() -> String {
+    "en".to_string()
+}
+
+fn provider(provider: &str, model: &str) -> Result<Arc<dyn llm::LLM + Send + Sync>> {
+    let model = model.to_string();
+    let builder = match provider {
+        "openai" => OpenAICompatible::openai(model),
+        "xai" => OpenAICompatible::xai(model),
+        "mistral" => OpenAICompatible::mistral(model),
+        "deepseek" => OpenAICompatible::deepseek(model),
+        "groq" => OpenAICompatible::groq(model),
+        _ => {
+            return Err(Error::Unsupported(format!(
+                "unknown provider {}, expected openai, xai, mistral, deepseek, or groq",
+                provider
+            )));
+        }
+    };
+    Ok(builder.build())
+}
+
+type Events = Arc<Mutex<VecDeque<String>>>;
+
+// receives the json lines of an EventLogger and queues them for agent_poll_event
+struct EventQueue {
+    events: Events,
+    line: Vec<u8>,
+}
+
+impl std::io::Write for EventQueue {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        for &byte in buf {
+            if byte == b'\n' {
+                let line = String::from_utf8_lossy(&self.line).into_owned();
+                self.events.lock().unwrap().push_back(line);
+                self.line.clear();
+            } else {
+                self.line.push(byte);
+            }
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+pub struct AgentHandle {
+    config: AgentConfig,
+    llm: Arc<dyn llm::LLM + Send + Sync>,
+    runtime: tokio::runtime::Runtime,
+    events: Events,
+    // how far the orchestrator events of a research run have been read, and the first line of
+    // the file they were read from, which tells when the file was rotated
+    events_read: u64,
+    events_head: Vec<u8>,
+    result: Arc<Mutex<Option<Result<String>>>>,
+    task: Option<tokio::task::JoinHandle<()>>,
+    cancel: CancellationToken,
+}
+
+impl AgentHandle {
+    fn new(config: &str) -> Result<Self> {
+        let config: AgentConfig = serde_json::from_str(config)?;
+        if config.research {
+            std::fs::create_dir_all(&config.log_dir)?;
+        }
+        Ok(Self {
+            llm: provider(&config.provider, &config.model)?,
+            config,
+            runtime: tokio::runtime::Runtime::new()?,
+            events: Events::default(),
+            events_read: 0,
+            events_head: Vec::new(),
+            result: Arc::new(Mutex::new(None)),
+            task: None,
+            cancel: CancellationToken::new(),
+        })
+    }
+
+    fn agent(&self) -> Result<agent::Agent> {
+        let mut builder = AgentBuilder::new()
+            .name(&self.config.name)
+            .llm(self.llm.clone())
+            .stop_condition(Box::new(FinalAnswer));
+        for tool in &self.config.tools {
+            builder = match tool.as_str() {
+                "calc" => builder.tools(tools::CalcTool::new().tools()?),
+                "memory" => builder.tools(tools::KVMemoryTool::new().tools()?),
+                "outline" => builder.tool(tools::OutlineTool::new()),
+                "web_fetch" => builder.tool(tools::WebFetchTool::new(tools::WebAccess::new(
+                    tools::WebPolicy::default(),
+                ))?),
+                _ => return Err(Error::Unsupported(format!("unknown tool {}", tool))),
+            };
+        }
+        builder
+            .callback(callbacks::EventLogger::new(
+                &self.config.name,
+                EventQueue {
+                    events: self.events.clone(),
+                    line: Vec::new(),
+                },
+            ))
+            .build()
+    }
+
+    // whether a task was started and has not finished, a task that panicked is finished without
+    // a result
+    fn running(&self) -> bool {
+        self.task.as_ref().is_some_and(|task| !task.is_finished())
+    }
+
+    fn run(&mut self, task: String) -> Result<()> {
+        if self.running() {
+            return Err(Error::AgentWorkflowError(
+                "the agent is already running a task".to_string(),
+            ));
+        }
+        *self.result.lock().unwrap() = None;
+        self.events.lock().unwrap().clear();
+        self.events_read = 0;
+        self.events_head.clear();
+        self.cancel = CancellationToken::new();
+
+        let result = self.result.clone();
+        let cancel = self.cancel.clone();
+        self.task = Some(if self.config.research {
+            let orchestrator = OrchestratorBuilder::new()
+                .llm(self.llm.clone())
+                .log_dir(&self.config.log_dir)
+                .config(Config {
+                    model: self.config.model.clone(),
+                    language: self.config.language.clone(),
+                    verify: self.config.verify,
+                    ..Default::default()
+                })
+                .build()?;
+            self.runtime.spawn(async move {
+                let res = orchestrator.run(task, &cancel).await;
+                *result.lock().unwrap() = Some(res);
+            })
+        } else {
+            let mut agent = self.agent()?;
+            let mut history = Vec::new();
+            if let Some(system_prompt) = &self.config.system_prompt {
+                history.push(Arc::new(Message::System(system_prompt.clone())));
+            }
+            history.push(Arc::new(Message::User(task)));
+            self.runtime.spawn(async move {
+                let res = agent.run(history, &cancel).await.map(|history| {
+                    match history.last().map(|m| m.as_ref()) {
+                        Some(Message::Assistant(answer, _)) => answer.clone(),
+                        _ => String::new(),
+                    }
+                });
+                *result.lock().unwrap() = Some(res);
+            })
+        });
+        Ok(())
+    }
+
+    // the orchestrator and its sub-agents log their events to files, the new complete lines of
+    // the orchestrator's are queued. A rotated file starts over, the events that were not read
+    // yet before it was rotated are in its newest segment
+    fn read_research_events(&mut self) -> Result<()> {
+        let path = self.config.log_dir.join("orchestrator.events.jsonl");
+        let Ok(mut file) = std::fs::File::open(&path) else {
+            return Ok(());
+        };
+        let mut head = vec![0; self.events_head.len()];
+        if file.read_exact(&mut head).is_err() || head != self.events_head {
+            if let Some(segment) = callbacks::segments(&path).pop() {
+                let segment = callbacks::read_segment(&segment)?;
+                if segment.starts_with(&self.events_head) {
+                    let rest = segment.get(self.events_read as usize..).unwrap_or_default();
+                    self.queue_events(rest);
+                }
+            }
+            self.events_read = 0;
+            self.events_head.clear();
+        }
+        file.seek(SeekFrom::Start(self.events_read))?;
+        let mut new = Vec::new();
+        file.read_to_end(&mut new)?;
+        if self.events_head.is_empty()
+            && let Some(end) = new.iter().position(|&b| b == b'\n')
+        {
+            self.events_head = new[..=end].to_vec();
+        }
+        self.events_read += self.queue_events(&new) as u64;
+        Ok(())
+    }
+
+    // queues the complete lines and returns their length
+    fn queue_events(&self, new: &[u8]) -> usize {
+        let complete = new.iter().rposition(|&b| b == b'\n').map_or(0, |i| i + 1);
+        let mut events = self.events.lock().unwrap();
+        for line in String::from_utf8_lossy(&new[..complete])
+            .lines()
+            .filter(|line| !line.is_empty())
+        {
+            events.push_back(line.to_string());
+        }
+        complete
+    }
+
+    fn poll_event(&mut self) -> Result<Option<String>> {
+        if self.config.research {
+            self.read_research_events()?;
+        }
+        Ok(self.events.lock().unwrap().pop_front())
+    }
+
+    fn result(&self) -> Option<String> {
+        let result = self.result.lock().unwrap();
+        let result = match result.as_ref() {
+            Some(Ok(result)) => serde_json::json!({"ok": true, "result": result}),
+            Some(Err(e)) => serde_json::json!({"ok": false, "error": e.to_string()}),
+            None if self.task.is_some() && !self.running() => {
+                serde_json::json!({"ok": false, "error": "the task panicked"})
+            }
+            None => return None,
+        };
+        Some(result.to_string())
+    }
+}
+
+impl Drop for AgentHandle {
+    fn drop(&mut self) {
+        self.cancel.cancel();
+    }
+}
+
+thread_local! {
+    static LAST_ERROR: RefCell<CString> = RefCell::new(CString::default());
+}
+
+fn set_last_error(e: &Error) {
+    let message = CString::new(e.to_string().replace('\0', " ")).unwrap_or_default();
+    LAST_ERROR.with(|last| *last.borrow_mut() = message);
+}
+
+// panics must not unwind into the caller, they are reported like errors
+fn catch<T>(fallback: T, f: impl FnOnce() -> T) -> T {
+    std::panic::catch_unwind(std::panic::AssertUnwindSafe(f)).unwrap_or_else(|panic| {
+        let message = panic
+            .downcast_ref::<&str>()
+            .map(|message| message.to_string())
+            .or_else(|| panic.downcast_ref::<String>().cloned())
+            .unwrap_or_default();
+        set_last_error(&Error::AgentWorkflowError(format!(
+            "the agent library panicked: {}",
+            message
+        )));
+        fallback
+    })
+}
+
+fn to_c_string(s: String) -> *mut c_char {
+    CString::new(s.replace('\0', " "))
+        .unwrap_or_default()
+        .into_raw()
+}
+
+/// # Safety
+/// `s` must be null or a valid NUL terminated string.
+unsafe fn from_c_string(s: *const c_char) -> Result<String> {
+    if s.is_null() {
+        return Err(Error::MissingArg("the string argument is null".to_string()));
+    }
+    unsafe { CStr::from_ptr(s) }
+        .to_str()
+        .map(String::from)
+        .map_err(|e| Error::MissingArg(format!("the string argument is not utf-8: {}", e)))
+}
+
+/// # Safety
+/// `config_json` must be null or a valid NUL terminated string.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn agent_create(config_json: *const c_char) -> *mut AgentHandle {
+    catch(std::ptr::null_mut(), || {
+        match unsafe { from_c_string(config_json) }.and_then(|config| AgentHandle::new(&config)) {
+            Ok(agent) => Box::into_raw(Box::new(agent)),
+            Err(e) => {
+                set_last_error(&e);
+                std::ptr::null_mut()
+            }
+        }
+    })
+}
+
+/// # Safety
+/// `agent` must be a handle returned by `agent_create`, `task` a valid NUL terminated string.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn agent_run(agent: *mut AgentHandle, task: *const c_char) -> c_int {
+    catch(-1, || {
+        let Some(agent) = (unsafe { agent.as_mut() }) else {
+            set_last_error(&Error::MissingArg("the agent is null".to_string()));
+            return -1;
+        };
+        match unsafe { from_c_string(task) }.and_then(|task| agent.run(task)) {
+            Ok(()) => 0,
+            Err(e) => {
+                set_last_error(&e);
+                -1
+            }
+        }
+    })
+}
+
+/// # Safety
+/// `agent` must be a handle returned by `agent_create`.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn agent_poll_event(agent: *mut AgentHandle) -> *mut c_char {
+    catch(std::ptr::null_mut(), || {
+        let Some(agent) = (unsafe { agent.as_mut() }) else {
+            return std::ptr::null_mut();
+        };
+        match agent.poll_event() {
+            Ok(Some(event)) => to_c_string(event),
+            Ok(None) => std::ptr::null_mut(),
+            Err(e) => {
+                set_last_error(&e);
+                std::ptr::null_mut()
+            }
+        }
+    })
+}
+
+/// # Safety
+/// `agent` must be a handle returned by `agent_create`.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn agent_result(agent: *const AgentHandle) -> *mut c_char {
+    catch(std::ptr::null_mut(), || {
+        unsafe { agent.as_ref() }
+            .and_then(|agent| agent.result())
+            .map(to_c_string)
+            .unwrap_or(std::ptr::null_mut())
+    })
+}
+
+/// # Safety
+/// `agent` must be a handle returned by `agent_create`.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn agent_cancel(agent: *const AgentHandle) {
+    catch((), || {
+        if let Some(agent) = unsafe { agent.as_ref() } {
+            agent.cancel.cancel();
+        }
+    })
+}
+
+/// # Safety
+/// `agent` must be null or a handle returned by `agent_create` that was not freed yet.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn agent_free(agent: *mut AgentHandle) {
+    catch((), || {
+        if !agent.is_null() {
+            drop(unsafe { Box::from_raw(agent) });
+        }
+    })
+}
+
+/// # Safety
+/// `s` must be null or a string returned by this library that was not freed yet.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn agent_string_free(s: *mut c_char) {
+    catch((), || {
+        if !s.is_null() {
+            drop(unsafe { CString::from_raw(s) });
+        }
+    })
+}
+
+#[unsafe(no_mangle)]
+pub extern "C" fn agent_last_error() -> *const c_char {
+    LAST_ERROR.with(|last| last.borrow().as_ptr())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_event_queue() {
+        let events = Events::default();
+        let mut queue = EventQueue {
+            events: events.clone(),
+            line: Vec::new(),
+        };
+        std::io::Write::write_all(&mut queue, b"{\"a\":1}\n{\"b\"").unwrap();
+        assert_eq!(events.lock().unwrap().len(), 1);
+        std::io::Write::write_all(&mut queue, b":2}\n").unwrap();
+        assert_eq!(
+            events.lock().unwrap().iter().collect::<Vec<_>>(),
+            vec!["{\"a\":1}", "{\"b\":2}"]
+        );
+    }
+
+    #[test]
+    fn test_create() {
+        unsafe {
+            let agent = agent_create(c"{\"model\": \"gpt-4.1\", \"tools\": [\"calc\"]}".as_ptr());
+            assert!(!agent.is_null());
+            assert!(agent_poll_event(agent).is_null());
+            assert!(agent_result(agent).is_null());
+            agent_free(agent);
+
+            assert!(
+                agent_create(c"{\"model\": \"gpt-4.1\", \"provider\": \"x\"}".as_ptr()).is_null()
+            );
+            let error = CStr::from_ptr(agent_last_error()).to_str().unwrap();
+            assert!(error.contains("unknown provider x"));
+            assert!(agent_create(std::ptr::null()).is_null());
+        }
+        assert_eq!(catch(-1, || panic!("boom")), -1);
+        let error = unsafe { CStr::from_ptr(agent_last_error()) };
+        assert!(error.to_str().unwrap().contains("panicked: boom"));
+    }
+
+    #[test]
+    fn test_rotated_events() -> Result<()> {
+        let dir = std::env::temp_dir().join(format!("ffi-events-{}", std::process::id()));
+        let config = serde_json::json!({"model": "gpt-4.1", "research": true, "log_dir": dir});
+        let mut agent = AgentHandle::new(&config.to_string())?;
+        let path = dir.join("orchestrator.events.jsonl");
+        let mut poll = || -> Result<Vec<String>> {
+            let mut events = Vec::new();
+            while let Some(event) = agent.poll_event()? {
+                events.push(event);
+            }
+            Ok(events)
+        };
+
+        std::fs::write(&path, "{\"a\":1}\n{\"b\"")?;
+        assert_eq!(poll()?, vec!["{\"a\":1}"]);
+        // the file is rotated after more events were written to it
+        std::fs::write(
+            dir.join("orchestrator.events.1.jsonl"),
+            "{\"a\":1}\n{\"b\":2}\n{\"c\":3}\n",
+        )?;
+        std::fs::write(&path, "{\"d\":4}\n")?;
+        assert_eq!(poll()?, vec!["{\"b\":2}", "{\"c\":3}", "{\"d\":4}"]);
+        std::fs::write(&path, "{\"d\":4}\n{\"e\":5}\n")?;
+        assert_eq!(poll()?, vec!["{\"e\":5}"]);
+        std::fs::remove_dir_all(&dir)?;
+        Ok(())
+    }
+}
@@ -0,0 +1,304 @@
+use agent::llm::{self, Message, OpenAICompatible};
+use agent::tools::{FunctionalTool, ToolCall, ToolContext, ToolDefinition};
+use agent::{AgentBuilder, FinalAnswer};
+use async_trait::async_trait;
+use pyo3::exceptions::{PyRuntimeError, PyValueError};
+use pyo3::prelude::*;
+use pyo3::types::{PyDict, PyString};
+use research_core::{Config, OrchestratorBuilder};
+use std::path::PathBuf;
+use std::sync::{Arc, OnceLock};
+use std::time::Duration;
+use tokio_util::sync::CancellationToken;
+
+fn to_py_err(e: agent::Error) -> PyErr {
+    PyRuntimeError::new_err(e.to_string())
+}
+
+fn provider(provider: &str, model: &str) -> PyResult<Arc<dyn llm::LLM + Send + Sync>> {
+    let model = model.to_string();
+    let builder = match provider {
+        "openai" => OpenAICompatible::openai(model),
+        "xai" => OpenAICompatible::xai(model),
+        "mistral" => OpenAICompatible::mistral(model),
+        "deepseek" => OpenAICompatible::deepseek(model),
+        "groq" => OpenAICompatible::groq(model),
+        _ => {
+            return Err(PyValueError::new_err(format!(
+                "unknown provider {}, expected openai, xai, mistral, deepseek, or groq",
+                provider
+            )));
+        }
+    };
+    Ok(builder.build())
+}
+
+// how often a running agent checks for signals such as ctrl-c
+const SIGNAL_INTERVAL: Duration = Duration::from_millis(100);
+
+static RUNTIME: OnceLock<tokio::runtime::Runtime> = OnceLock::new();
+
+// the runtime shared by every call, it is created by the first one
+fn runtime() -> PyResult<&'static tokio::runtime::Runtime> {
+    if let Some(runtime) = RUNTIME.get() {
+        return Ok(runtime);
+    }
+    let runtime = tokio::runtime::Runtime::new()?;
+    Ok(RUNTIME.get_or_init(|| runtime))
+}
+
+// runs the future with the gil released, so tools written in python can take it while the agent
+// runs. a signal handler that raises, such as the KeyboardInterrupt of ctrl-c, cancels the run
+// through the token the future was given and its exception is returned once the run has stopped
+fn block_on<T: Send>(
+    py: Python<'_>,
+    cancel: &CancellationToken,
+    future: impl Future<Output = agent::Result<T>> + Send,
+) -> PyResult<T> {
+    let runtime = runtime()?;
+    py.allow_threads(|| {
+        runtime.block_on(async {
+            tokio::pin!(future);
+            loop {
+                tokio::select! {
+                    result = &mut future => return result.map_err(to_py_err),
+                    _ = tokio::time::sleep(SIGNAL_INTERVAL) => {
+                        if let Err(e) = Python::with_gil(|py| py.check_signals()) {
+                            cancel.cancel();
+                            let _ = future.await;
+                            return Err(e);
+                        }
+                    }
+                }
+            }
+        })
+    })
+}
+
+// a tool implemented by a python callable, it is called with the arguments of the tool call as
+// keyword arguments and a return value that is not a string is sent to the model as json
+struct PythonTool {
+    name: String,
+    description: String,
+    parameters: serde_json::Value,
+    func: Py<PyAny>,
+}
+
+impl PythonTool {
+    fn call(&self, py: Python<'_>, args: &str) -> PyResult<String> {
+        let json = py.import("json")?;
+        let kwargs = json.call_method1("loads", (args,))?;
+        let result = self.func.call(py, (), Some(kwargs.downcast::<PyDict>()?))?;
+        let result = result.bind(py);
+        if let Ok(text) = result.extract::<String>() {
+            return Ok(text);
+        }
+
+        let options = PyDict::new(py);
+        options.set_item("default", py.get_type::<PyString>())?;
+        json.call_method("dumps", (result,), Some(&options))?
+            .extract()
+    }
+
+    fn clone_ref(&self, py: Python<'_>) -> Self {
+        Self {
+            name: self.name.clone(),
+            description: self.description.clone(),
+            parameters: self.parameters.clone(),
+            func: self.func.clone_ref(py),
+        }
+    }
+}
+
+#[async_trait]
+impl FunctionalTool for PythonTool {
+    fn definition(&self) -> agent::Result<ToolDefinition> {
+        Ok(ToolDefinition {
+            name: self.name.clone(),
+            desc: self.description.clone(),
+            params: self.parameters.clone(),
+            strict: false,
+        })
+    }
+
+    async fn invoke_fn(&mut self, call: &ToolCall, _: &ToolContext) -> agent::Result<Message> {
+        let args: serde_json::Map<String, serde_json::Value> = call.args()?;
+        let args = serde_json::to_string(&args)?;
+        // an exception is reported to the model like any other failed tool call
+        let result = Python::with_gil(|py| self.call(py, &args))
+            .unwrap_or_else(|e| format!("the tool raised an exception: {}", e));
+
+        Ok(Message::Tool {
+            id: call.id.clone(),
+            name: self.name.clone(),
+            result: result.into(),
+        })
+    }
+}
+
+/// An agent that works on a task with a model and the Python functions added as its tools.
+#[pyclass(name = "Agent")]
+struct PyAgent {
+    llm: Arc<dyn llm::LLM + Send + Sync>,
+    name: String,
+    system_prompt: Option<String>,
+    tools: Vec<PythonTool>,
+}
+
+#[pymethods]
+impl PyAgent {
+    #[new]
+    #[pyo3(signature = (model, provider = "openai", system_prompt = None, name = "agent"))]
+    fn new(
+        model: &str,
+        provider: &str,
+        system_prompt: Option<String>,
+        name: &str,
+    ) -> PyResult<Self> {
+        Ok(Self {
+            llm: self::provider(provider, model)?,
+            name: name.to_string(),
+            system_prompt,
+            tools: Vec::new(),
+        })
+    }
+
+    /// Adds a Python function as a tool. The name and description default to the name and
+    /// docstring of the function, parameters is the JSON schema of its keyword arguments.
+    #[pyo3(signature = (func, name = None, description = None, parameters = None))]
+    fn add_tool(
+        &mut self,
+        py: Python<'_>,
+        func: Py<PyAny>,
+        name: Option<String>,
+        description: Option<String>,
+        parameters: Option<Bound<'_, PyAny>>,
+    ) -> PyResult<()> {
+        let callable = func.bind(py);
+        let name = match name {
+            Some(name) => name,
+            None => callable.getattr("__name__")?.extract()?,
+        };
+        let description = match description {
+            Some(description) => description,
+            None => callable
+                .getattr("__doc__")?
+                .extract::<Option<String>>()?
+                .unwrap_or_default(),
+        };
+        let parameters = match parameters {
+            Some(parameters) => {
+                let schema: String = py
+                    .import("json")?
+                    .call_method1("dumps", (parameters,))?
+                    .extract()?;
+                serde_json::from_str(&schema)
+                    .map_err(|e| PyValueError::new_err(format!("invalid parameters: {}", e)))?
+            }
+            None => serde_json::json!({"type": "object", "properties": {}}),
+        };
+
+        self.tools.push(PythonTool {
+            name,
+            description,
+            parameters,
+            func,
+        });
+        Ok(())
+    }
+
+    /// Works on the task until the model answers without calling a tool and returns the answer.
+    fn run(&self, py: Python<'_>, task: String) -> PyResult<String> {
+        let mut builder = AgentBuilder::new()
+            .name(&self.name)
+            .llm(self.llm.clone())
+            .stop_condition(Box::new(FinalAnswer));
+        for tool in &self.tools {
+            builder = builder.tool(Box::new(tool.clone_ref(py)));
+        }
+        let mut agent = builder.build().map_err(to_py_err)?;
+
+        let mut history = Vec::new();
+        if let Some(system_prompt) = &self.system_prompt {
+            history.push(Arc::new(Message::System(system_prompt.clone())));
+        }
+        history.push(Arc::new(Message::User(task)));
+        let cancel = CancellationToken::new();
+        let history = block_on(py, &cancel, agent.run(history, &cancel))?;
+
+        match history.last().map(|m| m.as_ref()) {
+            Some(Message::Assistant(answer, _)) => Ok(answer.clone()),
+            _ => Err(PyRuntimeError::new_err(
+                "the agent stopped without an answer",
+            )),
+        }
+    }
+}
+
+/// Researches the task with an orchestrator and its sub-agents and returns the report. The logs,
+/// sources, and artifacts of the run are written to log_dir.
+#[pyfunction]
+#[pyo3(signature = (task, model, provider = "openai", log_dir = PathBuf::from("./agent_logs"), language = "en", verify = false))]
+fn research(
+    py: Python<'_>,
+    task: String,
+    model: &str,
+    provider: &str,
+    log_dir: PathBuf,
+    language: &str,
+    verify: bool,
+) -> PyResult<String> {
+    std::fs::create_dir_all(&log_dir)?;
+    let orchestrator = OrchestratorBuilder::new()
+        .llm(self::provider(provider, model)?)
+        .log_dir(&log_dir)
+        .config(Config {
+            model: model.to_string(),
+            language: language.to_string(),
+            verify,
+            ..Default::default()
+        })
+        .build()
+        .map_err(to_py_err)?;
+    let cancel = CancellationToken::new();
+    block_on(py, &cancel, orchestrator.run(task, &cancel))
+}
+
+#[pymodule]
+fn agent_py(m: &Bound<'_, PyModule>) -> PyResult<()> {
+    m.add_class::<PyAgent>()?;
+    m.add_function(wrap_pyfunction!(research, m)?)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::PythonTool;
+    use pyo3::prelude::*;
+
+    #[test]
+    fn test_python_tool() -> PyResult<()> {
+        pyo3::prepare_freethreaded_python();
+        Python::with_gil(|py| {
+            let tool = |code: &std::ffi::CStr| -> PyResult<PythonTool> {
+                Ok(PythonTool {
+                    name: "tool".to_string(),
+                    description: String::new(),
+                    parameters: serde_json::json!({}),
+                    func: py.eval(code, None, None)?.unbind(),
+                })
+            };
+            assert_eq!(
+                tool(c"lambda a, b: f'{a} + {b}'")?.call(py, r#"{"a": 1, "b": 2}"#)?,
+                "1 + 2"
+            );
+            assert_eq!(
+                tool(c"lambda ticker: {'ticker': ticker, 'price': 1.5}")?
+                    .call(py, r#"{"ticker": "AAPL"}"#)?,
+                r#"{"ticker": "AAPL", "price": 1.5}"#
+            );
+            assert!(tool(c"lambda: 1")?.call(py, r#"{"a": 1}"#).is_err());
+            Ok(())
+        })
+    }
+}
@@ -1,15 +1,55 @@
-use crate::agent::llm::Message;
+use crate::artifacts::{ArtifactStore, Spill};
 use crate::callbacks;
+use crate::checkpoint::Checkpoint;
+use crate::context::{ContextBudget, Priority, RESERVED_OUTPUT_TOKENS, tool_tokens};
 use crate::llm;
+use crate::sanitize::Sanitizer;
 use crate::tools;
-use crate::{Error, Result};
+use crate::workdir::RunContext;
+use crate::{Error, History, Result};
+use async_trait::async_trait;
 use std::collections::HashMap;
 use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio_util::sync::CancellationToken;
 
 pub trait StopCondition {
-    fn done(&self, history: &[llm::Message]) -> bool;
+    fn done(&self, history: &dyn History) -> bool;
 }
 
+// the agent is done once the model answers without calling a tool, for agents that have no tool
+// to submit their result with
+pub struct FinalAnswer;
+
+impl StopCondition for FinalAnswer {
+    fn done(&self, history: &dyn History) -> bool {
+        matches!(
+            history.last().map(|m| m.as_ref()),
+            Some(llm::Message::Assistant(_, tool_calls)) if tool_calls.is_empty()
+        )
+    }
+}
+
+// provides the system prompt at the start of every step, so that a prompt can be changed while
+// the agent is running
+pub trait SystemPrompt {
+    fn system_prompt(&mut self) -> Result<String>;
+}
+
+// contributes messages with fresh state before every llm request, they are sent to the model
+// after the history but never stored in it
+#[async_trait]
+pub trait ContextProvider {
+    async fn context(&mut self, history: &dyn History) -> Result<Vec<Arc<llm::Message>>>;
+
+    // how much the messages matter for requests that have to fit the context budget
+    fn priority(&self) -> Priority {
+        Priority::Normal
+    }
+}
+
+type Context = Box<dyn ContextProvider + Send>;
+
 type Tool = Box<dyn tools::Tool + Send>;
 type Callback = Box<dyn callbacks::Callback + Send>;
 
@@ -20,25 +60,240 @@ pub struct Agent {
     tool_defs: Vec<tools::ToolDefinition>,
     stop_condition: Box<dyn StopCondition + Send>,
     llm_websearch: bool,
+    spill: Option<Spill>,
+    run: Option<Arc<RunContext>>,
+    sanitizer: Option<Arc<Sanitizer>>,
+    compactor: Option<Box<tools::SummarizeHistory>>,
+    step_timeout: Option<Duration>,
+    arg_repairs: HashMap<String, usize>,
+    name: String,
+    usage: Arc<llm::Usage>,
+    step: usize,
+    cancel: CancellationToken,
+    system_prompt: Option<Box<dyn SystemPrompt + Send>>,
+    context_providers: Vec<Context>,
+    context_budget: Option<ContextBudget>,
+    checkpoint: Option<std::path::PathBuf>,
+    profiler: Option<Box<callbacks::Profiler>>,
 }
 
+const MAX_ARG_REPAIRS: usize = 3;
+
 impl Agent {
+    fn context(&self) -> tools::ToolContext {
+        tools::ToolContext {
+            agent: self.name.clone(),
+            step: self.step,
+            artifacts: self.spill.as_ref().map(|spill| spill.store.clone()),
+            run: self.run.clone(),
+            usage: self.usage.clone(),
+            cancel: self.cancel.clone(),
+        }
+    }
+
     async fn execute_tool_call(
         &mut self,
         tool_call: &tools::ToolCall,
-        messages: Vec<llm::Message>,
-    ) -> Result<Vec<llm::Message>> {
+        history: &mut dyn History,
+        ctx: &tools::ToolContext,
+    ) -> Result<()> {
         let tool = self
             .tools
             .get_mut(&tool_call.name)
             .ok_or(Error::ToolDoesNotExist(tool_call.name.clone()))?;
 
-        let messages = tool.invoke(tool_call, messages).await?;
+        let third_party = tool.third_party();
+        let len = history.len();
+        match tool.invoke(tool_call, history, ctx).await {
+            Err(Error::InvalidToolArgs { error, .. })
+                if history.len() == len
+                    && self.arg_repairs.get(&tool_call.name).copied().unwrap_or(0)
+                        < MAX_ARG_REPAIRS =>
+            {
+                *self.arg_repairs.entry(tool_call.name.clone()).or_default() += 1;
+
+                let schema = self
+                    .tool_defs
+                    .iter()
+                    .find(|def| def.name == tool_call.name)
+                    .map(|def| def.params.to_string())
+                    .unwrap_or_default();
+
+                history.append(Arc::new(llm::Message::Tool {
+                    id: tool_call.id.clone(),
+                    name: tool_call.name.clone(),
+                    result: format!(
+                        "The arguments of this tool call could not be parsed: {}\nThe arguments must be a JSON object matching this schema:\n{}\nCall the tool again with corrected arguments.",
+                        error, schema
+                    )
+                    .into(),
+                }));
+                return Ok(());
+            }
+            res => res?,
+        }
+        self.arg_repairs.remove(&tool_call.name);
+
+        if let Some(spill) = &self.spill {
+            spill.apply(history, len)?;
+        }
+        // spilled artifacts keep the original text and are sanitized when they are read back
+        if third_party && let Some(sanitizer) = &self.sanitizer {
+            sanitizer.apply(history, len, ctx)?;
+        }
+
+        Ok(())
+    }
+
+    // replaces the system message at the start of the history if the prompt changed
+    fn refresh_system_prompt(&mut self, history: &mut dyn History) -> Result<()> {
+        let Some(source) = &mut self.system_prompt else {
+            return Ok(());
+        };
+        let prompt = source.system_prompt()?;
+        let changed = matches!(
+            history.iter().next().map(|m| m.as_ref()),
+            Some(llm::Message::System(current)) if *current != prompt
+        );
+        if changed {
+            history.replace(0, Arc::new(llm::Message::System(prompt)));
+        }
+        Ok(())
+    }
+
+    // the history followed by the ephemeral messages of the context providers, fitted into the
+    // context budget if there is one
+    async fn with_context(&mut self, history: &dyn History) -> Result<Vec<Arc<llm::Message>>> {
+        let messages = history.iter().cloned().collect::<Vec<_>>();
+        let mut context = Vec::with_capacity(self.context_providers.len());
+        for provider in &mut self.context_providers {
+            context.push((provider.priority(), provider.context(history).await?));
+        }
+        Ok(match &self.context_budget {
+            Some(budget) => budget.assemble(&messages, context),
+            None => messages
+                .into_iter()
+                .chain(context.into_iter().flat_map(|(_, messages)| messages))
+                .collect(),
+        })
+    }
+
+    // the clock only runs while a profiler is set, so agents that are not profiled never read it
+    fn start_timer(&self) -> Option<Instant> {
+        self.profiler.is_some().then(Instant::now)
+    }
+
+    async fn step(&mut self, history: &mut dyn History) -> Result<()> {
+        let mut profile = callbacks::StepProfile::default();
+
+        let timer = self.start_timer();
+        self.refresh_system_prompt(history)?;
+        let messages = self.with_context(history).await?;
+        profile.context = elapsed(timer);
+        let request = llm::CompletionRequest {
+            messages: &messages,
+            tools: &self.tool_defs,
+            web_search_tool: self.llm_websearch,
+            prefill: None,
+        };
+
+        let timer = self.start_timer();
+        let next = match self.llm.completion(request).await {
+            Err(Error::ContextOverflow(reason)) if self.compactor.is_some() => {
+                profile.llm = elapsed(timer);
+                // compact as aggressively as possible and give the request one more try, unless
+                // there was nothing to compact and the same request would be sent again
+                let timer = self.start_timer();
+                let before = history.iter().cloned().collect::<Vec<_>>();
+                if let Some(spill) = &self.spill {
+                    spill.compact(history)?;
+                }
+                if let Some(compactor) = &self.compactor {
+                    compactor.summarize_history(history).await?;
+                }
+                profile.compaction = elapsed(timer);
+                if history.len() == before.len()
+                    && history.iter().zip(&before).all(|(a, b)| Arc::ptr_eq(a, b))
+                {
+                    return Err(Error::ContextOverflow(reason));
+                }
+
+                let timer = self.start_timer();
+                let messages = self.with_context(history).await?;
+                profile.context += elapsed(timer);
+                let timer = self.start_timer();
+                let next = self
+                    .llm
+                    .completion(llm::CompletionRequest {
+                        messages: &messages,
+                        tools: &self.tool_defs,
+                        web_search_tool: self.llm_websearch,
+                        prefill: None,
+                    })
+                    .await?;
+                profile.llm += elapsed(timer);
+                next
+            }
+            res => {
+                profile.llm = elapsed(timer);
+                res?
+            }
+        };
+
+        self.usage.record(next.usage);
+
+        let message = Arc::new(llm::Message::Assistant(next.content, next.tool_calls));
+        history.append(message.clone());
+
+        let ctx = self.context();
+        if let llm::Message::Assistant(_, tool_calls) = message.as_ref() {
+            for tool_call in tool_calls {
+                let timer = self.start_timer();
+                self.execute_tool_call(tool_call, history, &ctx).await?;
+                profile.tools.push((tool_call.name.clone(), elapsed(timer)));
+            }
+        }
+        self.step += 1;
 
-        Ok(messages)
+        let profiled = self.profiler.is_some();
+        for callback in &mut self.callbacks {
+            let timer = profiled.then(Instant::now);
+            callback.call(history).await?;
+            profile
+                .callbacks
+                .push((callback.name().to_string(), elapsed(timer)));
+        }
+
+        let timer = self.start_timer();
+        if let Some(path) = &self.checkpoint {
+            self.checkpoint_state(history).save(path)?;
+        }
+        profile.checkpoint = elapsed(timer);
+
+        if let Some(profiler) = &mut self.profiler {
+            profiler.record(&self.name, &profile)?;
+        }
+
+        Ok(())
     }
 
-    pub async fn run(&mut self, mut messages: Vec<llm::Message>) -> Result<Vec<Message>> {
+    pub fn checkpoint_state(&self, history: &dyn History) -> Checkpoint {
+        Checkpoint {
+            step: self.step,
+            history: history.iter().map(|m| m.as_ref().clone()).collect(),
+            tools: self
+                .tools
+                .iter()
+                .filter_map(|(name, tool)| tool.checkpoint().map(|state| (name.clone(), state)))
+                .collect(),
+        }
+    }
+
+    // cancelling the token drops the in flight llm request or tool invocation, tools that
+    // spawn work of their own, such as sub-agents, derive child tokens from ToolContext::cancel
+    pub async fn run<H: History>(&mut self, history: H, cancel: &CancellationToken) -> Result<H> {
+        self.cancel = cancel.clone();
+
         for callback in &mut self.callbacks {
             callback.on_agent_start().await?;
         }
@@ -47,31 +302,69 @@ impl Agent {
             tool.on_agent_start().await?;
         }
 
-        while !self.stop_condition.done(&messages) {
-            let next = self
-                .llm
-                .completion(llm::CompletionRequest {
-                    messages: &messages,
-                    tools: &self.tool_defs,
-                    web_search_tool: self.llm_websearch,
-                })
-                .await?;
-
-            messages.push(llm::Message::Assistant(
-                next.content,
-                next.tool_calls.clone(),
-            ));
+        self.run_steps(history, cancel).await
+    }
 
-            for tool_call in &next.tool_calls {
-                messages = self.execute_tool_call(tool_call, messages).await?;
+    // continues a run from a checkpoint, the start hooks are not called because they reset the
+    // state of the run that is being resumed
+    pub async fn resume(
+        &mut self,
+        checkpoint: Checkpoint,
+        cancel: &CancellationToken,
+    ) -> Result<Vec<Arc<llm::Message>>> {
+        self.cancel = cancel.clone();
+        self.step = checkpoint.step;
+        let history = checkpoint.messages();
+        for (name, state) in checkpoint.tools {
+            if let Some(tool) = self.tools.get_mut(&name) {
+                tool.restore(state).await?;
             }
+        }
+
+        self.run_steps(history, cancel).await
+    }
 
-            for callback in &mut self.callbacks {
-                messages = callback.call(messages).await?;
+    async fn run_steps<H: History>(
+        &mut self,
+        mut history: H,
+        cancel: &CancellationToken,
+    ) -> Result<H> {
+        let cancelled = format!("run of agent {} was cancelled", self.name);
+        while !self.stop_condition.done(&history) {
+            let timeout = self.step_timeout;
+            let step = with_timeout(timeout, self.step(&mut history));
+
+            tokio::select! {
+                biased;
+                _ = cancel.cancelled() => {
+                    return Err(Error::Cancelled(cancelled));
+                }
+                res = step => res?,
             }
         }
 
-        Ok(messages)
+        Ok(history)
+    }
+}
+
+fn elapsed(timer: Option<Instant>) -> Duration {
+    timer.map(|timer| timer.elapsed()).unwrap_or_default()
+}
+
+async fn with_timeout(
+    timeout: Option<Duration>,
+    step: impl Future<Output = Result<()>>,
+) -> Result<()> {
+    match timeout {
+        #[cfg(feature = "native")]
+        Some(timeout) => tokio::time::timeout(timeout, step).await.map_err(|_| {
+            Error::Timeout(format!(
+                "agent step did not finish within {} seconds",
+                timeout.as_secs()
+            ))
+        })?,
+        // there is no timer without the tokio runtime, build refuses a timeout then
+        _ => step.await,
     }
 }
 
@@ -81,6 +374,24 @@ pub struct AgentBuilder {
     callbacks: Vec<Callback>,
     stop_condition: Option<Box<dyn StopCondition + Send>>,
     llm_websearch: bool,
+    spill: Option<Spill>,
+    run: Option<Arc<RunContext>>,
+    sanitizer: Option<Arc<Sanitizer>>,
+    recover_context_overflow: bool,
+    step_timeout: Option<Duration>,
+    name: String,
+    usage: Arc<llm::Usage>,
+    system_prompt: Option<Box<dyn SystemPrompt + Send>>,
+    context_providers: Vec<Context>,
+    context_budget: Option<usize>,
+    checkpoint: Option<std::path::PathBuf>,
+    profiler: Option<Box<callbacks::Profiler>>,
+}
+
+impl Default for AgentBuilder {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl AgentBuilder {
@@ -91,6 +402,18 @@ impl AgentBuilder {
             callbacks: Vec::new(),
             stop_condition: None,
             llm_websearch: false,
+            spill: None,
+            run: None,
+            sanitizer: None,
+            recover_context_overflow: false,
+            step_timeout: None,
+            name: "agent".to_string(),
+            usage: llm::Usage::new(),
+            system_prompt: None,
+            context_providers: Vec::new(),
+            context_budget: None,
+            checkpoint: None,
+            profiler: None,
         }
     }
 
@@ -124,6 +447,76 @@ impl AgentBuilder {
         self
     }
 
+    pub fn spill_tool_results(mut self, store: Arc<ArtifactStore>, threshold: usize) -> Self {
+        self.tools.push(tools::ReadArtifactTool::new(store.clone()));
+        self.spill = Some(Spill { store, threshold });
+        self
+    }
+
+    // gives the tools the working directory of the run for the files they create
+    pub fn run_context(mut self, run: Arc<RunContext>) -> Self {
+        self.run = Some(run);
+        self
+    }
+
+    // wraps the results of the tools with third party content in untrusted blocks and removes
+    // instruction-like text from them
+    pub fn sanitize_tool_results(mut self, sanitizer: Arc<Sanitizer>) -> Self {
+        self.sanitizer = Some(sanitizer);
+        self
+    }
+
+    pub fn recover_context_overflow(mut self) -> Self {
+        self.recover_context_overflow = true;
+        self
+    }
+
+    pub fn step_timeout(mut self, timeout: Duration) -> Self {
+        self.step_timeout = Some(timeout);
+        self
+    }
+
+    pub fn name(mut self, name: &str) -> Self {
+        self.name = name.to_string();
+        self
+    }
+
+    pub fn usage(mut self, usage: Arc<llm::Usage>) -> Self {
+        self.usage = usage;
+        self
+    }
+
+    pub fn reload_system_prompt(mut self, source: Box<dyn SystemPrompt + Send>) -> Self {
+        self.system_prompt = Some(source);
+        self
+    }
+
+    pub fn context_provider(mut self, provider: Context) -> Self {
+        self.context_providers.push(provider);
+        self
+    }
+
+    // fits every request into the number of tokens, the messages by the priority of their parts
+    // in what is left after the tool definitions and the reply, instead of sending the whole
+    // history and all context, see Priority
+    pub fn context_budget(mut self, max_tokens: usize) -> Self {
+        self.context_budget = Some(max_tokens);
+        self
+    }
+
+    // writes a checkpoint of the history and the tool state to the path after every step, see
+    // Agent::resume
+    pub fn checkpoint(mut self, path: &std::path::Path) -> Self {
+        self.checkpoint = Some(path.to_path_buf());
+        self
+    }
+
+    // measures where the time of every step goes and writes it to the profiler's file
+    pub fn profiler(mut self, profiler: Box<callbacks::Profiler>) -> Self {
+        self.profiler = Some(profiler);
+        self
+    }
+
     pub fn build(self) -> Result<Agent> {
         let mut tool_defs = Vec::new();
         let mut tools = HashMap::new();
@@ -134,17 +527,46 @@ impl AgentBuilder {
             tool_defs.push(def);
         }
 
+        let llm = self
+            .llm
+            .ok_or(Error::MissingArg("llm is required for agent".to_string()))?;
+        if self.step_timeout.is_some() && !cfg!(feature = "native") {
+            return Err(Error::Unsupported(
+                "step timeouts need the timers of the native feature".to_string(),
+            ));
+        }
+
+        // providers that cannot search the web fail every request that asks them to
+        let llm_websearch = self.llm_websearch && llm.web_search();
+        let context_budget = self.context_budget.map(|max_tokens| {
+            ContextBudget::new(max_tokens, tool_tokens(&tool_defs) + RESERVED_OUTPUT_TOKENS)
+        });
         Ok(Agent {
-            llm: self
-                .llm
-                .ok_or(Error::MissingArg("llm is required for agent".to_string()))?,
-            tools: tools,
-            tool_defs: tool_defs,
+            compactor: self
+                .recover_context_overflow
+                .then(|| tools::SummarizeHistory::new(llm.clone(), 2)),
+            llm,
+            tools,
+            tool_defs,
             callbacks: self.callbacks,
             stop_condition: self.stop_condition.ok_or(Error::MissingArg(
                 "stop_condition is required for agent".to_string(),
             ))?,
-            llm_websearch: self.llm_websearch,
+            llm_websearch,
+            spill: self.spill,
+            run: self.run,
+            sanitizer: self.sanitizer,
+            step_timeout: self.step_timeout,
+            arg_repairs: HashMap::new(),
+            name: self.name,
+            usage: self.usage,
+            step: 0,
+            cancel: CancellationToken::new(),
+            system_prompt: self.system_prompt,
+            context_providers: self.context_providers,
+            context_budget,
+            checkpoint: self.checkpoint,
+            profiler: self.profiler,
         })
     }
 }
@@ -153,11 +575,16 @@ impl AgentBuilder {
 mod tests {
     use core::panic;
 
+    use crate::artifacts::ArtifactStore;
+    use crate::checkpoint::Checkpoint;
     use crate::llm::{CompletionRequest, CompletionResponse, LLM, Message};
-    use crate::tools::{FunctionalTool, ToolCall, ToolDefinition};
-    use crate::{AgentBuilder, Result, StopCondition};
+    use crate::tools::{FunctionalTool, ToolCall, ToolContext, ToolDefinition};
+    use crate::{
+        AgentBuilder, ContextProvider, Error, History, Result, StopCondition, SystemPrompt,
+    };
     use async_trait::async_trait;
     use std::sync::Arc;
+    use tokio_util::sync::CancellationToken;
 
     struct MockLLM;
 
@@ -167,7 +594,7 @@ mod tests {
             &self,
             request: CompletionRequest<'a>,
         ) -> Result<CompletionResponse> {
-            match request.messages.last() {
+            match request.messages.last().map(|m| m.as_ref()) {
                 Some(Message::User(_)) => Ok(CompletionResponse {
                     content: "tool call".to_string(),
                     tool_calls: vec![ToolCall {
@@ -175,14 +602,15 @@ mod tests {
                         name: "double".to_string(),
                         args: "{\"arg\":123}".to_string(),
                     }],
+                    usage: Default::default(),
                 }),
                 Some(Message::Tool { .. }) => Ok(CompletionResponse {
                     content: "tool call recieved".to_string(),
-                    tool_calls: vec![],
+                    ..Default::default()
                 }),
                 Some(Message::Assistant(_, _)) => Ok(CompletionResponse {
                     content: "completed".to_string(),
-                    tool_calls: vec![],
+                    ..Default::default()
                 }),
                 _ => panic!("unexpected message sequence"),
             }
@@ -202,12 +630,12 @@ mod tests {
             ToolDefinition::new::<DoubleArgs>("double", "double")
         }
 
-        async fn invoke_fn(&mut self, tool_call: &ToolCall) -> Result<Message> {
+        async fn invoke_fn(&mut self, tool_call: &ToolCall, _: &ToolContext) -> Result<Message> {
             let args: DoubleArgs = tool_call.args()?;
             Ok(Message::Tool {
                 id: tool_call.id.clone(),
                 name: "double".to_string(),
-                result: format!("2 * {} = {}", args.arg, 2 * args.arg),
+                result: format!("2 * {} = {}", args.arg, 2 * args.arg).into(),
             })
         }
     }
@@ -215,8 +643,8 @@ mod tests {
     struct SimpleStop;
 
     impl StopCondition for SimpleStop {
-        fn done(&self, history: &[Message]) -> bool {
-            if let Some(Message::Assistant(content, _)) = history.last() {
+        fn done(&self, history: &dyn History) -> bool {
+            if let Some(Message::Assistant(content, _)) = history.last().map(|m| m.as_ref()) {
                 content == "completed"
             } else {
                 false
@@ -233,18 +661,357 @@ mod tests {
             .build()?;
 
         let history = agent
-            .run(vec![Message::User("do stuff".to_string())])
+            .run(
+                vec![Arc::new(Message::User("do stuff".to_string()))],
+                &CancellationToken::new(),
+            )
             .await?;
 
         assert_eq!(history.len(), 5);
 
-        assert!(matches!(&history[0], Message::User (content) if content == "do stuff"));
-        assert!(matches!(&history[1], Message::Assistant (_, tool_calls) if tool_calls.len() == 1));
-        assert!(matches!(&history[2], Message::Tool {  result,.. } if result == "2 * 123 = 246"));
+        assert!(matches!(history[0].as_ref(), Message::User (content) if content == "do stuff"));
+        assert!(
+            matches!(history[1].as_ref(), Message::Assistant (_, tool_calls) if tool_calls.len() == 1)
+        );
+        assert!(
+            matches!(history[2].as_ref(), Message::Tool {  result,.. } if result == "2 * 123 = 246")
+        );
+        assert!(
+            matches!(history[3].as_ref(), Message::Assistant (content, _) if content== "tool call recieved")
+        );
         assert!(
-            matches!(&history[3], Message::Assistant (content, _) if content== "tool call recieved")
+            matches!(history[4].as_ref(), Message::Assistant (content, _) if content== "completed")
         );
-        assert!(matches!(&history[4], Message::Assistant (content, _) if content== "completed"));
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_resume() -> Result<()> {
+        let path = std::env::temp_dir().join(format!("resume-test-{}.json", std::process::id()));
+        let mut agent = AgentBuilder::new()
+            .llm(Arc::new(MockLLM))
+            .tool(Box::new(DoubleTool))
+            .stop_condition(Box::new(SimpleStop))
+            .checkpoint(&path)
+            .build()?;
+        agent
+            .run(
+                vec![Arc::new(Message::User("do stuff".to_string()))],
+                &CancellationToken::new(),
+            )
+            .await?;
+
+        let mut checkpoint = Checkpoint::load(&path)?;
+        std::fs::remove_file(&path)?;
+        assert_eq!(checkpoint.step, 3);
+        assert_eq!(checkpoint.history.len(), 5);
+
+        // an agent that crashed after the tool result continues from there
+        checkpoint.history.truncate(3);
+        checkpoint.step = 1;
+        let mut agent = AgentBuilder::new()
+            .llm(Arc::new(MockLLM))
+            .tool(Box::new(DoubleTool))
+            .stop_condition(Box::new(SimpleStop))
+            .build()?;
+        let history = agent.resume(checkpoint, &CancellationToken::new()).await?;
+        assert_eq!(history.len(), 5);
+        assert!(
+            matches!(history[2].as_ref(), Message::Tool { result, .. } if result == "2 * 123 = 246")
+        );
+        assert!(
+            matches!(history[4].as_ref(), Message::Assistant(content, _) if content == "completed")
+        );
+        Ok(())
+    }
+
+    struct CountingPrompt(usize);
+
+    impl SystemPrompt for CountingPrompt {
+        fn system_prompt(&mut self) -> Result<String> {
+            self.0 += 1;
+            Ok(format!("prompt {}", self.0))
+        }
+    }
+
+    #[tokio::test]
+    async fn test_reload_system_prompt() -> Result<()> {
+        let mut agent = AgentBuilder::new()
+            .llm(Arc::new(MockLLM))
+            .tool(Box::new(DoubleTool))
+            .stop_condition(Box::new(SimpleStop))
+            .reload_system_prompt(Box::new(CountingPrompt(0)))
+            .build()?;
+
+        let history = agent
+            .run(
+                vec![
+                    Arc::new(Message::System("prompt 0".to_string())),
+                    Arc::new(Message::User("do stuff".to_string())),
+                ],
+                &CancellationToken::new(),
+            )
+            .await?;
+
+        assert_eq!(history.len(), 6);
+        assert!(matches!(history[0].as_ref(), Message::System(content) if content == "prompt 3"));
+
+        Ok(())
+    }
+
+    struct Status;
+
+    #[async_trait]
+    impl ContextProvider for Status {
+        async fn context(&mut self, history: &dyn History) -> Result<Vec<Arc<Message>>> {
+            Ok(vec![Arc::new(Message::User(format!(
+                "{} messages so far",
+                history.len()
+            )))])
+        }
+    }
+
+    // completes once it sees the message of the context provider
+    struct ContextLLM;
+
+    #[async_trait]
+    impl LLM for ContextLLM {
+        async fn completion<'a>(
+            &self,
+            request: CompletionRequest<'a>,
+        ) -> Result<CompletionResponse> {
+            match request.messages.last().map(|m| m.as_ref()) {
+                Some(Message::User(content)) if content == "1 messages so far" => {
+                    Ok(CompletionResponse {
+                        content: "completed".to_string(),
+                        ..Default::default()
+                    })
+                }
+                _ => panic!("context message missing"),
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_context_provider() -> Result<()> {
+        let mut agent = AgentBuilder::new()
+            .llm(Arc::new(ContextLLM))
+            .stop_condition(Box::new(SimpleStop))
+            .context_provider(Box::new(Status))
+            .build()?;
+
+        let history = agent
+            .run(
+                vec![Arc::new(Message::User("do stuff".to_string()))],
+                &CancellationToken::new(),
+            )
+            .await?;
+
+        // the context message is not stored in the history
+        assert_eq!(history.len(), 2);
+        assert!(matches!(history[0].as_ref(), Message::User(content) if content == "do stuff"));
+
+        Ok(())
+    }
+
+    struct OverflowLLM;
+
+    #[async_trait]
+    impl LLM for OverflowLLM {
+        async fn completion<'a>(
+            &self,
+            request: CompletionRequest<'a>,
+        ) -> Result<CompletionResponse> {
+            let too_long = request.messages.iter().any(|m| match m.as_ref() {
+                Message::Tool { result, .. } => result.len() > 3000,
+                _ => false,
+            });
+            if too_long {
+                return Err(Error::ContextOverflow("too many tokens".to_string()));
+            }
+
+            Ok(CompletionResponse {
+                content: "completed".to_string(),
+                ..Default::default()
+            })
+        }
+    }
+
+    #[tokio::test]
+    async fn test_context_overflow_recovery() -> Result<()> {
+        let dir = std::env::temp_dir().join(format!("overflow-test-{}", std::process::id()));
+        let history = vec![
+            Arc::new(Message::System("system".to_string())),
+            Arc::new(Message::User("do stuff".to_string())),
+            Arc::new(Message::Tool {
+                id: "1".to_string(),
+                name: "fetch".to_string(),
+                result: "x".repeat(5000).into(),
+            }),
+            Arc::new(Message::User("continue".to_string())),
+        ];
+
+        let mut agent = AgentBuilder::new()
+            .llm(Arc::new(OverflowLLM))
+            .stop_condition(Box::new(SimpleStop))
+            .build()?;
+        assert!(matches!(
+            agent.run(history.clone(), &CancellationToken::new()).await,
+            Err(Error::ContextOverflow(_))
+        ));
+
+        // a history too short to summarize is not sent again unchanged
+        let mut agent = AgentBuilder::new()
+            .llm(Arc::new(OverflowLLM))
+            .recover_context_overflow()
+            .stop_condition(Box::new(SimpleStop))
+            .build()?;
+        assert!(matches!(
+            agent
+                .run(history[1..].to_vec(), &CancellationToken::new())
+                .await,
+            Err(Error::ContextOverflow(_))
+        ));
+
+        let mut agent = AgentBuilder::new()
+            .llm(Arc::new(OverflowLLM))
+            .spill_tool_results(ArtifactStore::new(&dir)?, 16 * 1024)
+            .recover_context_overflow()
+            .stop_condition(Box::new(SimpleStop))
+            .build()?;
+        let history = agent.run(history, &CancellationToken::new()).await?;
+
+        assert!(matches!(history.last().map(|m| m.as_ref()),
+            Some(Message::Assistant(content, _)) if content == "completed"));
+        assert!(history.iter().any(|m| matches!(m.as_ref(),
+            Message::Tool { result, .. } if result.contains("stored as artifact `fetch-0`"))));
+
+        std::fs::remove_dir_all(dir)?;
+        Ok(())
+    }
+
+    struct RepairLLM;
+
+    #[async_trait]
+    impl LLM for RepairLLM {
+        async fn completion<'a>(
+            &self,
+            request: CompletionRequest<'a>,
+        ) -> Result<CompletionResponse> {
+            let call = |args: &str| CompletionResponse {
+                content: String::new(),
+                tool_calls: vec![ToolCall {
+                    id: "call".to_string(),
+                    name: "double".to_string(),
+                    args: args.to_string(),
+                }],
+                usage: Default::default(),
+            };
+
+            let hopeless = matches!(request.messages.iter().next().map(|m| m.as_ref()),
+                Some(Message::User(content)) if content == "hopeless");
+
+            match request.messages.last().map(|m| m.as_ref()) {
+                Some(Message::User(_)) => Ok(call("{\"arg\":\"x\"}")),
+                Some(Message::Tool { result, .. }) if result.contains("could not be parsed") => {
+                    if hopeless || request.messages.len() <= 4 {
+                        Ok(call("not json"))
+                    } else {
+                        Ok(call("{\"arg\":1}"))
+                    }
+                }
+                _ => Ok(CompletionResponse {
+                    content: "completed".to_string(),
+                    ..Default::default()
+                }),
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_tool_arg_repair() -> Result<()> {
+        let mut agent = AgentBuilder::new()
+            .llm(Arc::new(RepairLLM))
+            .tool(Box::new(DoubleTool))
+            .stop_condition(Box::new(SimpleStop))
+            .build()?;
+
+        let history = agent
+            .run(
+                vec![Arc::new(Message::User("repairable".to_string()))],
+                &CancellationToken::new(),
+            )
+            .await?;
+
+        assert_eq!(history.len(), 8);
+        assert!(matches!(history[2].as_ref(),
+            Message::Tool { result, .. } if result.contains("\"arg\"") && result.contains("could not be parsed")));
+        assert!(matches!(history[6].as_ref(),
+            Message::Tool { result, .. } if result == "2 * 1 = 2"));
+
+        let mut agent = AgentBuilder::new()
+            .llm(Arc::new(RepairLLM))
+            .tool(Box::new(DoubleTool))
+            .stop_condition(Box::new(SimpleStop))
+            .build()?;
+
+        assert!(matches!(
+            agent
+                .run(
+                    vec![Arc::new(Message::User("hopeless".to_string()))],
+                    &CancellationToken::new(),
+                )
+                .await,
+            Err(Error::InvalidToolArgs { .. })
+        ));
+
+        Ok(())
+    }
+
+    #[cfg(not(feature = "native"))]
+    #[test]
+    fn test_step_timeout_needs_native() {
+        let res = AgentBuilder::new()
+            .llm(Arc::new(MockLLM))
+            .stop_condition(Box::new(SimpleStop))
+            .step_timeout(std::time::Duration::from_secs(60))
+            .build();
+        assert!(matches!(res, Err(Error::Unsupported(_))));
+    }
+
+    struct PendingLLM;
+
+    #[async_trait]
+    impl LLM for PendingLLM {
+        async fn completion<'a>(&self, _: CompletionRequest<'a>) -> Result<CompletionResponse> {
+            std::future::pending().await
+        }
+    }
+
+    #[tokio::test]
+    async fn test_cancellation() -> Result<()> {
+        let mut agent = AgentBuilder::new()
+            .llm(Arc::new(PendingLLM))
+            .stop_condition(Box::new(SimpleStop))
+            .build()?;
+
+        let cancel = CancellationToken::new();
+        let trigger = cancel.clone();
+        tokio::spawn(async move {
+            tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+            trigger.cancel();
+        });
+
+        assert!(matches!(
+            agent
+                .run(
+                    vec![Arc::new(Message::User("do stuff".to_string()))],
+                    &cancel
+                )
+                .await,
+            Err(Error::Cancelled(_))
+        ));
 
         Ok(())
     }
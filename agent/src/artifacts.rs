@@ -0,0 +1,248 @@
+use crate::llm::Message;
+use crate::{Error, History, Result};
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+const MAX_CHAR_LEN: u64 = 4;
+
+pub struct ArtifactStore {
+    dir: PathBuf,
+    next_id: AtomicU64,
+}
+
+impl ArtifactStore {
+    pub fn new(dir: &Path) -> Result<Arc<Self>> {
+        std::fs::create_dir_all(dir)?;
+        Ok(Arc::new(Self {
+            dir: dir.to_path_buf(),
+            next_id: AtomicU64::new(0),
+        }))
+    }
+
+    fn path(&self, id: &str) -> Result<PathBuf> {
+        if id.is_empty()
+            || !id
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-')
+        {
+            return Err(Error::ArtifactError(format!(
+                "invalid artifact id '{}'",
+                id
+            )));
+        }
+        Ok(self.dir.join(id))
+    }
+
+    pub fn put(&self, prefix: &str, content: &str) -> Result<String> {
+        self.put_bytes(prefix, content.as_bytes())
+    }
+
+    // stores binary content such as screenshots, which read cannot return as text
+    pub fn put_bytes(&self, prefix: &str, content: &[u8]) -> Result<String> {
+        let prefix = prefix
+            .chars()
+            .map(|c| if c.is_ascii_alphanumeric() { c } else { '_' })
+            .collect::<String>();
+        let id = format!("{}-{}", prefix, self.next_id.fetch_add(1, Ordering::SeqCst));
+
+        let mut file = std::fs::File::create(self.path(&id)?)?;
+        file.write_all(content)?;
+
+        Ok(id)
+    }
+
+    pub fn list(&self) -> Result<Vec<String>> {
+        let mut ids = std::fs::read_dir(&self.dir)?
+            .map(|entry| Ok(entry?.file_name().to_string_lossy().into_owned()))
+            .collect::<Result<Vec<_>>>()?;
+        ids.sort();
+        Ok(ids)
+    }
+
+    pub fn size(&self, id: &str) -> Result<u64> {
+        Ok(std::fs::metadata(self.path(id)?)?.len())
+    }
+
+    // reads at most len bytes from the offset on, both ends move back to the start of the
+    // character they fall into, or the end forward past it if the character is longer than len.
+    // Returns the byte range that was read with its text
+    pub fn read(&self, id: &str, offset: u64, len: usize) -> Result<(Range<u64>, String)> {
+        let mut file = std::fs::File::open(self.path(id)?)?;
+        // the bytes of the characters the ends fall into are read as well
+        let from = offset.saturating_sub(MAX_CHAR_LEN);
+        file.seek(SeekFrom::Start(from))?;
+        let mut buf = Vec::new();
+        file.take(
+            (offset - from)
+                .saturating_add(len as u64)
+                .saturating_add(MAX_CHAR_LEN),
+        )
+        .read_to_end(&mut buf)?;
+
+        let continues = |i: usize| i < buf.len() && buf[i] & 0xC0 == 0x80;
+        let mut start = ((offset - from) as usize).min(buf.len());
+        while start > 0 && continues(start) {
+            start -= 1;
+        }
+        let mut end = start.saturating_add(len).min(buf.len());
+        while end > start && continues(end) {
+            end -= 1;
+        }
+        if end == start && start < buf.len() {
+            end += 1;
+            while continues(end) {
+                end += 1;
+            }
+        }
+
+        Ok((
+            from + start as u64..from + end as u64,
+            String::from_utf8_lossy(&buf[start..end]).into_owned(),
+        ))
+    }
+}
+
+pub(crate) struct Spill {
+    pub store: Arc<ArtifactStore>,
+    pub threshold: usize,
+}
+
+const PREVIEW_LEN: usize = 2000;
+pub(crate) const SPILLED_PREFIX: &str = "[the output of this tool is ";
+
+fn floor_char_boundary(s: &str, index: usize) -> usize {
+    (0..=index.min(s.len()))
+        .rev()
+        .find(|i| s.is_char_boundary(*i))
+        .unwrap_or(0)
+}
+
+impl Spill {
+    pub fn apply(&self, history: &mut dyn History, from: usize) -> Result<()> {
+        self.spill(history, from, self.threshold)
+    }
+
+    // spills every tool result in the history that is longer than a preview, used to recover
+    // from context overflows
+    pub fn compact(&self, history: &mut dyn History) -> Result<()> {
+        self.spill(history, 0, PREVIEW_LEN)
+    }
+
+    fn spill(&self, history: &mut dyn History, from: usize, threshold: usize) -> Result<()> {
+        if history.len() <= from
+            || !history
+                .iter()
+                .skip(from)
+                .any(|m| should_spill(m, threshold))
+        {
+            return Ok(());
+        }
+
+        let appended = history.iter().skip(from).cloned().collect::<Vec<_>>();
+        history.truncate(from);
+
+        for message in appended {
+            match message.as_ref() {
+                Message::Tool { id, name, result } if should_spill(&message, threshold) => {
+                    let artifact = self.store.put(name, result.as_str())?;
+                    let preview = &result[..floor_char_boundary(result, PREVIEW_LEN)];
+                    history.append(Arc::new(Message::Tool {
+                        id: id.clone(),
+                        name: name.clone(),
+                        result: format!(
+                            "{}{} bytes and was stored as artifact `{}`, the first {} bytes are shown below, use the read_artifact tool to read the rest]\n{}",
+                            SPILLED_PREFIX,
+                            result.len(),
+                            artifact,
+                            preview.len(),
+                            preview
+                        )
+                        .into(),
+                    }));
+                }
+                _ => history.append(message),
+            }
+        }
+
+        Ok(())
+    }
+}
+
+fn should_spill(message: &Message, threshold: usize) -> bool {
+    matches!(message, Message::Tool { result, .. }
+        if result.len() > threshold && !result.starts_with(SPILLED_PREFIX))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{ArtifactStore, Spill};
+    use crate::Result;
+    use crate::llm::Message;
+    use std::sync::Arc;
+
+    #[test]
+    fn test_spill_tool_results() -> Result<()> {
+        let dir = std::env::temp_dir().join(format!("artifacts-test-{}", std::process::id()));
+        let spill = Spill {
+            store: ArtifactStore::new(&dir)?,
+            threshold: 10,
+        };
+
+        let mut history = vec![
+            Arc::new(Message::User("task".to_string())),
+            Arc::new(Message::Tool {
+                id: "1".to_string(),
+                name: "fetch".to_string(),
+                result: "x".repeat(5000).into(),
+            }),
+            Arc::new(Message::Tool {
+                id: "2".to_string(),
+                name: "fetch".to_string(),
+                result: "short".into(),
+            }),
+        ];
+
+        spill.apply(&mut history, 1)?;
+
+        assert_eq!(history.len(), 3);
+        match history[1].as_ref() {
+            Message::Tool { result, .. } => {
+                assert!(result.starts_with(
+                    "[the output of this tool is 5000 bytes and was stored as artifact `fetch-0`"
+                ));
+                assert!(result.len() < 2200);
+            }
+            _ => panic!("not a tool message"),
+        }
+        assert!(matches!(history[2].as_ref(), Message::Tool { result, .. } if result == "short"));
+
+        assert_eq!(spill.store.size("fetch-0")?, 5000);
+        assert_eq!(
+            spill.store.read("fetch-0", 4990, 100)?,
+            (4990..5000, "x".repeat(10))
+        );
+        assert!(spill.store.read("../fetch-0", 0, 10).is_err());
+        assert_eq!(spill.store.list()?, vec!["fetch-0".to_string()]);
+
+        // offsets inside a character move to its start, and a chunk shorter than a character
+        // still reads it
+        let id = spill.store.put("text", "aé€b")?;
+        assert_eq!(spill.store.read(&id, 2, 3)?, (1..3, "é".to_string()));
+        assert_eq!(spill.store.read(&id, 4, 1)?, (3..6, "€".to_string()));
+        assert_eq!(spill.store.read(&id, 10, 5)?, (7..7, String::new()));
+        assert_eq!(
+            spill.store.read(&id, 10, usize::MAX)?,
+            (7..7, String::new())
+        );
+        assert_eq!(
+            spill.store.read(&id, 1, usize::MAX)?,
+            (1..7, "é€b".to_string())
+        );
+
+        std::fs::remove_dir_all(dir)?;
+        Ok(())
+    }
+}
@@ -0,0 +1,201 @@
+use crate::callbacks::Callback;
+use crate::callbacks::tee::{HistoryChanges, LogSink, Tracker};
+use crate::llm::Message;
+use crate::{History, Result};
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+use std::io::Write;
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum EventKind {
+    System,
+    User,
+    Developer,
+    Assistant,
+    ToolCall,
+    ToolResult,
+    // messages were removed or rewritten, e.g. by compaction, the text says how many. The new
+    // messages that replace them follow as events of their own
+    HistoryCleared,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Event {
+    // milliseconds since the unix epoch
+    pub time_ms: u64,
+    pub agent: String,
+    pub step: u32,
+    pub kind: EventKind,
+    // the name of the tool for tool calls and results
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tool: Option<String>,
+    pub text: String,
+}
+
+// writes every new message of the history as one json event per line, so that other processes
+// can follow a run while it is in progress
+pub struct EventLogger<W: Write + Send> {
+    name: String,
+    tracker: Tracker,
+    writer: W,
+    step: u32,
+}
+
+fn now_ms() -> u64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|d| d.as_millis() as u64)
+        .unwrap_or_default()
+}
+
+impl<W: Write + Send> EventLogger<W> {
+    pub fn new(name: &str, writer: W) -> Box<Self> {
+        Box::new(Self {
+            name: name.to_string(),
+            tracker: Tracker::default(),
+            writer,
+            step: 0,
+        })
+    }
+
+    fn write_event(&mut self, kind: EventKind, tool: Option<&str>, text: &str) -> Result<()> {
+        let event = Event {
+            time_ms: now_ms(),
+            agent: self.name.clone(),
+            step: self.step,
+            kind,
+            tool: tool.map(String::from),
+            text: text.to_string(),
+        };
+        writeln!(self.writer, "{}", serde_json::to_string(&event)?)?;
+        Ok(())
+    }
+
+    fn write_message(&mut self, message: &Message) -> Result<()> {
+        match message {
+            Message::System(content) => self.write_event(EventKind::System, None, content),
+            Message::User(content) => self.write_event(EventKind::User, None, content),
+            Message::Developer(content) => self.write_event(EventKind::Developer, None, content),
+            // the images are left out like in the markdown logs
+            Message::UserImages(content, _) => self.write_event(EventKind::User, None, content),
+            Message::Assistant(content, tool_calls) => {
+                if !content.is_empty() {
+                    self.write_event(EventKind::Assistant, None, content)?;
+                }
+                tool_calls.iter().try_for_each(|call| {
+                    self.write_event(EventKind::ToolCall, Some(&call.name), &call.args)
+                })
+            }
+            Message::Tool { name, result, .. } => {
+                self.write_event(EventKind::ToolResult, Some(name), result)
+            }
+        }
+    }
+}
+
+impl<W: Write + Send> LogSink for EventLogger<W> {
+    // readers follow the events as they are written, so the messages that were kept are not
+    // written again when others were removed or rewritten, a history_cleared event marks that
+    fn write_changes(&mut self, changes: &HistoryChanges) -> Result<()> {
+        self.step = changes.step;
+        if changes.reset || changes.removed > 0 {
+            let text = format!(
+                "{} messages were removed or rewritten, {} were kept",
+                changes.removed,
+                changes.history.len() - changes.new.len()
+            );
+            self.write_event(EventKind::HistoryCleared, None, &text)?;
+        }
+        for message in &changes.new {
+            self.write_message(message)?;
+        }
+        self.writer.flush()?;
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl<W: Write + Send> Callback for EventLogger<W> {
+    async fn call(&mut self, history: &mut dyn History) -> Result<()> {
+        let changes = self.tracker.changes(history);
+        self.write_changes(&changes)?;
+        self.tracker.advance(history);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Event, EventKind, EventLogger};
+    use crate::Result;
+    use crate::callbacks::Callback;
+    use crate::llm::Message;
+    use crate::tools::ToolCall;
+    use std::sync::Arc;
+
+    fn events(buf: &[u8]) -> Vec<Event> {
+        std::str::from_utf8(buf)
+            .unwrap()
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn test_event_logger() -> Result<()> {
+        let mut buf = Vec::new();
+        let mut logger = EventLogger::new("agent", &mut buf);
+
+        let mut history = vec![
+            Arc::new(Message::System("system".to_string())),
+            Arc::new(Message::User("task".to_string())),
+        ];
+        logger.call(&mut history).await?;
+
+        history.push(Arc::new(Message::Assistant(
+            String::new(),
+            vec![ToolCall {
+                id: "1".to_string(),
+                name: "search".to_string(),
+                args: "{}".to_string(),
+            }],
+        )));
+        history.push(Arc::new(Message::Tool {
+            id: "1".to_string(),
+            name: "search".to_string(),
+            result: "found".into(),
+        }));
+        logger.call(&mut history).await?;
+
+        // compaction replaces the tool call and result with a summary
+        history.truncate(2);
+        history.push(Arc::new(Message::User("summary".to_string())));
+        logger.call(&mut history).await?;
+        drop(logger);
+
+        let kinds = events(&buf).iter().map(|e| e.kind).collect::<Vec<_>>();
+        assert_eq!(
+            kinds,
+            vec![
+                EventKind::System,
+                EventKind::User,
+                EventKind::ToolCall,
+                EventKind::ToolResult,
+                EventKind::HistoryCleared,
+                EventKind::User,
+            ]
+        );
+        let events = events(&buf);
+        assert_eq!(
+            events[4].text,
+            "2 messages were removed or rewritten, 2 were kept"
+        );
+        assert_eq!(events[5].text, "summary");
+        assert_eq!(events[3].tool.as_deref(), Some("search"));
+        assert_eq!(events[3].step, 1);
+        assert_eq!(events[3].agent, "agent");
+
+        Ok(())
+    }
+}
@@ -1,13 +1,15 @@
+use crate::History;
 use crate::Result;
 use crate::callbacks::Callback;
+use crate::callbacks::tee::{HistoryChanges, LogSink, Tracker};
 use crate::llm::Message;
 use async_trait::async_trait;
 use std::io::Write;
+use std::sync::Arc;
 
 pub struct MessageLogger<W: Write + Send> {
-    last_hashes: Vec<u64>,
+    tracker: Tracker,
     writer: W,
-    step: u32,
 }
 
 impl<W: Write + Send> MessageLogger<W> {
@@ -15,20 +17,31 @@ impl<W: Write + Send> MessageLogger<W> {
         write!(writer, "## {}\n\n", name)?;
 
         Ok(Box::new(Self {
-            last_hashes: Vec::new(),
-            writer: writer,
-            step: 0,
+            tracker: Tracker::default(),
+            writer,
         }))
     }
 
-    fn display_messages(&mut self, messages: &[Message]) -> Result<()> {
-        write!(self.writer, "### Step {}\n", self.step)?;
+    fn display_messages<'a>(
+        &mut self,
+        step: u32,
+        messages: impl Iterator<Item = &'a Arc<Message>>,
+        removed: usize,
+    ) -> Result<()> {
+        writeln!(self.writer, "### Step {}", step)?;
+        if removed > 0 {
+            write!(
+                self.writer,
+                "[{} EARLIER MESSAGES REMOVED OR REWRITTEN]\n\n",
+                removed
+            )?;
+        }
 
         messages
-            .iter()
+            .into_iter()
             .try_for_each(|m| write!(self.writer, "{}", m))?;
 
-        write!(self.writer, "---\n")?;
+        writeln!(self.writer, "---")?;
 
         Ok(())
     }
@@ -37,35 +50,108 @@ impl<W: Write + Send> MessageLogger<W> {
         write!(self.writer, "## [HISTORY CLEARED]\n\n")?;
         Ok(())
     }
+}
 
-    fn prefix_match_len(&self, new_hashes: &[u64]) -> usize {
-        new_hashes
-            .iter()
-            .zip(self.last_hashes.iter())
-            .filter(|&(a, b)| *a == *b)
-            .count()
+impl<W: Write + Send> LogSink for MessageLogger<W> {
+    // compaction replaces some messages and keeps others, the history is only shown as cleared
+    // if none of the logged messages are left
+    fn write_changes(&mut self, changes: &HistoryChanges) -> Result<()> {
+        if changes.reset {
+            self.display_history_cleared()?;
+            self.display_messages(changes.step, changes.history.iter(), 0)?;
+        } else {
+            self.display_messages(changes.step, changes.new.iter().copied(), changes.removed)?;
+        }
+
+        self.writer.flush()?;
+        Ok(())
     }
 }
 
 #[async_trait]
 impl<W: Write + Send> Callback for MessageLogger<W> {
-    async fn call(&mut self, messages: Vec<Message>) -> Result<Vec<Message>> {
-        let new_hashes = messages.iter().map(Message::get_hash).collect::<Vec<_>>();
+    async fn call(&mut self, history: &mut dyn History) -> Result<()> {
+        let changes = self.tracker.changes(history);
+        self.write_changes(&changes)?;
+        self.tracker.advance(history);
+        Ok(())
+    }
+}
 
-        if new_hashes.len() < self.last_hashes.len()
-            || self.prefix_match_len(&new_hashes) != self.last_hashes.len()
-        {
-            self.display_history_cleared()?;
-            self.display_messages(&messages)?;
-        } else {
-            self.display_messages(&messages[self.last_hashes.len()..])?;
-        }
+#[cfg(test)]
+mod tests {
+    use super::MessageLogger;
+    use crate::Result;
+    use crate::callbacks::Callback;
+    use crate::llm::Message;
+    use crate::{History, tools::ToolCall};
+    use std::sync::Arc;
 
-        self.writer.flush()?;
+    #[tokio::test]
+    async fn test_message_logger() -> Result<()> {
+        let mut buf = Vec::new();
+        let mut logger = MessageLogger::new("agent", &mut buf)?;
+        let user = |text: &str| Arc::new(Message::User(text.to_string()));
+
+        let mut history = vec![
+            Arc::new(Message::System("system".to_string())),
+            user("task"),
+        ];
+        logger.call(&mut history).await?;
+
+        // the same content twice is still two messages
+        history.push(Arc::new(Message::Assistant(
+            String::new(),
+            vec![ToolCall {
+                id: "1".to_string(),
+                name: "search".to_string(),
+                args: "{}".to_string(),
+            }],
+        )));
+        history.push(user("task"));
+        logger.call(&mut history).await?;
+
+        // compaction rewrites a message in the middle
+        history.replace(2, user("summary"));
+        logger.call(&mut history).await?;
+
+        history.truncate(0);
+        history.push(user("new task"));
+        logger.call(&mut history).await?;
+        drop(logger);
+
+        assert_eq!(
+            String::from_utf8(buf).unwrap(),
+            "## agent
+
+### Step 0
+__System:__ system
 
-        self.step += 1;
-        self.last_hashes = new_hashes;
+__User:__ task
 
-        Ok(messages)
+---
+### Step 1
+__Assistant:__ 
+- search (1)
+\t- `{}
+`
+__User:__ task
+
+---
+### Step 2
+[1 EARLIER MESSAGES REMOVED OR REWRITTEN]
+
+__User:__ summary
+
+---
+## [HISTORY CLEARED]
+
+### Step 3
+__User:__ new task
+
+---
+"
+        );
+        Ok(())
     }
 }
@@ -1,26 +1,40 @@
-use crate::Result;
-use crate::llm::Message;
 use crate::tools::SummarizeHistory;
+use crate::{History, Result};
 use async_trait::async_trait;
 
+mod events;
 mod logger;
+mod profiler;
+mod rotate;
+mod tee;
+pub use events::{Event, EventKind, EventLogger};
 pub use logger::MessageLogger;
+pub use profiler::{Profiler, StepProfile};
+pub use rotate::{RotatingFile, Rotation, read_segment, segments};
+pub use tee::{HistoryChanges, LogSink, TeeLogger};
 
 #[async_trait]
 pub trait Callback {
-    async fn call(&mut self, messages: Vec<Message>) -> Result<Vec<Message>>;
+    async fn call(&mut self, history: &mut dyn History) -> Result<()>;
 
     async fn on_agent_start(&mut self) -> Result<()> {
         Ok(())
     }
+
+    // the name the callback is profiled under, its type name without the module path
+    fn name(&self) -> &'static str {
+        let name = std::any::type_name::<Self>();
+        let name = name.split('<').next().unwrap_or(name);
+        name.rsplit("::").next().unwrap_or(name)
+    }
 }
 
 #[async_trait]
 impl Callback for SummarizeHistory {
-    async fn call(&mut self, messages: Vec<Message>) -> Result<Vec<Message>> {
-        if messages.iter().map(Message::ntokens).sum::<usize>() > 5000 {
-            return self.summarize_history(messages).await;
+    async fn call(&mut self, history: &mut dyn History) -> Result<()> {
+        if history.token_count() > 5000 {
+            return self.summarize_history(history).await;
         }
-        Ok(messages)
+        Ok(())
     }
 }
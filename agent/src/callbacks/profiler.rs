@@ -0,0 +1,127 @@
+use crate::Result;
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+// where the time of one agent step went
+#[derive(Clone, Debug, Default)]
+pub struct StepProfile {
+    // refreshing the system prompt and collecting the messages of the context providers
+    pub context: Duration,
+    // the completion request, both attempts if the context overflowed
+    pub llm: Duration,
+    // shrinking the history after the context overflowed, including the summary request
+    pub compaction: Duration,
+    // every tool call of the step by tool name, spilling large results included
+    pub tools: Vec<(String, Duration)>,
+    // every callback by name, this is where the logging happens
+    pub callbacks: Vec<(String, Duration)>,
+    // serializing and writing the checkpoint
+    pub checkpoint: Duration,
+}
+
+// the folded format separates frames with ';' and the count with a space
+fn frame(name: &str) -> String {
+    name.replace([';', ' '], "_")
+}
+
+impl StepProfile {
+    fn stacks(&self, agent: &str) -> Vec<(String, Duration)> {
+        let agent = frame(agent);
+        let mut stacks = vec![
+            (format!("{};context", agent), self.context),
+            (format!("{};llm", agent), self.llm),
+            (format!("{};compaction", agent), self.compaction),
+            (format!("{};checkpoint", agent), self.checkpoint),
+        ];
+        for (name, time) in &self.tools {
+            stacks.push((format!("{};tools;{}", agent, frame(name)), *time));
+        }
+        for (name, time) in &self.callbacks {
+            stacks.push((format!("{};callbacks;{}", agent, frame(name)), *time));
+        }
+        stacks
+    }
+}
+
+// sums the step profiles of an agent by phase and rewrites them after every step as folded
+// stacks, one "agent;phase;name microseconds" line each, which flamegraph tools such as
+// inferno-flamegraph render directly
+pub struct Profiler {
+    path: PathBuf,
+    stacks: BTreeMap<String, Duration>,
+}
+
+impl Profiler {
+    pub fn new(path: &Path) -> Box<Self> {
+        Box::new(Self {
+            path: path.to_path_buf(),
+            stacks: BTreeMap::new(),
+        })
+    }
+
+    pub fn record(&mut self, agent: &str, profile: &StepProfile) -> Result<()> {
+        for (stack, time) in profile.stacks(agent) {
+            *self.stacks.entry(stack).or_default() += time;
+        }
+        std::fs::write(&self.path, self.folded())?;
+        Ok(())
+    }
+
+    fn folded(&self) -> String {
+        self.stacks
+            .iter()
+            .filter(|(_, time)| !time.is_zero())
+            .map(|(stack, time)| format!("{} {}\n", stack, time.as_micros()))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Profiler, StepProfile};
+    use crate::Result;
+    use std::time::Duration;
+
+    #[test]
+    fn test_profiler() -> Result<()> {
+        let path = std::env::temp_dir().join(format!("profile-{}.folded", std::process::id()));
+        let ms = Duration::from_millis;
+        let mut profiler = Profiler::new(&path);
+        profiler.record(
+            "sub agent",
+            &StepProfile {
+                context: ms(1),
+                llm: ms(900),
+                tools: vec![("web_fetch".to_string(), ms(300))],
+                callbacks: vec![("MessageLogger".to_string(), ms(2))],
+                ..Default::default()
+            },
+        )?;
+        profiler.record(
+            "sub agent",
+            &StepProfile {
+                llm: ms(100),
+                tools: vec![
+                    ("web_fetch".to_string(), ms(200)),
+                    ("calculate".to_string(), ms(0)),
+                ],
+                checkpoint: ms(5),
+                ..Default::default()
+            },
+        )?;
+
+        let folded = std::fs::read_to_string(&path)?;
+        std::fs::remove_file(&path)?;
+        assert_eq!(
+            folded,
+            "sub_agent;callbacks;MessageLogger 2000
+sub_agent;checkpoint 5000
+sub_agent;context 1000
+sub_agent;llm 1000000
+sub_agent;tools;web_fetch 500000
+"
+        );
+        Ok(())
+    }
+}
@@ -0,0 +1,206 @@
+use crate::Result;
+use std::fs::File;
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+
+// when a log file is rotated and how many of its old segments are kept
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Rotation {
+    pub max_bytes: u64,
+    // old segments kept besides the current file, older ones are deleted
+    pub max_files: usize,
+    // gzip old segments
+    pub compress: bool,
+}
+
+// a log file that is moved aside once it grows past the size limit. The old segments are named
+// after the file with a number before the extension, name.1.ext being the newest. Files are
+// only rotated on flush, which the loggers call at the end of every step, so a step is never
+// split across two segments
+pub struct RotatingFile {
+    path: PathBuf,
+    rotation: Option<Rotation>,
+    file: File,
+    written: u64,
+}
+
+fn segment(path: &Path, index: usize, compressed: bool) -> PathBuf {
+    let stem = path
+        .file_stem()
+        .unwrap_or_default()
+        .to_string_lossy()
+        .into_owned();
+    let mut name = match path.extension() {
+        Some(extension) => format!("{}.{}.{}", stem, index, extension.to_string_lossy()),
+        None => format!("{}.{}", stem, index),
+    };
+    if compressed {
+        name.push_str(".gz");
+    }
+    path.with_file_name(name)
+}
+
+// the existing segment with the index, compressed or not
+fn existing_segment(path: &Path, index: usize) -> Option<PathBuf> {
+    [false, true]
+        .into_iter()
+        .map(|compressed| segment(path, index, compressed))
+        .find(|path| path.exists())
+}
+
+/// The old segments of a rotated log file that are still kept, oldest first, without the file
+/// itself.
+pub fn segments(path: &Path) -> Vec<PathBuf> {
+    let mut segments = (1..)
+        .map_while(|index| existing_segment(path, index))
+        .collect::<Vec<_>>();
+    segments.reverse();
+    segments
+}
+
+/// Reads a log file or one of its segments, compressed segments are decompressed.
+pub fn read_segment(path: &Path) -> std::io::Result<Vec<u8>> {
+    let mut file = File::open(path)?;
+    let mut content = Vec::new();
+    if path.extension().is_some_and(|e| e == "gz") {
+        flate2::read::GzDecoder::new(file).read_to_end(&mut content)?;
+    } else {
+        file.read_to_end(&mut content)?;
+    }
+    Ok(content)
+}
+
+impl RotatingFile {
+    // without a rotation the file just grows, append keeps the content of an existing file
+    pub fn open(path: &Path, rotation: Option<Rotation>, append: bool) -> Result<Self> {
+        let file = std::fs::OpenOptions::new()
+            .create(true)
+            .write(true)
+            .append(append)
+            .truncate(!append)
+            .open(path)?;
+        Ok(Self {
+            path: path.to_path_buf(),
+            rotation,
+            written: file.metadata()?.len(),
+            file,
+        })
+    }
+
+    fn segment(&self, index: usize, compressed: bool) -> PathBuf {
+        segment(&self.path, index, compressed)
+    }
+
+    fn existing_segment(&self, index: usize) -> Option<PathBuf> {
+        existing_segment(&self.path, index)
+    }
+
+    fn rotate(&mut self, rotation: Rotation) -> std::io::Result<()> {
+        if rotation.max_files > 0 {
+            if let Some(oldest) = self.existing_segment(rotation.max_files) {
+                std::fs::remove_file(oldest)?;
+            }
+            for index in (1..rotation.max_files).rev() {
+                if let Some(segment) = self.existing_segment(index) {
+                    let compressed = segment.extension().is_some_and(|e| e == "gz");
+                    std::fs::rename(&segment, self.segment(index + 1, compressed))?;
+                }
+            }
+
+            if rotation.compress {
+                let mut encoder = flate2::write::GzEncoder::new(
+                    File::create(self.segment(1, true))?,
+                    flate2::Compression::default(),
+                );
+                std::io::copy(&mut File::open(&self.path)?, &mut encoder)?;
+                encoder.finish()?;
+            } else {
+                std::fs::copy(&self.path, self.segment(1, false))?;
+            }
+        }
+
+        // the file is truncated rather than replaced so that readers following it by path, such
+        // as the tail command, see it shrink and start over
+        self.file = std::fs::OpenOptions::new()
+            .write(true)
+            .truncate(true)
+            .open(&self.path)?;
+        self.written = 0;
+        Ok(())
+    }
+}
+
+impl Write for RotatingFile {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        let written = self.file.write(buf)?;
+        self.written += written as u64;
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.file.flush()?;
+        match self.rotation {
+            Some(rotation) if self.written >= rotation.max_bytes => self.rotate(rotation),
+            _ => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{RotatingFile, Rotation, read_segment, segments};
+    use crate::Result;
+    use std::io::{Read, Write};
+
+    #[test]
+    fn test_rotation() -> Result<()> {
+        let dir = std::env::temp_dir().join(format!("rotate-{}", std::process::id()));
+        std::fs::create_dir_all(&dir)?;
+        let path = dir.join("agent.events.jsonl");
+        let rotation = Rotation {
+            max_bytes: 10,
+            max_files: 2,
+            compress: true,
+        };
+        let mut file = RotatingFile::open(&path, Some(rotation), false)?;
+
+        // the size limit is only checked on flush
+        for step in 0..4 {
+            writeln!(file, "step {}", step)?;
+            writeln!(file, "{}", "x".repeat(8))?;
+            file.flush()?;
+        }
+        writeln!(file, "step 4")?;
+        file.flush()?;
+
+        let mut names = std::fs::read_dir(&dir)?
+            .map(|entry| entry.unwrap().file_name().to_string_lossy().into_owned())
+            .collect::<Vec<_>>();
+        names.sort();
+        assert_eq!(
+            names,
+            vec![
+                "agent.events.1.jsonl.gz",
+                "agent.events.2.jsonl.gz",
+                "agent.events.jsonl"
+            ]
+        );
+        assert_eq!(std::fs::read_to_string(&path)?, "step 4\n");
+
+        let mut newest = String::new();
+        flate2::read::GzDecoder::new(std::fs::File::open(dir.join("agent.events.1.jsonl.gz"))?)
+            .read_to_string(&mut newest)?;
+        assert_eq!(newest, "step 3\nxxxxxxxx\n");
+        let segments = segments(&path);
+        assert_eq!(
+            segments,
+            vec![
+                dir.join("agent.events.2.jsonl.gz"),
+                dir.join("agent.events.1.jsonl.gz")
+            ]
+        );
+        assert_eq!(read_segment(&segments[1])?, newest.as_bytes());
+        std::fs::remove_dir_all(&dir)?;
+        Ok(())
+    }
+}
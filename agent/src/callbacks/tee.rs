@@ -0,0 +1,178 @@
+use crate::callbacks::Callback;
+use crate::llm::Message;
+use crate::{History, Result};
+use async_trait::async_trait;
+use std::sync::Arc;
+
+// how the history changed since the previous step
+pub struct HistoryChanges<'a> {
+    pub step: u32,
+    // none of the messages of the previous step are left
+    pub reset: bool,
+    // messages of the previous step that were removed or rewritten, e.g. by compaction
+    pub removed: usize,
+    // messages that were not in the history at the previous step, in history order
+    pub new: Vec<&'a Arc<Message>>,
+    pub history: &'a dyn History,
+}
+
+// finds the changes of the history between steps
+#[derive(Default)]
+pub(crate) struct Tracker {
+    // the messages of the history at the last step with their ids, a message keeps its id while
+    // it stays in the history, however the messages around it are rewritten
+    logged: Vec<(Arc<Message>, u64)>,
+    next_id: u64,
+    step: u32,
+}
+
+impl Tracker {
+    // the id of a message that was in the history at the last step, messages are the same
+    // message if they share their allocation, whatever their content
+    fn id(&self, message: &Arc<Message>) -> Option<u64> {
+        self.logged
+            .iter()
+            .find(|(logged, _)| Arc::ptr_eq(logged, message))
+            .map(|(_, id)| *id)
+    }
+
+    pub(crate) fn changes<'a>(&self, history: &'a dyn History) -> HistoryChanges<'a> {
+        let (kept, new): (Vec<_>, Vec<_>) = history.iter().partition(|m| self.id(m).is_some());
+        HistoryChanges {
+            step: self.step,
+            reset: !self.logged.is_empty() && kept.is_empty(),
+            removed: self.logged.len() - kept.len(),
+            new,
+            history,
+        }
+    }
+
+    pub(crate) fn advance(&mut self, history: &dyn History) {
+        let mut logged = Vec::with_capacity(history.len());
+        for message in history.iter() {
+            let id = self.id(message).unwrap_or_else(|| {
+                self.next_id += 1;
+                self.next_id
+            });
+            logged.push((message.clone(), id));
+        }
+        self.logged = logged;
+        self.step += 1;
+    }
+}
+
+// a logger that writes the changes found by another logger's diffing, see TeeLogger
+pub trait LogSink: Send {
+    fn write_changes(&mut self, changes: &HistoryChanges) -> Result<()>;
+}
+
+// diffs the history once per step and hands the changes to every sink, e.g. a MessageLogger for
+// humans and an EventLogger for machines
+pub struct TeeLogger {
+    tracker: Tracker,
+    sinks: Vec<Box<dyn LogSink>>,
+}
+
+impl TeeLogger {
+    pub fn new(sinks: Vec<Box<dyn LogSink>>) -> Box<Self> {
+        Box::new(Self {
+            tracker: Tracker::default(),
+            sinks,
+        })
+    }
+}
+
+#[async_trait]
+impl Callback for TeeLogger {
+    async fn call(&mut self, history: &mut dyn History) -> Result<()> {
+        let changes = self.tracker.changes(history);
+        for sink in &mut self.sinks {
+            sink.write_changes(&changes)?;
+        }
+        self.tracker.advance(history);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{TeeLogger, Tracker};
+    use crate::Result;
+    use crate::callbacks::{Callback, Event, EventKind, EventLogger, MessageLogger};
+    use crate::llm::Message;
+    use std::io::Write;
+    use std::sync::{Arc, Mutex};
+
+    #[derive(Clone, Default)]
+    struct Buffer(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for Buffer {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.lock().unwrap().write(buf)
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    impl Buffer {
+        fn text(&self) -> String {
+            String::from_utf8(self.0.lock().unwrap().clone()).unwrap()
+        }
+    }
+
+    #[tokio::test]
+    async fn test_tee_logger() -> Result<()> {
+        let (markdown, events) = (Buffer::default(), Buffer::default());
+        let mut logger = TeeLogger::new(vec![
+            MessageLogger::new("agent", markdown.clone())?,
+            EventLogger::new("agent", events.clone()),
+        ]);
+
+        let mut history = vec![Arc::new(Message::User("task".to_string()))];
+        logger.call(&mut history).await?;
+        history.push(Arc::new(Message::Assistant("answer".to_string(), vec![])));
+        logger.call(&mut history).await?;
+
+        assert_eq!(
+            markdown.text(),
+            "## agent\n\n### Step 0\n__User:__ task\n\n---\n### Step 1\n__Assistant:__ answer\n\n---\n"
+        );
+        let events = events
+            .text()
+            .lines()
+            .map(|line| serde_json::from_str::<Event>(line).unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            events.iter().map(|e| (e.kind, e.step)).collect::<Vec<_>>(),
+            vec![(EventKind::User, 0), (EventKind::Assistant, 1)]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_tracker_ids() {
+        let user = |text: &str| Arc::new(Message::User(text.to_string()));
+        let mut tracker = Tracker::default();
+        let mut history = vec![user("task"), user("step"), user("step")];
+        tracker.advance(&history);
+        let ids = |tracker: &Tracker| tracker.logged.iter().map(|(_, id)| *id).collect::<Vec<_>>();
+        assert_eq!(ids(&tracker), vec![1, 2, 3]);
+
+        // a rewritten message gets a new id and the others keep theirs
+        history[1] = user("summary");
+        let changes = tracker.changes(&history);
+        assert_eq!((changes.removed, changes.new.len()), (1, 1));
+        tracker.advance(&history);
+        assert_eq!(ids(&tracker), vec![1, 4, 3]);
+
+        // ids are not reused once their messages are gone
+        history.truncate(0);
+        tracker.advance(&history);
+        history.push(user("task"));
+        assert_eq!(tracker.changes(&history).new.len(), 1);
+        tracker.advance(&history);
+        assert_eq!(ids(&tracker), vec![5]);
+    }
+}
@@ -0,0 +1,86 @@
+use crate::Result;
+use crate::llm::Message;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::path::Path;
+use std::sync::Arc;
+
+// the history of an agent after its last completed step together with the state of its tools,
+// written after every step so that a crashed agent can resume where it stopped
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct Checkpoint {
+    pub step: usize,
+    pub history: Vec<Message>,
+    // state of the tools that have any, by tool name
+    #[serde(default)]
+    pub tools: BTreeMap<String, serde_json::Value>,
+}
+
+impl Checkpoint {
+    pub fn load(path: &Path) -> Result<Self> {
+        let file = std::io::BufReader::new(std::fs::File::open(path)?);
+        Ok(serde_json::from_reader(file)?)
+    }
+
+    // written to a temporary file first, so a crash while writing keeps the previous checkpoint
+    pub fn save(&self, path: &Path) -> Result<()> {
+        let tmp = path.with_extension("tmp");
+        {
+            let mut file = std::io::BufWriter::new(std::fs::File::create(&tmp)?);
+            serde_json::to_writer(&mut file, self)?;
+            std::io::Write::flush(&mut file)?;
+        }
+        std::fs::rename(&tmp, path)?;
+        Ok(())
+    }
+
+    pub fn messages(&self) -> Vec<Arc<Message>> {
+        self.history.iter().cloned().map(Arc::new).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Checkpoint;
+    use crate::Result;
+    use crate::llm::{Message, ToolResult};
+    use crate::tools::ToolCall;
+
+    #[test]
+    fn test_checkpoint() -> Result<()> {
+        let path = std::env::temp_dir().join(format!("checkpoint-{}.json", std::process::id()));
+        let checkpoint = Checkpoint {
+            step: 2,
+            history: vec![
+                Message::User("task".to_string()),
+                Message::Assistant(
+                    String::new(),
+                    vec![ToolCall {
+                        id: "1".to_string(),
+                        name: "quote".to_string(),
+                        args: "{}".to_string(),
+                    }],
+                ),
+                Message::Tool {
+                    id: "1".to_string(),
+                    name: "quote".to_string(),
+                    result: ToolResult::json(&serde_json::json!({"price": 1.5}))?,
+                },
+            ],
+            tools: [("memory_set_key".to_string(), serde_json::json!({"a": "b"}))].into(),
+        };
+        checkpoint.save(&path)?;
+
+        let loaded = Checkpoint::load(&path)?;
+        std::fs::remove_file(&path)?;
+        assert_eq!(loaded.step, 2);
+        assert_eq!(loaded.tools, checkpoint.tools);
+        let hashes = |c: &Checkpoint| c.history.iter().map(|m| m.get_hash()).collect::<Vec<_>>();
+        assert_eq!(hashes(&loaded), hashes(&checkpoint));
+        assert!(matches!(
+            &loaded.history[2],
+            Message::Tool { result, .. } if result.data() == Some(&serde_json::json!({"price": 1.5}))
+        ));
+        Ok(())
+    }
+}
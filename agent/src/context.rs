@@ -0,0 +1,247 @@
+use crate::llm::Message;
+use crate::tools::ToolDefinition;
+use std::sync::Arc;
+
+/// How much a part of the context matters when the messages of a request have to fit a token
+/// budget, parts of lower priority are left out first.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Priority {
+    /// left out before the older turns of the history, e.g. recalled memory
+    Low,
+    #[default]
+    Normal,
+    /// kept ahead of the recent turns of the history, e.g. the todo list
+    High,
+    /// always sent, whatever the budget, like the system prompt and the task
+    Pinned,
+}
+
+// the latest turns of the history, which are kept ahead of context of normal priority
+const RECENT_TURNS: usize = 4;
+
+// the room left in the budget for the reply of the model, which shares the context window with
+// the request
+pub(crate) const RESERVED_OUTPUT_TOKENS: usize = 4096;
+
+// fits the messages of every request into a token budget instead of sending the whole history.
+// The system prompt, the task, the latest turn, and pinned context are always sent, the budget
+// that is left goes to the context of high priority, the recent turns, the context of normal
+// priority, the older turns from the newest to the oldest, and the context of low priority, in
+// this order. Turns are left out whole and only from the start, so that the model never sees a
+// tool result without its call, and the history itself is not changed. The tool definitions and
+// the reply are sent with every request, so their tokens are reserved from the budget.
+pub(crate) struct ContextBudget {
+    max_tokens: usize,
+}
+
+fn tokens(messages: &[Arc<Message>]) -> usize {
+    messages.iter().map(|m| m.ntokens()).sum()
+}
+
+// the tokens of the tool definitions, estimated at four characters of the schemas per token
+pub(crate) fn tool_tokens(tools: &[ToolDefinition]) -> usize {
+    tools
+        .iter()
+        .map(|tool| {
+            tool.name.len().div_ceil(4)
+                + tool.desc.split_whitespace().count()
+                + tool.params.to_string().len().div_ceil(4)
+        })
+        .sum()
+}
+
+// splits the history into turns, a message other than a tool result with the results after it
+fn turns(messages: &[Arc<Message>]) -> Vec<&[Arc<Message>]> {
+    let mut starts = messages
+        .iter()
+        .enumerate()
+        .filter(|(i, m)| *i == 0 || !matches!(m.as_ref(), Message::Tool { .. }))
+        .map(|(i, _)| i)
+        .collect::<Vec<_>>();
+    starts.push(messages.len());
+    starts
+        .windows(2)
+        .map(|range| &messages[range[0]..range[1]])
+        .collect()
+}
+
+impl ContextBudget {
+    // the budget of the messages, reserved tokens of the max_tokens are kept for the rest of
+    // the request and the reply
+    pub(crate) fn new(max_tokens: usize, reserved: usize) -> Self {
+        Self {
+            max_tokens: max_tokens.saturating_sub(reserved),
+        }
+    }
+
+    // the messages to send, the history followed by the context messages that fit
+    pub(crate) fn assemble(
+        &self,
+        history: &[Arc<Message>],
+        context: Vec<(Priority, Vec<Arc<Message>>)>,
+    ) -> Vec<Arc<Message>> {
+        // the system prompt and the task lead the history
+        let mut head = usize::from(matches!(
+            history.first().map(|m| m.as_ref()),
+            Some(Message::System(_))
+        ));
+        if matches!(
+            history.get(head).map(|m| m.as_ref()),
+            Some(Message::User(_) | Message::UserImages(..))
+        ) {
+            head += 1;
+        }
+        let turns = turns(&history[head..]);
+
+        let mut used = tokens(&history[..head]) + turns.last().map_or(0, |turn| tokens(turn));
+        let mut sent = context
+            .iter()
+            .map(|(priority, _)| *priority == Priority::Pinned)
+            .collect::<Vec<_>>();
+        used += context
+            .iter()
+            .filter(|(priority, _)| *priority == Priority::Pinned)
+            .map(|(_, messages)| tokens(messages))
+            .sum::<usize>();
+
+        let mut fits = |messages: &[Arc<Message>]| {
+            let n = tokens(messages);
+            let fits = used + n <= self.max_tokens;
+            if fits {
+                used += n;
+            }
+            fits
+        };
+        let mut fill = |priority: Priority, fits: &mut dyn FnMut(&[Arc<Message>]) -> bool| {
+            for (i, (p, messages)) in context.iter().enumerate() {
+                if *p == priority && !sent[i] {
+                    sent[i] = fits(messages);
+                }
+            }
+        };
+
+        // the oldest turn that is sent, once a turn does not fit the older ones are left out too
+        let mut oldest = turns.len().saturating_sub(1);
+        let mut full = false;
+        let mut take_turns = |until: usize, fits: &mut dyn FnMut(&[Arc<Message>]) -> bool| {
+            while !full && oldest > until {
+                if fits(turns[oldest - 1]) {
+                    oldest -= 1;
+                } else {
+                    full = true;
+                }
+            }
+        };
+
+        fill(Priority::High, &mut fits);
+        take_turns(turns.len().saturating_sub(RECENT_TURNS), &mut fits);
+        fill(Priority::Normal, &mut fits);
+        take_turns(0, &mut fits);
+        fill(Priority::Low, &mut fits);
+
+        let mut messages = history[..head].to_vec();
+        let left_out = turns[..oldest].iter().map(|turn| turn.len()).sum::<usize>();
+        if left_out > 0 {
+            messages.push(Arc::new(Message::Developer(format!(
+                "[{} earlier messages of this conversation are left out to fit the context budget, the results you need from them should be in your notes or in the tools you stored them with]",
+                left_out
+            ))));
+        }
+        messages.extend(turns[oldest..].iter().flat_map(|turn| turn.iter().cloned()));
+        messages.extend(
+            context
+                .into_iter()
+                .zip(sent)
+                .filter(|(_, sent)| *sent)
+                .flat_map(|((_, messages), _)| messages),
+        );
+        messages
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{ContextBudget, Priority, tool_tokens};
+    use crate::llm::Message;
+    use crate::tools::{ToolCall, ToolDefinition};
+    use std::sync::Arc;
+
+    fn text(message: &Message) -> String {
+        match message {
+            Message::User(content) | Message::System(content) | Message::Developer(content) => {
+                content.clone()
+            }
+            Message::Assistant(content, _) => content.clone(),
+            Message::Tool { result, .. } => result.to_string(),
+            Message::UserImages(content, _) => content.clone(),
+        }
+    }
+
+    #[test]
+    fn test_assemble() {
+        let call = |n: usize| {
+            vec![
+                Arc::new(Message::Assistant(
+                    format!("step {} a b c", n),
+                    vec![ToolCall {
+                        id: n.to_string(),
+                        name: "search".to_string(),
+                        args: "{}".to_string(),
+                    }],
+                )),
+                Arc::new(Message::Tool {
+                    id: n.to_string(),
+                    name: "search".to_string(),
+                    result: format!("result {} d e f", n).into(),
+                }),
+            ]
+        };
+        let mut history = vec![
+            Arc::new(Message::System("the system prompt".to_string())),
+            Arc::new(Message::User("the task".to_string())),
+        ];
+        for n in 1..=6 {
+            history.extend(call(n));
+        }
+        let context = |todo: usize| {
+            vec![
+                (
+                    Priority::Low,
+                    vec![Arc::new(Message::User("recalled memory".to_string()))],
+                ),
+                (
+                    Priority::High,
+                    vec![Arc::new(Message::User("todo ".repeat(todo)))],
+                ),
+            ]
+        };
+
+        // everything fits
+        let messages = ContextBudget::new(1000, 0).assemble(&history, context(2));
+        assert_eq!(messages.len(), history.len() + 2);
+
+        // the older turns and the recalled memory are left out, whole turns only, from what is
+        // left of the budget once the rest of the request and the reply are reserved
+        let messages = ContextBudget::new(1000, 1000 - (5 + 40 + 2)).assemble(&history, context(2));
+        let texts = messages.iter().map(|m| text(m)).collect::<Vec<_>>();
+        assert_eq!(texts[..2], ["the system prompt", "the task"]);
+        assert!(texts[2].starts_with("[4 earlier messages"));
+        assert_eq!(texts[3], "step 3 a b c");
+        assert_eq!(texts.last().unwrap(), "todo todo ");
+        assert_eq!(texts.len(), 3 + 8 + 1);
+
+        // the latest turn is sent even when it is over the budget
+        let messages = ContextBudget::new(1, 10).assemble(&history, context(100));
+        let texts = messages.iter().map(|m| text(m)).collect::<Vec<_>>();
+        assert!(texts[2].starts_with("[10 earlier messages"));
+        assert_eq!(texts[3..], ["step 6 a b c", "result 6 d e f"]);
+
+        let search = ToolDefinition {
+            name: "search".to_string(),
+            desc: "searches the web".to_string(),
+            params: serde_json::json!({"type": "object"}),
+            strict: false,
+        };
+        assert_eq!(tool_tokens(&[search]), 2 + 3 + 5);
+    }
+}
@@ -1,23 +1,57 @@
-use async_openai::error::OpenAIError;
+#[cfg(feature = "native")]
+use async_openai::error::{ApiError, OpenAIError};
 use thiserror::Error;
 
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ErrorKind {
+    /// the same request may succeed if it is retried later
+    Retryable,
+    /// retrying will not help, the run or step should be abandoned
+    Fatal,
+    /// the user has to fix something, such as credentials or quota, before retrying
+    UserActionable,
+}
+
 #[derive(Error, Debug)]
 pub enum Error {
     #[error("Json error: {0}")]
     JsonError(#[from] serde_json::Error),
 
+    #[cfg(feature = "native")]
     #[error("Openai error: {0}")]
-    OpenaiError(#[from] OpenAIError),
+    OpenaiError(OpenAIError),
+
+    #[error("Rate limited by provider: {0}")]
+    RateLimited(String),
+
+    #[error("Context length exceeded: {0}")]
+    ContextOverflow(String),
+
+    #[error("Authentication error: {0}")]
+    AuthError(String),
+
+    #[error("Timed out: {0}")]
+    Timeout(String),
+
+    #[error("Cancelled: {0}")]
+    Cancelled(String),
 
     #[error("No response from llm: {0}")]
     LLMResponseError(String),
 
+    #[error("Unsupported: {0}")]
+    Unsupported(String),
+
     #[error("Tool {0} does not exist")]
     ToolDoesNotExist(String),
 
+    #[error("Invalid arguments for tool {tool}: {error}")]
+    InvalidToolArgs { tool: String, error: String },
+
     #[error("Missing arg: {0}")]
     MissingArg(String),
 
+    #[cfg(feature = "native")]
     #[error("Task join error: {0}")]
     TaskJoinError(#[from] tokio::task::JoinError),
 
@@ -26,4 +60,113 @@ pub enum Error {
 
     #[error("IO Error: {0}")]
     IOError(#[from] std::io::Error),
+
+    #[error("Artifact error: {0}")]
+    ArtifactError(String),
+
+    #[error("Working directory error: {0}")]
+    WorkDirError(String),
+}
+
+impl Error {
+    pub fn kind(&self) -> ErrorKind {
+        match self {
+            Error::RateLimited(_) | Error::Timeout(_) | Error::LLMResponseError(_) => {
+                ErrorKind::Retryable
+            }
+            Error::AuthError(_) => ErrorKind::UserActionable,
+            #[cfg(feature = "native")]
+            Error::OpenaiError(OpenAIError::Reqwest(_) | OpenAIError::StreamError(_)) => {
+                ErrorKind::Retryable
+            }
+            #[cfg(feature = "native")]
+            Error::OpenaiError(OpenAIError::ApiError(e)) if is_code(e, "insufficient_quota") => {
+                ErrorKind::UserActionable
+            }
+            #[cfg(feature = "native")]
+            Error::OpenaiError(OpenAIError::ApiError(e))
+                if matches!(e.r#type.as_deref(), Some("server_error")) =>
+            {
+                ErrorKind::Retryable
+            }
+            _ => ErrorKind::Fatal,
+        }
+    }
+
+    pub fn is_retryable(&self) -> bool {
+        self.kind() == ErrorKind::Retryable
+    }
+}
+
+#[cfg(feature = "native")]
+fn is_code(e: &ApiError, code: &str) -> bool {
+    e.code.as_deref() == Some(code) || e.r#type.as_deref() == Some(code)
+}
+
+#[cfg(feature = "native")]
+impl From<OpenAIError> for Error {
+    fn from(e: OpenAIError) -> Self {
+        match e {
+            OpenAIError::ApiError(e) if is_code(&e, "rate_limit_exceeded") => {
+                Error::RateLimited(e.message)
+            }
+            OpenAIError::ApiError(e) if is_code(&e, "context_length_exceeded") => {
+                Error::ContextOverflow(e.message)
+            }
+            OpenAIError::ApiError(e)
+                if is_code(&e, "invalid_api_key")
+                    || is_code(&e, "authentication_error")
+                    || is_code(&e, "permission_error") =>
+            {
+                Error::AuthError(e.message)
+            }
+            e => Error::OpenaiError(e),
+        }
+    }
+}
+
+#[cfg(all(test, feature = "native"))]
+mod tests {
+    use super::{Error, ErrorKind};
+    use async_openai::error::{ApiError, OpenAIError};
+
+    fn api_error(r#type: Option<&str>, code: Option<&str>) -> Error {
+        Error::from(OpenAIError::ApiError(ApiError {
+            message: "message".to_string(),
+            r#type: r#type.map(str::to_string),
+            param: None,
+            code: code.map(str::to_string),
+        }))
+    }
+
+    #[test]
+    fn test_error_kind() {
+        let e = api_error(Some("tokens"), Some("rate_limit_exceeded"));
+        assert!(matches!(e, Error::RateLimited(_)));
+        assert_eq!(e.kind(), ErrorKind::Retryable);
+
+        let e = api_error(
+            Some("invalid_request_error"),
+            Some("context_length_exceeded"),
+        );
+        assert!(matches!(e, Error::ContextOverflow(_)));
+        assert_eq!(e.kind(), ErrorKind::Fatal);
+
+        let e = api_error(Some("invalid_request_error"), Some("invalid_api_key"));
+        assert!(matches!(e, Error::AuthError(_)));
+        assert_eq!(e.kind(), ErrorKind::UserActionable);
+
+        let e = api_error(Some("insufficient_quota"), None);
+        assert_eq!(e.kind(), ErrorKind::UserActionable);
+
+        assert_eq!(
+            api_error(Some("server_error"), None).kind(),
+            ErrorKind::Retryable
+        );
+        assert_eq!(
+            api_error(None, Some("invalid_value")).kind(),
+            ErrorKind::Fatal
+        );
+        assert_eq!(Error::MissingArg("x".to_string()).kind(), ErrorKind::Fatal);
+    }
 }
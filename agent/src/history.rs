@@ -0,0 +1,63 @@
+use crate::llm::Message;
+use std::sync::Arc;
+
+pub trait History: Send + Sync {
+    fn append(&mut self, message: Arc<Message>);
+
+    fn iter(&self) -> Box<dyn Iterator<Item = &Arc<Message>> + '_>;
+
+    fn truncate(&mut self, len: usize);
+
+    fn len(&self) -> usize;
+
+    fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    fn last(&self) -> Option<&Arc<Message>> {
+        self.iter().last()
+    }
+
+    fn token_count(&self) -> usize {
+        self.iter().map(|m| m.ntokens()).sum()
+    }
+
+    // histories that cannot replace a message in place are rebuilt
+    fn replace(&mut self, index: usize, message: Arc<Message>) {
+        let messages = self.iter().cloned().collect::<Vec<_>>();
+        self.truncate(0);
+        for (i, existing) in messages.into_iter().enumerate() {
+            self.append(if i == index {
+                message.clone()
+            } else {
+                existing
+            });
+        }
+    }
+}
+
+impl History for Vec<Arc<Message>> {
+    fn append(&mut self, message: Arc<Message>) {
+        self.push(message);
+    }
+
+    fn iter(&self) -> Box<dyn Iterator<Item = &Arc<Message>> + '_> {
+        Box::new(self.as_slice().iter())
+    }
+
+    fn truncate(&mut self, len: usize) {
+        Vec::truncate(self, len);
+    }
+
+    fn len(&self) -> usize {
+        Vec::len(self)
+    }
+
+    fn last(&self) -> Option<&Arc<Message>> {
+        self.as_slice().last()
+    }
+
+    fn replace(&mut self, index: usize, message: Arc<Message>) {
+        self[index] = message;
+    }
+}
@@ -1,10 +1,20 @@
 mod agent;
+pub mod artifacts;
 pub mod callbacks;
+pub mod checkpoint;
+mod context;
 mod error;
+mod history;
 pub mod llm;
+pub mod sanitize;
+#[cfg(feature = "native")]
+pub mod secrets;
 pub mod tools;
+pub mod workdir;
 
-pub use error::Error;
+pub use context::Priority;
+pub use error::{Error, ErrorKind};
+pub use history::History;
 pub type Result<T> = std::result::Result<T, Error>;
 
-pub use agent::{Agent, AgentBuilder, StopCondition};
+pub use agent::{Agent, AgentBuilder, ContextProvider, FinalAnswer, StopCondition, SystemPrompt};
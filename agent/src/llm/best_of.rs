@@ -0,0 +1,247 @@
+use crate::Result;
+use crate::llm::{CompletionRequest, CompletionResponse, LLM, Message, TokenUsage};
+use async_trait::async_trait;
+use std::sync::Arc;
+
+const JUDGE_PROMPT: &str = "You compare candidate replies to the same request and pick the best one.
+Instructions:
+- The best reply follows the instructions of the request most closely, is accurate and complete, and supports its statements with sources where the request asks for them.
+- Length is no merit of its own, prefer the clearer of two equally complete replies.
+- Reply with the number of the best candidate and nothing else.";
+
+// the request and the candidates are cut so that the judge request stays small
+const MAX_JUDGED_TASK_CHARS: usize = 4000;
+const MAX_CANDIDATE_CHARS: usize = 12000;
+
+// picks the best of several completions of the same request
+#[async_trait]
+pub trait Selector {
+    // the index of the best candidate and the tokens the selection used
+    async fn select<'a>(
+        &self,
+        request: CompletionRequest<'a>,
+        candidates: &[CompletionResponse],
+    ) -> Result<(usize, TokenUsage)>;
+}
+
+// picks the candidate with the highest score, the first of equally scored ones
+pub struct Heuristic(Box<dyn Fn(&CompletionResponse) -> f64 + Send + Sync>);
+
+impl Heuristic {
+    pub fn new(score: impl Fn(&CompletionResponse) -> f64 + Send + Sync + 'static) -> Box<Self> {
+        Box::new(Self(Box::new(score)))
+    }
+}
+
+#[async_trait]
+impl Selector for Heuristic {
+    async fn select<'a>(
+        &self,
+        _: CompletionRequest<'a>,
+        candidates: &[CompletionResponse],
+    ) -> Result<(usize, TokenUsage)> {
+        let mut best = (0, f64::NEG_INFINITY);
+        for (i, candidate) in candidates.iter().enumerate() {
+            let score = (self.0)(candidate);
+            if score > best.1 {
+                best = (i, score);
+            }
+        }
+        Ok((best.0, TokenUsage::default()))
+    }
+}
+
+// asks a model which candidate answers the task of the request best
+pub struct Judge(Arc<dyn LLM + Send + Sync>);
+
+impl Judge {
+    pub fn new(llm: Arc<dyn LLM + Send + Sync>) -> Box<Self> {
+        Box::new(Self(llm))
+    }
+}
+
+fn truncate(text: &str, max_chars: usize) -> String {
+    text.chars().take(max_chars).collect()
+}
+
+#[async_trait]
+impl Selector for Judge {
+    async fn select<'a>(
+        &self,
+        request: CompletionRequest<'a>,
+        candidates: &[CompletionResponse],
+    ) -> Result<(usize, TokenUsage)> {
+        // the system prompt holds the instructions and the first user message the task
+        let mut judged = String::new();
+        for message in request.messages.iter().take(2) {
+            match message.as_ref() {
+                Message::System(content) => judged.push_str(&format!(
+                    "<instructions>\n{}\n</instructions>\n",
+                    truncate(content, MAX_JUDGED_TASK_CHARS)
+                )),
+                Message::User(content) | Message::UserImages(content, _) => {
+                    judged.push_str(&format!(
+                        "<task>\n{}\n</task>\n",
+                        truncate(content, MAX_JUDGED_TASK_CHARS)
+                    ))
+                }
+                _ => {}
+            }
+        }
+        for (i, candidate) in candidates.iter().enumerate() {
+            judged.push_str(&format!(
+                "<candidate number=\"{}\">\n{}\n</candidate>\n",
+                i + 1,
+                truncate(&candidate.text(), MAX_CANDIDATE_CHARS)
+            ));
+        }
+
+        let res = self
+            .0
+            .completion(CompletionRequest {
+                messages: &vec![
+                    Arc::new(Message::System(JUDGE_PROMPT.to_string())),
+                    Arc::new(Message::User(judged)),
+                ],
+                tools: &[],
+                web_search_tool: false,
+                prefill: None,
+            })
+            .await?;
+        let index = res
+            .content
+            .split(|c: char| !c.is_ascii_digit())
+            .find_map(|number| number.parse::<usize>().ok())
+            .filter(|number| (1..=candidates.len()).contains(number))
+            .ok_or_else(|| {
+                crate::Error::LLMResponseError(format!(
+                    "the judge did not pick one of the {} candidates: {}",
+                    candidates.len(),
+                    res.content
+                ))
+            })?;
+        Ok((index - 1, res.usage))
+    }
+}
+
+// samples several completions of every request and returns the one the selector picks, so that
+// only the best candidate ends up in the history of an agent; the usage of the response covers
+// all candidates and the selection, and the first candidate is returned if the selection fails
+pub struct BestOf {
+    inner: Arc<dyn LLM + Send + Sync>,
+    n: usize,
+    selector: Box<dyn Selector + Send + Sync>,
+}
+
+impl BestOf {
+    pub fn new(
+        inner: Arc<dyn LLM + Send + Sync>,
+        n: usize,
+        selector: Box<dyn Selector + Send + Sync>,
+    ) -> Arc<Self> {
+        Arc::new(Self {
+            inner,
+            n: n.max(1),
+            selector,
+        })
+    }
+}
+
+#[async_trait]
+impl LLM for BestOf {
+    async fn completion<'a>(&self, request: CompletionRequest<'a>) -> Result<CompletionResponse> {
+        let mut candidates = self.inner.completions(request, self.n).await?;
+        if candidates.is_empty() {
+            return Err(crate::Error::LLMResponseError(
+                "no completions were returned".to_string(),
+            ));
+        }
+        let mut usage = TokenUsage::default();
+        for candidate in &candidates {
+            usage += candidate.usage;
+        }
+        let index = match candidates.len() {
+            1 => 0,
+            _ => match self.selector.select(request, &candidates).await {
+                Ok((index, selection)) if index < candidates.len() => {
+                    usage += selection;
+                    index
+                }
+                _ => 0,
+            },
+        };
+        let mut best = candidates.swap_remove(index);
+        best.usage = usage;
+        Ok(best)
+    }
+
+    fn web_search(&self) -> bool {
+        self.inner.web_search()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{BestOf, Heuristic, Judge};
+    use crate::Result;
+    use crate::llm::{CompletionRequest, CompletionResponse, LLM, Message, TokenUsage};
+    use async_trait::async_trait;
+    use std::sync::Arc;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    // writes a longer draft with every request, and picks the second candidate when judging
+    struct Drafts(AtomicUsize);
+
+    #[async_trait]
+    impl LLM for Drafts {
+        async fn completion<'a>(
+            &self,
+            request: CompletionRequest<'a>,
+        ) -> Result<CompletionResponse> {
+            let n = self.0.fetch_add(1, Ordering::SeqCst);
+            let judging = matches!(
+                request.messages.last().map(|m| m.as_ref()),
+                Some(Message::User(content)) if content.contains("<candidate number=\"3\">")
+            );
+            Ok(CompletionResponse {
+                content: if judging {
+                    "Candidate 2".to_string()
+                } else {
+                    "draft ".repeat(n + 1)
+                },
+                tool_calls: Vec::new(),
+                usage: TokenUsage {
+                    requests: 1,
+                    ..Default::default()
+                },
+            })
+        }
+    }
+
+    #[tokio::test]
+    async fn test_best_of() -> Result<()> {
+        let messages = vec![Arc::new(Message::User("write the report".to_string()))];
+        let request = CompletionRequest {
+            messages: &messages,
+            tools: &[],
+            web_search_tool: false,
+            prefill: None,
+        };
+
+        let drafts = Arc::new(Drafts(AtomicUsize::new(0)));
+        let longest = BestOf::new(
+            drafts.clone(),
+            3,
+            Heuristic::new(|candidate| candidate.content.len() as f64),
+        );
+        let res = longest.completion(request).await?;
+        assert_eq!(res.content, "draft draft draft ");
+        assert_eq!(res.usage.requests, 3);
+
+        let judged = BestOf::new(drafts.clone(), 3, Judge::new(drafts.clone()));
+        let res = judged.completion(request).await?;
+        assert_eq!(res.content, "draft ".repeat(5));
+        assert_eq!(res.usage.requests, 4);
+        Ok(())
+    }
+}
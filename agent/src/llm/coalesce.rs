@@ -0,0 +1,219 @@
+use crate::llm::{CompletionRequest, CompletionResponse, LLM, TokenUsage};
+use crate::{Error, ErrorKind, Result};
+use async_trait::async_trait;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use tokio::sync::oneshot;
+
+type Waiters = Vec<oneshot::Sender<Result<CompletionResponse>>>;
+
+pub struct Coalescing {
+    inner: Arc<dyn LLM + Send + Sync>,
+    // waiting requests by the whole request they share, so that different requests are never
+    // mistaken for each other
+    in_flight: Mutex<HashMap<String, Waiters>>,
+}
+
+impl Coalescing {
+    pub fn new(inner: Arc<dyn LLM + Send + Sync>) -> Arc<Self> {
+        Arc::new(Self {
+            inner,
+            in_flight: Mutex::new(HashMap::new()),
+        })
+    }
+}
+
+fn request_key(request: &CompletionRequest) -> Result<String> {
+    let messages = request
+        .messages
+        .iter()
+        .map(|m| m.as_ref())
+        .collect::<Vec<_>>();
+    let tools = request
+        .tools
+        .iter()
+        .map(|tool| (&tool.name, &tool.desc, &tool.params, tool.strict))
+        .collect::<Vec<_>>();
+    Ok(serde_json::to_string(&(
+        messages,
+        tools,
+        request.web_search_tool,
+        request.prefill,
+    ))?)
+}
+
+// the error of the leading request for a waiting one, errors that cannot be cloned are passed on
+// as errors of the same kind
+fn shared(e: &Error) -> Error {
+    match e {
+        Error::RateLimited(m) => Error::RateLimited(m.clone()),
+        Error::ContextOverflow(m) => Error::ContextOverflow(m.clone()),
+        Error::AuthError(m) => Error::AuthError(m.clone()),
+        Error::Timeout(m) => Error::Timeout(m.clone()),
+        Error::Cancelled(m) => Error::Cancelled(m.clone()),
+        Error::LLMResponseError(m) => Error::LLMResponseError(m.clone()),
+        Error::Unsupported(m) => Error::Unsupported(m.clone()),
+        e => match e.kind() {
+            ErrorKind::Retryable => Error::LLMResponseError(e.to_string()),
+            ErrorKind::UserActionable => Error::AuthError(e.to_string()),
+            ErrorKind::Fatal => Error::AgentWorkflowError(e.to_string()),
+        },
+    }
+}
+
+// removes the in flight entry if the leading request is dropped before it completes, so
+// that waiting requests fail rather than waiting forever
+struct InFlight<'a> {
+    key: String,
+    map: &'a Mutex<HashMap<String, Waiters>>,
+    finished: bool,
+}
+
+impl InFlight<'_> {
+    fn finish(mut self, res: &Result<CompletionResponse>) {
+        self.finished = true;
+        let waiters = self
+            .map
+            .lock()
+            .unwrap()
+            .remove(&self.key)
+            .unwrap_or_default();
+        for waiter in waiters {
+            let _ = waiter.send(match res {
+                Ok(res) => Ok(res.clone()),
+                Err(e) => Err(shared(e)),
+            });
+        }
+    }
+}
+
+impl Drop for InFlight<'_> {
+    fn drop(&mut self) {
+        if !self.finished {
+            self.map.lock().unwrap().remove(&self.key);
+        }
+    }
+}
+
+#[async_trait]
+impl LLM for Coalescing {
+    async fn completion<'a>(&self, request: CompletionRequest<'a>) -> Result<CompletionResponse> {
+        let key = request_key(&request)?;
+
+        let receiver = {
+            let mut in_flight = self.in_flight.lock().unwrap();
+            match in_flight.get_mut(&key) {
+                Some(waiters) => {
+                    let (sender, receiver) = oneshot::channel();
+                    waiters.push(sender);
+                    Some(receiver)
+                }
+                None => {
+                    in_flight.insert(key.clone(), Vec::new());
+                    None
+                }
+            }
+        };
+
+        if let Some(receiver) = receiver {
+            let mut res = receiver.await.map_err(|_| {
+                Error::LLMResponseError("coalesced request was cancelled".to_string())
+            })??;
+            // only the leading request was sent to the provider
+            res.usage = TokenUsage::default();
+            return Ok(res);
+        }
+
+        let guard = InFlight {
+            key,
+            map: &self.in_flight,
+            finished: false,
+        };
+        let res = self.inner.completion(request).await;
+        guard.finish(&res);
+        res
+    }
+
+    // several candidates are sampled to differ, so they are never shared with other requests
+    async fn completions<'a>(
+        &self,
+        request: CompletionRequest<'a>,
+        n: usize,
+    ) -> Result<Vec<CompletionResponse>> {
+        self.inner.completions(request, n).await
+    }
+
+    fn web_search(&self) -> bool {
+        self.inner.web_search()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Coalescing;
+    use crate::llm::{CompletionRequest, CompletionResponse, LLM, Message};
+    use crate::{Error, Result};
+    use async_trait::async_trait;
+    use std::sync::Arc;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    struct SlowLLM(AtomicUsize);
+
+    #[async_trait]
+    impl LLM for SlowLLM {
+        async fn completion<'a>(
+            &self,
+            request: CompletionRequest<'a>,
+        ) -> Result<CompletionResponse> {
+            let n = self.0.fetch_add(1, Ordering::SeqCst);
+            tokio::time::sleep(std::time::Duration::from_millis(50)).await;
+            if matches!(request.messages.last().map(|m| m.as_ref()),
+                Some(Message::User(content)) if content == "limited")
+            {
+                return Err(Error::RateLimited("slow down".to_string()));
+            }
+            Ok(CompletionResponse {
+                content: format!("response {}", n),
+                ..Default::default()
+            })
+        }
+    }
+
+    #[tokio::test]
+    async fn test_coalescing() -> Result<()> {
+        let inner = Arc::new(SlowLLM(AtomicUsize::new(0)));
+        let llm = Coalescing::new(inner.clone());
+
+        let a = vec![Arc::new(Message::User("a".to_string()))];
+        let b = vec![Arc::new(Message::User("b".to_string()))];
+        let request = |messages| CompletionRequest {
+            messages,
+            tools: &[],
+            web_search_tool: false,
+            prefill: None,
+        };
+
+        let (r1, r2, r3) = tokio::join!(
+            llm.completion(request(&a)),
+            llm.completion(request(&a)),
+            llm.completion(request(&b)),
+        );
+
+        let (r1, r2, r3) = (r1?, r2?, r3?);
+        assert_eq!(r1.content, r2.content);
+        assert_ne!(r1.content, r3.content);
+        assert_eq!(inner.0.load(Ordering::SeqCst), 2);
+
+        // waiting requests get the error of the leading one with its kind
+        let limited = vec![Arc::new(Message::User("limited".to_string()))];
+        let (r1, r2) = tokio::join!(
+            llm.completion(request(&limited)),
+            llm.completion(request(&limited)),
+        );
+        assert!(matches!(r1, Err(Error::RateLimited(_))));
+        assert!(matches!(r2, Err(Error::RateLimited(_))));
+        assert_eq!(inner.0.load(Ordering::SeqCst), 3);
+
+        Ok(())
+    }
+}
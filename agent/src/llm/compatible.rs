@@ -0,0 +1,907 @@
+use crate::llm::{self, RateLimiter};
+use crate::{Error, ErrorKind, Result};
+use async_openai::{
+    config::{Config, OpenAIConfig},
+    error::{ApiError, OpenAIError, WrappedError},
+    types::{
+        ChatCompletionRequestMessage, ChatCompletionTool, CreateChatCompletionRequestArgs, Stop,
+        WebSearchOptions,
+    },
+};
+use async_trait::async_trait;
+use serde_json::Value;
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::Duration;
+
+// how often a request that was rate limited or failed with a server error is retried
+const MAX_RETRIES: u32 = 3;
+// the first wait before retrying a server error, it doubles with every retry
+const SERVER_ERROR_BACKOFF: Duration = Duration::from_millis(200);
+// how providers that do not send openai error codes word that the prompt is too long
+const CONTEXT_OVERFLOW_WORDING: &[&str] = &[
+    "context length",
+    "context_length",
+    "context window",
+    "maximum context",
+    "too many tokens",
+    "prompt is too long",
+];
+
+#[derive(Clone, Copy, Debug)]
+pub struct Quirks {
+    /// the provider supports the web_search_options request field
+    pub web_search: bool,
+    /// the provider returns its reasoning inline in the content wrapped in <think> tags
+    pub think_tags: bool,
+    /// the provider knows the developer role, developer messages are sent as system messages
+    /// otherwise
+    pub developer_role: bool,
+    /// the provider continues a final assistant message that is marked as a prefix, which of
+    /// the presets only mistral does; otherwise no assistant message is sent and the model is
+    /// told how to start its reply
+    pub prefill: bool,
+    /// the provider samples several choices of one request with the n request field
+    pub several_choices: bool,
+}
+
+impl Default for Quirks {
+    fn default() -> Self {
+        Self {
+            web_search: true,
+            think_tags: false,
+            developer_role: false,
+            prefill: false,
+            several_choices: false,
+        }
+    }
+}
+
+/// Sampling settings that are sent with every request to the model, whatever step of the agent it
+/// is for, so a stop sequence also ends the replies that call tools.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct GenerationParams {
+    /// bias from -100 to 100 added to the logits of tokens before sampling, by the id of the token
+    /// in the tokenizer of the model; -100 bans a token, e.g. the start of boilerplate phrases
+    pub logit_bias: HashMap<u32, i32>,
+    /// sequences at which the model stops generating, openai accepts up to 4
+    pub stop: Vec<String>,
+}
+
+pub struct OpenAICompatible {
+    model: String,
+    config: OpenAIConfig,
+    http: reqwest::Client,
+    timeout: Option<Duration>,
+    rate_limiter: Arc<RateLimiter>,
+    quirks: Quirks,
+    params: GenerationParams,
+}
+
+impl OpenAICompatible {
+    pub fn builder(model: String, api_base: &str, api_key: &str) -> OpenAICompatibleBuilder {
+        OpenAICompatibleBuilder {
+            model,
+            config: OpenAIConfig::new()
+                .with_api_base(api_base)
+                .with_api_key(api_key),
+            timeout: None,
+            rate_limiter: None,
+            quirks: Quirks::default(),
+            params: GenerationParams::default(),
+        }
+    }
+
+    pub fn xai(model: String) -> OpenAICompatibleBuilder {
+        Self::builder(model, "https://api.x.ai/v1", &api_key("XAI_API_KEY")).quirks(Quirks {
+            web_search: false,
+            ..Quirks::default()
+        })
+    }
+
+    pub fn mistral(model: String) -> OpenAICompatibleBuilder {
+        Self::builder(
+            model,
+            "https://api.mistral.ai/v1",
+            &api_key("MISTRAL_API_KEY"),
+        )
+        .quirks(Quirks {
+            web_search: false,
+            prefill: true,
+            ..Quirks::default()
+        })
+    }
+
+    pub fn deepseek(model: String) -> OpenAICompatibleBuilder {
+        Self::builder(
+            model,
+            "https://api.deepseek.com/v1",
+            &api_key("DEEPSEEK_API_KEY"),
+        )
+        .quirks(Quirks {
+            web_search: false,
+            ..Quirks::default()
+        })
+    }
+
+    pub fn groq(model: String) -> OpenAICompatibleBuilder {
+        Self::builder(
+            model,
+            "https://api.groq.com/openai/v1",
+            &api_key("GROQ_API_KEY"),
+        )
+        .quirks(Quirks {
+            web_search: false,
+            think_tags: true,
+            ..Quirks::default()
+        })
+    }
+
+    async fn send(&self, request: &Value, tokens: usize) -> Result<Value> {
+        let mut retries = 0;
+        loop {
+            self.rate_limiter.acquire(tokens).await;
+
+            let mut http_request = self
+                .http
+                .post(self.config.url("/chat/completions"))
+                .query(&self.config.query())
+                .headers(self.config.headers())
+                .json(request);
+            if let Some(timeout) = self.timeout {
+                http_request = http_request.timeout(timeout);
+            }
+
+            let response = http_request.send().await.map_err(|e| self.http_error(e))?;
+            self.rate_limiter.update(response.headers());
+
+            let status = response.status();
+            let bytes = response.bytes().await.map_err(|e| self.http_error(e))?;
+
+            if status.is_success() {
+                return Ok(serde_json::from_slice(&bytes)?);
+            }
+
+            match status_error(status, &bytes) {
+                Error::RateLimited(_) if retries < MAX_RETRIES => {
+                    self.rate_limiter.exhausted();
+                }
+                _ if status.is_server_error() && retries < MAX_RETRIES => {
+                    tokio::time::sleep(SERVER_ERROR_BACKOFF * 2u32.pow(retries)).await;
+                }
+                error => return Err(error),
+            }
+            retries += 1;
+        }
+    }
+
+    // the json of the request, with the quirks of the provider and the generation parameters
+    fn body(&self, request: &llm::CompletionRequest) -> Result<Value> {
+        let mut messages = group_tool_results(request.messages.iter().map(|m| m.as_ref()));
+        if let Some(prefill) = request.prefill
+            && !self.quirks.prefill
+        {
+            messages.push(llm::Message::Developer(format!(
+                "Start your reply with exactly the following text and continue from there:\n{}",
+                prefill
+            )));
+        }
+
+        let mut completion = CreateChatCompletionRequestArgs::default();
+        completion
+            .model(&self.model)
+            .messages(
+                messages
+                    .into_iter()
+                    .map(|message| match message {
+                        llm::Message::Developer(content) if !self.quirks.developer_role => {
+                            llm::Message::System(content)
+                        }
+                        message => message,
+                    })
+                    .map(|message| ChatCompletionRequestMessage::try_from(&message))
+                    .collect::<Result<Vec<_>>>()?,
+            )
+            .tools(
+                request
+                    .tools
+                    .iter()
+                    .map(ChatCompletionTool::try_from)
+                    .collect::<Result<Vec<_>>>()?,
+            );
+
+        if !self.params.logit_bias.is_empty() {
+            completion.logit_bias(
+                self.params
+                    .logit_bias
+                    .iter()
+                    .map(|(token, bias)| (token.to_string(), Value::from(*bias)))
+                    .collect::<HashMap<_, _>>(),
+            );
+        }
+        if !self.params.stop.is_empty() {
+            completion.stop(Stop::StringArray(self.params.stop.clone()));
+        }
+
+        if request.web_search_tool {
+            if !self.quirks.web_search {
+                return Err(Error::Unsupported(format!(
+                    "{} does not support web search",
+                    self.config.api_base()
+                )));
+            }
+            completion.web_search_options(WebSearchOptions::default());
+        }
+
+        let mut completion = serde_json::to_value(completion.build()?)?;
+        if let Some(prefill) = request.prefill
+            && self.quirks.prefill
+            && let Some(messages) = completion["messages"].as_array_mut()
+        {
+            messages.push(serde_json::json!({
+                "role": "assistant",
+                "content": prefill,
+                "prefix": true,
+            }));
+        }
+        Ok(completion)
+    }
+
+    fn http_error(&self, e: reqwest::Error) -> Error {
+        match self.timeout {
+            Some(timeout) if e.is_timeout() => Error::Timeout(format!(
+                "completion request did not finish within {} seconds",
+                timeout.as_secs()
+            )),
+            _ => Error::from(OpenAIError::Reqwest(e)),
+        }
+    }
+}
+
+// the error of a response that failed, classified by its status first, since not every provider
+// sends errors shaped like the ones of openai. Only the body tells a prompt that is too long
+// from other bad requests, and a 429 for an exhausted quota from a rate limit
+fn status_error(status: reqwest::StatusCode, body: &[u8]) -> Error {
+    let parsed = serde_json::from_slice::<WrappedError>(body)
+        .ok()
+        .map(|wrapped| wrapped.error);
+    let message = match &parsed {
+        Some(e) => e.message.clone(),
+        None => String::from_utf8_lossy(body).into_owned(),
+    };
+    let parsed = Error::from(OpenAIError::ApiError(parsed.unwrap_or_else(|| ApiError {
+        message: message.clone(),
+        r#type: None,
+        param: None,
+        code: Some(status.as_u16().to_string()),
+    })));
+    let overflow = {
+        let message = message.to_lowercase();
+        CONTEXT_OVERFLOW_WORDING
+            .iter()
+            .any(|wording| message.contains(wording))
+    };
+
+    match status.as_u16() {
+        429 if parsed.kind() != ErrorKind::UserActionable => Error::RateLimited(message),
+        401 | 403 => Error::AuthError(message),
+        400 | 413 if overflow => Error::ContextOverflow(message),
+        500..=599 => Error::OpenaiError(OpenAIError::ApiError(ApiError {
+            message,
+            r#type: Some("server_error".to_string()),
+            param: None,
+            code: Some(status.as_u16().to_string()),
+        })),
+        _ => parsed,
+    }
+}
+
+pub(crate) fn api_key(var: &str) -> String {
+    std::env::var(var).unwrap_or_default()
+}
+
+pub struct OpenAICompatibleBuilder {
+    model: String,
+    config: OpenAIConfig,
+    timeout: Option<Duration>,
+    rate_limiter: Option<Arc<RateLimiter>>,
+    quirks: Quirks,
+    params: GenerationParams,
+}
+
+impl OpenAICompatibleBuilder {
+    pub(crate) fn openai(model: String) -> Self {
+        Self {
+            model,
+            config: OpenAIConfig::new(),
+            timeout: None,
+            rate_limiter: None,
+            // few providers besides openai know the developer role or sample several choices
+            quirks: Quirks {
+                developer_role: true,
+                several_choices: true,
+                ..Quirks::default()
+            },
+            params: GenerationParams::default(),
+        }
+    }
+
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = Some(timeout);
+        self
+    }
+
+    pub fn rate_limiter(mut self, rate_limiter: Arc<RateLimiter>) -> Self {
+        self.rate_limiter = Some(rate_limiter);
+        self
+    }
+
+    pub fn quirks(mut self, quirks: Quirks) -> Self {
+        self.quirks = quirks;
+        self
+    }
+
+    pub fn params(mut self, params: GenerationParams) -> Self {
+        self.params = params;
+        self
+    }
+
+    pub fn build(self) -> Arc<OpenAICompatible> {
+        Arc::new(OpenAICompatible {
+            model: self.model,
+            config: self.config,
+            http: reqwest::Client::new(),
+            timeout: self.timeout,
+            rate_limiter: self.rate_limiter.unwrap_or_else(RateLimiter::new),
+            quirks: self.quirks,
+            params: self.params,
+        })
+    }
+}
+
+#[async_trait]
+impl llm::LLM for OpenAICompatible {
+    async fn completion<'a>(
+        &self,
+        request: llm::CompletionRequest<'a>,
+    ) -> Result<llm::CompletionResponse> {
+        let completion = self.body(&request)?;
+        let res = self
+            .send(&completion, request.messages.token_count())
+            .await?;
+
+        let mut res = parse_response(&res, &self.quirks)?;
+        if let Some(prefill) = request.prefill {
+            res.content = prefilled(res.content, prefill);
+        }
+        Ok(res)
+    }
+
+    async fn completions<'a>(
+        &self,
+        request: llm::CompletionRequest<'a>,
+        n: usize,
+    ) -> Result<Vec<llm::CompletionResponse>> {
+        if !self.quirks.several_choices || n <= 1 {
+            let mut completions = Vec::with_capacity(n);
+            for _ in 0..n.max(1) {
+                completions.push(self.completion(request).await?);
+            }
+            return Ok(completions);
+        }
+
+        let mut completion = self.body(&request)?;
+        completion["n"] = n.into();
+        let res = self
+            .send(&completion, request.messages.token_count())
+            .await?;
+
+        (0..res["choices"].as_array().map_or(0, |choices| choices.len()))
+            .map(|i| {
+                let mut choice = parse_choice(&res, i, &self.quirks)?;
+                if let Some(prefill) = request.prefill {
+                    choice.content = prefilled(choice.content, prefill);
+                }
+                Ok(choice)
+            })
+            .collect()
+    }
+
+    fn web_search(&self) -> bool {
+        self.quirks.web_search
+    }
+}
+
+// providers expect exactly one tool result per tool call directly after the assistant message
+// that made the calls, so results sharing an id are merged and any other messages produced while
+// calls are outstanding are moved after the results
+fn group_tool_results<'a>(messages: impl Iterator<Item = &'a llm::Message>) -> Vec<llm::Message> {
+    let mut grouped = Vec::new();
+    let mut outstanding: Vec<&str> = Vec::new();
+    let mut results: Vec<llm::Message> = Vec::new();
+    let mut deferred = Vec::new();
+
+    fn flush(
+        grouped: &mut Vec<llm::Message>,
+        results: &mut Vec<llm::Message>,
+        deferred: &mut Vec<llm::Message>,
+    ) {
+        grouped.append(results);
+        grouped.append(deferred);
+    }
+
+    for message in messages {
+        match message {
+            llm::Message::Assistant(_, calls) => {
+                flush(&mut grouped, &mut results, &mut deferred);
+                outstanding = calls.iter().map(|call| call.id.as_str()).collect();
+                grouped.push(message.clone());
+            }
+            llm::Message::Tool { id, name, result } => {
+                outstanding.retain(|outstanding| outstanding != id);
+                match results.iter_mut().find(
+                    |existing| matches!(existing, llm::Message::Tool { id: other, .. } if other == id),
+                ) {
+                    Some(llm::Message::Tool {
+                        result: existing, ..
+                    }) => {
+                        existing.append(result);
+                    }
+                    _ => results.push(llm::Message::Tool {
+                        id: id.clone(),
+                        name: name.clone(),
+                        result: result.clone(),
+                    }),
+                }
+            }
+            _ if !outstanding.is_empty() => deferred.push(message.clone()),
+            _ => {
+                flush(&mut grouped, &mut results, &mut deferred);
+                grouped.push(message.clone());
+            }
+        }
+    }
+    flush(&mut grouped, &mut results, &mut deferred);
+
+    grouped
+}
+
+fn parse_response(res: &Value, quirks: &Quirks) -> Result<llm::CompletionResponse> {
+    parse_choice(res, 0, quirks)
+}
+
+// the usage of the request is reported with the first choice, and the others use no tokens of
+// their own
+fn parse_choice(res: &Value, index: usize, quirks: &Quirks) -> Result<llm::CompletionResponse> {
+    let message = res["choices"]
+        .get(index)
+        .map(|choice| &choice["message"])
+        .ok_or(Error::LLMResponseError("choices is empty".to_string()))?;
+
+    if message["role"] != "assistant" {
+        return Err(Error::LLMResponseError(
+            "expected role to be assistant".to_string(),
+        ));
+    }
+
+    let tool_calls = message["tool_calls"]
+        .as_array()
+        .into_iter()
+        .flatten()
+        .map(|call| {
+            let function = &call["function"];
+            Ok(llm::ToolCall {
+                id: call["id"]
+                    .as_str()
+                    .ok_or(Error::LLMResponseError(
+                        "tool call id is missing".to_string(),
+                    ))?
+                    .to_string(),
+                name: function["name"]
+                    .as_str()
+                    .ok_or(Error::LLMResponseError(
+                        "tool call name is missing".to_string(),
+                    ))?
+                    .to_string(),
+                // some providers return the arguments as a json object instead of a string
+                args: match &function["arguments"] {
+                    Value::String(args) => args.clone(),
+                    Value::Null => "{}".to_string(),
+                    args => args.to_string(),
+                },
+            })
+        })
+        .collect::<Result<Vec<_>>>()?;
+
+    let mut content = match message["content"].as_str() {
+        Some(content) => content.to_string(),
+        None if !tool_calls.is_empty() => String::new(),
+        None => return Err(Error::LLMResponseError("content is empty".to_string())),
+    };
+
+    if quirks.think_tags {
+        content = strip_think_tags(&content);
+    }
+
+    let usage = &res["usage"];
+
+    Ok(llm::CompletionResponse {
+        content,
+        tool_calls,
+        usage: match index {
+            0 => llm::TokenUsage {
+                requests: 1,
+                prompt_tokens: usage["prompt_tokens"].as_u64().unwrap_or_default(),
+                completion_tokens: usage["completion_tokens"].as_u64().unwrap_or_default(),
+            },
+            _ => llm::TokenUsage::default(),
+        },
+    })
+}
+
+// providers return the continuation of the prefill, with or without the prefill itself
+fn prefilled(content: String, prefill: &str) -> String {
+    if content.starts_with(prefill) {
+        content
+    } else {
+        format!("{}{}", prefill, content)
+    }
+}
+
+fn strip_think_tags(content: &str) -> String {
+    match (content.find("<think>"), content.find("</think>")) {
+        (Some(start), Some(end)) if start < end => format!(
+            "{}{}",
+            &content[..start],
+            content[end + "</think>".len()..].trim_start()
+        ),
+        _ => content.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        GenerationParams, OpenAICompatible, Quirks, group_tool_results, parse_choice,
+        parse_response, prefilled,
+    };
+    use crate::Error;
+    use crate::llm::CompletionRequest;
+    use crate::llm::LLM;
+    use crate::llm::Message;
+    use crate::tools::ToolCall;
+    use async_openai::types::ChatCompletionRequestMessage;
+    use std::sync::Arc;
+
+    #[test]
+    fn test_group_tool_results() {
+        let call = |id: &str| ToolCall {
+            id: id.to_string(),
+            name: "crawl".to_string(),
+            args: "{}".to_string(),
+        };
+        let result = |id: &str, result: &str| Message::Tool {
+            id: id.to_string(),
+            name: "crawl".to_string(),
+            result: result.into(),
+        };
+
+        let messages = [
+            Message::User("task".to_string()),
+            Message::Assistant(String::new(), vec![call("a"), call("b")]),
+            result("a", "page 1"),
+            Message::User("note".to_string()),
+            result("a", "page 2"),
+            result("b", "page 3"),
+            Message::User("next".to_string()),
+        ];
+
+        let grouped = group_tool_results(messages.iter())
+            .iter()
+            .map(|m| m.to_string())
+            .collect::<Vec<_>>();
+        let expected = [
+            Message::User("task".to_string()),
+            Message::Assistant(String::new(), vec![call("a"), call("b")]),
+            result("a", "page 1\n\npage 2"),
+            result("b", "page 3"),
+            Message::User("note".to_string()),
+            Message::User("next".to_string()),
+        ]
+        .iter()
+        .map(|m| m.to_string())
+        .collect::<Vec<_>>();
+
+        assert_eq!(grouped, expected);
+    }
+
+    #[test]
+    fn test_developer_message() {
+        let message = Message::Developer("2 minutes left".to_string());
+        let request =
+            serde_json::to_value(ChatCompletionRequestMessage::try_from(&message).unwrap())
+                .unwrap();
+        assert_eq!(
+            request,
+            serde_json::json!({"role": "developer", "content": "2 minutes left"})
+        );
+        assert_eq!(message.to_string(), "__Developer:__ 2 minutes left\n\n");
+    }
+
+    #[test]
+    fn test_body() {
+        let messages = vec![
+            Arc::new(Message::User("task".to_string())),
+            Arc::new(Message::Developer("2 minutes left".to_string())),
+        ];
+        let request = CompletionRequest {
+            messages: &messages,
+            tools: &[],
+            web_search_tool: false,
+            prefill: Some("## Findings\n"),
+        };
+        let body = OpenAICompatible::mistral("mistral-large".to_string())
+            .params(GenerationParams {
+                logit_bias: [(1722, -100)].into(),
+                stop: vec!["</report>".to_string()],
+            })
+            .build()
+            .body(&request)
+            .unwrap();
+        assert_eq!(body["logit_bias"], serde_json::json!({"1722": -100}));
+        assert_eq!(body["stop"], serde_json::json!(["</report>"]));
+        assert_eq!(
+            body["messages"],
+            serde_json::json!([
+                {"role": "user", "content": "task"},
+                {"role": "system", "content": "2 minutes left"},
+                {"role": "assistant", "content": "## Findings\n", "prefix": true},
+            ])
+        );
+
+        // openai knows the developer role but not the prefill, and has no parameters by default
+        let body = OpenAICompatible::openai("gpt".to_string())
+            .build()
+            .body(&request)
+            .unwrap();
+        assert!(body.get("logit_bias").is_none() && body.get("stop").is_none());
+        let messages = body["messages"].as_array().unwrap();
+        assert_eq!(messages[1]["role"], "developer");
+        assert!(
+            messages[2]["content"]
+                .as_str()
+                .unwrap()
+                .ends_with("continue from there:\n## Findings\n")
+        );
+
+        // the other compatible providers get no trailing assistant message either
+        for builder in [
+            OpenAICompatible::xai("grok".to_string()),
+            OpenAICompatible::deepseek("deepseek-chat".to_string()),
+            OpenAICompatible::groq("llama".to_string()),
+        ] {
+            let body = builder.build().body(&request).unwrap();
+            let messages = body["messages"].as_array().unwrap();
+            assert_eq!(messages.len(), 3);
+            assert!(
+                messages.iter().all(
+                    |message| message["role"] != "assistant" && message.get("prefix").is_none()
+                )
+            );
+            assert!(
+                messages[2]["content"]
+                    .as_str()
+                    .unwrap()
+                    .ends_with("continue from there:\n## Findings\n")
+            );
+        }
+
+        // agents of providers without web search do not ask for it
+        assert!(
+            OpenAICompatible::openai("gpt".to_string())
+                .build()
+                .web_search()
+        );
+        assert!(
+            !OpenAICompatible::groq("llama".to_string())
+                .build()
+                .web_search()
+        );
+    }
+
+    #[tokio::test]
+    async fn test_status_errors() -> crate::Result<()> {
+        use std::sync::atomic::{AtomicUsize, Ordering};
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+        // a server that answers by the model of the request, with errors of providers that do not
+        // send openai error codes, and fails /flaky once before it succeeds
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await?;
+        let addr = listener.local_addr()?;
+        let requests = Arc::new(AtomicUsize::new(0));
+        let served = requests.clone();
+        tokio::spawn(async move {
+            while let Ok((mut socket, _)) = listener.accept().await {
+                let mut buf = vec![0; 16 * 1024];
+                let n = socket.read(&mut buf).await.unwrap_or(0);
+                let request = String::from_utf8_lossy(&buf[..n]).into_owned();
+                let count = served.fetch_add(1, Ordering::SeqCst);
+                let model = |model: &str| request.contains(&format!("\"model\":\"{}\"", model));
+                let (status, headers, body) = if model("limited") {
+                    (
+                        "429 Too Many Requests",
+                        "x-ratelimit-remaining-requests: 0\r\nx-ratelimit-reset-requests: 100ms\r\n",
+                        "Too Many Requests".to_string(),
+                    )
+                } else if model("quota") {
+                    ("429 Too Many Requests", "", r#"{"error": {"message": "quota", "type": "insufficient_quota", "param": null, "code": null}}"#.to_string())
+                } else if model("unauthorized") {
+                    (
+                        "401 Unauthorized",
+                        "",
+                        r#"{"detail": "Unauthorized"}"#.to_string(),
+                    )
+                } else if model("forbidden") {
+                    ("403 Forbidden", "", "Forbidden".to_string())
+                } else if model("overloaded") || (model("flaky") && count == 0) {
+                    (
+                        "503 Service Unavailable",
+                        "",
+                        "upstream connect error".to_string(),
+                    )
+                } else if model("long") {
+                    ("400 Bad Request", "", r#"{"object": "error", "message": "Prompt contains 40000 tokens, too large for model with 32768 maximum context length"}"#.to_string())
+                } else if model("large") {
+                    (
+                        "413 Payload Too Large",
+                        "",
+                        "Request too large: too many tokens".to_string(),
+                    )
+                } else if model("invalid") {
+                    ("400 Bad Request", "", "invalid temperature".to_string())
+                } else {
+                    (
+                        "200 OK",
+                        "",
+                        r#"{"choices": [{"message": {"role": "assistant", "content": "answer"}}]}"#
+                            .to_string(),
+                    )
+                };
+                let res = format!(
+                    "HTTP/1.1 {}\r\n{}content-length: {}\r\nconnection: close\r\n\r\n{}",
+                    status,
+                    headers,
+                    body.len(),
+                    body
+                );
+                let _ = socket.write_all(res.as_bytes()).await;
+            }
+        });
+
+        let messages = vec![Arc::new(Message::User("task".to_string()))];
+        let complete = |model: &str| {
+            let llm =
+                OpenAICompatible::builder(model.to_string(), &format!("http://{}/v1", addr), "key")
+                    .build();
+            let messages = messages.clone();
+            async move {
+                llm.completion(CompletionRequest {
+                    messages: &messages,
+                    tools: &[],
+                    web_search_tool: false,
+                    prefill: None,
+                })
+                .await
+            }
+        };
+
+        assert_eq!(complete("flaky").await?.content, "answer");
+        requests.store(0, Ordering::SeqCst);
+        assert!(matches!(
+            complete("limited").await,
+            Err(Error::RateLimited(_))
+        ));
+        assert_eq!(requests.swap(0, Ordering::SeqCst), 4);
+        assert_eq!(
+            complete("quota").await.err().map(|e| e.kind()),
+            Some(crate::ErrorKind::UserActionable)
+        );
+        assert_eq!(requests.swap(0, Ordering::SeqCst), 1);
+        assert!(matches!(
+            complete("unauthorized").await,
+            Err(Error::AuthError(m)) if m == "{\"detail\": \"Unauthorized\"}"
+        ));
+        assert!(matches!(
+            complete("forbidden").await,
+            Err(Error::AuthError(_))
+        ));
+        requests.store(0, Ordering::SeqCst);
+        assert!(
+            complete("overloaded")
+                .await
+                .err()
+                .is_some_and(|e| e.is_retryable())
+        );
+        assert_eq!(requests.load(Ordering::SeqCst), 4);
+        assert!(matches!(
+            complete("long").await,
+            Err(Error::ContextOverflow(_))
+        ));
+        assert!(matches!(
+            complete("large").await,
+            Err(Error::ContextOverflow(_))
+        ));
+        assert_eq!(
+            complete("invalid").await.err().map(|e| e.kind()),
+            Some(crate::ErrorKind::Fatal)
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_prefilled() {
+        assert_eq!(
+            prefilled("Solar grew.".to_string(), "## Findings\n"),
+            "## Findings\nSolar grew."
+        );
+        assert_eq!(
+            prefilled("## Findings\nSolar grew.".to_string(), "## Findings\n"),
+            "## Findings\nSolar grew."
+        );
+    }
+
+    #[test]
+    fn test_parse_response() {
+        let res = serde_json::json!({
+            "choices": [{
+                "message": {
+                    "role": "assistant",
+                    "content": null,
+                    "tool_calls": [
+                        {"id": "1", "type": "function", "function": {"name": "a", "arguments": "{\"x\":1}"}},
+                        {"id": "2", "type": "function", "function": {"name": "b", "arguments": {"y": 2}}},
+                    ],
+                },
+            }],
+        });
+
+        let parsed = parse_response(&res, &Quirks::default()).unwrap();
+        assert_eq!(parsed.content, "");
+        assert_eq!(parsed.tool_calls.len(), 2);
+        assert_eq!(parsed.tool_calls[0].args, "{\"x\":1}");
+        assert_eq!(parsed.tool_calls[1].args, "{\"y\":2}");
+
+        let res = serde_json::json!({
+            "choices": [{"message": {"role": "assistant", "content": "<think>hmm</think>\nanswer"}}],
+        });
+        let quirks = Quirks {
+            think_tags: true,
+            ..Quirks::default()
+        };
+        assert_eq!(parse_response(&res, &quirks).unwrap().content, "answer");
+
+        let res = serde_json::json!({
+            "choices": [
+                {"message": {"role": "assistant", "content": "first"}},
+                {"message": {"role": "assistant", "content": "second"}},
+            ],
+            "usage": {"prompt_tokens": 10, "completion_tokens": 4},
+        });
+        let second = parse_choice(&res, 1, &Quirks::default()).unwrap();
+        assert_eq!(second.content, "second");
+        assert_eq!(second.usage.requests, 0);
+        assert_eq!(
+            parse_response(&res, &Quirks::default())
+                .unwrap()
+                .usage
+                .prompt_tokens,
+            10
+        );
+
+        let res = serde_json::json!({"choices": []});
+        assert!(matches!(
+            parse_response(&res, &Quirks::default()),
+            Err(Error::LLMResponseError(_))
+        ));
+    }
+}
@@ -0,0 +1,193 @@
+use crate::Result;
+use crate::llm::TokenUsage;
+use async_trait::async_trait;
+#[cfg(feature = "native")]
+use {
+    crate::Error,
+    std::sync::{Arc, OnceLock},
+    std::time::Duration,
+    tokio::sync::{mpsc, oneshot},
+};
+
+// one embedding per input, in the order of the inputs
+#[derive(Clone, Default, Debug, PartialEq)]
+pub struct EmbeddingResponse {
+    pub embeddings: Vec<Vec<f32>>,
+    pub usage: TokenUsage,
+}
+
+#[async_trait]
+pub trait Embeddings {
+    async fn embed(&self, inputs: &[String]) -> Result<EmbeddingResponse>;
+}
+
+#[cfg(feature = "native")]
+type Pending = (
+    Vec<String>,
+    oneshot::Sender<std::result::Result<EmbeddingResponse, String>>,
+);
+
+// collects the inputs of concurrent requests for a short window and embeds them in one request,
+// it runs on a tokio task that is spawned with the first request, so that it can be created
+// outside of a runtime
+#[cfg(feature = "native")]
+pub struct BatchedEmbeddings {
+    inner: Arc<dyn Embeddings + Send + Sync>,
+    window: Duration,
+    max_batch: usize,
+    sender: OnceLock<mpsc::UnboundedSender<Pending>>,
+}
+
+#[cfg(feature = "native")]
+impl BatchedEmbeddings {
+    pub fn new(
+        inner: Arc<dyn Embeddings + Send + Sync>,
+        window: Duration,
+        max_batch: usize,
+    ) -> Arc<Self> {
+        Arc::new(Self {
+            inner,
+            window,
+            max_batch,
+            sender: OnceLock::new(),
+        })
+    }
+
+    fn sender(&self) -> &mpsc::UnboundedSender<Pending> {
+        self.sender.get_or_init(|| {
+            let (sender, receiver) = mpsc::unbounded_channel();
+            tokio::spawn(Self::run_batches(
+                self.inner.clone(),
+                receiver,
+                self.window,
+                self.max_batch,
+            ));
+            sender
+        })
+    }
+
+    async fn run_batches(
+        inner: Arc<dyn Embeddings + Send + Sync>,
+        mut receiver: mpsc::UnboundedReceiver<Pending>,
+        window: Duration,
+        max_batch: usize,
+    ) {
+        while let Some(first) = receiver.recv().await {
+            let mut batch = vec![first];
+            let mut size = batch[0].0.len();
+
+            let deadline = tokio::time::Instant::now() + window;
+            while size < max_batch {
+                match tokio::time::timeout_at(deadline, receiver.recv()).await {
+                    Ok(Some(pending)) => {
+                        size += pending.0.len();
+                        batch.push(pending);
+                    }
+                    _ => break,
+                }
+            }
+
+            let inputs = batch
+                .iter()
+                .flat_map(|(inputs, _)| inputs.iter().cloned())
+                .collect::<Vec<_>>();
+
+            match inner.embed(&inputs).await {
+                Ok(EmbeddingResponse {
+                    mut embeddings,
+                    usage,
+                }) => {
+                    // the tokens are shared in proportion to the inputs and the request is
+                    // counted once, with the first sender
+                    let total = inputs.len().max(1) as u64;
+                    for (i, (inputs, sender)) in batch.into_iter().enumerate() {
+                        let rest = embeddings.split_off(inputs.len().min(embeddings.len()));
+                        let share = TokenUsage {
+                            requests: if i == 0 { usage.requests } else { 0 },
+                            prompt_tokens: usage.prompt_tokens * inputs.len() as u64 / total,
+                            completion_tokens: 0,
+                        };
+                        let _ = sender.send(Ok(EmbeddingResponse {
+                            embeddings: std::mem::replace(&mut embeddings, rest),
+                            usage: share,
+                        }));
+                    }
+                }
+                Err(e) => {
+                    for (_, sender) in batch {
+                        let _ = sender.send(Err(e.to_string()));
+                    }
+                }
+            }
+        }
+    }
+}
+
+#[cfg(feature = "native")]
+#[async_trait]
+impl Embeddings for BatchedEmbeddings {
+    async fn embed(&self, inputs: &[String]) -> Result<EmbeddingResponse> {
+        let (sender, receiver) = oneshot::channel();
+        self.sender()
+            .send((inputs.to_vec(), sender))
+            .map_err(|_| Error::LLMResponseError("embedding batcher stopped".to_string()))?;
+
+        receiver
+            .await
+            .map_err(|_| Error::LLMResponseError("embedding batcher stopped".to_string()))?
+            .map_err(Error::LLMResponseError)
+    }
+}
+
+#[cfg(all(test, feature = "native"))]
+mod tests {
+    use super::{BatchedEmbeddings, EmbeddingResponse, Embeddings};
+    use crate::Result;
+    use crate::llm::TokenUsage;
+    use async_trait::async_trait;
+    use std::sync::Arc;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::time::Duration;
+
+    struct CountingEmbeddings(AtomicUsize);
+
+    #[async_trait]
+    impl Embeddings for CountingEmbeddings {
+        async fn embed(&self, inputs: &[String]) -> Result<EmbeddingResponse> {
+            self.0.fetch_add(1, Ordering::SeqCst);
+            Ok(EmbeddingResponse {
+                embeddings: inputs.iter().map(|i| vec![i.len() as f32]).collect(),
+                usage: TokenUsage {
+                    requests: 1,
+                    prompt_tokens: 10 * inputs.len() as u64,
+                    completion_tokens: 0,
+                },
+            })
+        }
+    }
+
+    #[tokio::test]
+    async fn test_batched_embeddings() -> Result<()> {
+        let inner = Arc::new(CountingEmbeddings(AtomicUsize::new(0)));
+        // created outside of the runtime, which it only needs once it embeds
+        let batched = std::thread::spawn({
+            let inner = inner.clone();
+            move || BatchedEmbeddings::new(inner, Duration::from_millis(50), 16)
+        })
+        .join()
+        .unwrap();
+
+        let a = ["a".to_string(), "bb".to_string()];
+        let b = ["ccc".to_string()];
+        let (a, b) = tokio::join!(batched.embed(&a), batched.embed(&b));
+
+        let (a, b) = (a?, b?);
+        assert_eq!(a.embeddings, vec![vec![1.0], vec![2.0]]);
+        assert_eq!(b.embeddings, vec![vec![3.0]]);
+        assert_eq!((a.usage.requests, a.usage.prompt_tokens), (1, 20));
+        assert_eq!((b.usage.requests, b.usage.prompt_tokens), (0, 10));
+        assert_eq!(inner.0.load(Ordering::SeqCst), 1);
+
+        Ok(())
+    }
+}
@@ -1,20 +1,75 @@
-use crate::Result;
 use crate::tools::{ToolCall, ToolDefinition};
+use crate::{History, Result};
 use async_trait::async_trait;
 use std::hash::{Hash, Hasher};
 
+mod best_of;
+pub use best_of::{BestOf, Heuristic, Judge, Selector};
+
+mod coalesce;
+pub use coalesce::Coalescing;
+
+mod embeddings;
+#[cfg(feature = "native")]
+pub use embeddings::BatchedEmbeddings;
+pub use embeddings::{EmbeddingResponse, Embeddings};
+
+#[cfg(feature = "native")]
+mod compatible;
+#[cfg(feature = "native")]
+pub use compatible::{GenerationParams, OpenAICompatible, OpenAICompatibleBuilder, Quirks};
+
+#[cfg(feature = "native")]
 mod openai;
-pub use openai::OpenAI;
+#[cfg(feature = "native")]
+pub use openai::{OpenAI, OpenAIEmbeddings};
+
+#[cfg(feature = "native")]
+mod rate_limit;
+#[cfg(feature = "native")]
+pub use rate_limit::RateLimiter;
+
+mod routing;
+pub use routing::{RequestProfile, RoutingLLM, RoutingLLMBuilder, StepKind};
+
+mod tool_result;
+pub use tool_result::ToolResult;
 
-#[derive(Clone, std::hash::Hash, Debug)]
+mod usage;
+pub use usage::{Pricing, TokenUsage, Usage};
+
+// tokens a provider charges for an image of a typical screen size
+const IMAGE_TOKENS: usize = 765;
+
+// an image attached to a user message, as a data url with the encoded image or a url the provider
+// downloads, only vision capable models accept them
+#[derive(Clone, std::hash::Hash, Debug, serde::Serialize, serde::Deserialize)]
+pub struct Image {
+    pub url: String,
+}
+
+impl Image {
+    pub fn data_url(media_type: &str, base64: &str) -> Self {
+        Self {
+            url: format!("data:{};base64,{}", media_type, base64),
+        }
+    }
+}
+
+#[derive(Clone, std::hash::Hash, Debug, serde::Serialize, serde::Deserialize)]
 pub enum Message {
     User(String),
+    UserImages(String, Vec<Image>),
     Assistant(String, Vec<ToolCall>),
     System(String),
+    // instructions the application adds while the agent runs, such as status updates and
+    // warnings, told apart from the task of the user; providers without the developer role
+    // receive them as system messages
+    Developer(String),
     Tool {
         id: String,
         name: String,
-        result: String,
+        result: ToolResult,
     },
 }
 
@@ -22,8 +77,12 @@ impl Message {
     pub fn ntokens(&self) -> usize {
         match self {
             Message::User(content) => content.split_whitespace().count(),
+            Message::UserImages(content, images) => {
+                content.split_whitespace().count() + images.len() * IMAGE_TOKENS
+            }
             Message::Assistant(content, _) => content.split_whitespace().count(),
             Message::System(content) => content.split_whitespace().count(),
+            Message::Developer(content) => content.split_whitespace().count(),
             Message::Tool { result, .. } => result.split_whitespace().count(),
         }
     }
@@ -39,13 +98,23 @@ impl std::fmt::Display for Message {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
             Message::Assistant(content, tool_calls) => {
-                write!(f, "__Assistant:__ {}\n", content)?;
+                writeln!(f, "__Assistant:__ {}", content)?;
                 tool_calls.iter().try_for_each(|t| ToolCall::fmt(t, f))?;
             }
-            Message::System(content) => write!(f, "__System:__ {}\n", content)?,
-            Message::User(content) => write!(f, "__User:__ {}\n", content)?,
+            Message::System(content) => writeln!(f, "__System:__ {}", content)?,
+            Message::Developer(content) => writeln!(f, "__Developer:__ {}", content)?,
+            Message::User(content) => writeln!(f, "__User:__ {}", content)?,
+            // the encoded images would swamp the logs
+            Message::UserImages(content, images) => {
+                writeln!(f, "__User:__ {} [{} image(s)]", content, images.len())?
+            }
             Message::Tool { id, name, result } => {
-                write!(f, "__Tool:__ {} ({})\n{}\n", name, id, result)?
+                match result.data().map(serde_json::to_string_pretty) {
+                    Some(Ok(pretty)) => {
+                        write!(f, "__Tool:__ {} ({})\n```json\n{}\n```\n", name, id, pretty)?
+                    }
+                    _ => write!(f, "__Tool:__ {} ({})\n{}\n", name, id, result)?,
+                }
             }
         }
 
@@ -53,18 +122,56 @@ impl std::fmt::Display for Message {
     }
 }
 
+#[derive(Clone, Copy)]
 pub struct CompletionRequest<'a> {
-    pub messages: &'a [Message],
+    pub messages: &'a dyn History,
     pub tools: &'a [ToolDefinition],
     pub web_search_tool: bool,
+    // the start of the reply, which the model continues, e.g. "## Findings\n" to force a format;
+    // the content of the response starts with it. Of the openai compatible providers only
+    // mistral continues a prefilled assistant message, the others are told how to start instead
+    pub prefill: Option<&'a str>,
 }
 
+#[derive(Clone, Default)]
 pub struct CompletionResponse {
     pub content: String,
     pub tool_calls: Vec<ToolCall>,
+    pub usage: TokenUsage,
+}
+
+impl CompletionResponse {
+    // the content followed by the arguments of the tool calls, which is where agents put reports
+    // they submit with a tool
+    pub fn text(&self) -> String {
+        std::iter::once(self.content.as_str())
+            .chain(self.tool_calls.iter().map(|call| call.args.as_str()))
+            .filter(|text| !text.is_empty())
+            .collect::<Vec<_>>()
+            .join("\n")
+    }
 }
 
 #[async_trait]
 pub trait LLM {
     async fn completion<'a>(&self, request: CompletionRequest<'a>) -> Result<CompletionResponse>;
+
+    // n completions of the same request, one request after the other unless the provider samples
+    // several in one call
+    async fn completions<'a>(
+        &self,
+        request: CompletionRequest<'a>,
+        n: usize,
+    ) -> Result<Vec<CompletionResponse>> {
+        let mut completions = Vec::with_capacity(n);
+        for _ in 0..n.max(1) {
+            completions.push(self.completion(request).await?);
+        }
+        Ok(completions)
+    }
+
+    // whether the provider searches the web itself for requests with web_search_tool set
+    fn web_search(&self) -> bool {
+        true
+    }
 }
@@ -1,31 +1,35 @@
 use crate::llm;
+use crate::llm::compatible::api_key;
+use crate::llm::{OpenAICompatible, OpenAICompatibleBuilder};
 use crate::{Error, Result};
 use async_openai::{
     Client,
     config::OpenAIConfig,
     types::{
         ChatCompletionMessageToolCall, ChatCompletionRequestAssistantMessageArgs,
-        ChatCompletionRequestAssistantMessageContent, ChatCompletionRequestMessage,
+        ChatCompletionRequestAssistantMessageContent, ChatCompletionRequestDeveloperMessage,
+        ChatCompletionRequestDeveloperMessageContent, ChatCompletionRequestMessage,
+        ChatCompletionRequestMessageContentPartImage, ChatCompletionRequestMessageContentPartText,
         ChatCompletionRequestSystemMessage, ChatCompletionRequestSystemMessageContent,
         ChatCompletionRequestToolMessage, ChatCompletionRequestToolMessageContent,
         ChatCompletionRequestUserMessage, ChatCompletionRequestUserMessageContent,
-        ChatCompletionTool, ChatCompletionToolArgs, ChatCompletionToolType,
-        CreateChatCompletionRequestArgs, FunctionCall, FunctionObjectArgs, Role, WebSearchOptions,
+        ChatCompletionRequestUserMessageContentPart, ChatCompletionTool, ChatCompletionToolArgs,
+        ChatCompletionToolType, CreateEmbeddingRequestArgs, FunctionCall, FunctionObjectArgs,
+        ImageDetail, ImageUrl,
     },
 };
 use async_trait::async_trait;
+use std::sync::Arc;
 
-pub struct OpenAI {
-    model: String,
-    client: Client<OpenAIConfig>,
-}
+pub type OpenAI = OpenAICompatible;
 
-impl OpenAI {
-    pub fn new(model: String) -> std::sync::Arc<Self> {
-        std::sync::Arc::new(Self {
-            model,
-            client: Client::new(),
-        })
+impl OpenAICompatible {
+    pub fn new(model: String) -> Arc<Self> {
+        Self::openai(model).build()
+    }
+
+    pub fn openai(model: String) -> OpenAICompatibleBuilder {
+        OpenAICompatibleBuilder::openai(model)
     }
 }
 
@@ -40,15 +44,42 @@ impl TryFrom<&llm::Message> for ChatCompletionRequestMessage {
                     name: None,
                 },
             )),
+            llm::Message::UserImages(msg, images) => Ok(ChatCompletionRequestMessage::User(
+                ChatCompletionRequestUserMessage {
+                    content: ChatCompletionRequestUserMessageContent::Array(
+                        std::iter::once(ChatCompletionRequestUserMessageContentPart::Text(
+                            ChatCompletionRequestMessageContentPartText { text: msg.clone() },
+                        ))
+                        .chain(images.iter().map(|image| {
+                            ChatCompletionRequestUserMessageContentPart::ImageUrl(
+                                ChatCompletionRequestMessageContentPartImage {
+                                    image_url: ImageUrl {
+                                        url: image.url.clone(),
+                                        detail: Some(ImageDetail::Auto),
+                                    },
+                                },
+                            )
+                        }))
+                        .collect(),
+                    ),
+                    name: None,
+                },
+            )),
             llm::Message::System(msg) => Ok(ChatCompletionRequestMessage::System(
                 ChatCompletionRequestSystemMessage {
                     content: ChatCompletionRequestSystemMessageContent::Text(msg.clone()),
                     name: None,
                 },
             )),
+            llm::Message::Developer(msg) => Ok(ChatCompletionRequestMessage::Developer(
+                ChatCompletionRequestDeveloperMessage {
+                    content: ChatCompletionRequestDeveloperMessageContent::Text(msg.clone()),
+                    name: None,
+                },
+            )),
             llm::Message::Tool { id, result, .. } => Ok(ChatCompletionRequestMessage::Tool(
                 ChatCompletionRequestToolMessage {
-                    content: ChatCompletionRequestToolMessageContent::Text(result.clone()),
+                    content: ChatCompletionRequestToolMessageContent::Text(result.to_string()),
                     tool_call_id: id.clone(),
                 },
             )),
@@ -88,6 +119,7 @@ impl TryFrom<&llm::ToolDefinition> for ChatCompletionTool {
                     .name(tool.name.clone())
                     .description(tool.desc.clone())
                     .parameters(tool.params.clone())
+                    .strict(tool.strict)
                     .build()?,
             )
             .build()?;
@@ -96,70 +128,98 @@ impl TryFrom<&llm::ToolDefinition> for ChatCompletionTool {
     }
 }
 
+pub struct OpenAIEmbeddings {
+    model: String,
+    client: Client<OpenAIConfig>,
+}
+
+impl OpenAIEmbeddings {
+    pub fn new(model: String) -> Arc<Self> {
+        Arc::new(Self {
+            model,
+            client: Client::new(),
+        })
+    }
+
+    // the embeddings of another api that implements the embeddings endpoint of openai
+    pub fn compatible(model: String, api_base: &str, api_key: &str) -> Arc<Self> {
+        Arc::new(Self {
+            model,
+            client: Client::with_config(
+                OpenAIConfig::new()
+                    .with_api_base(api_base)
+                    .with_api_key(api_key),
+            ),
+        })
+    }
+
+    pub fn mistral(model: String) -> Arc<Self> {
+        Self::compatible(
+            model,
+            "https://api.mistral.ai/v1",
+            &api_key("MISTRAL_API_KEY"),
+        )
+    }
+}
+
 #[async_trait]
-impl llm::LLM for OpenAI {
-    async fn completion<'a>(
-        &self,
-        request: llm::CompletionRequest<'a>,
-    ) -> Result<llm::CompletionResponse> {
-        let mut completion = CreateChatCompletionRequestArgs::default();
-        completion
+impl llm::Embeddings for OpenAIEmbeddings {
+    async fn embed(&self, inputs: &[String]) -> Result<llm::EmbeddingResponse> {
+        let request = CreateEmbeddingRequestArgs::default()
             .model(&self.model)
-            .messages(
-                request
-                    .messages
-                    .into_iter()
-                    .map(ChatCompletionRequestMessage::try_from)
-                    .collect::<Result<Vec<_>>>()?,
-            )
-            .tools(
-                request
-                    .tools
-                    .into_iter()
-                    .map(ChatCompletionTool::try_from)
-                    .collect::<Result<Vec<_>>>()?,
-            );
-
-        if request.web_search_tool {
-            completion.web_search_options(WebSearchOptions::default());
-        }
-
-        let completion = completion.build()?;
+            .input(inputs.to_vec())
+            .build()?;
 
-        let res = self.client.chat().create(completion).await?;
+        let mut res = self.client.embeddings().create(request).await?;
 
-        if res.choices.is_empty() {
-            return Err(Error::LLMResponseError("choices is empty".to_string()));
+        if res.data.len() != inputs.len() {
+            return Err(Error::LLMResponseError(format!(
+                "expected {} embeddings, got {}",
+                inputs.len(),
+                res.data.len()
+            )));
         }
 
-        if res.choices[0].message.role != Role::User {
-            return Err(Error::LLMResponseError(
-                "expected role to be assistant".to_string(),
-            ));
-        }
+        res.data.sort_by_key(|e| e.index);
+        Ok(llm::EmbeddingResponse {
+            embeddings: res.data.into_iter().map(|e| e.embedding).collect(),
+            usage: llm::TokenUsage {
+                requests: 1,
+                prompt_tokens: res.usage.prompt_tokens as u64,
+                completion_tokens: 0,
+            },
+        })
+    }
+}
 
-        let content = res.choices[0]
-            .message
-            .content
-            .as_ref()
-            .ok_or(Error::LLMResponseError("content is empty".to_string()))?;
-
-        let tool_calls = res.choices[0]
-            .message
-            .tool_calls
-            .iter()
-            .flat_map(|calls| {
-                calls.iter().map(|call| llm::ToolCall {
-                    id: call.id.clone(),
-                    name: call.function.name.clone(),
-                    args: call.function.arguments.clone(),
-                })
+#[cfg(test)]
+mod tests {
+    use crate::llm::{Image, Message};
+    use async_openai::types::ChatCompletionRequestMessage;
+
+    #[test]
+    fn test_image_message() {
+        let message = Message::UserImages(
+            "describe the chart".to_string(),
+            vec![Image::data_url("image/png", "iVBORw0KGgo=")],
+        );
+        let request =
+            serde_json::to_value(ChatCompletionRequestMessage::try_from(&message).unwrap())
+                .unwrap();
+        assert_eq!(
+            request,
+            serde_json::json!({
+                "role": "user",
+                "content": [
+                    {"type": "text", "text": "describe the chart"},
+                    {"type": "image_url", "image_url": {"url": "data:image/png;base64,iVBORw0KGgo=", "detail": "auto"}},
+                ],
             })
-            .collect();
-
-        Ok(llm::CompletionResponse {
-            content: content.clone(),
-            tool_calls,
-        })
+        );
+        // the encoded image stays out of the logs
+        assert_eq!(
+            message.to_string(),
+            "__User:__ describe the chart [1 image(s)]\n\n"
+        );
     }
 }
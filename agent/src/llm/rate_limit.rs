@@ -0,0 +1,198 @@
+use reqwest::header::HeaderMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+#[derive(Default)]
+struct Limit {
+    remaining: Option<u64>,
+    reset: Option<Instant>,
+}
+
+impl Limit {
+    fn update(&mut self, remaining: Option<u64>, reset: Option<Duration>, now: Instant) {
+        if let Some(remaining) = remaining {
+            self.remaining = Some(remaining);
+            self.reset = reset.map(|reset| now + reset);
+        }
+    }
+
+    fn expire(&mut self, now: Instant) {
+        if self.reset.is_some_and(|reset| reset <= now) {
+            *self = Self::default();
+        }
+    }
+
+    // returns how long to wait before `amount` is available in the remaining quota
+    fn wait(&self, amount: u64, now: Instant) -> Option<Duration> {
+        match (self.remaining, self.reset) {
+            (Some(remaining), Some(reset)) if remaining < amount => Some(reset - now),
+            _ => None,
+        }
+    }
+
+    fn consume(&mut self, amount: u64) {
+        self.remaining = self.remaining.map(|r| r.saturating_sub(amount));
+    }
+}
+
+#[derive(Default)]
+struct Quota {
+    requests: Limit,
+    tokens: Limit,
+}
+
+impl Quota {
+    fn acquire(&mut self, tokens: u64, now: Instant) -> Option<Duration> {
+        self.requests.expire(now);
+        self.tokens.expire(now);
+
+        let wait = self
+            .requests
+            .wait(1, now)
+            .max(self.tokens.wait(tokens, now));
+        if wait.is_none() {
+            self.requests.consume(1);
+            self.tokens.consume(tokens);
+        }
+        wait
+    }
+}
+
+pub struct RateLimiter {
+    quota: Mutex<Quota>,
+}
+
+impl RateLimiter {
+    pub fn new() -> Arc<Self> {
+        Arc::new(Self {
+            quota: Mutex::new(Quota::default()),
+        })
+    }
+
+    pub async fn acquire(&self, tokens: usize) {
+        loop {
+            let wait = self
+                .quota
+                .lock()
+                .unwrap()
+                .acquire(tokens as u64, Instant::now());
+
+            match wait {
+                Some(wait) => tokio::time::sleep(wait).await,
+                None => return,
+            }
+        }
+    }
+
+    pub fn update(&self, headers: &HeaderMap) {
+        let header = |name: &str| headers.get(name).and_then(|v| v.to_str().ok());
+
+        let now = Instant::now();
+        let mut quota = self.quota.lock().unwrap();
+        quota.requests.update(
+            header("x-ratelimit-remaining-requests").and_then(|v| v.parse().ok()),
+            header("x-ratelimit-reset-requests").and_then(parse_duration),
+            now,
+        );
+        quota.tokens.update(
+            header("x-ratelimit-remaining-tokens").and_then(|v| v.parse().ok()),
+            header("x-ratelimit-reset-tokens").and_then(parse_duration),
+            now,
+        );
+    }
+
+    // called when the provider rejected a request with a rate limit error
+    pub fn exhausted(&self) {
+        let now = Instant::now();
+        let mut quota = self.quota.lock().unwrap();
+
+        let reset = quota
+            .requests
+            .reset
+            .max(quota.tokens.reset)
+            .filter(|reset| *reset > now)
+            .unwrap_or(now + Duration::from_secs(1));
+
+        quota.requests.remaining = Some(0);
+        quota.requests.reset = Some(reset);
+    }
+}
+
+// parses durations in the format used by the rate limit headers, e.g. "1s", "6m0s", or "20ms"
+fn parse_duration(s: &str) -> Option<Duration> {
+    let mut total = 0.0;
+    let mut rest = s.trim();
+
+    if rest.is_empty() {
+        return None;
+    }
+
+    while !rest.is_empty() {
+        let split = rest
+            .find(|c: char| !c.is_ascii_digit() && c != '.')
+            .unwrap_or(rest.len());
+        let value: f64 = rest[..split].parse().ok()?;
+        rest = &rest[split..];
+
+        let unit_len = rest
+            .find(|c: char| c.is_ascii_digit())
+            .unwrap_or(rest.len());
+        let scale = match &rest[..unit_len] {
+            "ms" => 0.001,
+            "s" => 1.0,
+            "m" => 60.0,
+            "h" => 3600.0,
+            _ => return None,
+        };
+        rest = &rest[unit_len..];
+
+        total += value * scale;
+    }
+
+    Some(Duration::from_secs_f64(total))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Limit, Quota, parse_duration};
+    use std::time::{Duration, Instant};
+
+    #[test]
+    fn test_parse_duration() {
+        assert_eq!(parse_duration("1s"), Some(Duration::from_secs(1)));
+        assert_eq!(parse_duration("6m0s"), Some(Duration::from_secs(360)));
+        assert_eq!(parse_duration("20ms"), Some(Duration::from_millis(20)));
+        assert_eq!(
+            parse_duration("1h1m1.5s"),
+            Some(Duration::from_secs_f64(3661.5))
+        );
+        assert_eq!(parse_duration("soon"), None);
+        assert_eq!(parse_duration(""), None);
+    }
+
+    #[test]
+    fn test_quota() {
+        let now = Instant::now();
+        let mut quota = Quota::default();
+        assert_eq!(quota.acquire(1000, now), None);
+
+        quota
+            .requests
+            .update(Some(1), Some(Duration::from_secs(2)), now);
+        quota
+            .tokens
+            .update(Some(500), Some(Duration::from_secs(10)), now);
+
+        assert_eq!(quota.acquire(1000, now), Some(Duration::from_secs(10)));
+        assert_eq!(quota.acquire(100, now), None);
+        assert_eq!(quota.acquire(100, now), Some(Duration::from_secs(2)));
+
+        let later = now + Duration::from_secs(3);
+        assert_eq!(quota.acquire(100, later), None);
+        assert_eq!(quota.tokens.remaining, Some(300));
+
+        let mut limit = Limit::default();
+        limit.update(None, Some(Duration::from_secs(1)), now);
+        assert_eq!(limit.remaining, None);
+    }
+}
@@ -0,0 +1,169 @@
+use crate::Result;
+use crate::llm::{CompletionRequest, CompletionResponse, LLM, Message};
+use async_trait::async_trait;
+use std::sync::Arc;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum StepKind {
+    // no tools are offered, e.g. summarizing a history or extracting claims from a report
+    Extract,
+    // tools are offered and the model decides how to proceed from a user message, e.g. planning
+    Reason,
+    // tools are offered and the model continues after the results of its tool calls
+    ToolFollowUp,
+}
+
+// what a routing condition knows about a request
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct RequestProfile {
+    pub kind: StepKind,
+    // rough size of the request in tokens
+    pub tokens: usize,
+    pub tool_results: usize,
+}
+
+impl RequestProfile {
+    pub fn of(request: &CompletionRequest) -> Self {
+        let last_is_tool = matches!(
+            request.messages.last().map(|m| m.as_ref()),
+            Some(Message::Tool { .. })
+        );
+        let kind = if request.tools.is_empty() && !request.web_search_tool {
+            StepKind::Extract
+        } else if last_is_tool {
+            StepKind::ToolFollowUp
+        } else {
+            StepKind::Reason
+        };
+
+        Self {
+            kind,
+            tokens: request.messages.token_count(),
+            tool_results: request
+                .messages
+                .iter()
+                .filter(|m| matches!(m.as_ref(), Message::Tool { .. }))
+                .count(),
+        }
+    }
+}
+
+type Condition = Box<dyn Fn(&RequestProfile) -> bool + Send + Sync>;
+
+// sends every request to the first model whose condition matches it, for instance cheap models
+// for summaries and extraction and a frontier model for planning and synthesis
+pub struct RoutingLLM {
+    routes: Vec<(Condition, Arc<dyn LLM + Send + Sync>)>,
+    default: Arc<dyn LLM + Send + Sync>,
+}
+
+pub struct RoutingLLMBuilder {
+    routes: Vec<(Condition, Arc<dyn LLM + Send + Sync>)>,
+    default: Arc<dyn LLM + Send + Sync>,
+}
+
+impl RoutingLLM {
+    pub fn builder(default: Arc<dyn LLM + Send + Sync>) -> RoutingLLMBuilder {
+        RoutingLLMBuilder {
+            routes: Vec::new(),
+            default,
+        }
+    }
+
+    fn select(&self, profile: &RequestProfile) -> &Arc<dyn LLM + Send + Sync> {
+        self.routes
+            .iter()
+            .find(|(condition, _)| condition(profile))
+            .map(|(_, llm)| llm)
+            .unwrap_or(&self.default)
+    }
+}
+
+impl RoutingLLMBuilder {
+    pub fn route(
+        mut self,
+        condition: impl Fn(&RequestProfile) -> bool + Send + Sync + 'static,
+        llm: Arc<dyn LLM + Send + Sync>,
+    ) -> Self {
+        self.routes.push((Box::new(condition), llm));
+        self
+    }
+
+    pub fn build(self) -> Arc<RoutingLLM> {
+        Arc::new(RoutingLLM {
+            routes: self.routes,
+            default: self.default,
+        })
+    }
+}
+
+#[async_trait]
+impl LLM for RoutingLLM {
+    async fn completion<'a>(&self, request: CompletionRequest<'a>) -> Result<CompletionResponse> {
+        let profile = RequestProfile::of(&request);
+        self.select(&profile).completion(request).await
+    }
+
+    async fn completions<'a>(
+        &self,
+        request: CompletionRequest<'a>,
+        n: usize,
+    ) -> Result<Vec<CompletionResponse>> {
+        let profile = RequestProfile::of(&request);
+        self.select(&profile).completions(request, n).await
+    }
+
+    // any of the models may get a request that searches the web
+    fn web_search(&self) -> bool {
+        self.default.web_search() && self.routes.iter().all(|(_, llm)| llm.web_search())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{RoutingLLM, StepKind};
+    use crate::Result;
+    use crate::llm::{CompletionRequest, CompletionResponse, LLM, Message};
+    use crate::tools::ToolDefinition;
+    use async_trait::async_trait;
+    use std::sync::Arc;
+
+    struct Named(&'static str);
+
+    #[async_trait]
+    impl LLM for Named {
+        async fn completion<'a>(&self, _: CompletionRequest<'a>) -> Result<CompletionResponse> {
+            Ok(CompletionResponse {
+                content: self.0.to_string(),
+                ..Default::default()
+            })
+        }
+    }
+
+    #[tokio::test]
+    async fn test_routing() -> Result<()> {
+        let llm = RoutingLLM::builder(Arc::new(Named("frontier")))
+            .route(|p| p.kind == StepKind::Extract, Arc::new(Named("cheap")))
+            .route(|p| p.tokens > 3, Arc::new(Named("long")))
+            .build();
+        let tools = [ToolDefinition::no_args("finish", "finish")];
+
+        let short = vec![Arc::new(Message::User("task".to_string()))];
+        let long = vec![Arc::new(Message::User("a much longer task".to_string()))];
+        let route = |messages, tools| {
+            llm.completion(CompletionRequest {
+                messages,
+                tools,
+                web_search_tool: false,
+                prefill: None,
+            })
+        };
+
+        assert_eq!(route(&short, &[]).await?.content, "cheap");
+        assert_eq!(route(&long, &[]).await?.content, "cheap");
+        assert_eq!(route(&short, &tools).await?.content, "frontier");
+        assert_eq!(route(&long, &tools).await?.content, "long");
+
+        Ok(())
+    }
+}
@@ -0,0 +1,132 @@
+use crate::{Error, Result};
+use serde::Serialize;
+use serde::de::DeserializeOwned;
+use std::hash::{Hash, Hasher};
+
+// the result of a tool call, either plain text or structured json. The text is what providers
+// send to the model, for json results it is the serialized value, and the value itself lets
+// code that inspects the history read the result without parsing the text again
+#[derive(Clone, Debug, Default, PartialEq, Serialize, serde::Deserialize)]
+pub struct ToolResult {
+    text: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    data: Option<serde_json::Value>,
+}
+
+impl ToolResult {
+    pub fn text(text: impl Into<String>) -> Self {
+        Self {
+            text: text.into(),
+            data: None,
+        }
+    }
+
+    pub fn json<T: Serialize>(value: &T) -> Result<Self> {
+        let data = serde_json::to_value(value)?;
+        Ok(Self {
+            text: data.to_string(),
+            data: Some(data),
+        })
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.text
+    }
+
+    pub fn data(&self) -> Option<&serde_json::Value> {
+        self.data.as_ref()
+    }
+
+    // reads a typed value from a json result, text results are parsed as json
+    pub fn parse<T: DeserializeOwned>(&self) -> Result<T> {
+        match &self.data {
+            Some(data) => T::deserialize(data),
+            None => serde_json::from_str(self.text.trim()),
+        }
+        .map_err(|e| Error::AgentWorkflowError(format!("unexpected tool result: {}", e)))
+    }
+
+    // results that are combined are no longer a single json value, so the structure is dropped
+    pub fn append(&mut self, other: &ToolResult) {
+        self.text.push_str("\n\n");
+        self.text.push_str(&other.text);
+        self.data = None;
+    }
+}
+
+impl std::ops::Deref for ToolResult {
+    type Target = str;
+
+    fn deref(&self) -> &str {
+        &self.text
+    }
+}
+
+impl Hash for ToolResult {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.text.hash(state);
+    }
+}
+
+impl PartialEq<str> for ToolResult {
+    fn eq(&self, other: &str) -> bool {
+        self.text == other
+    }
+}
+
+impl From<String> for ToolResult {
+    fn from(text: String) -> Self {
+        Self::text(text)
+    }
+}
+
+impl From<&str> for ToolResult {
+    fn from(text: &str) -> Self {
+        Self::text(text)
+    }
+}
+
+impl std::fmt::Display for ToolResult {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.text)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::ToolResult;
+    use crate::Result;
+
+    #[derive(serde::Serialize, serde::Deserialize, Debug, PartialEq)]
+    struct Verdict {
+        status: String,
+        confidence: f64,
+    }
+
+    #[test]
+    fn test_tool_result() -> Result<()> {
+        let verdict = Verdict {
+            status: "supported".to_string(),
+            confidence: 0.5,
+        };
+        let result = ToolResult::json(&verdict)?;
+        assert_eq!(
+            result.as_str(),
+            r#"{"confidence":0.5,"status":"supported"}"#
+        );
+        assert_eq!(result.parse::<Verdict>()?, verdict);
+
+        let text = ToolResult::from(r#" {"status": "supported", "confidence": 0.5} "#);
+        assert_eq!(text.parse::<Verdict>()?, verdict);
+        assert!(text.data().is_none());
+        assert!(ToolResult::from("plain").parse::<Verdict>().is_err());
+
+        let mut combined = result.clone();
+        combined.append(&ToolResult::from("more"));
+        assert!(combined.data().is_none());
+        assert!(combined.ends_with("\n\nmore"));
+        assert!(&combined != "more");
+
+        Ok(())
+    }
+}
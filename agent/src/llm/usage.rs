@@ -0,0 +1,89 @@
+use std::sync::{Arc, Mutex};
+
+#[derive(Clone, Copy, Default, Debug, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct TokenUsage {
+    pub requests: u64,
+    pub prompt_tokens: u64,
+    pub completion_tokens: u64,
+}
+
+impl TokenUsage {
+    pub fn total_tokens(&self) -> u64 {
+        self.prompt_tokens + self.completion_tokens
+    }
+
+    pub fn cost(&self, pricing: &Pricing) -> f64 {
+        (self.prompt_tokens as f64 * pricing.prompt
+            + self.completion_tokens as f64 * pricing.completion)
+            / 1_000_000.0
+    }
+}
+
+// prices in USD per million tokens
+#[derive(Clone, Copy, Default, Debug, PartialEq)]
+pub struct Pricing {
+    pub prompt: f64,
+    pub completion: f64,
+}
+
+impl std::ops::AddAssign for TokenUsage {
+    fn add_assign(&mut self, other: Self) {
+        self.requests += other.requests;
+        self.prompt_tokens += other.prompt_tokens;
+        self.completion_tokens += other.completion_tokens;
+    }
+}
+
+#[derive(Default)]
+pub struct Usage {
+    total: Mutex<TokenUsage>,
+    parent: Option<Arc<Usage>>,
+}
+
+impl Usage {
+    pub fn new() -> Arc<Self> {
+        Arc::new(Self::default())
+    }
+
+    // usage recorded by the child is also added to this usage, which lets a single agent's
+    // usage be tracked while it still counts towards the total of the run
+    pub fn child(self: &Arc<Self>) -> Arc<Self> {
+        Arc::new(Self {
+            total: Mutex::new(TokenUsage::default()),
+            parent: Some(self.clone()),
+        })
+    }
+
+    pub fn record(&self, usage: TokenUsage) {
+        *self.total.lock().unwrap() += usage;
+        if let Some(parent) = &self.parent {
+            parent.record(usage);
+        }
+    }
+
+    pub fn total(&self) -> TokenUsage {
+        *self.total.lock().unwrap()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{TokenUsage, Usage};
+
+    #[test]
+    fn test_child_usage() {
+        let usage = Usage::new();
+        let child = usage.child();
+        let record = TokenUsage {
+            requests: 1,
+            prompt_tokens: 10,
+            completion_tokens: 5,
+        };
+        usage.record(record);
+        child.record(record);
+
+        assert_eq!(child.total().total_tokens(), 15);
+        assert_eq!(usage.total().total_tokens(), 30);
+        assert_eq!(usage.total().requests, 2);
+    }
+}
@@ -0,0 +1,353 @@
+use crate::{Error, Result};
+use ring::aead::{AES_256_GCM, Aad, LessSafeKey, NONCE_LEN, Nonce, UnboundKey};
+use ring::rand::{SecureRandom, SystemRandom};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+use std::num::NonZeroU32;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+/// The environment variable holding the passphrase of the encrypted secrets file, unless the
+/// config names another one.
+pub const DEFAULT_KEY_ENV: &str = "RESEARCH_SECRETS_KEY";
+
+const MAGIC: &[u8] = b"research-secrets-1\n";
+const SALT_LEN: usize = 16;
+const KEY_LEN: usize = 32;
+const PBKDF2_ROUNDS: u32 = 100_000;
+const REDACTED: &str = "[redacted]";
+
+/// Where the value of a named credential is read from.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "from", rename_all = "lowercase")]
+pub enum SecretSource {
+    /// an environment variable
+    Env { var: String },
+    /// the keyring of the operating system, read with secret-tool on linux and security on macos
+    Keyring { service: String, account: String },
+    /// an entry of the encrypted secrets file, the name of the credential unless given
+    File {
+        #[serde(default)]
+        entry: Option<String>,
+    },
+}
+
+/// The credentials the tools authenticate with and where they are read from.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct SecretsConfig {
+    /// the sources of the credentials by name
+    #[serde(default)]
+    pub credentials: BTreeMap<String, SecretSource>,
+    /// the encrypted file the file credentials are read from
+    #[serde(default)]
+    pub file: Option<PathBuf>,
+    /// the environment variable holding the passphrase of the file, defaults to
+    /// RESEARCH_SECRETS_KEY
+    #[serde(default)]
+    pub key_env: Option<String>,
+}
+
+impl SecretsConfig {
+    /// Reads a config from a JSON file, a relative path of the secrets file is read from the
+    /// directory of the config.
+    pub fn read(path: &Path) -> Result<Self> {
+        let mut config: Self = serde_json::from_slice(&std::fs::read(path)?)?;
+        if let Some(file) = &config.file
+            && file.is_relative()
+            && let Some(dir) = path.parent()
+        {
+            config.file = Some(dir.join(file));
+        }
+        Ok(config)
+    }
+}
+
+/// The value of a credential. It is printed as [redacted], so that it cannot end up in logs or
+/// prompts by accident, and only read with expose where a request is authenticated.
+#[derive(Clone)]
+pub struct Secret(Arc<str>);
+
+impl Secret {
+    pub fn new(value: &str) -> Self {
+        Self(value.into())
+    }
+
+    pub fn expose(&self) -> &str {
+        &self.0
+    }
+
+    /// Replaces the value in text shown to the model, such as an error that quotes the url of a
+    /// request authenticated with a query parameter, also where it is percent-encoded as in
+    /// urls and form bodies.
+    pub fn redact(&self, text: &str) -> String {
+        if self.0.is_empty() {
+            return text.to_string();
+        }
+        let mut text = text.replace(&*self.0, REDACTED);
+        for space in ["+", "%20"] {
+            let encoded = encode(&self.0, space);
+            if encoded != *self.0 {
+                text = text.replace(&encoded, REDACTED);
+            }
+        }
+        text
+    }
+}
+
+// percent-encodes the value as urls and form bodies do, which differ in how they encode spaces
+fn encode(value: &str, space: &str) -> String {
+    value
+        .bytes()
+        .map(|b| match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'*' | b'~' => {
+                (b as char).to_string()
+            }
+            b' ' => space.to_string(),
+            b => format!("%{:02X}", b),
+        })
+        .collect()
+}
+
+impl std::fmt::Debug for Secret {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(REDACTED)
+    }
+}
+
+impl std::fmt::Display for Secret {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(REDACTED)
+    }
+}
+
+/// Credentials looked up by name, so that tools refer to them instead of reading the environment
+/// themselves. Names without a source in the config are read from the environment variable of
+/// the same name.
+#[derive(Default)]
+pub struct Secrets {
+    credentials: BTreeMap<String, SecretSource>,
+    // the entries of the decrypted file
+    file: HashMap<String, Secret>,
+}
+
+impl Secrets {
+    /// Credentials that are all read from environment variables.
+    pub fn new() -> Arc<Self> {
+        Arc::new(Self::default())
+    }
+
+    /// Fails if the config has a secrets file that cannot be decrypted.
+    pub fn load(config: SecretsConfig) -> Result<Arc<Self>> {
+        let file = match &config.file {
+            Some(path) => {
+                let key_env = config.key_env.as_deref().unwrap_or(DEFAULT_KEY_ENV);
+                let passphrase = std::env::var(key_env).map_err(|_| {
+                    Error::AuthError(format!(
+                        "set {} to the passphrase of {}",
+                        key_env,
+                        path.display()
+                    ))
+                })?;
+                decrypt(path, &passphrase)?
+                    .into_iter()
+                    .map(|(name, value)| (name, Secret::new(&value)))
+                    .collect()
+            }
+            None => HashMap::new(),
+        };
+        Ok(Arc::new(Self {
+            credentials: config.credentials,
+            file,
+        }))
+    }
+
+    pub fn get(&self, name: &str) -> Result<Secret> {
+        let missing = |detail: String| {
+            Error::AuthError(format!("the credential {} is not set: {}", name, detail))
+        };
+        match self.credentials.get(name) {
+            None => std::env::var(name)
+                .map(|value| Secret::new(&value))
+                .map_err(|_| missing(format!("set the environment variable {}", name))),
+            Some(SecretSource::Env { var }) => std::env::var(var)
+                .map(|value| Secret::new(&value))
+                .map_err(|_| missing(format!("set the environment variable {}", var))),
+            Some(SecretSource::Keyring { service, account }) => keyring(service, account)
+                .map(|value| Secret::new(&value))
+                .map_err(missing),
+            Some(SecretSource::File { entry }) => {
+                let entry = entry.as_deref().unwrap_or(name);
+                self.file
+                    .get(entry)
+                    .cloned()
+                    .ok_or_else(|| missing(format!("the secrets file has no entry {}", entry)))
+            }
+        }
+    }
+}
+
+// reads a password from the keyring of the operating system with its command line tool
+fn keyring(service: &str, account: &str) -> std::result::Result<String, String> {
+    let mut command = if cfg!(target_os = "macos") {
+        let mut command = std::process::Command::new("security");
+        command.args(["find-generic-password", "-s", service, "-a", account, "-w"]);
+        command
+    } else if cfg!(unix) {
+        let mut command = std::process::Command::new("secret-tool");
+        command.args(["lookup", "service", service, "account", account]);
+        command
+    } else {
+        return Err("the keyring is only supported on linux and macos".to_string());
+    };
+    let output = command
+        .output()
+        .map_err(|e| format!("failed to run the keyring tool: {}", e))?;
+    let value = String::from_utf8_lossy(&output.stdout)
+        .trim_end_matches(['\r', '\n'])
+        .to_string();
+    if !output.status.success() || value.is_empty() {
+        return Err(format!(
+            "the keyring has no password for service {} and account {}",
+            service, account
+        ));
+    }
+    Ok(value)
+}
+
+fn key(passphrase: &str, salt: &[u8]) -> LessSafeKey {
+    let mut key = [0; KEY_LEN];
+    ring::pbkdf2::derive(
+        ring::pbkdf2::PBKDF2_HMAC_SHA256,
+        NonZeroU32::new(PBKDF2_ROUNDS).unwrap(),
+        salt,
+        passphrase.as_bytes(),
+        &mut key,
+    );
+    // the key has the length aes-256 needs
+    LessSafeKey::new(UnboundKey::new(&AES_256_GCM, &key).unwrap())
+}
+
+/// Reads the entries of an encrypted secrets file. Fails if the passphrase is wrong or the file
+/// was changed.
+pub fn decrypt(path: &Path, passphrase: &str) -> Result<BTreeMap<String, String>> {
+    let data = std::fs::read(path)?;
+    let invalid = || {
+        Error::AuthError(format!(
+            "the passphrase of {} is wrong or the file is damaged",
+            path.display()
+        ))
+    };
+    let rest = data
+        .strip_prefix(MAGIC)
+        .ok_or_else(|| Error::Unsupported(format!("{} is not a secrets file", path.display())))?;
+    if rest.len() < SALT_LEN + NONCE_LEN {
+        return Err(invalid());
+    }
+    let (salt, rest) = rest.split_at(SALT_LEN);
+    let (nonce, sealed) = rest.split_at(NONCE_LEN);
+    let nonce = Nonce::try_assume_unique_for_key(nonce).map_err(|_| invalid())?;
+    let mut sealed = sealed.to_vec();
+    let plain = key(passphrase, salt)
+        .open_in_place(nonce, Aad::from(MAGIC), &mut sealed)
+        .map_err(|_| invalid())?;
+    Ok(serde_json::from_slice(plain)?)
+}
+
+/// Writes the entries to an encrypted secrets file, replacing the file. The key is derived from
+/// the passphrase with a new salt every time.
+pub fn encrypt(path: &Path, passphrase: &str, entries: &BTreeMap<String, String>) -> Result<()> {
+    let random = SystemRandom::new();
+    let mut salt = [0; SALT_LEN];
+    let mut nonce = [0; NONCE_LEN];
+    random
+        .fill(&mut salt)
+        .and_then(|_| random.fill(&mut nonce))
+        .map_err(|_| Error::Unsupported("the system has no random number source".to_string()))?;
+    let mut sealed = serde_json::to_vec(entries)?;
+    key(passphrase, &salt)
+        .seal_in_place_append_tag(
+            Nonce::assume_unique_for_key(nonce),
+            Aad::from(MAGIC),
+            &mut sealed,
+        )
+        .map_err(|_| Error::Unsupported("the secrets are too large to encrypt".to_string()))?;
+
+    let mut data = MAGIC.to_vec();
+    data.extend_from_slice(&salt);
+    data.extend_from_slice(&nonce);
+    data.extend_from_slice(&sealed);
+    // written next to the file and renamed, so that a failed write keeps the old secrets
+    let partial = path.with_extension("partial");
+    std::fs::write(&partial, data)?;
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        std::fs::set_permissions(&partial, std::fs::Permissions::from_mode(0o600))?;
+    }
+    std::fs::rename(partial, path)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Secret, SecretSource, Secrets, SecretsConfig, decrypt, encrypt};
+    use std::collections::BTreeMap;
+
+    #[test]
+    fn test_secrets() {
+        let path = std::env::temp_dir().join(format!("secrets-{}.bin", std::process::id()));
+        let entries = BTreeMap::from([("github".to_string(), "ghp_123".to_string())]);
+        encrypt(&path, "correct horse", &entries).unwrap();
+        assert!(!String::from_utf8_lossy(&std::fs::read(&path).unwrap()).contains("ghp_123"));
+        assert_eq!(decrypt(&path, "correct horse").unwrap(), entries);
+        assert!(decrypt(&path, "wrong").is_err());
+
+        // SAFETY: no other test reads these variables
+        unsafe {
+            std::env::set_var("SECRETS_TEST_KEY", "correct horse");
+            std::env::set_var("SECRETS_TEST_FRED", "fred-key");
+        }
+        let secrets = Secrets::load(SecretsConfig {
+            credentials: BTreeMap::from([
+                (
+                    "fred".to_string(),
+                    SecretSource::Env {
+                        var: "SECRETS_TEST_FRED".to_string(),
+                    },
+                ),
+                ("github".to_string(), SecretSource::File { entry: None }),
+                (
+                    "shopify".to_string(),
+                    SecretSource::File {
+                        entry: Some("missing".to_string()),
+                    },
+                ),
+            ]),
+            file: Some(path.clone()),
+            key_env: Some("SECRETS_TEST_KEY".to_string()),
+        })
+        .unwrap();
+        assert_eq!(secrets.get("fred").unwrap().expose(), "fred-key");
+        assert_eq!(secrets.get("github").unwrap().expose(), "ghp_123");
+        assert!(secrets.get("shopify").is_err());
+        // names without a source are environment variables
+        assert_eq!(
+            secrets.get("SECRETS_TEST_FRED").unwrap().expose(),
+            "fred-key"
+        );
+        assert!(secrets.get("SECRETS_TEST_UNSET").is_err());
+
+        let secret = Secret::new("ghp_123");
+        assert_eq!(format!("{} {:?}", secret, secret), "[redacted] [redacted]");
+        assert_eq!(
+            secret.redact("GET /repos?token=ghp_123 failed"),
+            "GET /repos?token=[redacted] failed"
+        );
+        let secret = Secret::new("a b/c=");
+        assert_eq!(
+            secret.redact("GET /x?key=a+b%2Fc%3D and /a%20b%2Fc%3D/y"),
+            "GET /x?key=[redacted] and /[redacted]/y"
+        );
+        std::fs::remove_file(path).unwrap();
+    }
+}
@@ -0,0 +1,275 @@
+use crate::llm::Message;
+use crate::tools::web_fetch::html_to_text;
+use crate::tools::{
+    FunctionalTool, HttpClient, Tool, ToolCall, ToolContext, ToolDefinition, Trust, WebAccess,
+    tool_result,
+};
+use crate::{Error, Result};
+use async_trait::async_trait;
+use schemars::JsonSchema;
+use serde::Deserialize;
+use std::sync::Arc;
+use std::time::Duration;
+
+const AVAILABLE_URL: &str = "https://archive.org/wayback/available";
+const SNAPSHOT_URL: &str = "https://web.archive.org/web";
+const SAVE_URL: &str = "https://web.archive.org/save";
+
+#[derive(Deserialize)]
+struct Available {
+    archived_snapshots: Snapshots,
+}
+
+#[derive(Deserialize)]
+struct Snapshots {
+    closest: Option<Snapshot>,
+}
+
+#[derive(Deserialize)]
+struct Snapshot {
+    available: bool,
+    url: String,
+    timestamp: String,
+}
+
+// turns a date such as 2024, 2024-03, or 2024-03-15 into a wayback timestamp
+fn timestamp(date: &str) -> std::result::Result<String, String> {
+    let digits = date.trim().replace('-', "");
+    if !matches!(digits.len(), 4 | 6 | 8) || !digits.chars().all(|c| c.is_ascii_digit()) {
+        return Err(format!(
+            "{} is not a date in the form YYYY, YYYY-MM, or YYYY-MM-DD",
+            date
+        ));
+    }
+    Ok(digits)
+}
+
+// formats the date part of a wayback timestamp such as 20240315123456
+fn format_timestamp(timestamp: &str) -> String {
+    match (timestamp.get(..4), timestamp.get(4..6), timestamp.get(6..8)) {
+        (Some(year), Some(month), Some(day)) => format!("{}-{}-{}", year, month, day),
+        _ => timestamp.to_string(),
+    }
+}
+
+fn closest(available: Available) -> Option<Snapshot> {
+    available
+        .archived_snapshots
+        .closest
+        .filter(|snapshot| snapshot.available)
+}
+
+// reads and creates snapshots of pages on the Wayback Machine of archive.org, snapshots of
+// domains the web access policy does not allow are not accessed, and every snapshot read or saved
+// counts towards the request limit of the domain of the page
+#[derive(Clone)]
+pub struct ArchiveTool {
+    access: Arc<WebAccess>,
+    http: HttpClient,
+}
+
+impl ArchiveTool {
+    pub fn new(access: Arc<WebAccess>) -> Result<Box<Self>> {
+        Ok(Box::new(Self {
+            http: access.policy().http.client(Duration::from_secs(60))?,
+            access,
+        }))
+    }
+
+    pub fn tools(&self) -> Result<Vec<Box<dyn Tool + Send>>> {
+        Ok(vec![
+            Box::new(ArchiveLookupTool(self.clone())),
+            Box::new(ArchiveSaveTool(self.clone())),
+        ])
+    }
+
+    fn parse(&self, url: &str) -> std::result::Result<reqwest::Url, String> {
+        let parsed = match reqwest::Url::parse(url) {
+            Ok(parsed) if matches!(parsed.scheme(), "http" | "https") => parsed,
+            _ => return Err(format!("{} is not a valid http or https url", url)),
+        };
+        self.access.check(&parsed)?;
+        Ok(parsed)
+    }
+
+    async fn get(&self, url: reqwest::Url) -> Result<reqwest::Response> {
+        self.http
+            .send(self.http.get(url.clone()))
+            .await
+            .map_err(|e| Error::AgentWorkflowError(format!("failed to fetch {}: {}", url, e)))
+    }
+
+    async fn lookup(&self, url: &str, date: Option<&str>) -> Result<String> {
+        let url = match self.parse(url) {
+            Ok(url) => url,
+            Err(reason) => return Ok(reason),
+        };
+        let mut params = vec![("url", url.to_string())];
+        if let Some(date) = date {
+            match timestamp(date) {
+                Ok(timestamp) => params.push(("timestamp", timestamp)),
+                Err(reason) => return Ok(reason),
+            }
+        }
+
+        let available = reqwest::Url::parse_with_params(AVAILABLE_URL, &params)
+            .map_err(|e| Error::AgentWorkflowError(e.to_string()))?;
+        let available: Available = self.get(available).await?.json().await.map_err(|e| {
+            Error::AgentWorkflowError(format!("unexpected archive.org reply: {}", e))
+        })?;
+        let Some(snapshot) = closest(available) else {
+            return Ok(format!("archive.org has no snapshot of {}", url));
+        };
+
+        // the id_ suffix returns the page as it was archived, without the wayback toolbar
+        let raw = reqwest::Url::parse(&format!(
+            "{}/{}id_/{}",
+            SNAPSHOT_URL, snapshot.timestamp, url
+        ))
+        .map_err(|e| Error::AgentWorkflowError(e.to_string()))?;
+        let res = self.get(raw).await?;
+        let status = res.status();
+        let html = res
+            .text()
+            .await
+            .map_err(|e| Error::AgentWorkflowError(format!("failed to read {}: {}", url, e)))?;
+        if !status.is_success() {
+            return Ok(format!(
+                "Reading the snapshot {} failed with status {}",
+                snapshot.url, status
+            ));
+        }
+
+        let text = html_to_text(&html);
+        self.access.record(&snapshot.url, &text, Trust::Web);
+        Ok(format!(
+            "Snapshot of {} taken on {}, cite it as {}\n\n{}",
+            url,
+            format_timestamp(&snapshot.timestamp),
+            snapshot.url,
+            text
+        ))
+    }
+
+    async fn save(&self, url: &str) -> Result<String> {
+        let url = match self.parse(url) {
+            Ok(url) => url,
+            Err(reason) => return Ok(reason),
+        };
+        let save = reqwest::Url::parse(&format!("{}/{}", SAVE_URL, url))
+            .map_err(|e| Error::AgentWorkflowError(e.to_string()))?;
+        // every save creates a new snapshot, so it is never answered from the cache
+        let res = self
+            .http
+            .send(
+                self.http
+                    .get(save.clone())
+                    .header(reqwest::header::CACHE_CONTROL, "no-cache"),
+            )
+            .await
+            .map_err(|e| Error::AgentWorkflowError(format!("failed to fetch {}: {}", save, e)))?;
+        let status = res.status();
+        if !status.is_success() {
+            return Ok(format!(
+                "archive.org could not save {}, it replied with status {}",
+                url, status
+            ));
+        }
+
+        // the save endpoint redirects to the new snapshot, older deployments name it in a header
+        let snapshot = res
+            .headers()
+            .get(reqwest::header::CONTENT_LOCATION)
+            .and_then(|location| location.to_str().ok())
+            .map(|location| format!("https://web.archive.org{}", location))
+            .unwrap_or_else(|| res.url().to_string());
+        Ok(format!("Saved a snapshot of {} at {}", url, snapshot))
+    }
+}
+
+#[derive(Deserialize, JsonSchema)]
+struct ArchiveLookupArgs {
+    /// the http or https url of the page
+    url: String,
+    /// the date of the version to read as YYYY, YYYY-MM, or YYYY-MM-DD, the snapshot closest to it is returned; leave empty for the most recent snapshot
+    #[serde(default)]
+    date: Option<String>,
+}
+
+struct ArchiveLookupTool(ArchiveTool);
+
+#[async_trait]
+impl FunctionalTool for ArchiveLookupTool {
+    fn definition(&self) -> Result<ToolDefinition> {
+        ToolDefinition::new::<ArchiveLookupArgs>(
+            "archive_lookup",
+            "This tool returns the text of the Wayback Machine snapshot of a web page closest to a date. Use it to read how a page looked at a point in time, for instance prices or statements that have since changed, or to read pages that are no longer online.",
+        )
+    }
+
+    fn third_party_fn(&self) -> bool {
+        true
+    }
+
+    async fn invoke_fn(&mut self, call: &ToolCall, _: &ToolContext) -> Result<Message> {
+        let args: ArchiveLookupArgs = call.args()?;
+        Ok(tool_result(
+            call,
+            self.0.lookup(&args.url, args.date.as_deref()).await,
+        ))
+    }
+}
+
+#[derive(Deserialize, JsonSchema)]
+struct ArchiveSaveArgs {
+    /// the http or https url of the page to archive
+    url: String,
+}
+
+struct ArchiveSaveTool(ArchiveTool);
+
+#[async_trait]
+impl FunctionalTool for ArchiveSaveTool {
+    fn definition(&self) -> Result<ToolDefinition> {
+        ToolDefinition::new::<ArchiveSaveArgs>(
+            "archive_save",
+            "This tool asks the Wayback Machine to archive the current version of a web page and returns the url of the new snapshot. Use it for important sources that are likely to change, and cite the snapshot url next to the original.",
+        )
+    }
+
+    async fn invoke_fn(&mut self, call: &ToolCall, _: &ToolContext) -> Result<Message> {
+        let args: ArchiveSaveArgs = call.args()?;
+        Ok(tool_result(call, self.0.save(&args.url).await))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Available, closest, format_timestamp, timestamp};
+
+    #[test]
+    fn test_timestamp() {
+        assert_eq!(timestamp("2024-03-15"), Ok("20240315".to_string()));
+        assert_eq!(timestamp("2024-03"), Ok("202403".to_string()));
+        assert_eq!(timestamp(" 2024 "), Ok("2024".to_string()));
+        assert!(timestamp("March 2024").is_err());
+        assert!(timestamp("24-3-15").is_err());
+
+        assert_eq!(format_timestamp("20240315123456"), "2024-03-15");
+        assert_eq!(format_timestamp("2024"), "2024");
+    }
+
+    #[test]
+    fn test_closest() {
+        let available: Available = serde_json::from_str(
+            r#"{"url": "example.com", "archived_snapshots": {"closest": {"status": "200", "available": true, "url": "http://web.archive.org/web/20240315123456/https://example.com/", "timestamp": "20240315123456"}}}"#,
+        )
+        .unwrap();
+        let snapshot = closest(available).unwrap();
+        assert_eq!(snapshot.timestamp, "20240315123456");
+
+        let missing: Available =
+            serde_json::from_str(r#"{"url": "example.com", "archived_snapshots": {}}"#).unwrap();
+        assert!(closest(missing).is_none());
+    }
+}
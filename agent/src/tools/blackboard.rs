@@ -0,0 +1,311 @@
+use crate::llm::Message;
+use crate::tools::{FunctionalTool, Tool, ToolCall, ToolContext, ToolDefinition};
+use crate::{ContextProvider, History, Result};
+use async_trait::async_trait;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+
+// only the most recent notes of the other agents are shown before every step, at most as many
+// as fit in the characters, read_blackboard returns the rest
+const MAX_NOTIFICATIONS: usize = 20;
+const MAX_NOTIFICATION_CHARS: usize = 6000;
+// longer findings belong in the report of the agent rather than in a note
+const MAX_NOTE_CHARS: usize = 1000;
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Note {
+    // increases with every post, so agents can tell which notes they have not seen yet
+    pub seq: usize,
+    pub agent: String,
+    pub topic: String,
+    pub content: String,
+}
+
+// notes on what each agent is doing, has covered, or has found, shared by all agents of a run
+// so they can coordinate without the orchestrator relaying every detail
+pub struct Blackboard {
+    notes: Mutex<Vec<Note>>,
+    next_seq: AtomicUsize,
+}
+
+impl Blackboard {
+    pub fn new() -> Arc<Self> {
+        Arc::new(Self {
+            notes: Mutex::new(Vec::new()),
+            next_seq: AtomicUsize::new(1),
+        })
+    }
+
+    // a note on a topic the agent already posted about replaces its earlier note
+    pub fn post(&self, agent: &str, topic: &str, content: &str) -> Note {
+        // the sequence number is taken under the lock so notes are stored in the order of it
+        let mut notes = self.notes.lock().unwrap();
+        let note = Note {
+            seq: self.next_seq.fetch_add(1, Ordering::SeqCst),
+            agent: agent.to_string(),
+            topic: topic.trim().to_string(),
+            content: content.trim().to_string(),
+        };
+        notes.retain(|n| !(n.agent == note.agent && n.topic.eq_ignore_ascii_case(&note.topic)));
+        notes.push(note.clone());
+        note
+    }
+
+    // puts back the notes of a resumed run, later posts continue their sequence
+    pub fn restore(&self, notes: Vec<Note>) {
+        let next = notes.iter().map(|n| n.seq + 1).max().unwrap_or(1);
+        self.next_seq.fetch_max(next, Ordering::SeqCst);
+        *self.notes.lock().unwrap() = notes;
+    }
+
+    pub fn notes(&self) -> Vec<Note> {
+        self.notes.lock().unwrap().clone()
+    }
+
+    pub fn read(&self, topic: &Option<String>) -> Vec<Note> {
+        self.notes
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|n| {
+                topic.as_ref().is_none_or(|topic| {
+                    n.topic
+                        .to_lowercase()
+                        .contains(&topic.trim().to_lowercase())
+                })
+            })
+            .cloned()
+            .collect()
+    }
+
+    // the notes of all agents except the given one, oldest first
+    fn others(&self, agent: &str) -> Vec<Note> {
+        self.notes
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|n| n.agent != agent)
+            .cloned()
+            .collect()
+    }
+}
+
+fn format_notes(notes: &[Note]) -> String {
+    notes
+        .iter()
+        .map(|n| format!("- [{}] {}: {}\n", n.agent, n.topic, n.content))
+        .collect()
+}
+
+#[derive(Clone)]
+pub struct BlackboardTool {
+    board: Arc<Blackboard>,
+}
+
+impl BlackboardTool {
+    pub fn new(board: Arc<Blackboard>) -> Box<Self> {
+        Box::new(Self { board })
+    }
+
+    pub fn tools(&self) -> Result<Vec<Box<dyn Tool + Send>>> {
+        Ok(vec![
+            Box::new(PostTool(self.clone())),
+            Box::new(ReadTool(self.clone())),
+        ])
+    }
+}
+
+#[derive(Deserialize, JsonSchema)]
+struct PostArgs {
+    /// a short topic the note is about, e.g. "EU battery regulation" or "coverage"; a new note on one of your topics replaces your earlier note
+    topic: String,
+    /// a short note for the other agents, e.g. what you have covered, found, or are about to investigate
+    content: String,
+}
+
+struct PostTool(BlackboardTool);
+
+#[async_trait]
+impl FunctionalTool for PostTool {
+    fn definition(&self) -> Result<ToolDefinition> {
+        ToolDefinition::new::<PostArgs>(
+            "post_to_blackboard",
+            "This tool posts a short note to a blackboard shared by all agents of the research, and every other agent sees it before its next step. Post what you have already covered, key findings other agents can build on, and what you are about to investigate, so that the other agents do not duplicate your work.",
+        )
+    }
+
+    async fn invoke_fn(&mut self, call: &ToolCall, ctx: &ToolContext) -> Result<Message> {
+        let args: PostArgs = call.args()?;
+        let len = args.content.trim().chars().count();
+        let result = if args.topic.trim().is_empty() || args.content.trim().is_empty() {
+            "the note was not posted, it needs a topic and a content".to_string()
+        } else if len > MAX_NOTE_CHARS {
+            format!(
+                "the note was not posted, it has {} characters but notes may have at most {}. Post a shorter note and keep the details for your report",
+                len, MAX_NOTE_CHARS
+            )
+        } else {
+            let note = self.0.board.post(&ctx.agent, &args.topic, &args.content);
+            format!(
+                "posted note {} on {}, the other agents will see it",
+                note.seq, note.topic
+            )
+        };
+
+        Ok(Message::Tool {
+            id: call.id.clone(),
+            name: "post_to_blackboard".to_string(),
+            result: result.into(),
+        })
+    }
+}
+
+#[derive(Deserialize, JsonSchema)]
+struct ReadArgs {
+    /// only return notes whose topic contains this text; leave empty for all notes
+    #[serde(default)]
+    topic: Option<String>,
+}
+
+struct ReadTool(BlackboardTool);
+
+#[async_trait]
+impl FunctionalTool for ReadTool {
+    fn definition(&self) -> Result<ToolDefinition> {
+        ToolDefinition::new::<ReadArgs>(
+            "read_blackboard",
+            "This tool returns all notes on the blackboard shared by the agents of the research, optionally filtered by topic.",
+        )
+    }
+
+    async fn invoke_fn(&mut self, call: &ToolCall, _: &ToolContext) -> Result<Message> {
+        let args: ReadArgs = call.args()?;
+        let notes = self.0.board.read(&args.topic);
+        let result = if notes.is_empty() {
+            "no notes match".to_string()
+        } else {
+            format!("{} notes:\n{}", notes.len(), format_notes(&notes))
+        };
+
+        Ok(Message::Tool {
+            id: call.id.clone(),
+            name: "read_blackboard".to_string(),
+            result: result.into(),
+        })
+    }
+}
+
+// shows an agent the latest notes of the other agents before every step and marks those posted
+// since its previous step as new
+pub struct BlackboardNotifications {
+    board: Arc<Blackboard>,
+    agent: String,
+    seen: usize,
+}
+
+impl BlackboardNotifications {
+    pub fn new(board: Arc<Blackboard>, agent: &str) -> Box<Self> {
+        Box::new(Self {
+            board,
+            agent: agent.to_string(),
+            seen: 0,
+        })
+    }
+
+    fn render(&mut self) -> Option<String> {
+        let notes = self.board.others(&self.agent);
+        let mut chars = 0;
+        let shown = notes
+            .iter()
+            .rev()
+            .take(MAX_NOTIFICATIONS)
+            .take_while(|n| {
+                chars += format_notes(std::slice::from_ref(n)).chars().count();
+                chars <= MAX_NOTIFICATION_CHARS
+            })
+            .count();
+        let recent = &notes[notes.len() - shown..];
+        if recent.is_empty() {
+            return None;
+        }
+
+        let (new, old): (Vec<Note>, Vec<Note>) =
+            recent.iter().cloned().partition(|n| n.seq > self.seen);
+        self.seen = notes.iter().map(|n| n.seq).max().unwrap_or(self.seen);
+        let mut result = "<blackboard>\n".to_string();
+        if !new.is_empty() {
+            result.push_str(&format!(
+                "New notes of other agents since your last step:\n{}",
+                format_notes(&new)
+            ));
+        }
+        if !old.is_empty() {
+            result.push_str(&format!("Earlier notes:\n{}", format_notes(&old)));
+        }
+        result.push_str(
+            "Skip work other agents have already covered and build on their findings.\n</blackboard>",
+        );
+        Some(result)
+    }
+}
+
+#[async_trait]
+impl ContextProvider for BlackboardNotifications {
+    async fn context(&mut self, _: &dyn History) -> Result<Vec<Arc<Message>>> {
+        Ok(self
+            .render()
+            .map(|notes| Arc::new(Message::Developer(notes)))
+            .into_iter()
+            .collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Blackboard, BlackboardNotifications, MAX_NOTE_CHARS, MAX_NOTIFICATION_CHARS};
+
+    #[test]
+    fn test_notifications() {
+        let board = Blackboard::new();
+        let mut notifications = BlackboardNotifications::new(board.clone(), "subagent_1");
+        assert_eq!(notifications.render(), None);
+
+        board.post("subagent_1", "coverage", "covered solar");
+        assert_eq!(notifications.render(), None);
+
+        board.post("subagent_3", "coverage", "covered wind");
+        assert_eq!(
+            notifications.render().unwrap(),
+            "<blackboard>\nNew notes of other agents since your last step:\n- [subagent_3] coverage: covered wind\nSkip work other agents have already covered and build on their findings.\n</blackboard>"
+        );
+
+        // a second note on the same topic replaces the first one
+        board.post("subagent_3", "Coverage", "covered wind and hydro");
+        board.post("orchestrator", "plan", "focus on Europe");
+        let notes = notifications.render().unwrap();
+        assert!(notes.contains("New notes of other agents since your last step:\n- [subagent_3] Coverage: covered wind and hydro\n- [orchestrator] plan: focus on Europe\n"));
+        assert!(!notes.contains("Earlier notes"));
+        assert!(
+            notifications
+                .render()
+                .unwrap()
+                .starts_with("<blackboard>\nEarlier notes:\n")
+        );
+        assert_eq!(board.read(&Some("cover".to_string())).len(), 2);
+    }
+
+    #[test]
+    fn test_notifications_size() {
+        let board = Blackboard::new();
+        let mut notifications = BlackboardNotifications::new(board.clone(), "orchestrator");
+        let content = "x".repeat(MAX_NOTE_CHARS);
+        for i in 0..10 {
+            board.post("subagent_1", &format!("topic {}", i), &content);
+        }
+        let notes = notifications.render().unwrap();
+        assert!(notes.chars().count() < MAX_NOTIFICATION_CHARS + 200);
+        assert!(notes.contains("topic 9") && !notes.contains("topic 4"));
+    }
+}
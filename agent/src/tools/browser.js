@@ -0,0 +1,172 @@
+// drives a headless chromium with playwright for the browser tools: reads one json command per
+// line from stdin and writes one json reply per line to stdout, until stdin is closed. While a
+// command runs, every http request of the page is sent to stdout as a check and held until the
+// answer comes back on stdin
+const crypto = require("crypto");
+const fs = require("fs");
+const readline = require("readline");
+const { chromium } = require("playwright");
+
+let browser;
+let page;
+// the answers to the checks that are in flight by id
+const checks = new Map();
+let nextCheck = 0;
+// why the policy refused the last navigation of the command
+let blocked;
+
+// asks the agent whether the web access policy allows the request
+function check(url, navigation) {
+  const id = nextCheck++;
+  process.stdout.write(JSON.stringify({ id, check: url, navigation }) + "\n");
+  return new Promise((resolve) => checks.set(id, resolve));
+}
+
+async function guard(route) {
+  const request = route.request();
+  const navigation = request.isNavigationRequest();
+  const { allowed, reason } = await check(request.url(), navigation);
+  if (!allowed) {
+    if (navigation) {
+      blocked = reason;
+    }
+    return route.abort("blockedbyclient");
+  }
+  if (!navigation) {
+    return route.continue();
+  }
+  // redirects are not routed, so navigations are fetched one hop at a time and the browser
+  // follows each redirect with a request that is checked again
+  try {
+    const response = await route.fetch({ maxRedirects: 0 });
+    await route.fulfill({ response });
+  } catch (e) {
+    await route.abort("failed").catch(() => {});
+  }
+}
+
+async function snapshot() {
+  return {
+    ok: true,
+    url: page.url(),
+    title: await page.title(),
+    text: await page.evaluate(() => (document.body ? document.body.innerText : "")),
+  };
+}
+
+// waits for the scripts of the page to settle, pages that keep polling never become idle
+async function settle(ms) {
+  await page.waitForLoadState("networkidle", { timeout: ms }).catch(() => {});
+}
+
+// chromium takes the credentials of a proxy apart from its address
+function proxySettings(proxy) {
+  if (!proxy) {
+    return undefined;
+  }
+  const url = new URL(proxy);
+  return {
+    server: `${url.protocol}//${url.host}`,
+    username: url.username ? decodeURIComponent(url.username) : undefined,
+    password: url.password ? decodeURIComponent(url.password) : undefined,
+  };
+}
+
+// chromium cannot be given extra root certificates, it is told to accept the chains that contain
+// one of their public keys instead
+function certificateArgs(caCert) {
+  if (!caCert) {
+    return [];
+  }
+  const pems = fs.readFileSync(caCert, "utf8").match(/-----BEGIN CERTIFICATE-----[^-]+-----END CERTIFICATE-----/g) || [];
+  const hashes = pems.map((pem) => {
+    const spki = new crypto.X509Certificate(pem).publicKey.export({ type: "spki", format: "der" });
+    return crypto.createHash("sha256").update(spki).digest("base64");
+  });
+  return hashes.length ? [`--ignore-certificate-errors-spki-list=${hashes.join(",")}`] : [];
+}
+
+const commands = {
+  async open({ url, wait_ms, proxy, ca_cert, user_agent }) {
+    if (!browser) {
+      browser = await chromium.launch({
+        headless: true,
+        proxy: proxySettings(proxy),
+        args: certificateArgs(ca_cert),
+      });
+    }
+    if (!page) {
+      const context = await browser.newContext({
+        viewport: { width: 1280, height: 800 },
+        userAgent: user_agent || undefined,
+      });
+      await context.route((url) => url.protocol === "http:" || url.protocol === "https:", guard);
+      page = await context.newPage();
+    }
+    await page.goto(url, { waitUntil: "domcontentloaded", timeout: 30000 });
+    await settle(wait_ms);
+    return snapshot();
+  },
+  async click({ selector, text, wait_ms }) {
+    const target = selector ? page.locator(selector) : page.getByText(text);
+    await target.first().click({ timeout: 10000 });
+    await settle(wait_ms);
+    return snapshot();
+  },
+  async scroll({ pages, wait_ms }) {
+    await page.evaluate((pages) => window.scrollBy(0, pages * window.innerHeight), pages);
+    await settle(wait_ms);
+    return snapshot();
+  },
+  // writes the screenshot to the path, or returns it encoded as base64 without one
+  async screenshot({ path, full_page }) {
+    const image = await page.screenshot({ path, fullPage: full_page });
+    return {
+      ok: true,
+      url: page.url(),
+      title: await page.title(),
+      image: path ? undefined : image.toString("base64"),
+    };
+  },
+};
+
+async function run(command) {
+  const handler = commands[command.op];
+  if (!handler) {
+    return { ok: false, error: `unknown command ${command.op}` };
+  }
+  if (!page && command.op !== "open") {
+    return { ok: false, error: "no page is open, open one with browser_open first" };
+  }
+  blocked = undefined;
+  try {
+    return await handler(command);
+  } catch (e) {
+    return { ok: false, error: blocked || String(e.message || e).split("\n")[0] };
+  }
+}
+
+// the answers to checks are handled as they come, commands one after the other
+const lines = readline.createInterface({ input: process.stdin });
+let queue = Promise.resolve();
+lines.on("line", (line) => {
+  const message = JSON.parse(line);
+  if (message.op === undefined) {
+    const resolve = checks.get(message.id);
+    checks.delete(message.id);
+    if (resolve) {
+      resolve(message);
+    }
+    return;
+  }
+  queue = queue.then(async () => {
+    const reply = await run(message);
+    process.stdout.write(JSON.stringify(reply) + "\n");
+  });
+});
+lines.on("close", async () => {
+  await queue;
+  if (browser) {
+    await browser.close();
+  }
+});
@@ -0,0 +1,838 @@
+use crate::Result;
+use crate::llm::Message;
+use crate::tools::{FunctionalTool, Tool, ToolCall, ToolContext, ToolDefinition};
+use async_trait::async_trait;
+use schemars::JsonSchema;
+use serde::Deserialize;
+
+// a recursive descent parser for arithmetic expressions with the usual precedence, where ^ binds
+// tighter than unary minus and a postfix % divides by 100
+struct Parser<'a> {
+    chars: std::iter::Peekable<std::str::Chars<'a>>,
+}
+
+type Eval = std::result::Result<f64, String>;
+
+impl Parser<'_> {
+    fn skip_whitespace(&mut self) {
+        while self.chars.peek().is_some_and(|c| c.is_whitespace()) {
+            self.chars.next();
+        }
+    }
+
+    fn eat(&mut self, c: char) -> bool {
+        self.skip_whitespace();
+        if self.chars.peek() == Some(&c) {
+            self.chars.next();
+            true
+        } else {
+            false
+        }
+    }
+
+    fn expression(&mut self) -> Eval {
+        let mut value = self.term()?;
+        loop {
+            if self.eat('+') {
+                value += self.term()?;
+            } else if self.eat('-') {
+                value -= self.term()?;
+            } else {
+                return Ok(value);
+            }
+        }
+    }
+
+    fn term(&mut self) -> Eval {
+        let mut value = self.unary()?;
+        loop {
+            if self.eat('*') {
+                value *= self.unary()?;
+            } else if self.eat('/') {
+                let divisor = self.unary()?;
+                if divisor == 0.0 {
+                    return Err("division by zero".to_string());
+                }
+                value /= divisor;
+            } else {
+                return Ok(value);
+            }
+        }
+    }
+
+    fn unary(&mut self) -> Eval {
+        if self.eat('-') {
+            Ok(-self.unary()?)
+        } else if self.eat('+') {
+            self.unary()
+        } else {
+            self.power()
+        }
+    }
+
+    fn power(&mut self) -> Eval {
+        let base = self.postfix()?;
+        if self.eat('^') {
+            // right associative, 2^3^2 is 2^9
+            Ok(base.powf(self.unary()?))
+        } else {
+            Ok(base)
+        }
+    }
+
+    fn postfix(&mut self) -> Eval {
+        let mut value = self.atom()?;
+        while self.eat('%') {
+            value /= 100.0;
+        }
+        Ok(value)
+    }
+
+    fn atom(&mut self) -> Eval {
+        self.skip_whitespace();
+        match self.chars.peek().copied() {
+            Some('(') => {
+                self.chars.next();
+                let value = self.expression()?;
+                if !self.eat(')') {
+                    return Err("missing closing parenthesis".to_string());
+                }
+                Ok(value)
+            }
+            Some(c) if c.is_ascii_digit() || c == '.' => self.number(),
+            Some(c) if c.is_ascii_alphabetic() => self.function(),
+            Some(c) => Err(format!("unexpected character {}", c)),
+            None => Err("unexpected end of expression".to_string()),
+        }
+    }
+
+    fn number(&mut self) -> Eval {
+        let mut number = String::new();
+        while let Some(&c) = self.chars.peek() {
+            if c.is_ascii_digit() || c == '.' || c == '_' {
+                if c != '_' {
+                    number.push(c);
+                }
+                self.chars.next();
+            } else if (c == 'e' || c == 'E') && !number.contains('e') {
+                // an exponent such as 1.5e9, a lone e after a number is not allowed
+                number.push('e');
+                self.chars.next();
+                if let Some(&sign) = self.chars.peek()
+                    && (sign == '-' || sign == '+')
+                {
+                    number.push(sign);
+                    self.chars.next();
+                }
+            } else {
+                break;
+            }
+        }
+        number
+            .parse()
+            .map_err(|_| format!("{} is not a number", number))
+    }
+
+    fn function(&mut self) -> Eval {
+        let mut name = String::new();
+        while let Some(&c) = self.chars.peek() {
+            if c.is_ascii_alphanumeric() {
+                name.push(c);
+                self.chars.next();
+            } else {
+                break;
+            }
+        }
+        match name.to_ascii_lowercase().as_str() {
+            "pi" => return Ok(std::f64::consts::PI),
+            "e" => return Ok(std::f64::consts::E),
+            _ => {}
+        }
+
+        if !self.eat('(') {
+            return Err(format!("unknown constant {}", name));
+        }
+        let mut args = vec![self.expression()?];
+        while self.eat(',') {
+            args.push(self.expression()?);
+        }
+        if !self.eat(')') {
+            return Err(format!("missing closing parenthesis after {}", name));
+        }
+
+        let arity = |n: usize| {
+            if args.len() == n {
+                Ok(())
+            } else {
+                Err(format!("{} takes {} arguments", name, n))
+            }
+        };
+        match name.to_ascii_lowercase().as_str() {
+            "sqrt" => arity(1).map(|_| args[0].sqrt()),
+            "abs" => arity(1).map(|_| args[0].abs()),
+            "ln" => arity(1).map(|_| args[0].ln()),
+            "log10" | "log" if args.len() == 1 => Ok(args[0].log10()),
+            "log" => arity(2).map(|_| args[0].log(args[1])),
+            "exp" => arity(1).map(|_| args[0].exp()),
+            "floor" => arity(1).map(|_| args[0].floor()),
+            "ceil" => arity(1).map(|_| args[0].ceil()),
+            "round" if args.len() == 1 => Ok(args[0].round()),
+            "round" => arity(2).map(|_| {
+                let scale = 10f64.powi(args[1] as i32);
+                (args[0] * scale).round() / scale
+            }),
+            "pow" => arity(2).map(|_| args[0].powf(args[1])),
+            "min" => Ok(args.iter().copied().fold(f64::INFINITY, f64::min)),
+            "max" => Ok(args.iter().copied().fold(f64::NEG_INFINITY, f64::max)),
+            _ => Err(format!("unknown function {}", name)),
+        }
+    }
+}
+
+fn evaluate(expression: &str) -> Eval {
+    let mut parser = Parser {
+        chars: expression.chars().peekable(),
+    };
+    let value = parser.expression()?;
+    parser.skip_whitespace();
+    if let Some(c) = parser.chars.peek() {
+        return Err(format!("unexpected character {}", c));
+    }
+    if !value.is_finite() {
+        return Err("the result is not a finite number".to_string());
+    }
+    Ok(value)
+}
+
+// prints up to 10 significant decimals without trailing zeros
+fn format_number(value: f64) -> String {
+    if value != 0.0 && (value.abs() >= 1e15 || value.abs() < 1e-6) {
+        return format!("{:e}", value);
+    }
+    let formatted = format!("{:.10}", value);
+    let formatted = formatted.trim_end_matches('0').trim_end_matches('.');
+    if formatted == "-0" {
+        "0".to_string()
+    } else {
+        formatted.to_string()
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+enum Dimension {
+    Length,
+    Mass,
+    Time,
+    Area,
+    Volume,
+    Speed,
+    Energy,
+    Power,
+    Data,
+    Temperature,
+}
+
+// unit names, their dimension, and their size in the base unit of the dimension
+const UNITS: &[(&[&str], Dimension, f64)] = &[
+    (
+        &["m", "meter", "meters", "metre", "metres"],
+        Dimension::Length,
+        1.0,
+    ),
+    (
+        &["km", "kilometer", "kilometers", "kilometre", "kilometres"],
+        Dimension::Length,
+        1e3,
+    ),
+    (
+        &["cm", "centimeter", "centimeters"],
+        Dimension::Length,
+        1e-2,
+    ),
+    (
+        &["mm", "millimeter", "millimeters"],
+        Dimension::Length,
+        1e-3,
+    ),
+    (&["mi", "mile", "miles"], Dimension::Length, 1609.344),
+    (&["yd", "yard", "yards"], Dimension::Length, 0.9144),
+    (&["ft", "foot", "feet"], Dimension::Length, 0.3048),
+    (&["in", "inch", "inches"], Dimension::Length, 0.0254),
+    (
+        &["nmi", "nautical mile", "nautical miles"],
+        Dimension::Length,
+        1852.0,
+    ),
+    (&["kg", "kilogram", "kilograms"], Dimension::Mass, 1.0),
+    (&["g", "gram", "grams"], Dimension::Mass, 1e-3),
+    (&["mg", "milligram", "milligrams"], Dimension::Mass, 1e-6),
+    (
+        &["t", "tonne", "tonnes", "metric ton", "metric tons"],
+        Dimension::Mass,
+        1e3,
+    ),
+    (
+        &["lb", "lbs", "pound", "pounds"],
+        Dimension::Mass,
+        0.45359237,
+    ),
+    (&["oz", "ounce", "ounces"], Dimension::Mass, 0.028349523125),
+    (&["short ton", "short tons"], Dimension::Mass, 907.18474),
+    (&["s", "sec", "second", "seconds"], Dimension::Time, 1.0),
+    (&["min", "minute", "minutes"], Dimension::Time, 60.0),
+    (&["h", "hr", "hour", "hours"], Dimension::Time, 3600.0),
+    (&["d", "day", "days"], Dimension::Time, 86400.0),
+    (&["week", "weeks"], Dimension::Time, 604800.0),
+    (&["year", "years", "yr"], Dimension::Time, 31557600.0),
+    (
+        &["m2", "sq m", "square meter", "square meters"],
+        Dimension::Area,
+        1.0,
+    ),
+    (
+        &["km2", "sq km", "square kilometer", "square kilometers"],
+        Dimension::Area,
+        1e6,
+    ),
+    (&["ha", "hectare", "hectares"], Dimension::Area, 1e4),
+    (&["acre", "acres"], Dimension::Area, 4046.8564224),
+    (
+        &["ft2", "sq ft", "square foot", "square feet"],
+        Dimension::Area,
+        0.09290304,
+    ),
+    (
+        &["mi2", "sq mi", "square mile", "square miles"],
+        Dimension::Area,
+        2589988.110336,
+    ),
+    (
+        &["m3", "cubic meter", "cubic meters"],
+        Dimension::Volume,
+        1.0,
+    ),
+    (
+        &["l", "liter", "liters", "litre", "litres"],
+        Dimension::Volume,
+        1e-3,
+    ),
+    (
+        &["ml", "milliliter", "milliliters"],
+        Dimension::Volume,
+        1e-6,
+    ),
+    (
+        &["gal", "gallon", "gallons"],
+        Dimension::Volume,
+        0.003785411784,
+    ),
+    (
+        &["qt", "quart", "quarts"],
+        Dimension::Volume,
+        0.000946352946,
+    ),
+    (
+        &["fl oz", "fluid ounce", "fluid ounces"],
+        Dimension::Volume,
+        2.95735295625e-5,
+    ),
+    (
+        &["bbl", "barrel", "barrels"],
+        Dimension::Volume,
+        0.158987294928,
+    ),
+    (&["m/s", "meters per second"], Dimension::Speed, 1.0),
+    (
+        &["km/h", "kph", "kilometers per hour"],
+        Dimension::Speed,
+        1.0 / 3.6,
+    ),
+    (&["mph", "miles per hour"], Dimension::Speed, 0.44704),
+    (&["kn", "knot", "knots"], Dimension::Speed, 1852.0 / 3600.0),
+    (&["j", "joule", "joules"], Dimension::Energy, 1.0),
+    (&["kj", "kilojoule", "kilojoules"], Dimension::Energy, 1e3),
+    (&["mj", "megajoule", "megajoules"], Dimension::Energy, 1e6),
+    (&["wh"], Dimension::Energy, 3600.0),
+    (&["kwh"], Dimension::Energy, 3.6e6),
+    (&["mwh"], Dimension::Energy, 3.6e9),
+    (&["gwh"], Dimension::Energy, 3.6e12),
+    (&["twh"], Dimension::Energy, 3.6e15),
+    (&["cal", "calorie", "calories"], Dimension::Energy, 4.184),
+    (
+        &["kcal", "kilocalorie", "kilocalories"],
+        Dimension::Energy,
+        4184.0,
+    ),
+    (&["btu"], Dimension::Energy, 1055.05585262),
+    (&["w", "watt", "watts"], Dimension::Power, 1.0),
+    (&["kw", "kilowatt", "kilowatts"], Dimension::Power, 1e3),
+    (&["mw", "megawatt", "megawatts"], Dimension::Power, 1e6),
+    (&["gw", "gigawatt", "gigawatts"], Dimension::Power, 1e9),
+    (&["hp", "horsepower"], Dimension::Power, 745.699871582270),
+    (&["bit", "bits"], Dimension::Data, 0.125),
+    (&["b", "byte", "bytes"], Dimension::Data, 1.0),
+    (&["kb", "kilobyte", "kilobytes"], Dimension::Data, 1e3),
+    (&["mb", "megabyte", "megabytes"], Dimension::Data, 1e6),
+    (&["gb", "gigabyte", "gigabytes"], Dimension::Data, 1e9),
+    (&["tb", "terabyte", "terabytes"], Dimension::Data, 1e12),
+    (&["kib"], Dimension::Data, 1024.0),
+    (&["mib"], Dimension::Data, 1048576.0),
+    (&["gib"], Dimension::Data, 1073741824.0),
+    (&["tib"], Dimension::Data, 1099511627776.0),
+    (&["megabit", "megabits"], Dimension::Data, 1.25e5),
+    (&["gigabit", "gigabits"], Dimension::Data, 1.25e8),
+    (&["c", "celsius", "°c"], Dimension::Temperature, 0.0),
+    (&["f", "fahrenheit", "°f"], Dimension::Temperature, 0.0),
+    (&["k", "kelvin"], Dimension::Temperature, 0.0),
+];
+
+// symbols that only their case tells apart, such as mW and MW or Mb and MB, matched before the
+// names of UNITS, which ignore case
+const CASED_UNITS: &[(&str, Dimension, f64)] = &[
+    ("mW", Dimension::Power, 1e-3),
+    ("MW", Dimension::Power, 1e6),
+    ("mJ", Dimension::Energy, 1e-3),
+    ("MJ", Dimension::Energy, 1e6),
+    ("mWh", Dimension::Energy, 3.6),
+    ("MWh", Dimension::Energy, 3.6e9),
+    ("Mb", Dimension::Data, 1.25e5),
+    ("MB", Dimension::Data, 1e6),
+    ("Gb", Dimension::Data, 1.25e8),
+    ("GB", Dimension::Data, 1e9),
+    ("Tb", Dimension::Data, 1.25e11),
+    ("TB", Dimension::Data, 1e12),
+];
+
+fn unit(name: &str) -> std::result::Result<(&'static str, Dimension, f64), String> {
+    let name = name.trim();
+    if let Some((symbol, dimension, factor)) =
+        CASED_UNITS.iter().find(|(symbol, _, _)| *symbol == name)
+    {
+        return Ok((symbol, *dimension, *factor));
+    }
+    let lower = name.to_lowercase();
+    UNITS
+        .iter()
+        .find(|(names, _, _)| names.contains(&lower.as_str()))
+        .map(|(names, dimension, factor)| (names[0], *dimension, *factor))
+        .ok_or_else(|| format!("unknown unit {}", name))
+}
+
+fn to_kelvin(value: f64, unit: &str) -> f64 {
+    match unit {
+        "c" => value + 273.15,
+        "f" => (value - 32.0) * 5.0 / 9.0 + 273.15,
+        _ => value,
+    }
+}
+
+fn from_kelvin(value: f64, unit: &str) -> f64 {
+    match unit {
+        "c" => value - 273.15,
+        "f" => (value - 273.15) * 9.0 / 5.0 + 32.0,
+        _ => value,
+    }
+}
+
+fn convert(value: f64, from: &str, to: &str) -> Eval {
+    let (from_name, from_dimension, from_factor) = unit(from)?;
+    let (to_name, to_dimension, to_factor) = unit(to)?;
+    if from_dimension != to_dimension {
+        return Err(format!(
+            "{} and {} measure different quantities and cannot be converted",
+            from, to
+        ));
+    }
+    if from_dimension == Dimension::Temperature {
+        return Ok(from_kelvin(to_kelvin(value, from_name), to_name));
+    }
+    Ok(value * from_factor / to_factor)
+}
+
+// days since 1970-01-01 of a proleptic gregorian date, from Howard Hinnant's date algorithms
+fn days_from_civil(year: i64, month: u32, day: u32) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let year_of_era = year - era * 400;
+    let month = month as i64;
+    let day_of_year =
+        (153 * (if month > 2 { month - 3 } else { month + 9 }) + 2) / 5 + day as i64 - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    era * 146097 + day_of_era - 719468
+}
+
+fn civil_from_days(days: i64) -> (i64, u32, u32) {
+    let days = days + 719468;
+    let era = days.div_euclid(146097);
+    let day_of_era = days - era * 146097;
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let mp = (5 * day_of_year + 2) / 153;
+    let day = (day_of_year - (153 * mp + 2) / 5 + 1) as u32;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 } as u32;
+    let year = year_of_era + era * 400 + if month <= 2 { 1 } else { 0 };
+    (year, month, day)
+}
+
+// the date in utc as year, month, and day
+#[cfg(feature = "native")]
+pub(crate) fn today() -> (i64, u32, u32) {
+    let secs = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|elapsed| elapsed.as_secs())
+        .unwrap_or_default();
+    civil_from_days((secs / 86_400) as i64)
+}
+
+// the date in utc the number of days before today as YYYY-MM-DD
+#[cfg(feature = "native")]
+pub(crate) fn days_before_today(days: i64) -> String {
+    let (year, month, day) = today();
+    let (year, month, day) = civil_from_days(days_from_civil(year, month, day) - days);
+    format!("{:04}-{:02}-{:02}", year, month, day)
+}
+
+fn is_leap_year(year: i64) -> bool {
+    (year % 4 == 0 && year % 100 != 0) || year % 400 == 0
+}
+
+fn days_in_month(year: i64, month: u32) -> u32 {
+    match month {
+        2 if is_leap_year(year) => 29,
+        2 => 28,
+        4 | 6 | 9 | 11 => 30,
+        _ => 31,
+    }
+}
+
+// dates are limited to years whose day counts cannot overflow
+const MAX_YEAR: i64 = 1_000_000;
+
+fn out_of_range() -> String {
+    format!(
+        "the date is outside of the supported years -{} to {}",
+        MAX_YEAR, MAX_YEAR
+    )
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+struct Date {
+    year: i64,
+    month: u32,
+    day: u32,
+}
+
+impl Date {
+    fn parse(date: &str) -> std::result::Result<Self, String> {
+        let invalid = || format!("{} is not a date in the form YYYY-MM-DD", date);
+        let mut parts = date.trim().splitn(3, '-');
+        let mut next = || -> std::result::Result<i64, String> {
+            parts
+                .next()
+                .and_then(|part| part.parse().ok())
+                .ok_or_else(invalid)
+        };
+        let (year, month, day) = (next()?, next()?, next()?);
+        if year.abs() > MAX_YEAR {
+            return Err(out_of_range());
+        }
+        if !(1..=12).contains(&month) {
+            return Err(invalid());
+        }
+        let month = month as u32;
+        if day < 1 || day as u32 > days_in_month(year, month) {
+            return Err(invalid());
+        }
+        Ok(Self {
+            year,
+            month,
+            day: day as u32,
+        })
+    }
+
+    fn days(&self) -> i64 {
+        days_from_civil(self.year, self.month, self.day)
+    }
+
+    fn from_days(days: i64) -> std::result::Result<Self, String> {
+        if days.abs() > MAX_YEAR * 366 {
+            return Err(out_of_range());
+        }
+        let (year, month, day) = civil_from_days(days);
+        if year.abs() > MAX_YEAR {
+            return Err(out_of_range());
+        }
+        Ok(Self { year, month, day })
+    }
+
+    // the day is clamped to the end of shorter months, 2024-01-31 plus one month is 2024-02-29
+    fn add_months(&self, months: i64) -> std::result::Result<Self, String> {
+        let total = (self.year * 12 + self.month as i64 - 1)
+            .checked_add(months)
+            .ok_or_else(out_of_range)?;
+        let (year, month) = (total.div_euclid(12), total.rem_euclid(12) as u32 + 1);
+        if year.abs() > MAX_YEAR {
+            return Err(out_of_range());
+        }
+        Ok(Self {
+            year,
+            month,
+            day: self.day.min(days_in_month(year, month)),
+        })
+    }
+
+    fn weekday(&self) -> &'static str {
+        // 1970-01-01 was a thursday
+        [
+            "Thursday",
+            "Friday",
+            "Saturday",
+            "Sunday",
+            "Monday",
+            "Tuesday",
+            "Wednesday",
+        ][self.days().rem_euclid(7) as usize]
+    }
+}
+
+impl std::fmt::Display for Date {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{:04}-{:02}-{:02}", self.year, self.month, self.day)
+    }
+}
+
+fn date_math(args: &DateMathArgs) -> std::result::Result<String, String> {
+    let date = Date::parse(&args.date)?;
+    if let Some(until) = &args.until {
+        let until = Date::parse(until)?;
+        let days = until.days() - date.days();
+        return Ok(format!(
+            "{} days from {} ({}) to {} ({}), {} weeks and {} days",
+            days,
+            date,
+            date.weekday(),
+            until,
+            until.weekday(),
+            days / 7,
+            days % 7
+        ));
+    }
+
+    let months = args
+        .add_years
+        .unwrap_or_default()
+        .checked_mul(12)
+        .and_then(|months| months.checked_add(args.add_months.unwrap_or_default()))
+        .ok_or_else(out_of_range)?;
+    let shifted = date.add_months(months)?;
+    let days = shifted
+        .days()
+        .checked_add(args.add_days.unwrap_or_default())
+        .ok_or_else(out_of_range)?;
+    let shifted = Date::from_days(days)?;
+    Ok(format!("{} ({})", shifted, shifted.weekday()))
+}
+
+// deterministic arithmetic, unit conversions, and date calculations, so that numbers in reports
+// do not depend on the model doing the math
+#[derive(Clone)]
+pub struct CalcTool;
+
+impl CalcTool {
+    pub fn new() -> Box<Self> {
+        Box::new(Self)
+    }
+
+    pub fn tools(&self) -> Result<Vec<Box<dyn Tool + Send>>> {
+        Ok(vec![
+            Box::new(CalculateTool),
+            Box::new(ConvertUnitsTool),
+            Box::new(DateMathTool),
+        ])
+    }
+}
+
+fn tool_result(call: &ToolCall, result: std::result::Result<String, String>) -> Message {
+    Message::Tool {
+        id: call.id.clone(),
+        name: call.name.clone(),
+        result: result.unwrap_or_else(|e| format!("error: {}", e)).into(),
+    }
+}
+
+#[derive(Deserialize, JsonSchema)]
+struct CalculateArgs {
+    /// the expression, e.g. (1250000 - 980000) / 980000 * 100; supports + - * / ^, a postfix % that divides by 100, parentheses, pi, e, and the functions sqrt, abs, ln, log10, log(x, base), exp, floor, ceil, round(x, digits), pow, min, and max
+    expression: String,
+}
+
+struct CalculateTool;
+
+#[async_trait]
+impl FunctionalTool for CalculateTool {
+    fn definition(&self) -> Result<ToolDefinition> {
+        ToolDefinition::new::<CalculateArgs>(
+            "calculate",
+            "This tool evaluates an arithmetic expression exactly. Use it for every calculation in the report, such as growth rates, shares, sums, and averages, instead of calculating in your head.",
+        )
+    }
+
+    async fn invoke_fn(&mut self, call: &ToolCall, _: &ToolContext) -> Result<Message> {
+        let args: CalculateArgs = call.args()?;
+        Ok(tool_result(
+            call,
+            evaluate(&args.expression)
+                .map(|value| format!("{} = {}", args.expression.trim(), format_number(value))),
+        ))
+    }
+}
+
+#[derive(Deserialize, JsonSchema)]
+struct ConvertUnitsArgs {
+    /// the value to convert
+    value: f64,
+    /// the unit of the value, e.g. mi, kg, kWh, gal, acre, mph, GB, or F; the case of a symbol matters where it tells units apart, such as mW and MW or Mb (megabit) and MB
+    from: String,
+    /// the unit to convert to
+    to: String,
+}
+
+struct ConvertUnitsTool;
+
+#[async_trait]
+impl FunctionalTool for ConvertUnitsTool {
+    fn definition(&self) -> Result<ToolDefinition> {
+        ToolDefinition::new::<ConvertUnitsArgs>(
+            "convert_units",
+            "This tool converts a value between units of length, mass, time, area, volume, speed, energy, power, data size, or temperature. US units are used for gallons, quarts, and fluid ounces.",
+        )
+    }
+
+    async fn invoke_fn(&mut self, call: &ToolCall, _: &ToolContext) -> Result<Message> {
+        let args: ConvertUnitsArgs = call.args()?;
+        Ok(tool_result(
+            call,
+            convert(args.value, &args.from, &args.to).map(|value| {
+                format!(
+                    "{} {} = {} {}",
+                    format_number(args.value),
+                    args.from.trim(),
+                    format_number(value),
+                    args.to.trim()
+                )
+            }),
+        ))
+    }
+}
+
+#[derive(Deserialize, JsonSchema)]
+struct DateMathArgs {
+    /// the date as YYYY-MM-DD
+    date: String,
+    /// a second date as YYYY-MM-DD, to count the days from date until it
+    #[serde(default)]
+    until: Option<String>,
+    /// the number of years to add, negative to subtract
+    #[serde(default)]
+    add_years: Option<i64>,
+    /// the number of months to add, negative to subtract
+    #[serde(default)]
+    add_months: Option<i64>,
+    /// the number of days to add, negative to subtract
+    #[serde(default)]
+    add_days: Option<i64>,
+}
+
+struct DateMathTool;
+
+#[async_trait]
+impl FunctionalTool for DateMathTool {
+    fn definition(&self) -> Result<ToolDefinition> {
+        ToolDefinition::new::<DateMathArgs>(
+            "date_math",
+            "This tool counts the days between two dates, or adds years, months, and days to a date, and returns the weekdays. Use it for durations, ages, and deadlines instead of counting in your head.",
+        )
+    }
+
+    async fn invoke_fn(&mut self, call: &ToolCall, _: &ToolContext) -> Result<Message> {
+        let args: DateMathArgs = call.args()?;
+        Ok(tool_result(call, date_math(&args)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Date, DateMathArgs, convert, date_math, evaluate, format_number};
+
+    #[test]
+    fn test_evaluate() {
+        assert_eq!(evaluate("1 + 2 * 3"), Ok(7.0));
+        assert_eq!(evaluate("(1 + 2) * 3"), Ok(9.0));
+        assert_eq!(evaluate("-2^2"), Ok(-4.0));
+        assert_eq!(evaluate("2^3^2"), Ok(512.0));
+        assert_eq!(evaluate("15% * 200"), Ok(30.0));
+        assert_eq!(evaluate("1.5e3 + 1_000"), Ok(2500.0));
+        assert_eq!(evaluate("round(10 / 3, 2)"), Ok(3.33));
+        assert_eq!(evaluate("max(1, 5, 3) + log(8, 2)"), Ok(8.0));
+        assert!(evaluate("1 / 0").is_err());
+        assert!(evaluate("(1 + 2").is_err());
+        assert!(evaluate("1 + ").is_err());
+        assert!(evaluate("foo(1)").is_err());
+
+        assert_eq!(format_number(0.1 + 0.2), "0.3");
+        assert_eq!(format_number(27.55102040816326), "27.5510204082");
+        assert_eq!(format_number(2.5e20), "2.5e20");
+    }
+
+    #[test]
+    fn test_convert() {
+        let close = |a: f64, b: f64| (a - b).abs() < 1e-9 * b.abs().max(1.0);
+        assert!(close(convert(26.2, "miles", "km").unwrap(), 42.1648128));
+        assert!(close(convert(212.0, "F", "C").unwrap(), 100.0));
+        assert!(close(convert(1.0, "TWh", "GWh").unwrap(), 1000.0));
+        assert!(close(convert(1.0, "GiB", "MB").unwrap(), 1073.741824));
+        assert!(close(convert(1.0, "MW", "mW").unwrap(), 1e9));
+        assert!(close(convert(100.0, "Mb", "MB").unwrap(), 12.5));
+        assert!(close(convert(1.0, "mw", "kW").unwrap(), 1e3));
+        assert!(convert(1.0, "kg", "m").is_err());
+        assert!(convert(1.0, "parsec", "m").is_err());
+    }
+
+    #[test]
+    fn test_date_math() {
+        let args = |date: &str, until: Option<&str>, months: Option<i64>, days: Option<i64>| {
+            DateMathArgs {
+                date: date.to_string(),
+                until: until.map(String::from),
+                add_years: None,
+                add_months: months,
+                add_days: days,
+            }
+        };
+
+        assert_eq!(
+            date_math(&args("2024-03-01", Some("2024-05-31"), None, None)),
+            Ok(
+                "91 days from 2024-03-01 (Friday) to 2024-05-31 (Friday), 13 weeks and 0 days"
+                    .to_string()
+            )
+        );
+        assert_eq!(
+            date_math(&args("2024-01-31", None, Some(1), None)),
+            Ok("2024-02-29 (Thursday)".to_string())
+        );
+        assert_eq!(
+            date_math(&args("2000-01-01", None, None, Some(-1))),
+            Ok("1999-12-31 (Friday)".to_string())
+        );
+        assert!(date_math(&args("2023-02-29", None, None, None)).is_err());
+        assert!(date_math(&args("2024-01-01", None, None, Some(i64::MAX))).is_err());
+        assert!(date_math(&args("2024-01-01", None, Some(i64::MIN), None)).is_err());
+        assert!(date_math(&args("99999999999999-01-01", None, None, None)).is_err());
+
+        let date = Date::parse("1969-07-20").unwrap();
+        assert_eq!(Date::from_days(date.days()), Ok(date));
+        assert_eq!(date.weekday(), "Sunday");
+    }
+}
@@ -0,0 +1,376 @@
+// restrictions for the commands of the process backend of the sandbox, applied in the child
+// between fork and exec: resource limits, a landlock ruleset that confines the filesystem to the
+// working directory and read only system directories, and a seccomp filter in the style of pledge
+// that denies unix sockets, all sockets without network, and the syscalls for debugging other
+// processes, changing mounts and namespaces, and loading kernel code. Confining the filesystem
+// needs linux, the process backend is refused elsewhere
+use std::path::PathBuf;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub(crate) struct Limits {
+    pub memory_bytes: u64,
+    pub cpu_secs: u64,
+    // the size a file the command writes may grow to
+    pub file_bytes: u64,
+    pub network: bool,
+}
+
+// the directories commands may read and run programs from, besides the working directory, which
+// is the only one they may write to
+const SYSTEM_PATHS: &[&str] = &[
+    "/usr", "/bin", "/sbin", "/lib", "/lib32", "/lib64", "/libx32", "/etc", "/opt", "/proc",
+];
+// devices such as /dev/null and /dev/urandom are read and written
+const DEVICES: &str = "/dev";
+
+// the filesystem of a command once it is confined
+#[derive(Clone, Debug, PartialEq)]
+pub(crate) struct Paths {
+    pub work_dir: PathBuf,
+    // read only directories besides the system ones, e.g. a python installation in a home
+    pub read: Vec<PathBuf>,
+}
+
+impl Paths {
+    // whether a command started from this directory can be run once the filesystem is confined
+    pub fn readable(&self, dir: &std::path::Path) -> bool {
+        SYSTEM_PATHS
+            .iter()
+            .map(std::path::Path::new)
+            .chain(self.read.iter().map(|path| path.as_path()))
+            .chain([self.work_dir.as_path()])
+            .any(|root| dir.starts_with(root))
+    }
+}
+
+#[cfg(target_os = "linux")]
+mod landlock {
+    use super::{DEVICES, Paths, SYSTEM_PATHS};
+    use std::ffi::CString;
+    use std::os::unix::ffi::OsStrExt;
+
+    const CREATE_RULESET_VERSION: u32 = 1;
+    const RULE_PATH_BENEATH: libc::c_int = 1;
+
+    const EXECUTE: u64 = 1 << 0;
+    const WRITE_FILE: u64 = 1 << 1;
+    const READ_FILE: u64 = 1 << 2;
+    const READ_DIR: u64 = 1 << 3;
+    // removing and making directories, files, sockets, fifos, devices, and symlinks
+    const CHANGE_TREE: u64 = 0b1_1111_1111_0000;
+    // since abi 2, renaming and linking between directories
+    const REFER: u64 = 1 << 13;
+    // since abi 3
+    const TRUNCATE: u64 = 1 << 14;
+
+    const READ: u64 = EXECUTE | READ_FILE | READ_DIR;
+
+    #[repr(C)]
+    struct RulesetAttr {
+        handled_access_fs: u64,
+    }
+
+    #[repr(C, packed)]
+    struct PathBeneathAttr {
+        allowed_access: u64,
+        parent_fd: i32,
+    }
+
+    // the abi version of landlock, None where the kernel does not have it or it is disabled
+    pub(crate) fn abi() -> Option<i64> {
+        // SAFETY: asking for the version reads no memory
+        let abi = unsafe {
+            libc::syscall(
+                libc::SYS_landlock_create_ruleset,
+                std::ptr::null::<RulesetAttr>(),
+                0usize,
+                CREATE_RULESET_VERSION,
+            )
+        };
+        (abi >= 1).then_some(abi)
+    }
+
+    // the rights the ruleset handles, everything the abi can restrict on the filesystem but ioctls
+    pub(super) fn handled(abi: i64) -> u64 {
+        let mut handled = READ | WRITE_FILE | CHANGE_TREE;
+        if abi >= 2 {
+            handled |= REFER;
+        }
+        if abi >= 3 {
+            handled |= TRUNCATE;
+        }
+        handled
+    }
+
+    // the rules of the ruleset, built before the fork since the child may not allocate
+    pub(super) fn rules(paths: &Paths, handled: u64) -> Vec<(CString, u64)> {
+        let path = |path: &std::path::Path| CString::new(path.as_os_str().as_bytes()).ok();
+        SYSTEM_PATHS
+            .iter()
+            .map(|dir| (CString::new(*dir).ok(), READ))
+            .chain(paths.read.iter().map(|dir| (path(dir), READ)))
+            .chain([(
+                CString::new(DEVICES).ok(),
+                READ_FILE | READ_DIR | WRITE_FILE | TRUNCATE,
+            )])
+            .chain([(path(&paths.work_dir), handled)])
+            .filter_map(|(path, access)| Some((path?, access & handled)))
+            .collect()
+    }
+
+    // restricts the calling process to the rules, a directory that does not exist is skipped
+    //
+    // SAFETY: only async-signal-safe syscalls, it may run between fork and exec
+    pub(super) unsafe fn restrict(rules: &[(CString, u64)], handled: u64) -> std::io::Result<()> {
+        let attr = RulesetAttr {
+            handled_access_fs: handled,
+        };
+        let ruleset = unsafe {
+            libc::syscall(
+                libc::SYS_landlock_create_ruleset,
+                &attr as *const RulesetAttr,
+                std::mem::size_of::<RulesetAttr>(),
+                0u32,
+            )
+        };
+        if ruleset < 0 {
+            return Err(std::io::Error::last_os_error());
+        }
+        let ruleset = ruleset as libc::c_int;
+        for (path, access) in rules {
+            let parent = unsafe { libc::open(path.as_ptr(), libc::O_PATH | libc::O_CLOEXEC) };
+            if parent < 0 {
+                continue;
+            }
+            let beneath = PathBeneathAttr {
+                allowed_access: *access,
+                parent_fd: parent,
+            };
+            let added = unsafe {
+                libc::syscall(
+                    libc::SYS_landlock_add_rule,
+                    ruleset,
+                    RULE_PATH_BENEATH,
+                    &beneath as *const PathBeneathAttr,
+                    0u32,
+                )
+            };
+            unsafe { libc::close(parent) };
+            if added != 0 {
+                let e = std::io::Error::last_os_error();
+                unsafe { libc::close(ruleset) };
+                return Err(e);
+            }
+        }
+        let restricted = unsafe { libc::syscall(libc::SYS_landlock_restrict_self, ruleset, 0u32) };
+        let e = std::io::Error::last_os_error();
+        unsafe { libc::close(ruleset) };
+        if restricted != 0 {
+            return Err(e);
+        }
+        Ok(())
+    }
+}
+
+#[cfg(all(target_os = "linux", test))]
+pub(crate) use landlock::abi as landlock_abi;
+
+#[cfg(target_os = "linux")]
+mod seccomp {
+    use libc::{
+        BPF_ABS, BPF_JEQ, BPF_JGE, BPF_JMP, BPF_K, BPF_LD, BPF_RET, BPF_W, SECCOMP_RET_ALLOW,
+        SECCOMP_RET_DATA, SECCOMP_RET_ERRNO, SECCOMP_RET_KILL_PROCESS, sock_filter,
+    };
+
+    // offsets of the fields of struct seccomp_data
+    const NR: u32 = 0;
+    const ARCH: u32 = 4;
+    const ARG0: u32 = 16;
+
+    #[cfg(target_arch = "x86_64")]
+    const AUDIT_ARCH: Option<u32> = Some(0xc000_003e);
+    #[cfg(target_arch = "aarch64")]
+    const AUDIT_ARCH: Option<u32> = Some(0xc000_00b7);
+    #[cfg(not(any(target_arch = "x86_64", target_arch = "aarch64")))]
+    const AUDIT_ARCH: Option<u32> = None;
+
+    // syscalls of the x32 abi have this bit set and would get past the syscall numbers below
+    #[cfg(target_arch = "x86_64")]
+    const X32_SYSCALL_BIT: Option<u32> = Some(0x4000_0000);
+    #[cfg(not(target_arch = "x86_64"))]
+    const X32_SYSCALL_BIT: Option<u32> = None;
+
+    const DENIED: &[libc::c_long] = &[
+        libc::SYS_ptrace,
+        libc::SYS_process_vm_readv,
+        libc::SYS_process_vm_writev,
+        libc::SYS_mount,
+        libc::SYS_umount2,
+        libc::SYS_pivot_root,
+        libc::SYS_chroot,
+        libc::SYS_setns,
+        libc::SYS_unshare,
+        libc::SYS_reboot,
+        libc::SYS_kexec_load,
+        libc::SYS_init_module,
+        libc::SYS_finit_module,
+        libc::SYS_delete_module,
+        libc::SYS_bpf,
+        libc::SYS_perf_event_open,
+        libc::SYS_keyctl,
+        libc::SYS_add_key,
+        libc::SYS_request_key,
+        libc::SYS_swapon,
+        libc::SYS_swapoff,
+        // io_uring can open sockets without the socket syscall
+        libc::SYS_io_uring_setup,
+    ];
+
+    fn statement(code: u32, k: u32) -> sock_filter {
+        sock_filter {
+            code: code as u16,
+            jt: 0,
+            jf: 0,
+            k,
+        }
+    }
+
+    fn jump(code: u32, k: u32, jt: u8, jf: u8) -> sock_filter {
+        sock_filter {
+            code: code as u16,
+            jt,
+            jf,
+            k,
+        }
+    }
+
+    // the filter program, None on architectures whose syscall numbers it does not know
+    pub(super) fn program(network: bool) -> Option<Vec<sock_filter>> {
+        let deny = statement(
+            BPF_RET | BPF_K,
+            SECCOMP_RET_ERRNO | (libc::EPERM as u32 & SECCOMP_RET_DATA),
+        );
+        let allow = statement(BPF_RET | BPF_K, SECCOMP_RET_ALLOW);
+        let kill = statement(BPF_RET | BPF_K, SECCOMP_RET_KILL_PROCESS);
+        let mut program = vec![
+            statement(BPF_LD | BPF_W | BPF_ABS, ARCH),
+            jump(BPF_JMP | BPF_JEQ | BPF_K, AUDIT_ARCH?, 1, 0),
+            kill,
+            statement(BPF_LD | BPF_W | BPF_ABS, NR),
+        ];
+        if let Some(bit) = X32_SYSCALL_BIT {
+            program.push(jump(BPF_JMP | BPF_JGE | BPF_K, bit, 0, 1));
+            program.push(kill);
+        }
+        for &nr in DENIED {
+            program.push(jump(BPF_JMP | BPF_JEQ | BPF_K, nr as u32, 0, 1));
+            program.push(deny);
+        }
+        if network {
+            // unix sockets reach the services of the host, such as the docker daemon, the ssh
+            // agent, and the secret service on d-bus; the domain is the first argument
+            program.push(jump(
+                BPF_JMP | BPF_JEQ | BPF_K,
+                libc::SYS_socket as u32,
+                0,
+                4,
+            ));
+            program.push(statement(BPF_LD | BPF_W | BPF_ABS, ARG0));
+            program.push(jump(BPF_JMP | BPF_JEQ | BPF_K, libc::AF_UNIX as u32, 0, 1));
+            program.push(deny);
+            program.push(allow);
+        } else {
+            for nr in [libc::SYS_socket, libc::SYS_connect] {
+                program.push(jump(BPF_JMP | BPF_JEQ | BPF_K, nr as u32, 0, 1));
+                program.push(deny);
+            }
+        }
+        program.push(allow);
+        Some(program)
+    }
+}
+
+#[cfg(target_os = "linux")]
+fn set_limit(resource: i32, value: u64) -> std::io::Result<()> {
+    let limit = libc::rlimit {
+        rlim_cur: value as libc::rlim_t,
+        rlim_max: value as libc::rlim_t,
+    };
+    // SAFETY: setrlimit only reads the struct, which lives until it returns
+    if unsafe { libc::setrlimit(resource as _, &limit) } != 0 {
+        return Err(std::io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+// makes the command apply the limits and confine its filesystem when it starts, fails where
+// either cannot be enforced
+#[cfg(target_os = "linux")]
+pub(crate) fn confine(
+    command: &mut tokio::process::Command,
+    limits: Limits,
+    paths: &Paths,
+) -> crate::Result<()> {
+    let filter = seccomp::program(limits.network).ok_or_else(|| {
+        crate::Error::Unsupported(
+            "the process sandbox cannot filter syscalls on this architecture".to_string(),
+        )
+    })?;
+    let abi = landlock::abi().ok_or_else(|| {
+        crate::Error::Unsupported(
+            "the process sandbox needs landlock, linux 5.13 or later with landlock enabled, to confine the filesystem".to_string(),
+        )
+    })?;
+    let handled = landlock::handled(abi);
+    let rules = landlock::rules(paths, handled);
+
+    // SAFETY: the closure runs in the forked child before exec, where only async-signal-safe
+    // functions may be called; it calls setrlimit, prctl, open, close, and the landlock syscalls
+    // and does not allocate, the filter program and the rules are built beforehand
+    unsafe {
+        command.pre_exec(move || {
+            set_limit(libc::RLIMIT_AS as i32, limits.memory_bytes)?;
+            // the kernel stops the command with SIGXCPU once it used up its cpu time
+            set_limit(libc::RLIMIT_CPU as i32, limits.cpu_secs)?;
+            set_limit(libc::RLIMIT_CORE as i32, 0)?;
+            set_limit(libc::RLIMIT_FSIZE as i32, limits.file_bytes)?;
+            if libc::prctl(libc::PR_SET_NO_NEW_PRIVS, 1, 0, 0, 0) != 0 {
+                return Err(std::io::Error::last_os_error());
+            }
+            landlock::restrict(&rules, handled)?;
+            let program = libc::sock_fprog {
+                len: filter.len() as u16,
+                filter: filter.as_ptr() as *mut libc::sock_filter,
+            };
+            if libc::prctl(
+                libc::PR_SET_SECCOMP,
+                libc::SECCOMP_MODE_FILTER,
+                &program as *const libc::sock_fprog,
+            ) != 0
+            {
+                return Err(std::io::Error::last_os_error());
+            }
+            Ok(())
+        });
+    }
+    Ok(())
+}
+
+#[cfg(not(target_os = "linux"))]
+pub(crate) fn confine(_: &mut tokio::process::Command, _: Limits, _: &Paths) -> crate::Result<()> {
+    Err(crate::Error::Unsupported(
+        "the process sandbox can only confine the filesystem on linux".to_string(),
+    ))
+}
+
+// kills the process group the command was started in, with everything it spawned
+#[cfg(unix)]
+pub(crate) fn kill_group(pid: u32) {
+    // SAFETY: kill has no memory effects, a group that is gone already is not an error here
+    unsafe {
+        libc::kill(-(pid as i32), libc::SIGKILL);
+    }
+}
+
+#[cfg(not(unix))]
+pub(crate) fn kill_group(_: u32) {}
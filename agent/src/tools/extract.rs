@@ -0,0 +1,665 @@
+use crate::tools::web_fetch::matches_domain;
+
+// turns the html of a page of a particular site into clean text, more precisely than the generic
+// pass that keeps every visible word of the page, navigation and footers included
+pub trait Extractor: Send + Sync {
+    // the text of the page, or None if the page does not look as expected, e.g. after a redesign,
+    // in which case the generic pass is used
+    fn extract(&self, url: &reqwest::Url, html: &str) -> Option<String>;
+
+    // another url of the same page that is easier to extract, fetched instead of the original
+    fn fetch_url(&self, _url: &reqwest::Url) -> Option<reqwest::Url> {
+        None
+    }
+}
+
+// a domain with its subdomains, optionally followed by a path prefix, e.g. wikipedia.org/wiki/
+struct UrlPattern {
+    domain: String,
+    path: String,
+}
+
+impl UrlPattern {
+    fn parse(pattern: &str) -> Self {
+        let pattern = pattern
+            .trim_start_matches("https://")
+            .trim_start_matches("http://");
+        let (domain, path) = match pattern.find('/') {
+            Some(i) => (&pattern[..i], &pattern[i..]),
+            None => (pattern, "/"),
+        };
+        Self {
+            domain: domain.to_string(),
+            path: path.to_string(),
+        }
+    }
+
+    fn matches(&self, url: &reqwest::Url) -> bool {
+        url.host_str()
+            .is_some_and(|host| matches_domain(host, &self.domain))
+            && url.path().starts_with(&self.path)
+    }
+}
+
+// the extractors of the fetch tool by the url pattern they were registered for
+pub struct Extractors {
+    rules: Vec<(UrlPattern, Box<dyn Extractor>)>,
+}
+
+impl Default for Extractors {
+    fn default() -> Self {
+        Self::builtin()
+    }
+}
+
+impl Extractors {
+    pub fn empty() -> Self {
+        Self { rules: Vec::new() }
+    }
+
+    // extractors for Wikipedia articles, arXiv abstract pages, GitHub repositories and issues,
+    // SEC EDGAR filings, and Reddit threads
+    pub fn builtin() -> Self {
+        Self::empty()
+            .register("wikipedia.org/wiki/", Box::new(Wikipedia))
+            .register("arxiv.org/abs/", Box::new(Arxiv))
+            .register("github.com", Box::new(GitHub))
+            .register("sec.gov/Archives/", Box::new(Edgar))
+            .register("reddit.com/r/", Box::new(Reddit))
+    }
+
+    // a pattern registered later takes precedence over earlier ones matching the same url
+    pub fn register(mut self, pattern: &str, extractor: Box<dyn Extractor>) -> Self {
+        self.rules.push((UrlPattern::parse(pattern), extractor));
+        self
+    }
+
+    pub(crate) fn find(&self, url: &reqwest::Url) -> Option<&dyn Extractor> {
+        self.rules
+            .iter()
+            .rev()
+            .find(|(pattern, _)| pattern.matches(url))
+            .map(|(_, extractor)| extractor.as_ref())
+    }
+}
+
+// the start of the first opening tag from the offset on that contains the marker, and the tag name
+fn opening_tag(lower: &str, marker: &str, from: usize) -> Option<(usize, String)> {
+    let min = from;
+    let mut from = from;
+    loop {
+        let i = from + lower.get(from..)?.find(marker)?;
+        let start = lower[..=i].rfind('<')?;
+        // the marker must be inside the tag, not in the text after it
+        if start >= min && !lower[start..i].contains('>') {
+            let name = lower[start + 1..]
+                .split(|c: char| c.is_whitespace() || c == '>' || c == '/')
+                .next()
+                .unwrap_or_default()
+                .to_string();
+            if !name.is_empty() {
+                return Some((start, name));
+            }
+        }
+        from = i + marker.len();
+    }
+}
+
+// the start of the content of the element at the opening tag, the start of its matching closing
+// tag, and the end of the element, which is the end of the page if the element is not closed
+fn element_at(lower: &str, start: usize, name: &str) -> (usize, usize, usize) {
+    let content_start = lower[start..]
+        .find('>')
+        .map_or(lower.len(), |i| start + i + 1);
+    let (open, close) = (format!("<{}", name), format!("</{}", name));
+    let mut depth = 1;
+    let mut pos = content_start;
+    while let Some(i) = lower[pos..].find('<') {
+        let i = pos + i;
+        let rest = &lower[i..];
+        let boundary = |tag: &str| {
+            rest.starts_with(tag)
+                && rest[tag.len()..]
+                    .chars()
+                    .next()
+                    .is_some_and(|c| c.is_whitespace() || c == '>' || c == '/')
+        };
+        if boundary(&close) {
+            depth -= 1;
+            if depth == 0 {
+                let end = lower[i..].find('>').map_or(lower.len(), |j| i + j + 1);
+                return (content_start, i, end);
+            }
+        } else if boundary(&open) && !rest[..rest.find('>').unwrap_or(rest.len())].ends_with('/') {
+            depth += 1;
+        }
+        pos = i + 1;
+    }
+    (content_start, lower.len(), lower.len())
+}
+
+// the content of the first element whose opening tag contains the marker, markers are lowercase
+fn element<'a>(html: &'a str, lower: &str, marker: &str) -> Option<&'a str> {
+    let (start, name) = opening_tag(lower, marker, 0)?;
+    let (content, close, _) = element_at(lower, start, &name);
+    Some(&html[content..close])
+}
+
+// the contents of every element whose opening tag contains the marker, nested ones excluded
+fn elements<'a>(html: &'a str, lower: &str, marker: &str) -> Vec<&'a str> {
+    let mut res = Vec::new();
+    let mut from = 0;
+    while let Some((start, name)) = opening_tag(lower, marker, from) {
+        let (content, close, end) = element_at(lower, start, &name);
+        res.push(&html[content..close]);
+        from = end;
+    }
+    res
+}
+
+// elements without content or closing tag
+const VOID_ELEMENTS: &[&str] = &[
+    "area", "base", "br", "col", "embed", "hr", "img", "input", "link", "meta", "source", "track",
+    "wbr",
+];
+
+// removes every element whose opening tag contains one of the markers, in a single pass over the
+// page that keeps the next tag of every marker
+fn remove_elements(html: &str, markers: &[&str]) -> String {
+    let lower = html.to_ascii_lowercase();
+    let mut next = markers
+        .iter()
+        .map(|marker| opening_tag(&lower, marker, 0))
+        .collect::<Vec<_>>();
+    let mut res = String::with_capacity(html.len());
+    let mut pos = 0;
+    while let Some((start, name)) = next
+        .iter()
+        .flatten()
+        .min_by_key(|(start, _)| *start)
+        .cloned()
+    {
+        let tag_end = lower[start..]
+            .find('>')
+            .map_or(lower.len(), |j| start + j + 1);
+        // a void or self-closing element ends with its tag, the rest of the page is not inside it
+        let end = if VOID_ELEMENTS.contains(&name.as_str()) || lower[..tag_end].ends_with("/>") {
+            tag_end
+        } else {
+            element_at(&lower, start, &name).2
+        };
+        res.push_str(&html[pos..start]);
+        res.push(' ');
+        pos = end;
+        for (marker, tag) in markers.iter().zip(next.iter_mut()) {
+            if tag.as_ref().is_some_and(|(start, _)| *start < pos) {
+                *tag = opening_tag(&lower, marker, pos);
+            }
+        }
+    }
+    res.push_str(&html[pos..]);
+    res
+}
+
+// the name and value of every attribute of an opening tag, names are lowercased
+fn attributes(tag: &str) -> Vec<(String, String)> {
+    let mut res = Vec::new();
+    let tag = tag.trim_start_matches('<');
+    let mut rest = tag
+        .find(char::is_whitespace)
+        .map_or("", |i| &tag[i..])
+        .trim_end_matches(['>', '/']);
+    loop {
+        rest = rest.trim_start();
+        let end = rest
+            .find(|c: char| c.is_whitespace() || c == '=')
+            .unwrap_or(rest.len());
+        if end == 0 {
+            return res;
+        }
+        let name = rest[..end].to_ascii_lowercase();
+        rest = rest[end..].trim_start();
+        let Some(value) = rest.strip_prefix('=') else {
+            res.push((name, String::new()));
+            continue;
+        };
+        let value = value.trim_start();
+        let (value, after) = match value.chars().next() {
+            Some(quote @ ('"' | '\'')) => match value[1..].find(quote) {
+                Some(i) => (&value[1..=i], &value[i + 2..]),
+                None => (&value[1..], ""),
+            },
+            _ => {
+                let i = value.find(char::is_whitespace).unwrap_or(value.len());
+                (&value[..i], &value[i..])
+            }
+        };
+        res.push((name, decode_entities(value)));
+        rest = after;
+    }
+}
+
+// the content of the first meta tag whose name, property or itemprop is one of the names, which
+// are lowercase
+pub(crate) fn meta_content(html: &str, names: &[&str]) -> Option<String> {
+    let lower = html.to_ascii_lowercase();
+    let mut found: Option<(usize, String)> = None;
+    for (start, _) in lower.match_indices("<meta") {
+        let end = lower[start..].find('>').map_or(lower.len(), |i| start + i);
+        let attributes = attributes(&html[start..end]);
+        let named = attributes.iter().find_map(|(attribute, value)| {
+            ["name", "property", "itemprop"]
+                .contains(&attribute.as_str())
+                .then(|| {
+                    names
+                        .iter()
+                        .position(|name| value.eq_ignore_ascii_case(name))
+                })
+                .flatten()
+        });
+        let content = attributes
+            .into_iter()
+            .find(|(attribute, _)| attribute == "content")
+            .map(|(_, content)| content.trim().to_string())
+            .filter(|content| !content.is_empty());
+        // earlier names are preferred over later ones
+        if let (Some(rank), Some(content)) = (named, content)
+            && found.as_ref().is_none_or(|(best, _)| rank < *best)
+        {
+            found = Some((rank, content));
+        }
+    }
+    found.map(|(_, content)| content)
+}
+
+fn decode_entities(text: &str) -> String {
+    let mut res = String::with_capacity(text.len());
+    let mut rest = text;
+    while let Some(i) = rest.find('&') {
+        res.push_str(&rest[..i]);
+        rest = &rest[i..];
+        let end = rest.bytes().take(12).position(|b| b == b';');
+        let decoded = end.and_then(|end| {
+            let entity = &rest[1..end];
+            let c = match entity {
+                "nbsp" => Some(' '),
+                "amp" => Some('&'),
+                "lt" => Some('<'),
+                "gt" => Some('>'),
+                "quot" => Some('"'),
+                "apos" => Some('\''),
+                "ndash" => Some('–'),
+                "mdash" => Some('—'),
+                _ => match entity.strip_prefix("#x").or(entity.strip_prefix("#X")) {
+                    Some(hex) => u32::from_str_radix(hex, 16).ok().and_then(char::from_u32),
+                    None => entity
+                        .strip_prefix('#')
+                        .and_then(|dec| dec.parse().ok())
+                        .and_then(char::from_u32),
+                },
+            };
+            c.map(|c| (c, end))
+        });
+        match decoded {
+            Some((c, end)) => {
+                res.push(c);
+                rest = &rest[end + 1..];
+            }
+            None => {
+                res.push('&');
+                rest = &rest[1..];
+            }
+        }
+    }
+    res.push_str(rest);
+    res
+}
+
+const INLINE_TAGS: &[&str] = &[
+    "a", "abbr", "b", "cite", "code", "em", "i", "q", "small", "span", "strong", "sub", "sup",
+    "time", "u",
+];
+
+// like the generic pass, but keeps the paragraphs, headings, list items, and table rows of the
+// html on lines of their own
+fn text(html: &str) -> String {
+    let mut out = String::with_capacity(html.len() / 2);
+    let mut rest = html;
+    while let Some(start) = rest.find('<') {
+        out.push_str(&rest[..start]);
+        rest = &rest[start..];
+        let tag_end = rest.find('>').map_or(rest.len(), |i| i + 1);
+        let tag = rest[..tag_end].to_ascii_lowercase();
+        let closing = tag.starts_with("</");
+        let name = tag
+            .trim_start_matches("</")
+            .trim_start_matches('<')
+            .split(|c: char| c.is_whitespace() || c == '>' || c == '/')
+            .next()
+            .unwrap_or_default()
+            .to_string();
+        rest = &rest[tag_end..];
+
+        if !closing && matches!(name.as_str(), "script" | "style" | "noscript") {
+            let close = format!("</{}", name);
+            rest = match rest.to_ascii_lowercase().find(&close) {
+                Some(i) => &rest[i..],
+                None => "",
+            };
+            continue;
+        }
+        match name.as_str() {
+            "h1" | "h2" | "h3" | "h4" | "h5" | "h6" if !closing => {
+                let level = name[1..].parse().unwrap_or(1);
+                out.push_str(&format!("\n{} ", "#".repeat(level)));
+            }
+            "li" if !closing => out.push_str("\n- "),
+            "td" | "th" if !closing => out.push_str(" | "),
+            "br" | "p" | "div" | "tr" | "li" | "ul" | "ol" | "table" | "blockquote" | "pre"
+            | "section" | "article" | "dd" | "dt" | "h1" | "h2" | "h3" | "h4" | "h5" | "h6" => {
+                out.push('\n')
+            }
+            name if INLINE_TAGS.contains(&name) => {}
+            _ => out.push(' '),
+        }
+    }
+    out.push_str(rest);
+
+    decode_entities(&out)
+        .lines()
+        .map(|line| line.split_whitespace().collect::<Vec<_>>().join(" "))
+        .map(|line| line.trim_start_matches("| ").to_string())
+        .filter(|line| !line.is_empty() && line != "-" && !line.chars().all(|c| c == '#'))
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+struct Wikipedia;
+
+impl Extractor for Wikipedia {
+    fn extract(&self, _: &reqwest::Url, html: &str) -> Option<String> {
+        let lower = html.to_ascii_lowercase();
+        let content = element(html, &lower, "id=\"mw-content-text\"")?;
+        // footnote markers, edit links, and the navigation boxes at the end of articles
+        let content = remove_elements(
+            content,
+            &[
+                "class=\"reference\"",
+                "class=\"mw-editsection",
+                "class=\"navbox",
+                "id=\"toc\"",
+                "role=\"navigation\"",
+            ],
+        );
+        let title = element(html, &lower, "id=\"firstheading\"").map(text);
+        let body = text(&content);
+        Some(match title {
+            Some(title) if !title.is_empty() => format!("# {}\n{}", title, body),
+            _ => body,
+        })
+    }
+}
+
+struct Arxiv;
+
+impl Extractor for Arxiv {
+    fn extract(&self, _: &reqwest::Url, html: &str) -> Option<String> {
+        let lower = html.to_ascii_lowercase();
+        // the labels of the fields are glued to their values in the html
+        let field = |marker: &str| {
+            element(html, &lower, marker)
+                .map(|field| text(&remove_elements(field, &["class=\"descriptor\""])))
+                .map(|field| field.replace('\n', " "))
+                .filter(|field| !field.is_empty())
+        };
+        let abstract_ = field("class=\"abstract mathjax\"")?;
+        let mut lines = Vec::new();
+        for (label, marker) in [
+            ("Title", "class=\"title mathjax\""),
+            ("Authors", "class=\"authors\""),
+            ("Comments", "class=\"tablecell comments mathjax\""),
+            ("Subjects", "class=\"tablecell subjects\""),
+        ] {
+            if let Some(value) = field(marker) {
+                lines.push(format!("{}: {}", label, value));
+            }
+        }
+        if let Some(dateline) = field("class=\"dateline\"") {
+            lines.push(dateline);
+        }
+        lines.push(String::new());
+        lines.push(format!("Abstract: {}", abstract_));
+        Some(lines.join("\n"))
+    }
+}
+
+struct GitHub;
+
+impl Extractor for GitHub {
+    // the readme of repositories and the comments of issues and pull requests, which are all
+    // rendered markdown
+    fn extract(&self, _: &reqwest::Url, html: &str) -> Option<String> {
+        let lower = html.to_ascii_lowercase();
+        let bodies = elements(html, &lower, "markdown-body");
+        if bodies.is_empty() {
+            return None;
+        }
+        let mut parts = Vec::new();
+        if let Some(title) = element(html, &lower, "<title") {
+            parts.push(format!("# {}", text(title)));
+        }
+        if let Some(about) = element(html, &lower, "class=\"f4 my-3\"") {
+            parts.push(text(about));
+        }
+        parts.extend(bodies.into_iter().map(text));
+        Some(parts.join("\n\n"))
+    }
+}
+
+struct Edgar;
+
+impl Extractor for Edgar {
+    // filings are long documents of tables, the inline xbrl header and the hidden elements only
+    // carry metadata for machines
+    fn extract(&self, _: &reqwest::Url, html: &str) -> Option<String> {
+        let lower = html.to_ascii_lowercase();
+        let body = element(html, &lower, "<body").unwrap_or(html);
+        let body = remove_elements(body, &["<ix:header", "display:none", "display: none"]);
+        Some(text(&body))
+    }
+}
+
+struct Reddit;
+
+impl Extractor for Reddit {
+    // the old interface renders the post and its comments on the server, the new one with scripts
+    fn fetch_url(&self, url: &reqwest::Url) -> Option<reqwest::Url> {
+        if url.host_str() == Some("old.reddit.com") {
+            return None;
+        }
+        let mut old = url.clone();
+        old.set_host(Some("old.reddit.com")).ok()?;
+        Some(old)
+    }
+
+    fn extract(&self, _: &reqwest::Url, html: &str) -> Option<String> {
+        // the sidebar holds the description of the subreddit
+        let html = remove_elements(html, &["class=\"side\""]);
+        let lower = html.to_ascii_lowercase();
+        let posts = elements(&html, &lower, "class=\"md\"");
+        if posts.is_empty() {
+            return None;
+        }
+        let thread = posts
+            .into_iter()
+            .map(text)
+            .collect::<Vec<_>>()
+            .join("\n\n---\n\n");
+        Some(match element(&html, &lower, "class=\"title may-blank") {
+            Some(title) => format!("# {}\n\n{}", text(title), thread),
+            None => thread,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Extractor, Extractors, meta_content, remove_elements, text};
+
+    fn url(url: &str) -> reqwest::Url {
+        reqwest::Url::parse(url).unwrap()
+    }
+
+    #[test]
+    fn test_meta_content() {
+        let html = "<head><meta charset=utf-8><META property=\"article:published_time\" content='2021-05-04T10:00:00Z'><meta name=\"citation_publication_date\" content=\"2020/01/02\" /><meta name=description content=\"R&amp;D\"></head>";
+        assert_eq!(
+            meta_content(
+                html,
+                &["citation_publication_date", "article:published_time"]
+            )
+            .as_deref(),
+            Some("2020/01/02")
+        );
+        assert_eq!(meta_content(html, &["description"]).as_deref(), Some("R&D"));
+        assert_eq!(meta_content(html, &["citation_doi"]), None);
+    }
+
+    fn extract(url_: &str, html: &str) -> Option<String> {
+        Extractors::builtin()
+            .find(&url(url_))?
+            .extract(&url(url_), html)
+    }
+
+    #[test]
+    fn test_text() {
+        assert_eq!(
+            text(
+                "<h2>Sales<span class=\"x\">!</span></h2><p>EV sales grew by <b>20</b>&nbsp;&#37; in 2024.</p><script>var x = '<p>';</script><ul><li>Norway</li><li>China &amp; EU</li></ul><table><tr><th>Year</th><th>Sales</th></tr><tr><td>2024</td><td>17&#x2009;m</td></tr></table>"
+            ),
+            "## Sales!\nEV sales grew by 20 % in 2024.\n- Norway\n- China & EU\nYear | Sales\n2024 | 17 m"
+        );
+    }
+
+    #[test]
+    fn test_registry() {
+        struct Custom;
+        impl Extractor for Custom {
+            fn extract(&self, _: &reqwest::Url, _: &str) -> Option<String> {
+                Some("custom".to_string())
+            }
+        }
+
+        let extractors =
+            Extractors::builtin().register("en.wikipedia.org/wiki/Main", Box::new(Custom));
+        let find = |u: &str| {
+            extractors
+                .find(&url(u))
+                .and_then(|e| e.extract(&url(u), "<html></html>"))
+        };
+        assert_eq!(
+            find("https://en.wikipedia.org/wiki/Main_Page").as_deref(),
+            Some("custom")
+        );
+        // the wikipedia extractor does not find the article content
+        assert_eq!(find("https://de.wikipedia.org/wiki/Elektroauto"), None);
+        assert!(
+            extractors
+                .find(&url("https://en.wikipedia.org/w/index.php"))
+                .is_none()
+        );
+        assert!(
+            extractors
+                .find(&url("https://example.com/wiki/a"))
+                .is_none()
+        );
+    }
+
+    #[test]
+    fn test_wikipedia() {
+        let html = r##"<html><body><div id="mw-navigation">Main page Contents</div>
+<h1 id="firstHeading" class="firstHeading"><span>Electric car</span></h1>
+<div id="mw-content-text" class="mw-body-content"><div class="mw-parser-output">
+<p>An <b>electric car</b> is an automobile propelled by electric motors.<sup id="cite_ref-1" class="reference"><a href="#cite_note-1">[1]</a></sup></p>
+<div id="toc" class="toc"><div>Contents</div><ul><li>History</li></ul></div>
+<h2><span class="mw-headline">History</span><span class="mw-editsection">[edit]</span></h2>
+<p>Early electric cars appeared in the 1830s.</p>
+<div class="navbox"><div><div>Electric vehicles</div></div></div>
+</div></div><div id="footer">Privacy policy</div></body></html>"##;
+        assert_eq!(
+            extract("https://en.wikipedia.org/wiki/Electric_car", html).unwrap(),
+            "# Electric car\nAn electric car is an automobile propelled by electric motors.\n## History\nEarly electric cars appeared in the 1830s."
+        );
+    }
+
+    #[test]
+    fn test_arxiv() {
+        let html = r##"<div id="abs"><div class="dateline">[Submitted on 12 Jun 2017]</div>
+<h1 class="title mathjax"><span class="descriptor">Title:</span>Attention Is All You Need</h1>
+<div class="authors"><span class="descriptor">Authors:</span><a href="/a/1">Ashish Vaswani</a>, <a href="/a/2">Noam Shazeer</a></div>
+<blockquote class="abstract mathjax"><span class="descriptor">Abstract:</span>The dominant sequence
+transduction models are based on recurrent networks.</blockquote>
+<table><tr><td class="tablecell label">Subjects:</td><td class="tablecell subjects"><span class="primary-subject">Computation and Language (cs.CL)</span></td></tr></table></div>"##;
+        assert_eq!(
+            extract("https://arxiv.org/abs/1706.03762", html).unwrap(),
+            "Title: Attention Is All You Need\nAuthors: Ashish Vaswani, Noam Shazeer\nSubjects: Computation and Language (cs.CL)\n[Submitted on 12 Jun 2017]\n\nAbstract: The dominant sequence transduction models are based on recurrent networks."
+        );
+    }
+
+    #[test]
+    fn test_github() {
+        let html = r##"<html><head><title>tokio-rs/tokio: A runtime for Rust</title></head><body>
+<nav>Sign in</nav><p class="f4 my-3">A runtime for writing reliable asynchronous applications</p>
+<article class="markdown-body entry-content" itemprop="text"><h1>Tokio</h1><p>Tokio is an event-driven platform.</p></article>
+<footer>Terms</footer></body></html>"##;
+        assert_eq!(
+            extract("https://github.com/tokio-rs/tokio", html).unwrap(),
+            "# tokio-rs/tokio: A runtime for Rust\n\nA runtime for writing reliable asynchronous applications\n\n# Tokio\nTokio is an event-driven platform."
+        );
+    }
+
+    #[test]
+    fn test_edgar() {
+        let html = r##"<html><body><div style="display:none"><ix:header><ix:hidden>dei:EntityCentralIndexKey</ix:hidden></ix:header></div>
+<p>Total revenues</p><table><tr><td>2024</td><td>$97,690</td></tr></table></body></html>"##;
+        assert_eq!(
+            extract(
+                "https://www.sec.gov/Archives/edgar/data/1318605/tsla-10k.htm",
+                html
+            )
+            .unwrap(),
+            "Total revenues\n2024 | $97,690"
+        );
+
+        // a marker in an element without closing tag removes only that element
+        assert_eq!(
+            remove_elements(
+                r#"<p>a</p><img style="display:none" src="x.png"><p>b</p><br style="display:none"/><div style="display:none"><p>c</p></div><p>d</p>"#,
+                &["display:none"]
+            ),
+            "<p>a</p> <p>b</p>  <p>d</p>"
+        );
+    }
+
+    #[test]
+    fn test_reddit() {
+        let extractors = Extractors::builtin();
+        let thread = url("https://www.reddit.com/r/electricvehicles/comments/abc/range/?sort=top");
+        let extractor = extractors.find(&thread).unwrap();
+        let old = extractor.fetch_url(&thread).unwrap();
+        assert_eq!(
+            old.as_str(),
+            "https://old.reddit.com/r/electricvehicles/comments/abc/range/?sort=top"
+        );
+        assert!(extractor.fetch_url(&old).is_none());
+
+        let html = r##"<div class="side"><div class="md"><p>Welcome to r/electricvehicles</p></div></div>
+<div class="content"><p class="title"><a class="title may-blank " href="/r/x">Real world winter range?</a></p>
+<div class="usertext-body"><div class="md"><p>My range drops by 30% in winter.</p></div></div>
+<div class="comment"><div class="md"><p>Same here, preconditioning helps.</p></div></div></div>"##;
+        assert_eq!(
+            extractor.extract(&old, html).unwrap(),
+            "# Real world winter range?\n\nMy range drops by 30% in winter.\n\n---\n\nSame here, preconditioning helps."
+        );
+    }
+}
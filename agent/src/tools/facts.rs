@@ -0,0 +1,458 @@
+use crate::Result;
+use crate::llm::Message;
+use crate::tools::{FunctionalTool, Tool, ToolCall, ToolContext, ToolDefinition};
+use async_trait::async_trait;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+// tables with more rows are cut off, narrower queries return the rest
+const MAX_ROWS: usize = 200;
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub struct Fact {
+    /// the company, person, country, product, or other entity the fact is about, e.g. "Tesla"
+    pub entity: String,
+    /// the measured or described property, e.g. "revenue 2023" or "headquarters"
+    pub attribute: String,
+    /// the value including its unit, e.g. "96.8 billion USD"
+    pub value: String,
+    /// the url or artifact id of the source of the value
+    pub source_id: String,
+    /// the date or period the value refers to, e.g. "2023" or "2024-03-31"
+    #[serde(default)]
+    pub date: Option<String>,
+    /// the agent that recorded the fact, filled in automatically
+    #[serde(default)]
+    #[schemars(skip)]
+    pub agent: String,
+}
+
+impl Fact {
+    fn same_as(&self, other: &Fact) -> bool {
+        self.entity.eq_ignore_ascii_case(&other.entity)
+            && self.attribute.eq_ignore_ascii_case(&other.attribute)
+            && self.value == other.value
+            && self.source_id == other.source_id
+            && self.date == other.date
+    }
+}
+
+// structured facts recorded by all agents of a run, so data-heavy reports can be built from
+// tables instead of prose
+pub struct FactStore {
+    facts: Mutex<Vec<Fact>>,
+    // lowercase alias and the canonical name it resolves to, e.g. googl and Alphabet
+    aliases: Mutex<HashMap<String, String>>,
+}
+
+fn matches(value: &str, filter: &Option<String>) -> bool {
+    filter
+        .as_ref()
+        .is_none_or(|filter| value.to_lowercase().contains(&filter.trim().to_lowercase()))
+}
+
+fn escape(cell: &str) -> String {
+    cell.replace('|', "\\|").replace('\n', " ")
+}
+
+impl FactStore {
+    pub fn new() -> Arc<Self> {
+        Arc::new(Self {
+            facts: Mutex::new(Vec::new()),
+            aliases: Mutex::new(HashMap::new()),
+        })
+    }
+
+    // the canonical name of an entity, or the name itself if it has no alias
+    pub fn canonical(&self, entity: &str) -> String {
+        let entity = entity.trim();
+        self.aliases
+            .lock()
+            .unwrap()
+            .get(&entity.to_lowercase())
+            .cloned()
+            .unwrap_or_else(|| entity.to_string())
+    }
+
+    // the distinct entity names of all facts, in the order they were first recorded
+    pub fn entities(&self) -> Vec<String> {
+        let mut entities: Vec<String> = Vec::new();
+        for fact in self.facts.lock().unwrap().iter() {
+            if !entities
+                .iter()
+                .any(|e| e.eq_ignore_ascii_case(&fact.entity))
+            {
+                entities.push(fact.entity.clone());
+            }
+        }
+        entities
+    }
+
+    pub fn aliases(&self) -> Vec<(String, String)> {
+        let mut aliases = self
+            .aliases
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|(alias, canonical)| (alias.clone(), canonical.clone()))
+            .collect::<Vec<_>>();
+        aliases.sort();
+        aliases
+    }
+
+    // makes the aliases names of the canonical entity, including the facts already recorded
+    // under them, and returns the number of renamed facts
+    pub fn merge(&self, canonical: &str, aliases: &[String]) -> usize {
+        let canonical = canonical.trim();
+        let names = aliases
+            .iter()
+            .map(|alias| alias.trim().to_lowercase())
+            .filter(|alias| !alias.is_empty() && *alias != canonical.to_lowercase())
+            .collect::<Vec<_>>();
+        {
+            let mut known = self.aliases.lock().unwrap();
+            // the canonical name may itself have been an alias of another name before
+            known.remove(&canonical.to_lowercase());
+            for target in known.values_mut() {
+                if names.contains(&target.to_lowercase()) {
+                    *target = canonical.to_string();
+                }
+            }
+            for name in &names {
+                known.insert(name.clone(), canonical.to_string());
+            }
+        }
+
+        let mut facts = self.facts.lock().unwrap();
+        let mut renamed = 0;
+        for fact in facts.iter_mut() {
+            if names.contains(&fact.entity.to_lowercase()) {
+                fact.entity = canonical.to_string();
+                renamed += 1;
+            }
+        }
+        // facts that only differed in their entity name are now duplicates
+        let mut merged: Vec<Fact> = Vec::with_capacity(facts.len());
+        for fact in facts.drain(..) {
+            if !merged.iter().any(|f| f.same_as(&fact)) {
+                merged.push(fact);
+            }
+        }
+        *facts = merged;
+        renamed
+    }
+
+    pub fn facts(&self) -> Vec<Fact> {
+        self.facts.lock().unwrap().clone()
+    }
+
+    // a fact that was already recorded with the same value and source is not added again
+    pub fn add(&self, fact: Fact) -> bool {
+        let fact = Fact {
+            entity: self.canonical(&fact.entity),
+            ..fact
+        };
+        let mut facts = self.facts.lock().unwrap();
+        let duplicate = facts.iter().any(|f| f.same_as(&fact));
+        if !duplicate {
+            facts.push(fact);
+        }
+        !duplicate
+    }
+
+    pub fn query(&self, entity: &Option<String>, attribute: &Option<String>) -> Vec<Fact> {
+        let entity = entity.as_ref().map(|entity| self.canonical(entity));
+        self.facts
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|f| matches(&f.entity, &entity) && matches(&f.attribute, attribute))
+            .cloned()
+            .collect()
+    }
+}
+
+// the note below a table that was cut off
+fn cut_note(shown: usize, total: usize) -> String {
+    format!(
+        "\nOnly the first {} of {} rows are shown, query by entity or attribute for the others.\n",
+        shown, total
+    )
+}
+
+// one row per fact with its source and date
+pub fn list_table(facts: &[Fact]) -> String {
+    let mut table =
+        "| Entity | Attribute | Value | Date | Source |\n|---|---|---|---|---|\n".to_string();
+    for fact in facts.iter().take(MAX_ROWS) {
+        table.push_str(&format!(
+            "| {} | {} | {} | {} | {} |\n",
+            escape(&fact.entity),
+            escape(&fact.attribute),
+            escape(&fact.value),
+            escape(fact.date.as_deref().unwrap_or("")),
+            escape(&fact.source_id)
+        ));
+    }
+    if facts.len() > MAX_ROWS {
+        table.push_str(&cut_note(MAX_ROWS, facts.len()));
+    }
+    table
+}
+
+// one row per entity and one column per attribute, differing values of the same cell are all
+// shown with their sources so conflicts stay visible
+pub fn pivot_table(facts: &[Fact]) -> String {
+    let mut entities: Vec<&str> = Vec::new();
+    let mut attributes: Vec<&str> = Vec::new();
+    for fact in facts {
+        if !entities
+            .iter()
+            .any(|e| e.eq_ignore_ascii_case(&fact.entity))
+        {
+            entities.push(&fact.entity);
+        }
+        if !attributes
+            .iter()
+            .any(|a| a.eq_ignore_ascii_case(&fact.attribute))
+        {
+            attributes.push(&fact.attribute);
+        }
+    }
+
+    let mut table = format!(
+        "| Entity | {} |\n|---|{}\n",
+        attributes
+            .iter()
+            .map(|a| escape(a))
+            .collect::<Vec<_>>()
+            .join(" | "),
+        "---|".repeat(attributes.len())
+    );
+    for entity in entities.iter().take(MAX_ROWS) {
+        let cells = attributes
+            .iter()
+            .map(|attribute| {
+                let mut values: Vec<String> = Vec::new();
+                for fact in facts.iter().filter(|f| {
+                    f.entity.eq_ignore_ascii_case(entity)
+                        && f.attribute.eq_ignore_ascii_case(attribute)
+                }) {
+                    let value = match &fact.date {
+                        Some(date) => format!("{} ({}) [{}]", fact.value, date, fact.source_id),
+                        None => format!("{} [{}]", fact.value, fact.source_id),
+                    };
+                    if !values.contains(&value) {
+                        values.push(value);
+                    }
+                }
+                escape(&values.join("; "))
+            })
+            .collect::<Vec<_>>();
+        table.push_str(&format!("| {} | {} |\n", escape(entity), cells.join(" | ")));
+    }
+    if entities.len() > MAX_ROWS {
+        table.push_str(&cut_note(MAX_ROWS, entities.len()));
+    }
+    table
+}
+
+#[derive(Clone)]
+pub struct FactsTool {
+    store: Arc<FactStore>,
+}
+
+impl FactsTool {
+    pub fn new(store: Arc<FactStore>) -> Box<Self> {
+        Box::new(Self { store })
+    }
+
+    pub fn tools(&self) -> Result<Vec<Box<dyn Tool + Send>>> {
+        Ok(vec![
+            Box::new(RecordFactsTool(self.clone())),
+            Box::new(QueryFactsTool(self.clone())),
+        ])
+    }
+}
+
+#[derive(Deserialize, JsonSchema)]
+struct RecordFactsArgs {
+    /// the facts to record, one per entity, attribute, and source
+    facts: Vec<Fact>,
+}
+
+struct RecordFactsTool(FactsTool);
+
+#[async_trait]
+impl FunctionalTool for RecordFactsTool {
+    fn definition(&self) -> Result<ToolDefinition> {
+        ToolDefinition::new::<RecordFactsArgs>(
+            "record_facts",
+            "This tool records quantitative and factual data points, such as figures, dates, locations, and names, as entity, attribute, and value with their source, in a fact table shared by all agents of the research. Record every data point you find that the report may need to compare or tabulate, using consistent entity and attribute names.",
+        )
+    }
+
+    async fn invoke_fn(&mut self, call: &ToolCall, ctx: &ToolContext) -> Result<Message> {
+        let args: RecordFactsArgs = call.args()?;
+        let total = args.facts.len();
+        let added = args
+            .facts
+            .into_iter()
+            .filter(|fact| !fact.entity.trim().is_empty() && !fact.attribute.trim().is_empty())
+            .map(|fact| {
+                self.0.store.add(Fact {
+                    entity: fact.entity.trim().to_string(),
+                    attribute: fact.attribute.trim().to_string(),
+                    agent: ctx.agent.clone(),
+                    ..fact
+                })
+            })
+            .filter(|added| *added)
+            .count();
+
+        Ok(Message::Tool {
+            id: call.id.clone(),
+            name: "record_facts".to_string(),
+            result: format!(
+                "recorded {} of {} facts, the others were empty or already recorded",
+                added, total
+            )
+            .into(),
+        })
+    }
+}
+
+#[derive(Deserialize, JsonSchema, Default, Clone, Copy)]
+#[serde(rename_all = "lowercase")]
+enum Layout {
+    /// one row per fact with its date and source
+    #[default]
+    List,
+    /// one row per entity and one column per attribute
+    Pivot,
+}
+
+#[derive(Deserialize, JsonSchema)]
+struct QueryFactsArgs {
+    /// only return facts whose entity contains this text; leave empty for all entities
+    #[serde(default)]
+    entity: Option<String>,
+    /// only return facts whose attribute contains this text; leave empty for all attributes
+    #[serde(default)]
+    attribute: Option<String>,
+    /// list for one row per fact, pivot for a comparison table with one row per entity
+    #[serde(default)]
+    layout: Layout,
+}
+
+struct QueryFactsTool(FactsTool);
+
+#[async_trait]
+impl FunctionalTool for QueryFactsTool {
+    fn definition(&self) -> Result<ToolDefinition> {
+        ToolDefinition::new::<QueryFactsArgs>(
+            "query_facts",
+            "This tool returns the facts recorded with record_facts by any agent as a Markdown table, optionally filtered by entity and attribute. Use the pivot layout to compare entities side by side, and include the tables in the report where they support its statements.",
+        )
+    }
+
+    async fn invoke_fn(&mut self, call: &ToolCall, _: &ToolContext) -> Result<Message> {
+        let args: QueryFactsArgs = call.args()?;
+        let facts = self.0.store.query(&args.entity, &args.attribute);
+        let result = if facts.is_empty() {
+            "no facts match the query".to_string()
+        } else {
+            let table = match args.layout {
+                Layout::List => list_table(&facts),
+                Layout::Pivot => pivot_table(&facts),
+            };
+            format!("{} facts:\n{}", facts.len(), table)
+        };
+
+        Ok(Message::Tool {
+            id: call.id.clone(),
+            name: "query_facts".to_string(),
+            result: result.into(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Fact, FactStore, list_table, pivot_table};
+
+    fn fact(entity: &str, attribute: &str, value: &str, source_id: &str) -> Fact {
+        Fact {
+            entity: entity.to_string(),
+            attribute: attribute.to_string(),
+            value: value.to_string(),
+            source_id: source_id.to_string(),
+            date: None,
+            agent: String::new(),
+        }
+    }
+
+    #[test]
+    fn test_fact_tables() {
+        let store = FactStore::new();
+        assert!(store.add(fact("Tesla", "revenue", "96.8B USD", "a")));
+        assert!(!store.add(fact("tesla", "Revenue", "96.8B USD", "a")));
+        assert!(store.add(fact("Tesla", "revenue", "97B USD", "b")));
+        assert!(store.add(Fact {
+            date: Some("2023".to_string()),
+            ..fact("BYD", "revenue", "602B CNY", "c")
+        }));
+        assert!(store.add(fact("BYD", "headquarters", "Shenzhen", "d")));
+
+        let revenue = store.query(&None, &Some("REVENUE ".to_string()));
+        assert_eq!(revenue.len(), 3);
+        assert_eq!(store.query(&Some("byd".to_string()), &None).len(), 2);
+
+        assert_eq!(
+            list_table(&store.query(&Some("BYD".to_string()), &Some("rev".to_string()))),
+            "| Entity | Attribute | Value | Date | Source |\n|---|---|---|---|---|\n| BYD | revenue | 602B CNY | 2023 | c |\n"
+        );
+        assert_eq!(
+            pivot_table(&store.facts()),
+            "| Entity | revenue | headquarters |\n|---|---|---|\n| Tesla | 96.8B USD [a]; 97B USD [b] |  |\n| BYD | 602B CNY (2023) [c] | Shenzhen [d] |\n"
+        );
+
+        let many = (0..250)
+            .map(|i| fact(&format!("company {}", i), "revenue", "1B USD", "a"))
+            .collect::<Vec<_>>();
+        for table in [list_table(&many), pivot_table(&many)] {
+            assert_eq!(
+                table.lines().filter(|l| l.starts_with("| company")).count(),
+                200
+            );
+            assert!(table.ends_with("Only the first 200 of 250 rows are shown, query by entity or attribute for the others.\n"));
+        }
+    }
+
+    #[test]
+    fn test_merge_entities() {
+        let store = FactStore::new();
+        store.add(fact("Google", "revenue", "307B USD", "a"));
+        store.add(fact("Alphabet Inc.", "revenue", "307B USD", "a"));
+        store.add(fact("GOOGL", "employees", "182,502", "b"));
+        store.add(fact("Microsoft", "revenue", "212B USD", "c"));
+
+        assert_eq!(
+            store.merge("Alphabet", &["Google".to_string(), "GOOGL".to_string()]),
+            2
+        );
+        assert_eq!(store.merge("Alphabet Inc.", &["alphabet".to_string()]), 2);
+        assert_eq!(
+            store.entities(),
+            vec!["Alphabet Inc.".to_string(), "Microsoft".to_string()]
+        );
+        assert_eq!(store.facts().len(), 3);
+        assert_eq!(store.canonical("googl"), "Alphabet Inc.");
+        assert_eq!(store.query(&Some("Google".to_string()), &None).len(), 2);
+
+        store.add(fact("google", "headquarters", "Mountain View", "d"));
+        assert_eq!(store.facts()[3].entity, "Alphabet Inc.");
+    }
+}
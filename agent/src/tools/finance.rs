@@ -0,0 +1,646 @@
+use crate::llm::Message;
+use crate::secrets::Secrets;
+use crate::tools::web_fetch::html_to_text;
+use crate::tools::{
+    FunctionalTool, HttpClient, Tool, ToolCall, ToolContext, ToolDefinition, Trust, WebAccess,
+    tool_result,
+};
+use crate::{Error, Result};
+use async_trait::async_trait;
+use schemars::JsonSchema;
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::OnceCell;
+
+const QUOTE_URL: &str = "https://stooq.com/q/l/";
+const TICKERS_URL: &str = "https://www.sec.gov/files/company_tickers.json";
+const SUBMISSIONS_URL: &str = "https://data.sec.gov/submissions";
+const FACTS_URL: &str = "https://data.sec.gov/api/xbrl/companyfacts";
+const SEARCH_URL: &str = "https://efts.sec.gov/LATEST/search-index";
+const ARCHIVES_URL: &str = "https://www.sec.gov/Archives/edgar/data";
+const MAX_FILINGS: usize = 40;
+
+// the us-gaap concepts reported as key fundamentals, companies switch concepts over the years, so
+// the latest period of any of them is used
+const FUNDAMENTALS: &[(&str, &[&str])] = &[
+    (
+        "Revenue",
+        &[
+            "Revenues",
+            "RevenueFromContractWithCustomerExcludingAssessedTax",
+            "SalesRevenueNet",
+        ],
+    ),
+    ("Operating income", &["OperatingIncomeLoss"]),
+    ("Net income", &["NetIncomeLoss"]),
+    ("Diluted EPS", &["EarningsPerShareDiluted"]),
+    ("Total assets", &["Assets"]),
+    ("Total liabilities", &["Liabilities"]),
+    ("Stockholders' equity", &["StockholdersEquity"]),
+    ("Cash", &["CashAndCashEquivalentsAtCarryingValue"]),
+    (
+        "Operating cash flow",
+        &["NetCashProvidedByUsedInOperatingActivities"],
+    ),
+];
+
+#[derive(Debug, PartialEq)]
+struct Quote {
+    symbol: String,
+    date: String,
+    open: String,
+    high: String,
+    low: String,
+    close: String,
+    volume: String,
+}
+
+// parses the csv stooq returns for a single symbol, unknown symbols have N/D fields
+fn parse_quote(csv: &str) -> Option<Quote> {
+    let row = csv.lines().nth(1)?;
+    let fields = row.split(',').map(str::trim).collect::<Vec<_>>();
+    match fields.as_slice() {
+        [symbol, date, _, open, high, low, close, volume] if *close != "N/D" => Some(Quote {
+            symbol: symbol.to_string(),
+            date: date.to_string(),
+            open: open.to_string(),
+            high: high.to_string(),
+            low: low.to_string(),
+            close: close.to_string(),
+            volume: volume.to_string(),
+        }),
+        _ => None,
+    }
+}
+
+#[derive(Deserialize)]
+struct TickerEntry {
+    cik_str: u64,
+    ticker: String,
+    title: String,
+}
+
+#[derive(Deserialize)]
+struct CompanyFacts {
+    #[serde(rename = "entityName")]
+    entity_name: String,
+    facts: HashMap<String, HashMap<String, Concept>>,
+}
+
+#[derive(Deserialize)]
+struct Concept {
+    units: HashMap<String, Vec<Fact>>,
+}
+
+#[derive(Deserialize)]
+struct Fact {
+    end: String,
+    val: f64,
+    #[serde(default)]
+    fy: Option<i64>,
+    #[serde(default)]
+    fp: Option<String>,
+    form: String,
+}
+
+// the most recent value of each key concept from an annual report
+fn fundamentals(facts: &CompanyFacts) -> Vec<(&'static str, String)> {
+    let Some(gaap) = facts.facts.get("us-gaap") else {
+        return Vec::new();
+    };
+    FUNDAMENTALS
+        .iter()
+        .filter_map(|(label, concepts)| {
+            let (unit, fact) = concepts
+                .iter()
+                .filter_map(|concept| gaap.get(*concept))
+                .flat_map(|concept| &concept.units)
+                .flat_map(|(unit, facts)| facts.iter().map(move |fact| (unit, fact)))
+                .filter(|(_, fact)| {
+                    fact.form.starts_with("10-K") && fact.fp.as_deref() == Some("FY")
+                })
+                .max_by(|a, b| a.1.end.cmp(&b.1.end))?;
+            let value = if unit == "USD" {
+                format!("{:.0} USD", fact.val)
+            } else {
+                format!("{} {}", fact.val, unit)
+            };
+            let year = fact.fy.map(|fy| format!("FY{}, ", fy)).unwrap_or_default();
+            Some((
+                *label,
+                format!("{} ({}period ending {})", value, year, fact.end),
+            ))
+        })
+        .collect()
+}
+
+#[derive(Deserialize)]
+struct Submissions {
+    name: String,
+    filings: SubmissionFilings,
+}
+
+#[derive(Deserialize)]
+struct SubmissionFilings {
+    recent: RecentFilings,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct RecentFilings {
+    accession_number: Vec<String>,
+    filing_date: Vec<String>,
+    form: Vec<String>,
+    primary_document: Vec<String>,
+}
+
+#[derive(Debug, PartialEq)]
+struct Filing {
+    company: String,
+    form: String,
+    date: String,
+    url: String,
+}
+
+fn filing_url(cik: u64, accession: &str, document: &str) -> String {
+    format!(
+        "{}/{}/{}/{}",
+        ARCHIVES_URL,
+        cik,
+        accession.replace('-', ""),
+        document
+    )
+}
+
+fn recent_filings(
+    cik: u64,
+    submissions: &Submissions,
+    form: Option<&str>,
+    limit: usize,
+) -> Vec<Filing> {
+    let recent = &submissions.filings.recent;
+    (0..recent.form.len())
+        .filter(|&i| form.is_none_or(|form| recent.form[i].eq_ignore_ascii_case(form)))
+        .filter_map(|i| {
+            Some(Filing {
+                company: submissions.name.clone(),
+                form: recent.form[i].clone(),
+                date: recent.filing_date.get(i)?.clone(),
+                url: filing_url(
+                    cik,
+                    recent.accession_number.get(i)?,
+                    recent.primary_document.get(i)?,
+                ),
+            })
+        })
+        .take(limit)
+        .collect()
+}
+
+#[derive(Deserialize)]
+struct SearchResponse {
+    hits: SearchHits,
+}
+
+#[derive(Deserialize)]
+struct SearchHits {
+    hits: Vec<SearchHit>,
+}
+
+#[derive(Deserialize)]
+struct SearchHit {
+    // the accession number and the file name, e.g. 0000320193-23-000106:aapl-20230930.htm
+    #[serde(rename = "_id")]
+    id: String,
+    #[serde(rename = "_source")]
+    source: SearchSource,
+}
+
+#[derive(Deserialize)]
+struct SearchSource {
+    #[serde(default)]
+    ciks: Vec<String>,
+    #[serde(default)]
+    display_names: Vec<String>,
+    form: String,
+    file_date: String,
+}
+
+fn search_filings(res: SearchResponse, limit: usize) -> Vec<Filing> {
+    res.hits
+        .hits
+        .into_iter()
+        .filter_map(|hit| {
+            let (accession, document) = hit.id.split_once(':')?;
+            let cik = hit.source.ciks.first()?.parse().ok()?;
+            Some(Filing {
+                company: hit
+                    .source
+                    .display_names
+                    .first()
+                    .cloned()
+                    .unwrap_or_default(),
+                form: hit.source.form,
+                date: hit.source.file_date,
+                url: filing_url(cik, accession, document),
+            })
+        })
+        .take(limit)
+        .collect()
+}
+
+fn format_filings(filings: &[Filing]) -> String {
+    filings
+        .iter()
+        .map(|f| format!("- {} {} filed {}\n  {}", f.company, f.form, f.date, f.url))
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+// market data from stooq and company filings and reported financials from SEC EDGAR. EDGAR
+// asks clients to identify themselves with a name and an email address, the user agent is the
+// SEC_USER_AGENT credential
+#[derive(Clone)]
+pub struct FinanceTool {
+    access: Arc<WebAccess>,
+    http: HttpClient,
+    user_agent: String,
+    tickers: Arc<OnceCell<HashMap<String, (u64, String)>>>,
+}
+
+impl FinanceTool {
+    pub fn new(access: Arc<WebAccess>, secrets: &Secrets) -> Result<Box<Self>> {
+        Ok(Box::new(Self {
+            user_agent: secrets.get("SEC_USER_AGENT")?.expose().to_string(),
+            http: access.policy().http.client(Duration::from_secs(30))?,
+            access,
+            tickers: Arc::new(OnceCell::new()),
+        }))
+    }
+
+    pub fn tools(&self) -> Result<Vec<Box<dyn Tool + Send>>> {
+        Ok(vec![
+            Box::new(StockQuoteTool(self.clone())),
+            Box::new(FundamentalsTool(self.clone())),
+            Box::new(SecFilingsTool(self.clone())),
+            Box::new(SecFilingTextTool(self.clone())),
+        ])
+    }
+
+    async fn get(&self, url: reqwest::Url) -> Result<reqwest::Response> {
+        let mut request = self
+            .http
+            .get(url.clone())
+            .header(reqwest::header::USER_AGENT, &self.user_agent);
+        // quotes change during the trading day, filings and reported financials do not
+        if url.as_str().starts_with(QUOTE_URL) {
+            request = request.header(reqwest::header::CACHE_CONTROL, "no-cache");
+        }
+        self.http.send_ok(request, &url).await
+    }
+
+    async fn get_json<T: serde::de::DeserializeOwned>(&self, url: &str) -> Result<T> {
+        let url = reqwest::Url::parse(url).map_err(|e| Error::AgentWorkflowError(e.to_string()))?;
+        self.get(url.clone())
+            .await?
+            .json()
+            .await
+            .map_err(|e| Error::AgentWorkflowError(format!("unexpected reply from {}: {}", url, e)))
+    }
+
+    // the cik and name of a ticker, the ticker list of EDGAR is downloaded once
+    async fn company(&self, ticker: &str) -> Result<Option<(u64, String)>> {
+        let tickers = self
+            .tickers
+            .get_or_try_init(|| async {
+                let entries: HashMap<String, TickerEntry> = self.get_json(TICKERS_URL).await?;
+                Ok::<_, Error>(
+                    entries
+                        .into_values()
+                        .map(|e| (e.ticker.to_ascii_uppercase(), (e.cik_str, e.title)))
+                        .collect(),
+                )
+            })
+            .await?;
+        Ok(tickers.get(&ticker.trim().to_ascii_uppercase()).cloned())
+    }
+
+    async fn quote(&self, ticker: &str) -> Result<String> {
+        let symbol = if ticker.contains('.') {
+            ticker.to_ascii_lowercase()
+        } else {
+            format!("{}.us", ticker.to_ascii_lowercase())
+        };
+        let url = reqwest::Url::parse_with_params(
+            QUOTE_URL,
+            &[
+                ("s", symbol.as_str()),
+                ("f", "sd2t2ohlcv"),
+                ("h", ""),
+                ("e", "csv"),
+            ],
+        )
+        .map_err(|e| Error::AgentWorkflowError(e.to_string()))?;
+        let csv = self
+            .get(url)
+            .await?
+            .text()
+            .await
+            .map_err(|e| Error::AgentWorkflowError(e.to_string()))?;
+        Ok(match parse_quote(&csv) {
+            Some(q) => format!(
+                "{} on {}: close {}, open {}, high {}, low {}, volume {} (source: stooq.com, prices may be delayed)",
+                q.symbol, q.date, q.close, q.open, q.high, q.low, q.volume
+            ),
+            None => format!("no quote was found for {}", ticker),
+        })
+    }
+
+    async fn fundamentals(&self, ticker: &str) -> Result<String> {
+        let Some((cik, _)) = self.company(ticker).await? else {
+            return Ok(format!(
+                "{} is not a ticker of a company filing with the SEC",
+                ticker
+            ));
+        };
+        let facts: CompanyFacts = self
+            .get_json(&format!("{}/CIK{:010}.json", FACTS_URL, cik))
+            .await?;
+        let fundamentals = fundamentals(&facts);
+        if fundamentals.is_empty() {
+            return Ok(format!(
+                "{} reports no us-gaap financial data",
+                facts.entity_name
+            ));
+        }
+        Ok(format!(
+            "Key fundamentals of {} from its latest annual reports (source: SEC EDGAR):\n{}",
+            facts.entity_name,
+            fundamentals
+                .iter()
+                .map(|(label, value)| format!("- {}: {}", label, value))
+                .collect::<Vec<_>>()
+                .join("\n")
+        ))
+    }
+
+    async fn filings(&self, args: &SecFilingsArgs) -> Result<String> {
+        let limit = args.limit.unwrap_or(10).clamp(1, MAX_FILINGS);
+        let filings = match (&args.ticker, &args.query) {
+            (_, Some(query)) => {
+                let mut params = vec![("q", format!("\"{}\"", query))];
+                if let Some(form) = &args.form {
+                    params.push(("forms", form.clone()));
+                }
+                if let Some(ticker) = &args.ticker
+                    && let Some((cik, _)) = self.company(ticker).await?
+                {
+                    params.push(("ciks", format!("{:010}", cik)));
+                }
+                let url = reqwest::Url::parse_with_params(SEARCH_URL, &params)
+                    .map_err(|e| Error::AgentWorkflowError(e.to_string()))?;
+                search_filings(self.get_json(url.as_str()).await?, limit)
+            }
+            (Some(ticker), None) => {
+                let Some((cik, _)) = self.company(ticker).await? else {
+                    return Ok(format!(
+                        "{} is not a ticker of a company filing with the SEC",
+                        ticker
+                    ));
+                };
+                let submissions: Submissions = self
+                    .get_json(&format!("{}/CIK{:010}.json", SUBMISSIONS_URL, cik))
+                    .await?;
+                recent_filings(cik, &submissions, args.form.as_deref(), limit)
+            }
+            (None, None) => return Ok("either a ticker or a query is required".to_string()),
+        };
+        if filings.is_empty() {
+            return Ok("no filings were found".to_string());
+        }
+        Ok(format_filings(&filings))
+    }
+
+    async fn filing_text(&self, url: &str) -> Result<String> {
+        let url = match reqwest::Url::parse(url) {
+            Ok(url) if url.host_str().is_some_and(is_sec_host) => url,
+            _ => return Ok(format!("{} is not a url of a document on sec.gov", url)),
+        };
+        if let Err(reason) = self.access.allows(&url) {
+            return Ok(reason);
+        }
+        let html = self
+            .get(url.clone())
+            .await?
+            .text()
+            .await
+            .map_err(|e| Error::AgentWorkflowError(e.to_string()))?;
+        let text = html_to_text(&html);
+        self.access.record(url.as_str(), &text, Trust::Verified);
+        Ok(text)
+    }
+}
+
+fn is_sec_host(host: &str) -> bool {
+    host == "sec.gov" || host.ends_with(".sec.gov")
+}
+
+#[derive(Deserialize, JsonSchema)]
+struct TickerArgs {
+    /// the ticker symbol, e.g. AAPL; for quotes of non-US listings add the market, e.g. SAP.DE
+    ticker: String,
+}
+
+struct StockQuoteTool(FinanceTool);
+
+#[async_trait]
+impl FunctionalTool for StockQuoteTool {
+    fn definition(&self) -> Result<ToolDefinition> {
+        ToolDefinition::new::<TickerArgs>(
+            "stock_quote",
+            "This tool returns the latest daily price and volume of a stock. Use it instead of reading prices from web pages.",
+        )
+    }
+
+    async fn invoke_fn(&mut self, call: &ToolCall, _: &ToolContext) -> Result<Message> {
+        let args: TickerArgs = call.args()?;
+        Ok(tool_result(call, self.0.quote(&args.ticker).await))
+    }
+}
+
+struct FundamentalsTool(FinanceTool);
+
+#[async_trait]
+impl FunctionalTool for FundamentalsTool {
+    fn definition(&self) -> Result<ToolDefinition> {
+        ToolDefinition::new::<TickerArgs>(
+            "company_fundamentals",
+            "This tool returns key financials of a company filing with the SEC, such as revenue, net income, assets, and cash, as reported in its latest annual report. Use it for reliable figures on US listed companies.",
+        )
+    }
+
+    async fn invoke_fn(&mut self, call: &ToolCall, _: &ToolContext) -> Result<Message> {
+        let args: TickerArgs = call.args()?;
+        Ok(tool_result(call, self.0.fundamentals(&args.ticker).await))
+    }
+}
+
+#[derive(Deserialize, JsonSchema)]
+struct SecFilingsArgs {
+    /// the ticker of the company whose filings are listed, e.g. AAPL
+    #[serde(default)]
+    ticker: Option<String>,
+    /// a phrase to search for in the full text of all filings, optionally limited to the company of the ticker
+    #[serde(default)]
+    query: Option<String>,
+    /// the form type, e.g. 10-K, 10-Q, 8-K, or DEF 14A
+    #[serde(default)]
+    form: Option<String>,
+    /// the maximum number of filings to return, at most 40
+    #[serde(default)]
+    limit: Option<usize>,
+}
+
+struct SecFilingsTool(FinanceTool);
+
+#[async_trait]
+impl FunctionalTool for SecFilingsTool {
+    fn definition(&self) -> Result<ToolDefinition> {
+        ToolDefinition::new::<SecFilingsArgs>(
+            "sec_filings",
+            "This tool lists SEC EDGAR filings, either the recent filings of a company or the filings whose full text contains a phrase, with their form type, filing date, and document url. Read a filing with sec_filing_text.",
+        )
+    }
+
+    async fn invoke_fn(&mut self, call: &ToolCall, _: &ToolContext) -> Result<Message> {
+        let args: SecFilingsArgs = call.args()?;
+        Ok(tool_result(call, self.0.filings(&args).await))
+    }
+}
+
+#[derive(Deserialize, JsonSchema)]
+struct SecFilingTextArgs {
+    /// the sec.gov url of the filing document, as returned by sec_filings
+    url: String,
+}
+
+struct SecFilingTextTool(FinanceTool);
+
+#[async_trait]
+impl FunctionalTool for SecFilingTextTool {
+    fn definition(&self) -> Result<ToolDefinition> {
+        ToolDefinition::new::<SecFilingTextArgs>(
+            "sec_filing_text",
+            "This tool returns the full text of an SEC filing document. Filings are long, so look for the sections relevant to the task.",
+        )
+    }
+
+    fn third_party_fn(&self) -> bool {
+        true
+    }
+
+    async fn invoke_fn(&mut self, call: &ToolCall, _: &ToolContext) -> Result<Message> {
+        let args: SecFilingTextArgs = call.args()?;
+        Ok(tool_result(call, self.0.filing_text(&args.url).await))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        CompanyFacts, Quote, SearchResponse, Submissions, fundamentals, is_sec_host, parse_quote,
+        recent_filings, search_filings,
+    };
+
+    #[test]
+    fn test_parse_quote() {
+        assert_eq!(
+            parse_quote(
+                "Symbol,Date,Time,Open,High,Low,Close,Volume\nAAPL.US,2024-03-15,22:00:09,171.17,172.62,170.285,172.62,121752699\n"
+            ),
+            Some(Quote {
+                symbol: "AAPL.US".to_string(),
+                date: "2024-03-15".to_string(),
+                open: "171.17".to_string(),
+                high: "172.62".to_string(),
+                low: "170.285".to_string(),
+                close: "172.62".to_string(),
+                volume: "121752699".to_string(),
+            })
+        );
+        assert_eq!(
+            parse_quote(
+                "Symbol,Date,Time,Open,High,Low,Close,Volume\nXX.US,N/D,N/D,N/D,N/D,N/D,N/D,N/D"
+            ),
+            None
+        );
+    }
+
+    #[test]
+    fn test_fundamentals() {
+        let facts: CompanyFacts = serde_json::from_str(
+            r#"{"cik": 320193, "entityName": "Apple Inc.", "facts": {"us-gaap": {
+                "Revenues": {"units": {"USD": [
+                    {"end": "2018-09-29", "val": 265595000000, "fy": 2018, "fp": "FY", "form": "10-K"}
+                ]}},
+                "RevenueFromContractWithCustomerExcludingAssessedTax": {"units": {"USD": [
+                    {"end": "2022-09-24", "val": 394328000000, "fy": 2022, "fp": "FY", "form": "10-K"},
+                    {"end": "2023-09-30", "val": 383285000000, "fy": 2023, "fp": "FY", "form": "10-K"},
+                    {"end": "2023-12-30", "val": 119575000000, "fy": 2024, "fp": "Q1", "form": "10-Q"}
+                ]}},
+                "EarningsPerShareDiluted": {"units": {"USD/shares": [
+                    {"end": "2023-09-30", "val": 6.13, "fy": 2023, "fp": "FY", "form": "10-K"}
+                ]}}
+            }}}"#,
+        )
+        .unwrap();
+
+        assert_eq!(
+            fundamentals(&facts),
+            vec![
+                (
+                    "Revenue",
+                    "383285000000 USD (FY2023, period ending 2023-09-30)".to_string()
+                ),
+                (
+                    "Diluted EPS",
+                    "6.13 USD/shares (FY2023, period ending 2023-09-30)".to_string()
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_filings() {
+        let submissions: Submissions = serde_json::from_str(
+            r#"{"name": "Apple Inc.", "filings": {"recent": {
+                "accessionNumber": ["0000320193-24-000006", "0000320193-23-000106"],
+                "filingDate": ["2024-02-02", "2023-11-03"],
+                "form": ["8-K", "10-K"],
+                "primaryDocument": ["aapl-20240201.htm", "aapl-20230930.htm"]
+            }}}"#,
+        )
+        .unwrap();
+        let filings = recent_filings(320193, &submissions, Some("10-k"), 10);
+        assert_eq!(filings.len(), 1);
+        assert_eq!(
+            filings[0].url,
+            "https://www.sec.gov/Archives/edgar/data/320193/000032019323000106/aapl-20230930.htm"
+        );
+        assert_eq!(recent_filings(320193, &submissions, None, 1).len(), 1);
+
+        let res: SearchResponse = serde_json::from_str(
+            r#"{"hits": {"hits": [{"_id": "0000320193-23-000106:aapl-20230930.htm", "_source": {
+                "ciks": ["0000320193"], "display_names": ["Apple Inc.  (AAPL)  (CIK 0000320193)"],
+                "form": "10-K", "file_date": "2023-11-03"}}]}}"#,
+        )
+        .unwrap();
+        assert_eq!(search_filings(res, 10)[0].url, filings[0].url);
+
+        assert!(is_sec_host("www.sec.gov"));
+        assert!(is_sec_host("sec.gov"));
+        assert!(!is_sec_host("notsec.gov"));
+    }
+}
@@ -0,0 +1,997 @@
+use crate::llm::Message;
+use crate::secrets::{Secret, Secrets};
+use crate::tools::web_fetch::MAX_DATA_CHARS;
+use crate::tools::{
+    ApiAuth, FunctionalTool, HttpClient, HttpClientConfig, Tool, ToolCall, ToolContext,
+    ToolDefinition, Trust, WebAccess, tool_result,
+};
+use crate::{Error, Result};
+use async_trait::async_trait;
+use schemars::JsonSchema;
+use serde::Deserialize;
+use serde_json::{Value, json};
+use std::collections::HashMap;
+use std::path::Path;
+use std::sync::Arc;
+use std::time::Duration;
+
+const DEFAULT_MAX_DEPTH: usize = 8;
+// queries selecting more fields than this usually return more than fits the context
+const MAX_FIELDS: usize = 300;
+// strings in responses, such as the bodies of issues, are cut to this length
+const MAX_STRING_CHARS: usize = 2000;
+const MAX_LISTED_FIELDS: usize = 40;
+
+const INTROSPECTION: &str = "query {
+  __schema {
+    queryType { name }
+    types {
+      kind name description
+      fields { name description args { name type { ...TypeRef } } type { ...TypeRef } }
+      inputFields { name type { ...TypeRef } }
+      enumValues { name }
+    }
+  }
+}
+fragment TypeRef on __Type {
+  kind name ofType { kind name ofType { kind name ofType { kind name ofType { kind name } } } }
+}";
+
+/// A GraphQL endpoint whose schema is read by introspection, agents query it with a tool.
+#[derive(Clone, Debug, Default, Deserialize)]
+pub struct GraphQLConfig {
+    /// prefix of the tool names, e.g. github for the tool github_graphql_query
+    pub name: String,
+    /// http url of the endpoint, e.g. https://api.github.com/graphql
+    pub endpoint: String,
+    /// credentials sent with every request
+    #[serde(default)]
+    pub auth: Option<ApiAuth>,
+    /// deepest nesting of fields a query may select, defaults to 8
+    #[serde(default)]
+    pub max_depth: Option<usize>,
+}
+
+impl GraphQLConfig {
+    /// Reads a config from a JSON file.
+    pub fn read(path: &Path) -> Result<Self> {
+        Ok(serde_json::from_slice(&std::fs::read(path)?)?)
+    }
+}
+
+#[derive(Deserialize)]
+struct Introspection {
+    #[serde(rename = "__schema")]
+    schema: IntrospectedSchema,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct IntrospectedSchema {
+    query_type: Named,
+    types: Vec<TypeInfo>,
+}
+
+#[derive(Debug, Deserialize)]
+struct Named {
+    name: String,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct TypeInfo {
+    kind: String,
+    name: String,
+    #[serde(default)]
+    description: Option<String>,
+    #[serde(default)]
+    fields: Option<Vec<FieldInfo>>,
+    #[serde(default)]
+    input_fields: Option<Vec<InputValue>>,
+    #[serde(default)]
+    enum_values: Option<Vec<Named>>,
+}
+
+#[derive(Debug, Deserialize)]
+struct FieldInfo {
+    name: String,
+    #[serde(default)]
+    description: Option<String>,
+    #[serde(default)]
+    args: Vec<InputValue>,
+    #[serde(rename = "type")]
+    ty: TypeRef,
+}
+
+#[derive(Debug, Deserialize)]
+struct InputValue {
+    name: String,
+    #[serde(rename = "type")]
+    ty: TypeRef,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct TypeRef {
+    kind: String,
+    #[serde(default)]
+    name: Option<String>,
+    #[serde(default)]
+    of_type: Option<Box<TypeRef>>,
+}
+
+impl TypeRef {
+    // the type without its list and non null wrappers
+    fn named(&self) -> &str {
+        match (&self.name, &self.of_type) {
+            (Some(name), _) => name,
+            (None, Some(inner)) => inner.named(),
+            (None, None) => "",
+        }
+    }
+}
+
+impl std::fmt::Display for TypeRef {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match (self.kind.as_str(), &self.of_type) {
+            ("NON_NULL", Some(inner)) => write!(f, "{}!", inner),
+            ("LIST", Some(inner)) => write!(f, "[{}]", inner),
+            _ => f.write_str(self.named()),
+        }
+    }
+}
+
+#[derive(Debug)]
+struct Schema {
+    query_type: String,
+    types: HashMap<String, TypeInfo>,
+}
+
+impl Schema {
+    fn field(&self, type_name: &str, field: &str) -> Option<&FieldInfo> {
+        self.types
+            .get(type_name)?
+            .fields
+            .as_ref()?
+            .iter()
+            .find(|f| f.name == field)
+    }
+
+    // whether values of the type are objects whose fields have to be selected
+    fn has_fields(&self, type_name: &str) -> bool {
+        self.types
+            .get(type_name)
+            .is_some_and(|t| matches!(t.kind.as_str(), "OBJECT" | "INTERFACE" | "UNION"))
+    }
+
+    // the fields of the type with their arguments and types, the query type without a name
+    fn describe(&self, type_name: Option<&str>) -> String {
+        let name = type_name.unwrap_or(&self.query_type);
+        let Some(info) = self.types.get(name) else {
+            return format!("the schema has no type {}", name);
+        };
+        let mut res = format!("{} {}", info.kind.to_ascii_lowercase(), info.name);
+        if let Some(description) = info.description.as_deref().filter(|d| !d.is_empty()) {
+            res.push_str(&format!(": {}", description.trim()));
+        }
+        res.push('\n');
+        for field in info.fields.iter().flatten() {
+            let args = field
+                .args
+                .iter()
+                .map(|arg| format!("{}: {}", arg.name, arg.ty))
+                .collect::<Vec<_>>();
+            res.push_str(&format!("- {}", field.name));
+            if !args.is_empty() {
+                res.push_str(&format!("({})", args.join(", ")));
+            }
+            res.push_str(&format!(": {}", field.ty));
+            if let Some(description) = field.description.as_deref().filter(|d| !d.is_empty()) {
+                let line = description.lines().next().unwrap_or_default().trim();
+                res.push_str(&format!(" - {}", line));
+            }
+            res.push('\n');
+        }
+        for field in info.input_fields.iter().flatten() {
+            res.push_str(&format!("- {}: {}\n", field.name, field.ty));
+        }
+        if let Some(values) = &info.enum_values {
+            let values = values.iter().map(|v| v.name.as_str()).collect::<Vec<_>>();
+            res.push_str(&format!("values: {}\n", values.join(", ")));
+        }
+        if matches!(info.kind.as_str(), "UNION" | "INTERFACE") {
+            res.push_str(
+                "select the fields of the types it stands for with inline fragments, e.g. ... on TypeName { id }\n",
+            );
+        }
+        truncate(&res, MAX_DATA_CHARS)
+    }
+}
+
+fn truncate(text: &str, limit: usize) -> String {
+    match text.char_indices().nth(limit) {
+        Some((end, _)) => format!("{}...", &text[..end]),
+        None => text.to_string(),
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum Token {
+    Punct(char),
+    Spread,
+    Name(String),
+    // strings and numbers, which only appear in arguments
+    Value,
+}
+
+fn tokenize(query: &str) -> std::result::Result<Vec<Token>, String> {
+    let mut tokens = Vec::new();
+    let mut chars = query.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            c if c.is_whitespace() || c == ',' || c == '\u{feff}' => {}
+            '#' => {
+                for c in chars.by_ref() {
+                    if c == '\n' {
+                        break;
+                    }
+                }
+            }
+            '{' | '}' | '(' | ')' | '[' | ']' | ':' | '@' | '$' | '!' | '=' | '|' | '&' => {
+                tokens.push(Token::Punct(c))
+            }
+            '.' => {
+                if chars.next() != Some('.') || chars.next() != Some('.') {
+                    return Err(
+                        "a single . is not valid GraphQL, spreads are written ...".to_string()
+                    );
+                }
+                tokens.push(Token::Spread);
+            }
+            '"' => {
+                let mut closed = false;
+                let mut escaped = false;
+                for c in chars.by_ref() {
+                    match (escaped, c) {
+                        (true, _) => escaped = false,
+                        (false, '\\') => escaped = true,
+                        (false, '"') => {
+                            closed = true;
+                            break;
+                        }
+                        _ => {}
+                    }
+                }
+                if !closed {
+                    return Err("a string is not closed".to_string());
+                }
+                tokens.push(Token::Value);
+            }
+            c if c.is_ascii_digit() || c == '-' => {
+                while chars
+                    .peek()
+                    .is_some_and(|c| c.is_ascii_alphanumeric() || matches!(c, '.' | '+' | '-'))
+                {
+                    chars.next();
+                }
+                tokens.push(Token::Value);
+            }
+            c if c.is_ascii_alphabetic() || c == '_' => {
+                let mut name = c.to_string();
+                while let Some(&c) = chars.peek()
+                    && (c.is_ascii_alphanumeric() || c == '_')
+                {
+                    name.push(c);
+                    chars.next();
+                }
+                tokens.push(Token::Name(name));
+            }
+            c => return Err(format!("unexpected {} in the query", c)),
+        }
+    }
+    Ok(tokens)
+}
+
+#[derive(Debug)]
+enum Selection {
+    Field {
+        name: String,
+        selections: Vec<Selection>,
+    },
+    Spread(String),
+    Inline {
+        on: Option<String>,
+        selections: Vec<Selection>,
+    },
+}
+
+#[derive(Default)]
+struct Document {
+    // the selections of the operations of the document
+    operations: Vec<Vec<Selection>>,
+    fragments: HashMap<String, (String, Vec<Selection>)>,
+}
+
+struct Parser {
+    tokens: Vec<Token>,
+    pos: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.pos)
+    }
+
+    fn next(&mut self) -> Option<Token> {
+        let token = self.tokens.get(self.pos).cloned();
+        self.pos += 1;
+        token
+    }
+
+    fn eat(&mut self, c: char) -> bool {
+        if self.peek() == Some(&Token::Punct(c)) {
+            self.pos += 1;
+            true
+        } else {
+            false
+        }
+    }
+
+    fn name(&mut self) -> std::result::Result<String, String> {
+        match self.next() {
+            Some(Token::Name(name)) => Ok(name),
+            other => Err(format!("expected a name, found {:?}", other)),
+        }
+    }
+
+    // skips the tokens up to the closing bracket, the opening one already read
+    fn skip_group(&mut self, open: char, close: char) -> std::result::Result<(), String> {
+        let mut depth = 1;
+        while depth > 0 {
+            match self.next() {
+                Some(Token::Punct(c)) if c == open => depth += 1,
+                Some(Token::Punct(c)) if c == close => depth -= 1,
+                Some(_) => {}
+                None => return Err(format!("a {} is not closed", open)),
+            }
+        }
+        Ok(())
+    }
+
+    fn directives(&mut self) -> std::result::Result<(), String> {
+        while self.eat('@') {
+            self.name()?;
+            if self.eat('(') {
+                self.skip_group('(', ')')?;
+            }
+        }
+        Ok(())
+    }
+
+    fn selection_set(&mut self) -> std::result::Result<Vec<Selection>, String> {
+        if !self.eat('{') {
+            return Err(format!("expected {{, found {:?}", self.peek()));
+        }
+        let mut selections = Vec::new();
+        while !self.eat('}') {
+            match self.peek() {
+                None => return Err("a { is not closed".to_string()),
+                Some(Token::Spread) => {
+                    self.pos += 1;
+                    match self.peek() {
+                        Some(Token::Name(name)) if name != "on" => {
+                            let name = self.name()?;
+                            self.directives()?;
+                            selections.push(Selection::Spread(name));
+                        }
+                        _ => {
+                            let on = match self.peek() {
+                                Some(Token::Name(on)) if on == "on" => {
+                                    self.pos += 1;
+                                    Some(self.name()?)
+                                }
+                                _ => None,
+                            };
+                            self.directives()?;
+                            selections.push(Selection::Inline {
+                                on,
+                                selections: self.selection_set()?,
+                            });
+                        }
+                    }
+                }
+                Some(_) => {
+                    let mut name = self.name()?;
+                    // an alias before the name of the field
+                    if self.eat(':') {
+                        name = self.name()?;
+                    }
+                    if self.eat('(') {
+                        self.skip_group('(', ')')?;
+                    }
+                    self.directives()?;
+                    let selections_of = if self.peek() == Some(&Token::Punct('{')) {
+                        self.selection_set()?
+                    } else {
+                        Vec::new()
+                    };
+                    selections.push(Selection::Field {
+                        name,
+                        selections: selections_of,
+                    });
+                }
+            }
+        }
+        Ok(selections)
+    }
+
+    fn document(&mut self) -> std::result::Result<Document, String> {
+        let mut document = Document::default();
+        while let Some(token) = self.peek() {
+            match token {
+                Token::Punct('{') => document.operations.push(self.selection_set()?),
+                Token::Name(keyword) if keyword == "query" => {
+                    self.pos += 1;
+                    if matches!(self.peek(), Some(Token::Name(_))) {
+                        self.pos += 1;
+                    }
+                    if self.eat('(') {
+                        self.skip_group('(', ')')?;
+                    }
+                    self.directives()?;
+                    document.operations.push(self.selection_set()?);
+                }
+                Token::Name(keyword) if keyword == "mutation" || keyword == "subscription" => {
+                    return Err(format!(
+                        "{} operations are not allowed, only queries",
+                        keyword
+                    ));
+                }
+                Token::Name(keyword) if keyword == "fragment" => {
+                    self.pos += 1;
+                    let name = self.name()?;
+                    if self.name()? != "on" {
+                        return Err(format!("the fragment {} has no type condition", name));
+                    }
+                    let on = self.name()?;
+                    self.directives()?;
+                    let selections = self.selection_set()?;
+                    document.fragments.insert(name, (on, selections));
+                }
+                other => return Err(format!("unexpected {:?} in the query", other)),
+            }
+        }
+        if document.operations.is_empty() {
+            return Err("the query has no operation".to_string());
+        }
+        Ok(document)
+    }
+}
+
+struct Validator<'a> {
+    schema: &'a Schema,
+    document: &'a Document,
+    max_depth: usize,
+    fields: usize,
+    // the fragments being checked, to stop at fragments that spread themselves
+    visiting: Vec<&'a str>,
+}
+
+impl<'a> Validator<'a> {
+    fn check(
+        &mut self,
+        selections: &'a [Selection],
+        type_name: &str,
+        depth: usize,
+    ) -> std::result::Result<(), String> {
+        if depth > self.max_depth {
+            return Err(format!(
+                "the query nests fields deeper than the limit of {}",
+                self.max_depth
+            ));
+        }
+        for selection in selections {
+            match selection {
+                Selection::Field { name, selections } => {
+                    self.fields += 1;
+                    if self.fields > MAX_FIELDS {
+                        return Err(format!(
+                            "the query selects more than {} fields, select fewer",
+                            MAX_FIELDS
+                        ));
+                    }
+                    if name == "__typename" {
+                        continue;
+                    }
+                    let Some(field) = self.schema.field(type_name, name) else {
+                        let known = self
+                            .schema
+                            .types
+                            .get(type_name)
+                            .and_then(|t| t.fields.as_ref())
+                            .map(|fields| {
+                                fields
+                                    .iter()
+                                    .take(MAX_LISTED_FIELDS)
+                                    .map(|f| f.name.as_str())
+                                    .collect::<Vec<_>>()
+                                    .join(", ")
+                            })
+                            .unwrap_or_default();
+                        return Err(format!(
+                            "the type {} has no field {}, its fields are: {}",
+                            type_name, name, known
+                        ));
+                    };
+                    let inner = field.ty.named();
+                    match (self.schema.has_fields(inner), selections.is_empty()) {
+                        (true, true) => {
+                            return Err(format!(
+                                "the field {} returns {} and needs a selection of its fields",
+                                name, field.ty
+                            ));
+                        }
+                        (false, false) => {
+                            return Err(format!(
+                                "the field {} returns {} which has no fields to select",
+                                name, field.ty
+                            ));
+                        }
+                        (true, false) => self.check(selections, inner, depth + 1)?,
+                        (false, true) => {}
+                    }
+                }
+                Selection::Inline { on, selections } => {
+                    let on = on.as_deref().unwrap_or(type_name);
+                    if !self.schema.types.contains_key(on) {
+                        return Err(format!("the schema has no type {}", on));
+                    }
+                    self.check(selections, on, depth)?;
+                }
+                Selection::Spread(name) => {
+                    let Some((on, selections)) = self.document.fragments.get(name) else {
+                        return Err(format!("the fragment {} is not defined", name));
+                    };
+                    if self.visiting.contains(&name.as_str()) {
+                        return Err(format!("the fragment {} spreads itself", name));
+                    }
+                    self.visiting.push(name);
+                    self.check(selections, on, depth)?;
+                    self.visiting.pop();
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+// drops nulls and empty values and cuts long strings, which keeps responses of wide queries
+// readable
+fn trim(value: Value) -> Option<Value> {
+    match value {
+        Value::Null => None,
+        Value::String(text) => Some(Value::String(truncate(&text, MAX_STRING_CHARS))),
+        Value::Array(items) => {
+            let items = items.into_iter().filter_map(trim).collect::<Vec<_>>();
+            (!items.is_empty()).then_some(Value::Array(items))
+        }
+        Value::Object(fields) => {
+            let fields = fields
+                .into_iter()
+                .filter_map(|(key, value)| trim(value).map(|value| (key, value)))
+                .collect::<serde_json::Map<_, _>>();
+            (!fields.is_empty()).then_some(Value::Object(fields))
+        }
+        other => Some(other),
+    }
+}
+
+#[derive(Debug)]
+struct Endpoint {
+    name: String,
+    url: reqwest::Url,
+    auth: Option<(ApiAuth, Secret)>,
+    max_depth: usize,
+    schema: Schema,
+}
+
+/// A GraphQL endpoint with the schema read from it, cheap to clone so that every agent gets its
+/// own tools with GraphQLTool.
+#[derive(Clone, Debug)]
+pub struct GraphQL(Arc<Endpoint>);
+
+// posts a query to the endpoint and returns the data and errors of the response
+async fn post(
+    http: &HttpClient,
+    endpoint: &Endpoint,
+    query: &str,
+    variables: Option<&Value>,
+) -> Result<Value> {
+    let mut url = endpoint.url.clone();
+    let mut request_body = json!({ "query": query });
+    if let Some(variables) = variables {
+        request_body["variables"] = variables.clone();
+    }
+    let mut header = None;
+    match &endpoint.auth {
+        Some((ApiAuth::Query { name, .. }, secret)) => {
+            url.query_pairs_mut().append_pair(name, secret.expose());
+        }
+        Some((auth @ ApiAuth::Header { name, .. }, secret)) => {
+            let value = auth.header_value(secret).ok_or_else(|| {
+                Error::AuthError(format!(
+                    "the credentials of the {} API are not a valid header",
+                    endpoint.name
+                ))
+            })?;
+            header = Some((name.clone(), value));
+        }
+        None => {}
+    }
+    let mut request = http.request(reqwest::Method::POST, url).json(&request_body);
+    if let Some((name, value)) = header {
+        request = request.header(name, value);
+    }
+    // the url of the error would hold the secret of a query auth
+    let res = http.send(request).await.map_err(|e| {
+        Error::AgentWorkflowError(format!(
+            "failed to query {}: {}",
+            endpoint.url,
+            e.without_url()
+        ))
+    })?;
+    let status = res.status();
+    let text = res.text().await.map_err(|e| {
+        Error::AgentWorkflowError(format!(
+            "failed to read {}: {}",
+            endpoint.url,
+            e.without_url()
+        ))
+    })?;
+    // graphql servers also report invalid queries with an error status and a json body
+    match serde_json::from_str::<Value>(&text) {
+        Ok(body) if body.get("data").is_some() || body.get("errors").is_some() => Ok(body),
+        _ => Err(Error::AgentWorkflowError(format!(
+            "the {} API replied with status {}: {}",
+            endpoint.name,
+            status,
+            truncate(text.trim(), 1000)
+        ))),
+    }
+}
+
+fn errors(body: &Value) -> Vec<String> {
+    body.get("errors")
+        .and_then(Value::as_array)
+        .into_iter()
+        .flatten()
+        .map(|error| match error.get("message").and_then(Value::as_str) {
+            Some(message) => message.to_string(),
+            None => error.to_string(),
+        })
+        .collect()
+}
+
+impl GraphQL {
+    /// Reads the schema of the endpoint by introspection, with the http settings of the web
+    /// tools. Fails if the endpoint does not allow introspection.
+    pub async fn load(
+        config: GraphQLConfig,
+        http: &HttpClientConfig,
+        secrets: &Secrets,
+    ) -> Result<Self> {
+        let url = reqwest::Url::parse(&config.endpoint).map_err(|e| {
+            Error::MissingArg(format!("{} is not a valid url: {}", config.endpoint, e))
+        })?;
+        let auth = match &config.auth {
+            Some(auth) => Some((auth.clone(), auth.resolve(secrets)?)),
+            None => None,
+        };
+        let mut endpoint = Endpoint {
+            name: config.name,
+            url,
+            auth,
+            max_depth: config.max_depth.unwrap_or(DEFAULT_MAX_DEPTH),
+            schema: Schema {
+                query_type: String::new(),
+                types: HashMap::new(),
+            },
+        };
+        let http = http.client(Duration::from_secs(60))?;
+        let body = post(&http, &endpoint, INTROSPECTION, None).await?;
+        let data = body.get("data").cloned().unwrap_or_default();
+        let introspection: Introspection = serde_json::from_value(data).map_err(|_| {
+            Error::Unsupported(format!(
+                "the {} API does not allow introspection: {}",
+                endpoint.name,
+                errors(&body).join("; ")
+            ))
+        })?;
+        endpoint.schema = Schema::from(introspection);
+        Ok(Self(Arc::new(endpoint)))
+    }
+
+    pub fn name(&self) -> &str {
+        &self.0.name
+    }
+}
+
+impl From<Introspection> for Schema {
+    fn from(introspection: Introspection) -> Self {
+        Schema {
+            query_type: introspection.schema.query_type.name,
+            types: introspection
+                .schema
+                .types
+                .into_iter()
+                .map(|t| (t.name.clone(), t))
+                .collect(),
+        }
+    }
+}
+
+// queries a graphql api configured by the user, whose answers are treated as authoritative data
+// like those of the other api tools
+#[derive(Clone)]
+pub struct GraphQLTool {
+    api: GraphQL,
+    access: Arc<WebAccess>,
+    http: HttpClient,
+}
+
+impl GraphQLTool {
+    pub fn new(api: GraphQL, access: Arc<WebAccess>) -> Result<Box<Self>> {
+        Ok(Box::new(Self {
+            api,
+            http: access.policy().http.client(Duration::from_secs(60))?,
+            access,
+        }))
+    }
+
+    pub fn tools(&self) -> Result<Vec<Box<dyn Tool + Send>>> {
+        Ok(vec![
+            Box::new(GraphQLQueryTool(self.clone())),
+            Box::new(GraphQLSchemaTool(self.clone())),
+        ])
+    }
+
+    fn validate(&self, query: &str) -> std::result::Result<(), String> {
+        let document = Parser {
+            tokens: tokenize(query)?,
+            pos: 0,
+        }
+        .document()?;
+        let endpoint = &self.api.0;
+        let mut validator = Validator {
+            schema: &endpoint.schema,
+            document: &document,
+            max_depth: endpoint.max_depth,
+            fields: 0,
+            visiting: Vec::new(),
+        };
+        for operation in &document.operations {
+            validator.check(operation, &endpoint.schema.query_type, 1)?;
+        }
+        Ok(())
+    }
+
+    async fn query(&self, query: &str, variables: Option<&Value>) -> Result<String> {
+        if let Err(reason) = self.validate(query) {
+            return Ok(format!("The query is invalid: {}", reason));
+        }
+        let endpoint = &self.api.0;
+        if let Err(reason) = self.access.check(&endpoint.url) {
+            return Ok(reason);
+        }
+        let body = post(&self.http, endpoint, query, variables).await?;
+        let mut res = String::new();
+        let errors = errors(&body);
+        if !errors.is_empty() {
+            res.push_str(&format!(
+                "The query returned errors: {}\n\n",
+                errors.join("; ")
+            ));
+        }
+        match body.get("data").cloned().and_then(trim) {
+            Some(data) => {
+                let pretty = serde_json::to_string_pretty(&data)?;
+                match pretty.char_indices().nth(MAX_DATA_CHARS) {
+                    Some((end, _)) => res.push_str(&format!(
+                        "{}\n...\nThe response was cut after {} of {} characters, select fewer fields or items.",
+                        &pretty[..end],
+                        end,
+                        pretty.len()
+                    )),
+                    None => res.push_str(&pretty),
+                }
+            }
+            None if errors.is_empty() => res.push_str("The query returned no data."),
+            None => {}
+        }
+        self.access
+            .record(endpoint.url.as_str(), &res, Trust::Verified);
+        Ok(res)
+    }
+}
+
+// errors of reqwest quote the url, which holds the secret of a query auth
+fn redacted(api: &GraphQL, result: Result<String>) -> Result<String> {
+    match &api.0.auth {
+        Some((_, secret)) => Ok(secret.redact(&result.unwrap_or_else(|e| e.to_string()))),
+        None => result,
+    }
+}
+
+#[derive(Deserialize, JsonSchema)]
+struct GraphQLQueryArgs {
+    /// the GraphQL query, e.g. { repository(owner: "rust-lang", name: "rust") { stargazerCount } }
+    query: String,
+    /// the values of the variables the query declares
+    #[serde(default)]
+    variables: Option<Value>,
+}
+
+struct GraphQLQueryTool(GraphQLTool);
+
+#[async_trait]
+impl FunctionalTool for GraphQLQueryTool {
+    fn definition(&self) -> Result<ToolDefinition> {
+        let endpoint = &self.0.api.0;
+        let fields = endpoint
+            .schema
+            .types
+            .get(&endpoint.schema.query_type)
+            .and_then(|t| t.fields.as_ref())
+            .map(|fields| {
+                fields
+                    .iter()
+                    .take(MAX_LISTED_FIELDS)
+                    .map(|f| f.name.as_str())
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            })
+            .unwrap_or_default();
+        ToolDefinition::new::<GraphQLQueryArgs>(
+            &format!("{}_graphql_query", endpoint.name),
+            &format!(
+                "This tool runs a read-only GraphQL query against the {} API and returns the data as JSON without null fields. Queries are checked against the schema and may nest fields at most {} levels deep. The top level fields are: {}. Look up the fields of a type with {}_graphql_schema before writing a query.",
+                endpoint.name, endpoint.max_depth, fields, endpoint.name
+            ),
+        )
+    }
+
+    fn third_party_fn(&self) -> bool {
+        true
+    }
+
+    async fn invoke_fn(&mut self, call: &ToolCall, _: &ToolContext) -> Result<Message> {
+        let args: GraphQLQueryArgs = call.args()?;
+        let result = self.0.query(&args.query, args.variables.as_ref()).await;
+        Ok(tool_result(call, redacted(&self.0.api, result)))
+    }
+}
+
+#[derive(Deserialize, JsonSchema)]
+struct GraphQLSchemaArgs {
+    /// the name of the type to describe, e.g. Repository; leave empty for the top level query fields
+    #[serde(default)]
+    type_name: Option<String>,
+}
+
+struct GraphQLSchemaTool(GraphQLTool);
+
+#[async_trait]
+impl FunctionalTool for GraphQLSchemaTool {
+    fn definition(&self) -> Result<ToolDefinition> {
+        let name = &self.0.api.0.name;
+        ToolDefinition::new::<GraphQLSchemaArgs>(
+            &format!("{}_graphql_schema", name),
+            &format!(
+                "This tool lists the fields of a type of the {} GraphQL API with their arguments and types. Use it to find the fields to select in a {}_graphql_query.",
+                name, name
+            ),
+        )
+    }
+
+    async fn invoke_fn(&mut self, call: &ToolCall, _: &ToolContext) -> Result<Message> {
+        let args: GraphQLSchemaArgs = call.args()?;
+        let description = self.0.api.0.schema.describe(args.type_name.as_deref());
+        Ok(tool_result(call, Ok(description)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Endpoint, GraphQL, GraphQLTool, Introspection, Schema, trim};
+    use crate::tools::WebAccess;
+    use serde_json::json;
+    use std::sync::Arc;
+
+    fn schema() -> Schema {
+        let object = |name: &str, fields: serde_json::Value| json!({"kind": "OBJECT", "name": name, "fields": fields});
+        let field = |name: &str, kind: &str, ty: &str| json!({"name": name, "args": [], "type": {"kind": "NON_NULL", "ofType": {"kind": kind, "name": ty}}});
+        let introspection: Introspection = serde_json::from_value(json!({
+            "__schema": {
+                "queryType": {"name": "Query"},
+                "types": [
+                    object("Query", json!([
+                        {"name": "repository", "args": [{"name": "name", "type": {"kind": "SCALAR", "name": "String"}}], "type": {"kind": "OBJECT", "name": "Repository"}},
+                    ])),
+                    object("Repository", json!([
+                        field("name", "SCALAR", "String"),
+                        field("owner", "OBJECT", "User"),
+                        field("stargazerCount", "SCALAR", "Int"),
+                    ])),
+                    object("User", json!([
+                        field("login", "SCALAR", "String"),
+                        field("repository", "OBJECT", "Repository"),
+                    ])),
+                    {"kind": "SCALAR", "name": "String"},
+                    {"kind": "SCALAR", "name": "Int"},
+                ],
+            },
+        }))
+        .unwrap();
+        Schema::from(introspection)
+    }
+
+    #[test]
+    fn test_validate() {
+        let api = GraphQL(Arc::new(Endpoint {
+            name: "github".to_string(),
+            url: "https://api.github.com/graphql".parse().unwrap(),
+            auth: None,
+            max_depth: 3,
+            schema: schema(),
+        }));
+        let tool = GraphQLTool::new(api, WebAccess::new(Default::default())).unwrap();
+        let ok = |query: &str| tool.validate(query);
+
+        assert!(ok(r#"{ repository(name: "rust") { name stargazerCount } }"#).is_ok());
+        assert!(
+            ok(r#"query Stars($name: String!) {
+                # aliases, fragments, and directives
+                repo: repository(name: $name) { ...Info owner @include(if: true) { login } }
+            }
+            fragment Info on Repository { __typename name }"#)
+            .is_ok()
+        );
+        assert!(ok("{ repository { ... on Repository { name } } }").is_ok());
+
+        let error = |query: &str| ok(query).unwrap_err();
+        assert!(error("{ repository { forks } }").contains("has no field forks"));
+        assert!(error("{ repository }").contains("needs a selection"));
+        assert!(error("{ repository { name { x } } }").contains("no fields to select"));
+        assert!(error("mutation { addStar { id } }").contains("not allowed"));
+        assert!(error("{ repository { ...Missing } }").contains("not defined"));
+        assert!(error("{ repository { name }").contains("not closed"));
+        assert!(
+            error("{ repository { owner { repository { owner { login } } } } }")
+                .contains("deeper than the limit of 3")
+        );
+        assert!(
+            error(
+                "{ repository { ...A } } fragment A on Repository { owner { repository { ...A } } }"
+            )
+            .contains("deeper")
+        );
+    }
+
+    #[test]
+    fn test_describe_and_trim() {
+        let schema = schema();
+        let query = schema.describe(None);
+        assert!(query.contains("- repository(name: String): Repository"));
+        assert!(
+            schema
+                .describe(Some("Repository"))
+                .contains("- owner: User!")
+        );
+        assert!(schema.describe(Some("Issue")).contains("no type Issue"));
+
+        assert_eq!(
+            trim(json!({"a": null, "b": [], "c": {"d": null}, "e": [1, null], "f": "x"})),
+            Some(json!({"e": [1], "f": "x"}))
+        );
+    }
+}
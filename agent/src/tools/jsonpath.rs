@@ -0,0 +1,371 @@
+use serde_json::Value;
+
+// the subset of JSONPath the fetch tool supports: children, wildcards, indices, slices, unions,
+// recursive descent, and filters comparing a field with a literal, e.g.
+// $.data[?(@.country == 'DE')].values[-3:]
+#[derive(Debug, PartialEq)]
+enum Selector {
+    Child(String),
+    Index(i64),
+    Slice(Option<i64>, Option<i64>),
+    Wildcard,
+    Union(Vec<Selector>),
+    Descendants(Box<Selector>),
+    Filter(Filter),
+}
+
+#[derive(Debug, PartialEq)]
+enum Op {
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+}
+
+#[derive(Debug, PartialEq)]
+struct Filter {
+    // the keys after @, an empty path is the item itself
+    path: Vec<String>,
+    // without a comparison the filter checks that the field exists
+    comparison: Option<(Op, Value)>,
+}
+
+fn is_name_char(c: char) -> bool {
+    c.is_alphanumeric() || c == '_' || c == '-' || c == '$' || c == '@'
+}
+
+// the part of the path up to the bracket closing the one it starts after, quotes included
+fn bracket(path: &str) -> Result<(&str, &str), String> {
+    let mut quote = None;
+    let mut depth = 0;
+    for (i, c) in path.char_indices() {
+        match (quote, c) {
+            (Some(q), c) if c == q => quote = None,
+            (Some(_), _) => {}
+            (None, '\'' | '"') => quote = Some(c),
+            (None, '[' | '(') => depth += 1,
+            (None, ')') => depth -= 1,
+            (None, ']') if depth == 0 => return Ok((&path[..i], &path[i + 1..])),
+            (None, ']') => depth -= 1,
+            _ => {}
+        }
+    }
+    Err("a [ is not closed".to_string())
+}
+
+fn unquote(item: &str) -> Option<String> {
+    let item = item.trim();
+    let quote = item.chars().next()?;
+    if item.len() >= 2 && matches!(quote, '\'' | '"') && item.ends_with(quote) {
+        Some(item[1..item.len() - 1].to_string())
+    } else {
+        None
+    }
+}
+
+fn literal(text: &str) -> Result<Value, String> {
+    match unquote(text) {
+        Some(string) => Ok(Value::String(string)),
+        None => serde_json::from_str(text.trim()).map_err(|_| {
+            format!(
+                "{} is not a number, string, true, false, or null",
+                text.trim()
+            )
+        }),
+    }
+}
+
+fn parse_filter(expression: &str) -> Result<Filter, String> {
+    let expression = expression.trim();
+    let rest = expression
+        .strip_prefix('@')
+        .ok_or_else(|| format!("the filter {} does not start with @", expression))?;
+    let ops = [
+        ("==", Op::Eq),
+        ("!=", Op::Ne),
+        ("<=", Op::Le),
+        (">=", Op::Ge),
+        ("<", Op::Lt),
+        (">", Op::Gt),
+    ];
+    let found = ops
+        .into_iter()
+        .filter_map(|(token, op)| rest.find(token).map(|i| (i, token, op)))
+        .min_by_key(|(i, token, _)| (*i, std::cmp::Reverse(token.len())));
+    let (field, comparison) = match found {
+        Some((i, token, op)) => (&rest[..i], Some((op, literal(&rest[i + token.len()..])?))),
+        None => (rest, None),
+    };
+    let path = field
+        .trim()
+        .split('.')
+        .filter(|key| !key.is_empty())
+        .map(|key| key.trim().to_string())
+        .collect();
+    Ok(Filter { path, comparison })
+}
+
+fn parse_item(item: &str) -> Result<Selector, String> {
+    let item = item.trim();
+    if item == "*" {
+        return Ok(Selector::Wildcard);
+    }
+    if let Some(name) = unquote(item) {
+        return Ok(Selector::Child(name));
+    }
+    let number = |text: &str| -> Result<Option<i64>, String> {
+        let text = text.trim();
+        if text.is_empty() {
+            return Ok(None);
+        }
+        text.parse()
+            .map(Some)
+            .map_err(|_| format!("{} is not an index", text))
+    };
+    match item.split_once(':') {
+        Some((start, end)) => Ok(Selector::Slice(number(start)?, number(end)?)),
+        None => match number(item)? {
+            Some(index) => Ok(Selector::Index(index)),
+            None => Err("an empty [] selects nothing".to_string()),
+        },
+    }
+}
+
+// splits the contents of brackets at the commas outside of quotes
+fn split_items(contents: &str) -> Vec<&str> {
+    let mut items = Vec::new();
+    let mut quote = None;
+    let mut start = 0;
+    for (i, c) in contents.char_indices() {
+        match (quote, c) {
+            (Some(q), c) if c == q => quote = None,
+            (Some(_), _) => {}
+            (None, '\'' | '"') => quote = Some(c),
+            (None, ',') => {
+                items.push(&contents[start..i]);
+                start = i + 1;
+            }
+            _ => {}
+        }
+    }
+    items.push(&contents[start..]);
+    items
+}
+
+fn parse_bracket(contents: &str) -> Result<Selector, String> {
+    let contents = contents.trim();
+    if let Some(filter) = contents.strip_prefix('?') {
+        let filter = filter.trim();
+        let filter = filter
+            .strip_prefix('(')
+            .and_then(|filter| filter.strip_suffix(')'))
+            .unwrap_or(filter);
+        return Ok(Selector::Filter(parse_filter(filter)?));
+    }
+    let mut items = split_items(contents)
+        .into_iter()
+        .map(parse_item)
+        .collect::<Result<Vec<_>, _>>()?;
+    Ok(if items.len() == 1 {
+        items.remove(0)
+    } else {
+        Selector::Union(items)
+    })
+}
+
+fn parse(path: &str) -> Result<Vec<Selector>, String> {
+    let path = path.trim();
+    // paths without the root are read from it, e.g. data.items
+    let mut rest = match path.strip_prefix('$') {
+        Some(rest) => rest.to_string(),
+        None if path.starts_with('[') || path.starts_with('.') => path.to_string(),
+        None => format!(".{}", path),
+    };
+    let mut selectors = Vec::new();
+    while !rest.is_empty() {
+        let (descend, after) = match rest.strip_prefix("..") {
+            Some(after) => (true, after),
+            None => (false, rest.strip_prefix('.').unwrap_or(&rest)),
+        };
+        let (selector, after) = if let Some(after) = after.strip_prefix('[') {
+            let (contents, after) = bracket(after)?;
+            (parse_bracket(contents)?, after)
+        } else if let Some(after) = after.strip_prefix('*') {
+            (Selector::Wildcard, after)
+        } else {
+            let end = after
+                .find(|c: char| !is_name_char(c))
+                .unwrap_or(after.len());
+            if end == 0 {
+                return Err(format!("unexpected {} in the path", after));
+            }
+            if !rest.starts_with('.') {
+                return Err(format!("expected . or [ before {}", after));
+            }
+            (Selector::Child(after[..end].to_string()), &after[end..])
+        };
+        selectors.push(if descend {
+            Selector::Descendants(Box::new(selector))
+        } else {
+            selector
+        });
+        rest = after.to_string();
+    }
+    Ok(selectors)
+}
+
+fn index(len: usize, index: i64) -> Option<usize> {
+    let index = if index < 0 { len as i64 + index } else { index };
+    (0..len as i64).contains(&index).then_some(index as usize)
+}
+
+fn compare(value: &Value, op: &Op, literal: &Value) -> bool {
+    let ordering = match (value, literal) {
+        (Value::Number(a), Value::Number(b)) => a.as_f64().partial_cmp(&b.as_f64()),
+        (Value::String(a), Value::String(b)) => Some(a.cmp(b)),
+        _ => None,
+    };
+    match op {
+        Op::Eq => value == literal || ordering == Some(std::cmp::Ordering::Equal),
+        Op::Ne => value != literal && ordering != Some(std::cmp::Ordering::Equal),
+        Op::Lt => ordering.is_some_and(|o| o.is_lt()),
+        Op::Le => ordering.is_some_and(|o| o.is_le()),
+        Op::Gt => ordering.is_some_and(|o| o.is_gt()),
+        Op::Ge => ordering.is_some_and(|o| o.is_ge()),
+    }
+}
+
+fn children(value: &Value) -> Vec<&Value> {
+    match value {
+        Value::Array(items) => items.iter().collect(),
+        Value::Object(fields) => fields.values().collect(),
+        _ => Vec::new(),
+    }
+}
+
+fn descendants<'a>(value: &'a Value, found: &mut Vec<&'a Value>) {
+    found.push(value);
+    for child in children(value) {
+        descendants(child, found);
+    }
+}
+
+fn select<'a>(value: &'a Value, selector: &Selector, found: &mut Vec<&'a Value>) {
+    match selector {
+        Selector::Child(name) => found.extend(value.get(name)),
+        Selector::Index(i) => {
+            if let Value::Array(items) = value {
+                found.extend(index(items.len(), *i).map(|i| &items[i]));
+            }
+        }
+        Selector::Slice(start, end) => {
+            if let Value::Array(items) = value {
+                let bound = |i: i64| {
+                    let i = if i < 0 { items.len() as i64 + i } else { i };
+                    i.clamp(0, items.len() as i64) as usize
+                };
+                let start = start.map(bound).unwrap_or(0);
+                let end = end.map(bound).unwrap_or(items.len());
+                if start < end {
+                    found.extend(&items[start..end]);
+                }
+            }
+        }
+        Selector::Wildcard => found.extend(children(value)),
+        Selector::Union(selectors) => {
+            for selector in selectors {
+                select(value, selector, found);
+            }
+        }
+        Selector::Descendants(selector) => {
+            let mut nodes = Vec::new();
+            descendants(value, &mut nodes);
+            for node in nodes {
+                select(node, selector, found);
+            }
+        }
+        Selector::Filter(filter) => {
+            for child in children(value) {
+                let field = filter
+                    .path
+                    .iter()
+                    .try_fold(child, |value, key| value.get(key));
+                let keep = match (field, &filter.comparison) {
+                    (Some(field), Some((op, literal))) => compare(field, op, literal),
+                    (Some(_), None) => true,
+                    (None, _) => false,
+                };
+                if keep {
+                    found.push(child);
+                }
+            }
+        }
+    }
+}
+
+// the values the path selects, in document order for each selector
+pub(crate) fn query<'a>(value: &'a Value, path: &str) -> Result<Vec<&'a Value>, String> {
+    let selectors = parse(path).map_err(|e| format!("invalid JSONPath {}: {}", path, e))?;
+    let mut current = vec![value];
+    for selector in &selectors {
+        let mut found = Vec::new();
+        for value in current {
+            select(value, selector, &mut found);
+        }
+        current = found;
+    }
+    Ok(current)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::query;
+    use serde_json::json;
+
+    #[test]
+    fn test_query() {
+        let data = json!({
+            "source": "eurostat",
+            "data": [
+                {"country": "DE", "share": 31.2, "values": [1, 2, 3, 4]},
+                {"country": "FR", "share": 18.5, "values": [5, 6]},
+                {"country": "NO", "share": 82.4, "meta": {"share": 90}},
+            ],
+        });
+        let run = |path: &str| {
+            query(&data, path)
+                .unwrap()
+                .into_iter()
+                .cloned()
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(run("$.source"), vec![json!("eurostat")]);
+        assert_eq!(run("source"), vec![json!("eurostat")]);
+        assert_eq!(run("$.data[1].country"), vec![json!("FR")]);
+        assert_eq!(run("$.data[-1]['country']"), vec![json!("NO")]);
+        assert_eq!(
+            run("$.data[*].country"),
+            vec![json!("DE"), json!("FR"), json!("NO")]
+        );
+        assert_eq!(run("$.data[0].values[-2:]"), vec![json!(3), json!(4)]);
+        assert_eq!(run("$.data[0,2].country"), vec![json!("DE"), json!("NO")]);
+        assert_eq!(
+            run("$..share"),
+            vec![json!(31.2), json!(18.5), json!(82.4), json!(90)]
+        );
+        assert_eq!(
+            run("$.data[?(@.share > 20)].country"),
+            vec![json!("DE"), json!("NO")]
+        );
+        assert_eq!(run("$.data[?(@.country == 'FR')].share"), vec![json!(18.5)]);
+        assert_eq!(run("$.data[?(@.meta)].country"), vec![json!("NO")]);
+        assert!(run("$.data[7]").is_empty());
+
+        assert!(query(&data, "$.data[0").is_err());
+        assert!(query(&data, "$.data[x]").is_err());
+        assert!(query(&data, "$.data[?(@.share > big)]").is_err());
+    }
+}
@@ -1,14 +1,20 @@
 use crate::Result;
 use crate::llm::Message;
-use crate::tools::{FunctionalTool, Tool, ToolCall, ToolDefinition};
+use crate::tools::{
+    ChunkReader, FunctionalTool, OutlineTool, Tool, ToolCall, ToolContext, ToolDefinition,
+};
 use async_trait::async_trait;
 use schemars::JsonSchema;
 use serde::Deserialize;
 use std::collections::HashMap;
 use std::sync::{Arc, Mutex};
 
+pub(crate) type Memory = Arc<Mutex<HashMap<String, String>>>;
+
+// clones share the memory, so that the keys can be read after the agent that owns the tools ran
+#[derive(Clone)]
 pub struct KVMemoryTool {
-    memory: Arc<Mutex<HashMap<String, String>>>,
+    memory: Memory,
 }
 
 impl KVMemoryTool {
@@ -41,10 +47,16 @@ impl KVMemoryTool {
         msg
     }
 
-    fn clone(&self) -> Self {
-        Self {
-            memory: self.memory.clone(),
-        }
+    pub fn keys(&self) -> Vec<String> {
+        let mut keys = self
+            .memory
+            .lock()
+            .unwrap()
+            .keys()
+            .cloned()
+            .collect::<Vec<_>>();
+        keys.sort();
+        keys
     }
 
     fn clear(&mut self) {
@@ -59,12 +71,24 @@ impl KVMemoryTool {
         Box::new(MemoryGetTool(self.clone()))
     }
 
+    fn values(&self) -> HashMap<String, String> {
+        self.memory.lock().unwrap().clone()
+    }
+
     fn set_tool(&self) -> Box<MemorySetTool> {
         Box::new(MemorySetTool(self.clone()))
     }
 
     pub fn tools(&self) -> Result<Vec<Box<dyn Tool + Send>>> {
-        Ok(vec![self.list_tool(), self.get_tool(), self.set_tool()])
+        let mut tools: Vec<Box<dyn Tool + Send>> = vec![
+            self.list_tool(),
+            self.get_tool(),
+            self.set_tool(),
+            // long values are outlined and read in chunks like artifacts
+            OutlineTool::with_memory(self.memory.clone()),
+        ];
+        tools.extend(ChunkReader::with_memory(self.memory.clone()).tools()?);
+        Ok(tools)
     }
 }
 
@@ -73,17 +97,17 @@ struct MemoryListTool(KVMemoryTool);
 #[async_trait]
 impl FunctionalTool for MemoryListTool {
     fn definition(&self) -> Result<super::ToolDefinition> {
-        ToolDefinition::new::<()>(
+        Ok(ToolDefinition::no_args(
             "memory_list_keys",
             "This tool allows you to view the different memory keys you have saved.",
-        )
+        ))
     }
 
-    async fn invoke_fn(&mut self, call: &ToolCall) -> Result<Message> {
+    async fn invoke_fn(&mut self, call: &ToolCall, _: &ToolContext) -> Result<Message> {
         Ok(Message::Tool {
             id: call.id.clone(),
             name: "memory_list_keys".to_string(),
-            result: self.0.list_keys(),
+            result: self.0.list_keys().into(),
         })
     }
 }
@@ -105,12 +129,12 @@ impl FunctionalTool for MemoryGetTool {
         )
     }
 
-    async fn invoke_fn(&mut self, call: &ToolCall) -> Result<Message> {
+    async fn invoke_fn(&mut self, call: &ToolCall, _: &ToolContext) -> Result<Message> {
         let args: MemoryGetArgs = call.args()?;
         Ok(Message::Tool {
             id: call.id.clone(),
             name: "memory_get_key".to_string(),
-            result: self.0.get_key(&args.key),
+            result: self.0.get_key(&args.key).into(),
         })
     }
 
@@ -139,14 +163,24 @@ impl FunctionalTool for MemorySetTool {
         )
     }
 
-    async fn invoke_fn(&mut self, call: &ToolCall) -> Result<Message> {
+    async fn invoke_fn(&mut self, call: &ToolCall, _: &ToolContext) -> Result<Message> {
         let args: MemorySetArgs = call.args()?;
         Ok(Message::Tool {
             id: call.id.clone(),
             name: "memory_set_key".to_string(),
-            result: self.0.set_key(args.key, args.value),
+            result: self.0.set_key(args.key, args.value).into(),
         })
     }
+
+    fn checkpoint_fn(&self) -> Option<serde_json::Value> {
+        serde_json::to_value(self.0.values()).ok()
+    }
+
+    async fn restore_fn(&mut self, state: serde_json::Value) -> Result<()> {
+        let values: HashMap<String, String> = serde_json::from_value(state)?;
+        self.0.memory.lock().unwrap().extend(values);
+        Ok(())
+    }
 }
 
 #[cfg(test)]
@@ -154,18 +188,21 @@ mod tests {
     use super::KVMemoryTool;
     use crate::Result;
     use crate::llm::Message;
-    use crate::tools::{FunctionalTool, ToolCall};
+    use crate::tools::{FunctionalTool, ToolCall, ToolContext};
 
     async fn call_tool(tool: &mut dyn FunctionalTool, args: &str) -> Result<String> {
         match tool
-            .invoke_fn(&ToolCall {
-                id: String::new(),
-                name: String::new(),
-                args: args.to_string(),
-            })
+            .invoke_fn(
+                &ToolCall {
+                    id: String::new(),
+                    name: String::new(),
+                    args: args.to_string(),
+                },
+                &ToolContext::default(),
+            )
             .await?
         {
-            Message::Tool { result, .. } => Ok(result),
+            Message::Tool { result, .. } => Ok(result.to_string()),
             _ => panic!("not a tool message"),
         }
     }
@@ -218,6 +255,15 @@ mod tests {
             "value of key abc:\n345"
         );
 
+        // the values survive a restart from a checkpoint
+        let state = set_tool.checkpoint_fn().unwrap();
+        let restored = KVMemoryTool::new();
+        restored.set_tool().restore_fn(state).await?;
+        assert_eq!(
+            call_tool(&mut *restored.get_tool(), "{\"key\":\"xyz\"}").await?,
+            "value of key xyz:\n456"
+        );
+
         Ok(())
     }
 }
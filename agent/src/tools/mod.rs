@@ -1,33 +1,205 @@
-use crate::Result;
-use crate::llm::Message;
+use crate::artifacts::ArtifactStore;
+use crate::llm::{Message, Usage};
+use crate::workdir::RunContext;
+use crate::{Error, History, Result};
 use async_trait::async_trait;
 use schemars::{JsonSchema, schema_for};
+use std::sync::Arc;
+use tokio_util::sync::CancellationToken;
+
+#[cfg(feature = "native")]
+mod archive;
+#[cfg(feature = "native")]
+pub use archive::ArchiveTool;
+
+#[cfg(feature = "browser")]
+mod browser;
+#[cfg(feature = "browser")]
+pub use browser::BrowserTool;
+
+mod blackboard;
+pub use blackboard::{Blackboard, BlackboardNotifications, BlackboardTool, Note};
+
+mod calc;
+pub use calc::CalcTool;
+
+#[cfg(feature = "native")]
+mod confine;
+
+mod facts;
+pub use facts::{Fact, FactStore, FactsTool};
+
+#[cfg(feature = "native")]
+mod extract;
+#[cfg(feature = "native")]
+pub use extract::{Extractor, Extractors};
+
+#[cfg(feature = "native")]
+mod finance;
+#[cfg(feature = "native")]
+pub use finance::FinanceTool;
+
+#[cfg(feature = "native")]
+mod graphql;
+#[cfg(feature = "native")]
+pub use graphql::{GraphQL, GraphQLConfig, GraphQLTool};
+
+#[cfg(feature = "native")]
+mod http;
+#[cfg(feature = "native")]
+pub use http::{HttpClient, HttpClientConfig};
+
+#[cfg(feature = "native")]
+mod jsonpath;
 
 mod kv_memory;
 pub use kv_memory::KVMemoryTool;
 
+#[cfg(feature = "native")]
+mod news;
+#[cfg(feature = "native")]
+pub use news::{Article, GdeltNews, NewsApi, NewsProvider, NewsQuery, NewsTool};
+
+#[cfg(feature = "native")]
+mod openapi;
+#[cfg(feature = "native")]
+pub use openapi::{ApiAuth, OpenApi, OpenApiConfig, OpenApiTool};
+
+mod outline;
+pub use outline::OutlineTool;
+
+#[cfg(feature = "native")]
+mod quality;
+#[cfg(feature = "native")]
+pub use quality::latest_year;
+
+#[cfg(feature = "native")]
+mod region;
+#[cfg(feature = "native")]
+pub use region::Region;
+
+mod read_artifact;
+pub use read_artifact::ReadArtifactTool;
+
+mod read_chunk;
+pub use read_chunk::ChunkReader;
+
+#[cfg(feature = "native")]
+mod sandbox;
+#[cfg(feature = "native")]
+pub use sandbox::{SandboxBackend, SandboxConfig, SandboxTool};
+
+mod schema;
+
+#[cfg(feature = "native")]
+mod search;
+#[cfg(feature = "native")]
+pub use search::{
+    Reranker, SearchApi, SearchApiKind, SearchProvider, SearchQuery, SearchQuotas, SearchResult,
+    WebSearch, WebSearchTool,
+};
+
+#[cfg(feature = "native")]
+mod scholar;
+#[cfg(feature = "native")]
+pub use scholar::ScholarTool;
+
 mod summarize_history;
 pub use summarize_history::SummarizeHistory;
 
+#[cfg(feature = "native")]
+mod translate;
+#[cfg(feature = "native")]
+pub use translate::TranslateTool;
+
+#[cfg(feature = "native")]
+mod web_fetch;
+#[cfg(feature = "native")]
+pub use web_fetch::{WebAccess, WebFetchTool, WebPolicy};
+
+#[cfg(feature = "native")]
+mod youtube;
+#[cfg(feature = "native")]
+pub use youtube::YoutubeTool;
+
+mod vector_memory;
+pub use vector_memory::{MemoryRecall, SearchDocumentsTool, VectorMemory};
+
+// how far the results of a tool can be trusted, carried into the sources of a run so readers can
+// tell findings backed by authoritative data from those based on arbitrary pages. Ordered from
+// least to most trusted
+#[derive(
+    Clone,
+    Copy,
+    Debug,
+    Default,
+    PartialEq,
+    Eq,
+    PartialOrd,
+    Ord,
+    serde::Serialize,
+    serde::Deserialize,
+)]
+#[serde(rename_all = "lowercase")]
+pub enum Trust {
+    // written by a model, such as a translation or a summary
+    Generated,
+    // scraped from a webpage that anyone could have published
+    #[default]
+    Web,
+    // returned by an authoritative api or computed exactly
+    Verified,
+}
+
+impl std::fmt::Display for Trust {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Trust::Generated => "model-generated",
+            Trust::Web => "scraped web",
+            Trust::Verified => "verified API",
+        })
+    }
+}
+
 pub struct ToolDefinition {
     pub name: String,
     pub desc: String,
     pub params: serde_json::Value,
+    pub strict: bool,
 }
 
 impl ToolDefinition {
     pub fn new<P: JsonSchema>(name: &str, desc: &str) -> Result<Self> {
-        let schema = schema_for!(P);
-        let params = serde_json::to_value(&schema.schema)?;
+        let params = schema::normalize(serde_json::to_value(schema_for!(P))?);
         Ok(Self {
             name: name.to_string(),
             desc: desc.to_string(),
             params,
+            strict: false,
         })
     }
+
+    pub fn no_args(name: &str, desc: &str) -> Self {
+        Self {
+            name: name.to_string(),
+            desc: desc.to_string(),
+            params: serde_json::json!({
+                "type": "object",
+                "properties": {},
+                "additionalProperties": false,
+            }),
+            strict: false,
+        }
+    }
+
+    pub fn strict(mut self) -> Self {
+        schema::strict(&mut self.params);
+        self.strict = true;
+        self
+    }
 }
 
-#[derive(Clone, std::hash::Hash, Debug)]
+#[derive(Clone, std::hash::Hash, Debug, serde::Serialize, serde::Deserialize)]
 pub struct ToolCall {
     pub id: String,
     pub name: String,
@@ -36,8 +208,26 @@ pub struct ToolCall {
 
 impl ToolCall {
     pub fn args<O: for<'de> serde::Deserialize<'de>>(&self) -> Result<O> {
-        let args = serde_json::from_str(&self.args)?;
-        Ok(args)
+        let args = self.args.trim();
+
+        // models express "no arguments" as an empty string, {} or null, so all of these are
+        // accepted for both unit types and structs where every field has a default
+        let fallback = match args {
+            "" | "{}" => Some("null"),
+            "null" => Some("{}"),
+            _ => None,
+        };
+        let args = if args.is_empty() { "{}" } else { args };
+
+        serde_json::from_str(args)
+            .or_else(|e| match fallback {
+                Some(fallback) => serde_json::from_str(fallback).map_err(|_| e),
+                None => Err(e),
+            })
+            .map_err(|e| Error::InvalidToolArgs {
+                tool: self.name.clone(),
+                error: e.to_string(),
+            })
     }
 }
 
@@ -47,26 +237,91 @@ impl std::fmt::Display for ToolCall {
     }
 }
 
+// the reply to a call of a tool that reads a third party source. Network and api errors are
+// reported to the model, which can usually try another source
+#[cfg(feature = "native")]
+fn tool_result(call: &ToolCall, result: Result<String>) -> Message {
+    Message::Tool {
+        id: call.id.clone(),
+        name: call.name.clone(),
+        result: result.unwrap_or_else(|e| e.to_string()).into(),
+    }
+}
+
+// what a tool invocation knows of the agent running it, so that tools can attribute what they
+// record, write files of the run, count their tokens, and stop with the agent
+#[derive(Clone, Default)]
+pub struct ToolContext {
+    pub agent: String,
+    pub step: usize,
+    pub artifacts: Option<Arc<ArtifactStore>>,
+    // the working directory of the run for the files tools create
+    pub run: Option<Arc<RunContext>>,
+    pub usage: Arc<Usage>,
+    // cancelled when the run of the agent invoking the tool is aborted
+    pub cancel: CancellationToken,
+}
+
 #[async_trait]
 pub trait Tool {
     fn definition(&self) -> Result<ToolDefinition>;
 
-    async fn invoke(&mut self, args: &ToolCall, messages: Vec<Message>) -> Result<Vec<Message>>;
+    async fn invoke(
+        &mut self,
+        args: &ToolCall,
+        history: &mut dyn History,
+        ctx: &ToolContext,
+    ) -> Result<()>;
 
     async fn on_agent_start(&mut self) -> Result<()> {
         Ok(())
     }
+
+    // whether the results are content from third parties that anyone could have written, such
+    // as pages, documents, api responses, and the output of code run on them, which an agent
+    // with a sanitizer wraps in untrusted blocks
+    fn third_party(&self) -> bool {
+        false
+    }
+
+    // state saved with a checkpoint of the agent and handed back to restore when the agent
+    // resumes from it, such as the values of a memory
+    fn checkpoint(&self) -> Option<serde_json::Value> {
+        None
+    }
+
+    async fn restore(&mut self, _state: serde_json::Value) -> Result<()> {
+        Ok(())
+    }
 }
 
 #[async_trait]
-pub trait FunctionalTool {
+pub trait FunctionalTool: Send {
     fn definition(&self) -> Result<ToolDefinition>;
 
-    async fn invoke_fn(&mut self, args: &ToolCall) -> Result<Message>;
+    async fn invoke_fn(&mut self, args: &ToolCall, ctx: &ToolContext) -> Result<Message>;
+
+    // tools that produce several messages, such as an extra note for the model, override this to
+    // add them to the result of invoke_fn
+    async fn invoke_multi(&mut self, args: &ToolCall, ctx: &ToolContext) -> Result<Vec<Message>> {
+        Ok(vec![self.invoke_fn(args, ctx).await?])
+    }
 
     async fn on_agent_start_fn(&mut self) -> Result<()> {
         Ok(())
     }
+
+    fn third_party_fn(&self) -> bool {
+        false
+    }
+
+    fn checkpoint_fn(&self) -> Option<serde_json::Value> {
+        None
+    }
+
+    async fn restore_fn(&mut self, _state: serde_json::Value) -> Result<()> {
+        Ok(())
+    }
 }
 
 #[async_trait]
@@ -81,14 +336,104 @@ where
     async fn invoke(
         &mut self,
         args: &ToolCall,
-        mut messages: Vec<Message>,
-    ) -> Result<Vec<Message>> {
-        let result = self.invoke_fn(args).await?;
-        messages.push(result);
-        Ok(messages)
+        history: &mut dyn History,
+        ctx: &ToolContext,
+    ) -> Result<()> {
+        for message in self.invoke_multi(args, ctx).await? {
+            history.append(Arc::new(message));
+        }
+        Ok(())
     }
 
     async fn on_agent_start(&mut self) -> Result<()> {
         self.on_agent_start_fn().await
     }
+
+    fn third_party(&self) -> bool {
+        self.third_party_fn()
+    }
+
+    fn checkpoint(&self) -> Option<serde_json::Value> {
+        self.checkpoint_fn()
+    }
+
+    async fn restore(&mut self, state: serde_json::Value) -> Result<()> {
+        self.restore_fn(state).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{FunctionalTool, Tool, ToolCall, ToolContext, ToolDefinition};
+    use crate::Result;
+    use crate::llm::Message;
+    use async_trait::async_trait;
+
+    fn call(args: &str) -> ToolCall {
+        ToolCall {
+            id: "1".to_string(),
+            name: "tool".to_string(),
+            args: args.to_string(),
+        }
+    }
+
+    #[derive(serde::Deserialize, Debug, PartialEq)]
+    struct Defaults {
+        #[serde(default)]
+        limit: u32,
+    }
+
+    #[test]
+    fn test_empty_args() {
+        for args in ["", " ", "{}", "null"] {
+            assert!(call(args).args::<()>().is_ok(), "unit from {:?}", args);
+            assert_eq!(
+                call(args).args::<Defaults>().unwrap(),
+                Defaults { limit: 0 }
+            );
+        }
+
+        assert_eq!(
+            call("{\"limit\":3}").args::<Defaults>().unwrap(),
+            Defaults { limit: 3 }
+        );
+        assert!(call("[]").args::<()>().is_err());
+        assert!(call("{\"limit\":\"x\"}").args::<Defaults>().is_err());
+    }
+
+    struct Noted;
+
+    #[async_trait]
+    impl FunctionalTool for Noted {
+        fn definition(&self) -> Result<ToolDefinition> {
+            Ok(ToolDefinition::no_args("tool", "a tool with a note"))
+        }
+
+        async fn invoke_fn(&mut self, call: &ToolCall, _: &ToolContext) -> Result<Message> {
+            Ok(Message::Tool {
+                id: call.id.clone(),
+                name: call.name.clone(),
+                result: "result".to_string().into(),
+            })
+        }
+
+        async fn invoke_multi(
+            &mut self,
+            call: &ToolCall,
+            ctx: &ToolContext,
+        ) -> Result<Vec<Message>> {
+            let result = self.invoke_fn(call, ctx).await?;
+            Ok(vec![result, Message::User("note".to_string())])
+        }
+    }
+
+    #[tokio::test]
+    async fn test_invoke_multi() -> Result<()> {
+        let mut history = Vec::new();
+        Tool::invoke(&mut Noted, &call(""), &mut history, &ToolContext::default()).await?;
+        assert_eq!(history.len(), 2);
+        assert!(matches!(history[0].as_ref(), Message::Tool { .. }));
+        assert!(matches!(history[1].as_ref(), Message::User(note) if note == "note"));
+        Ok(())
+    }
 }
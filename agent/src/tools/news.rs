@@ -0,0 +1,628 @@
+use crate::llm::Message;
+use crate::secrets::{Secret, Secrets};
+use crate::tools::{
+    FunctionalTool, HttpClient, HttpClientConfig, Region, ToolCall, ToolContext, ToolDefinition,
+    WebAccess, calc,
+};
+use crate::{Error, Result};
+use async_trait::async_trait;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+const GDELT_URL: &str = "https://api.gdeltproject.org/api/v2/doc/doc";
+const NEWSAPI_URL: &str = "https://newsapi.org/v2/everything";
+const NEWSAPI_SOURCES_URL: &str = "https://newsapi.org/v2/top-headlines/sources";
+// the search endpoint takes at most this many sources
+const MAX_NEWSAPI_SOURCES: usize = 20;
+const MAX_ARTICLES: usize = 50;
+// the doc api of GDELT only searches the articles of the last three months
+const GDELT_DAYS: i64 = 90;
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct NewsQuery {
+    pub query: String,
+    // dates in the form YYYY-MM-DD, both ends are included
+    pub from: Option<String>,
+    pub to: Option<String>,
+    // domains such as reuters.com, if not empty only articles from these are returned
+    pub sources: Vec<String>,
+    pub limit: usize,
+    pub region: Option<Region>,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct Article {
+    pub title: String,
+    pub url: String,
+    pub source: String,
+    // the publication date as YYYY-MM-DD
+    pub published: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
+}
+
+#[async_trait]
+pub trait NewsProvider {
+    async fn search(&self, query: &NewsQuery) -> Result<Vec<Article>>;
+
+    // the earliest publication date as YYYY-MM-DD the provider can search, for providers that
+    // only cover recent news
+    fn earliest(&self) -> Option<String> {
+        None
+    }
+}
+
+fn http(config: &HttpClientConfig) -> Result<HttpClient> {
+    config.client(Duration::from_secs(30))
+}
+
+async fn get_json<T: serde::de::DeserializeOwned>(
+    http: &HttpClient,
+    url: &str,
+    params: &[(&str, String)],
+) -> Result<T> {
+    let url = reqwest::Url::parse_with_params(url, params)
+        .map_err(|e| Error::AgentWorkflowError(e.to_string()))?;
+    let res = http
+        .send(http.get(url))
+        .await
+        // the url of the error would hold the api key
+        .map_err(|e| {
+            Error::AgentWorkflowError(format!("news search failed: {}", e.without_url()))
+        })?;
+    let status = res.status();
+    if !status.is_success() {
+        return Err(Error::AgentWorkflowError(format!(
+            "news search failed with status {}",
+            status
+        )));
+    }
+    res.json()
+        .await
+        .map_err(|e| Error::AgentWorkflowError(format!("unexpected news search reply: {}", e)))
+}
+
+fn check_date(date: &str) -> std::result::Result<(), String> {
+    let parts = date.split('-').collect::<Vec<_>>();
+    let valid = matches!(parts.as_slice(), [year, month, day]
+        if year.len() == 4 && month.len() == 2 && day.len() == 2
+            && parts.iter().all(|p| p.chars().all(|c| c.is_ascii_digit())));
+    if valid {
+        Ok(())
+    } else {
+        Err(format!("{} is not a date in the form YYYY-MM-DD", date))
+    }
+}
+
+// searches the GDELT project, which covers news in many languages and needs no api key
+pub struct GdeltNews {
+    http: HttpClient,
+}
+
+impl GdeltNews {
+    pub fn new() -> Result<Arc<Self>> {
+        Self::with_http(&HttpClientConfig::default())
+    }
+
+    pub fn with_http(http: &HttpClientConfig) -> Result<Arc<Self>> {
+        Ok(Arc::new(Self {
+            http: self::http(http)?,
+        }))
+    }
+}
+
+#[derive(Deserialize)]
+struct GdeltResponse {
+    #[serde(default)]
+    articles: Vec<GdeltArticle>,
+}
+
+#[derive(Deserialize)]
+struct GdeltArticle {
+    url: String,
+    title: String,
+    // e.g. 20240315T123000Z
+    seendate: String,
+    domain: String,
+}
+
+fn gdelt_params(query: &NewsQuery) -> Vec<(&'static str, String)> {
+    let mut terms = query.query.clone();
+    match query.sources.as_slice() {
+        [] => {}
+        [source] => terms.push_str(&format!(" domain:{}", source)),
+        sources => terms.push_str(&format!(
+            " ({})",
+            sources
+                .iter()
+                .map(|source| format!("domain:{}", source))
+                .collect::<Vec<_>>()
+                .join(" OR ")
+        )),
+    }
+
+    // gdelt takes the name of the country without spaces
+    if let Some(country) = query.region.as_ref().and_then(Region::country_name) {
+        terms.push_str(&format!(" sourcecountry:{}", country.replace(' ', "")));
+    }
+
+    let mut params = vec![
+        ("query", terms),
+        ("mode", "artlist".to_string()),
+        ("format", "json".to_string()),
+        ("sort", "datedesc".to_string()),
+        ("maxrecords", query.limit.to_string()),
+    ];
+    if let Some(from) = &query.from {
+        params.push(("startdatetime", format!("{}000000", from.replace('-', ""))));
+    }
+    if let Some(to) = &query.to {
+        params.push(("enddatetime", format!("{}235959", to.replace('-', ""))));
+    }
+    params
+}
+
+#[async_trait]
+impl NewsProvider for GdeltNews {
+    async fn search(&self, query: &NewsQuery) -> Result<Vec<Article>> {
+        let res: GdeltResponse = get_json(&self.http, GDELT_URL, &gdelt_params(query)).await?;
+        Ok(res
+            .articles
+            .into_iter()
+            .map(|article| Article {
+                title: article.title,
+                url: article.url,
+                source: article.domain,
+                published: match (
+                    article.seendate.get(..4),
+                    article.seendate.get(4..6),
+                    article.seendate.get(6..8),
+                ) {
+                    (Some(year), Some(month), Some(day)) => format!("{}-{}-{}", year, month, day),
+                    _ => article.seendate,
+                },
+                description: None,
+            })
+            .collect())
+    }
+
+    fn earliest(&self) -> Option<String> {
+        Some(calc::days_before_today(GDELT_DAYS))
+    }
+}
+
+// searches NewsAPI with the key of the NEWSAPI_API_KEY credential
+pub struct NewsApi {
+    http: HttpClient,
+    api_key: Secret,
+    // the ids of the outlets of every country searched so far
+    country_sources: Mutex<HashMap<String, Vec<String>>>,
+}
+
+impl NewsApi {
+    pub fn new() -> Result<Arc<Self>> {
+        Self::with_http(&HttpClientConfig::default(), &Secrets::new())
+    }
+
+    // fails if the credential is not set
+    pub fn with_http(http: &HttpClientConfig, secrets: &Secrets) -> Result<Arc<Self>> {
+        Ok(Arc::new(Self {
+            http: self::http(http)?,
+            api_key: secrets.get("NEWSAPI_API_KEY")?,
+            country_sources: Mutex::new(HashMap::new()),
+        }))
+    }
+
+    // the search endpoint has no country filter, so searches are restricted to the outlets
+    // NewsAPI lists for the country, none for countries it has no outlets of
+    async fn country_sources(&self, region: &Region) -> Result<Vec<String>> {
+        if let Some(sources) = self.country_sources.lock().unwrap().get(&region.country) {
+            return Ok(sources.clone());
+        }
+        let params = [
+            ("country", region.country.clone()),
+            ("apiKey", self.api_key.expose().to_string()),
+        ];
+        let res: NewsApiSources = get_json(&self.http, NEWSAPI_SOURCES_URL, &params).await?;
+        let sources = res
+            .sources
+            .into_iter()
+            .map(|source| source.id)
+            .take(MAX_NEWSAPI_SOURCES)
+            .collect::<Vec<_>>();
+        self.country_sources
+            .lock()
+            .unwrap()
+            .insert(region.country.clone(), sources.clone());
+        Ok(sources)
+    }
+}
+
+#[derive(Deserialize)]
+struct NewsApiSources {
+    #[serde(default)]
+    sources: Vec<NewsApiSourceId>,
+}
+
+#[derive(Deserialize)]
+struct NewsApiSourceId {
+    id: String,
+}
+
+#[derive(Deserialize)]
+struct NewsApiResponse {
+    #[serde(default)]
+    articles: Vec<NewsApiArticle>,
+}
+
+#[derive(Deserialize)]
+struct NewsApiSource {
+    name: String,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct NewsApiArticle {
+    source: NewsApiSource,
+    title: String,
+    url: String,
+    // e.g. 2024-03-15T12:30:00Z
+    published_at: String,
+    description: Option<String>,
+}
+
+#[async_trait]
+impl NewsProvider for NewsApi {
+    async fn search(&self, query: &NewsQuery) -> Result<Vec<Article>> {
+        let mut params = vec![
+            ("q", query.query.clone()),
+            ("sortBy", "publishedAt".to_string()),
+            ("pageSize", query.limit.to_string()),
+            ("apiKey", self.api_key.expose().to_string()),
+        ];
+        if let Some(from) = &query.from {
+            params.push(("from", from.clone()));
+        }
+        if let Some(to) = &query.to {
+            params.push(("to", to.clone()));
+        }
+        if !query.sources.is_empty() {
+            params.push(("domains", query.sources.join(",")));
+        }
+        if let Some(region) = &query.region {
+            // outlets the agent asked for are searched whatever their country
+            if query.sources.is_empty() {
+                let sources = self.country_sources(region).await?;
+                if !sources.is_empty() {
+                    params.push(("sources", sources.join(",")));
+                }
+            }
+            if let Some(language) = &region.language {
+                params.push(("language", language.clone()));
+            }
+        }
+
+        let res: NewsApiResponse = get_json(&self.http, NEWSAPI_URL, &params).await?;
+        Ok(res
+            .articles
+            .into_iter()
+            .map(|article| Article {
+                title: article.title,
+                url: article.url,
+                source: article.source.name,
+                published: article
+                    .published_at
+                    .get(..10)
+                    .unwrap_or(&article.published_at)
+                    .to_string(),
+                description: article.description,
+            })
+            .collect())
+    }
+}
+
+#[derive(Deserialize, JsonSchema)]
+struct NewsSearchArgs {
+    /// the search terms
+    query: String,
+    /// the earliest publication date as YYYY-MM-DD
+    #[serde(default)]
+    from: Option<String>,
+    /// the latest publication date as YYYY-MM-DD
+    #[serde(default)]
+    to: Option<String>,
+    /// domains of news outlets such as reuters.com to restrict the search to
+    #[serde(default)]
+    sources: Vec<String>,
+    /// the maximum number of articles to return, at most 50
+    #[serde(default)]
+    limit: Option<usize>,
+}
+
+// searches news articles by publication date, articles from domains the web access policy does
+// not allow are left out
+pub struct NewsTool {
+    provider: Arc<dyn NewsProvider + Send + Sync>,
+    access: Arc<WebAccess>,
+    // the earliest publication date the research accepts, as YYYY-MM-DD
+    since: Option<String>,
+    region: Option<Region>,
+}
+
+impl NewsTool {
+    pub fn new(provider: Arc<dyn NewsProvider + Send + Sync>, access: Arc<WebAccess>) -> Box<Self> {
+        Self::with_scope(provider, access, None, None)
+    }
+
+    // searches only articles published since the date, whatever range the model asks for, and
+    // articles from the region
+    pub fn with_scope(
+        provider: Arc<dyn NewsProvider + Send + Sync>,
+        access: Arc<WebAccess>,
+        since: Option<String>,
+        region: Option<Region>,
+    ) -> Box<Self> {
+        Box::new(Self {
+            provider,
+            access,
+            since,
+            region,
+        })
+    }
+
+    async fn search(&self, args: NewsSearchArgs) -> Result<String> {
+        for date in args.from.iter().chain(&args.to) {
+            if let Err(reason) = check_date(date) {
+                return Ok(reason);
+            }
+        }
+        // dates in the form YYYY-MM-DD compare like strings
+        let mut from = match (args.from, &self.since) {
+            (Some(from), Some(since)) if from < *since => Some(since.clone()),
+            (None, Some(since)) => Some(since.clone()),
+            (from, _) => from,
+        };
+        if let (Some(to), Some(since)) = (&args.to, &self.since)
+            && to < since
+        {
+            return Ok(format!(
+                "This research only uses sources published since {}, search for a later period",
+                since
+            ));
+        }
+        // searches reaching back further than the provider covers are told so, instead of
+        // returning only the recent part of the period
+        let mut note = None;
+        if let Some(earliest) = self.provider.earliest() {
+            if args.to.as_ref().is_some_and(|to| *to < earliest) {
+                return Ok(format!(
+                    "The news search only covers articles published since {}, search older news with web search",
+                    earliest
+                ));
+            }
+            if from.as_ref().is_none_or(|from| *from < earliest) {
+                note = Some(format!(
+                    "Only articles published since {} were searched, search older news with web search.",
+                    earliest
+                ));
+                from = Some(earliest);
+            }
+        }
+        let query = NewsQuery {
+            query: args.query,
+            from,
+            to: args.to,
+            sources: args.sources,
+            limit: args.limit.unwrap_or(20).clamp(1, MAX_ARTICLES),
+            region: self.region.clone(),
+        };
+
+        let mut articles = self
+            .provider
+            .search(&query)
+            .await?
+            .into_iter()
+            .filter(|article| {
+                reqwest::Url::parse(&article.url).is_ok_and(|url| self.access.allows(&url).is_ok())
+            })
+            .collect::<Vec<_>>();
+        if let Some(region) = &self.region {
+            region.sort(&mut articles, |article| &article.url);
+        }
+        if articles.is_empty() {
+            let none = format!("No news articles found for {}", query.query);
+            return Ok(match note {
+                Some(note) => format!("{}. {}", none, note),
+                None => none,
+            });
+        }
+        let articles = articles
+            .iter()
+            .map(|article| {
+                let mut line = format!(
+                    "- {} ({}, {})\n  {}",
+                    article.title, article.source, article.published, article.url
+                );
+                if let Some(description) = &article.description {
+                    line.push_str(&format!("\n  {}", description));
+                }
+                line
+            })
+            .collect::<Vec<_>>()
+            .join("\n");
+        Ok(match note {
+            Some(note) => format!("{}\n\n{}", note, articles),
+            None => articles,
+        })
+    }
+}
+
+#[async_trait]
+impl FunctionalTool for NewsTool {
+    fn definition(&self) -> Result<ToolDefinition> {
+        ToolDefinition::new::<NewsSearchArgs>(
+            "news_search",
+            "This tool searches news articles published within a date range, optionally from specific outlets, and returns their titles, outlets, publication dates, and urls, newest first. Use it instead of web search for questions about events in a specific period, then read the most relevant articles with web_fetch.",
+        )
+    }
+
+    fn third_party_fn(&self) -> bool {
+        true
+    }
+
+    async fn invoke_fn(&mut self, call: &ToolCall, _: &ToolContext) -> Result<Message> {
+        let args: NewsSearchArgs = call.args()?;
+        let result = match self.search(args).await {
+            Ok(result) => result,
+            // failed searches are reported to the model, which can fall back to web search
+            Err(e) => e.to_string(),
+        };
+
+        Ok(Message::Tool {
+            id: call.id.clone(),
+            name: "news_search".to_string(),
+            result: result.into(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        Article, NewsProvider, NewsQuery, NewsSearchArgs, NewsTool, check_date, gdelt_params,
+    };
+    use crate::Result;
+    use crate::tools::Region;
+    use crate::tools::{WebAccess, WebPolicy};
+    use async_trait::async_trait;
+    use std::sync::{Arc, Mutex};
+
+    // the last query it was asked, and the earliest date it covers
+    #[derive(Default)]
+    struct FixedNews(Mutex<Option<NewsQuery>>, Option<String>);
+
+    #[async_trait]
+    impl NewsProvider for FixedNews {
+        async fn search(&self, query: &NewsQuery) -> Result<Vec<Article>> {
+            *self.0.lock().unwrap() = Some(query.clone());
+            Ok(["https://reuters.com/a", "https://blocked.com/b"]
+                .iter()
+                .map(|url| Article {
+                    title: "Title".to_string(),
+                    url: url.to_string(),
+                    source: "Outlet".to_string(),
+                    published: "2024-03-15".to_string(),
+                    description: None,
+                })
+                .collect())
+        }
+
+        fn earliest(&self) -> Option<String> {
+            self.1.clone()
+        }
+    }
+
+    #[tokio::test]
+    async fn test_news_tool() -> Result<()> {
+        let provider = Arc::new(FixedNews::default());
+        let tool = NewsTool::new(
+            provider.clone(),
+            WebAccess::new(WebPolicy {
+                blocked_domains: vec!["blocked.com".to_string()],
+                ..Default::default()
+            }),
+        );
+        let args = |from: &str| NewsSearchArgs {
+            query: "election".to_string(),
+            from: Some(from.to_string()),
+            to: None,
+            sources: vec![],
+            limit: Some(500),
+        };
+
+        let result = tool.search(args("2024-03-01")).await?;
+        assert_eq!(
+            result,
+            "- Title (Outlet, 2024-03-15)\n  https://reuters.com/a"
+        );
+        assert_eq!(provider.0.lock().unwrap().as_ref().unwrap().limit, 50);
+
+        assert!(
+            tool.search(args("March 2024"))
+                .await?
+                .contains("YYYY-MM-DD")
+        );
+
+        // the cutoff of the research moves earlier dates up
+        let tool = NewsTool::with_scope(
+            provider.clone(),
+            WebAccess::new(Default::default()),
+            Some("2024-03-10".to_string()),
+            None,
+        );
+        tool.search(args("2024-01-01")).await?;
+        let from = |provider: &FixedNews| provider.0.lock().unwrap().as_ref().unwrap().from.clone();
+        assert_eq!(from(&provider).as_deref(), Some("2024-03-10"));
+        tool.search(args("2024-04-01")).await?;
+        assert_eq!(from(&provider).as_deref(), Some("2024-04-01"));
+        let too_early = NewsSearchArgs {
+            to: Some("2024-02-01".to_string()),
+            ..args("2024-01-01")
+        };
+        assert!(tool.search(too_early).await?.contains("since 2024-03-10"));
+
+        // searches before the coverage of the provider are moved up and told so
+        let provider = Arc::new(FixedNews(Mutex::default(), Some("2024-02-01".to_string())));
+        let tool = NewsTool::new(provider.clone(), WebAccess::new(Default::default()));
+        let result = tool.search(args("2023-06-01")).await?;
+        assert!(
+            result.starts_with("Only articles published since 2024-02-01 were searched"),
+            "{}",
+            result
+        );
+        assert_eq!(from(&provider).as_deref(), Some("2024-02-01"));
+        assert!(
+            tool.search(args("2024-03-01"))
+                .await?
+                .starts_with("- Title")
+        );
+        let too_early = NewsSearchArgs {
+            to: Some("2023-12-31".to_string()),
+            ..args("2023-06-01")
+        };
+        assert!(tool.search(too_early).await?.contains("search older news"));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_gdelt_params() {
+        assert!(check_date("2024-03-15").is_ok());
+        assert!(check_date("2024-3-15").is_err());
+
+        let params = gdelt_params(&NewsQuery {
+            query: "election".to_string(),
+            from: Some("2024-03-01".to_string()),
+            to: Some("2024-05-31".to_string()),
+            sources: vec!["reuters.com".to_string(), "apnews.com".to_string()],
+            limit: 10,
+            region: Some(Region::new("gb")),
+        });
+        let param = |name: &str| {
+            params
+                .iter()
+                .find(|(key, _)| *key == name)
+                .map(|(_, value)| value.as_str())
+        };
+        assert_eq!(
+            param("query"),
+            Some("election (domain:reuters.com OR domain:apnews.com) sourcecountry:unitedkingdom")
+        );
+        assert_eq!(param("startdatetime"), Some("20240301000000"));
+        assert_eq!(param("enddatetime"), Some("20240531235959"));
+        assert_eq!(param("maxrecords"), Some("10"));
+    }
+}
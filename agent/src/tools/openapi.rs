@@ -0,0 +1,935 @@
+use crate::llm::Message;
+use crate::secrets::{Secret, Secrets};
+use crate::tools::web_fetch::{Format, MAX_DATA_CHARS};
+use crate::tools::{
+    FunctionalTool, HttpClient, HttpClientConfig, Tool, ToolCall, ToolContext, ToolDefinition,
+    Trust, WebAccess, schema, tool_result,
+};
+use crate::{Error, Result};
+use async_trait::async_trait;
+use serde::Deserialize;
+use serde_json::{Map, Value, json};
+use std::path::Path;
+use std::sync::Arc;
+use std::time::Duration;
+
+const METHODS: &[&str] = &["get", "put", "post", "delete", "patch", "head", "options"];
+// the limit of tool names of the openai api
+const MAX_NAME_CHARS: usize = 64;
+const MAX_DESCRIPTION_CHARS: usize = 1000;
+const MAX_ERROR_CHARS: usize = 1000;
+// the argument that selects parts of a json response, unless the operation has a parameter of
+// the same name
+const SELECT_ARG: &str = "jsonpath";
+// the argument that continues a long response, unless the operation has a parameter of the same
+// name
+const OFFSET_ARG: &str = "response_offset";
+
+/// An api described by an OpenAPI 3 spec in JSON, whose operations are given to agents as tools.
+#[derive(Clone, Debug, Default, Deserialize)]
+pub struct OpenApiConfig {
+    /// prefix of the tool names, e.g. fred for the tool fred_getSeries
+    pub name: String,
+    /// path or http url of the spec
+    pub spec: String,
+    /// url the operations are called on, defaults to the first server of the spec
+    #[serde(default)]
+    pub base_url: Option<String>,
+    /// ids of the operations to expose; without them every GET operation is exposed, operations
+    /// with other methods only when they are listed
+    #[serde(default)]
+    pub operations: Vec<String>,
+    /// credentials sent with every request
+    #[serde(default)]
+    pub auth: Option<ApiAuth>,
+}
+
+/// Credentials of an api, referring to a credential of the secrets by name so that configs can
+/// be shared.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(tag = "in", rename_all = "lowercase")]
+pub enum ApiAuth {
+    /// a header such as Authorization, with a prefix such as "Bearer " before the secret
+    Header {
+        name: String,
+        secret: String,
+        #[serde(default)]
+        prefix: String,
+    },
+    /// a query parameter such as api_key
+    Query { name: String, secret: String },
+}
+
+impl OpenApiConfig {
+    /// Reads a config from a JSON file, a relative spec path is read from the directory of the
+    /// file.
+    pub fn read(path: &Path) -> Result<Self> {
+        let mut config: Self = serde_json::from_slice(&std::fs::read(path)?)?;
+        if !is_url(&config.spec)
+            && let Some(dir) = path.parent()
+        {
+            config.spec = dir.join(&config.spec).to_string_lossy().into_owned();
+        }
+        Ok(config)
+    }
+}
+
+impl ApiAuth {
+    // looks up the secret the auth refers to
+    pub(crate) fn resolve(&self, secrets: &Secrets) -> Result<Secret> {
+        let (ApiAuth::Header { secret, .. } | ApiAuth::Query { secret, .. }) = self;
+        secrets.get(secret)
+    }
+
+    // the value of a header auth, marked as sensitive so that the http cache only stores a hash
+    // of it
+    pub(crate) fn header_value(&self, secret: &Secret) -> Option<reqwest::header::HeaderValue> {
+        let ApiAuth::Header { prefix, .. } = self else {
+            return None;
+        };
+        let mut value =
+            reqwest::header::HeaderValue::from_str(&format!("{}{}", prefix, secret.expose()))
+                .ok()?;
+        value.set_sensitive(true);
+        Some(value)
+    }
+}
+
+fn is_url(spec: &str) -> bool {
+    spec.starts_with("http://") || spec.starts_with("https://")
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Location {
+    Path,
+    Query,
+    Header,
+}
+
+#[derive(Debug)]
+struct Param {
+    name: String,
+    location: Location,
+    required: bool,
+}
+
+#[derive(Debug)]
+struct Operation {
+    tool: String,
+    description: String,
+    method: reqwest::Method,
+    path: String,
+    params: Vec<Param>,
+    // whether the arguments carry a json request body
+    body: bool,
+    schema: Value,
+}
+
+#[derive(Debug)]
+struct Api {
+    name: String,
+    base_url: reqwest::Url,
+    auth: Option<(ApiAuth, Secret)>,
+    operations: Vec<Operation>,
+}
+
+/// The operations of an api read from its OpenAPI spec, cheap to clone so that every agent gets
+/// its own tools with OpenApiTool.
+#[derive(Clone, Debug)]
+pub struct OpenApi(Arc<Api>);
+
+// the value at a reference to another part of the spec, or the value itself
+fn resolve<'a>(spec: &'a Value, value: &'a Value) -> &'a Value {
+    let mut value = value;
+    // references to references are followed a few times, longer chains are most likely cycles
+    for _ in 0..8 {
+        match value
+            .get("$ref")
+            .and_then(Value::as_str)
+            .and_then(|r| r.strip_prefix('#'))
+            .and_then(|pointer| spec.pointer(pointer))
+        {
+            Some(target) => value = target,
+            None => break,
+        }
+    }
+    value
+}
+
+fn text(value: &Value, key: &str) -> String {
+    value
+        .get(key)
+        .and_then(Value::as_str)
+        .unwrap_or_default()
+        .trim()
+        .to_string()
+}
+
+fn truncate(text: &str, limit: usize) -> String {
+    match text.char_indices().nth(limit) {
+        Some((end, _)) => format!("{}...", &text[..end]),
+        None => text.to_string(),
+    }
+}
+
+// tool names may only hold letters, digits, underscores, and dashes
+fn tool_name(api: &str, operation: &str) -> String {
+    format!("{}_{}", api, operation)
+        .chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() || c == '_' || c == '-' {
+                c
+            } else {
+                '_'
+            }
+        })
+        .take(MAX_NAME_CHARS)
+        .collect()
+}
+
+// the first server of the spec with its variables set to their defaults
+fn server(spec: &Value, spec_url: Option<&reqwest::Url>) -> Result<reqwest::Url> {
+    let server = spec
+        .get("servers")
+        .and_then(|servers| servers.get(0))
+        .ok_or_else(|| {
+            Error::MissingArg("the OpenAPI spec has no servers, set a base url".to_string())
+        })?;
+    let mut url = text(server, "url");
+    if let Some(Value::Object(variables)) = server.get("variables") {
+        for (name, variable) in variables {
+            url = url.replace(&format!("{{{}}}", name), &text(variable, "default"));
+        }
+    }
+    let parsed = match spec_url {
+        Some(spec_url) => spec_url.join(&url),
+        None => reqwest::Url::parse(&url),
+    };
+    parsed.map_err(|e| {
+        Error::MissingArg(format!(
+            "the server {} of the OpenAPI spec is not a valid url, set a base url: {}",
+            url, e
+        ))
+    })
+}
+
+fn operation(
+    spec: &Value,
+    config: &OpenApiConfig,
+    path: &str,
+    method: &str,
+    item: &Value,
+    value: &Value,
+) -> Option<Operation> {
+    let id = text(value, "operationId");
+    let listed = config.operations.contains(&id);
+    if !listed && (!config.operations.is_empty() || method != "get") {
+        return None;
+    }
+    let id = if id.is_empty() {
+        format!("{}{}", method, path.replace(['{', '}'], ""))
+    } else {
+        id
+    };
+
+    // the parameters of the operation replace those of its path with the same name
+    let mut params: Vec<(Param, Value)> = Vec::new();
+    let declared = [item, value].into_iter().flat_map(|owner| {
+        owner
+            .get("parameters")
+            .and_then(Value::as_array)
+            .into_iter()
+            .flatten()
+    });
+    for param in declared {
+        let param = resolve(spec, param);
+        let location = match param.get("in").and_then(Value::as_str) {
+            Some("path") => Location::Path,
+            Some("query") => Location::Query,
+            Some("header") => Location::Header,
+            _ => continue,
+        };
+        let name = text(param, "name");
+        if name.is_empty() {
+            continue;
+        }
+        let mut property = param
+            .get("schema")
+            .map(|schema| schema::inline_refs(resolve(spec, schema).clone(), spec))
+            .unwrap_or_else(|| json!({"type": "string"}));
+        let description = text(param, "description");
+        if !description.is_empty()
+            && let Value::Object(property) = &mut property
+        {
+            property.insert(
+                "description".to_string(),
+                Value::String(truncate(&description, MAX_DESCRIPTION_CHARS)),
+            );
+        }
+        let param = Param {
+            required: location == Location::Path
+                || param.get("required").and_then(Value::as_bool) == Some(true),
+            name,
+            location,
+        };
+        params.retain(|(other, _)| other.name != param.name);
+        params.push((param, property));
+    }
+
+    let body = value.get("requestBody").map(|body| resolve(spec, body));
+    let body_schema = body
+        .and_then(|body| body.pointer("/content/application~1json/schema"))
+        .map(|schema| schema::inline_refs(resolve(spec, schema).clone(), spec));
+
+    let mut properties = Map::new();
+    let mut required = Vec::new();
+    for (param, property) in &params {
+        if param.required {
+            required.push(Value::String(param.name.clone()));
+        }
+        properties.insert(param.name.clone(), property.clone());
+    }
+    if let Some(schema) = &body_schema {
+        if body
+            .and_then(|body| body.get("required"))
+            .and_then(Value::as_bool)
+            == Some(true)
+        {
+            required.push(Value::String("body".to_string()));
+        }
+        properties.insert("body".to_string(), schema.clone());
+    }
+    if !properties.contains_key(SELECT_ARG) {
+        properties.insert(
+            SELECT_ARG.to_string(),
+            json!({
+                "type": "string",
+                "description": "a JSONPath selecting parts of a JSON response, e.g. $.items[*].name; leave empty for the whole response",
+            }),
+        );
+    }
+    if !properties.contains_key(OFFSET_ARG) {
+        properties.insert(
+            OFFSET_ARG.to_string(),
+            json!({
+                "type": "integer",
+                "description": "the character offset to continue a long response from, as given at the end of the previous part",
+            }),
+        );
+    }
+
+    let summary = [text(value, "summary"), text(value, "description")]
+        .into_iter()
+        .filter(|text| !text.is_empty())
+        .collect::<Vec<_>>()
+        .join(" ");
+    let description = format!(
+        "{}{}This tool calls {} {} of the {} API.",
+        truncate(&summary, MAX_DESCRIPTION_CHARS),
+        if summary.is_empty() { "" } else { " " },
+        method.to_ascii_uppercase(),
+        path,
+        config.name
+    );
+    Some(Operation {
+        tool: tool_name(&config.name, &id),
+        description,
+        method: reqwest::Method::from_bytes(method.to_ascii_uppercase().as_bytes()).ok()?,
+        path: path.to_string(),
+        params: params.into_iter().map(|(param, _)| param).collect(),
+        body: body_schema.is_some(),
+        schema: json!({
+            "type": "object",
+            "properties": properties,
+            "required": required,
+        }),
+    })
+}
+
+impl OpenApi {
+    /// Reads the spec of the config from its path or url, the url is fetched with the http
+    /// settings of the web tools.
+    pub async fn load(
+        config: OpenApiConfig,
+        http: &HttpClientConfig,
+        secrets: &Secrets,
+    ) -> Result<Self> {
+        if !is_url(&config.spec) {
+            let spec = serde_json::from_slice(&std::fs::read(&config.spec)?)?;
+            return Self::from_spec(config, &spec, None, secrets);
+        }
+        let url = reqwest::Url::parse(&config.spec)
+            .map_err(|e| Error::MissingArg(format!("{} is not a valid url: {}", config.spec, e)))?;
+        let http = http.client(Duration::from_secs(30))?;
+        let res = http
+            .send(http.get(url.clone()))
+            .await
+            .and_then(|res| res.error_for_status())
+            .map_err(|e| Error::AgentWorkflowError(format!("failed to fetch {}: {}", url, e)))?;
+        let spec = res
+            .json::<Value>()
+            .await
+            .map_err(|e| Error::AgentWorkflowError(format!("failed to read {}: {}", url, e)))?;
+        Self::from_spec(config, &spec, Some(&url), secrets)
+    }
+
+    /// The operations of the spec selected by the config, fails if an operation it lists does
+    /// not exist or the secret of its auth is not set.
+    pub fn from_spec(
+        config: OpenApiConfig,
+        spec: &Value,
+        spec_url: Option<&reqwest::Url>,
+        secrets: &Secrets,
+    ) -> Result<Self> {
+        let base_url = match &config.base_url {
+            Some(url) => reqwest::Url::parse(url)
+                .map_err(|e| Error::MissingArg(format!("{} is not a valid url: {}", url, e)))?,
+            None => server(spec, spec_url)?,
+        };
+        let auth = match &config.auth {
+            Some(auth) => Some((auth.clone(), auth.resolve(secrets)?)),
+            None => None,
+        };
+
+        let mut operations = Vec::new();
+        if let Some(Value::Object(paths)) = spec.get("paths") {
+            for (path, item) in paths {
+                let item = resolve(spec, item);
+                for method in METHODS {
+                    if let Some(value) = item.get(*method)
+                        && let Some(operation) = operation(spec, &config, path, method, item, value)
+                    {
+                        operations.push(operation);
+                    }
+                }
+            }
+        }
+        for id in &config.operations {
+            let tool = tool_name(&config.name, id);
+            if !operations.iter().any(|operation| operation.tool == tool) {
+                return Err(Error::MissingArg(format!(
+                    "the OpenAPI spec of the {} API has no operation {}",
+                    config.name, id
+                )));
+            }
+        }
+        if operations.is_empty() {
+            return Err(Error::MissingArg(format!(
+                "the OpenAPI spec of the {} API has no GET operations, list the operations to expose",
+                config.name
+            )));
+        }
+
+        Ok(Self(Arc::new(Api {
+            name: config.name,
+            base_url,
+            auth,
+            operations,
+        })))
+    }
+
+    pub fn name(&self) -> &str {
+        &self.0.name
+    }
+
+    /// The names of the tools of the operations.
+    pub fn tool_names(&self) -> Vec<&str> {
+        self.0
+            .operations
+            .iter()
+            .map(|operation| operation.tool.as_str())
+            .collect()
+    }
+}
+
+// arguments as they appear in urls and headers, arrays as comma separated lists
+// the argument percent-encoded as one path segment, urls resolve . and .. even when encoded so
+// they are refused
+fn path_segment(text: &str) -> std::result::Result<String, String> {
+    if text == "." || text == ".." {
+        return Err(format!("{} is not a valid path argument", text));
+    }
+    Ok(text
+        .bytes()
+        .map(|b| match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' => {
+                (b as char).to_string()
+            }
+            b => format!("%{:02X}", b),
+        })
+        .collect())
+}
+
+fn arg_text(value: &Value) -> String {
+    match value {
+        Value::String(text) => text.clone(),
+        Value::Array(items) => items.iter().map(arg_text).collect::<Vec<_>>().join(","),
+        other => other.to_string(),
+    }
+}
+
+// calls the operations of an api configured by the user, whose answers are treated as
+// authoritative data like those of the other api tools
+#[derive(Clone)]
+pub struct OpenApiTool {
+    api: OpenApi,
+    access: Arc<WebAccess>,
+    http: HttpClient,
+}
+
+impl OpenApiTool {
+    pub fn new(api: OpenApi, access: Arc<WebAccess>) -> Result<Box<Self>> {
+        Ok(Box::new(Self {
+            api,
+            http: access.policy().http.client(Duration::from_secs(60))?,
+            access,
+        }))
+    }
+
+    pub fn tools(&self) -> Result<Vec<Box<dyn Tool + Send>>> {
+        Ok((0..self.api.0.operations.len())
+            .map(|index| -> Box<dyn Tool + Send> { Box::new(OperationTool(self.clone(), index)) })
+            .collect())
+    }
+
+    // the url of the call and the request, the url has no secrets so it can be cited
+    fn request(
+        &self,
+        operation: &Operation,
+        args: &Map<String, Value>,
+    ) -> std::result::Result<(reqwest::Url, reqwest::RequestBuilder), String> {
+        for param in &operation.params {
+            if param.required && args.get(&param.name).is_none_or(Value::is_null) {
+                return Err(format!("the argument {} is required", param.name));
+            }
+        }
+        let arg = |param: &Param| args.get(&param.name).filter(|value| !value.is_null());
+
+        let mut url = self.api.0.base_url.clone();
+        // the arguments are substituted into the segments of the template, so that a slash or
+        // dots in an argument cannot add segments or lead to another path
+        let segments = operation
+            .path
+            .split('/')
+            .filter(|segment| !segment.is_empty())
+            .map(|segment| {
+                let mut segment = segment.to_string();
+                for param in &operation.params {
+                    if param.location == Location::Path
+                        && let Some(value) = arg(param)
+                    {
+                        segment = segment.replace(
+                            &format!("{{{}}}", param.name),
+                            &path_segment(&arg_text(value))?,
+                        );
+                    }
+                }
+                Ok(segment)
+            })
+            .collect::<std::result::Result<Vec<_>, String>>()?;
+        // the segments are already encoded, which set_path keeps as they are
+        let path = format!(
+            "{}/{}",
+            url.path().trim_end_matches('/'),
+            segments.join("/")
+        );
+        url.set_path(&path);
+        for param in &operation.params {
+            if param.location == Location::Query
+                && let Some(value) = arg(param)
+            {
+                match value {
+                    Value::Array(items) => {
+                        for item in items {
+                            url.query_pairs_mut()
+                                .append_pair(&param.name, &arg_text(item));
+                        }
+                    }
+                    value => {
+                        url.query_pairs_mut()
+                            .append_pair(&param.name, &arg_text(value));
+                    }
+                }
+            }
+        }
+        if url.query() == Some("") {
+            url.set_query(None);
+        }
+
+        let mut target = url.clone();
+        let mut header_auth = None;
+        match &self.api.0.auth {
+            Some((ApiAuth::Query { name, .. }, secret)) => {
+                target.query_pairs_mut().append_pair(name, secret.expose());
+            }
+            Some((auth @ ApiAuth::Header { name, .. }, secret)) => {
+                let value = auth.header_value(secret).ok_or_else(|| {
+                    format!(
+                        "the credentials of the {} API are not a valid header",
+                        self.api.0.name
+                    )
+                })?;
+                header_auth = Some((name.clone(), value));
+            }
+            None => {}
+        }
+
+        let mut request = self.http.request(operation.method.clone(), target);
+        for param in &operation.params {
+            if param.location == Location::Header
+                && let Some(value) = arg(param)
+            {
+                request = request.header(&param.name, arg_text(value));
+            }
+        }
+        if let Some((name, value)) = header_auth {
+            request = request.header(name, value);
+        }
+        // the cache would store the secret with the url
+        if matches!(self.api.0.auth, Some((ApiAuth::Query { .. }, _))) {
+            request = request.header(reqwest::header::CACHE_CONTROL, "no-store");
+        }
+        if operation.body
+            && let Some(body) = args.get("body").filter(|body| !body.is_null())
+        {
+            request = request.json(body);
+        }
+        Ok((url, request))
+    }
+
+    async fn call(&self, operation: &Operation, args: &Map<String, Value>) -> Result<String> {
+        let (url, request) = match self.request(operation, args) {
+            Ok(request) => request,
+            Err(reason) => return Ok(reason),
+        };
+        if let Err(reason) = self.access.check(&url) {
+            return Ok(reason);
+        }
+        let res = self.http.send(request).await.map_err(|e| {
+            // the url of the error would hold the secret of a query auth
+            Error::AgentWorkflowError(format!("failed to call {}: {}", url, e.without_url()))
+        })?;
+        let status = res.status();
+        let content_type = res
+            .headers()
+            .get(reqwest::header::CONTENT_TYPE)
+            .and_then(|value| value.to_str().ok())
+            .unwrap_or_default()
+            .to_ascii_lowercase();
+        let body = res.bytes().await.map_err(|e| {
+            Error::AgentWorkflowError(format!("failed to read {}: {}", url, e.without_url()))
+        })?;
+        let text = String::from_utf8_lossy(&body);
+        if !status.is_success() {
+            return Ok(format!(
+                "the {} API replied with status {}: {}",
+                self.api.0.name,
+                status,
+                truncate(text.trim(), MAX_ERROR_CHARS)
+            ));
+        }
+        let select = if operation
+            .params
+            .iter()
+            .any(|param| param.name == SELECT_ARG)
+        {
+            None
+        } else {
+            args.get(SELECT_ARG).and_then(Value::as_str)
+        };
+        let offset = if operation
+            .params
+            .iter()
+            .any(|param| param.name == OFFSET_ARG)
+        {
+            0
+        } else {
+            args.get(OFFSET_ARG)
+                .and_then(Value::as_u64)
+                .unwrap_or_default() as usize
+        };
+        let result = match Format::detect(&content_type, &body) {
+            Some(format) => format.render(&text, select, offset, OFFSET_ARG),
+            None => truncate(text.trim(), MAX_DATA_CHARS),
+        };
+        self.access.record(url.as_str(), &result, Trust::Verified);
+        Ok(result)
+    }
+}
+
+struct OperationTool(OpenApiTool, usize);
+
+impl OperationTool {
+    fn operation(&self) -> &Operation {
+        &self.0.api.0.operations[self.1]
+    }
+}
+
+#[async_trait]
+impl FunctionalTool for OperationTool {
+    fn definition(&self) -> Result<ToolDefinition> {
+        let operation = self.operation();
+        Ok(ToolDefinition {
+            name: operation.tool.clone(),
+            desc: operation.description.clone(),
+            params: operation.schema.clone(),
+            strict: false,
+        })
+    }
+
+    fn third_party_fn(&self) -> bool {
+        true
+    }
+
+    async fn invoke_fn(&mut self, call: &ToolCall, _: &ToolContext) -> Result<Message> {
+        let args: Option<Map<String, Value>> = call.args()?;
+        let api = self.0.clone();
+        let mut result = api.call(self.operation(), &args.unwrap_or_default()).await;
+        // errors of reqwest quote the url, which holds the secret of a query auth
+        if let Some((_, secret)) = &api.api.0.auth {
+            result = Ok(secret.redact(&result.unwrap_or_else(|e| e.to_string())));
+        }
+        Ok(tool_result(call, result))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{ApiAuth, OpenApi, OpenApiConfig, OpenApiTool};
+    use crate::Result;
+    use crate::secrets::Secrets;
+    use crate::tools::{ToolCall, ToolContext, WebAccess, WebPolicy};
+    use serde_json::json;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    fn spec(server: &str) -> serde_json::Value {
+        json!({
+            "openapi": "3.0.0",
+            "servers": [{"url": format!("{}/{{version}}", server), "variables": {"version": {"default": "v1"}}}],
+            "paths": {
+                "/series/{series_id}/observations": {
+                    "parameters": [{"$ref": "#/components/parameters/SeriesId"}],
+                    "get": {
+                        "operationId": "getObservations",
+                        "summary": "Observations of an economic data series.",
+                        "parameters": [
+                            {"name": "start", "in": "query", "schema": {"type": "string", "format": "date"}},
+                            {"name": "units", "in": "query", "schema": {"$ref": "#/components/schemas/Units"}},
+                        ],
+                    },
+                },
+                "/series": {
+                    "post": {
+                        "operationId": "createSeries",
+                        "requestBody": {"required": true, "content": {"application/json": {"schema": {"type": "object"}}}},
+                    },
+                },
+            },
+            "components": {
+                "parameters": {
+                    "SeriesId": {"name": "series_id", "in": "path", "required": true, "description": "the id of the series", "schema": {"type": "string"}},
+                },
+                "schemas": {
+                    "Units": {"type": "string", "enum": ["lin", "pch"]},
+                },
+            },
+        })
+    }
+
+    #[test]
+    fn test_operations() {
+        let config = OpenApiConfig {
+            name: "fred".to_string(),
+            ..Default::default()
+        };
+        let api = OpenApi::from_spec(
+            config.clone(),
+            &spec("https://api.example.com"),
+            None,
+            &Secrets::new(),
+        )
+        .unwrap();
+        // write operations are only exposed when they are listed
+        assert_eq!(api.tool_names(), vec!["fred_getObservations"]);
+        let operation = &api.0.operations[0];
+        assert_eq!(api.0.base_url.as_str(), "https://api.example.com/v1");
+        assert_eq!(
+            operation.schema["properties"]["series_id"],
+            json!({"type": "string", "description": "the id of the series"})
+        );
+        assert_eq!(
+            operation.schema["properties"]["units"]["enum"],
+            json!(["lin", "pch"])
+        );
+        assert_eq!(operation.schema["required"], json!(["series_id"]));
+        assert!(
+            operation
+                .description
+                .contains("GET /series/{series_id}/observations")
+        );
+
+        let listed = OpenApiConfig {
+            operations: vec!["createSeries".to_string()],
+            ..config.clone()
+        };
+        let api = OpenApi::from_spec(
+            listed,
+            &spec("https://api.example.com"),
+            None,
+            &Secrets::new(),
+        )
+        .unwrap();
+        assert_eq!(api.tool_names(), vec!["fred_createSeries"]);
+        assert_eq!(api.0.operations[0].schema["required"], json!(["body"]));
+
+        let missing = OpenApiConfig {
+            operations: vec!["deleteSeries".to_string()],
+            ..config.clone()
+        };
+        assert!(
+            OpenApi::from_spec(
+                missing,
+                &spec("https://api.example.com"),
+                None,
+                &Secrets::new()
+            )
+            .is_err()
+        );
+        let no_secret = OpenApiConfig {
+            auth: Some(ApiAuth::Query {
+                name: "api_key".to_string(),
+                secret: "OPENAPI_TEST_UNSET_KEY".to_string(),
+            }),
+            ..config
+        };
+        assert!(
+            OpenApi::from_spec(
+                no_secret,
+                &spec("https://api.example.com"),
+                None,
+                &Secrets::new()
+            )
+            .is_err()
+        );
+    }
+
+    #[tokio::test]
+    async fn test_call() -> Result<()> {
+        // a server that answers with the request line and the auth header it received
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await?;
+        let server = format!("http://{}", listener.local_addr()?);
+        tokio::spawn(async move {
+            while let Ok((mut socket, _)) = listener.accept().await {
+                let mut buf = [0; 4096];
+                let n = socket.read(&mut buf).await.unwrap_or_default();
+                let request = String::from_utf8_lossy(&buf[..n]).to_string();
+                let line = request.lines().next().unwrap_or_default().to_string();
+                let auth = request
+                    .lines()
+                    .find_map(|line| line.strip_prefix("authorization: "))
+                    .unwrap_or_default()
+                    .to_string();
+                let body = json!({"request": line, "auth": auth}).to_string();
+                let res = format!(
+                    "HTTP/1.1 200 OK\r\ncontent-type: application/json\r\ncontent-length: {}\r\nconnection: close\r\n\r\n{}",
+                    body.len(),
+                    body
+                );
+                let _ = socket.write_all(res.as_bytes()).await;
+            }
+        });
+
+        // SAFETY: no other test reads this variable
+        unsafe { std::env::set_var("OPENAPI_TEST_TOKEN", "secret") };
+        let config = OpenApiConfig {
+            name: "fred".to_string(),
+            auth: Some(ApiAuth::Header {
+                name: "Authorization".to_string(),
+                secret: "OPENAPI_TEST_TOKEN".to_string(),
+                prefix: "Bearer ".to_string(),
+            }),
+            ..Default::default()
+        };
+        let api = OpenApi::from_spec(config, &spec(&server), None, &Secrets::new())?;
+        let access = WebAccess::new(WebPolicy::default());
+        let mut tools = OpenApiTool::new(api, access.clone())?.tools()?;
+        let call = |args: serde_json::Value| ToolCall {
+            id: "1".to_string(),
+            name: "fred_getObservations".to_string(),
+            args: args.to_string(),
+        };
+        let mut history = Vec::new();
+        tools[0]
+            .invoke(
+                &call(json!({"series_id": "GDP A", "units": "pch", "jsonpath": "$.request"})),
+                &mut history,
+                &ToolContext::default(),
+            )
+            .await?;
+        let result = history[0].to_string();
+        assert!(result.contains("GET /v1/series/GDP%20A/observations?units=pch HTTP/1.1"));
+
+        history.clear();
+        tools[0]
+            .invoke(
+                &call(json!({"series_id": "GDP", "jsonpath": "$.auth"})),
+                &mut history,
+                &ToolContext::default(),
+            )
+            .await?;
+        // the server echoes the header, whose secret is never shown to the model
+        assert!(history[0].to_string().contains("Bearer [redacted]"));
+
+        history.clear();
+        tools[0]
+            .invoke(&call(json!({})), &mut history, &ToolContext::default())
+            .await?;
+        assert!(
+            history[0]
+                .to_string()
+                .contains("the argument series_id is required")
+        );
+
+        let fetched = access.fetched();
+        assert_eq!(fetched.len(), 2);
+        assert_eq!(
+            fetched[0].0,
+            format!("{}/v1/series/GDP%20A/observations?units=pch", server)
+        );
+
+        // a path argument stays one segment of the path
+        for (series_id, path) in [
+            ("../../admin", "/v1/series/..%2F..%2Fadmin/observations"),
+            ("a b%2F", "/v1/series/a%20b%252F/observations"),
+        ] {
+            history.clear();
+            tools[0]
+                .invoke(
+                    &call(json!({"series_id": series_id, "jsonpath": "$.request"})),
+                    &mut history,
+                    &ToolContext::default(),
+                )
+                .await?;
+            let result = history[0].to_string();
+            assert!(
+                result.contains(&format!("GET {} HTTP/1.1", path)),
+                "{}",
+                result
+            );
+        }
+        history.clear();
+        tools[0]
+            .invoke(
+                &call(json!({"series_id": "..", "jsonpath": "$.request"})),
+                &mut history,
+                &ToolContext::default(),
+            )
+            .await?;
+        assert!(
+            history[0]
+                .to_string()
+                .contains(".. is not a valid path argument")
+        );
+        Ok(())
+    }
+}
@@ -0,0 +1,316 @@
+use crate::Result;
+use crate::llm::Message;
+use crate::tools::kv_memory::Memory;
+use crate::tools::{FunctionalTool, ToolCall, ToolContext, ToolDefinition};
+use async_trait::async_trait;
+use schemars::JsonSchema;
+use serde::Deserialize;
+
+// outlines longer than this only keep their upper levels
+const MAX_SECTIONS: usize = 150;
+const MAX_HEADING_LEN: usize = 100;
+const MAX_HEADING_WORDS: usize = 12;
+// lines that look like headings this often are running headers or footers of a pdf
+const MAX_REPEATS: usize = 3;
+// documents without headings are outlined in parts of this many bytes
+const PART_LEN: usize = 8000;
+
+#[derive(Debug, PartialEq)]
+pub(crate) struct Section {
+    pub level: usize,
+    pub title: String,
+    // byte offset of the heading and byte length of the section including its subsections
+    pub offset: usize,
+    pub length: usize,
+}
+
+const KEYWORDS: &[(&str, usize)] = &[
+    ("part", 1),
+    ("chapter", 1),
+    ("appendix", 1),
+    ("annex", 1),
+    ("section", 2),
+    ("article", 2),
+];
+
+// the level and title of a line that looks like a heading, for Markdown headings, numbered
+// headings such as 2.3 Results, keyword headings such as Chapter 4, and all caps lines
+fn heading(line: &str) -> Option<(usize, String)> {
+    let line = line.trim();
+    if line.is_empty() || line.len() > MAX_HEADING_LEN {
+        return None;
+    }
+
+    let hashes = line.chars().take_while(|c| *c == '#').count();
+    if (1..=6).contains(&hashes) && line[hashes..].starts_with(' ') {
+        return Some((hashes, line[hashes..].trim().to_string()));
+    }
+
+    let words = line.split_whitespace().collect::<Vec<_>>();
+    if words.len() > MAX_HEADING_WORDS || line.ends_with(['.', ',', ';', ':']) {
+        return None;
+    }
+
+    let number = words[0].trim_end_matches('.');
+    if words.len() > 1
+        && !number.is_empty()
+        && number.split('.').all(|part| {
+            !part.is_empty() && part.len() <= 3 && part.chars().all(|c| c.is_ascii_digit())
+        })
+        && words[1].starts_with(|c: char| c.is_uppercase())
+    {
+        return Some((number.split('.').count(), line.to_string()));
+    }
+
+    let first = words[0].to_lowercase();
+    if words.len() > 1
+        && let Some((_, level)) = KEYWORDS.iter().find(|(keyword, _)| *keyword == first)
+    {
+        return Some((*level, line.to_string()));
+    }
+
+    let letters = line.chars().filter(|c| c.is_alphabetic()).count();
+    if letters >= 4 && line.chars().filter(|c| c.is_lowercase()).count() == 0 {
+        return Some((1, line.to_string()));
+    }
+    None
+}
+
+fn fill_lengths(sections: &mut [Section], len: usize) {
+    for i in 0..sections.len() {
+        let end = sections[i + 1..]
+            .iter()
+            .find(|next| next.level <= sections[i].level)
+            .map_or(len, |next| next.offset);
+        sections[i].length = end - sections[i].offset;
+    }
+}
+
+// splits a document without headings into parts that start at line boundaries, titled by their
+// first words
+fn parts(text: &str) -> Vec<Section> {
+    let mut sections = Vec::new();
+    let mut offset = 0;
+    while offset < text.len() {
+        let mut end = (offset + PART_LEN).min(text.len());
+        if end < text.len() {
+            end = text[offset..end]
+                .rfind('\n')
+                .map_or(end, |i| offset + i + 1);
+            while !text.is_char_boundary(end) {
+                end += 1;
+            }
+        }
+        let title = text[offset..end]
+            .split_whitespace()
+            .take(MAX_HEADING_WORDS)
+            .collect::<Vec<_>>()
+            .join(" ");
+        sections.push(Section {
+            level: 1,
+            title: format!("{} ...", title),
+            offset,
+            length: end - offset,
+        });
+        offset = end;
+    }
+    sections
+}
+
+pub(crate) fn outline(text: &str) -> Vec<Section> {
+    let mut candidates = Vec::new();
+    let mut offset = 0;
+    for line in text.split_inclusive('\n') {
+        if let Some((level, title)) = heading(line) {
+            candidates.push(Section {
+                level,
+                title,
+                offset,
+                length: 0,
+            });
+        }
+        offset += line.len();
+    }
+
+    let repeats = |title: &str| candidates.iter().filter(|s| s.title == title).count();
+    let mut sections = candidates
+        .iter()
+        .filter(|s| repeats(&s.title) <= MAX_REPEATS)
+        .map(|s| Section {
+            level: s.level,
+            title: s.title.clone(),
+            offset: s.offset,
+            length: 0,
+        })
+        .collect::<Vec<_>>();
+    if sections.is_empty() {
+        return parts(text);
+    }
+
+    while sections.len() > MAX_SECTIONS {
+        let deepest = sections.iter().map(|s| s.level).max().unwrap_or(1);
+        if deepest == 1 {
+            sections.truncate(MAX_SECTIONS);
+            break;
+        }
+        sections.retain(|s| s.level < deepest);
+    }
+    fill_lengths(&mut sections, text.len());
+    sections
+}
+
+fn format_outline(id: &str, text: &str, sections: &[Section]) -> String {
+    let top = sections.iter().map(|s| s.level).min().unwrap_or(1);
+    let mut result = format!(
+        "Outline of {} ({} bytes, {} sections), each section is listed with its byte offset and length:\n",
+        id,
+        text.len(),
+        sections.len()
+    );
+    for section in sections {
+        result.push_str(&format!(
+            "{}- {} (offset {}, {} bytes)\n",
+            "  ".repeat(section.level.saturating_sub(top)),
+            section.title,
+            section.offset,
+            section.length
+        ));
+    }
+    result
+}
+
+// the text of a memory key, or of an artifact if there is no such key
+pub(crate) fn stored_document(
+    memory: Option<&Memory>,
+    id: &str,
+    ctx: &ToolContext,
+) -> Option<String> {
+    if let Some(value) = memory.and_then(|memory| memory.lock().unwrap().get(id).cloned()) {
+        return Some(value);
+    }
+    let artifacts = ctx.artifacts.as_ref()?;
+    let size = artifacts.size(id).ok()?;
+    artifacts
+        .read(id, 0, size as usize)
+        .ok()
+        .map(|(_, text)| text)
+}
+
+// outlines a stored long document, a memory key or an artifact, so agents read only the
+// sections relevant to their task
+pub struct OutlineTool {
+    memory: Option<Memory>,
+}
+
+impl OutlineTool {
+    pub fn new() -> Box<Self> {
+        Box::new(Self { memory: None })
+    }
+
+    pub(crate) fn with_memory(memory: Memory) -> Box<Self> {
+        Box::new(Self {
+            memory: Some(memory),
+        })
+    }
+}
+
+#[derive(Deserialize, JsonSchema)]
+struct OutlineArgs {
+    /// the artifact id or memory key of the document
+    id: String,
+}
+
+#[async_trait]
+impl FunctionalTool for OutlineTool {
+    fn definition(&self) -> Result<ToolDefinition> {
+        ToolDefinition::new::<OutlineArgs>(
+            "outline_document",
+            "This tool returns the outline of a long stored document, an artifact or a memory key, with the byte offset and length of every section. Use it before reading long documents such as reports, filings, and papers, and then read only the relevant sections with read_chunk instead of the whole document.",
+        )
+    }
+
+    fn third_party_fn(&self) -> bool {
+        true
+    }
+
+    async fn invoke_fn(&mut self, call: &ToolCall, ctx: &ToolContext) -> Result<Message> {
+        let args: OutlineArgs = call.args()?;
+        let result = match stored_document(self.memory.as_ref(), &args.id, ctx) {
+            Some(text) => format_outline(&args.id, &text, &outline(&text)),
+            None => format!("there is no artifact or memory key {}", args.id),
+        };
+
+        Ok(Message::Tool {
+            id: call.id.clone(),
+            name: "outline_document".to_string(),
+            result: result.into(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{PART_LEN, Section, heading, outline};
+
+    #[test]
+    fn test_heading() {
+        assert_eq!(heading("## Results\n"), Some((2, "Results".to_string())));
+        assert_eq!(
+            heading("2.3 Sample Selection"),
+            Some((2, "2.3 Sample Selection".to_string()))
+        );
+        assert_eq!(
+            heading("Chapter 4 Market Overview"),
+            Some((1, "Chapter 4 Market Overview".to_string()))
+        );
+        assert_eq!(
+            heading("RISK FACTORS"),
+            Some((1, "RISK FACTORS".to_string()))
+        );
+        assert_eq!(heading("1. We expect revenue to grow."), None);
+        assert_eq!(heading("2024 was a good year for the company"), None);
+        assert_eq!(heading("#hashtag"), None);
+        assert_eq!(heading("12"), None);
+    }
+
+    #[test]
+    fn test_outline() {
+        let text = "ANNUAL REPORT\nintro\n1 Business\ntext\n1.1 Products\nmore text\nPage 2\n2 Risk Factors\nlast\n";
+        let sections = outline(text);
+        let titles = sections
+            .iter()
+            .map(|s| s.title.as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            titles,
+            vec![
+                "ANNUAL REPORT",
+                "1 Business",
+                "1.1 Products",
+                "2 Risk Factors"
+            ]
+        );
+        assert_eq!(
+            sections[1],
+            Section {
+                level: 1,
+                title: "1 Business".to_string(),
+                offset: 20,
+                length: 46,
+            }
+        );
+        assert_eq!(&text[sections[2].offset..][..12], "1.1 Products");
+        assert_eq!(sections[2].offset + sections[2].length, sections[3].offset);
+        assert_eq!(sections[3].offset + sections[3].length, text.len());
+
+        // running headers repeated on every page are not sections
+        let text = "CONFIDENTIAL\nsome text\n".repeat(5) + "# Summary\nend\n";
+        assert_eq!(outline(&text).len(), 1);
+
+        let text = "plain text without any headings\n".repeat(500);
+        let sections = outline(&text);
+        assert_eq!(sections.len(), text.len().div_ceil(PART_LEN));
+        assert!(sections.iter().all(|s| s.length <= PART_LEN));
+        assert!(sections[0].title.starts_with("plain text without"));
+    }
+}
@@ -0,0 +1,243 @@
+use crate::tools::calc;
+use crate::tools::web_fetch::matches_domain;
+
+// journals, statistical offices, and international organizations, whose content is reviewed
+const REFERENCE_DOMAINS: &[&str] = &[
+    "who.int",
+    "un.org",
+    "oecd.org",
+    "worldbank.org",
+    "imf.org",
+    "europa.eu",
+    "nature.com",
+    "science.org",
+    "sciencedirect.com",
+    "springer.com",
+    "wiley.com",
+    "thelancet.com",
+    "nejm.org",
+    "bmj.com",
+    "jamanetwork.com",
+    "cell.com",
+    "pnas.org",
+    "plos.org",
+    "acm.org",
+    "ieee.org",
+    "ncbi.nlm.nih.gov",
+];
+
+// established news agencies and reference works with editorial standards
+const ESTABLISHED_DOMAINS: &[&str] = &[
+    "reuters.com",
+    "apnews.com",
+    "bbc.com",
+    "bbc.co.uk",
+    "nytimes.com",
+    "ft.com",
+    "economist.com",
+    "theguardian.com",
+    "wsj.com",
+    "bloomberg.com",
+    "npr.org",
+    "wikipedia.org",
+    "britannica.com",
+    "arxiv.org",
+    "ourworldindata.org",
+];
+
+// forums, social networks, and blog hosts where anyone publishes without review
+const USER_CONTENT_DOMAINS: &[&str] = &[
+    "reddit.com",
+    "quora.com",
+    "medium.com",
+    "blogspot.com",
+    "wordpress.com",
+    "tumblr.com",
+    "substack.com",
+    "pinterest.com",
+    "fandom.com",
+    "answers.com",
+    "facebook.com",
+    "x.com",
+    "twitter.com",
+    "tiktok.com",
+];
+
+// signs that a text cites its sources
+const CITATION_MARKERS: &[&str] = &[
+    "doi.org/",
+    "doi:",
+    "et al.",
+    "[1]",
+    "references",
+    "bibliography",
+    "sources:",
+    "works cited",
+];
+
+// publication dates are usually near the top, later years are mostly about other things
+const DATE_CHARS: usize = 3000;
+
+// a heuristic score of how much a source can be relied on, from the reputation of its domain,
+// the most recent year it mentions, whether it cites sources, and for whole pages their length
+#[derive(Clone, Debug, PartialEq)]
+pub(crate) struct Quality {
+    pub score: u32,
+    pub signals: Vec<String>,
+}
+
+impl std::fmt::Display for Quality {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}/100", self.score)?;
+        if !self.signals.is_empty() {
+            write!(f, " ({})", self.signals.join(", "))?;
+        }
+        Ok(())
+    }
+}
+
+fn reputation(host: &str) -> Option<(i32, &'static str)> {
+    let host = host.to_ascii_lowercase();
+    let labels = host.split('.').collect::<Vec<_>>();
+    let tld = labels.last().copied().unwrap_or_default();
+    // gov.uk, ac.jp, and the like below country domains count as much as .gov and .edu
+    let second = if labels.len() > 2 && tld.len() == 2 {
+        labels[labels.len() - 2]
+    } else {
+        ""
+    };
+    let official = matches!(tld, "gov" | "mil") || second == "gov";
+    let academic = tld == "edu" || matches!(second, "ac" | "edu");
+    if official {
+        Some((25, "government domain"))
+    } else if academic {
+        Some((20, "academic domain"))
+    } else if REFERENCE_DOMAINS.iter().any(|d| matches_domain(&host, d)) {
+        Some((25, "journal or international organization"))
+    } else if ESTABLISHED_DOMAINS.iter().any(|d| matches_domain(&host, d)) {
+        Some((10, "established publisher"))
+    } else if USER_CONTENT_DOMAINS
+        .iter()
+        .any(|d| matches_domain(&host, d))
+    {
+        Some((-20, "user generated content"))
+    } else {
+        None
+    }
+}
+
+/// The most recent year up to the current one that is mentioned near the top of a text, which
+/// for most pages is the year they were published or last updated.
+pub fn latest_year(text: &str) -> Option<i64> {
+    latest_year_in(text, calc::today().0)
+}
+
+fn latest_year_in(text: &str, current_year: i64) -> Option<i64> {
+    let head = match text.char_indices().nth(DATE_CHARS) {
+        Some((i, _)) => &text[..i],
+        None => text,
+    };
+    head.split(|c: char| !c.is_ascii_digit())
+        .filter(|digits| digits.len() == 4)
+        .filter_map(|digits| digits.parse::<i64>().ok())
+        .filter(|year| (1900..=current_year).contains(year))
+        .max()
+}
+
+// scores a source, pages are read in full while search results are only a title and a snippet,
+// whose length says nothing about the source
+pub(crate) fn assess(url: &reqwest::Url, text: &str, page: bool) -> Quality {
+    assess_in(url, text, page, calc::today().0)
+}
+
+fn assess_in(url: &reqwest::Url, text: &str, page: bool, current_year: i64) -> Quality {
+    let mut score = 50;
+    let mut signals = Vec::new();
+    if let Some((points, signal)) = url.host_str().and_then(reputation) {
+        score += points;
+        signals.push(signal.to_string());
+    }
+
+    match latest_year_in(text, current_year) {
+        Some(year) => {
+            let age = current_year - year;
+            score += match age {
+                0..=1 => 10,
+                2..=4 => 5,
+                5..=9 => 0,
+                _ => -10,
+            };
+            signals.push(format!("latest year {}", year));
+        }
+        None if page => {
+            score -= 5;
+            signals.push("no date".to_string());
+        }
+        None => {}
+    }
+
+    let lower = text.to_lowercase();
+    let citations = CITATION_MARKERS
+        .iter()
+        .filter(|marker| lower.contains(*marker))
+        .count();
+    if citations >= 2 {
+        score += 10;
+        signals.push("cites sources".to_string());
+    }
+
+    if page {
+        let words = text.split_whitespace().count();
+        if words < 150 {
+            score -= 15;
+            signals.push(format!("only {} words", words));
+        } else if words >= 600 {
+            score += 5;
+            signals.push(format!("{} words", words));
+        }
+    }
+
+    Quality {
+        score: score.clamp(0, 100) as u32,
+        signals,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::assess_in;
+
+    #[test]
+    fn test_assess() {
+        let url = |url: &str| reqwest::Url::parse(url).unwrap();
+        let study = format!(
+            "Published 12 March 2025. {} See Smith et al. (2023). References [1] https://doi.org/10.1/x",
+            "word ".repeat(700)
+        );
+        let quality = assess_in(&url("https://www.cdc.gov/report"), &study, true, 2025);
+        assert_eq!(quality.score, 100);
+        assert_eq!(
+            quality.to_string(),
+            "100/100 (government domain, latest year 2025, cites sources, 712 words)"
+        );
+
+        let post = "I think the 2015 numbers are wrong, here is why";
+        let quality = assess_in(&url("https://www.reddit.com/r/x"), post, true, 2025);
+        assert_eq!(quality.score, 5);
+        assert_eq!(
+            quality.signals,
+            vec![
+                "user generated content",
+                "latest year 2015",
+                "only 10 words"
+            ]
+        );
+
+        // snippets of search results are not judged by their length
+        let quality = assess_in(&url("https://example.ac.uk/paper"), "A survey", false, 2025);
+        assert_eq!(quality.to_string(), "70/100 (academic domain)");
+        // years after the current one are not publication dates
+        let quality = assess_in(&url("https://example.com"), "Targets for 2050", false, 2025);
+        assert_eq!(quality.to_string(), "50/100");
+    }
+}
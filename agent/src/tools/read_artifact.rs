@@ -0,0 +1,122 @@
+use crate::Result;
+use crate::artifacts::ArtifactStore;
+use crate::llm::Message;
+use crate::tools::{FunctionalTool, ToolCall, ToolContext, ToolDefinition};
+use async_trait::async_trait;
+use schemars::JsonSchema;
+use serde::Deserialize;
+use std::sync::Arc;
+
+pub struct ReadArtifactTool {
+    store: Arc<ArtifactStore>,
+}
+
+impl ReadArtifactTool {
+    pub fn new(store: Arc<ArtifactStore>) -> Box<Self> {
+        Box::new(Self { store })
+    }
+}
+
+// the most bytes one call reads, so that a call does not bring a whole artifact back into the
+// history
+const MAX_LENGTH: usize = 32000;
+
+fn default_length() -> usize {
+    8000
+}
+
+#[derive(Deserialize, JsonSchema)]
+struct ReadArtifactArgs {
+    /// the id of the artifact to read
+    id: String,
+    /// the byte offset to start reading from
+    #[serde(default)]
+    offset: u64,
+    /// the maximum number of bytes to read, defaults to 8000 and is at most 32000
+    #[serde(default = "default_length")]
+    length: usize,
+}
+
+#[async_trait]
+impl FunctionalTool for ReadArtifactTool {
+    fn definition(&self) -> Result<ToolDefinition> {
+        ToolDefinition::new::<ReadArtifactArgs>(
+            "read_artifact",
+            "This tool reads part of a large tool output that was stored as an artifact. Provide the artifact id, and optionally the byte offset to start from and the number of bytes to read, to page through the full content.",
+        )
+    }
+
+    fn third_party_fn(&self) -> bool {
+        true
+    }
+
+    async fn invoke_fn(&mut self, call: &ToolCall, _: &ToolContext) -> Result<Message> {
+        let args: ReadArtifactArgs = call.args()?;
+
+        let result = match self.store.size(&args.id) {
+            Ok(size) => {
+                let length = args.length.min(MAX_LENGTH);
+                let (range, content) = self.store.read(&args.id, args.offset, length)?;
+                let clamped = if length < args.length {
+                    format!(
+                        ", the length was limited to {} bytes, read on from offset {} for more",
+                        MAX_LENGTH, range.end
+                    )
+                } else {
+                    String::new()
+                };
+                format!(
+                    "artifact {} (bytes {}-{} of {}{}):\n{}",
+                    args.id, range.start, range.end, size, clamped, content
+                )
+            }
+            Err(_) => format!("artifact {} does not exist", args.id),
+        };
+
+        Ok(Message::Tool {
+            id: call.id.clone(),
+            name: "read_artifact".to_string(),
+            result: result.into(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::ReadArtifactTool;
+    use crate::Result;
+    use crate::artifacts::ArtifactStore;
+    use crate::llm::Message;
+    use crate::tools::{FunctionalTool, ToolCall, ToolContext};
+
+    #[tokio::test]
+    async fn test_read_artifact() -> Result<()> {
+        let dir = std::env::temp_dir().join(format!("read-artifact-{}", std::process::id()));
+        let store = ArtifactStore::new(&dir)?;
+        let id = store.put("fetch", &"x".repeat(50000))?;
+        let mut tool = ReadArtifactTool::new(store);
+        let mut read = async |args: serde_json::Value| -> Result<String> {
+            let call = ToolCall {
+                id: "1".to_string(),
+                name: "read_artifact".to_string(),
+                args: args.to_string(),
+            };
+            match tool.invoke_fn(&call, &ToolContext::default()).await? {
+                Message::Tool { result, .. } => Ok(result.to_string()),
+                _ => panic!("not a tool message"),
+            }
+        };
+
+        let result = read(serde_json::json!({"id": id, "length": usize::MAX})).await?;
+        assert!(result.starts_with(&format!(
+            "artifact {} (bytes 0-32000 of 50000, the length was limited to 32000 bytes, read on from offset 32000 for more):\n",
+            id
+        )));
+        assert!(result.len() < 33000);
+        let result = read(serde_json::json!({"id": id, "offset": 49990})).await?;
+        assert!(result.starts_with(&format!("artifact {} (bytes 49990-50000 of 50000):\n", id)));
+
+        std::fs::remove_dir_all(dir)?;
+        Ok(())
+    }
+}
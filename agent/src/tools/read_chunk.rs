@@ -0,0 +1,282 @@
+use crate::Result;
+use crate::llm::Message;
+use crate::tools::kv_memory::Memory;
+use crate::tools::outline::stored_document;
+use crate::tools::{FunctionalTool, Tool, ToolCall, ToolContext, ToolDefinition};
+use async_trait::async_trait;
+use schemars::JsonSchema;
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+// a chunk ends at the last line break in this many final bytes, so it does not cut a sentence
+const LINE_BREAK_WINDOW: usize = 500;
+
+fn default_length() -> usize {
+    8000
+}
+
+fn floor_char_boundary(s: &str, index: usize) -> usize {
+    (0..=index.min(s.len()))
+        .rev()
+        .find(|i| s.is_char_boundary(*i))
+        .unwrap_or(0)
+}
+
+// the byte range of the chunk of text starting at offset, ending at a line break if one is near
+// the end of the chunk. Both ends move back to the start of the character they fall into, a chunk
+// shorter than its first character reads that character, so that reading always moves on
+fn chunk_range(text: &str, offset: usize, length: usize) -> (usize, usize) {
+    let start = floor_char_boundary(text, offset);
+    let mut end = floor_char_boundary(text, start.saturating_add(length.max(1)));
+    if end == start && start < text.len() {
+        end = (start + 1..=text.len())
+            .find(|i| text.is_char_boundary(*i))
+            .unwrap_or(text.len());
+    }
+    if end == text.len() {
+        return (start, end);
+    }
+    let window = floor_char_boundary(text, end.saturating_sub(LINE_BREAK_WINDOW).max(start));
+    match text[window..end].rfind('\n') {
+        Some(i) if window + i + 1 > start => (start, window + i + 1),
+        _ => (start, end),
+    }
+}
+
+#[derive(Clone, Copy)]
+struct Bookmark {
+    offset: usize,
+    size: usize,
+}
+
+// reads stored long documents, memory keys or artifacts, in chunks and remembers where the agent
+// stopped reading each of them, the bookmarks live outside the history so they survive its
+// summarization
+#[derive(Clone)]
+pub struct ChunkReader {
+    memory: Option<Memory>,
+    bookmarks: Arc<Mutex<HashMap<String, Bookmark>>>,
+}
+
+impl ChunkReader {
+    pub fn new() -> Box<Self> {
+        Box::new(Self {
+            memory: None,
+            bookmarks: Arc::new(Mutex::new(HashMap::new())),
+        })
+    }
+
+    pub(crate) fn with_memory(memory: Memory) -> Box<Self> {
+        Box::new(Self {
+            memory: Some(memory),
+            bookmarks: Arc::new(Mutex::new(HashMap::new())),
+        })
+    }
+
+    pub fn tools(&self) -> Result<Vec<Box<dyn Tool + Send>>> {
+        Ok(vec![
+            Box::new(ReadChunkTool(self.clone())),
+            Box::new(ResumeReadingTool(self.clone())),
+        ])
+    }
+
+    fn read(&self, id: &str, offset: usize, length: usize, ctx: &ToolContext) -> String {
+        let Some(text) = stored_document(self.memory.as_ref(), id, ctx) else {
+            return format!("there is no artifact or memory key {}", id);
+        };
+        if offset >= text.len() {
+            return format!(
+                "{} has {} bytes, there is nothing to read at offset {}",
+                id,
+                text.len(),
+                offset
+            );
+        }
+
+        let (start, end) = chunk_range(&text, offset, length);
+        self.bookmarks.lock().unwrap().insert(
+            id.to_string(),
+            Bookmark {
+                offset: end,
+                size: text.len(),
+            },
+        );
+        let next = if end == text.len() {
+            "this is the end of the document".to_string()
+        } else {
+            format!(
+                "{} bytes remain, call resume_reading to continue at offset {}",
+                text.len() - end,
+                end
+            )
+        };
+        format!(
+            "{} (bytes {}-{} of {}, {}):\n{}",
+            id,
+            start,
+            end,
+            text.len(),
+            next,
+            &text[start..end]
+        )
+    }
+
+    fn list_bookmarks(&self) -> String {
+        let bookmarks = self.bookmarks.lock().unwrap();
+        if bookmarks.is_empty() {
+            return "you have not started reading any document with read_chunk".to_string();
+        }
+
+        let mut ids = bookmarks.keys().collect::<Vec<_>>();
+        ids.sort();
+        let mut result = "Documents you are reading:\n".to_string();
+        for id in ids {
+            let bookmark = bookmarks[id];
+            result.push_str(&format!(
+                "- {}: read up to byte {} of {} ({}%)\n",
+                id,
+                bookmark.offset,
+                bookmark.size,
+                bookmark.offset * 100 / bookmark.size.max(1)
+            ));
+        }
+        result
+    }
+}
+
+#[derive(Deserialize, JsonSchema)]
+struct ReadChunkArgs {
+    /// the artifact id or memory key of the document
+    doc_id: String,
+    /// the byte offset to start reading from, such as the offset of a section in the outline
+    #[serde(default)]
+    offset: usize,
+    /// the maximum number of bytes to read, defaults to 8000
+    #[serde(default = "default_length")]
+    length: usize,
+}
+
+struct ReadChunkTool(ChunkReader);
+
+#[async_trait]
+impl FunctionalTool for ReadChunkTool {
+    fn definition(&self) -> Result<ToolDefinition> {
+        ToolDefinition::new::<ReadChunkArgs>(
+            "read_chunk",
+            "This tool reads a chunk of a long stored document, an artifact or a memory key, starting at a byte offset, and bookmarks where the chunk ends. Use it with the offsets from outline_document to read the relevant sections, and use resume_reading to continue reading a document from its bookmark.",
+        )
+    }
+
+    fn third_party_fn(&self) -> bool {
+        true
+    }
+
+    async fn invoke_fn(&mut self, call: &ToolCall, ctx: &ToolContext) -> Result<Message> {
+        let args: ReadChunkArgs = call.args()?;
+        Ok(Message::Tool {
+            id: call.id.clone(),
+            name: "read_chunk".to_string(),
+            result: self
+                .0
+                .read(&args.doc_id, args.offset, args.length, ctx)
+                .into(),
+        })
+    }
+}
+
+#[derive(Deserialize, JsonSchema)]
+struct ResumeReadingArgs {
+    /// the artifact id or memory key of the document; leave empty to list the documents you are reading and how far you got
+    #[serde(default)]
+    doc_id: Option<String>,
+    /// the maximum number of bytes to read, defaults to 8000
+    #[serde(default = "default_length")]
+    length: usize,
+}
+
+struct ResumeReadingTool(ChunkReader);
+
+#[async_trait]
+impl FunctionalTool for ResumeReadingTool {
+    fn definition(&self) -> Result<ToolDefinition> {
+        ToolDefinition::new::<ResumeReadingArgs>(
+            "resume_reading",
+            "This tool continues reading a long stored document where you last stopped with read_chunk or resume_reading. Bookmarks are kept even when your history is summarized, so use this tool to work through large sources across many steps. Without a document id it lists the documents you are reading.",
+        )
+    }
+
+    fn third_party_fn(&self) -> bool {
+        true
+    }
+
+    async fn invoke_fn(&mut self, call: &ToolCall, ctx: &ToolContext) -> Result<Message> {
+        let args: ResumeReadingArgs = call.args()?;
+        let result = match &args.doc_id {
+            Some(id) => {
+                let offset = self
+                    .0
+                    .bookmarks
+                    .lock()
+                    .unwrap()
+                    .get(id)
+                    .map_or(0, |bookmark| bookmark.offset);
+                self.0.read(id, offset, args.length, ctx)
+            }
+            None => self.0.list_bookmarks(),
+        };
+
+        Ok(Message::Tool {
+            id: call.id.clone(),
+            name: "resume_reading".to_string(),
+            result: result.into(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{ChunkReader, chunk_range};
+    use crate::tools::ToolContext;
+    use std::collections::HashMap;
+    use std::sync::{Arc, Mutex};
+
+    #[test]
+    fn test_chunk_range() {
+        let text = "first line\nsecond line\nthird";
+        assert_eq!(chunk_range(text, 0, 15), (0, 11));
+        assert_eq!(chunk_range(text, 11, 100), (11, text.len()));
+        // a chunk without a line break ends at the length
+        assert_eq!(chunk_range(text, 2, 5), (2, 7));
+        // offsets and ends inside a character move to its start
+        assert_eq!(chunk_range("aé b", 2, 2), (1, 3));
+        assert_eq!(chunk_range("a€b", 1, 1), (1, 4));
+    }
+
+    #[test]
+    fn test_bookmarks() {
+        let memory = Arc::new(Mutex::new(HashMap::from([(
+            "doc".to_string(),
+            "a".repeat(30),
+        )])));
+        let reader = ChunkReader::with_memory(memory);
+        let ctx = ToolContext::default();
+
+        assert!(reader.read("doc", 0, 10, &ctx).starts_with(
+            "doc (bytes 0-10 of 30, 20 bytes remain, call resume_reading to continue at offset 10)"
+        ));
+        assert_eq!(
+            reader.list_bookmarks(),
+            "Documents you are reading:\n- doc: read up to byte 10 of 30 (33%)\n"
+        );
+        assert!(
+            reader
+                .read("doc", 25, 10, &ctx)
+                .starts_with("doc (bytes 25-30 of 30, this is the end of the document)")
+        );
+        assert_eq!(
+            reader.read("missing", 0, 10, &ctx),
+            "there is no artifact or memory key missing"
+        );
+    }
+}
@@ -0,0 +1,146 @@
+// english names of the countries some apis take instead of codes, by ISO 3166-1 alpha-2 code
+const COUNTRIES: &[(&str, &str)] = &[
+    ("ar", "argentina"),
+    ("at", "austria"),
+    ("au", "australia"),
+    ("be", "belgium"),
+    ("br", "brazil"),
+    ("ca", "canada"),
+    ("ch", "switzerland"),
+    ("cl", "chile"),
+    ("cn", "china"),
+    ("co", "colombia"),
+    ("cz", "czech republic"),
+    ("de", "germany"),
+    ("dk", "denmark"),
+    ("eg", "egypt"),
+    ("es", "spain"),
+    ("fi", "finland"),
+    ("fr", "france"),
+    ("gb", "united kingdom"),
+    ("gr", "greece"),
+    ("hk", "hong kong"),
+    ("hu", "hungary"),
+    ("id", "indonesia"),
+    ("ie", "ireland"),
+    ("il", "israel"),
+    ("in", "india"),
+    ("it", "italy"),
+    ("jp", "japan"),
+    ("kr", "south korea"),
+    ("mx", "mexico"),
+    ("my", "malaysia"),
+    ("ng", "nigeria"),
+    ("nl", "netherlands"),
+    ("no", "norway"),
+    ("nz", "new zealand"),
+    ("ph", "philippines"),
+    ("pk", "pakistan"),
+    ("pl", "poland"),
+    ("pt", "portugal"),
+    ("ro", "romania"),
+    ("ru", "russia"),
+    ("sa", "saudi arabia"),
+    ("se", "sweden"),
+    ("sg", "singapore"),
+    ("th", "thailand"),
+    ("tr", "turkey"),
+    ("tw", "taiwan"),
+    ("ua", "ukraine"),
+    ("us", "united states"),
+    ("vn", "vietnam"),
+    ("za", "south africa"),
+];
+
+/// The country, and optionally the language, searches are scoped to, for research about a
+/// market or the regulation of a jurisdiction.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Region {
+    /// ISO 3166-1 alpha-2 code of the country, e.g. de
+    pub country: String,
+    /// ISO 639-1 code of the language of the results, e.g. de
+    pub language: Option<String>,
+    /// top level domains whose results are listed first, e.g. de and eu; the domain of the
+    /// country when empty
+    pub tlds: Vec<String>,
+}
+
+impl Region {
+    pub fn new(country: &str) -> Self {
+        Self {
+            country: country.to_ascii_lowercase(),
+            ..Default::default()
+        }
+    }
+
+    // the english name of the country, None for countries the table does not have
+    pub fn country_name(&self) -> Option<&'static str> {
+        COUNTRIES
+            .iter()
+            .find(|(code, _)| *code == self.country)
+            .map(|(_, name)| *name)
+    }
+
+    fn tlds(&self) -> Vec<String> {
+        if !self.tlds.is_empty() {
+            return self
+                .tlds
+                .iter()
+                .map(|tld| tld.trim_start_matches('.').to_ascii_lowercase())
+                .collect();
+        }
+        // the united kingdom uses .uk rather than its country code
+        match self.country.as_str() {
+            "gb" => vec!["uk".to_string()],
+            country => vec![country.to_string()],
+        }
+    }
+
+    fn prefers(&self, tlds: &[String], url: &str) -> bool {
+        reqwest::Url::parse(url)
+            .ok()
+            .and_then(|url| url.host_str().map(|host| host.to_ascii_lowercase()))
+            .is_some_and(|host| tlds.iter().any(|tld| host.ends_with(&format!(".{}", tld))))
+    }
+
+    // moves the items from the preferred domains to the front, keeping the order otherwise
+    pub(crate) fn sort<T>(&self, items: &mut [T], url: impl Fn(&T) -> &str) {
+        let tlds = self.tlds();
+        items.sort_by_key(|item| !self.prefers(&tlds, url(item)));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Region;
+
+    #[test]
+    fn test_region() {
+        let mut urls = vec![
+            "https://example.com/a",
+            "https://gov.uk/b",
+            "https://example.org/c",
+            "https://bbc.co.uk/d",
+        ];
+        let region = Region::new("GB");
+        assert_eq!(region.country_name(), Some("united kingdom"));
+        region.sort(&mut urls, |url| url);
+        assert_eq!(
+            urls,
+            vec![
+                "https://gov.uk/b",
+                "https://bbc.co.uk/d",
+                "https://example.com/a",
+                "https://example.org/c",
+            ]
+        );
+
+        let region = Region {
+            tlds: vec![".org".to_string()],
+            ..Region::new("xx")
+        };
+        assert_eq!(region.country_name(), None);
+        region.sort(&mut urls, |url| url);
+        assert_eq!(urls[0], "https://example.org/c");
+    }
+}
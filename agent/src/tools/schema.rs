@@ -0,0 +1,186 @@
+use serde_json::{Map, Value};
+
+// nested definitions deeper than this are most likely recursive and are left as references
+const MAX_DEPTH: usize = 32;
+
+// string formats accepted by OpenAI structured outputs, other formats are dropped
+const SUPPORTED_FORMATS: &[&str] = &[
+    "date-time",
+    "time",
+    "date",
+    "duration",
+    "email",
+    "hostname",
+    "ipv4",
+    "ipv6",
+    "uuid",
+];
+
+// converts the root schema generated by schemars into a self contained parameter schema
+pub(crate) fn normalize(mut root: Value) -> Value {
+    let definitions = match &mut root {
+        Value::Object(map) => {
+            map.remove("$schema");
+            map.remove("title");
+            map.remove("definitions").unwrap_or_default()
+        }
+        _ => Value::Null,
+    };
+
+    inline(
+        &mut root,
+        &serde_json::json!({ "definitions": definitions }),
+        0,
+    );
+    root
+}
+
+// inlines the references of a schema taken from a larger document, such as an OpenAPI spec
+#[cfg(feature = "native")]
+pub(crate) fn inline_refs(mut schema: Value, document: &Value) -> Value {
+    inline(&mut schema, document, 0);
+    schema
+}
+
+fn inline(schema: &mut Value, document: &Value, depth: usize) {
+    match schema {
+        Value::Object(map) => {
+            if depth < MAX_DEPTH {
+                while inline_ref(map, document) || inline_all_of(map) {}
+            }
+
+            if let Some(Value::String(format)) = map.get("format")
+                && !SUPPORTED_FORMATS.contains(&format.as_str())
+            {
+                map.remove("format");
+            }
+
+            for value in map.values_mut() {
+                inline(value, document, depth + 1);
+            }
+        }
+        Value::Array(values) => {
+            for value in values {
+                inline(value, document, depth + 1);
+            }
+        }
+        _ => {}
+    }
+}
+
+fn merge(map: &mut Map<String, Value>, other: &Value) {
+    if let Value::Object(other) = other {
+        for (key, value) in other {
+            map.entry(key.clone()).or_insert_with(|| value.clone());
+        }
+    }
+}
+
+// references are json pointers into the document, e.g. #/definitions/Status
+fn inline_ref(map: &mut Map<String, Value>, document: &Value) -> bool {
+    let definition = match map.get("$ref") {
+        Some(Value::String(r)) => r
+            .strip_prefix('#')
+            .and_then(|pointer| document.pointer(pointer)),
+        _ => None,
+    };
+
+    match definition {
+        Some(definition) => {
+            map.remove("$ref");
+            merge(map, definition);
+            true
+        }
+        None => false,
+    }
+}
+
+// schemars wraps references that carry a description in a single element allOf
+fn inline_all_of(map: &mut Map<String, Value>) -> bool {
+    match map.get("allOf") {
+        Some(Value::Array(all_of)) if all_of.len() == 1 => {
+            let inner = all_of[0].clone();
+            map.remove("allOf");
+            merge(map, &inner);
+            true
+        }
+        _ => false,
+    }
+}
+
+// strict mode requires every object to list all of its properties as required and to
+// disallow additional properties
+pub(crate) fn strict(schema: &mut Value) {
+    let Value::Object(map) = schema else {
+        return;
+    };
+
+    map.remove("default");
+
+    if let Some(Value::Object(properties)) = map.get_mut("properties") {
+        properties.values_mut().for_each(strict);
+
+        let required = properties.keys().cloned().map(Value::String).collect();
+        map.insert("required".to_string(), Value::Array(required));
+        map.insert("additionalProperties".to_string(), Value::Bool(false));
+    }
+
+    for key in ["items", "anyOf", "oneOf", "allOf"] {
+        match map.get_mut(key) {
+            Some(Value::Array(schemas)) => schemas.iter_mut().for_each(strict),
+            Some(schema) => strict(schema),
+            None => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{normalize, strict};
+    use schemars::{JsonSchema, schema_for};
+
+    #[allow(dead_code)]
+    #[derive(JsonSchema)]
+    enum Status {
+        Open,
+        Closed,
+    }
+
+    #[allow(dead_code)]
+    #[derive(JsonSchema)]
+    struct Args {
+        /// the status
+        status: Status,
+        count: u32,
+        default: Vec<u32>,
+        #[serde(default)]
+        note: Option<String>,
+    }
+
+    #[test]
+    fn test_normalize() {
+        let mut schema = normalize(serde_json::to_value(schema_for!(Args)).unwrap());
+
+        assert!(schema.get("definitions").is_none());
+        assert!(schema.get("$schema").is_none());
+        assert_eq!(
+            schema["properties"]["status"],
+            serde_json::json!({
+                "description": "the status",
+                "type": "string",
+                "enum": ["Open", "Closed"],
+            })
+        );
+        assert!(schema["properties"]["count"].get("format").is_none());
+        assert!(!schema.to_string().contains("$ref"));
+
+        strict(&mut schema);
+        assert_eq!(schema["additionalProperties"], false);
+        assert_eq!(
+            schema["required"],
+            serde_json::json!(["count", "default", "note", "status"])
+        );
+        assert!(schema["properties"]["note"].get("default").is_none());
+        assert_eq!(schema["properties"]["default"]["type"], "array");
+    }
+}
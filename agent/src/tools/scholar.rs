@@ -0,0 +1,551 @@
+use crate::llm::Message;
+use crate::secrets::{Secret, Secrets};
+use crate::tools::{
+    FunctionalTool, HttpClient, Tool, ToolCall, ToolContext, ToolDefinition, Trust, WebAccess,
+    tool_result,
+};
+use crate::{Error, Result};
+use async_trait::async_trait;
+use schemars::JsonSchema;
+use serde::Deserialize;
+use std::sync::Arc;
+use std::time::Duration;
+
+const S2_URL: &str = "https://api.semanticscholar.org/graph/v1/paper";
+const CROSSREF_URL: &str = "https://api.crossref.org/works";
+const PAPER_FIELDS: &str =
+    "title,year,authors,venue,citationCount,referenceCount,externalIds,url,abstract";
+const LINK_FIELDS: &str = "title,year,authors,venue,citationCount,externalIds";
+const MAX_PAPERS: usize = 100;
+const MAX_AUTHORS: usize = 3;
+
+#[derive(Deserialize, Default)]
+#[serde(rename_all = "camelCase")]
+struct Paper {
+    #[serde(default)]
+    paper_id: Option<String>,
+    #[serde(default)]
+    title: Option<String>,
+    #[serde(default)]
+    year: Option<u32>,
+    #[serde(default)]
+    authors: Vec<Author>,
+    #[serde(default)]
+    venue: Option<String>,
+    #[serde(default)]
+    citation_count: Option<u64>,
+    #[serde(default)]
+    reference_count: Option<u64>,
+    #[serde(default)]
+    external_ids: Option<ExternalIds>,
+    #[serde(default)]
+    url: Option<String>,
+    #[serde(default, rename = "abstract")]
+    summary: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct Author {
+    name: String,
+}
+
+#[derive(Deserialize)]
+struct ExternalIds {
+    #[serde(default, rename = "DOI")]
+    doi: Option<String>,
+    #[serde(default, rename = "ArXiv")]
+    arxiv: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct Search {
+    #[serde(default)]
+    data: Vec<Paper>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Link {
+    #[serde(default)]
+    citing_paper: Option<Paper>,
+    #[serde(default)]
+    cited_paper: Option<Paper>,
+}
+
+#[derive(Deserialize)]
+struct Links {
+    #[serde(default)]
+    data: Vec<Link>,
+}
+
+#[derive(Deserialize)]
+struct CrossrefWork {
+    message: CrossrefMessage,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "kebab-case")]
+struct CrossrefMessage {
+    #[serde(rename = "DOI")]
+    doi: String,
+    #[serde(default)]
+    title: Vec<String>,
+    #[serde(default)]
+    author: Vec<CrossrefAuthor>,
+    #[serde(default)]
+    container_title: Vec<String>,
+    #[serde(default)]
+    issued: Option<CrossrefDate>,
+    #[serde(default)]
+    is_referenced_by_count: Option<u64>,
+    #[serde(default)]
+    reference_count: Option<u64>,
+}
+
+#[derive(Deserialize)]
+struct CrossrefAuthor {
+    #[serde(default)]
+    given: Option<String>,
+    #[serde(default)]
+    family: Option<String>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "kebab-case")]
+struct CrossrefDate {
+    date_parts: Vec<Vec<u32>>,
+}
+
+impl From<CrossrefMessage> for Paper {
+    fn from(work: CrossrefMessage) -> Self {
+        Paper {
+            paper_id: None,
+            title: work.title.into_iter().next(),
+            year: work
+                .issued
+                .and_then(|date| date.date_parts.first()?.first().copied()),
+            authors: work
+                .author
+                .into_iter()
+                .map(|author| Author {
+                    name: [author.given, author.family]
+                        .into_iter()
+                        .flatten()
+                        .collect::<Vec<_>>()
+                        .join(" "),
+                })
+                .collect(),
+            venue: work.container_title.into_iter().next(),
+            citation_count: work.is_referenced_by_count,
+            reference_count: work.reference_count,
+            url: Some(format!("https://doi.org/{}", work.doi)),
+            external_ids: Some(ExternalIds {
+                doi: Some(work.doi),
+                arxiv: None,
+            }),
+            summary: None,
+        }
+    }
+}
+
+// the semantic scholar id of a paper given as a doi, doi url, arxiv id, or semantic scholar id
+fn paper_id(id: &str) -> String {
+    let id = id.trim();
+    let id = id
+        .strip_prefix("https://doi.org/")
+        .or_else(|| id.strip_prefix("http://doi.org/"))
+        .or_else(|| id.strip_prefix("doi:"))
+        .unwrap_or(id);
+    if id.starts_with("10.") {
+        format!("DOI:{}", id)
+    } else if let Some(arxiv) = id
+        .strip_prefix("arXiv:")
+        .or_else(|| id.strip_prefix("arxiv:"))
+    {
+        format!("ARXIV:{}", arxiv)
+    } else {
+        id.to_string()
+    }
+}
+
+// the id percent-encoded for the path of an api url. The slashes of dois and the colon after the
+// prefix are kept, since the apis take them as they are, and dot segments, which urls resolve
+// even when encoded, are refused
+fn id_path(id: &str) -> std::result::Result<String, String> {
+    if id
+        .split('/')
+        .any(|segment| segment == "." || segment == "..")
+    {
+        return Err(format!("{} is not a valid paper id", id));
+    }
+    Ok(id
+        .bytes()
+        .map(|b| match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' | b'/' | b':' => {
+                (b as char).to_string()
+            }
+            b => format!("%{:02X}", b),
+        })
+        .collect())
+}
+
+fn format_paper(paper: &Paper, details: bool) -> String {
+    let mut authors = paper
+        .authors
+        .iter()
+        .take(MAX_AUTHORS)
+        .map(|author| author.name.as_str())
+        .collect::<Vec<_>>()
+        .join(", ");
+    if paper.authors.len() > MAX_AUTHORS {
+        authors.push_str(" et al.");
+    }
+    let mut line = format!(
+        "- {} ({}{})",
+        paper.title.as_deref().unwrap_or("untitled"),
+        if authors.is_empty() {
+            String::new()
+        } else {
+            format!("{}, ", authors)
+        },
+        paper
+            .year
+            .map_or("year unknown".to_string(), |y| y.to_string()),
+    );
+    if let Some(venue) = paper.venue.as_deref().filter(|v| !v.is_empty()) {
+        line.push_str(&format!(", {}", venue));
+    }
+    if let Some(count) = paper.citation_count {
+        line.push_str(&format!(", cited by {}", count));
+    }
+    let ids = paper.external_ids.as_ref();
+    match (
+        ids.and_then(|ids| ids.doi.as_ref()),
+        ids.and_then(|ids| ids.arxiv.as_ref()),
+        &paper.paper_id,
+    ) {
+        (Some(doi), _, _) => line.push_str(&format!("\n  id: {}", doi)),
+        (None, Some(arxiv), _) => line.push_str(&format!("\n  id: arXiv:{}", arxiv)),
+        (None, None, Some(id)) => line.push_str(&format!("\n  id: {}", id)),
+        _ => {}
+    }
+    if details {
+        if let Some(count) = paper.reference_count {
+            line.push_str(&format!("\n  references: {}", count));
+        }
+        if let Some(url) = &paper.url {
+            line.push_str(&format!("\n  url: {}", url));
+        }
+        if let Some(summary) = &paper.summary {
+            line.push_str(&format!("\n  abstract: {}", summary));
+        }
+    }
+    line
+}
+
+// finds papers and walks the citation graph with semantic scholar, dois it does not know are
+// looked up in crossref. An api key raises the rate limit, it is the SEMANTIC_SCHOLAR_API_KEY
+// credential if that is set. Requests to the apis count towards the limits of the web access
+// policy, and the papers that were looked up are recorded as sources
+#[derive(Clone)]
+pub struct ScholarTool {
+    access: Arc<WebAccess>,
+    http: HttpClient,
+    api_key: Option<Secret>,
+}
+
+impl ScholarTool {
+    pub fn new(access: Arc<WebAccess>, secrets: &Secrets) -> Result<Box<Self>> {
+        Ok(Box::new(Self {
+            http: access.policy().http.client(Duration::from_secs(30))?,
+            api_key: secrets.get("SEMANTIC_SCHOLAR_API_KEY").ok(),
+            access,
+        }))
+    }
+
+    pub fn tools(&self) -> Result<Vec<Box<dyn Tool + Send>>> {
+        Ok(vec![
+            Box::new(PaperLookupTool(self.clone())),
+            Box::new(CitationsTool {
+                scholar: self.clone(),
+                direction: Direction::Citations,
+            }),
+            Box::new(CitationsTool {
+                scholar: self.clone(),
+                direction: Direction::References,
+            }),
+        ])
+    }
+
+    // returns None for papers the api does not know
+    async fn get<T: serde::de::DeserializeOwned>(
+        &self,
+        url: &str,
+        params: &[(&str, String)],
+    ) -> Result<Option<T>> {
+        let url = reqwest::Url::parse_with_params(url, params)
+            .map_err(|e| Error::AgentWorkflowError(e.to_string()))?;
+        self.access.check(&url).map_err(Error::AgentWorkflowError)?;
+        let mut request = self.http.get(url.clone());
+        if let Some(api_key) = &self.api_key
+            && url.host_str() == Some("api.semanticscholar.org")
+        {
+            // marked as sensitive so that it is never written to the http cache
+            let mut value =
+                reqwest::header::HeaderValue::from_str(api_key.expose()).map_err(|_| {
+                    Error::AuthError("the Semantic Scholar key is not a valid header".to_string())
+                })?;
+            value.set_sensitive(true);
+            request = request.header("x-api-key", value);
+        }
+        let res =
+            self.http.send(request).await.map_err(|e| {
+                Error::AgentWorkflowError(format!("failed to fetch {}: {}", url, e))
+            })?;
+        let status = res.status();
+        if status == reqwest::StatusCode::NOT_FOUND {
+            return Ok(None);
+        }
+        if !status.is_success() {
+            return Err(Error::AgentWorkflowError(format!(
+                "fetching {} failed with status {}",
+                url, status
+            )));
+        }
+        res.json()
+            .await
+            .map(Some)
+            .map_err(|e| Error::AgentWorkflowError(format!("unexpected reply from {}: {}", url, e)))
+    }
+
+    async fn lookup(&self, args: &PaperLookupArgs) -> Result<String> {
+        let fields = ("fields", PAPER_FIELDS.to_string());
+        if let Some(id) = &args.id {
+            let id = paper_id(id);
+            let path = match id_path(&id) {
+                Ok(path) => path,
+                Err(reason) => return Ok(reason),
+            };
+            let paper = match self
+                .get::<Paper>(&format!("{}/{}", S2_URL, path), &[fields])
+                .await?
+            {
+                Some(paper) => Some(paper),
+                None => match path.strip_prefix("DOI:") {
+                    Some(doi) => self
+                        .get::<CrossrefWork>(&format!("{}/{}", CROSSREF_URL, doi), &[])
+                        .await?
+                        .map(|work| work.message.into()),
+                    None => None,
+                },
+            };
+            let Some(paper) = paper else {
+                return Ok(format!("no paper with the id {} was found", id));
+            };
+            let text = format_paper(&paper, true);
+            if let Some(url) = &paper.url {
+                self.access.record(url, &text, Trust::Verified);
+            }
+            return Ok(text);
+        }
+
+        let Some(title) = &args.title else {
+            return Ok("either the id or the title of the paper is required".to_string());
+        };
+        let search = self
+            .get::<Search>(
+                &format!("{}/search", S2_URL),
+                &[("query", title.clone()), ("limit", "5".to_string()), fields],
+            )
+            .await?
+            .unwrap_or(Search { data: Vec::new() });
+        if search.data.is_empty() {
+            return Ok(format!("no paper titled {} was found", title));
+        }
+        Ok(search
+            .data
+            .iter()
+            .map(|paper| format_paper(paper, true))
+            .collect::<Vec<_>>()
+            .join("\n"))
+    }
+
+    async fn links(&self, direction: Direction, args: &CitationsArgs) -> Result<String> {
+        let id = paper_id(&args.id);
+        let path = match id_path(&id) {
+            Ok(path) => path,
+            Err(reason) => return Ok(reason),
+        };
+        let limit = args.limit.unwrap_or(20).clamp(1, MAX_PAPERS);
+        let Some(links) = self
+            .get::<Links>(
+                &format!("{}/{}/{}", S2_URL, path, direction.endpoint()),
+                &[
+                    ("fields", LINK_FIELDS.to_string()),
+                    ("limit", limit.to_string()),
+                ],
+            )
+            .await?
+        else {
+            return Ok(format!("no paper with the id {} was found", id));
+        };
+
+        let papers = links
+            .data
+            .into_iter()
+            .filter_map(|link| match direction {
+                Direction::Citations => link.citing_paper,
+                Direction::References => link.cited_paper,
+            })
+            .filter(|paper| paper.title.is_some())
+            .collect::<Vec<_>>();
+        if papers.is_empty() {
+            return Ok(format!("no {} of {} are known", direction.endpoint(), id));
+        }
+        Ok(papers
+            .iter()
+            .map(|paper| format_paper(paper, false))
+            .collect::<Vec<_>>()
+            .join("\n"))
+    }
+}
+
+#[derive(Deserialize, JsonSchema)]
+struct PaperLookupArgs {
+    /// the doi, arXiv id such as arXiv:1706.03762, or Semantic Scholar id of the paper
+    #[serde(default)]
+    id: Option<String>,
+    /// the title of the paper, used when the id is not known
+    #[serde(default)]
+    title: Option<String>,
+}
+
+struct PaperLookupTool(ScholarTool);
+
+#[async_trait]
+impl FunctionalTool for PaperLookupTool {
+    fn definition(&self) -> Result<ToolDefinition> {
+        ToolDefinition::new::<PaperLookupArgs>(
+            "paper_lookup",
+            "This tool finds a scholarly paper by its doi, arXiv id, or title and returns its authors, year, venue, citation count, ids, and abstract. Use the returned id with citations_of and references_of.",
+        )
+    }
+
+    fn third_party_fn(&self) -> bool {
+        true
+    }
+
+    async fn invoke_fn(&mut self, call: &ToolCall, _: &ToolContext) -> Result<Message> {
+        let args: PaperLookupArgs = call.args()?;
+        Ok(tool_result(call, self.0.lookup(&args).await))
+    }
+}
+
+#[derive(Clone, Copy)]
+enum Direction {
+    Citations,
+    References,
+}
+
+impl Direction {
+    fn endpoint(&self) -> &'static str {
+        match self {
+            Direction::Citations => "citations",
+            Direction::References => "references",
+        }
+    }
+}
+
+#[derive(Deserialize, JsonSchema)]
+struct CitationsArgs {
+    /// the doi, arXiv id, or Semantic Scholar id of the paper
+    id: String,
+    /// the maximum number of papers to return, at most 100
+    #[serde(default)]
+    limit: Option<usize>,
+}
+
+struct CitationsTool {
+    scholar: ScholarTool,
+    direction: Direction,
+}
+
+#[async_trait]
+impl FunctionalTool for CitationsTool {
+    fn definition(&self) -> Result<ToolDefinition> {
+        match self.direction {
+            Direction::Citations => ToolDefinition::new::<CitationsArgs>(
+                "citations_of",
+                "This tool lists the papers that cite a paper. Use it to find later work that builds on, replicates, or disputes a paper.",
+            ),
+            Direction::References => ToolDefinition::new::<CitationsArgs>(
+                "references_of",
+                "This tool lists the papers a paper cites. Use it to find the earlier work a paper builds on.",
+            ),
+        }
+    }
+
+    fn third_party_fn(&self) -> bool {
+        true
+    }
+
+    async fn invoke_fn(&mut self, call: &ToolCall, _: &ToolContext) -> Result<Message> {
+        let args: CitationsArgs = call.args()?;
+        Ok(tool_result(
+            call,
+            self.scholar.links(self.direction, &args).await,
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{CrossrefWork, Links, Paper, format_paper, id_path, paper_id};
+
+    #[test]
+    fn test_paper_id() {
+        assert_eq!(paper_id("10.1038/nature14539"), "DOI:10.1038/nature14539");
+        assert_eq!(
+            paper_id("https://doi.org/10.1038/nature14539"),
+            "DOI:10.1038/nature14539"
+        );
+        assert_eq!(paper_id("arXiv:1706.03762"), "ARXIV:1706.03762");
+        assert_eq!(
+            paper_id(" 204e3073870fae3d05bcbc2f6a8e263d9b72e776 "),
+            "204e3073870fae3d05bcbc2f6a8e263d9b72e776"
+        );
+
+        assert_eq!(
+            id_path("DOI:10.1002/(SICI)1097-4636<45:2>?x#y"),
+            Ok("DOI:10.1002/%28SICI%291097-4636%3C45:2%3E%3Fx%23y".to_string())
+        );
+        assert!(id_path("DOI:10.1000/../../search").is_err());
+    }
+
+    #[test]
+    fn test_format_paper() {
+        let links: Links = serde_json::from_str(
+            r#"{"data": [{"citingPaper": {"paperId": "abc", "title": "Attention Is All You Need", "year": 2017,
+                "authors": [{"name": "A. Vaswani"}, {"name": "N. Shazeer"}, {"name": "N. Parmar"}, {"name": "J. Uszkoreit"}],
+                "venue": "NeurIPS", "citationCount": 100000, "externalIds": {"ArXiv": "1706.03762"}}}]}"#,
+        )
+        .unwrap();
+        let paper = links.data.into_iter().next().unwrap().citing_paper.unwrap();
+        assert_eq!(
+            format_paper(&paper, false),
+            "- Attention Is All You Need (A. Vaswani, N. Shazeer, N. Parmar et al., 2017), NeurIPS, cited by 100000\n  id: arXiv:1706.03762"
+        );
+
+        let work: CrossrefWork = serde_json::from_str(
+            r#"{"message": {"DOI": "10.1038/nature14539", "title": ["Deep learning"],
+                "author": [{"given": "Yann", "family": "LeCun"}], "container-title": ["Nature"],
+                "issued": {"date-parts": [[2015, 5, 27]]}, "is-referenced-by-count": 50000, "reference-count": 103}}"#,
+        )
+        .unwrap();
+        let paper: Paper = work.message.into();
+        assert_eq!(
+            format_paper(&paper, true),
+            "- Deep learning (Yann LeCun, 2015), Nature, cited by 50000\n  id: 10.1038/nature14539\n  references: 103\n  url: https://doi.org/10.1038/nature14539"
+        );
+    }
+}
@@ -0,0 +1,1245 @@
+use crate::llm::{CompletionRequest, Embeddings, LLM, Message, Usage};
+use crate::secrets::Secret;
+use crate::tools::vector_memory::cosine_similarity;
+use crate::tools::{
+    HttpClient, HttpClientConfig, Region, Tool, ToolCall, ToolContext, ToolDefinition, WebAccess,
+};
+use crate::tools::{calc, quality};
+use crate::{Error, History, Result};
+use async_trait::async_trait;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+const TAVILY_URL: &str = "https://api.tavily.com/search";
+const BRAVE_URL: &str = "https://api.search.brave.com/res/v1/web/search";
+const SERPER_URL: &str = "https://google.serper.dev/search";
+const MAX_RESULTS: usize = 20;
+
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct SearchResult {
+    pub title: String,
+    pub url: String,
+    pub snippet: String,
+}
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct SearchQuery {
+    pub query: String,
+    pub limit: usize,
+    // a date in the form YYYY-MM-DD, if set only results published since then are returned
+    pub since: Option<String>,
+    pub region: Option<Region>,
+}
+
+#[async_trait]
+pub trait SearchProvider {
+    // the name quotas are tracked under, e.g. brave
+    fn name(&self) -> &str;
+
+    async fn search(&self, query: &SearchQuery) -> Result<Vec<SearchResult>>;
+}
+
+/// A web search api.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SearchApiKind {
+    Tavily,
+    Brave,
+    Serper,
+}
+
+impl SearchApiKind {
+    pub fn name(&self) -> &'static str {
+        match self {
+            SearchApiKind::Tavily => "tavily",
+            SearchApiKind::Brave => "brave",
+            SearchApiKind::Serper => "serper",
+        }
+    }
+
+    /// The credential the key is read from with [`crate::secrets::Secrets::get`].
+    pub fn credential(&self) -> &'static str {
+        match self {
+            SearchApiKind::Tavily => "TAVILY_API_KEY",
+            SearchApiKind::Brave => "BRAVE_API_KEY",
+            SearchApiKind::Serper => "SERPER_API_KEY",
+        }
+    }
+
+    /// About a thirtieth of the monthly searches of the free plan.
+    pub fn default_quota(&self) -> u64 {
+        match self {
+            SearchApiKind::Tavily => 30,
+            SearchApiKind::Brave => 60,
+            SearchApiKind::Serper => 80,
+        }
+    }
+
+    fn url(&self) -> &'static str {
+        match self {
+            SearchApiKind::Tavily => TAVILY_URL,
+            SearchApiKind::Brave => BRAVE_URL,
+            SearchApiKind::Serper => SERPER_URL,
+        }
+    }
+}
+
+#[derive(Deserialize)]
+struct TavilyResponse {
+    #[serde(default)]
+    results: Vec<TavilyResult>,
+}
+
+#[derive(Deserialize)]
+struct TavilyResult {
+    title: String,
+    url: String,
+    #[serde(default)]
+    content: String,
+}
+
+#[derive(Deserialize)]
+struct BraveResponse {
+    #[serde(default)]
+    web: Option<BraveWeb>,
+}
+
+#[derive(Deserialize)]
+struct BraveWeb {
+    #[serde(default)]
+    results: Vec<BraveResult>,
+}
+
+#[derive(Deserialize)]
+struct BraveResult {
+    title: String,
+    url: String,
+    #[serde(default)]
+    description: String,
+}
+
+#[derive(Deserialize)]
+struct SerperResponse {
+    #[serde(default)]
+    organic: Vec<SerperResult>,
+}
+
+#[derive(Deserialize)]
+struct SerperResult {
+    title: String,
+    link: String,
+    #[serde(default)]
+    snippet: String,
+}
+
+// the results of a reply of the api, brave marks the matched terms with <strong>
+fn parse(kind: SearchApiKind, body: &[u8]) -> Result<Vec<SearchResult>> {
+    let results = match kind {
+        SearchApiKind::Tavily => serde_json::from_slice::<TavilyResponse>(body)?
+            .results
+            .into_iter()
+            .map(|result| SearchResult {
+                title: result.title,
+                url: result.url,
+                snippet: result.content,
+            })
+            .collect(),
+        SearchApiKind::Brave => serde_json::from_slice::<BraveResponse>(body)?
+            .web
+            .map(|web| web.results)
+            .unwrap_or_default()
+            .into_iter()
+            .map(|result| SearchResult {
+                title: result.title,
+                url: result.url,
+                snippet: result
+                    .description
+                    .replace("<strong>", "")
+                    .replace("</strong>", ""),
+            })
+            .collect(),
+        SearchApiKind::Serper => serde_json::from_slice::<SerperResponse>(body)?
+            .organic
+            .into_iter()
+            .map(|result| SearchResult {
+                title: result.title,
+                url: result.link,
+                snippet: result.snippet,
+            })
+            .collect(),
+    };
+    Ok(results)
+}
+
+// searches one of the web search apis with its key
+pub struct SearchApi {
+    kind: SearchApiKind,
+    key: Secret,
+    http: HttpClient,
+    url: String,
+}
+
+impl SearchApi {
+    pub fn new(kind: SearchApiKind, key: Secret, http: &HttpClientConfig) -> Result<Arc<Self>> {
+        Ok(Arc::new(Self {
+            kind,
+            key,
+            http: http.client(Duration::from_secs(30))?,
+            url: kind.url().to_string(),
+        }))
+    }
+
+    fn request(&self, query: &SearchQuery) -> Result<reqwest::RequestBuilder> {
+        let url = reqwest::Url::parse(&self.url)
+            .map_err(|e| Error::AgentWorkflowError(format!("invalid search url: {}", e)))?;
+        // the key is marked as sensitive so that it is never written to the http cache
+        let header = |value: String| {
+            let mut value = reqwest::header::HeaderValue::from_str(&value).map_err(|_| {
+                Error::AuthError(format!(
+                    "the {} key is not a valid header",
+                    self.kind.name()
+                ))
+            })?;
+            value.set_sensitive(true);
+            Ok::<_, Error>(value)
+        };
+        Ok(match self.kind {
+            SearchApiKind::Tavily => {
+                let mut body =
+                    serde_json::json!({"query": query.query, "max_results": query.limit});
+                if let Some(since) = &query.since {
+                    body["start_date"] = since.clone().into();
+                }
+                // tavily takes the name of the country, and only for general searches
+                if let Some(country) = query.region.as_ref().and_then(Region::country_name) {
+                    body["country"] = country.into();
+                    body["topic"] = "general".into();
+                }
+                self.http
+                    .request(reqwest::Method::POST, url)
+                    .header(
+                        reqwest::header::AUTHORIZATION,
+                        header(format!("Bearer {}", self.key.expose()))?,
+                    )
+                    .json(&body)
+            }
+            SearchApiKind::Brave => self
+                .http
+                .get(url)
+                .query(&[("q", &query.query), ("count", &query.limit.to_string())])
+                // a date range such as 2024-01-01to2024-06-30
+                .query(
+                    &query
+                        .since
+                        .iter()
+                        .map(|since| ("freshness", format!("{}to{}", since, today())))
+                        .chain(query.region.iter().flat_map(|region| {
+                            std::iter::once(("country", region.country.clone())).chain(
+                                region
+                                    .language
+                                    .clone()
+                                    .map(|language| ("search_lang", language)),
+                            )
+                        }))
+                        .collect::<Vec<_>>(),
+                )
+                .header(reqwest::header::ACCEPT, "application/json")
+                .header(
+                    "X-Subscription-Token",
+                    header(self.key.expose().to_string())?,
+                ),
+            SearchApiKind::Serper => {
+                let mut body = serde_json::json!({"q": query.query, "num": query.limit});
+                if let Some(since) = &query.since {
+                    body["tbs"] = format!("cdr:1,cd_min:{}", us_date(since)).into();
+                }
+                if let Some(region) = &query.region {
+                    body["gl"] = region.country.clone().into();
+                    if let Some(language) = &region.language {
+                        body["hl"] = language.clone().into();
+                    }
+                }
+                self.http
+                    .request(reqwest::Method::POST, url)
+                    .header("X-API-KEY", header(self.key.expose().to_string())?)
+                    .json(&body)
+            }
+        })
+    }
+}
+
+#[async_trait]
+impl SearchProvider for SearchApi {
+    fn name(&self) -> &str {
+        self.kind.name()
+    }
+
+    async fn search(&self, query: &SearchQuery) -> Result<Vec<SearchResult>> {
+        let res = self
+            .http
+            .send(self.request(query)?)
+            .await
+            .map_err(|e| Error::AgentWorkflowError(self.key.redact(&e.to_string())))?;
+        let status = res.status();
+        if status.as_u16() == 429 {
+            return Err(Error::RateLimited(format!(
+                "{} refused the search with status 429",
+                self.kind.name()
+            )));
+        }
+        if !status.is_success() {
+            return Err(Error::AgentWorkflowError(format!(
+                "{} failed with status {}",
+                self.kind.name(),
+                status
+            )));
+        }
+        let body = res
+            .bytes()
+            .await
+            .map_err(|e| Error::AgentWorkflowError(e.to_string()))?;
+        parse(self.kind, &body)
+    }
+}
+
+// google takes dates in the form M/D/YYYY
+fn us_date(date: &str) -> String {
+    match date.split('-').collect::<Vec<_>>().as_slice() {
+        [year, month, day] => format!(
+            "{}/{}/{}",
+            month.trim_start_matches('0'),
+            day.trim_start_matches('0'),
+            year
+        ),
+        _ => date.to_string(),
+    }
+}
+
+// today in utc as YYYY-MM-DD, the day quotas are counted for
+fn today() -> String {
+    let (year, month, day) = calc::today();
+    format!("{:04}-{:02}-{:02}", year, month, day)
+}
+
+#[derive(Default, Serialize, Deserialize)]
+struct QuotaState {
+    date: String,
+    used: BTreeMap<String, u64>,
+}
+
+impl QuotaState {
+    fn read(path: &Path) -> Result<Self> {
+        match std::fs::read(path) {
+            Ok(data) => Ok(serde_json::from_slice(&data)?),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(Self::default()),
+            Err(e) => Err(e.into()),
+        }
+    }
+}
+
+/// The searches every provider was used for today, kept in a file so that the count carries
+/// over to later runs of the day.
+pub struct SearchQuotas {
+    path: Option<PathBuf>,
+    state: Mutex<QuotaState>,
+}
+
+impl SearchQuotas {
+    /// Quotas that are only counted for the lifetime of the process.
+    pub fn new() -> Arc<Self> {
+        Arc::new(Self {
+            path: None,
+            state: Mutex::new(QuotaState::default()),
+        })
+    }
+
+    /// Reads the counts of earlier runs from the file, which is created with the first search.
+    pub fn open(path: &Path) -> Result<Arc<Self>> {
+        Ok(Arc::new(Self {
+            path: Some(path.to_path_buf()),
+            state: Mutex::new(QuotaState::read(path)?),
+        }))
+    }
+
+    pub fn used(&self, provider: &str) -> u64 {
+        self.used_on(provider, &today())
+    }
+
+    fn used_on(&self, provider: &str, date: &str) -> u64 {
+        let state = self.state.lock().unwrap();
+        if state.date != date {
+            return 0;
+        }
+        state.used.get(provider).copied().unwrap_or_default()
+    }
+
+    // counts a search with the provider before it is made, false if its quota is used up
+    fn reserve(&self, provider: &str, quota: u64) -> Result<bool> {
+        self.change(provider, &today(), |used| {
+            let left = *used < quota;
+            if left {
+                *used += 1;
+            }
+            left
+        })
+    }
+
+    // gives back the search of a request that failed
+    fn release(&self, provider: &str) -> Result<()> {
+        self.change(provider, &today(), |used| {
+            *used = used.saturating_sub(1);
+            true
+        })?;
+        Ok(())
+    }
+
+    // changes the count of the provider unless the change returns false. The counts are read
+    // from the file again under a lock, so that the runs sharing it add up their searches instead
+    // of overwriting each other's. A search is still counted within the process when the file
+    // cannot be updated, the error is returned after the change
+    fn change(
+        &self,
+        provider: &str,
+        date: &str,
+        change: impl Fn(&mut u64) -> bool,
+    ) -> Result<bool> {
+        let mut state = self.state.lock().unwrap();
+        let file = self
+            .path
+            .as_ref()
+            .map(|path| Self::lock(path).and_then(|lock| Ok((lock, QuotaState::read(path)?))));
+        let (lock, error) = match file {
+            Some(Ok((lock, saved))) => {
+                *state = saved;
+                (Some(lock), None)
+            }
+            Some(Err(e)) => (None, Some(e)),
+            None => (None, None),
+        };
+        // the counts start over every day
+        if state.date != date {
+            *state = QuotaState {
+                date: date.to_string(),
+                used: BTreeMap::new(),
+            };
+        }
+        if !change(state.used.entry(provider.to_string()).or_default()) {
+            return Ok(false);
+        }
+        if let (Some(path), Some(_lock)) = (&self.path, lock) {
+            let partial = path.with_extension("json.tmp");
+            std::fs::write(&partial, serde_json::to_vec_pretty(&*state)?)?;
+            std::fs::rename(partial, path)?;
+        }
+        match error {
+            Some(e) => Err(e),
+            None => Ok(true),
+        }
+    }
+
+    // the lock of the file, held while its counts are read and written
+    fn lock(path: &Path) -> Result<std::fs::File> {
+        let lock = std::fs::OpenOptions::new()
+            .create(true)
+            .truncate(false)
+            .write(true)
+            .open(path.with_extension("json.lock"))?;
+        lock.lock()?;
+        Ok(lock)
+    }
+}
+
+// words that do not change what a query finds
+const STOPWORDS: &[&str] = &[
+    "a", "an", "and", "are", "about", "as", "at", "by", "do", "does", "for", "from", "how", "in",
+    "is", "it", "of", "on", "or", "the", "to", "what", "when", "where", "which", "who", "why",
+    "with",
+];
+
+// the key under which queries that differ only in case, punctuation, word order, stopwords, and
+// plural endings count as the same search, e.g. "Solar panels in Germany?" and "germany solar
+// panel"
+fn normalize(query: &str) -> String {
+    let mut terms = query
+        .to_lowercase()
+        .split(|c: char| !c.is_alphanumeric())
+        .filter(|term| !term.is_empty() && !STOPWORDS.contains(term))
+        .map(|term| match term.strip_suffix('s') {
+            Some(stem) if stem.chars().count() >= 3 && !stem.ends_with('s') => stem.to_string(),
+            _ => term.to_string(),
+        })
+        .collect::<Vec<_>>();
+    terms.sort();
+    terms.dedup();
+    if terms.is_empty() {
+        return query.trim().to_lowercase();
+    }
+    terms.join(" ")
+}
+
+#[derive(Clone)]
+struct Searched {
+    // the query as the model wrote it the first time
+    query: String,
+    provider: String,
+    limit: usize,
+    results: Vec<SearchResult>,
+}
+
+struct Rotation {
+    providers: Vec<(Arc<dyn SearchProvider + Send + Sync>, u64)>,
+    quotas: Arc<SearchQuotas>,
+    next: AtomicUsize,
+    // the searches of the run by normalized query
+    searched: Mutex<HashMap<String, Searched>>,
+}
+
+const RERANK_PROMPT: &str = "You rate how useful web search results are for a research task.
+Instructions:
+- Rate every result from 0, unrelated to the task, to 10, exactly what the task needs, judging by its title, url, and snippet.
+- Reply with one line per result in the form NUMBER: RATING, e.g. 3: 7, and nothing else.";
+
+// the longest part of the task of an agent the results are ranked against
+const MAX_TASK_CHARS: usize = 2000;
+
+/// Ranks search results by their relevance to the query and the task of the agent searching,
+/// so that only the most relevant ones are shown to the model.
+#[derive(Clone)]
+pub enum Reranker {
+    /// by the cosine similarity of the embeddings of the results and of the query with the task
+    Embeddings(Arc<dyn Embeddings + Send + Sync>),
+    /// by the ratings a model, usually a cheap one, gives the results
+    Model(Arc<dyn LLM + Send + Sync>),
+}
+
+impl Reranker {
+    // a score for every result, higher is more relevant, the tokens used for it are recorded in
+    // the usage of the agent
+    async fn scores(
+        &self,
+        query: &str,
+        task: &str,
+        results: &[SearchResult],
+        usage: &Usage,
+    ) -> Result<Vec<f32>> {
+        let describe =
+            |result: &SearchResult| format!("{}\n{}\n{}", result.title, result.url, result.snippet);
+        match self {
+            Reranker::Embeddings(embeddings) => {
+                let mut inputs = vec![format!("{}\n{}", query, task)];
+                inputs.extend(results.iter().map(describe));
+                let res = embeddings.embed(&inputs).await?;
+                usage.record(res.usage);
+                let vectors = res.embeddings;
+                if vectors.len() != inputs.len() {
+                    return Err(Error::LLMResponseError(format!(
+                        "expected {} embeddings, got {}",
+                        inputs.len(),
+                        vectors.len()
+                    )));
+                }
+                Ok(vectors[1..]
+                    .iter()
+                    .map(|vector| cosine_similarity(&vectors[0], vector))
+                    .collect())
+            }
+            Reranker::Model(llm) => {
+                let listing = results
+                    .iter()
+                    .enumerate()
+                    .map(|(i, result)| format!("{}. {}", i + 1, describe(result)))
+                    .collect::<Vec<_>>()
+                    .join("\n\n");
+                let res = llm
+                    .completion(CompletionRequest {
+                        messages: &vec![
+                            Arc::new(Message::System(RERANK_PROMPT.to_string())),
+                            Arc::new(Message::User(format!(
+                                "Task: {}\nQuery: {}\n\nResults:\n{}",
+                                task, query, listing
+                            ))),
+                        ],
+                        tools: &[],
+                        web_search_tool: false,
+                        prefill: None,
+                    })
+                    .await?;
+                usage.record(res.usage);
+                Ok(parse_ratings(&res.content, results.len()))
+            }
+        }
+    }
+}
+
+// the ratings in a reply of the model, results it did not rate get 0
+fn parse_ratings(reply: &str, count: usize) -> Vec<f32> {
+    let mut scores = vec![0.0; count];
+    for line in reply.lines() {
+        let Some((number, rating)) = line.split_once(':') else {
+            continue;
+        };
+        let number = number.trim().trim_start_matches('#').parse::<usize>();
+        if let (Ok(number), Ok(rating)) = (number, rating.trim().parse::<f32>())
+            && (1..=count).contains(&number)
+        {
+            scores[number - 1] = rating;
+        }
+    }
+    scores
+}
+
+// web search apis with daily quotas that are used in turn, the agents of a run share it so
+// that they spread their searches over the providers together
+#[derive(Clone)]
+pub struct WebSearch {
+    rotation: Arc<Rotation>,
+    rerank: Option<(Reranker, usize)>,
+    since: Option<String>,
+    region: Option<Region>,
+}
+
+impl WebSearch {
+    // the providers with their daily quotas
+    pub fn new(
+        providers: Vec<(Arc<dyn SearchProvider + Send + Sync>, u64)>,
+        quotas: Arc<SearchQuotas>,
+    ) -> Self {
+        Self {
+            rotation: Arc::new(Rotation {
+                providers,
+                quotas,
+                next: AtomicUsize::new(0),
+                searched: Mutex::new(HashMap::new()),
+            }),
+            rerank: None,
+            since: None,
+            region: None,
+        }
+    }
+
+    // searches for results from the region, which are listed before the others
+    pub fn region(mut self, region: Region) -> Self {
+        self.region = Some(region);
+        self
+    }
+
+    // only searches for results published since the date, in the form YYYY-MM-DD
+    pub fn since(mut self, date: String) -> Self {
+        self.since = Some(date);
+        self
+    }
+
+    // asks the providers for as many results as they give and shows the model only the keep
+    // results the reranker finds most relevant to the task of the agent
+    pub fn rerank(mut self, reranker: Reranker, keep: usize) -> Self {
+        self.rerank = Some((reranker, keep.max(1)));
+        self
+    }
+
+    // the searches every provider has left today, e.g. tavily 28/30, brave 0/60
+    fn remaining(&self) -> String {
+        self.rotation
+            .providers
+            .iter()
+            .map(|(provider, quota)| {
+                let used = self.rotation.quotas.used(provider.name());
+                format!(
+                    "{} {}/{}",
+                    provider.name(),
+                    quota.saturating_sub(used),
+                    quota
+                )
+            })
+            .collect::<Vec<_>>()
+            .join(", ")
+    }
+
+    // the number of results the providers are asked for
+    fn fetch_limit(&self, limit: usize) -> usize {
+        if self.rerank.is_some() {
+            MAX_RESULTS
+        } else {
+            limit
+        }
+    }
+
+    // an earlier search of the run for the same normalized query with at least as many results
+    fn earlier(&self, key: &str, limit: usize) -> Option<Searched> {
+        let searched = self.rotation.searched.lock().unwrap();
+        let earlier = searched.get(key)?;
+        (earlier.limit >= limit || earlier.results.len() < earlier.limit).then(|| Searched {
+            results: earlier.results.iter().take(limit).cloned().collect(),
+            ..earlier.clone()
+        })
+    }
+
+    // searches with the next provider that has quota left, falling back to the others when it
+    // fails. None when every quota is used up
+    async fn search(
+        &self,
+        query: &str,
+        limit: usize,
+        failures: &mut Vec<String>,
+    ) -> Result<Option<(String, Vec<SearchResult>)>> {
+        let count = self.rotation.providers.len();
+        let start = self.rotation.next.fetch_add(1, Ordering::SeqCst);
+        for i in 0..count {
+            let (provider, quota) = &self.rotation.providers[(start + i) % count];
+            // the search is counted before it is made, so that agents searching at the same time
+            // cannot go over the quota together
+            match self.rotation.quotas.reserve(provider.name(), *quota) {
+                Ok(true) => {}
+                Ok(false) => continue,
+                Err(e) => failures.push(format!(
+                    "the searches of {} could not be saved: {}",
+                    provider.name(),
+                    e
+                )),
+            }
+            let request = SearchQuery {
+                query: query.to_string(),
+                limit,
+                since: self.since.clone(),
+                region: self.region.clone(),
+            };
+            match provider.search(&request).await {
+                Ok(results) => {
+                    self.rotation.searched.lock().unwrap().insert(
+                        normalize(query),
+                        Searched {
+                            query: query.to_string(),
+                            provider: provider.name().to_string(),
+                            limit,
+                            results: results.clone(),
+                        },
+                    );
+                    return Ok(Some((provider.name().to_string(), results)));
+                }
+                Err(e) => {
+                    failures.push(format!("{} failed: {}", provider.name(), e));
+                    if let Err(e) = self.rotation.quotas.release(provider.name()) {
+                        failures.push(format!(
+                            "the searches of {} could not be saved: {}",
+                            provider.name(),
+                            e
+                        ));
+                    }
+                }
+            }
+        }
+        Ok(None)
+    }
+
+    // the limit results most relevant to the task, with a note on the reranking. A reranker
+    // that fails leaves the order of the provider
+    async fn select(
+        &self,
+        query: &str,
+        task: &str,
+        mut results: Vec<SearchResult>,
+        limit: usize,
+        usage: &Usage,
+    ) -> (Vec<SearchResult>, Option<String>) {
+        let Some((reranker, keep)) = &self.rerank else {
+            results.truncate(limit);
+            return (results, None);
+        };
+        let keep = limit.min(*keep);
+        if results.len() <= keep {
+            return (results, None);
+        }
+        let found = results.len();
+        match reranker.scores(query, task, &results, usage).await {
+            Ok(scores) => {
+                let mut ranked = results.into_iter().zip(scores).collect::<Vec<_>>();
+                // the sort is stable, so results with the same score keep the order of the provider
+                ranked.sort_by(|a, b| b.1.total_cmp(&a.1));
+                let results = ranked
+                    .into_iter()
+                    .take(keep)
+                    .map(|(result, _)| result)
+                    .collect();
+                let note = format!(
+                    "Showing the {} of {} results most relevant to your task",
+                    keep, found
+                );
+                (results, Some(note))
+            }
+            Err(e) => {
+                results.truncate(keep);
+                (results, Some(format!("Ranking the results failed: {}", e)))
+            }
+        }
+    }
+}
+
+#[derive(Deserialize, JsonSchema)]
+struct WebSearchArgs {
+    /// the search terms
+    query: String,
+    /// the maximum number of results, at most 20, defaults to 10
+    #[serde(default)]
+    limit: Option<usize>,
+}
+
+// searches the web with the search apis, results from domains the web access policy does not
+// allow are left out. Every result reports the searches left today, so that the model can
+// economize them, and a query that was searched before in the run, by this agent or another,
+// is answered with the earlier results instead of using up another search
+pub struct WebSearchTool {
+    search: WebSearch,
+    access: Arc<WebAccess>,
+    // the normalized queries this agent searched for
+    searched: HashSet<String>,
+    // the first user message of the agent, which results are reranked against
+    task: String,
+    // the usage of the agent, which reranking counts towards
+    usage: Arc<Usage>,
+}
+
+impl WebSearchTool {
+    pub fn new(search: WebSearch, access: Arc<WebAccess>) -> Box<Self> {
+        Box::new(Self {
+            search,
+            access,
+            searched: HashSet::new(),
+            task: String::new(),
+            usage: Usage::new(),
+        })
+    }
+
+    async fn list(&self, query: &str, results: Vec<SearchResult>, limit: usize) -> String {
+        let mut results = results
+            .into_iter()
+            .filter(|result| {
+                reqwest::Url::parse(&result.url).is_ok_and(|url| self.access.allows(&url).is_ok())
+            })
+            .collect::<Vec<_>>();
+        if results.is_empty() {
+            return format!("No results found for {}", query);
+        }
+        if let Some(region) = &self.search.region {
+            region.sort(&mut results, |result| &result.url);
+        }
+        let (results, note) = self
+            .search
+            .select(query, &self.task, results, limit, &self.usage)
+            .await;
+        let mut text = results
+            .iter()
+            .enumerate()
+            .map(|(i, result)| {
+                let mut line = format!("{}. {}\n   {}", i + 1, result.title, result.url);
+                if !result.snippet.is_empty() {
+                    line.push_str(&format!("\n   {}", result.snippet));
+                }
+                if let Ok(url) = reqwest::Url::parse(&result.url) {
+                    let text = format!("{}\n{}", result.title, result.snippet);
+                    line.push_str(&format!(
+                        "\n   quality {}",
+                        quality::assess(&url, &text, false)
+                    ));
+                }
+                line
+            })
+            .collect::<Vec<_>>()
+            .join("\n");
+        if let Some(note) = note {
+            text.push_str(&format!("\n\n{}", note));
+        }
+        text
+    }
+
+    async fn run(&mut self, args: WebSearchArgs) -> Result<String> {
+        let limit = args.limit.unwrap_or(10).clamp(1, MAX_RESULTS);
+        let fetch = self.search.fetch_limit(limit);
+        let key = normalize(&args.query);
+        let mut text = if let Some(earlier) = self.search.earlier(&key, fetch) {
+            let by = if self.searched.contains(&key) {
+                "You already searched"
+            } else {
+                "Another agent of this research already searched"
+            };
+            let mut text = self.list(&earlier.query, earlier.results, limit).await;
+            text.push_str(&format!(
+                "\n\n{} for \"{}\" with {}, these are the results of that search and no search was used up. Search with different terms to find other sources.",
+                by, earlier.query, earlier.provider
+            ));
+            self.searched.insert(key);
+            text
+        } else {
+            self.search_new(&args.query, key, limit).await?
+        };
+        text.push_str(&format!(
+            "\nSearches left today: {}. Prefer few precise searches once they run low.",
+            self.search.remaining()
+        ));
+        Ok(text)
+    }
+
+    async fn search_new(&mut self, query: &str, key: String, limit: usize) -> Result<String> {
+        let mut failures = Vec::new();
+        let found = self
+            .search
+            .search(query, self.search.fetch_limit(limit), &mut failures)
+            .await?;
+        Ok(match found {
+            None if failures.is_empty() => "The daily quota of every search provider is used up, work with the sources found so far and fetch pages whose urls you know with web_fetch".to_string(),
+            None => format!("Every search provider failed: {}", failures.join("; ")),
+            Some((provider, results)) => {
+                self.searched.insert(key);
+                let mut text = self.list(query, results, limit).await;
+                text.push_str(&format!("\n\nSearched with {}", provider));
+                if !failures.is_empty() {
+                    text.push_str(&format!(" after {}", failures.join("; ")));
+                }
+                text
+            }
+        })
+    }
+}
+
+// implemented without FunctionalTool, since the results are reranked against the task the
+// agent was given, which only the history has
+#[async_trait]
+impl Tool for WebSearchTool {
+    fn definition(&self) -> Result<ToolDefinition> {
+        ToolDefinition::new::<WebSearchArgs>(
+            "web_search",
+            "This tool searches the web and returns the titles, urls, and snippets of the results with a heuristic quality score of each source, prefer the better sources. Searches are limited per day, so make each query count: combine related terms in one query instead of searching variations, and read promising results with web_fetch.",
+        )
+    }
+
+    fn third_party(&self) -> bool {
+        true
+    }
+
+    async fn invoke(
+        &mut self,
+        call: &ToolCall,
+        history: &mut dyn History,
+        ctx: &ToolContext,
+    ) -> Result<()> {
+        let args: WebSearchArgs = call.args()?;
+        self.usage = ctx.usage.clone();
+        if self.task.is_empty()
+            && let Some(task) = history.iter().find_map(|message| match &**message {
+                Message::User(task) | Message::UserImages(task, _) => Some(task),
+                _ => None,
+            })
+        {
+            self.task = task.chars().take(MAX_TASK_CHARS).collect();
+        }
+        let result = match self.run(args).await {
+            Ok(result) => result,
+            Err(e) => e.to_string(),
+        };
+        history.append(Arc::new(Message::Tool {
+            id: call.id.clone(),
+            name: "web_search".to_string(),
+            result: result.into(),
+        }));
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        Reranker, SearchApi, SearchApiKind, SearchProvider, SearchQuery, SearchQuotas,
+        SearchResult, WebSearch, WebSearchArgs, WebSearchTool, normalize, parse, parse_ratings,
+        us_date,
+    };
+    use crate::llm::{EmbeddingResponse, Embeddings, TokenUsage};
+    use crate::secrets::Secret;
+    use crate::tools::{Region, WebAccess, WebPolicy};
+    use crate::{Error, Result};
+    use async_trait::async_trait;
+    use std::sync::Arc;
+
+    struct FixedSearch(&'static str, bool);
+
+    #[async_trait]
+    impl SearchProvider for FixedSearch {
+        fn name(&self) -> &str {
+            self.0
+        }
+
+        async fn search(&self, query: &SearchQuery) -> Result<Vec<SearchResult>> {
+            if !self.1 {
+                return Err(Error::RateLimited("status 429".to_string()));
+            }
+            Ok(["https://example.com/a", "https://blocked.com/b"]
+                .iter()
+                .map(|url| SearchResult {
+                    title: format!("{} from {}", query.query, self.0),
+                    url: url.to_string(),
+                    snippet: String::new(),
+                })
+                .collect())
+        }
+    }
+
+    fn args(query: &str) -> WebSearchArgs {
+        WebSearchArgs {
+            query: query.to_string(),
+            limit: None,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_rotation() -> Result<()> {
+        let path = std::env::temp_dir().join(format!("search-quota-{}.json", std::process::id()));
+        let quotas = SearchQuotas::open(&path)?;
+        let search = WebSearch::new(
+            vec![
+                (Arc::new(FixedSearch("tavily", true)), 1),
+                (Arc::new(FixedSearch("brave", false)), 5),
+                (Arc::new(FixedSearch("serper", true)), 2),
+            ],
+            quotas,
+        );
+        let mut tool = WebSearchTool::new(
+            search,
+            WebAccess::new(WebPolicy {
+                blocked_domains: vec!["blocked.com".to_string()],
+                ..Default::default()
+            }),
+        );
+
+        assert_eq!(
+            tool.run(args("solar")).await?,
+            "1. solar from tavily\n   https://example.com/a\n   quality 50/100\n\nSearched with tavily\nSearches left today: tavily 0/1, brave 5/5, serper 2/2. Prefer few precise searches once they run low."
+        );
+        // brave fails, so its quota is not used and serper answers
+        let second = tool.run(args("wind")).await?;
+        assert!(second.starts_with("1. wind from serper"));
+        assert!(second.contains(
+            "Searched with serper after brave failed: Rate limited by provider: status 429"
+        ));
+        assert!(second.contains("tavily 0/1, brave 5/5, serper 1/2"));
+        assert!(tool.run(args("hydro")).await?.contains("serper 0/2"));
+        assert!(
+            tool.run(args("coal"))
+                .await?
+                .starts_with("Every search provider failed: brave failed")
+        );
+
+        // a new run of the same day continues the counts
+        let quotas = SearchQuotas::open(&path)?;
+        assert_eq!(quotas.used("tavily"), 1);
+        assert_eq!(quotas.used("serper"), 2);
+        let search = WebSearch::new(
+            vec![(Arc::new(FixedSearch("tavily", true)), 1)],
+            quotas.clone(),
+        );
+        let mut failures = Vec::new();
+        assert!(search.search("gas", 10, &mut failures).await?.is_none());
+        // and the next day starts over
+        assert_eq!(quotas.used_on("tavily", "2999-01-01"), 0);
+        assert!(quotas.change("tavily", "2999-01-01", |used| {
+            *used += 1;
+            true
+        })?);
+        assert_eq!(quotas.used_on("tavily", "2999-01-01"), 1);
+        assert_eq!(quotas.used("serper"), 0);
+        std::fs::remove_file(&path)?;
+
+        // runs sharing the file add up their searches
+        let (first, second) = (SearchQuotas::open(&path)?, SearchQuotas::open(&path)?);
+        assert!(first.reserve("brave", 2)?);
+        assert!(second.reserve("brave", 2)?);
+        assert!(!first.reserve("brave", 2)?);
+        second.release("brave")?;
+        assert!(first.reserve("brave", 2)?);
+        assert_eq!(SearchQuotas::open(&path)?.used("brave"), 2);
+        std::fs::remove_file(&path)?;
+        std::fs::remove_file(path.with_extension("json.lock"))?;
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_dedup() -> Result<()> {
+        assert_eq!(
+            normalize("What are the solar panel prices in Germany?"),
+            normalize("germany: Solar panels, prices")
+        );
+        assert_ne!(normalize("solar prices"), normalize("wind prices"));
+        assert_eq!(normalize("news"), "new");
+        assert_eq!(normalize("glass"), "glass");
+        assert_eq!(normalize("the"), "the");
+
+        let search = WebSearch::new(
+            vec![(Arc::new(FixedSearch("tavily", true)), 3)],
+            SearchQuotas::new(),
+        );
+        let mut first = WebSearchTool::new(search.clone(), WebAccess::new(Default::default()));
+        let mut second = WebSearchTool::new(search, WebAccess::new(Default::default()));
+        assert!(
+            first
+                .run(args("Solar panel prices"))
+                .await?
+                .contains("tavily 2/3")
+        );
+        let again = first.run(args("solar panels: prices?")).await?;
+        assert!(again.starts_with("1. Solar panel prices from tavily"));
+        assert!(again.contains(
+            "You already searched for \"Solar panel prices\" with tavily, these are the results of that search and no search was used up"
+        ));
+        assert!(again.contains("tavily 2/3"));
+        let other = second.run(args("prices of solar panels")).await?;
+        assert!(other.contains("Another agent of this research already searched"));
+        // an earlier search that found fewer results than it asked for has found them all, one
+        // that was cut off is repeated when more results are asked for
+        let limit = |limit| WebSearchArgs {
+            limit: Some(limit),
+            ..args("solar panel prices")
+        };
+        assert!(second.run(limit(15)).await?.contains("tavily 2/3"));
+        let mut third = WebSearchTool::new(
+            WebSearch::new(
+                vec![(Arc::new(FixedSearch("tavily", true)), 3)],
+                SearchQuotas::new(),
+            ),
+            WebAccess::new(Default::default()),
+        );
+        assert!(third.run(limit(1)).await?.contains("tavily 2/3"));
+        assert!(third.run(limit(1)).await?.contains("tavily 2/3"));
+        assert!(third.run(limit(15)).await?.contains("tavily 1/3"));
+        Ok(())
+    }
+
+    struct Energy;
+
+    #[async_trait]
+    impl SearchProvider for Energy {
+        fn name(&self) -> &str {
+            "brave"
+        }
+
+        async fn search(&self, _: &SearchQuery) -> Result<Vec<SearchResult>> {
+            Ok(["wind farms", "solar panels", "solar tax credits"]
+                .iter()
+                .enumerate()
+                .map(|(i, title)| SearchResult {
+                    title: title.to_string(),
+                    url: format!("https://example.com/{}", i),
+                    snippet: String::new(),
+                })
+                .collect())
+        }
+    }
+
+    // embeds texts by the energy sources they mention
+    struct Keywords;
+
+    #[async_trait]
+    impl Embeddings for Keywords {
+        async fn embed(&self, inputs: &[String]) -> Result<EmbeddingResponse> {
+            Ok(EmbeddingResponse {
+                embeddings: inputs
+                    .iter()
+                    .map(|text| {
+                        ["solar", "wind", "tax"]
+                            .iter()
+                            .map(|word| text.contains(word) as u8 as f32)
+                            .collect()
+                    })
+                    .collect(),
+                usage: TokenUsage {
+                    requests: 1,
+                    prompt_tokens: inputs.len() as u64,
+                    completion_tokens: 0,
+                },
+            })
+        }
+    }
+
+    #[tokio::test]
+    async fn test_rerank() -> Result<()> {
+        let search = WebSearch::new(vec![(Arc::new(Energy), 10)], SearchQuotas::new())
+            .rerank(Reranker::Embeddings(Arc::new(Keywords)), 2);
+        let mut tool = WebSearchTool::new(search, WebAccess::new(Default::default()));
+        tool.task = "Compare the cost of solar power per household".to_string();
+        let text = tool.run(args("energy costs")).await?;
+        assert!(text.starts_with(
+            "1. solar panels\n   https://example.com/1\n   quality 50/100\n2. solar tax credits\n   https://example.com/2\n   quality 50/100\n\nShowing the 2 of 3 results most relevant to your task"
+        ));
+        // a smaller limit keeps fewer
+        let text = tool
+            .run(WebSearchArgs {
+                limit: Some(1),
+                ..args("energy costs")
+            })
+            .await?;
+        assert!(text.starts_with(
+            "1. solar panels\n   https://example.com/1\n   quality 50/100\n\nShowing the 1 of 3"
+        ));
+        assert_eq!(tool.usage.total().prompt_tokens, 8);
+
+        assert_eq!(
+            parse_ratings("1: 3\n#2: 9.5\nthe rest are off topic\n7: 10\n3: none", 3),
+            vec![3.0, 9.5, 0.0]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_request() -> Result<()> {
+        let query = SearchQuery {
+            query: "heat pumps".to_string(),
+            limit: 5,
+            since: Some("2024-01-05".to_string()),
+            region: Some(Region {
+                language: Some("de".to_string()),
+                ..Region::new("de")
+            }),
+        };
+        let http = Default::default();
+        let body = |kind| -> Result<serde_json::Value> {
+            let request = SearchApi::new(kind, Secret::new("key"), &http)?
+                .request(&query)?
+                .build()
+                .unwrap();
+            Ok(serde_json::from_slice(
+                request.body().and_then(|body| body.as_bytes()).unwrap(),
+            )?)
+        };
+        let tavily = body(SearchApiKind::Tavily)?;
+        assert_eq!(tavily["start_date"], "2024-01-05");
+        assert_eq!(tavily["country"], "germany");
+        let serper = body(SearchApiKind::Serper)?;
+        assert_eq!(serper["tbs"], "cdr:1,cd_min:1/5/2024");
+        assert_eq!((&serper["gl"], &serper["hl"]), (&"de".into(), &"de".into()));
+        let brave = SearchApi::new(SearchApiKind::Brave, Secret::new("key"), &http)?
+            .request(&query)?
+            .build()
+            .unwrap();
+        assert!(brave.url().as_str().contains("&freshness=2024-01-05to"));
+        assert!(brave.url().as_str().ends_with("&country=de&search_lang=de"));
+        assert_eq!(us_date("2024-11-30"), "11/30/2024");
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse() -> Result<()> {
+        let tavily = br#"{"query": "q", "results": [{"title": "T", "url": "https://a.com", "content": "text", "score": 0.9}]}"#;
+        let brave = br#"{"type": "search", "web": {"results": [{"title": "B", "url": "https://b.com", "description": "the <strong>solar</strong> share"}]}}"#;
+        let serper = br#"{"searchParameters": {}, "organic": [{"title": "S", "link": "https://c.com", "snippet": "s", "position": 1}]}"#;
+        assert_eq!(parse(SearchApiKind::Tavily, tavily)?[0].snippet, "text");
+        assert_eq!(
+            parse(SearchApiKind::Brave, brave)?[0].snippet,
+            "the solar share"
+        );
+        assert_eq!(
+            parse(SearchApiKind::Serper, serper)?[0].url,
+            "https://c.com"
+        );
+        assert!(parse(SearchApiKind::Brave, br#"{"type": "search"}"#)?.is_empty());
+        Ok(())
+    }
+}
@@ -1,6 +1,6 @@
-use crate::Result;
 use crate::llm::{CompletionRequest, LLM, Message};
-use crate::tools::{Tool, ToolCall, ToolDefinition};
+use crate::tools::{Tool, ToolCall, ToolContext, ToolDefinition};
+use crate::{Error, History, Result};
 use async_trait::async_trait;
 use std::sync::Arc;
 
@@ -14,30 +14,65 @@ impl SummarizeHistory {
         Box::new(Self { llm, keep_last })
     }
 
-    pub async fn summarize_history(&self, mut messages: Vec<Message>) -> Result<Vec<Message>> {
+    pub async fn summarize_history(&self, history: &mut dyn History) -> Result<()> {
         // assume that the first two messages are the system and user prompt which contains the task instructions
-        if messages.len() < 2 + self.keep_last {
-            return Ok(messages);
+        if history.len() < 2 + self.keep_last {
+            return Ok(());
         }
 
-        let last_messages = messages.split_off(messages.len() - self.keep_last);
+        let split = history.len() - self.keep_last;
+        let last_messages = history.iter().skip(split).cloned().collect::<Vec<_>>();
+        let older = history.iter().take(split).cloned().collect::<Vec<_>>();
 
-        messages.push(Message::User(PROMPT.to_string()));
+        // the history that overflowed the context can be too long to summarize too, then the
+        // older half of what is left after the task is left out until the request fits, starting
+        // at a message other than a tool result so that no result is sent without its call
+        let mut skip = 2;
+        let summary = loop {
+            let messages = older[..2]
+                .iter()
+                .chain(&older[skip..])
+                .cloned()
+                .chain([Arc::new(Message::User(PROMPT.to_string()))])
+                .collect::<Vec<_>>();
+            let res = self
+                .llm
+                .completion(CompletionRequest {
+                    messages: &messages,
+                    tools: &[],
+                    web_search_tool: false,
+                    prefill: None,
+                })
+                .await;
+            match res {
+                Ok(result) => break Some(result.content),
+                Err(Error::ContextOverflow(_)) if skip < older.len() => {
+                    skip += (older.len() - skip).div_ceil(2);
+                    while older
+                        .get(skip)
+                        .is_some_and(|m| matches!(m.as_ref(), Message::Tool { .. }))
+                    {
+                        skip += 1;
+                    }
+                }
+                Err(Error::ContextOverflow(_)) => break None,
+                Err(e) => return Err(e),
+            }
+        };
 
-        let result = self
-            .llm
-            .completion(CompletionRequest {
-                messages: &messages,
-                tools: &vec![],
-                web_search_tool: false,
-            })
-            .await?;
-
-        let _ = messages.split_off(2);
-        messages.push(Message::Assistant(result.content, vec![]));
-        messages.extend(last_messages.into_iter());
+        history.truncate(2);
+        if skip > 2 {
+            history.append(Arc::new(Message::Developer(format!(
+                "[{} earlier messages of this conversation were too long to summarize and are left out, the results you need from them should be in your notes or in the tools you stored them with]",
+                skip - 2
+            ))));
+        }
+        if let Some(summary) = summary {
+            history.append(Arc::new(Message::Assistant(summary, vec![])));
+        }
+        last_messages.into_iter().for_each(|m| history.append(m));
 
-        Ok(messages)
+        Ok(())
     }
 }
 
@@ -50,16 +85,79 @@ Instructions:
 #[async_trait]
 impl Tool for SummarizeHistory {
     fn definition(&self) -> Result<ToolDefinition> {
-        return Ok(ToolDefinition::new::<()>(
+        Ok(ToolDefinition::no_args(
             "summarize_history",
             &format!(
                 "This tool will take in the chat history, and generate a concise summary that preserves the key component. This prevents the conversational history from becoming too long, and makes it easier to find the relevant information in the history. Note that the last {} messages will not be changed, only the preceding messages will be summarized. Remember that you should also use the memory tool to store key information for retrieval later. You must use this tool to prevent the history from becoming too long. It will automatically be invoked if the chat history becomes too long.",
                 self.keep_last
             ),
-        )?);
+        ))
+    }
+
+    async fn invoke(
+        &mut self,
+        _: &ToolCall,
+        history: &mut dyn History,
+        _: &ToolContext,
+    ) -> Result<()> {
+        self.summarize_history(history).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::SummarizeHistory;
+    use crate::llm::{CompletionRequest, CompletionResponse, LLM, Message};
+    use crate::{Error, Result};
+    use async_trait::async_trait;
+    use std::sync::Arc;
+
+    // overflows with more than five messages
+    struct SmallLLM;
+
+    #[async_trait]
+    impl LLM for SmallLLM {
+        async fn completion<'a>(
+            &self,
+            request: CompletionRequest<'a>,
+        ) -> Result<CompletionResponse> {
+            if request.messages.len() > 5 {
+                return Err(Error::ContextOverflow("too many tokens".to_string()));
+            }
+            Ok(CompletionResponse {
+                content: "summary".to_string(),
+                ..Default::default()
+            })
+        }
     }
 
-    async fn invoke(&mut self, _: &ToolCall, messages: Vec<Message>) -> Result<Vec<Message>> {
-        self.summarize_history(messages).await
+    #[tokio::test]
+    async fn test_summarize_too_long_history() -> Result<()> {
+        let mut history: Vec<Arc<Message>> = vec![
+            Arc::new(Message::System("system".to_string())),
+            Arc::new(Message::User("task".to_string())),
+        ];
+        for i in 0..6 {
+            history.push(Arc::new(Message::Assistant(format!("step {}", i), vec![])));
+        }
+        history.push(Arc::new(Message::User("latest".to_string())));
+
+        SummarizeHistory::new(Arc::new(SmallLLM), 1)
+            .summarize_history(&mut history)
+            .await?;
+        let texts = history
+            .iter()
+            .map(|m| match m.as_ref() {
+                Message::System(text) | Message::User(text) | Message::Developer(text) => {
+                    text.clone()
+                }
+                Message::Assistant(text, _) => text.clone(),
+                _ => String::new(),
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(texts[..2], ["system", "task"]);
+        assert!(texts[2].starts_with("[5 earlier messages"));
+        assert_eq!(texts[3..], ["summary", "latest"]);
+        Ok(())
     }
 }
@@ -0,0 +1,165 @@
+use crate::Result;
+use crate::llm::{CompletionRequest, LLM, Message, Usage};
+use crate::tools::{FunctionalTool, ToolCall, ToolContext, ToolDefinition, WebAccess};
+use async_trait::async_trait;
+use schemars::JsonSchema;
+use serde::Deserialize;
+use std::sync::Arc;
+
+const PROMPT: &str = "You translate excerpts of sources for a researcher into the language with the code {{.Language}}.
+Instructions:
+- Translate faithfully and completely. Do not summarize, comment on, or add to the text.
+- Keep numbers, dates, names, units, and the Markdown formatting unchanged. Keep the original of names of organizations, laws, and publications and add the translation in parentheses.
+- Start your reply with the ISO 639-1 code of the language of the excerpt on a line of its own, followed by the translation.";
+
+// splits a reply into the language code on its first line and the translation
+fn split_reply(reply: &str) -> (Option<String>, &str) {
+    let reply = reply.trim_start();
+    let (first, rest) = reply.split_once('\n').unwrap_or((reply, ""));
+    let code = first
+        .trim()
+        .trim_matches(|c: char| !c.is_ascii_alphabetic());
+    if (2..=3).contains(&code.len()) && code.chars().all(|c| c.is_ascii_alphabetic()) {
+        (Some(code.to_ascii_lowercase()), rest.trim())
+    } else {
+        (None, reply.trim())
+    }
+}
+
+// translates excerpts of non-English sources into the language of the report, the translation
+// runs as a separate request without the research history so a cheap model can handle it
+pub struct TranslateTool {
+    llm: Arc<dyn LLM + Send + Sync>,
+    language: String,
+    access: Arc<WebAccess>,
+}
+
+impl TranslateTool {
+    pub fn new(
+        llm: Arc<dyn LLM + Send + Sync>,
+        language: &str,
+        access: Arc<WebAccess>,
+    ) -> Box<Self> {
+        Box::new(Self {
+            llm,
+            language: language.to_string(),
+            access,
+        })
+    }
+
+    async fn translate(&self, args: &TranslateArgs, usage: &Usage) -> Result<String> {
+        let mut text = args.text.clone();
+        if let Some(language) = &args.source_language {
+            text = format!("The excerpt is in the language {}.\n\n{}", language, text);
+        }
+        let res = self
+            .llm
+            .completion(CompletionRequest {
+                messages: &vec![
+                    Arc::new(Message::System(
+                        PROMPT.replace("{{.Language}}", &self.language),
+                    )),
+                    Arc::new(Message::User(text)),
+                ],
+                tools: &[],
+                web_search_tool: false,
+                prefill: None,
+            })
+            .await?;
+        usage.record(res.usage);
+
+        let (detected, translation) = split_reply(&res.content);
+        let language = detected
+            .or_else(|| args.source_language.clone())
+            .unwrap_or_else(|| "another language".to_string());
+        if language == self.language.to_ascii_lowercase() {
+            return Ok(format!(
+                "The text is already in the language {}, it was not translated.",
+                self.language
+            ));
+        }
+
+        let citation = match &args.source_url {
+            Some(url) => {
+                self.access.record_translation(url, &language);
+                format!(
+                    "Cite it as {} (translated from {}), and quote the original next to the translation for important statements.",
+                    url, language
+                )
+            }
+            None => format!(
+                "Mark citations of it as translated from {}, and quote the original next to the translation for important statements.",
+                language
+            ),
+        };
+        Ok(format!(
+            "Machine translation from {}. {}\n\n{}",
+            language, citation, translation
+        ))
+    }
+}
+
+#[derive(Deserialize, JsonSchema)]
+struct TranslateArgs {
+    /// the excerpt to translate, such as the relevant paragraphs of a page read with web_fetch
+    text: String,
+    /// the url of the source the excerpt is from, so citations of it can be marked as translated
+    #[serde(default)]
+    source_url: Option<String>,
+    /// the language of the excerpt if known, e.g. "ja" or "German"
+    #[serde(default)]
+    source_language: Option<String>,
+}
+
+#[async_trait]
+impl FunctionalTool for TranslateTool {
+    fn definition(&self) -> Result<ToolDefinition> {
+        ToolDefinition::new::<TranslateArgs>(
+            "translate",
+            &format!(
+                "This tool translates an excerpt of a source into the language {} of the report. Use it for primary sources in other languages, such as foreign government documents, local news, and company filings, and only pass the relevant parts of long pages. Citations of translated sources must be marked as translated.",
+                self.language
+            ),
+        )
+    }
+
+    fn third_party_fn(&self) -> bool {
+        true
+    }
+
+    async fn invoke_fn(&mut self, call: &ToolCall, ctx: &ToolContext) -> Result<Message> {
+        let args: TranslateArgs = call.args()?;
+        let result = match self.translate(&args, &ctx.usage).await {
+            Ok(result) => result,
+            // a failed translation is reported to the model, which can continue with the original
+            Err(e) => format!("The translation failed: {}", e),
+        };
+
+        Ok(Message::Tool {
+            id: call.id.clone(),
+            name: "translate".to_string(),
+            result: result.into(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::split_reply;
+
+    #[test]
+    fn test_split_reply() {
+        assert_eq!(
+            split_reply("de\nThe central bank raised rates.\n"),
+            (Some("de".to_string()), "The central bank raised rates.")
+        );
+        assert_eq!(
+            split_reply("**JA**\n\nRevenue grew by 12%."),
+            (Some("ja".to_string()), "Revenue grew by 12%.")
+        );
+        assert_eq!(
+            split_reply("The central bank raised rates."),
+            (None, "The central bank raised rates.")
+        );
+    }
+}
@@ -0,0 +1,362 @@
+use crate::llm::{Embeddings, Message, TokenUsage, Usage};
+use crate::tools::{FunctionalTool, ToolCall, ToolContext, ToolDefinition};
+use crate::{ContextProvider, History, Priority, Result};
+use async_trait::async_trait;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use tokio::sync::OnceCell;
+
+// embedding endpoints limit the number of inputs per request
+const EMBED_BATCH: usize = 256;
+
+// the recalled passages are found with the start of the latest message of the agent
+const MAX_RECALL_QUERY_CHARS: usize = 1000;
+// passages are a few hundred words each, more than this would crowd the context of the agent
+const MAX_SEARCH_PASSAGES: usize = 20;
+
+#[derive(Serialize, Deserialize)]
+struct Entry {
+    source: String,
+    text: String,
+    embedding: Vec<f32>,
+}
+
+// in-memory store of embedded text chunks, searched by cosine similarity
+pub struct VectorMemory {
+    embeddings: Arc<dyn Embeddings + Send + Sync>,
+    entries: Mutex<Vec<Entry>>,
+    // agents sharing the memory often search for the same thing, so query embeddings are kept
+    queries: Mutex<HashMap<String, Arc<OnceCell<Vec<f32>>>>>,
+}
+
+fn normalize_query(query: &str) -> String {
+    query.split_whitespace().collect::<Vec<_>>().join(" ")
+}
+
+pub(crate) fn cosine_similarity(a: &[f32], b: &[f32]) -> f32 {
+    let dot = a.iter().zip(b).map(|(x, y)| x * y).sum::<f32>();
+    let norm = |v: &[f32]| v.iter().map(|x| x * x).sum::<f32>().sqrt();
+    let denom = norm(a) * norm(b);
+    if denom == 0.0 { 0.0 } else { dot / denom }
+}
+
+impl VectorMemory {
+    pub fn new(embeddings: Arc<dyn Embeddings + Send + Sync>) -> Arc<Self> {
+        Arc::new(Self {
+            embeddings,
+            entries: Mutex::new(Vec::new()),
+            queries: Mutex::new(HashMap::new()),
+        })
+    }
+
+    // returns the usage of the embedding requests
+    pub async fn add(&self, source: &str, chunks: Vec<String>) -> Result<TokenUsage> {
+        let mut usage = TokenUsage::default();
+        for batch in chunks.chunks(EMBED_BATCH) {
+            let res = self.embeddings.embed(batch).await?;
+            usage += res.usage;
+            self.entries
+                .lock()
+                .unwrap()
+                .extend(
+                    batch
+                        .iter()
+                        .zip(res.embeddings)
+                        .map(|(text, embedding)| Entry {
+                            source: source.to_string(),
+                            text: text.clone(),
+                            embedding,
+                        }),
+                );
+        }
+        Ok(usage)
+    }
+
+    pub fn remove_source(&self, source: &str) {
+        self.entries
+            .lock()
+            .unwrap()
+            .retain(|entry| entry.source != source);
+    }
+
+    pub fn save(&self, path: &Path) -> Result<()> {
+        let file = std::io::BufWriter::new(std::fs::File::create(path)?);
+        serde_json::to_writer(file, &*self.entries.lock().unwrap())?;
+        Ok(())
+    }
+
+    // the embeddings must come from the same model that created the saved entries
+    pub fn load(embeddings: Arc<dyn Embeddings + Send + Sync>, path: &Path) -> Result<Arc<Self>> {
+        let file = std::io::BufReader::new(std::fs::File::open(path)?);
+        Ok(Arc::new(Self {
+            embeddings,
+            entries: Mutex::new(serde_json::from_reader(file)?),
+            queries: Mutex::new(HashMap::new()),
+        }))
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.lock().unwrap().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    // returns the source and text of the limit most similar chunks, most similar first, the
+    // embedding of a query that is not cached yet is recorded in usage
+    pub async fn search(
+        &self,
+        query: &str,
+        limit: usize,
+        usage: &Usage,
+    ) -> Result<Vec<(String, String)>> {
+        let query = normalize_query(query);
+        let embedding = self
+            .queries
+            .lock()
+            .unwrap()
+            .entry(query.clone())
+            .or_default()
+            .clone();
+        let query = embedding
+            .get_or_try_init(|| async {
+                let res = self.embeddings.embed(std::slice::from_ref(&query)).await?;
+                usage.record(res.usage);
+                Ok::<_, crate::Error>(res.embeddings.into_iter().next().unwrap_or_default())
+            })
+            .await?;
+
+        let entries = self.entries.lock().unwrap();
+        let mut scored = entries
+            .iter()
+            .map(|entry| (cosine_similarity(query, &entry.embedding), entry))
+            .collect::<Vec<_>>();
+        scored.sort_by(|a, b| b.0.total_cmp(&a.0));
+
+        Ok(scored
+            .into_iter()
+            .take(limit)
+            .map(|(_, entry)| (entry.source.clone(), entry.text.clone()))
+            .collect())
+    }
+
+    pub fn search_tool(self: &Arc<Self>) -> Box<SearchDocumentsTool> {
+        Box::new(SearchDocumentsTool(self.clone()))
+    }
+
+    // a context provider that shows the agent the limit passages most similar to what it is
+    // working on before every request, the embeddings of the queries are recorded in usage
+    pub fn recall(self: &Arc<Self>, limit: usize, usage: Arc<Usage>) -> Box<MemoryRecall> {
+        Box::new(MemoryRecall {
+            memory: self.clone(),
+            limit,
+            usage,
+        })
+    }
+}
+
+// recalls passages of the documents for the latest message of the agent, or for the task before
+// the agent said anything, as context of low priority that is left out first when the context
+// budget runs short
+pub struct MemoryRecall {
+    memory: Arc<VectorMemory>,
+    limit: usize,
+    usage: Arc<Usage>,
+}
+
+impl MemoryRecall {
+    fn query(history: &dyn History) -> Option<String> {
+        let mut latest = None;
+        for message in history.iter() {
+            match message.as_ref() {
+                Message::Assistant(content, _) if !content.trim().is_empty() => {
+                    latest = Some(content)
+                }
+                Message::User(content) | Message::UserImages(content, _) if latest.is_none() => {
+                    latest = Some(content)
+                }
+                _ => {}
+            }
+        }
+        latest.map(|query| query.chars().take(MAX_RECALL_QUERY_CHARS).collect())
+    }
+}
+
+#[async_trait]
+impl ContextProvider for MemoryRecall {
+    async fn context(&mut self, history: &dyn History) -> Result<Vec<Arc<Message>>> {
+        let Some(query) = Self::query(history) else {
+            return Ok(Vec::new());
+        };
+        if self.memory.is_empty() {
+            return Ok(Vec::new());
+        }
+        let passages = self.memory.search(&query, self.limit, &self.usage).await?;
+        let mut recalled = "<recalled_memory>\nPassages of the documents that may be relevant to your current step, search_documents finds more:\n".to_string();
+        for (source, text) in passages {
+            recalled.push_str(&format!(
+                "<passage source=\"{}\">\n{}\n</passage>\n",
+                source, text
+            ));
+        }
+        recalled.push_str("</recalled_memory>");
+        Ok(vec![Arc::new(Message::Developer(recalled))])
+    }
+
+    fn priority(&self) -> Priority {
+        Priority::Low
+    }
+}
+
+fn default_limit() -> usize {
+    5
+}
+
+#[derive(Deserialize, JsonSchema)]
+struct SearchDocumentsArgs {
+    /// what to look for in the documents, phrased as a question or description
+    query: String,
+    /// the maximum number of passages to return, defaults to 5 and at most 20
+    #[serde(default = "default_limit")]
+    limit: usize,
+}
+
+pub struct SearchDocumentsTool(Arc<VectorMemory>);
+
+#[async_trait]
+impl FunctionalTool for SearchDocumentsTool {
+    fn definition(&self) -> Result<ToolDefinition> {
+        ToolDefinition::new::<SearchDocumentsArgs>(
+            "search_documents",
+            "This tool searches the documents provided by the user and returns the passages most relevant to the query, together with the document they come from.",
+        )
+    }
+
+    fn third_party_fn(&self) -> bool {
+        true
+    }
+
+    async fn invoke_fn(&mut self, call: &ToolCall, ctx: &ToolContext) -> Result<Message> {
+        let args: SearchDocumentsArgs = call.args()?;
+        let limit = args.limit.clamp(1, MAX_SEARCH_PASSAGES);
+        let passages = self.0.search(&args.query, limit, &ctx.usage).await?;
+
+        let result = if passages.is_empty() {
+            "no documents were provided".to_string()
+        } else {
+            passages
+                .into_iter()
+                .map(|(source, text)| {
+                    format!("<passage source=\"{}\">\n{}\n</passage>", source, text)
+                })
+                .collect::<Vec<_>>()
+                .join("\n")
+        };
+
+        Ok(Message::Tool {
+            id: call.id.clone(),
+            name: "search_documents".to_string(),
+            result: result.into(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::VectorMemory;
+    use crate::llm::{EmbeddingResponse, Embeddings, Message, TokenUsage, Usage};
+    use crate::{ContextProvider, Result};
+    use async_trait::async_trait;
+    use std::sync::Arc;
+
+    // embeds a text by counting the occurrences of a few keywords
+    struct KeywordEmbeddings;
+
+    #[async_trait]
+    impl Embeddings for KeywordEmbeddings {
+        async fn embed(&self, inputs: &[String]) -> Result<EmbeddingResponse> {
+            Ok(EmbeddingResponse {
+                embeddings: inputs
+                    .iter()
+                    .map(|input| {
+                        ["rust", "python", "cooking"]
+                            .iter()
+                            .map(|k| input.matches(k).count() as f32)
+                            .collect()
+                    })
+                    .collect(),
+                usage: TokenUsage {
+                    requests: 1,
+                    prompt_tokens: 5,
+                    completion_tokens: 0,
+                },
+            })
+        }
+    }
+
+    #[tokio::test]
+    async fn test_vector_memory() -> Result<()> {
+        let memory = VectorMemory::new(Arc::new(KeywordEmbeddings));
+        let added = memory
+            .add(
+                "notes.md",
+                vec![
+                    "rust and more rust".to_string(),
+                    "python scripts".to_string(),
+                    "cooking pasta".to_string(),
+                ],
+            )
+            .await?;
+        assert_eq!(memory.len(), 3);
+        assert_eq!(added.requests, 1);
+
+        // a cached query embedding is not requested or counted again
+        let usage = Usage::new();
+        let results = memory.search("cooking", 2, &usage).await?;
+        assert_eq!(results.len(), 2);
+        assert_eq!(
+            results[0],
+            ("notes.md".to_string(), "cooking pasta".to_string())
+        );
+        assert_eq!(memory.search(" cooking\n", 2, &usage).await?, results);
+        assert_eq!(memory.queries.lock().unwrap().len(), 1);
+        assert_eq!(usage.total().requests, 1);
+
+        let path = std::env::temp_dir().join(format!("vector-memory-{}.json", std::process::id()));
+        memory.save(&path)?;
+        let loaded = VectorMemory::load(Arc::new(KeywordEmbeddings), &path)?;
+        std::fs::remove_file(&path)?;
+        assert_eq!(loaded.len(), 3);
+
+        // passages are recalled for the latest message of the agent
+        let history = vec![
+            Arc::new(Message::User("write about python".to_string())),
+            Arc::new(Message::Assistant(
+                "next I look into cooking".to_string(),
+                Vec::new(),
+            )),
+        ];
+        let recalled = memory.recall(1, usage.clone()).context(&history).await?;
+        let Message::Developer(recalled) = recalled[0].as_ref() else {
+            panic!("the recalled passages are not a developer message");
+        };
+        assert!(recalled.contains("<passage source=\"notes.md\">\ncooking pasta\n</passage>"));
+        assert!(!recalled.contains("python"));
+
+        loaded.remove_source("notes.md");
+        assert!(loaded.is_empty());
+        assert!(
+            loaded
+                .recall(1, usage.clone())
+                .context(&history)
+                .await?
+                .is_empty()
+        );
+
+        Ok(())
+    }
+}
@@ -0,0 +1,1315 @@
+use crate::llm::Message;
+use crate::sanitize::Sanitizer;
+use crate::tools::{
+    Extractors, FunctionalTool, HttpClient, HttpClientConfig, ToolCall, ToolContext,
+    ToolDefinition, Trust, extract, http, quality, tool_result,
+};
+use crate::{Error, Result};
+use async_trait::async_trait;
+use schemars::JsonSchema;
+use serde::Deserialize;
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::future::Future;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::sync::OnceCell;
+
+// markers sites use to label content that is only available to subscribers
+const PAYWALL_MARKERS: &[&str] = &[
+    "\"isaccessibleforfree\":false",
+    "\"isaccessibleforfree\": false",
+    "\"isaccessibleforfree\":\"false\"",
+    "class=\"paywall",
+    "id=\"paywall",
+];
+
+#[derive(Clone, Debug, Default)]
+pub struct WebPolicy {
+    /// if not empty, only these domains and their subdomains may be accessed
+    pub allowed_domains: Vec<String>,
+    /// domains and their subdomains that may never be accessed
+    pub blocked_domains: Vec<String>,
+    /// maximum number of requests to a single domain over the whole run
+    pub max_requests_per_domain: Option<usize>,
+    /// skip pages that are marked as available to subscribers only
+    pub respect_paywalls: bool,
+    /// wrap third party content in untrusted blocks and remove instructions aimed at the model
+    pub sanitize_content: bool,
+    /// user agent, proxy, timeouts, retries, certificates, and response cache of the requests of
+    /// the web tools
+    pub http: HttpClientConfig,
+}
+
+pub(crate) fn matches_domain(host: &str, domain: &str) -> bool {
+    let domain = domain.trim_start_matches("*.").trim_end_matches('.');
+    host.eq_ignore_ascii_case(domain)
+        || host
+            .to_ascii_lowercase()
+            .ends_with(&format!(".{}", domain.to_ascii_lowercase()))
+}
+
+impl WebPolicy {
+    // domain lists cannot be enforced on searches run by the provider, so callers should not
+    // enable provider side web search for restrictive policies
+    pub fn restricts_domains(&self) -> bool {
+        !self.allowed_domains.is_empty() || !self.blocked_domains.is_empty()
+    }
+
+    fn allows(&self, host: &str) -> std::result::Result<(), String> {
+        if self.blocked_domains.iter().any(|d| matches_domain(host, d)) {
+            return Err(format!(
+                "access to {} is blocked by the web access policy",
+                host
+            ));
+        }
+        if !self.allowed_domains.is_empty()
+            && !self.allowed_domains.iter().any(|d| matches_domain(host, d))
+        {
+            return Err(format!(
+                "{} is not on the list of domains allowed by the web access policy",
+                host
+            ));
+        }
+        Ok(())
+    }
+}
+
+// meta tags with the publication date of a page, the most specific first
+const PUBLISHED_META: &[&str] = &[
+    "citation_publication_date",
+    "citation_date",
+    "article:published_time",
+    "datepublished",
+    "dc.date.issued",
+    "dcterms.issued",
+    "dc.date",
+    "pubdate",
+];
+
+// meta tags with the doi of the article a page is, unlike the dois the page only cites
+const DOI_META: &[&str] = &[
+    "citation_doi",
+    "prism.doi",
+    "bepress_citation_doi",
+    "dc.identifier",
+];
+
+// the year a page was published, from its meta tags or its json-ld metadata
+fn published_year(html: &str) -> Option<i64> {
+    let year = |date: &str| {
+        let digits = date.trim_start_matches(['"', ' ']).get(..4)?;
+        digits
+            .bytes()
+            .all(|b| b.is_ascii_digit())
+            .then(|| digits.parse::<i64>().ok())
+            .flatten()
+            .filter(|year| *year >= 1000)
+    };
+    if let Some(date) = extract::meta_content(html, PUBLISHED_META) {
+        return year(&date);
+    }
+    let lower = html.to_ascii_lowercase();
+    let i = lower.find("\"datepublished\"")? + "\"datepublished\"".len();
+    year(lower[i..].trim_start().strip_prefix(':')?)
+}
+
+fn is_paywalled(html: &str) -> bool {
+    let html = html.to_ascii_lowercase();
+    PAYWALL_MARKERS.iter().any(|marker| html.contains(marker))
+}
+
+// the cache key of a url, fragments and tracking parameters do not change the page and a
+// trailing slash rarely does
+fn normalize_url(url: &reqwest::Url) -> String {
+    let mut url = url.clone();
+    url.set_fragment(None);
+    let query = url
+        .query_pairs()
+        .filter(|(key, _)| !key.starts_with("utm_"))
+        .map(|(key, value)| (key.into_owned(), value.into_owned()))
+        .collect::<Vec<_>>();
+    if query.is_empty() {
+        url.set_query(None);
+    } else {
+        url.query_pairs_mut().clear().extend_pairs(query);
+    }
+    url.as_str().trim_end_matches('/').to_string()
+}
+
+// the result of fetching a page, either its text or why it could not be read
+pub(crate) enum Fetched {
+    Page(String),
+    Failed(String),
+}
+
+// why a fetch was not kept for later fetches of the page
+enum NotCached {
+    Failed(String),
+    Error(Error),
+}
+
+// the pages fetched so far by their url and query, the oldest are dropped beyond
+// MAX_CACHED_PAGES
+#[derive(Default)]
+struct PageCache {
+    pages: HashMap<String, Arc<OnceCell<String>>>,
+    order: VecDeque<String>,
+}
+
+impl PageCache {
+    fn get(&mut self, key: String) -> Arc<OnceCell<String>> {
+        if let Some(page) = self.pages.get(&key) {
+            return page.clone();
+        }
+        let page = Arc::new(OnceCell::new());
+        self.pages.insert(key.clone(), page.clone());
+        self.order.push_back(key);
+        while self.order.len() > MAX_CACHED_PAGES {
+            if let Some(oldest) = self.order.pop_front() {
+                self.pages.remove(&oldest);
+            }
+        }
+        page
+    }
+}
+
+// shared by all agents of a run so that per domain request limits apply to the run as a whole,
+// and so that a page several agents want is only fetched once
+pub struct WebAccess {
+    policy: WebPolicy,
+    requests: Mutex<HashMap<String, usize>>,
+    pages: Mutex<PageCache>,
+    // the url and text of every page that was read, in the order they were fetched
+    fetched: Mutex<Vec<(String, String)>>,
+    // the url and source language of every page that was translated
+    translated: Mutex<Vec<(String, String)>>,
+    // the trust of the tools each page was read with, the most trusted one if there are several
+    trust: Mutex<BTreeMap<String, Trust>>,
+    // the year every page whose metadata has a publication date was published
+    published: Mutex<BTreeMap<String, i64>>,
+    // the doi every page whose metadata names one is the article of
+    dois: Mutex<BTreeMap<String, String>>,
+    sanitizer: Option<Arc<Sanitizer>>,
+}
+
+impl WebAccess {
+    pub fn new(policy: WebPolicy) -> Arc<Self> {
+        Arc::new(Self {
+            requests: Mutex::new(HashMap::new()),
+            pages: Mutex::new(PageCache::default()),
+            fetched: Mutex::new(Vec::new()),
+            translated: Mutex::new(Vec::new()),
+            trust: Mutex::new(BTreeMap::new()),
+            published: Mutex::new(BTreeMap::new()),
+            dois: Mutex::new(BTreeMap::new()),
+            sanitizer: policy.sanitize_content.then(Sanitizer::new),
+            policy,
+        })
+    }
+
+    pub fn fetched(&self) -> Vec<(String, String)> {
+        self.fetched.lock().unwrap().clone()
+    }
+
+    // puts back the pages read by a resumed run
+    pub fn restore_fetched(&self, pages: Vec<(String, String)>) {
+        self.fetched.lock().unwrap().extend(pages);
+    }
+
+    // records a page that was read for the sources of the run
+    pub(crate) fn record(&self, url: &str, text: &str, trust: Trust) {
+        self.fetched
+            .lock()
+            .unwrap()
+            .push((url.to_string(), text.to_string()));
+        let mut levels = self.trust.lock().unwrap();
+        let level = levels.entry(url.to_string()).or_insert(trust);
+        *level = (*level).max(trust);
+    }
+
+    pub fn trust_levels(&self) -> Vec<(String, Trust)> {
+        self.trust
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|(url, trust)| (url.clone(), *trust))
+            .collect()
+    }
+
+    pub fn trust(&self, url: &str) -> Option<Trust> {
+        self.trust.lock().unwrap().get(url).copied()
+    }
+
+    // puts back the trust levels of the pages read by a resumed run
+    pub fn restore_trust(&self, levels: Vec<(String, Trust)>) {
+        self.trust.lock().unwrap().extend(levels);
+    }
+
+    pub fn published(&self) -> Vec<(String, i64)> {
+        self.published
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|(url, year)| (url.clone(), *year))
+            .collect()
+    }
+
+    // puts back the publication years of the pages read by a resumed run
+    pub fn restore_published(&self, years: Vec<(String, i64)>) {
+        self.published.lock().unwrap().extend(years);
+    }
+
+    pub fn dois(&self) -> Vec<(String, String)> {
+        self.dois.lock().unwrap().clone().into_iter().collect()
+    }
+
+    // puts back the dois of the pages read by a resumed run
+    pub fn restore_dois(&self, dois: Vec<(String, String)>) {
+        self.dois.lock().unwrap().extend(dois);
+    }
+
+    pub fn translations(&self) -> Vec<(String, String)> {
+        self.translated.lock().unwrap().clone()
+    }
+
+    // records that a source was cited from a translation, so it can be marked as translated
+    pub(crate) fn record_translation(&self, url: &str, language: &str) {
+        let mut translated = self.translated.lock().unwrap();
+        if !translated.iter().any(|(u, _)| u == url) {
+            translated.push((url.to_string(), language.to_string()));
+        }
+    }
+
+    // checks the url against the domain lists without counting a request
+    pub(crate) fn allows(&self, url: &reqwest::Url) -> std::result::Result<(), String> {
+        let host = url
+            .host_str()
+            .ok_or_else(|| format!("{} has no host", url))?;
+        self.policy.allows(&host.to_ascii_lowercase())
+    }
+
+    // returns the page of an earlier or in flight fetch of the same url and query, or runs the
+    // fetch. Failed fetches and pages that could not be read are not cached so that the next
+    // agent can try again
+    async fn fetch_once<F>(
+        &self,
+        url: &reqwest::Url,
+        query: Option<&str>,
+        offset: usize,
+        fetch: F,
+    ) -> Result<String>
+    where
+        F: Future<Output = Result<Fetched>>,
+    {
+        let mut key = normalize_url(url);
+        if let Some(query) = query {
+            key = format!("{} {}", key, query);
+        }
+        if offset > 0 {
+            key = format!("{} @{}", key, offset);
+        }
+        let page = self.pages.lock().unwrap().get(key);
+        let res = page
+            .get_or_try_init(|| async {
+                match fetch.await {
+                    Ok(Fetched::Page(text)) => Ok(text),
+                    Ok(Fetched::Failed(reason)) => Err(NotCached::Failed(reason)),
+                    Err(e) => Err(NotCached::Error(e)),
+                }
+            })
+            .await;
+        match res {
+            Ok(text) => Ok(text.clone()),
+            Err(NotCached::Failed(reason)) => Ok(reason),
+            Err(NotCached::Error(e)) => Err(e),
+        }
+    }
+
+    pub fn policy(&self) -> &WebPolicy {
+        &self.policy
+    }
+
+    // shared by the agents of the run when the policy sanitizes content
+    pub fn sanitizer(&self) -> Option<Arc<Sanitizer>> {
+        self.sanitizer.clone()
+    }
+
+    // checks the url against the policy and counts the request towards the domain's limit
+    pub fn check(&self, url: &reqwest::Url) -> std::result::Result<(), String> {
+        let host = url
+            .host_str()
+            .ok_or_else(|| format!("{} has no host", url))?
+            .to_ascii_lowercase();
+        self.policy.allows(&host)?;
+
+        let mut requests = self.requests.lock().unwrap();
+        let count = requests.entry(host.clone()).or_default();
+        if let Some(max) = self.policy.max_requests_per_domain
+            && *count >= max
+        {
+            return Err(format!(
+                "the limit of {} requests to {} set by the web access policy was reached",
+                max, host
+            ));
+        }
+        *count += 1;
+        Ok(())
+    }
+}
+
+// reduces an html page to its visible text
+pub(crate) fn html_to_text(html: &str) -> String {
+    let mut text = String::with_capacity(html.len() / 2);
+    let mut rest = html;
+    while let Some(start) = rest.find('<') {
+        text.push_str(&rest[..start]);
+        text.push(' ');
+        rest = &rest[start..];
+
+        let lower = rest.get(..8).unwrap_or(rest).to_ascii_lowercase();
+        let end = if lower.starts_with("<script") || lower.starts_with("<style") {
+            let close = if lower.starts_with("<script") {
+                "</script>"
+            } else {
+                "</style>"
+            };
+            rest.to_ascii_lowercase()
+                .find(close)
+                .map(|i| i + close.len())
+        } else {
+            rest.find('>').map(|i| i + 1)
+        };
+
+        match end {
+            Some(end) => rest = &rest[end..],
+            None => {
+                rest = "";
+                break;
+            }
+        }
+    }
+    text.push_str(rest);
+
+    text.replace("&nbsp;", " ")
+        .replace("&amp;", "&")
+        .replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&quot;", "\"")
+        .replace("&#39;", "'")
+        .split_whitespace()
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+// the visible text before a paywall that is kept when no other version of the page is found,
+// publishers usually show the abstract or the first paragraphs
+const ABSTRACT_LEN: usize = 2000;
+
+enum Page {
+    Text(String),
+    Paywalled(String),
+    Pdf,
+    // the body of an api or a data file, returned as is rather than reduced to text
+    Data(Format, String),
+    Failed(String),
+}
+
+// data responses longer than this are cut, the model can select parts of json with a query
+pub(crate) const MAX_DATA_CHARS: usize = 20_000;
+// pages are not downloaded beyond this size
+const MAX_PAGE_BYTES: usize = 20 * 1024 * 1024;
+const MAX_REDIRECTS: usize = 10;
+const MAX_CACHED_PAGES: usize = 256;
+const MAX_OUTLINE_KEYS: usize = 30;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub(crate) enum Format {
+    Json,
+    Xml,
+    Csv,
+}
+
+// describes the top level of a json value, for responses that were cut or not matched
+fn outline(value: &serde_json::Value) -> String {
+    let keys = |fields: &serde_json::Map<String, serde_json::Value>| {
+        let mut keys = fields
+            .keys()
+            .take(MAX_OUTLINE_KEYS)
+            .map(String::as_str)
+            .collect::<Vec<_>>()
+            .join(", ");
+        if fields.len() > MAX_OUTLINE_KEYS {
+            keys.push_str(", ...");
+        }
+        keys
+    };
+    match value {
+        serde_json::Value::Object(fields) => format!("an object with the keys {}", keys(fields)),
+        serde_json::Value::Array(items) => match items.first() {
+            Some(serde_json::Value::Object(fields)) => format!(
+                "an array of {} items, the first of them with the keys {}",
+                items.len(),
+                keys(fields)
+            ),
+            _ => format!("an array of {} items", items.len()),
+        },
+        _ => "a single value".to_string(),
+    }
+}
+
+// cuts the text at the last line break before the limit
+fn cut(text: &str, limit: usize) -> Option<&str> {
+    let (end, _) = text.char_indices().nth(limit)?;
+    Some(match text[..end].rfind('\n') {
+        Some(line) if line > 0 => &text[..line],
+        _ => &text[..end],
+    })
+}
+
+impl Format {
+    // from the content type, or from the body for servers that send data as plain text
+    pub(crate) fn detect(content_type: &str, body: &[u8]) -> Option<Self> {
+        let mime = content_type.split(';').next().unwrap_or_default().trim();
+        if mime.ends_with("/json") || mime.ends_with("+json") {
+            return Some(Format::Json);
+        }
+        if matches!(
+            mime,
+            "text/csv" | "application/csv" | "text/tab-separated-values"
+        ) {
+            return Some(Format::Csv);
+        }
+        if (mime.ends_with("/xml") || mime.ends_with("+xml")) && mime != "application/xhtml+xml" {
+            return Some(Format::Xml);
+        }
+        if !matches!(mime, "" | "text/plain" | "application/octet-stream") {
+            return None;
+        }
+        let start = body
+            .iter()
+            .position(|b| !b.is_ascii_whitespace())
+            .map(|i| &body[i..])
+            .unwrap_or_default();
+        if matches!(start.first(), Some(b'{' | b'['))
+            && serde_json::from_slice::<serde::de::IgnoredAny>(body).is_ok()
+        {
+            Some(Format::Json)
+        } else if start.starts_with(b"<?xml") {
+            Some(Format::Xml)
+        } else {
+            None
+        }
+    }
+
+    fn name(&self) -> &'static str {
+        match self {
+            Format::Json => "JSON",
+            Format::Xml => "XML",
+            Format::Csv => "CSV",
+        }
+    }
+
+    // pretty-prints json and passes xml and csv through, long responses are shown in parts of
+    // MAX_DATA_CHARS characters from the offset on, the argument the tool takes the offset in is
+    // named in the response
+    pub(crate) fn render(
+        &self,
+        body: &str,
+        query: Option<&str>,
+        offset: usize,
+        offset_arg: &str,
+    ) -> String {
+        let mut notes = Vec::new();
+        let json = match self {
+            Format::Json => serde_json::from_str::<serde_json::Value>(body).ok(),
+            _ => None,
+        };
+        let (text, value) = match (&json, query) {
+            (Some(value), Some(query)) => match crate::tools::jsonpath::query(value, query) {
+                Ok(found) if found.is_empty() => {
+                    return format!(
+                        "The JSONPath {} matched nothing, the response is {}",
+                        query,
+                        outline(value)
+                    );
+                }
+                Ok(found) => {
+                    notes.push(format!(
+                        "{} value(s) matched the JSONPath {}.",
+                        found.len(),
+                        query
+                    ));
+                    let text = match found.as_slice() {
+                        [single] => serde_json::to_string_pretty(single),
+                        _ => serde_json::to_string_pretty(&found),
+                    };
+                    (text.unwrap_or_default(), Some(value))
+                }
+                Err(reason) => return reason,
+            },
+            (Some(value), None) => (
+                serde_json::to_string_pretty(value).unwrap_or_default(),
+                Some(value),
+            ),
+            (None, query) => {
+                if query.is_some() {
+                    notes.push(
+                        "The JSONPath query was ignored since the response is not valid JSON."
+                            .to_string(),
+                    );
+                }
+                (body.trim().to_string(), None)
+            }
+        };
+
+        let total = text.chars().count();
+        let start = match text.char_indices().nth(offset) {
+            Some((start, _)) => start,
+            None if offset == 0 => text.len(),
+            None => {
+                return format!(
+                    "The {} response has {} characters, there is nothing to show from offset {}.",
+                    self.name(),
+                    total,
+                    offset
+                );
+            }
+        };
+        let rest = &text[start..];
+        let shown = cut(rest, MAX_DATA_CHARS).unwrap_or(rest);
+        // the next part starts at the line after the cut
+        let end =
+            offset + shown.chars().count() + usize::from(rest[shown.len()..].starts_with('\n'));
+        let mut body = shown.to_string();
+        if offset > 0 || end < total {
+            let part = match (self, value) {
+                (Format::Csv, _) => {
+                    // every part is headed by the header row
+                    if offset > 0 {
+                        body = format!("{}\n{}", text.lines().next().unwrap_or_default(), shown);
+                    }
+                    format!(
+                        "Rows {} to {} of {} are shown",
+                        text[..start].lines().count().max(1),
+                        text[..start + shown.len()]
+                            .lines()
+                            .count()
+                            .saturating_sub(1),
+                        text.lines().count().saturating_sub(1)
+                    )
+                }
+                (_, Some(value)) => format!(
+                    "Characters {} to {} of {} are shown, the response is {}; select the parts you need with a JSONPath query",
+                    offset,
+                    end,
+                    total,
+                    outline(value)
+                ),
+                _ => format!("Characters {} to {} of {} are shown", offset, end, total),
+            };
+            notes.push(if end < total {
+                format!(
+                    "{}, request it again with the {} {} for the next part.",
+                    part, offset_arg, end
+                )
+            } else {
+                format!("{}.", part)
+            });
+        }
+        notes.insert(0, format!("{} response.", self.name()));
+        format!("{}\n\n{}", notes.join(" "), body)
+    }
+}
+
+// other versions of a page to read when it is a pdf or behind a paywall, in the order they are
+// tried. Archives hold full copies of paywalled articles, so they are only used for paywalls when
+// the policy does not respect them
+fn fallbacks(
+    url: &reqwest::Url,
+    is_pdf: bool,
+    respect_paywalls: bool,
+) -> Vec<(&'static str, reqwest::Url)> {
+    let mut fallbacks = Vec::new();
+    let host = url.host_str().unwrap_or_default().to_ascii_lowercase();
+    if (host == "arxiv.org" || host.ends_with(".arxiv.org"))
+        && let Some(id) = url.path().strip_prefix("/pdf/")
+        && let Ok(abs) = reqwest::Url::parse(&format!(
+            "https://arxiv.org/abs/{}",
+            id.trim_end_matches(".pdf")
+        ))
+    {
+        fallbacks.push(("arXiv abstract page", abs));
+    }
+    if !is_pdf
+        && !respect_paywalls
+        && let Ok(archived) = reqwest::Url::parse(&format!("https://web.archive.org/web/{}", url))
+    {
+        fallbacks.push(("archive.org snapshot", archived));
+    }
+    fallbacks
+}
+
+pub struct WebFetchTool {
+    access: Arc<WebAccess>,
+    extractors: Extractors,
+    http: HttpClient,
+}
+
+impl WebFetchTool {
+    pub fn new(access: Arc<WebAccess>) -> Result<Box<Self>> {
+        Self::with_extractors(access, Extractors::builtin())
+    }
+
+    // reads the pages of the domains the extractors are registered for with them instead of the
+    // generic pass
+    pub fn with_extractors(access: Arc<WebAccess>, extractors: Extractors) -> Result<Box<Self>> {
+        // every page a redirect leads to is checked like the one that was asked for
+        let policy = access.clone();
+        let redirects = reqwest::redirect::Policy::custom(move |attempt| {
+            if attempt.previous().len() >= MAX_REDIRECTS {
+                return attempt.error(format!("more than {} redirects", MAX_REDIRECTS));
+            }
+            match policy.check(attempt.url()) {
+                Ok(()) => attempt.follow(),
+                Err(reason) => attempt.error(reason),
+            }
+        });
+        Ok(Box::new(Self {
+            http: access
+                .policy()
+                .http
+                .client_with_redirects(Duration::from_secs(30), redirects)?,
+            access,
+            extractors,
+        }))
+    }
+
+    async fn fetch(&self, url: &str, query: Option<&str>, offset: usize) -> Result<String> {
+        let url = match reqwest::Url::parse(url) {
+            Ok(url) if matches!(url.scheme(), "http" | "https") => url,
+            _ => return Ok(format!("{} is not a valid http or https url", url)),
+        };
+        self.access
+            .fetch_once(&url, query, offset, self.fetch_page(&url, query, offset))
+            .await
+    }
+
+    // a single request, without fallbacks
+    async fn get(&self, url: &reqwest::Url) -> Result<Page> {
+        if let Err(reason) = self.access.check(url) {
+            return Ok(Page::Failed(format!(
+                "The page was not fetched: {}",
+                reason
+            )));
+        }
+        self.request(url).await
+    }
+
+    // a request that was already checked against the policy
+    async fn request(&self, url: &reqwest::Url) -> Result<Page> {
+        let res = match self.http.send(self.http.get(url.clone())).await {
+            Ok(res) => res,
+            Err(e) if e.is_redirect() => {
+                let reason = std::error::Error::source(&e)
+                    .map(|reason| reason.to_string())
+                    .unwrap_or_else(|| e.to_string());
+                return Ok(Page::Failed(format!(
+                    "The page was not fetched, {} redirects to a page that cannot be read: {}",
+                    url, reason
+                )));
+            }
+            Err(e) => {
+                return Err(Error::AgentWorkflowError(format!(
+                    "failed to fetch {}: {}",
+                    url, e
+                )));
+            }
+        };
+        let status = res.status();
+        let content_type = res
+            .headers()
+            .get(reqwest::header::CONTENT_TYPE)
+            .and_then(|value| value.to_str().ok())
+            .unwrap_or_default()
+            .to_ascii_lowercase();
+        let Some(body) = http::limited_body(res, MAX_PAGE_BYTES)
+            .await
+            .map_err(|e| Error::AgentWorkflowError(format!("failed to read {}: {}", url, e)))?
+        else {
+            return Ok(Page::Failed(format!(
+                "{} is larger than {} MB and was not read, look for a smaller version of it",
+                url,
+                MAX_PAGE_BYTES / 1024 / 1024
+            )));
+        };
+
+        if !status.is_success() {
+            return Ok(Page::Failed(format!(
+                "Fetching {} failed with status {}",
+                url, status
+            )));
+        }
+        if content_type.contains("application/pdf") || body.starts_with(b"%PDF") {
+            return Ok(Page::Pdf);
+        }
+        if let Some(format) = Format::detect(&content_type, &body) {
+            return Ok(Page::Data(
+                format,
+                String::from_utf8_lossy(&body).into_owned(),
+            ));
+        }
+        let html = String::from_utf8_lossy(&body);
+        if let Some(year) = published_year(&html) {
+            self.access
+                .published
+                .lock()
+                .unwrap()
+                .insert(url.to_string(), year);
+        }
+        if let Some(doi) = extract::meta_content(&html, DOI_META) {
+            self.access
+                .dois
+                .lock()
+                .unwrap()
+                .insert(url.to_string(), doi);
+        }
+        if is_paywalled(&html) {
+            return Ok(Page::Paywalled(self.extract(url, &html)));
+        }
+        Ok(Page::Text(self.extract(url, &html)))
+    }
+
+    fn extract(&self, url: &reqwest::Url, html: &str) -> String {
+        self.extractors
+            .find(url)
+            .and_then(|extractor| extractor.extract(url, html))
+            .unwrap_or_else(|| html_to_text(html))
+    }
+
+    async fn fetch_page(
+        &self,
+        url: &reqwest::Url,
+        query: Option<&str>,
+        offset: usize,
+    ) -> Result<Fetched> {
+        let page = match self
+            .extractors
+            .find(url)
+            .and_then(|extractor| extractor.fetch_url(url))
+        {
+            // the original page is still tried if the easier version cannot be fetched, both
+            // count as one request towards the limit of the domain of the original
+            Some(easier) => match self.access.check(url) {
+                Err(reason) => Page::Failed(format!("The page was not fetched: {}", reason)),
+                Ok(()) => {
+                    let page = match self.access.allows(&easier) {
+                        Ok(()) => self.request(&easier).await,
+                        Err(reason) => Ok(Page::Failed(reason)),
+                    };
+                    match page {
+                        Ok(Page::Text(text)) => Page::Text(text),
+                        _ => self.request(url).await?,
+                    }
+                }
+            },
+            None => self.get(url).await?,
+        };
+        match page {
+            Page::Failed(reason) => Ok(Fetched::Failed(reason)),
+            page => Ok(Fetched::Page(
+                self.read_fetched(url, query, offset, page).await?,
+            )),
+        }
+    }
+
+    // pdfs and paywalled pages are read from other versions of the page when possible, the
+    // result names the version that was read so that it can be cited correctly
+    async fn read_fetched(
+        &self,
+        url: &reqwest::Url,
+        query: Option<&str>,
+        offset: usize,
+        page: Page,
+    ) -> Result<String> {
+        let (is_pdf, visible) = match page {
+            Page::Text(text) => return Ok(self.read_page(url, text)),
+            Page::Data(format, body) => {
+                return Ok(self.read(url, format.render(&body, query, offset, "offset")));
+            }
+            Page::Failed(reason) => return Ok(reason),
+            Page::Pdf => (true, String::new()),
+            Page::Paywalled(visible) => (false, visible),
+        };
+
+        let respect_paywalls = self.access.policy().respect_paywalls;
+        for (variant, fallback) in fallbacks(url, is_pdf, respect_paywalls) {
+            // fallbacks are best effort, a failed attempt moves on to the next one
+            if let Ok(Page::Text(text)) = self.get(&fallback).await {
+                let reason = if is_pdf {
+                    "is a pdf that cannot be read"
+                } else {
+                    "is behind a paywall"
+                };
+                return Ok(self.read_page(
+                    url,
+                    format!(
+                        "Read from the {} at {} since {} {}, cite the original url.\n\n{}",
+                        variant, fallback, url, reason, text
+                    ),
+                ));
+            }
+        }
+
+        if is_pdf {
+            Ok(format!(
+                "{} is a pdf that cannot be read and no other version of it was found, look for another source",
+                url
+            ))
+        } else if !respect_paywalls {
+            Ok(self.read_page(url, visible))
+        } else if visible.is_empty() {
+            Ok(format!(
+                "The page {} is behind a paywall and was not read, look for another source",
+                url
+            ))
+        } else {
+            let visible = match visible.char_indices().nth(ABSTRACT_LEN) {
+                Some((i, _)) => &visible[..i],
+                None => &visible,
+            };
+            Ok(self.read_page(
+                url,
+                format!(
+                    "The page {} is behind a paywall, only the abstract or the beginning shown before the paywall was read, look for another source for the full content.\n\n{}",
+                    url, visible
+                ),
+            ))
+        }
+    }
+
+    fn read(&self, url: &reqwest::Url, text: String) -> String {
+        self.access.record(url.as_str(), &text, Trust::Web);
+        text
+    }
+
+    // a page of text, headed by its quality score so that the model can weigh it against the
+    // other sources
+    fn read_page(&self, url: &reqwest::Url, text: String) -> String {
+        let quality = quality::assess(url, &text, true);
+        format!("Source quality: {}\n\n{}", quality, self.read(url, text))
+    }
+}
+
+#[derive(Deserialize, JsonSchema)]
+struct WebFetchArgs {
+    /// the http or https url of the page to fetch
+    url: String,
+    /// a JSONPath selecting parts of a JSON response, e.g. $.data[?(@.year >= 2020)].value; leave empty for the whole response
+    #[serde(default)]
+    query: Option<String>,
+    /// the character offset to continue a long JSON, XML, or CSV response from, as given at the end of the previous part
+    #[serde(default)]
+    offset: Option<usize>,
+}
+
+#[async_trait]
+impl FunctionalTool for WebFetchTool {
+    fn definition(&self) -> Result<ToolDefinition> {
+        ToolDefinition::new::<WebFetchArgs>(
+            "web_fetch",
+            "This tool fetches a web page and returns its text content. Use it to read the full content of a promising search result instead of relying on search snippets. Pages are headed by a heuristic quality score of the source, rely on the better sources when they disagree. JSON, XML, and CSV responses of apis and data files are returned as data, long JSON responses can be narrowed with a JSONPath query, and the rest of a long response is read with the offset given at its end.",
+        )
+    }
+
+    fn third_party_fn(&self) -> bool {
+        true
+    }
+
+    async fn invoke_fn(&mut self, call: &ToolCall, _: &ToolContext) -> Result<Message> {
+        let args: WebFetchArgs = call.args()?;
+        Ok(tool_result(
+            call,
+            self.fetch(
+                &args.url,
+                args.query.as_deref(),
+                args.offset.unwrap_or_default(),
+            )
+            .await,
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        Fetched, Format, MAX_DATA_CHARS, WebAccess, WebPolicy, fallbacks, html_to_text,
+        is_paywalled, normalize_url, published_year,
+    };
+    use crate::{Error, Result};
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    #[tokio::test]
+    async fn test_fetch_limits() -> Result<()> {
+        // a server that redirects /start to a blocked host, announces a body that is too large
+        // for /large, and sends a body without end for /endless
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await?;
+        let addr = listener.local_addr()?;
+        tokio::spawn(async move {
+            while let Ok((mut socket, _)) = listener.accept().await {
+                tokio::spawn(async move {
+                    let mut buf = [0; 4096];
+                    let n = socket.read(&mut buf).await.unwrap_or(0);
+                    let request = String::from_utf8_lossy(&buf[..n]);
+                    let res = if request.starts_with("GET /start") {
+                        format!(
+                            "HTTP/1.1 302 Found\r\nlocation: http://localhost:{}/secret\r\ncontent-length: 0\r\nconnection: close\r\n\r\n",
+                            addr.port()
+                        )
+                    } else if request.starts_with("GET /endless") {
+                        "HTTP/1.1 200 OK\r\ncontent-type: text/html\r\nconnection: close\r\n\r\n"
+                            .to_string()
+                    } else {
+                        "HTTP/1.1 200 OK\r\ncontent-type: text/html\r\ncontent-length: 104857600\r\nconnection: close\r\n\r\n".to_string()
+                    };
+                    let _ = socket.write_all(res.as_bytes()).await;
+                    if request.starts_with("GET /endless") {
+                        let chunk = vec![b'a'; 64 * 1024];
+                        while socket.write_all(&chunk).await.is_ok() {}
+                    }
+                });
+            }
+        });
+
+        // the limits also hold when responses are cached
+        let dir = std::env::temp_dir().join(format!("fetch-limits-{}", std::process::id()));
+        for cache_dir in [None, Some(dir.clone())] {
+            let mut policy = WebPolicy {
+                blocked_domains: vec!["localhost".to_string()],
+                ..Default::default()
+            };
+            policy.http.cache_dir = cache_dir;
+            let tool = super::WebFetchTool::new(WebAccess::new(policy))?;
+
+            let redirected = tool
+                .fetch(&format!("http://{}/start", addr), None, 0)
+                .await?;
+            assert!(
+                redirected.contains("access to localhost is blocked by the web access policy"),
+                "{}",
+                redirected
+            );
+            for path in ["large", "endless"] {
+                let large = tool
+                    .fetch(&format!("http://{}/{}", addr, path), None, 0)
+                    .await?;
+                assert!(
+                    large.contains("is larger than 20 MB and was not read"),
+                    "{}",
+                    large
+                );
+            }
+        }
+        assert_eq!(std::fs::read_dir(&dir)?.count(), 0);
+        std::fs::remove_dir_all(dir)?;
+        Ok(())
+    }
+
+    // a page with an easier version counts once towards the limit of its domain, also when the
+    // easier version cannot be fetched
+    #[tokio::test]
+    async fn test_fetch_easier_version() -> Result<()> {
+        struct Easier;
+        impl crate::tools::Extractor for Easier {
+            fn extract(&self, _: &reqwest::Url, _: &str) -> Option<String> {
+                None
+            }
+            fn fetch_url(&self, url: &reqwest::Url) -> Option<reqwest::Url> {
+                url.join("/easy").ok()
+            }
+        }
+
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await?;
+        let addr = listener.local_addr()?;
+        tokio::spawn(async move {
+            while let Ok((mut socket, _)) = listener.accept().await {
+                let mut buf = [0; 4096];
+                let n = socket.read(&mut buf).await.unwrap_or(0);
+                let res = if String::from_utf8_lossy(&buf[..n]).starts_with("GET /easy") {
+                    "HTTP/1.1 404 Not Found\r\ncontent-length: 0\r\nconnection: close\r\n\r\n"
+                } else {
+                    "HTTP/1.1 200 OK\r\ncontent-type: text/html\r\ncontent-length: 23\r\nconnection: close\r\n\r\n<p>the original page</p>"
+                };
+                let _ = socket.write_all(res.as_bytes()).await;
+            }
+        });
+        let tool = super::WebFetchTool::with_extractors(
+            WebAccess::new(WebPolicy {
+                max_requests_per_domain: Some(1),
+                ..Default::default()
+            }),
+            super::Extractors::empty().register("127.0.0.1", Box::new(Easier)),
+        )?;
+
+        let page = tool
+            .fetch(&format!("http://{}/page", addr), None, 0)
+            .await?;
+        assert!(page.contains("the original page"), "{}", page);
+        Ok(())
+    }
+
+    #[test]
+    fn test_web_policy() {
+        let access = WebAccess::new(WebPolicy {
+            allowed_domains: vec!["example.com".to_string(), "*.wiki.org".to_string()],
+            blocked_domains: vec!["private.example.com".to_string()],
+            max_requests_per_domain: Some(2),
+            respect_paywalls: true,
+            sanitize_content: false,
+            ..Default::default()
+        });
+        let check = |url: &str| access.check(&reqwest::Url::parse(url).unwrap());
+
+        assert!(check("https://example.com/a").is_ok());
+        assert!(check("https://docs.example.com/a").is_ok());
+        assert!(check("https://en.wiki.org/a").is_ok());
+        assert!(check("https://private.example.com/a").is_err());
+        assert!(check("https://notexample.com/a").is_err());
+        assert!(check("https://other.org/a").is_err());
+
+        assert!(check("https://example.com/b").is_ok());
+        assert!(
+            check("https://example.com/c")
+                .unwrap_err()
+                .contains("limit")
+        );
+
+        assert!(!WebPolicy::default().restricts_domains());
+    }
+
+    #[test]
+    fn test_normalize_url() {
+        let normalize = |url: &str| normalize_url(&reqwest::Url::parse(url).unwrap());
+
+        assert_eq!(
+            normalize("https://Example.com/a/?utm_source=x&id=1#section"),
+            "https://example.com/a/?id=1"
+        );
+        assert_eq!(
+            normalize("https://example.com/a/?utm_source=x"),
+            "https://example.com/a"
+        );
+        assert_eq!(normalize("https://example.com/"), "https://example.com");
+        assert_ne!(
+            normalize("https://example.com/a?id=1"),
+            normalize("https://example.com/a?id=2")
+        );
+    }
+
+    #[test]
+    fn test_fallbacks() {
+        let variants = |url: &str, is_pdf, respect_paywalls| {
+            fallbacks(&reqwest::Url::parse(url).unwrap(), is_pdf, respect_paywalls)
+                .into_iter()
+                .map(|(variant, url)| format!("{} {}", variant, url))
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(
+            variants("https://arxiv.org/pdf/2301.00001v2.pdf", true, true),
+            vec!["arXiv abstract page https://arxiv.org/abs/2301.00001v2"]
+        );
+        assert!(variants("https://example.com/report.pdf", true, false).is_empty());
+        assert!(variants("https://news.example.com/a", false, true).is_empty());
+        assert_eq!(
+            variants("https://news.example.com/a", false, false),
+            vec!["archive.org snapshot https://web.archive.org/web/https://news.example.com/a"]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_fetch_once() -> Result<()> {
+        let access = WebAccess::new(WebPolicy::default());
+        let fetches = AtomicUsize::new(0);
+        let fetch = |result: Result<Fetched>| async {
+            fetches.fetch_add(1, Ordering::SeqCst);
+            tokio::time::sleep(std::time::Duration::from_millis(50)).await;
+            result
+        };
+        let url = |url: &str| reqwest::Url::parse(url).unwrap();
+
+        let (a, b) = (url("https://example.com/a"), url("https://example.com/a#b"));
+        let (a, b) = tokio::join!(
+            access.fetch_once(&a, None, 0, fetch(Ok(Fetched::Page("a".to_string())))),
+            access.fetch_once(&b, None, 0, fetch(Ok(Fetched::Page("b".to_string())))),
+        );
+        assert_eq!((a?, b?), ("a".to_string(), "a".to_string()));
+        assert_eq!(fetches.load(Ordering::SeqCst), 1);
+
+        let failed = access
+            .fetch_once(
+                &url("https://example.com/c"),
+                None,
+                0,
+                fetch(Err(Error::AgentWorkflowError("failed".to_string()))),
+            )
+            .await;
+        assert!(failed.is_err());
+        // neither are pages that could not be read
+        let denied = access
+            .fetch_once(
+                &url("https://example.com/c"),
+                None,
+                0,
+                fetch(Ok(Fetched::Failed("status 429".to_string()))),
+            )
+            .await?;
+        assert_eq!(denied, "status 429");
+        let retried = access
+            .fetch_once(
+                &url("https://example.com/c"),
+                None,
+                0,
+                fetch(Ok(Fetched::Page("c".to_string()))),
+            )
+            .await?;
+        assert_eq!(retried, "c");
+        assert_eq!(fetches.load(Ordering::SeqCst), 4);
+
+        // the oldest pages are dropped once the cache is full
+        let mut cache = super::PageCache::default();
+        let first = cache.get("https://example.com/0".to_string());
+        assert!(std::sync::Arc::ptr_eq(
+            &first,
+            &cache.get("https://example.com/0".to_string())
+        ));
+        for i in 1..=super::MAX_CACHED_PAGES {
+            cache.get(format!("https://example.com/{}", i));
+        }
+        assert_eq!(cache.pages.len(), super::MAX_CACHED_PAGES);
+        assert!(!cache.pages.contains_key("https://example.com/0"));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_published_year() {
+        assert_eq!(
+            published_year("<meta property=\"article:published_time\" content=\"2021-05-04\">"),
+            Some(2021)
+        );
+        assert_eq!(
+            published_year(
+                "<script type=\"application/ld+json\">{\"datePublished\": \"2019-11-30\"}</script>"
+            ),
+            Some(2019)
+        );
+        // years the text mentions are no publication date
+        assert_eq!(published_year("<p>EV sales in 2023 rose</p>"), None);
+        assert_eq!(
+            published_year("<meta name=\"pubdate\" content=\"yesterday\">"),
+            None
+        );
+    }
+
+    #[test]
+    fn test_html_to_text() {
+        assert_eq!(
+            html_to_text(
+                "<html><head><style>p { color: red }</style><SCRIPT>var a = 1 < 2;</SCRIPT></head><body><p>Hello&nbsp;<b>world</b> &amp; more</p></body></html>"
+            ),
+            "Hello world & more"
+        );
+        assert_eq!(html_to_text("plain <unclosed"), "plain");
+        assert!(is_paywalled(
+            "<script type=\"application/ld+json\">{\"isAccessibleForFree\": false}</script>"
+        ));
+        assert!(!is_paywalled("<p>free</p>"));
+    }
+
+    #[test]
+    fn test_data_formats() {
+        let detect = |content_type: &str, body: &str| Format::detect(content_type, body.as_bytes());
+        assert_eq!(
+            detect("application/json; charset=utf-8", ""),
+            Some(Format::Json)
+        );
+        assert_eq!(detect("application/vnd.api+json", ""), Some(Format::Json));
+        assert_eq!(detect("text/csv", ""), Some(Format::Csv));
+        assert_eq!(detect("application/rss+xml", ""), Some(Format::Xml));
+        assert_eq!(detect("application/xhtml+xml", "<?xml"), None);
+        assert_eq!(detect("text/html", "{}"), None);
+        assert_eq!(detect("text/plain", " [1, 2]"), Some(Format::Json));
+        assert_eq!(detect("text/plain", "{not json"), None);
+        assert_eq!(detect("", "<?xml version=\"1.0\"?><a/>"), Some(Format::Xml));
+
+        let body = r#"{"data": [{"year": 2019, "value": 1}, {"year": 2021, "value": 2}]}"#;
+        let all = Format::Json.render(body, None, 0, "offset");
+        assert!(all.starts_with("JSON response.\n\n{\n  \"data\""));
+        let query = Format::Json.render(body, Some("$.data[?(@.year >= 2020)].value"), 0, "offset");
+        assert!(query.contains("1 value(s) matched"));
+        assert!(query.ends_with("\n\n2"));
+        assert!(
+            Format::Json
+                .render(body, Some("$.rows"), 0, "offset")
+                .contains("matched nothing, the response is an object with the keys data")
+        );
+        assert!(
+            Format::Json
+                .render(body, Some("$.data["), 0, "offset")
+                .contains("invalid JSONPath")
+        );
+
+        let csv = (0..5_000)
+            .map(|i| format!("{},{}", i, i * 2))
+            .collect::<Vec<_>>()
+            .join("\n");
+        let csv = format!("n,double\n{}", csv);
+        let first = Format::Csv.render(&csv, None, 0, "offset");
+        assert!(first.len() < MAX_DATA_CHARS + 200);
+        assert!(first.contains("Rows 1 to "));
+        assert!(first.contains(" of 5000 are shown"));
+        assert!(first.lines().last().unwrap().contains(','));
+
+        // the next part starts at the row after the last one shown and repeats the header
+        let offset = first
+            .split("with the offset ")
+            .nth(1)
+            .and_then(|rest| rest.split(' ').next())
+            .and_then(|offset| offset.parse::<usize>().ok())
+            .unwrap();
+        let last = first.lines().last().unwrap().split(',').next().unwrap();
+        let next = Format::Csv.render(&csv, None, offset, "offset");
+        let mut lines = next.split("\n\n").nth(1).unwrap().lines();
+        assert_eq!(lines.next(), Some("n,double"));
+        assert_eq!(
+            lines
+                .next()
+                .unwrap()
+                .split(',')
+                .next()
+                .unwrap()
+                .parse::<usize>(),
+            Ok(last.parse::<usize>().unwrap() + 1)
+        );
+        assert!(
+            Format::Csv
+                .render(&csv, None, 1_000_000, "offset")
+                .contains("nothing to show from offset 1000000")
+        );
+
+        // limits count characters rather than bytes
+        let text = "ü".repeat(MAX_DATA_CHARS + 10);
+        let xml = Format::Xml.render(&text, None, 0, "offset");
+        assert!(xml.contains(&format!(
+            "Characters 0 to {} of {} are shown",
+            MAX_DATA_CHARS,
+            MAX_DATA_CHARS + 10
+        )));
+        assert!(
+            Format::Xml
+                .render(&text, None, MAX_DATA_CHARS, "offset")
+                .ends_with(&"ü".repeat(10))
+        );
+    }
+}
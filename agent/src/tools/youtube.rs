@@ -0,0 +1,604 @@
+use crate::llm::Message;
+use crate::tools::{
+    FunctionalTool, HttpClient, Tool, ToolCall, ToolContext, ToolDefinition, Trust, WebAccess,
+    tool_result,
+};
+use crate::{Error, Result};
+use async_trait::async_trait;
+use schemars::JsonSchema;
+use serde::Deserialize;
+use std::sync::Arc;
+use std::time::Duration;
+
+const WATCH_URL: &str = "https://www.youtube.com/watch";
+const PLAYER_RESPONSE: &str = "ytInitialPlayerResponse = ";
+// captions are merged into passages of about this length, which keeps the ranges citable
+// without a line per caption
+const PASSAGE_MS: u64 = 30_000;
+const DESCRIPTION_CHARS: usize = 1500;
+
+#[derive(Deserialize, Default)]
+#[serde(rename_all = "camelCase")]
+struct PlayerResponse {
+    #[serde(default)]
+    playability_status: Option<Playability>,
+    #[serde(default)]
+    video_details: Option<VideoDetails>,
+    #[serde(default)]
+    microformat: Option<Microformat>,
+    #[serde(default)]
+    captions: Option<Captions>,
+}
+
+#[derive(Deserialize)]
+struct Playability {
+    status: String,
+    #[serde(default)]
+    reason: Option<String>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct VideoDetails {
+    title: String,
+    #[serde(default)]
+    author: String,
+    #[serde(default)]
+    length_seconds: String,
+    #[serde(default)]
+    view_count: String,
+    #[serde(default)]
+    short_description: String,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Microformat {
+    player_microformat_renderer: MicroformatRenderer,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct MicroformatRenderer {
+    #[serde(default)]
+    publish_date: Option<String>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Captions {
+    player_captions_tracklist_renderer: Tracklist,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Tracklist {
+    #[serde(default)]
+    caption_tracks: Vec<Track>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Track {
+    base_url: String,
+    language_code: String,
+    #[serde(default)]
+    name: Option<TrackName>,
+    // asr for captions generated by speech recognition
+    #[serde(default)]
+    kind: Option<String>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct TrackName {
+    #[serde(default)]
+    simple_text: Option<String>,
+    #[serde(default)]
+    runs: Vec<Run>,
+}
+
+#[derive(Deserialize)]
+struct Run {
+    text: String,
+}
+
+impl Track {
+    fn name(&self) -> String {
+        match &self.name {
+            Some(TrackName {
+                simple_text: Some(text),
+                ..
+            }) => text.clone(),
+            Some(name) if !name.runs.is_empty() => {
+                name.runs.iter().map(|run| run.text.as_str()).collect()
+            }
+            _ => self.language_code.clone(),
+        }
+    }
+
+    fn generated(&self) -> bool {
+        self.kind.as_deref() == Some("asr")
+    }
+}
+
+// the json3 format of the timedtext endpoint
+#[derive(Deserialize)]
+struct Timedtext {
+    #[serde(default)]
+    events: Vec<Caption>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Caption {
+    #[serde(default)]
+    t_start_ms: u64,
+    #[serde(default)]
+    d_duration_ms: u64,
+    #[serde(default)]
+    segs: Vec<Segment>,
+}
+
+#[derive(Deserialize)]
+struct Segment {
+    #[serde(default)]
+    utf8: String,
+}
+
+// the id of a video from its id or any of the url forms youtube uses
+fn video_id(video: &str) -> Option<String> {
+    let video = video.trim();
+    let valid = |id: &str| {
+        id.len() == 11
+            && id
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+    };
+    if valid(video) {
+        return Some(video.to_string());
+    }
+
+    let url = reqwest::Url::parse(video)
+        .or_else(|_| reqwest::Url::parse(&format!("https://{}", video)))
+        .ok()?;
+    let host = url.host_str()?.to_ascii_lowercase();
+    let id = if host == "youtu.be" {
+        url.path_segments()?.next()?.to_string()
+    } else if host == "youtube.com" || host.ends_with(".youtube.com") {
+        let mut segments = url.path_segments()?;
+        match segments.next()? {
+            "watch" => url
+                .query_pairs()
+                .find(|(key, _)| key == "v")
+                .map(|(_, id)| id.into_owned())?,
+            "shorts" | "embed" | "live" | "v" => segments.next()?.to_string(),
+            _ => return None,
+        }
+    } else {
+        return None;
+    };
+    valid(&id).then_some(id)
+}
+
+// the player response embedded in a watch page as a javascript assignment
+fn player_response(html: &str) -> Option<PlayerResponse> {
+    let start = html.find(PLAYER_RESPONSE)? + PLAYER_RESPONSE.len();
+    // the assignment is followed by more script, so only the first json value is read
+    serde_json::Deserializer::from_str(&html[start..])
+        .into_iter::<PlayerResponse>()
+        .next()?
+        .ok()
+}
+
+// formats milliseconds as m:ss or h:mm:ss
+fn clock(ms: u64) -> String {
+    let seconds = ms / 1000;
+    let (hours, minutes, seconds) = (seconds / 3600, seconds / 60 % 60, seconds % 60);
+    if hours > 0 {
+        format!("{}:{:02}:{:02}", hours, minutes, seconds)
+    } else {
+        format!("{}:{:02}", minutes, seconds)
+    }
+}
+
+// merges the captions between the start and end into passages with their time ranges
+fn passages(timedtext: &Timedtext, start_ms: u64, end_ms: Option<u64>) -> Vec<String> {
+    let mut passages = Vec::new();
+    let mut current: Option<(u64, u64, String)> = None;
+    for caption in &timedtext.events {
+        let end = caption.t_start_ms + caption.d_duration_ms;
+        if end < start_ms || end_ms.is_some_and(|end_ms| caption.t_start_ms > end_ms) {
+            continue;
+        }
+        let text = caption
+            .segs
+            .iter()
+            .map(|seg| seg.utf8.as_str())
+            .collect::<String>()
+            .split_whitespace()
+            .collect::<Vec<_>>()
+            .join(" ");
+        if text.is_empty() {
+            continue;
+        }
+        match &mut current {
+            Some((from, to, passage)) if caption.t_start_ms < *from + PASSAGE_MS => {
+                *to = (*to).max(end);
+                passage.push(' ');
+                passage.push_str(&text);
+            }
+            _ => {
+                if let Some((from, to, passage)) = current.take() {
+                    passages.push(format!("[{}-{}] {}", clock(from), clock(to), passage));
+                }
+                current = Some((caption.t_start_ms, end, text));
+            }
+        }
+    }
+    if let Some((from, to, passage)) = current {
+        passages.push(format!("[{}-{}] {}", clock(from), clock(to), passage));
+    }
+    passages
+}
+
+// the captions of a track in the language, preferring ones written by people over generated ones.
+// Without a language the spoken one is used, which is the language of the generated track
+fn pick_track<'a>(tracks: &'a [Track], language: Option<&str>) -> Option<&'a Track> {
+    let spoken = tracks
+        .iter()
+        .find(|track| track.generated())
+        .map(|track| track.language_code.as_str());
+    let matches = |track: &&Track| match language.or(spoken) {
+        Some(language) => {
+            let language = language.to_ascii_lowercase();
+            let code = track.language_code.to_ascii_lowercase();
+            code == language || code.starts_with(&format!("{}-", language))
+        }
+        None => true,
+    };
+    tracks
+        .iter()
+        .filter(matches)
+        .min_by_key(|track| track.generated())
+}
+
+// reads the metadata and captions of youtube videos for talks, interviews, and hearings that are
+// only published as video, through the watch page since the data api needs a key
+#[derive(Clone)]
+pub struct YoutubeTool {
+    access: Arc<WebAccess>,
+    http: HttpClient,
+}
+
+impl YoutubeTool {
+    pub fn new(access: Arc<WebAccess>) -> Result<Box<Self>> {
+        Ok(Box::new(Self {
+            http: access.policy().http.client(Duration::from_secs(60))?,
+            access,
+        }))
+    }
+
+    pub fn tools(&self) -> Result<Vec<Box<dyn Tool + Send>>> {
+        Ok(vec![
+            Box::new(YoutubeVideoTool(self.clone())),
+            Box::new(YoutubeTranscriptTool(self.clone())),
+        ])
+    }
+
+    async fn get(&self, url: reqwest::Url) -> Result<String> {
+        if let Err(reason) = self.access.check(&url) {
+            return Err(Error::AgentWorkflowError(reason));
+        }
+        let request = self
+            .http
+            .get(url.clone())
+            // the consent page shown to visitors from the eu has no player response
+            .header(reqwest::header::COOKIE, "CONSENT=YES+1")
+            .header(reqwest::header::ACCEPT_LANGUAGE, "en");
+        self.http
+            .send_ok(request, &url)
+            .await?
+            .text()
+            .await
+            .map_err(|e| Error::AgentWorkflowError(format!("failed to read {}: {}", url, e)))
+    }
+
+    // the watch url and the player response of a video
+    async fn player(
+        &self,
+        video: &str,
+    ) -> Result<std::result::Result<(String, PlayerResponse), String>> {
+        let Some(id) = video_id(video) else {
+            return Ok(Err(format!("{} is not a youtube video id or url", video)));
+        };
+        let url = reqwest::Url::parse_with_params(WATCH_URL, &[("v", id.as_str())])
+            .map_err(|e| Error::AgentWorkflowError(e.to_string()))?;
+        let html = self.get(url.clone()).await?;
+        let Some(player) = player_response(&html) else {
+            return Ok(Err(format!("the page of {} has no video data", url)));
+        };
+        if let Some(playability) = &player.playability_status
+            && playability.status != "OK"
+        {
+            return Ok(Err(format!(
+                "the video {} cannot be played: {}",
+                url,
+                playability.reason.as_deref().unwrap_or(&playability.status)
+            )));
+        }
+        Ok(Ok((url.to_string(), player)))
+    }
+
+    async fn video(&self, video: &str) -> Result<String> {
+        let (url, player) = match self.player(video).await? {
+            Ok(player) => player,
+            Err(reason) => return Ok(reason),
+        };
+        let mut res = String::new();
+        if let Some(details) = &player.video_details {
+            res.push_str(&format!("{}\nChannel: {}\n", details.title, details.author));
+            if let Ok(seconds) = details.length_seconds.parse::<u64>() {
+                res.push_str(&format!("Length: {}\n", clock(seconds * 1000)));
+            }
+            if !details.view_count.is_empty() {
+                res.push_str(&format!("Views: {}\n", details.view_count));
+            }
+        }
+        if let Some(date) = player.microformat.as_ref().and_then(|microformat| {
+            microformat
+                .player_microformat_renderer
+                .publish_date
+                .as_ref()
+        }) {
+            res.push_str(&format!("Published: {}\n", date));
+        }
+        res.push_str(&format!("Url: {}\n", url));
+
+        let tracks = player
+            .captions
+            .as_ref()
+            .map(|captions| {
+                captions
+                    .player_captions_tracklist_renderer
+                    .caption_tracks
+                    .as_slice()
+            })
+            .unwrap_or_default();
+        if tracks.is_empty() {
+            res.push_str("Transcripts: none\n");
+        } else {
+            res.push_str("Transcripts:\n");
+            for track in tracks {
+                res.push_str(&format!(
+                    "- {} ({}){}\n",
+                    track.name(),
+                    track.language_code,
+                    if track.generated() {
+                        ", generated by speech recognition"
+                    } else {
+                        ""
+                    }
+                ));
+            }
+        }
+
+        if let Some(details) = &player.video_details
+            && !details.short_description.is_empty()
+        {
+            let description = details
+                .short_description
+                .chars()
+                .take(DESCRIPTION_CHARS)
+                .collect::<String>();
+            res.push_str(&format!("\nDescription:\n{}\n", description));
+        }
+        Ok(res)
+    }
+
+    async fn transcript(&self, args: &YoutubeTranscriptArgs) -> Result<String> {
+        let (url, player) = match self.player(&args.video).await? {
+            Ok(player) => player,
+            Err(reason) => return Ok(reason),
+        };
+        let tracks = player
+            .captions
+            .as_ref()
+            .map(|captions| {
+                captions
+                    .player_captions_tracklist_renderer
+                    .caption_tracks
+                    .as_slice()
+            })
+            .unwrap_or_default();
+        let Some(track) = pick_track(tracks, args.language.as_deref()) else {
+            return Ok(match &args.language {
+                Some(language) if !tracks.is_empty() => format!(
+                    "The video {} has no transcript in {}, the available languages are {}",
+                    url,
+                    language,
+                    tracks
+                        .iter()
+                        .map(|track| track.language_code.as_str())
+                        .collect::<Vec<_>>()
+                        .join(", ")
+                ),
+                _ => format!("The video {} has no transcript", url),
+            });
+        };
+
+        let timedtext = reqwest::Url::parse(&format!("{}&fmt=json3", track.base_url))
+            .map_err(|e| Error::AgentWorkflowError(e.to_string()))?;
+        let body = self.get(timedtext).await?;
+        if body.trim().is_empty() {
+            return Ok(format!("YouTube returned an empty transcript for {}", url));
+        }
+        let timedtext: Timedtext = serde_json::from_str(&body).map_err(|e| {
+            Error::AgentWorkflowError(format!("unexpected youtube transcript: {}", e))
+        })?;
+        let start_ms = args.start_seconds.unwrap_or_default() * 1000;
+        let passages = passages(&timedtext, start_ms, args.end_seconds.map(|end| end * 1000));
+        if passages.is_empty() {
+            return Ok(format!(
+                "The transcript of {} has no captions in the requested range",
+                url
+            ));
+        }
+
+        let text = passages.join("\n");
+        self.access.record(&url, &text, Trust::Web);
+        let title = player
+            .video_details
+            .as_ref()
+            .map(|details| details.title.as_str())
+            .unwrap_or_default();
+        Ok(format!(
+            "Transcript of {} ({}), {} captions in {}. Cite a passage as {}&t=<seconds>s with the start of its range.\n\n{}",
+            title,
+            url,
+            if track.generated() {
+                "speech recognition"
+            } else {
+                "written"
+            },
+            track.name(),
+            url,
+            text
+        ))
+    }
+}
+
+#[derive(Deserialize, JsonSchema)]
+struct YoutubeVideoArgs {
+    /// the id or url of the video, e.g. dQw4w9WgXcQ or https://youtu.be/dQw4w9WgXcQ
+    video: String,
+}
+
+struct YoutubeVideoTool(YoutubeTool);
+
+#[async_trait]
+impl FunctionalTool for YoutubeVideoTool {
+    fn definition(&self) -> Result<ToolDefinition> {
+        ToolDefinition::new::<YoutubeVideoArgs>(
+            "youtube_video",
+            "This tool returns the title, channel, length, publication date, description, and available transcript languages of a YouTube video. Use it to check whether a talk or interview is relevant and has a transcript before reading it with youtube_transcript.",
+        )
+    }
+
+    fn third_party_fn(&self) -> bool {
+        true
+    }
+
+    async fn invoke_fn(&mut self, call: &ToolCall, _: &ToolContext) -> Result<Message> {
+        let args: YoutubeVideoArgs = call.args()?;
+        Ok(tool_result(call, self.0.video(&args.video).await))
+    }
+}
+
+#[derive(Deserialize, JsonSchema)]
+struct YoutubeTranscriptArgs {
+    /// the id or url of the video
+    video: String,
+    /// the language code of the transcript, e.g. en or de; leave empty for the original language
+    #[serde(default)]
+    language: Option<String>,
+    /// only return captions from this many seconds into the video on
+    #[serde(default)]
+    start_seconds: Option<u64>,
+    /// only return captions up to this many seconds into the video
+    #[serde(default)]
+    end_seconds: Option<u64>,
+}
+
+struct YoutubeTranscriptTool(YoutubeTool);
+
+#[async_trait]
+impl FunctionalTool for YoutubeTranscriptTool {
+    fn definition(&self) -> Result<ToolDefinition> {
+        ToolDefinition::new::<YoutubeTranscriptArgs>(
+            "youtube_transcript",
+            "This tool returns the transcript of a YouTube video in passages with their time ranges. Use it for conference talks, interviews, hearings, and earnings calls that are only published as video, and read long videos in parts with start_seconds and end_seconds. Transcripts generated by speech recognition can misspell names and numbers.",
+        )
+    }
+
+    fn third_party_fn(&self) -> bool {
+        true
+    }
+
+    async fn invoke_fn(&mut self, call: &ToolCall, _: &ToolContext) -> Result<Message> {
+        let args: YoutubeTranscriptArgs = call.args()?;
+        Ok(tool_result(call, self.0.transcript(&args).await))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Timedtext, clock, passages, pick_track, player_response, video_id};
+
+    #[test]
+    fn test_video_id() {
+        let id = Some("dQw4w9WgXcQ".to_string());
+        assert_eq!(video_id("dQw4w9WgXcQ"), id);
+        assert_eq!(
+            video_id("https://www.youtube.com/watch?v=dQw4w9WgXcQ&t=42s"),
+            id
+        );
+        assert_eq!(video_id("youtube.com/watch?v=dQw4w9WgXcQ"), id);
+        assert_eq!(video_id("https://youtu.be/dQw4w9WgXcQ?si=abc"), id);
+        assert_eq!(video_id("https://m.youtube.com/shorts/dQw4w9WgXcQ"), id);
+        assert_eq!(video_id("https://www.youtube.com/embed/dQw4w9WgXcQ"), id);
+        assert_eq!(video_id("https://vimeo.com/dQw4w9WgXcQ"), None);
+        assert_eq!(video_id("https://www.youtube.com/@channel"), None);
+        assert_eq!(video_id("too short"), None);
+    }
+
+    #[test]
+    fn test_player_response() {
+        let html = r#"<script>var ytInitialPlayerResponse = {"playabilityStatus": {"status": "OK"}, "videoDetails": {"videoId": "dQw4w9WgXcQ", "title": "Grid storage in 2030", "author": "Energy Summit", "lengthSeconds": "3725", "viewCount": "1200", "shortDescription": "Keynote"}, "captions": {"playerCaptionsTracklistRenderer": {"captionTracks": [{"baseUrl": "https://www.youtube.com/api/timedtext?v=dQw4w9WgXcQ&lang=en&kind=asr", "languageCode": "en", "name": {"runs": [{"text": "English (auto-generated)"}]}, "kind": "asr"}, {"baseUrl": "https://www.youtube.com/api/timedtext?v=dQw4w9WgXcQ&lang=en-GB", "languageCode": "en-GB", "name": {"simpleText": "English (United Kingdom)"}}, {"baseUrl": "https://www.youtube.com/api/timedtext?v=dQw4w9WgXcQ&lang=de", "languageCode": "de", "name": {"simpleText": "German"}}]}}};var meta = document.createElement('meta');</script>"#;
+        let player = player_response(html).unwrap();
+        assert_eq!(player.video_details.unwrap().title, "Grid storage in 2030");
+
+        let tracks = player
+            .captions
+            .unwrap()
+            .player_captions_tracklist_renderer
+            .caption_tracks;
+        assert_eq!(tracks[0].name(), "English (auto-generated)");
+        // written captions are preferred over generated ones in the same language
+        assert_eq!(
+            pick_track(&tracks, Some("EN")).unwrap().language_code,
+            "en-GB"
+        );
+        assert_eq!(pick_track(&tracks, Some("de")).unwrap().name(), "German");
+        assert_eq!(pick_track(&tracks, None).unwrap().language_code, "en-GB");
+        assert!(pick_track(&tracks, Some("fr")).is_none());
+        assert!(player_response("<html>consent required</html>").is_none());
+    }
+
+    #[test]
+    fn test_passages() {
+        let timedtext: Timedtext = serde_json::from_str(
+            r#"{"events": [
+                {"tStartMs": 0, "dDurationMs": 4000, "segs": [{"utf8": "Welcome to"}, {"utf8": " the keynote."}]},
+                {"tStartMs": 4000, "dDurationMs": 100},
+                {"tStartMs": 12000, "dDurationMs": 5000, "segs": [{"utf8": "Storage grew\nthreefold."}]},
+                {"tStartMs": 31000, "dDurationMs": 6000, "segs": [{"utf8": "Costs fell 40%."}]},
+                {"tStartMs": 3725000, "dDurationMs": 2000, "segs": [{"utf8": "Thank you."}]}
+            ]}"#,
+        )
+        .unwrap();
+        assert_eq!(
+            passages(&timedtext, 0, None),
+            vec![
+                "[0:00-0:17] Welcome to the keynote. Storage grew threefold.",
+                "[0:31-0:37] Costs fell 40%.",
+                "[1:02:05-1:02:07] Thank you.",
+            ]
+        );
+        assert_eq!(
+            passages(&timedtext, 20_000, Some(60_000)),
+            vec!["[0:31-0:37] Costs fell 40%."]
+        );
+        assert_eq!(clock(65_000), "1:05");
+    }
+}
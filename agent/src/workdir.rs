@@ -0,0 +1,208 @@
+use crate::{Error, Result};
+use serde::{Deserialize, Serialize};
+use std::path::{Component, Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+
+/// What happens to the working directory of a run when the run ends.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Cleanup {
+    /// the directory is deleted
+    Delete,
+    /// the directory is kept for inspection
+    Keep,
+    /// the directory is kept when the run failed, so that the run can be resumed with the files
+    /// it had created, and deleted when it succeeded
+    #[default]
+    KeepOnFailure,
+}
+
+impl Cleanup {
+    pub fn keeps(&self, succeeded: bool) -> bool {
+        match self {
+            Cleanup::Delete => false,
+            Cleanup::Keep => true,
+            Cleanup::KeepOnFailure => !succeeded,
+        }
+    }
+}
+
+/// The working directory of a run, where tools put downloads, temporary files, and any other
+/// files they create on the way, so that runs never see each other's files. Every run gets a
+/// directory of its own below a shared root, which is deleted or kept when the run ends.
+pub struct RunContext {
+    dir: PathBuf,
+    cleanup: Cleanup,
+    files: Mutex<Vec<PathBuf>>,
+    next_id: AtomicU64,
+}
+
+impl RunContext {
+    /// Creates the directory of the run below root, removing whatever an earlier run with the
+    /// same id left there.
+    pub fn new(root: &Path, run_id: &str, cleanup: Cleanup) -> Result<Arc<Self>> {
+        let dir = run_dir(root, run_id)?;
+        if dir.exists() {
+            std::fs::remove_dir_all(&dir)?;
+        }
+        Self::open(dir, cleanup)
+    }
+
+    /// Opens the directory of a resumed run, keeping the files it had created.
+    pub fn reopen(root: &Path, run_id: &str, cleanup: Cleanup) -> Result<Arc<Self>> {
+        Self::open(run_dir(root, run_id)?, cleanup)
+    }
+
+    fn open(dir: PathBuf, cleanup: Cleanup) -> Result<Arc<Self>> {
+        std::fs::create_dir_all(&dir)?;
+        let mut files = Vec::new();
+        walk(&dir, &mut files)?;
+        Ok(Arc::new(Self {
+            dir,
+            cleanup,
+            files: Mutex::new(files),
+            next_id: AtomicU64::new(0),
+        }))
+    }
+
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    /// The path of a file in the working directory, which is tracked as a file of the run. The
+    /// name is relative to the directory and may have subdirectories, which are created, but it
+    /// may not lead out of the directory.
+    pub fn path(&self, name: &str) -> Result<PathBuf> {
+        let relative = Path::new(name);
+        let inside = relative
+            .components()
+            .all(|component| matches!(component, Component::Normal(_) | Component::CurDir));
+        if name.is_empty() || !inside {
+            return Err(Error::WorkDirError(format!(
+                "'{}' is not a path inside the working directory",
+                name
+            )));
+        }
+        let path = self.dir.join(relative);
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        let mut files = self.files.lock().unwrap();
+        if !files.contains(&path) {
+            files.push(path.clone());
+        }
+        Ok(path)
+    }
+
+    /// A tracked path no other file of the run has, e.g. tmp/screenshot-3.png.
+    pub fn temp_file(&self, prefix: &str, extension: &str) -> Result<PathBuf> {
+        let prefix = prefix
+            .chars()
+            .map(|c| if c.is_ascii_alphanumeric() { c } else { '_' })
+            .collect::<String>();
+        self.path(&format!(
+            "tmp/{}-{}.{}",
+            prefix,
+            self.next_id.fetch_add(1, Ordering::SeqCst),
+            extension
+        ))
+    }
+
+    /// The tracked files that still exist, in the order they were created.
+    pub fn files(&self) -> Vec<PathBuf> {
+        self.files
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|path| path.exists())
+            .cloned()
+            .collect()
+    }
+
+    /// Deletes the directory or keeps it, as the cleanup of the run says. Returns whether the
+    /// directory was kept.
+    pub fn finish(&self, succeeded: bool) -> Result<bool> {
+        if self.cleanup.keeps(succeeded) {
+            return Ok(true);
+        }
+        if self.dir.exists() {
+            std::fs::remove_dir_all(&self.dir)?;
+        }
+        self.files.lock().unwrap().clear();
+        Ok(false)
+    }
+}
+
+fn run_dir(root: &Path, run_id: &str) -> Result<PathBuf> {
+    if run_id.is_empty()
+        || !run_id
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-' || c == '.')
+        || run_id.chars().all(|c| c == '.')
+    {
+        return Err(Error::WorkDirError(format!(
+            "invalid run id '{}' for a working directory",
+            run_id
+        )));
+    }
+    Ok(root.join(run_id))
+}
+
+fn walk(dir: &Path, files: &mut Vec<PathBuf>) -> Result<()> {
+    let mut entries = std::fs::read_dir(dir)?
+        .map(|entry| Ok(entry?.path()))
+        .collect::<Result<Vec<_>>>()?;
+    entries.sort();
+    for path in entries {
+        if path.is_dir() {
+            walk(&path, files)?;
+        } else {
+            files.push(path);
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Cleanup, RunContext};
+
+    #[test]
+    fn test_run_context() {
+        let root = std::env::temp_dir().join(format!("workdir-test-{}", std::process::id()));
+
+        let run = RunContext::new(&root, "run-1", Cleanup::KeepOnFailure).unwrap();
+        let other = RunContext::new(&root, "run-2", Cleanup::Delete).unwrap();
+        assert_ne!(run.dir(), other.dir());
+
+        let download = run.path("downloads/report.pdf").unwrap();
+        std::fs::write(&download, "pdf").unwrap();
+        let first = run.temp_file("screen shot", "png").unwrap();
+        let second = run.temp_file("screen shot", "png").unwrap();
+        assert_ne!(first, second);
+        assert!(first.starts_with(run.dir().join("tmp")));
+        std::fs::write(&first, "png").unwrap();
+        assert_eq!(run.files(), vec![download.clone(), first.clone()]);
+
+        assert!(run.path("../run-2/secret").is_err());
+        assert!(run.path("/etc/passwd").is_err());
+        assert!(run.path("").is_err());
+        assert!(RunContext::new(&root, "..", Cleanup::Delete).is_err());
+
+        // a resumed run keeps its files, a new run with the same id does not see them
+        let resumed = RunContext::reopen(&root, "run-1", Cleanup::KeepOnFailure).unwrap();
+        assert_eq!(resumed.files(), vec![download.clone(), first.clone()]);
+        assert!(resumed.finish(false).unwrap());
+        assert!(download.exists());
+        let fresh = RunContext::new(&root, "run-1", Cleanup::KeepOnFailure).unwrap();
+        assert!(fresh.files().is_empty());
+        assert!(!download.exists());
+
+        assert!(!fresh.finish(true).unwrap());
+        assert!(!fresh.dir().exists());
+        assert!(!other.finish(false).unwrap());
+        assert!(!other.dir().exists());
+        std::fs::remove_dir_all(root).unwrap();
+    }
+}
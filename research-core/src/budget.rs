@@ -0,0 +1,235 @@
+use crate::subagents::{SubAgentRecord, SubAgentStatus};
+use agent::Result;
+use agent::llm::{Pricing, TokenUsage};
+use std::io::Write;
+use std::path::Path;
+
+// usage of the sub-agents of earlier runs in the same log directory, one json object per line
+const HISTORY_FILE: &str = "subagent_usage.jsonl";
+// only the most recent sub-agents are averaged, so the estimate follows model and prompt changes
+const HISTORY_LEN: usize = 50;
+// assumed usage of a sub-agent when none has finished in this or an earlier run
+const DEFAULT_SUBAGENT_USAGE: TokenUsage = TokenUsage {
+    requests: 15,
+    prompt_tokens: 135_000,
+    completion_tokens: 15_000,
+};
+
+// limits on the tokens and cost of a whole run
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Budget {
+    pub max_tokens: Option<u64>,
+    pub max_cost: Option<f64>,
+}
+
+impl Budget {
+    pub fn is_empty(&self) -> bool {
+        self.max_tokens.is_none() && self.max_cost.is_none()
+    }
+}
+
+pub fn load_history(log_dir: &Path) -> Vec<TokenUsage> {
+    let Ok(history) = std::fs::read_to_string(log_dir.join(HISTORY_FILE)) else {
+        return Vec::new();
+    };
+    let usages = history
+        .lines()
+        .filter_map(|line| serde_json::from_str(line).ok())
+        .collect::<Vec<TokenUsage>>();
+    usages[usages.len().saturating_sub(HISTORY_LEN)..].to_vec()
+}
+
+// appends the usage of the sub-agents that completed their task to the history
+pub fn record_history(log_dir: &Path, records: &[SubAgentRecord]) -> Result<()> {
+    let mut file = std::fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(log_dir.join(HISTORY_FILE))?;
+    for record in records
+        .iter()
+        .filter(|r| r.status == SubAgentStatus::Completed)
+    {
+        writeln!(file, "{}", serde_json::to_string(&record.usage)?)?;
+    }
+    Ok(())
+}
+
+fn average(usages: &[TokenUsage]) -> Option<TokenUsage> {
+    let n = usages.len() as u64;
+    if n == 0 {
+        return None;
+    }
+    let mut total = TokenUsage::default();
+    usages.iter().for_each(|usage| total += *usage);
+    Some(TokenUsage {
+        requests: total.requests / n,
+        prompt_tokens: total.prompt_tokens / n,
+        completion_tokens: total.completion_tokens / n,
+    })
+}
+
+// the expected usage of one sub-agent, from the completed sub-agents of this run or else from
+// earlier runs
+pub fn expected_usage(records: &[SubAgentRecord], history: &[TokenUsage]) -> TokenUsage {
+    let completed = records
+        .iter()
+        .filter(|r| r.status == SubAgentStatus::Completed)
+        .map(|r| r.usage)
+        .collect::<Vec<_>>();
+    average(&completed)
+        .or_else(|| average(history))
+        .unwrap_or(DEFAULT_SUBAGENT_USAGE)
+}
+
+#[derive(Debug, PartialEq)]
+pub struct BudgetEstimate {
+    pub remaining_tokens: Option<u64>,
+    pub remaining_cost: Option<f64>,
+    pub per_subagent: TokenUsage,
+    pub per_subagent_cost: Option<f64>,
+    // sub-agents that can still be started once the running ones have finished
+    pub affordable: usize,
+}
+
+pub fn estimate(
+    budget: &Budget,
+    pricing: Option<&Pricing>,
+    used: TokenUsage,
+    per_subagent: TokenUsage,
+    running: usize,
+) -> Option<BudgetEstimate> {
+    if budget.is_empty() {
+        return None;
+    }
+
+    let remaining_tokens = budget
+        .max_tokens
+        .map(|max| max.saturating_sub(used.total_tokens()));
+    let per_subagent_cost = pricing.map(|pricing| per_subagent.cost(pricing));
+    let remaining_cost = budget
+        .max_cost
+        .zip(pricing)
+        .map(|(max, pricing)| (max - used.cost(pricing)).max(0.0));
+
+    let mut affordable = usize::MAX;
+    if let Some(remaining) = remaining_tokens {
+        affordable = affordable.min((remaining / per_subagent.total_tokens().max(1)) as usize);
+    }
+    if let (Some(remaining), Some(cost)) = (remaining_cost, per_subagent_cost)
+        && cost > 0.0
+    {
+        affordable = affordable.min((remaining / cost) as usize);
+    }
+
+    Some(BudgetEstimate {
+        remaining_tokens,
+        remaining_cost,
+        per_subagent,
+        per_subagent_cost,
+        affordable: affordable.saturating_sub(running),
+    })
+}
+
+impl BudgetEstimate {
+    pub fn describe(&self) -> String {
+        let remaining = [
+            self.remaining_tokens
+                .map(|tokens| format!("{} tokens", tokens)),
+            self.remaining_cost.map(|cost| format!("${:.2}", cost)),
+        ]
+        .into_iter()
+        .flatten()
+        .collect::<Vec<_>>()
+        .join(" and ");
+        let per_subagent = match self.per_subagent_cost {
+            Some(cost) => format!("{} tokens (${:.2})", self.per_subagent.total_tokens(), cost),
+            None => format!("{} tokens", self.per_subagent.total_tokens()),
+        };
+        format!(
+            "Budget: {} remaining, a sub-agent uses about {}, enough for about {} more sub-agents after the running ones",
+            remaining, per_subagent, self.affordable
+        )
+    }
+
+    // asks the planner to shrink a plan that the remaining budget cannot finish
+    pub fn plan_advice(&self, open_questions: usize) -> Option<String> {
+        (open_questions > self.affordable).then(|| {
+            format!(
+                "{}. The plan has {} unanswered sub-questions that need their own sub-agents, more than the budget covers. Merge closely related sub-questions into a single sub-agent task and drop or defer the least important ones, rather than starting sub-agents that the budget cannot finish.",
+                self.describe(),
+                open_questions
+            )
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Budget, TokenUsage, estimate, expected_usage};
+    use crate::subagents::{SubAgentRecord, SubAgentStatus};
+    use agent::llm::Pricing;
+
+    fn usage(prompt_tokens: u64, completion_tokens: u64) -> TokenUsage {
+        TokenUsage {
+            requests: 1,
+            prompt_tokens,
+            completion_tokens,
+        }
+    }
+
+    fn record(status: SubAgentStatus, usage: TokenUsage) -> SubAgentRecord {
+        SubAgentRecord {
+            name: "subagent_0".to_string(),
+            task: "task".to_string(),
+            status,
+            priority: Default::default(),
+            retry_of: None,
+            started_at: String::new(),
+            duration_secs: None,
+            usage,
+        }
+    }
+
+    #[test]
+    fn test_expected_usage() {
+        let history = vec![usage(1000, 0), usage(3000, 0)];
+        assert_eq!(expected_usage(&[], &history).prompt_tokens, 2000);
+
+        let records = vec![
+            record(SubAgentStatus::Completed, usage(100, 20)),
+            record(SubAgentStatus::Running, usage(5000, 0)),
+            record(SubAgentStatus::Completed, usage(300, 40)),
+        ];
+        assert_eq!(expected_usage(&records, &history), usage(200, 30));
+        assert!(expected_usage(&[], &[]).total_tokens() > 0);
+    }
+
+    #[test]
+    fn test_estimate() {
+        assert_eq!(
+            estimate(&Budget::default(), None, usage(0, 0), usage(100, 0), 0),
+            None
+        );
+
+        let budget = Budget {
+            max_tokens: Some(10_000),
+            max_cost: Some(0.45),
+        };
+        let pricing = Pricing {
+            prompt: 100.0,
+            completion: 0.0,
+        };
+        // 9,000 tokens cover 9 sub-agents but the remaining $0.35 only 3, one of which is running
+        let estimate =
+            estimate(&budget, Some(&pricing), usage(1000, 0), usage(1000, 0), 1).unwrap();
+        assert_eq!(estimate.remaining_tokens, Some(9000));
+        assert_eq!(estimate.affordable, 2);
+        assert!(estimate.plan_advice(2).is_none());
+        assert!(
+            estimate
+                .plan_advice(5)
+                .unwrap()
+                .contains("The plan has 5 unanswered sub-questions")
+        );
+    }
+}
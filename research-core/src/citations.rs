@@ -0,0 +1,286 @@
+//! Citations of the final report in the configured style.
+//!
+//! Reports cite their sources with markers that hold the urls of the sources, such as
+//! `[@https://example.com/ev]` or `[@https://a.com; @https://b.com]`. Once the report is written,
+//! the markers are numbered in the order the sources are first cited and replaced with the in-text
+//! citations of the style, and the list of references the style needs is appended, so that every
+//! report cites the same way whatever the model would have improvised.
+
+use crate::contract::CitationStyle;
+use crate::sources::canonical_url;
+
+const MARKER_START: &str = "[@";
+
+/// A source cited by the report.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Reference {
+    /// url of the first citation of the source
+    pub url: String,
+    /// host of the url without www., which stands in for the author of a web page
+    pub site: String,
+    /// year the page was published according to its metadata, if it is known
+    pub year: Option<i64>,
+}
+
+impl Reference {
+    /// The reference of a url, with its year among the publication years of the pages that were
+    /// read.
+    pub fn new(url: &str, published: &[(String, i64)]) -> Self {
+        let canonical = canonical_url(url);
+        let site = canonical
+            .split(['/', '?'])
+            .next()
+            .unwrap_or_default()
+            .to_string();
+        let year = published
+            .iter()
+            .find(|(page, _)| canonical_url(page) == canonical)
+            .map(|(_, year)| *year);
+        Self {
+            url: url.to_string(),
+            site,
+            year,
+        }
+    }
+}
+
+/// A cited source with its number, counted from one in the order the sources are first cited.
+pub type Cited<'a> = (usize, &'a Reference);
+
+/// Writes the citations of one style.
+pub trait CitationFormatter {
+    /// The in-text citation of the sources of one marker.
+    fn cite(&self, cited: &[Cited]) -> String;
+
+    /// The section appended to the report with every cited source, empty for styles without one.
+    fn references(&self, cited: &[Cited]) -> String;
+}
+
+/// Numbered citations such as `[1]` with the numbered references at the end.
+pub struct Ieee;
+
+impl CitationFormatter for Ieee {
+    fn cite(&self, cited: &[Cited]) -> String {
+        cited
+            .iter()
+            .map(|(n, _)| format!("[{}]", n))
+            .collect::<Vec<_>>()
+            .join(", ")
+    }
+
+    fn references(&self, cited: &[Cited]) -> String {
+        let mut references = "## References\n\n".to_string();
+        for (n, reference) in cited {
+            let year = reference
+                .year
+                .map(|year| format!(", {}", year))
+                .unwrap_or_default();
+            references.push_str(&format!(
+                "[{}] {}{}. [Online]. Available: {}\n\n",
+                n, reference.site, year, reference.url
+            ));
+        }
+        references
+    }
+}
+
+/// Site and year in parentheses such as (example.com, 2023) with the references sorted by site.
+pub struct Apa;
+
+fn year(reference: &Reference) -> String {
+    reference
+        .year
+        .map(|year| year.to_string())
+        .unwrap_or_else(|| "n.d.".to_string())
+}
+
+impl CitationFormatter for Apa {
+    fn cite(&self, cited: &[Cited]) -> String {
+        let citations = cited
+            .iter()
+            .map(|(_, reference)| format!("{}, {}", reference.site, year(reference)))
+            .collect::<Vec<_>>();
+        format!("({})", citations.join("; "))
+    }
+
+    fn references(&self, cited: &[Cited]) -> String {
+        let mut sorted = cited.to_vec();
+        sorted.sort_by(|(_, a), (_, b)| a.site.cmp(&b.site).then(a.year.cmp(&b.year)));
+        let mut references = "## References\n\n".to_string();
+        for (_, reference) in sorted {
+            references.push_str(&format!(
+                "{}. ({}). {}\n\n",
+                reference.site,
+                year(reference),
+                reference.url
+            ));
+        }
+        references
+    }
+}
+
+/// Markdown footnotes such as `[^1]` with the footnote definitions at the end.
+pub struct Footnotes;
+
+impl CitationFormatter for Footnotes {
+    fn cite(&self, cited: &[Cited]) -> String {
+        cited.iter().map(|(n, _)| format!("[^{}]", n)).collect()
+    }
+
+    fn references(&self, cited: &[Cited]) -> String {
+        cited
+            .iter()
+            .map(|(n, reference)| format!("[^{}]: {}, {}\n", n, reference.site, reference.url))
+            .collect()
+    }
+}
+
+/// Inline Markdown links to the sources, without references.
+pub struct Links;
+
+impl CitationFormatter for Links {
+    fn cite(&self, cited: &[Cited]) -> String {
+        let links = cited
+            .iter()
+            .map(|(_, reference)| format!("[{}]({})", reference.site, reference.url))
+            .collect::<Vec<_>>();
+        format!("({})", links.join(", "))
+    }
+
+    fn references(&self, _: &[Cited]) -> String {
+        String::new()
+    }
+}
+
+impl CitationStyle {
+    /// The formatter that writes the citations of the style.
+    pub fn formatter(&self) -> Box<dyn CitationFormatter + Send + Sync> {
+        match self {
+            CitationStyle::Ieee => Box::new(Ieee),
+            CitationStyle::Apa => Box::new(Apa),
+            CitationStyle::Footnotes => Box::new(Footnotes),
+            CitationStyle::Links => Box::new(Links),
+        }
+    }
+}
+
+// the length of the marker at the start of the text and its urls, or None for brackets that are
+// no citation marker
+fn marker(text: &str) -> Option<(usize, Vec<&str>)> {
+    let (inner, _) = text.strip_prefix('[')?.split_once(']')?;
+    let urls = inner
+        .split(';')
+        .map(|id| {
+            id.trim()
+                .strip_prefix('@')
+                .map(str::trim)
+                .filter(|url| url.starts_with("http://") || url.starts_with("https://"))
+        })
+        .collect::<Option<Vec<_>>>()?;
+    Some((inner.len() + 2, urls))
+}
+
+/// Whether the report cites any source with a citation marker.
+pub fn has_markers(report: &str) -> bool {
+    report
+        .match_indices(MARKER_START)
+        .any(|(i, _)| marker(&report[i..]).is_some())
+}
+
+/// Replaces the citation markers of the report with the citations of the style and appends its
+/// references. Sources are told apart by their canonical url, so that the different urls of one
+/// page share a number, and years are the publication years of the pages that were read, sources
+/// without one are undated.
+pub fn format(report: &str, style: CitationStyle, published: &[(String, i64)]) -> String {
+    let formatter = style.formatter();
+    let mut references: Vec<Reference> = Vec::new();
+    let mut formatted = String::with_capacity(report.len());
+    let mut rest = report;
+    while let Some(start) = rest.find(MARKER_START) {
+        formatted.push_str(&rest[..start]);
+        rest = &rest[start..];
+        let Some((len, urls)) = marker(rest) else {
+            formatted.push_str(MARKER_START);
+            rest = &rest[MARKER_START.len()..];
+            continue;
+        };
+
+        let mut numbers = Vec::new();
+        for url in urls {
+            let canonical = canonical_url(url);
+            let n = match references
+                .iter()
+                .position(|reference| canonical_url(&reference.url) == canonical)
+            {
+                Some(i) => i + 1,
+                None => {
+                    references.push(Reference::new(url, published));
+                    references.len()
+                }
+            };
+            if !numbers.contains(&n) {
+                numbers.push(n);
+            }
+        }
+        let cited = numbers
+            .iter()
+            .map(|&n| (n, &references[n - 1]))
+            .collect::<Vec<_>>();
+        formatted.push_str(&formatter.cite(&cited));
+        rest = &rest[len..];
+    }
+    formatted.push_str(rest);
+
+    let cited = references
+        .iter()
+        .enumerate()
+        .map(|(i, reference)| (i + 1, reference))
+        .collect::<Vec<_>>();
+    let appended = formatter.references(&cited);
+    if appended.is_empty() {
+        return formatted;
+    }
+    format!("{}\n\n{}", formatted.trim_end(), appended.trim_end())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{format, has_markers};
+    use crate::contract::CitationStyle;
+
+    #[test]
+    fn test_format() {
+        let report = "# EV market\n\nSales grew [@https://www.example.com/ev?utm_source=x] while prices fell [@https://news.org/prices; @https://example.com/ev]. See [the data](https://example.com) [@ not a url].";
+        let pages = vec![("https://example.com/ev".to_string(), 2023)];
+        assert!(has_markers(report));
+        assert!(!has_markers(
+            "see [the data](https://example.com) [@ not a url]"
+        ));
+
+        assert_eq!(
+            format(report, CitationStyle::Ieee, &pages),
+            "# EV market\n\nSales grew [1] while prices fell [2], [1]. See [the data](https://example.com) [@ not a url].\n\n## References\n\n[1] example.com, 2023. [Online]. Available: https://www.example.com/ev?utm_source=x\n\n[2] news.org. [Online]. Available: https://news.org/prices"
+        );
+
+        let apa = format(report, CitationStyle::Apa, &pages);
+        assert!(apa.contains(
+            "Sales grew (example.com, 2023) while prices fell (news.org, n.d.; example.com, 2023)."
+        ));
+        assert!(apa.ends_with(
+            "## References\n\nexample.com. (2023). https://www.example.com/ev?utm_source=x\n\nnews.org. (n.d.). https://news.org/prices"
+        ));
+
+        let footnotes = format(report, CitationStyle::Footnotes, &pages);
+        assert!(footnotes.contains("prices fell [^2][^1]."));
+        assert!(footnotes.ends_with("[^2]: news.org, https://news.org/prices"));
+
+        assert_eq!(
+            format(
+                "Sales grew [@https://news.org/prices].",
+                CitationStyle::Links,
+                &[]
+            ),
+            "Sales grew ([news.org](https://news.org/prices))."
+        );
+    }
+}
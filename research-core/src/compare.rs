@@ -0,0 +1,126 @@
+use crate::config::Config;
+use crate::inputs::Documents;
+use crate::prompts;
+use crate::research::OrchestratorBuilder;
+use agent::llm::{self, CompletionRequest, Message};
+use agent::{Error, Result};
+use std::path::Path;
+use std::sync::Arc;
+use tokio_util::sync::CancellationToken;
+
+fn item_task(items: &[String], index: usize, criteria: &[String]) -> String {
+    let mut task = format!(
+        "Research the following item as one part of a comparison between {}:\n{}",
+        items.join(", "),
+        items[index]
+    );
+    if !criteria.is_empty() {
+        task.push_str(&format!(
+            "\nMake sure to cover these aspects of the comparison: {}",
+            criteria.join(", ")
+        ));
+    }
+    task
+}
+
+// researches every item with its own orchestrator in parallel, then synthesizes the reports
+// into a comparison
+pub async fn compare(
+    llm: Arc<dyn llm::LLM + Send + Sync>,
+    log_dir: &Path,
+    config: Config,
+    items: Vec<String>,
+    criteria: Vec<String>,
+    documents: Option<&Documents>,
+    cancel: &CancellationToken,
+) -> Result<String> {
+    if items.len() < 2 {
+        return Err(Error::MissingArg(
+            "a comparison needs at least two items".to_string(),
+        ));
+    }
+
+    let mut handles = tokio::task::JoinSet::new();
+    for i in 0..items.len() {
+        let item_dir = log_dir.join(format!("item_{}", i));
+        std::fs::create_dir_all(&item_dir)?;
+
+        let mut item_config = config.clone();
+        item_config.run_id = format!("{}-{}", config.run_id, i);
+
+        let mut builder = OrchestratorBuilder::new()
+            .llm(llm.clone())
+            .log_dir(&item_dir)
+            .config(item_config);
+        if let Some(documents) = documents {
+            builder = builder.documents(documents.memory.clone());
+        }
+        let orchestrator = builder.build()?;
+        let mut task = item_task(&items, i, &criteria);
+        if let Some(documents) = documents {
+            task = documents.task_prompt(&task);
+        }
+        let cancel = cancel.child_token();
+        handles.spawn(async move { (i, orchestrator.run(task, &cancel).await) });
+    }
+
+    let mut reports = Vec::with_capacity(items.len());
+    while let Some(res) = handles.join_next().await {
+        let (i, report) = res?;
+        reports.push((i, report?));
+    }
+    reports.sort_by_key(|(i, _)| *i);
+
+    let mut request = String::new();
+    if !criteria.is_empty() {
+        request.push_str(&format!(
+            "<criteria>\n{}\n</criteria>\n",
+            criteria.join("\n")
+        ));
+    }
+    for (i, report) in &reports {
+        request.push_str(&format!(
+            "<report item=\"{}\">\n{}\n</report>\n",
+            items[*i], report
+        ));
+    }
+
+    let messages = vec![
+        Arc::new(Message::System(prompts::compare(&config))),
+        Arc::new(Message::User(request)),
+    ];
+    let synthesis = config.synthesis(&llm);
+    let res = tokio::select! {
+        _ = cancel.cancelled() => {
+            return Err(Error::Cancelled("comparison was cancelled".to_string()));
+        }
+        res = synthesis.completion(CompletionRequest {
+            messages: &messages,
+            tools: &[],
+            web_search_tool: false,
+            prefill: None,
+        }) => res?,
+    };
+
+    std::fs::write(log_dir.join("comparison.md"), &res.content)?;
+
+    Ok(res.content)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::item_task;
+
+    #[test]
+    fn test_item_task() {
+        let items = ["Postgres".to_string(), "MySQL".to_string()];
+        assert_eq!(
+            item_task(&items, 1, &[]),
+            "Research the following item as one part of a comparison between Postgres, MySQL:\nMySQL"
+        );
+        assert!(
+            item_task(&items, 0, &["licensing".to_string()])
+                .ends_with("aspects of the comparison: licensing")
+        );
+    }
+}
@@ -0,0 +1,196 @@
+use agent::llm::{CompletionRequest, CompletionResponse, LLM};
+use agent::{Error, Result};
+use async_trait::async_trait;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+// the ceiling when adaptive concurrency is on without a cap on concurrent sub-agents
+pub const DEFAULT_MAX_CONCURRENCY: usize = 8;
+// weight of the newest completion in the average latency
+const LATENCY_WEIGHT: f64 = 0.2;
+// the provider counts as overloaded once the average latency is this many times its baseline
+const SLOW_FACTOR: f64 = 2.0;
+// weight of the average latency in a baseline below it, so that the baseline follows the latency
+// of the provider when it is not loaded rather than the fastest completions of the run
+const BASELINE_WEIGHT: f64 = 0.02;
+// a burst of rate limit errors from requests that were in flight together only lowers the limit
+// once
+const COOLDOWN: Duration = Duration::from_secs(10);
+
+// additive increase, multiplicative decrease: the limit grows by one after a full round of
+// completions at the current limit without a rate limit error or a slowdown, halves on a rate
+// limit error, and shrinks by one while completions are much slower than the baseline
+struct Controller {
+    min: usize,
+    max: usize,
+    limit: usize,
+    // exponentially weighted average latency of successful completions
+    latency: Option<f64>,
+    // the latency of the provider when it is not loaded, which drops to the average at once and
+    // rises towards it slowly
+    baseline: Option<f64>,
+    // completions since the limit last changed
+    successes: usize,
+    last_decrease: Option<Instant>,
+}
+
+impl Controller {
+    fn new(max: usize) -> Self {
+        let max = max.max(1);
+        Self {
+            min: 1,
+            max,
+            limit: max.div_ceil(2),
+            latency: None,
+            baseline: None,
+            successes: 0,
+            last_decrease: None,
+        }
+    }
+
+    fn decrease(&mut self, limit: usize, now: Instant) {
+        if self
+            .last_decrease
+            .is_some_and(|last| now.duration_since(last) < COOLDOWN)
+        {
+            return;
+        }
+        self.limit = limit.max(self.min);
+        self.successes = 0;
+        self.last_decrease = Some(now);
+    }
+
+    fn observe(&mut self, latency: Duration, rate_limited: bool, now: Instant) {
+        if rate_limited {
+            self.decrease(self.limit / 2, now);
+            return;
+        }
+
+        let sample = latency.as_secs_f64();
+        let average = self.latency.map_or(sample, |average| {
+            average + LATENCY_WEIGHT * (sample - average)
+        });
+        self.latency = Some(average);
+        let baseline = self.baseline.map_or(average, |baseline| {
+            if average < baseline {
+                average
+            } else {
+                baseline + BASELINE_WEIGHT * (average - baseline)
+            }
+        });
+        self.baseline = Some(baseline);
+
+        if average > baseline * SLOW_FACTOR {
+            self.decrease(self.limit.saturating_sub(1), now);
+            return;
+        }
+        self.successes += 1;
+        if self.successes >= self.limit {
+            self.limit = (self.limit + 1).min(self.max);
+            self.successes = 0;
+        }
+    }
+}
+
+// decides how many sub-agents may run at once from the rate limit errors and latency of the
+// completions of the run, so throughput follows the quota the provider currently grants
+pub struct AdaptiveConcurrency {
+    controller: Mutex<Controller>,
+}
+
+impl AdaptiveConcurrency {
+    pub fn new(max: usize) -> Arc<Self> {
+        Arc::new(Self {
+            controller: Mutex::new(Controller::new(max)),
+        })
+    }
+
+    pub fn limit(&self) -> usize {
+        self.controller.lock().unwrap().limit
+    }
+
+    // wraps llm so that every completion is observed
+    pub fn monitor(self: &Arc<Self>, llm: Arc<dyn LLM + Send + Sync>) -> Arc<Monitored> {
+        Arc::new(Monitored {
+            inner: llm,
+            concurrency: self.clone(),
+        })
+    }
+}
+
+pub struct Monitored {
+    inner: Arc<dyn LLM + Send + Sync>,
+    concurrency: Arc<AdaptiveConcurrency>,
+}
+
+#[async_trait]
+impl LLM for Monitored {
+    async fn completion<'a>(&self, request: CompletionRequest<'a>) -> Result<CompletionResponse> {
+        let started = Instant::now();
+        let res = self.inner.completion(request).await;
+        // other errors say nothing about the load of the provider
+        if res.is_ok() || matches!(res, Err(Error::RateLimited(_))) {
+            self.concurrency.controller.lock().unwrap().observe(
+                started.elapsed(),
+                res.is_err(),
+                Instant::now(),
+            );
+        }
+        res
+    }
+
+    fn web_search(&self) -> bool {
+        self.inner.web_search()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{COOLDOWN, Controller};
+    use std::time::{Duration, Instant};
+
+    #[test]
+    fn test_controller() {
+        let mut controller = Controller::new(8);
+        let mut now = Instant::now();
+        let fast = Duration::from_secs(2);
+        assert_eq!(controller.limit, 4);
+
+        // a full round of fast completions raises the limit by one, up to the ceiling
+        for _ in 0..4 {
+            controller.observe(fast, false, now);
+        }
+        assert_eq!(controller.limit, 5);
+        for _ in 0..100 {
+            controller.observe(fast, false, now);
+        }
+        assert_eq!(controller.limit, 8);
+
+        // a burst of rate limit errors halves the limit once
+        controller.observe(fast, true, now);
+        controller.observe(fast, true, now);
+        assert_eq!(controller.limit, 4);
+        now += COOLDOWN;
+        controller.observe(fast, true, now);
+        assert_eq!(controller.limit, 2);
+
+        // completions far slower than the baseline lower it by one
+        now += COOLDOWN;
+        for _ in 0..10 {
+            controller.observe(Duration::from_secs(20), false, now);
+        }
+        assert_eq!(controller.limit, 1);
+        now += COOLDOWN;
+        controller.observe(fast, true, now);
+        assert_eq!(controller.limit, 1);
+
+        // a few fast completions do not hold the limit down once the provider is steadily slower
+        let mut controller = Controller::new(8);
+        controller.observe(Duration::from_millis(100), false, now);
+        for _ in 0..500 {
+            now += Duration::from_secs(1);
+            controller.observe(fast, false, now);
+        }
+        assert_eq!(controller.limit, 8);
+    }
+}
@@ -0,0 +1,211 @@
+use crate::contract::OutputContract;
+use crate::presets::{NewsSource, Persona, Preset, Role, Selection, ToolPolicy, ToolSelection};
+use agent::workdir::RunContext;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+static RUN_IDS: AtomicU64 = AtomicU64::new(0);
+
+/// A run id for a config without one, from the current time and unique within the process, so
+/// that runs started in the same second do not share a working directory.
+pub fn new_run_id() -> String {
+    format!(
+        "{}-{}-{}",
+        chrono::Local::now().format("%Y%m%d-%H%M%S"),
+        std::process::id(),
+        RUN_IDS.fetch_add(1, Ordering::SeqCst)
+    )
+}
+
+#[derive(Clone, Default)]
+pub struct Config {
+    /// identifier of this research run, shared by the orchestrator and all sub-agents
+    pub run_id: String,
+    /// command line the run was started with, saved with the state of the run so that resuming
+    /// it builds the same config again
+    pub args: Vec<String>,
+    /// name of the model used by the orchestrator and all sub-agents
+    pub model: String,
+    /// name of the model that handles requests without tools, such as summaries
+    pub cheap_model: Option<String>,
+    /// language the research report is written in, e.g. "en" or "de"
+    pub language: String,
+    /// optional preset bundling a system prompt, report format, and the optional tools it needs
+    pub persona: Option<Persona>,
+    /// run a claim-level verification pass over the final report
+    pub verify: bool,
+    /// deliver sub-agent results into the orchestrator history as soon as they finish
+    pub stream_subagent_results: bool,
+    /// maximum time a single sub-agent attempt may run before it is treated as failed
+    pub subagent_timeout: Option<std::time::Duration>,
+    /// write a draft report from the findings so far after every this many completed sub-agent
+    /// tasks
+    pub partial_report_every: Option<usize>,
+    /// maximum number of sub-agents running at once, further sub-agents are queued by priority
+    pub max_concurrent_subagents: Option<usize>,
+    /// raise and lower the number of concurrent sub-agents with the rate limit errors and latency
+    /// of the model, max_concurrent_subagents becomes the ceiling
+    pub adaptive_concurrency: bool,
+    /// maximum time a single sub-agent step, one completion plus its tool calls, may take
+    pub step_timeout: Option<std::time::Duration>,
+    /// write where the time of every agent step goes, model, tools, callbacks, and checkpoints,
+    /// as folded stacks for flamegraph tools
+    pub profile: bool,
+    /// size limit and number of kept segments of the orchestrator and sub-agent logs
+    pub log_rotation: Option<agent::callbacks::Rotation>,
+    /// directory the working directories of runs are created in, for the files the tools create,
+    /// the research directory of the system temp directory by default
+    pub work_root: Option<std::path::PathBuf>,
+    /// whether the working directory of the run is deleted or kept when the run ends
+    pub keep_work_dir: agent::workdir::Cleanup,
+    /// price of the model, used to report the cost of the run
+    pub pricing: Option<agent::llm::Pricing>,
+    /// token and cost limits of the run, the planner sizes the research to fit them
+    pub budget: crate::budget::Budget,
+    /// length, section, and citation requirements the final report is checked against
+    pub contract: OutputContract,
+    /// domains and request limits the web tools of all agents must respect
+    pub web_policy: agent::tools::WebPolicy,
+    /// write the suspected prompt injections the web content sanitizer removed to injections.json
+    /// for review, see WebPolicy::sanitize_content
+    pub flag_injections: bool,
+    /// news api behind the news_search tool of agents with web access
+    pub news: Option<NewsSource>,
+    /// give agents with web access stock quotes, company fundamentals, and SEC filings, which needs
+    /// the SEC_USER_AGENT credential
+    pub finance: bool,
+    /// give agents with web access the scholarly paper lookup and citation graph tools
+    pub scholar: bool,
+    /// give agents with web access the youtube video metadata and transcript tools
+    pub youtube: bool,
+    /// credentials of the news, scholar, and finance tools, read from the environment by default
+    pub secrets: Arc<agent::secrets::Secrets>,
+    /// apis described by OpenAPI specs whose operations agents with web access can call
+    pub openapi: Vec<agent::tools::OpenApi>,
+    /// graphql apis agents with web access can query
+    pub graphql: Vec<agent::tools::GraphQL>,
+    /// give agents with web access a headless browser for pages that render their content with
+    /// javascript, only takes effect when built with the browser feature
+    pub browser: bool,
+    /// search apis behind the web_search tool of agents with web access, which replaces the
+    /// search of the model provider
+    pub search: Option<agent::tools::WebSearch>,
+    /// container the run_shell and run_python tools of agents with web access run code in, the
+    /// tools are off without one
+    pub sandbox: Option<agent::tools::SandboxConfig>,
+    /// vision capable model that describes screenshots of browser pages, such as charts and
+    /// dashboards, for the browser_describe tool
+    pub vision: Option<std::sync::Arc<dyn agent::llm::LLM + Send + Sync>>,
+    /// model behind the translate tool of agents with web access
+    pub translator: Option<std::sync::Arc<dyn agent::llm::LLM + Send + Sync>>,
+    /// long-context model that writes the final report from all findings, facts, and sources,
+    /// with the finalized draft of the orchestrator as its outline
+    pub synthesizer: Option<std::sync::Arc<dyn agent::llm::LLM + Send + Sync>>,
+    /// write the final report from an outline, one section per request with only the findings,
+    /// facts, and passages of the documents it draws on, and then smooth the stitched sections
+    pub outline_report: bool,
+    /// only sources published on or after this date are acceptable, the searches of `search`
+    /// and `news` are restricted to it and cited sources that seem older are flagged. The search
+    /// of the model provider cannot be restricted, its agents are only told the date
+    pub since: Option<chrono::NaiveDate>,
+    /// country, and language, the web and news searches are scoped to
+    pub region: Option<agent::tools::Region>,
+    /// the number of tokens every request of the agents is fitted into, with its tool definitions
+    /// and room for the reply, older turns and recalled passages are left out first; the whole
+    /// history is sent when None
+    pub context_budget: Option<usize>,
+    /// the number of passages of the documents recalled for the latest step of every agent, for
+    /// agents with a context budget
+    pub recall_passages: usize,
+    /// the number of candidates sampled for the synthesis steps, the partial reports, the
+    /// judgement of a debate and the comparison, and how the best of them is kept
+    pub best_of: Option<(usize, Selection)>,
+    /// answer only from the local corpus, all web tools are disabled
+    pub offline: bool,
+    /// the tools the orchestrator and the sub-agents may use
+    pub tool_policy: ToolPolicy,
+    /// directory with orchestrator.md and subagent.md templates that replace the built-in
+    /// prompts and are re-read at the start of every agent step
+    pub prompt_dir: Option<std::path::PathBuf>,
+}
+
+impl Config {
+    pub fn preset(&self) -> Option<Preset> {
+        self.persona.map(|persona| persona.preset())
+    }
+
+    // the optional tools of the config and those of the preset, e.g. literature reviews always
+    // walk the citation graph
+    pub fn scholar(&self) -> bool {
+        self.scholar || self.preset().is_some_and(|preset| preset.tools.scholar)
+    }
+
+    // presets that follow the news search GDELT, which needs no api key, unless the config names
+    // a news api
+    pub fn news(&self) -> Option<NewsSource> {
+        self.news.or_else(|| {
+            self.preset()
+                .filter(|preset| preset.tools.news)
+                .map(|_| NewsSource::Gdelt)
+        })
+    }
+
+    // the tools of a role are those of the tool policy, without web access when offline
+    pub fn tools(&self, role: Role) -> ToolSelection {
+        let mut tools = self.tool_policy.role(role);
+        if self.offline {
+            tools.web_search = false;
+        }
+        tools
+    }
+
+    // fits the requests of an agent into the context budget, if there is one, with passages
+    // recalled from the documents for its latest step, whose embeddings are recorded in usage
+    pub fn context_budget(
+        &self,
+        mut builder: agent::AgentBuilder,
+        documents: Option<&Arc<agent::tools::VectorMemory>>,
+        usage: &Arc<agent::llm::Usage>,
+    ) -> agent::AgentBuilder {
+        if let Some(budget) = self.context_budget {
+            builder = builder.context_budget(budget);
+            if let Some(documents) = documents
+                && self.recall_passages > 0
+            {
+                builder =
+                    builder.context_provider(documents.recall(self.recall_passages, usage.clone()));
+            }
+        }
+        builder
+    }
+
+    // the model of the synthesis steps, which keeps the best of several candidates with best_of
+    pub fn synthesis(
+        &self,
+        llm: &Arc<dyn agent::llm::LLM + Send + Sync>,
+    ) -> Arc<dyn agent::llm::LLM + Send + Sync> {
+        match self.best_of {
+            Some((n, selection)) if n > 1 => {
+                agent::llm::BestOf::new(llm.clone(), n, selection.selector(llm))
+            }
+            _ => llm.clone(),
+        }
+    }
+
+    // the working directory of the run, a resumed run gets back the one it had created
+    pub fn run_context(&self, resumed: bool) -> agent::Result<Arc<RunContext>> {
+        let root = self
+            .work_root
+            .clone()
+            .unwrap_or_else(|| std::env::temp_dir().join("research"));
+        let run_id = match self.run_id.as_str() {
+            "" => new_run_id(),
+            run_id => run_id.to_string(),
+        };
+        if resumed {
+            RunContext::reopen(&root, &run_id, self.keep_work_dir)
+        } else {
+            RunContext::new(&root, &run_id, self.keep_work_dir)
+        }
+    }
+}
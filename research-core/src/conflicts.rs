@@ -0,0 +1,92 @@
+use crate::config::Config;
+use crate::prompts;
+use crate::state::SharedState;
+use agent::Result;
+use agent::llm::{self, CompletionRequest, Message};
+use agent::tools;
+use async_trait::async_trait;
+use std::sync::Arc;
+
+pub struct FindConflicts {
+    state: SharedState,
+    llm: Arc<dyn llm::LLM + Send + Sync>,
+    config: Arc<Config>,
+}
+
+impl FindConflicts {
+    pub fn new(
+        state: SharedState,
+        llm: Arc<dyn llm::LLM + Send + Sync>,
+        config: Arc<Config>,
+    ) -> Box<Self> {
+        Box::new(Self { state, llm, config })
+    }
+
+    fn format_findings(&self) -> Option<String> {
+        let state = self.state.lock().unwrap();
+        if state.findings.len() < 2 {
+            return None;
+        }
+
+        Some(
+            state
+                .findings
+                .iter()
+                .map(|f| {
+                    format!(
+                        "<finding subagent=\"{}\">\n<task>\n{}\n</task>\n<result>\n{}\n</result>\n</finding>\n",
+                        f.subagent, f.task, f.result
+                    )
+                })
+                .collect(),
+        )
+    }
+}
+
+#[async_trait]
+impl tools::FunctionalTool for FindConflicts {
+    fn definition(&self) -> Result<tools::ToolDefinition> {
+        Ok(tools::ToolDefinition::no_args(
+            "find_conflicts",
+            "This tool reviews the findings of all completed sub-agents and reports any statements that conflict with each other, along with their sources and suggested follow-up research tasks. Use this tool after several sub-agents have completed, and start targeted sub-agents to resolve any important conflicts before writing the final report.",
+        ))
+    }
+
+    async fn invoke_fn(
+        &mut self,
+        call: &tools::ToolCall,
+        _: &tools::ToolContext,
+    ) -> Result<Message> {
+        let result = match self.format_findings() {
+            Some(findings) => {
+                let res = self
+                    .llm
+                    .completion(CompletionRequest {
+                        messages: &vec![
+                            Arc::new(Message::System(prompts::conflicts(&self.config))),
+                            Arc::new(Message::User(findings)),
+                        ],
+                        tools: &[],
+                        web_search_tool: false,
+                        prefill: None,
+                    })
+                    .await?;
+
+                format!(
+                    "{}\n\nIf any of these conflicts affect the answer to the task, start sub-agents with targeted tasks to resolve them.",
+                    res.content
+                )
+            }
+            None => {
+                "at least two sub-agents must complete before their findings can be checked for conflicts"
+                    .to_string()
+            }
+        };
+
+        Ok(Message::Tool {
+            id: call.id.clone(),
+            name: "find_conflicts".to_string(),
+            result: result.into(),
+        })
+    }
+}
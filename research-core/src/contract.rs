@@ -0,0 +1,135 @@
+use crate::citations;
+
+#[derive(clap::ValueEnum, Clone, Copy, Debug, PartialEq)]
+pub enum CitationStyle {
+    /// Numbered references such as [1] with a list of references at the end
+    #[value(alias = "numeric")]
+    Ieee,
+    /// Site and year in parentheses such as (example.com, 2023) with a list of references at the end
+    #[value(alias = "author-year")]
+    Apa,
+    /// Numbered Markdown footnotes with the sources in the footnote definitions
+    Footnotes,
+    /// Inline Markdown links to the sources
+    Links,
+}
+
+// reports cite with markers of the source urls whatever the style, which are replaced with
+// the citations of the style once the report is delivered
+pub(crate) const CITATION_INSTRUCTION: &str = "Cite the sources of every important statement with a citation marker right after the statement, holding the url of the source after an @, such as [@https://example.com/article], or [@https://example.com/a; @https://example.org/b] for several sources. Do not write citations in any other form and do not list the sources at the end, the markers are turned into the citations and references of the report.";
+
+// reports deviating from the target length by more than this fraction violate the contract
+const LENGTH_TOLERANCE: f64 = 0.3;
+
+#[derive(Clone, Debug, Default)]
+pub struct OutputContract {
+    /// approximate number of words the report should have
+    pub target_words: Option<usize>,
+    /// headings that must appear in the report
+    pub sections: Vec<String>,
+    /// how sources are cited in the report
+    pub citation_style: Option<CitationStyle>,
+}
+
+impl OutputContract {
+    pub fn is_empty(&self) -> bool {
+        self.target_words.is_none() && self.sections.is_empty() && self.citation_style.is_none()
+    }
+
+    pub fn instructions(&self) -> String {
+        let mut instructions = Vec::new();
+        if let Some(words) = self.target_words {
+            instructions.push(format!("The report must be about {} words long.", words));
+        }
+        if !self.sections.is_empty() {
+            instructions.push(format!(
+                "The report must contain a Markdown heading for each of these sections: {}.",
+                self.sections.join(", ")
+            ));
+        }
+        if self.citation_style.is_some() {
+            instructions.push(CITATION_INSTRUCTION.to_string());
+        }
+        instructions.push(
+            "These requirements take precedence over any other formatting instructions, the report is checked against them before it is delivered.".to_string(),
+        );
+        instructions.join("\n")
+    }
+
+    // returns a description of every requirement the report does not meet
+    pub fn validate(&self, report: &str) -> Vec<String> {
+        let mut violations = Vec::new();
+
+        if let Some(target) = self.target_words {
+            let words = report.split_whitespace().count();
+            if (words as f64 - target as f64).abs() > target as f64 * LENGTH_TOLERANCE {
+                violations.push(format!(
+                    "the report has {} words but should have about {}",
+                    words, target
+                ));
+            }
+        }
+
+        let headings = report
+            .lines()
+            .filter_map(|line| line.trim_start().strip_prefix('#'))
+            .map(|heading| heading.trim_start_matches('#').trim().to_lowercase())
+            .collect::<Vec<_>>();
+        for section in &self.sections {
+            let lower = section.to_lowercase();
+            if !headings.iter().any(|heading| heading.contains(&lower)) {
+                violations.push(format!(
+                    "the report has no heading for the section '{}'",
+                    section
+                ));
+            }
+        }
+
+        if self.citation_style.is_some() && !citations::has_markers(report) {
+            violations.push(format!(
+                "the report does not cite its sources as required: {}",
+                CITATION_INSTRUCTION
+            ));
+        }
+
+        violations
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{CitationStyle, OutputContract};
+
+    #[test]
+    fn test_validate() {
+        let contract = OutputContract {
+            target_words: Some(12),
+            sections: vec!["Summary".to_string(), "Sources".to_string()],
+            citation_style: Some(CitationStyle::Ieee),
+        };
+
+        let report =
+            "# Summary\nThe market grew by 5% in 2023 [@https://a.com].\n## Sources\n1. report";
+        assert!(contract.validate(report).is_empty());
+
+        let violations = contract.validate("# Summary\nThe market grew by 5% in 2023.");
+        assert_eq!(violations.len(), 2);
+        assert!(violations[0].contains("'Sources'"));
+        assert!(violations[1].contains("cite"));
+
+        let long = format!(
+            "# Summary\n## Sources\n{} [@https://a.com]",
+            "word ".repeat(40)
+        );
+        assert!(contract.validate(&long)[0].contains("words"));
+
+        let uncited = |report: &str| contract.validate(report).iter().any(|v| v.contains("cite"));
+        assert!(!uncited("as shown [@https://a.com; @http://b.com]."));
+        assert!(uncited("as shown (Smith, 2021)."));
+        assert!(uncited("see [a](https://a.com) [@a]"));
+        assert!(uncited("as shown [1]."));
+
+        assert!(OutputContract::default().is_empty());
+        assert!(OutputContract::default().validate("").is_empty());
+    }
+}
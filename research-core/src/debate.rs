@@ -0,0 +1,376 @@
+use crate::config::{self, Config};
+use crate::inputs::Documents;
+use crate::presets::Role;
+use crate::prompts;
+use crate::research::{CompleteTask, TaskCompleted};
+use crate::subagents::SPILL_THRESHOLD;
+use crate::warnings::warn;
+use agent::artifacts::ArtifactStore;
+use agent::llm::{self, CompletionRequest, Message};
+use agent::{Agent, AgentBuilder, Error, Result, callbacks, tools};
+use std::path::Path;
+use std::sync::Arc;
+use tokio_util::sync::CancellationToken;
+
+// the question of a debate and the two positions argued, the moderator frames the positions
+// when none are given
+pub struct Motion {
+    pub question: String,
+    pub positions: Vec<String>,
+}
+
+#[derive(Debug, PartialEq)]
+struct Turn {
+    round: usize,
+    debater: usize,
+    argument: String,
+}
+
+// the moderator frames the debate as a json array of the two opposing positions
+fn parse_positions(content: &str) -> Result<Vec<String>> {
+    let content = content.trim();
+    let content = content
+        .strip_prefix("```json")
+        .or_else(|| content.strip_prefix("```"))
+        .and_then(|c| c.strip_suffix("```"))
+        .unwrap_or(content);
+
+    let positions: Vec<String> = serde_json::from_str(content.trim())
+        .map_err(|e| Error::AgentWorkflowError(format!("unexpected debate framing: {}", e)))?;
+    if positions.len() != 2 || positions.iter().any(|p| p.trim().is_empty()) {
+        return Err(Error::AgentWorkflowError(format!(
+            "the moderator framed {} positions instead of two",
+            positions.len()
+        )));
+    }
+    Ok(positions)
+}
+
+fn round_prompt(
+    question: &str,
+    round: usize,
+    rounds: usize,
+    opponent: Option<&str>,
+    notes: Option<&str>,
+) -> String {
+    let mut prompt = format!("<question>\n{}\n</question>\n", question);
+    if let Some(opponent) = opponent {
+        prompt.push_str(&format!(
+            "<opponent_argument>\n{}\n</opponent_argument>\n",
+            opponent
+        ));
+    }
+    if let Some(notes) = notes {
+        prompt.push_str(&format!(
+            "<moderator_notes>\n{}\n</moderator_notes>\n",
+            notes
+        ));
+    }
+    prompt.push_str(&match round {
+        1 => format!(
+            "This is round 1 of {}. Research the question and make your opening argument for your position.",
+            rounds
+        ),
+        round if round == rounds => format!(
+            "This is round {} of {}, the final round. Rebut your opponent's last argument and make your closing argument for your position.",
+            round, rounds
+        ),
+        round => format!(
+            "This is round {} of {}. Research the evidence you need, rebut your opponent's last argument, and strengthen your position.",
+            round, rounds
+        ),
+    });
+    prompt
+}
+
+fn transcript(question: &str, positions: &[String], turns: &[Turn]) -> String {
+    let mut transcript = format!("# Debate: {}\n\n", question);
+    for (i, position) in positions.iter().enumerate() {
+        transcript.push_str(&format!("- Debater {}: {}\n", i + 1, position));
+    }
+    for turn in turns {
+        transcript.push_str(&format!(
+            "\n## Round {}, debater {}\n\n{}\n",
+            turn.round,
+            turn.debater + 1,
+            turn.argument
+        ));
+    }
+    transcript
+}
+
+async fn complete(
+    llm: &Arc<dyn llm::LLM + Send + Sync>,
+    system: String,
+    request: String,
+    usage: &llm::Usage,
+    cancel: &CancellationToken,
+) -> Result<String> {
+    let messages = vec![
+        Arc::new(Message::System(system)),
+        Arc::new(Message::User(request)),
+    ];
+    tokio::select! {
+        _ = cancel.cancelled() => Err(Error::Cancelled("debate was cancelled".to_string())),
+        res = llm.completion(CompletionRequest {
+            messages: &messages,
+            tools: &[],
+            web_search_tool: false,
+            prefill: None,
+        }) => {
+            let res = res?;
+            usage.record(res.usage);
+            Ok(res.content)
+        }
+    }
+}
+
+// a research agent arguing one position, its history is kept across the rounds so it builds on
+// its own earlier research, and only replaced once a round produced an argument
+struct Debater {
+    agent: Agent,
+    history: Vec<Arc<Message>>,
+}
+
+impl Debater {
+    async fn argue(&mut self, prompt: String, cancel: &CancellationToken) -> Result<String> {
+        let mut history = self.history.clone();
+        history.push(Arc::new(Message::User(prompt)));
+        let history = self.agent.run(history, cancel).await?;
+
+        let argument = match history.last().map(|m| m.as_ref()) {
+            Some(Message::Tool { name, result, .. }) if name == "complete_task" => {
+                result.to_string()
+            }
+            _ => {
+                return Err(Error::AgentWorkflowError(
+                    "the debater stopped without submitting an argument".to_string(),
+                ));
+            }
+        };
+        self.history = history;
+        Ok(argument)
+    }
+}
+
+// two research agents argue opposing positions on the question over several rounds, the
+// moderator frames the debate and points each round at the contested claims, and a judge
+// synthesizes the transcript into a balanced answer, returned with the usage of the whole debate
+pub async fn debate(
+    llm: Arc<dyn llm::LLM + Send + Sync>,
+    log_dir: &Path,
+    mut config: Config,
+    motion: Motion,
+    rounds: usize,
+    documents: Option<&Documents>,
+    cancel: &CancellationToken,
+) -> Result<(String, llm::TokenUsage)> {
+    if rounds == 0 {
+        return Err(Error::MissingArg(
+            "a debate needs at least one round".to_string(),
+        ));
+    }
+    if config.run_id.is_empty() {
+        config.run_id = config::new_run_id();
+    }
+    let config = Arc::new(config);
+    let usage = llm::Usage::new();
+    let Motion {
+        question,
+        positions,
+    } = motion;
+    let positions = match positions.len() {
+        0 => {
+            let framing = complete(
+                &llm,
+                prompts::moderator(&config),
+                format!(
+                    "<question>\n{}\n</question>\nFrame the debate on this question.",
+                    question
+                ),
+                &usage,
+                cancel,
+            )
+            .await?;
+            parse_positions(&framing)?
+        }
+        2 => positions,
+        n => {
+            return Err(Error::MissingArg(format!(
+                "a debate needs two positions, {} were given",
+                n
+            )));
+        }
+    };
+    let task = match documents {
+        Some(documents) => documents.task_prompt(&question),
+        None => question.clone(),
+    };
+
+    let artifacts = ArtifactStore::new(&log_dir.join("artifacts"))?;
+    let web = tools::WebAccess::new(config.web_policy.clone());
+    let work_dir = config.run_context(false)?;
+    let mut debaters = Vec::with_capacity(2);
+    for (i, position) in positions.iter().enumerate() {
+        let name = format!("debater_{}", i + 1);
+        let file = std::fs::File::create(log_dir.join(format!("{}.md", name)))?;
+        let events = std::fs::File::create(log_dir.join(format!("{}.events.jsonl", name)))?;
+        let mut builder = AgentBuilder::new()
+            .name(&name)
+            .usage(usage.clone())
+            .llm(llm.clone())
+            .tool(Box::new(CompleteTask))
+            .tool(tools::SummarizeHistory::new(llm.clone(), 2))
+            .spill_tool_results(artifacts.clone(), SPILL_THRESHOLD)
+            .run_context(work_dir.clone())
+            .recover_context_overflow();
+        if let Some(step_timeout) = config.step_timeout {
+            builder = builder.step_timeout(step_timeout);
+        }
+        if let Some(documents) = documents {
+            builder = builder.tool(documents.memory.search_tool());
+        }
+        builder = config.context_budget(
+            builder,
+            documents.map(|documents| &documents.memory),
+            &usage,
+        );
+
+        let agent = config
+            .tools(Role::SubAgent)
+            .apply(builder, &web, &config)?
+            .callback(tools::SummarizeHistory::new(llm.clone(), 2))
+            .callback(callbacks::TeeLogger::new(vec![
+                callbacks::MessageLogger::new(&name, file)?,
+                callbacks::EventLogger::new(&name, events),
+            ]))
+            .stop_condition(Box::new(TaskCompleted))
+            .build()?;
+        debaters.push(Debater {
+            agent,
+            history: vec![Arc::new(Message::System(prompts::debater(
+                &config, position,
+            )))],
+        });
+    }
+
+    // the working directory is cleaned up however the debate ends
+    let res = async {
+        let mut turns: Vec<Turn> = Vec::new();
+        let mut notes: Option<String> = None;
+        for round in 1..=rounds {
+            // both debaters answer the other's argument of the previous round at the same time
+            let opponent = |debater: usize| {
+                turns
+                    .iter()
+                    .rev()
+                    .find(|turn| turn.debater != debater)
+                    .map(|turn| turn.argument.as_str())
+            };
+            let prompts = [
+                round_prompt(&task, round, rounds, opponent(0), notes.as_deref()),
+                round_prompt(&task, round, rounds, opponent(1), notes.as_deref()),
+            ];
+            let [first, second] = &mut debaters[..] else {
+                unreachable!("a debate has two debaters");
+            };
+            let [first_prompt, second_prompt] = prompts;
+            let (first, second) = tokio::try_join!(
+                first.argue(first_prompt, cancel),
+                second.argue(second_prompt, cancel)
+            )?;
+            turns.push(Turn {
+                round,
+                debater: 0,
+                argument: first,
+            });
+            turns.push(Turn {
+                round,
+                debater: 1,
+                argument: second,
+            });
+            std::fs::write(
+                log_dir.join("debate_transcript.md"),
+                transcript(&question, &positions, &turns),
+            )?;
+
+            if round < rounds {
+                notes = Some(
+                    complete(
+                        &llm,
+                        prompts::moderator(&config),
+                        format!(
+                            "{}\nWrite your notes for round {} of {}.",
+                            transcript(&question, &positions, &turns),
+                            round + 1,
+                            rounds
+                        ),
+                        &usage,
+                        cancel,
+                    )
+                    .await?,
+                );
+            }
+        }
+
+        let synthesis = complete(
+            &config.synthesis(&llm),
+            prompts::judge(&config),
+            transcript(&question, &positions, &turns),
+            &usage,
+            cancel,
+        )
+        .await?;
+        std::fs::write(log_dir.join("debate.md"), &synthesis)?;
+
+        Ok((synthesis, usage.total()))
+    }
+    .await;
+    if let Err(e) = work_dir.finish(res.is_ok()) {
+        warn(
+            log_dir,
+            &format!("the working directory could not be cleaned up: {}", e),
+        );
+    }
+    res
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Turn, parse_positions, round_prompt, transcript};
+
+    #[test]
+    fn test_parse_positions() {
+        assert_eq!(
+            parse_positions("```json\n[\"Rates will fall\", \"Rates will stay high\"]\n```")
+                .unwrap(),
+            vec![
+                "Rates will fall".to_string(),
+                "Rates will stay high".to_string()
+            ]
+        );
+        assert!(parse_positions("[\"only one side\"]").is_err());
+        assert!(parse_positions("both sides").is_err());
+    }
+
+    #[test]
+    fn test_round_prompt() {
+        let opening = round_prompt("Will rates fall?", 1, 3, None, None);
+        assert!(opening.starts_with("<question>\nWill rates fall?\n</question>\nThis is round 1"));
+
+        let closing = round_prompt("Will rates fall?", 3, 3, Some("they will"), Some("why?"));
+        assert!(closing.contains("<opponent_argument>\nthey will\n</opponent_argument>"));
+        assert!(closing.contains("<moderator_notes>\nwhy?\n</moderator_notes>"));
+        assert!(closing.ends_with("make your closing argument for your position."));
+
+        let turns = vec![Turn {
+            round: 1,
+            debater: 1,
+            argument: "no".to_string(),
+        }];
+        assert!(
+            transcript("Q", &["yes".to_string(), "no".to_string()], &turns)
+                .ends_with("- Debater 2: no\n\n## Round 1, debater 2\n\nno\n")
+        );
+    }
+}
@@ -0,0 +1,644 @@
+use crate::config::Config;
+use crate::inputs::truncate;
+use crate::prompts;
+use agent::callbacks::{self, Event, EventKind};
+use agent::llm::{self, CompletionRequest, Message};
+use agent::{Error, Result};
+use std::collections::BTreeSet;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use tokio_util::sync::CancellationToken;
+
+const EVENTS_SUFFIX: &str = ".events.jsonl";
+const ORCHESTRATOR: &str = "orchestrator";
+// tool arguments are shown on one line in the alignment
+const MAX_ARGS: usize = 120;
+// the texts around the first divergence and the reports are cut so that the request stays small
+const MAX_TEXT: usize = 2000;
+const MAX_REPORT: usize = 4000;
+
+#[derive(Clone, Debug, PartialEq)]
+struct Call {
+    tool: String,
+    args: String,
+}
+
+impl Call {
+    fn new(tool: &str, args: &str) -> Self {
+        // arguments that only differ in formatting or key order are the same call
+        let args = serde_json::from_str::<serde_json::Value>(args)
+            .map(|args| args.to_string())
+            .unwrap_or_else(|_| args.trim().to_string());
+        Self {
+            tool: tool.to_string(),
+            args,
+        }
+    }
+
+    fn render(&self) -> String {
+        let args = self.args.split_whitespace().collect::<Vec<_>>().join(" ");
+        match args.char_indices().nth(MAX_ARGS) {
+            Some((i, _)) => format!("{} {}...", self.tool, &args[..i]),
+            None => format!("{} {}", self.tool, args),
+        }
+    }
+}
+
+// one step of an agent as its events record it: what the model said, which tools it called,
+// and what they returned
+#[derive(Clone, Debug, Default, PartialEq)]
+struct Step {
+    step: u32,
+    text: String,
+    calls: Vec<Call>,
+    results: Vec<(String, String)>,
+}
+
+// after a history_cleared event the logger writes the messages that replace the removed ones,
+// such as a summary, with the number of the current step, and logs of earlier versions the whole
+// history again. Either way only the last turn is new
+fn last_turn<'a>(events: &'a [&'a Event]) -> &'a [&'a Event] {
+    let mut start = events.len();
+    while start > 0 && events[start - 1].kind == EventKind::ToolResult {
+        start -= 1;
+    }
+    while start > 0 && events[start - 1].kind == EventKind::ToolCall {
+        start -= 1;
+    }
+    if start > 0 && events[start - 1].kind == EventKind::Assistant {
+        start -= 1;
+    }
+    &events[start..]
+}
+
+fn steps(events: &[Event]) -> Vec<Step> {
+    let mut groups: Vec<(u32, Vec<&Event>)> = Vec::new();
+    for event in events {
+        match groups.last_mut() {
+            Some((step, group)) if *step == event.step => group.push(event),
+            _ => groups.push((event.step, vec![event])),
+        }
+    }
+
+    let mut steps = Vec::new();
+    for (step, group) in groups {
+        let group = match group
+            .iter()
+            .rposition(|e| e.kind == EventKind::HistoryCleared)
+        {
+            Some(cleared) => last_turn(&group[cleared + 1..]).to_vec(),
+            None => group,
+        };
+        let mut res = Step {
+            step,
+            ..Default::default()
+        };
+        for event in group {
+            let tool = event.tool.as_deref().unwrap_or_default();
+            match event.kind {
+                EventKind::Assistant => res.text.push_str(&event.text),
+                EventKind::ToolCall => res.calls.push(Call::new(tool, &event.text)),
+                EventKind::ToolResult => res.results.push((tool.to_string(), event.text.clone())),
+                EventKind::System
+                | EventKind::User
+                | EventKind::Developer
+                | EventKind::HistoryCleared => {}
+            }
+        }
+        // the task and the system prompt are the only content of the first callback
+        if !res.text.is_empty() || !res.calls.is_empty() || !res.results.is_empty() {
+            steps.push(res);
+        }
+    }
+    steps
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Alignment {
+    Same,
+    // the same tools were called with different arguments
+    Arguments,
+    Diverged,
+    OnlyA,
+    OnlyB,
+}
+
+impl Alignment {
+    fn of(a: Option<&Step>, b: Option<&Step>) -> Self {
+        match (a, b) {
+            (Some(a), Some(b)) if a.calls == b.calls => Self::Same,
+            (Some(a), Some(b))
+                if a.calls
+                    .iter()
+                    .map(|c| &c.tool)
+                    .eq(b.calls.iter().map(|c| &c.tool)) =>
+            {
+                Self::Arguments
+            }
+            (Some(_), None) => Self::OnlyA,
+            (None, Some(_)) => Self::OnlyB,
+            _ => Self::Diverged,
+        }
+    }
+}
+
+// how well two steps match when they are aligned, steps that call different tools are not
+fn match_score(a: &Step, b: &Step) -> Option<usize> {
+    match Alignment::of(Some(a), Some(b)) {
+        Alignment::Same => Some(2),
+        Alignment::Arguments => Some(1),
+        _ => None,
+    }
+}
+
+// pairs the steps of both runs so that as many steps as possible call the same tools, like a
+// diff of lines, so that one extra step in a run does not make every later step diverge. The
+// unmatched steps between two matches are paired as diverged steps, the rest are only in one run
+fn align_steps(a: Vec<Step>, b: Vec<Step>) -> Vec<(Alignment, Option<Step>, Option<Step>)> {
+    // best[i][j] is the best score of aligning a[i..] with b[j..]
+    let mut best = vec![vec![0; b.len() + 1]; a.len() + 1];
+    for i in (0..a.len()).rev() {
+        for j in (0..b.len()).rev() {
+            let matched = match_score(&a[i], &b[j]).map_or(0, |score| score + best[i + 1][j + 1]);
+            best[i][j] = matched.max(best[i + 1][j]).max(best[i][j + 1]);
+        }
+    }
+
+    let flush = |pairs: &mut Vec<_>, only_a: &mut Vec<Step>, only_b: &mut Vec<Step>| {
+        let (mut rest_a, mut rest_b) = (only_a.drain(..), only_b.drain(..));
+        loop {
+            let (a, b) = (rest_a.next(), rest_b.next());
+            if a.is_none() && b.is_none() {
+                break;
+            }
+            pairs.push((Alignment::of(a.as_ref(), b.as_ref()), a, b));
+        }
+    };
+    let mut pairs = Vec::new();
+    let (mut only_a, mut only_b) = (Vec::new(), Vec::new());
+    let (mut a, mut b) = (a.into_iter().peekable(), b.into_iter().peekable());
+    let (mut i, mut j) = (0, 0);
+    while let (Some(step_a), Some(step_b)) = (a.peek(), b.peek()) {
+        let matched = match_score(step_a, step_b)
+            .is_some_and(|score| best[i][j] == score + best[i + 1][j + 1]);
+        if matched {
+            flush(&mut pairs, &mut only_a, &mut only_b);
+            let (step_a, step_b) = (a.next(), b.next());
+            pairs.push((
+                Alignment::of(step_a.as_ref(), step_b.as_ref()),
+                step_a,
+                step_b,
+            ));
+            (i, j) = (i + 1, j + 1);
+        } else if best[i][j] == best[i + 1][j] {
+            only_a.extend(a.next());
+            i += 1;
+        } else {
+            only_b.extend(b.next());
+            j += 1;
+        }
+    }
+    only_a.extend(a);
+    only_b.extend(b);
+    flush(&mut pairs, &mut only_a, &mut only_b);
+    pairs
+}
+
+// the step numbers of an aligned pair of steps, which differ once one run took more steps
+fn step_label(a: Option<&Step>, b: Option<&Step>) -> String {
+    match (a, b) {
+        (Some(a), Some(b)) if a.step != b.step => format!("{} in a, {} in b", a.step, b.step),
+        (Some(step), _) | (None, Some(step)) => step.step.to_string(),
+        (None, None) => String::new(),
+    }
+}
+
+// the steps of an agent in both runs, aligned by the tools they call
+struct AgentDiff {
+    name: String,
+    steps: Vec<(Alignment, Option<Step>, Option<Step>)>,
+}
+
+impl AgentDiff {
+    fn new(name: &str, a: Vec<Step>, b: Vec<Step>) -> Self {
+        Self {
+            name: name.to_string(),
+            steps: align_steps(a, b),
+        }
+    }
+
+    fn label(&self, i: usize) -> String {
+        let (_, a, b) = &self.steps[i];
+        step_label(a.as_ref(), b.as_ref())
+    }
+
+    fn divergence(&self) -> Option<usize> {
+        self.steps
+            .iter()
+            .position(|(alignment, _, _)| *alignment != Alignment::Same)
+    }
+
+    fn render(&self) -> String {
+        let mut out = format!("## {}\n", self.name);
+        match self.divergence() {
+            Some(i) => out.push_str(&format!("The runs diverge at step {}.\n\n", self.label(i))),
+            None => out.push_str("The runs made the same tool calls.\n\n"),
+        }
+        let calls = |step: &Option<Step>| {
+            step.as_ref()
+                .map(|s| s.calls.iter().map(Call::render).collect::<Vec<_>>())
+                .unwrap_or_default()
+        };
+        for (i, (alignment, a, b)) in self.steps.iter().enumerate() {
+            let (a, b) = (calls(a), calls(b));
+            let label = match alignment {
+                Alignment::Same => {
+                    let tools = a
+                        .iter()
+                        .map(|c| c.split(' ').next().unwrap_or_default())
+                        .collect::<Vec<_>>();
+                    if tools.is_empty() {
+                        out.push_str(&format!("- step {}: same, no tool calls\n", self.label(i)));
+                    } else {
+                        out.push_str(&format!(
+                            "- step {}: same {}\n",
+                            self.label(i),
+                            tools.join(", ")
+                        ));
+                    }
+                    continue;
+                }
+                Alignment::Arguments => "different arguments",
+                Alignment::Diverged => "diverged",
+                Alignment::OnlyA => "only in run a",
+                Alignment::OnlyB => "only in run b",
+            };
+            out.push_str(&format!("- step {}: {}\n", self.label(i), label));
+            for (run, calls) in [("a", &a), ("b", &b)] {
+                if *alignment == Alignment::OnlyA && run == "b"
+                    || *alignment == Alignment::OnlyB && run == "a"
+                {
+                    continue;
+                }
+                if calls.is_empty() {
+                    out.push_str(&format!("  - {}: no tool calls\n", run));
+                }
+                for call in calls {
+                    out.push_str(&format!("  - {}: {}\n", run, call));
+                }
+            }
+        }
+        out
+    }
+}
+
+// a saved run, read from its log directory
+struct Run {
+    dir: PathBuf,
+    manifest: serde_json::Value,
+    // events file of each agent by its path relative to the log directory
+    agents: Vec<(String, Vec<Event>)>,
+}
+
+fn collect_event_files(dir: &Path, files: &mut Vec<PathBuf>) -> Result<()> {
+    for entry in std::fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.is_dir() {
+            collect_event_files(&path, files)?;
+        } else if path
+            .file_name()
+            .is_some_and(|name| name.to_string_lossy().ends_with(EVENTS_SUFFIX))
+        {
+            files.push(path);
+        }
+    }
+    Ok(())
+}
+
+impl Run {
+    fn load(dir: &Path) -> Result<Self> {
+        let mut files = Vec::new();
+        collect_event_files(dir, &mut files)?;
+        if files.is_empty() {
+            return Err(Error::MissingArg(format!(
+                "{} does not contain the event log of a run",
+                dir.display()
+            )));
+        }
+        let mut agents = Vec::new();
+        for file in files {
+            let name = file
+                .strip_prefix(dir)
+                .unwrap_or(&file)
+                .to_string_lossy()
+                .trim_end_matches(EVENTS_SUFFIX)
+                .to_string();
+            // a rotated log continues in the file from its oldest segment on
+            let mut content = Vec::new();
+            for segment in callbacks::segments(&file) {
+                content.extend(callbacks::read_segment(&segment)?);
+            }
+            content.extend(std::fs::read(&file)?);
+            let events = String::from_utf8_lossy(&content)
+                .lines()
+                .filter_map(|line| serde_json::from_str(line).ok())
+                .collect();
+            agents.push((name, events));
+        }
+        let manifest = std::fs::read_to_string(dir.join("manifest.json"))
+            .ok()
+            .and_then(|manifest| serde_json::from_str(&manifest).ok())
+            .unwrap_or_default();
+        Ok(Self {
+            dir: dir.to_path_buf(),
+            manifest,
+            agents,
+        })
+    }
+
+    fn events(&self, agent: &str) -> &[Event] {
+        self.agents
+            .iter()
+            .find(|(name, _)| name == agent)
+            .map(|(_, events)| events.as_slice())
+            .unwrap_or_default()
+    }
+
+    // the last draft the orchestrator submitted
+    fn report(&self) -> Option<String> {
+        self.events(ORCHESTRATOR)
+            .iter()
+            .rev()
+            .filter(|e| e.kind == EventKind::ToolCall && e.tool.as_deref() == Some("complete_task"))
+            .find_map(|e| {
+                let args = serde_json::from_str::<serde_json::Value>(&e.text).ok()?;
+                args["report"].as_str().map(String::from)
+            })
+    }
+
+    fn describe(&self, label: &str) -> String {
+        let field = |key: &str| self.manifest[key].as_str().unwrap_or("unknown").to_string();
+        let mut out = format!(
+            "<run name=\"{}\" dir=\"{}\">\n<task>\n{}\n</task>\n<outcome>\n{}, {} tokens\n</outcome>\n",
+            label,
+            self.dir.display(),
+            field("task"),
+            field("status"),
+            self.manifest["total_tokens"]
+        );
+        if let Some(run_abstract) = self.manifest["abstract"].as_str() {
+            out.push_str(&format!("<abstract>\n{}\n</abstract>\n", run_abstract));
+        }
+        if let Some(report) = self.report() {
+            out.push_str(&format!(
+                "<report>\n{}\n</report>\n",
+                truncate(&report, MAX_REPORT)
+            ));
+        }
+        out.push_str("</run>\n");
+        out
+    }
+}
+
+// aligns the agents of both runs by name, the orchestrator first
+fn align(a: &Run, b: &Run) -> Vec<AgentDiff> {
+    let names = a
+        .agents
+        .iter()
+        .chain(&b.agents)
+        .map(|(name, _)| name.as_str())
+        .collect::<BTreeSet<_>>();
+    let mut diffs = names
+        .into_iter()
+        .map(|name| AgentDiff::new(name, steps(a.events(name)), steps(b.events(name))))
+        .collect::<Vec<_>>();
+    diffs.sort_by_key(|diff| diff.name != ORCHESTRATOR);
+    diffs
+}
+
+fn render_step(step: &Step) -> String {
+    let mut out = format!("<step number=\"{}\">\n", step.step);
+    if !step.text.is_empty() {
+        out.push_str(&format!(
+            "<assistant>\n{}\n</assistant>\n",
+            truncate(&step.text, MAX_TEXT)
+        ));
+    }
+    for call in &step.calls {
+        out.push_str(&format!(
+            "<tool_call name=\"{}\">\n{}\n</tool_call>\n",
+            call.tool,
+            truncate(&call.args, MAX_TEXT)
+        ));
+    }
+    for (tool, result) in &step.results {
+        out.push_str(&format!(
+            "<tool_result name=\"{}\">\n{}\n</tool_result>\n",
+            tool,
+            truncate(result, MAX_TEXT)
+        ));
+    }
+    out.push_str("</step>\n");
+    out
+}
+
+// the step before the first divergence of the orchestrator, or of the first agent that diverges,
+// and the diverging step itself in both runs
+fn divergence(diffs: &[AgentDiff]) -> Option<String> {
+    let (diff, i) = diffs
+        .iter()
+        .find_map(|diff| diff.divergence().map(|i| (diff, i)))?;
+    let mut out = format!(
+        "<divergence agent=\"{}\" step=\"{}\">\n",
+        diff.name,
+        diff.label(i)
+    );
+    for (run, pick) in [("a", 0), ("b", 1)] {
+        out.push_str(&format!("<run name=\"{}\">\n", run));
+        for (_, a, b) in &diff.steps[i.saturating_sub(1)..=i] {
+            if let Some(step) = [a, b][pick] {
+                out.push_str(&render_step(step));
+            }
+        }
+        out.push_str("</run>\n");
+    }
+    out.push_str("</divergence>\n");
+    Some(out)
+}
+
+/// Aligns two saved runs of the same task by agent, step, and tool call, and returns the
+/// alignment in Markdown followed by an explanation of why the outcomes of the runs differ,
+/// together with the usage of the explanation.
+pub async fn diff(
+    llm: Arc<dyn llm::LLM + Send + Sync>,
+    config: &Config,
+    run_a: &Path,
+    run_b: &Path,
+    cancel: &CancellationToken,
+) -> Result<(String, llm::TokenUsage)> {
+    let (a, b) = (Run::load(run_a)?, Run::load(run_b)?);
+    let diffs = align(&a, &b);
+    let alignment = format!(
+        "# Diff of {} (a) and {} (b)\n\n{}",
+        run_a.display(),
+        run_b.display(),
+        diffs
+            .iter()
+            .map(AgentDiff::render)
+            .collect::<Vec<_>>()
+            .join("\n")
+    );
+
+    let mut request = a.describe("a");
+    request.push_str(&b.describe("b"));
+    request.push_str(&format!("<alignment>\n{}\n</alignment>\n", alignment));
+    if let Some(divergence) = divergence(&diffs) {
+        request.push_str(&divergence);
+    }
+    let messages = vec![
+        Arc::new(Message::System(prompts::diff(config))),
+        Arc::new(Message::User(request)),
+    ];
+    let res = tokio::select! {
+        _ = cancel.cancelled() => {
+            return Err(Error::Cancelled("diff was cancelled".to_string()));
+        }
+        res = llm.completion(CompletionRequest {
+            messages: &messages,
+            tools: &[],
+            web_search_tool: false,
+            prefill: None,
+        }) => res?,
+    };
+
+    Ok((
+        format!("{}\n## Explanation\n\n{}\n", alignment, res.content.trim()),
+        res.usage,
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{AgentDiff, Alignment, ORCHESTRATOR, Run, steps};
+    use agent::callbacks::{Event, EventKind};
+
+    fn event(step: u32, kind: EventKind, tool: Option<&str>, text: &str) -> Event {
+        Event {
+            time_ms: 0,
+            agent: "orchestrator".to_string(),
+            step,
+            kind,
+            tool: tool.map(String::from),
+            text: text.to_string(),
+        }
+    }
+
+    fn run(queries: &[&str]) -> Vec<Event> {
+        let mut events = vec![
+            event(0, EventKind::System, None, "prompt"),
+            event(0, EventKind::User, None, "task"),
+        ];
+        for (i, query) in queries.iter().enumerate() {
+            let step = i as u32 + 1;
+            let args = format!("{{\"query\": \"{}\"}}", query);
+            events.push(event(step, EventKind::Assistant, None, "searching"));
+            events.push(event(step, EventKind::ToolCall, Some("web_search"), &args));
+            events.push(event(
+                step,
+                EventKind::ToolResult,
+                Some("web_search"),
+                "hits",
+            ));
+        }
+        events
+    }
+
+    #[test]
+    fn test_steps() {
+        let mut events = run(&["ev market", "ev sales"]);
+        // a compaction writes the whole history again within the step it happened in
+        events.push(event(3, EventKind::HistoryCleared, None, ""));
+        events.push(event(3, EventKind::User, None, "summary of the research"));
+        events.push(event(3, EventKind::ToolCall, Some("finalize"), "{}"));
+        events.push(event(3, EventKind::ToolResult, Some("finalize"), "done"));
+
+        let steps = steps(&events);
+        assert_eq!(steps.len(), 3);
+        assert_eq!(steps[0].calls[0].args, "{\"query\":\"ev market\"}");
+        assert_eq!(steps[2].text, "");
+        assert_eq!(steps[2].calls.len(), 1);
+        assert_eq!(steps[2].calls[0].tool, "finalize");
+        assert_eq!(
+            steps[2].results,
+            vec![("finalize".to_string(), "done".to_string())]
+        );
+    }
+
+    #[test]
+    fn test_alignment() {
+        let a = steps(&run(&["ev market", "ev sales", "ev prices"]));
+        let mut b_events = run(&["ev market", "ev exports"]);
+        b_events.push(event(3, EventKind::ToolCall, Some("finalize"), "{}"));
+        let b = steps(&b_events);
+
+        let diff = AgentDiff::new("orchestrator", a, b);
+        assert_eq!(
+            diff.steps.iter().map(|(a, _, _)| *a).collect::<Vec<_>>(),
+            vec![Alignment::Same, Alignment::Arguments, Alignment::Diverged]
+        );
+        assert_eq!(diff.divergence(), Some(1));
+        assert_eq!(
+            diff.render(),
+            "## orchestrator
+The runs diverge at step 2.
+
+- step 1: same web_search
+- step 2: different arguments
+  - a: web_search {\"query\":\"ev sales\"}
+  - b: web_search {\"query\":\"ev exports\"}
+- step 3: diverged
+  - a: web_search {\"query\":\"ev prices\"}
+  - b: finalize {}
+"
+        );
+
+        let diff = AgentDiff::new("subagent_1", steps(&run(&["a"])), vec![]);
+        assert_eq!(diff.steps[0].0, Alignment::OnlyA);
+        assert!(
+            diff.render()
+                .ends_with("- step 1: only in run a\n  - a: web_search {\"query\":\"a\"}\n")
+        );
+
+        // an extra step in one run only shifts the later steps
+        let a = steps(&run(&["ev market", "ev sales", "ev prices"]));
+        let b = steps(&run(&["ev market", "ev prices"]));
+        let diff = AgentDiff::new("orchestrator", a, b);
+        assert_eq!(
+            diff.steps.iter().map(|(a, _, _)| *a).collect::<Vec<_>>(),
+            vec![Alignment::Same, Alignment::OnlyA, Alignment::Same]
+        );
+        assert!(diff.render().ends_with(
+            "- step 2: only in run a\n  - a: web_search {\"query\":\"ev sales\"}\n- step 3 in a, 2 in b: same web_search\n"
+        ));
+    }
+
+    #[test]
+    fn test_load_rotated() {
+        let dir = std::env::temp_dir().join(format!("diff-rotated-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let lines = |events: &[Event]| {
+            events
+                .iter()
+                .map(|e| serde_json::to_string(e).unwrap() + "\n")
+                .collect::<String>()
+        };
+        let events = run(&["ev market", "ev sales"]);
+        std::fs::write(dir.join("orchestrator.events.1.jsonl"), lines(&events[..5])).unwrap();
+        std::fs::write(dir.join("orchestrator.events.jsonl"), lines(&events[5..])).unwrap();
+
+        let run = Run::load(&dir).unwrap();
+        assert_eq!(run.agents.len(), 1);
+        assert_eq!(run.events(ORCHESTRATOR), &events[..]);
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
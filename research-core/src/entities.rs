@@ -0,0 +1,289 @@
+use crate::config::Config;
+use crate::inputs::truncate;
+use crate::prompts;
+use crate::state::SharedState;
+use agent::llm::{self, CompletionRequest, Message};
+use agent::{Error, History, Result, callbacks, tools};
+use async_trait::async_trait;
+use std::sync::{Arc, Mutex};
+
+// only the start of long findings is searched for entity names
+const FINDING_LEN: usize = 4000;
+
+#[derive(serde::Deserialize, Debug, PartialEq)]
+pub struct EntityGroup {
+    pub canonical: String,
+    pub aliases: Vec<String>,
+}
+
+fn parse_groups(content: &str) -> Result<Vec<EntityGroup>> {
+    let content = content.trim();
+    let content = content
+        .strip_prefix("```json")
+        .or_else(|| content.strip_prefix("```"))
+        .and_then(|c| c.strip_suffix("```"))
+        .unwrap_or(content);
+
+    let groups: Vec<EntityGroup> = serde_json::from_str(content.trim()).map_err(|e| {
+        Error::AgentWorkflowError(format!("unexpected entity resolution reply: {}", e))
+    })?;
+    Ok(groups
+        .into_iter()
+        .filter(|group| !group.canonical.trim().is_empty() && !group.aliases.is_empty())
+        .collect())
+}
+
+// normalizes the names of entities across the findings of the sub-agents and the fact table, so
+// that "Alphabet", "Google", and "GOOGL" end up in one row of a table and one section of a report
+pub struct EntityResolver {
+    llm: Arc<dyn llm::LLM + Send + Sync>,
+    config: Arc<Config>,
+    state: SharedState,
+    facts: Arc<tools::FactStore>,
+    usage: Arc<llm::Usage>,
+    // the number of findings and of entities left after the merges of the last resolution
+    resolved: Mutex<(usize, usize)>,
+}
+
+impl EntityResolver {
+    pub fn new(
+        llm: Arc<dyn llm::LLM + Send + Sync>,
+        config: Arc<Config>,
+        state: SharedState,
+        facts: Arc<tools::FactStore>,
+        usage: Arc<llm::Usage>,
+    ) -> Arc<Self> {
+        Arc::new(Self {
+            llm,
+            config,
+            state,
+            facts,
+            usage,
+            resolved: Mutex::new((0, 0)),
+        })
+    }
+
+    fn input(&self) -> Option<(String, usize)> {
+        let entities = self.facts.entities();
+        let findings = self.state.lock().unwrap().findings.clone();
+        if entities.len() + findings.len() < 2 {
+            return None;
+        }
+
+        let mut input = "<fact_table_entities>\n".to_string();
+        for entity in &entities {
+            input.push_str(&format!("- {}\n", entity));
+        }
+        input.push_str("</fact_table_entities>\n");
+        for finding in &findings {
+            input.push_str(&format!(
+                "<finding subagent=\"{}\">\n{}\n</finding>\n",
+                finding.subagent,
+                truncate(&finding.result, FINDING_LEN)
+            ));
+        }
+        Some((input, findings.len()))
+    }
+
+    pub async fn resolve(&self) -> Result<Vec<EntityGroup>> {
+        let Some((input, findings)) = self.input() else {
+            return Ok(Vec::new());
+        };
+        let res = self
+            .llm
+            .completion(CompletionRequest {
+                messages: &vec![
+                    Arc::new(Message::System(prompts::entities(&self.config))),
+                    Arc::new(Message::User(input)),
+                ],
+                tools: &[],
+                web_search_tool: false,
+                prefill: None,
+            })
+            .await?;
+        self.usage.record(res.usage);
+
+        let groups = parse_groups(&res.content)?;
+        for group in &groups {
+            self.facts.merge(&group.canonical, &group.aliases);
+        }
+        // the merges shrink the fact table, which is no reason to resolve again
+        *self.resolved.lock().unwrap() = (findings, self.facts.entities().len());
+        Ok(groups)
+    }
+
+    // nothing is resolved again until new findings or entities arrive
+    fn changed(&self) -> bool {
+        let findings = self.state.lock().unwrap().findings.len();
+        let counts = (findings, self.facts.entities().len());
+        *self.resolved.lock().unwrap() != counts
+    }
+}
+
+pub struct ResolveEntities(pub Arc<EntityResolver>);
+
+#[async_trait]
+impl tools::FunctionalTool for ResolveEntities {
+    fn definition(&self) -> Result<tools::ToolDefinition> {
+        Ok(tools::ToolDefinition::no_args(
+            "resolve_entities",
+            "This tool finds the different names the sub-agent findings and the fact table use for the same entity, such as a company, its ticker, and its former name, merges them in the fact table, and returns the canonical name of each entity. Name entities by their canonical names in the report and its tables.",
+        ))
+    }
+
+    async fn invoke_fn(
+        &mut self,
+        call: &tools::ToolCall,
+        _: &tools::ToolContext,
+    ) -> Result<Message> {
+        let result = match self.0.resolve().await {
+            Ok(groups) if groups.is_empty() => {
+                "no entity is referred to by more than one name".to_string()
+            }
+            Ok(groups) => {
+                let mut result = "Canonical entity names and their aliases:\n".to_string();
+                for group in groups {
+                    result.push_str(&format!(
+                        "- {}: {}\n",
+                        group.canonical,
+                        group.aliases.join(", ")
+                    ));
+                }
+                result
+            }
+            Err(e) => format!("entity resolution failed: {}", e),
+        };
+
+        Ok(Message::Tool {
+            id: call.id.clone(),
+            name: "resolve_entities".to_string(),
+            result: result.into(),
+        })
+    }
+}
+
+// resolves entity names in the background whenever new findings or facts arrived since the
+// last resolution, without holding up the step of the orchestrator
+pub struct EntityResolution {
+    resolver: Arc<EntityResolver>,
+    running: Option<tokio::task::JoinHandle<()>>,
+}
+
+impl EntityResolution {
+    pub fn new(resolver: Arc<EntityResolver>) -> Self {
+        Self {
+            resolver,
+            running: None,
+        }
+    }
+}
+
+#[async_trait]
+impl callbacks::Callback for EntityResolution {
+    async fn call(&mut self, _: &mut dyn History) -> Result<()> {
+        if self
+            .running
+            .as_ref()
+            .is_some_and(|running| !running.is_finished())
+        {
+            return Ok(());
+        }
+        if self.resolver.changed() {
+            let resolver = self.resolver.clone();
+            self.running = Some(tokio::spawn(async move {
+                // best effort, the resolve_entities tool reports failures to the orchestrator
+                let _ = resolver.resolve().await;
+            }));
+        }
+        Ok(())
+    }
+}
+
+impl Drop for EntityResolution {
+    fn drop(&mut self) {
+        if let Some(running) = &self.running {
+            running.abort();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{EntityGroup, EntityResolver, parse_groups};
+    use crate::config::Config;
+    use agent::Result;
+    use agent::llm::{self, CompletionRequest, CompletionResponse, LLM, TokenUsage};
+    use agent::tools::{Fact, FactStore};
+    use async_trait::async_trait;
+    use std::sync::Arc;
+
+    struct Merging;
+
+    #[async_trait]
+    impl LLM for Merging {
+        async fn completion<'a>(&self, _: CompletionRequest<'a>) -> Result<CompletionResponse> {
+            Ok(CompletionResponse {
+                content: "[{\"canonical\": \"Alphabet\", \"aliases\": [\"Google\", \"GOOGL\"]}]"
+                    .to_string(),
+                usage: TokenUsage {
+                    requests: 1,
+                    prompt_tokens: 100,
+                    completion_tokens: 10,
+                },
+                ..Default::default()
+            })
+        }
+    }
+
+    fn fact(entity: &str) -> Fact {
+        Fact {
+            entity: entity.to_string(),
+            attribute: "revenue".to_string(),
+            value: entity.to_string(),
+            source_id: "a".to_string(),
+            date: None,
+            agent: String::new(),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_resolve_counts_after_merges() {
+        let facts = FactStore::new();
+        for entity in ["Google", "GOOGL", "Alphabet", "Microsoft"] {
+            facts.add(fact(entity));
+        }
+        let usage = llm::Usage::new();
+        let resolver = EntityResolver::new(
+            Arc::new(Merging),
+            Arc::new(Config::default()),
+            Default::default(),
+            facts.clone(),
+            usage.clone(),
+        );
+        assert!(resolver.changed());
+
+        assert_eq!(resolver.resolve().await.unwrap().len(), 1);
+        assert_eq!(facts.entities().len(), 2);
+        assert!(!resolver.changed());
+        assert_eq!(usage.total().prompt_tokens, 100);
+
+        facts.add(fact("Amazon"));
+        assert!(resolver.changed());
+    }
+
+    #[test]
+    fn test_parse_groups() {
+        assert_eq!(
+            parse_groups(
+                "```json\n[{\"canonical\": \"Alphabet\", \"aliases\": [\"Google\", \"GOOGL\"]}, {\"canonical\": \"Meta\", \"aliases\": []}]\n```"
+            )
+            .unwrap(),
+            vec![EntityGroup {
+                canonical: "Alphabet".to_string(),
+                aliases: vec!["Google".to_string(), "GOOGL".to_string()],
+            }]
+        );
+        assert!(parse_groups("[]").unwrap().is_empty());
+        assert!(parse_groups("no entities").is_err());
+    }
+}
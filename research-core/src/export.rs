@@ -0,0 +1,799 @@
+//! Exports Markdown reports to Word documents and PDF files with a title page, a table of
+//! contents, and a bibliography of the cited sources.
+
+use crate::sources;
+use agent::{Error, Result};
+use docx_rs::{
+    AlignmentType, BreakType, Docx, Hyperlink, HyperlinkType, Paragraph, Run, RunFonts,
+    SpecialIndentType, Style, StyleType, Table, TableCell, TableOfContents, TableRow,
+};
+use pulldown_cmark::{Event, Options, Tag, TagEnd};
+use std::path::Path;
+
+const MONOSPACE_FONT: &str = "Courier New";
+const LINK_COLOR: &str = "0563C1";
+// indentation of list levels and quotes in word documents, in twentieths of a point
+const INDENT: i32 = 360;
+
+#[derive(clap::ValueEnum, Clone, Copy, Debug, PartialEq)]
+pub enum ExportFormat {
+    /// A Word document
+    Docx,
+    /// A PDF file, typeset with the typst command line tool
+    Pdf,
+}
+
+impl ExportFormat {
+    pub fn extension(&self) -> &'static str {
+        match self {
+            ExportFormat::Docx => "docx",
+            ExportFormat::Pdf => "pdf",
+        }
+    }
+
+    /// Checks that the tools the format needs are installed, so that a run does not fail only
+    /// once its report is written.
+    pub fn check(&self) -> Result<()> {
+        match self {
+            ExportFormat::Docx => Ok(()),
+            ExportFormat::Pdf => {
+                let output = std::process::Command::new("typst")
+                    .arg("--version")
+                    .output()
+                    .map_err(|e| {
+                        Error::Unsupported(format!(
+                            "exporting to pdf needs the typst command line tool: {}",
+                            e
+                        ))
+                    })?;
+                if !output.status.success() {
+                    return Err(Error::Unsupported(format!(
+                        "exporting to pdf needs the typst command line tool, typst --version failed with {}: {}",
+                        output.status,
+                        String::from_utf8_lossy(&output.stderr).trim()
+                    )));
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+/// The layout of exported reports, read from a JSON file in which every field is optional.
+#[derive(Clone, Debug, PartialEq, serde::Serialize, serde::Deserialize)]
+#[serde(default)]
+pub struct Template {
+    /// title of the report, the first top level heading of the report when None
+    pub title: Option<String>,
+    /// line under the title on the title page
+    pub subtitle: Option<String>,
+    /// author or organization on the title page
+    pub author: Option<String>,
+    /// date on the title page, the current date when None
+    pub date: Option<String>,
+    /// start with a page of its own for the title, subtitle, author, and date
+    pub title_page: bool,
+    /// list the sections after the title
+    pub toc: bool,
+    /// heading of the table of contents
+    pub toc_title: String,
+    /// end with a numbered list of the urls the report cites
+    pub bibliography: bool,
+    /// heading of the bibliography
+    pub bibliography_title: String,
+    /// font of the text, the default of the word processor or of typst when None
+    pub font: Option<String>,
+    /// size of the text in points
+    pub font_size: u32,
+}
+
+impl Default for Template {
+    fn default() -> Self {
+        Self {
+            title: None,
+            subtitle: None,
+            author: None,
+            date: None,
+            title_page: true,
+            toc: true,
+            toc_title: "Contents".to_string(),
+            bibliography: true,
+            bibliography_title: "References".to_string(),
+            font: None,
+            font_size: 11,
+        }
+    }
+}
+
+impl Template {
+    pub fn load(path: &Path) -> Result<Self> {
+        Ok(serde_json::from_str(&std::fs::read_to_string(path)?)?)
+    }
+}
+
+// a run of text with the same formatting
+#[derive(Clone, Debug, Default, PartialEq)]
+struct Span {
+    text: String,
+    bold: bool,
+    italic: bool,
+    code: bool,
+    link: Option<String>,
+}
+
+#[derive(Debug, PartialEq)]
+enum Block {
+    Heading(usize, Vec<Span>),
+    Paragraph(Vec<Span>),
+    // an item of a list at a nesting level from zero, with its number in ordered lists
+    Item(usize, Option<u64>, Vec<Span>),
+    Quote(Vec<Span>),
+    Code(String),
+    // the rows of a table, the first is the header
+    Table(Vec<Vec<Vec<Span>>>),
+    Rule,
+}
+
+fn text(spans: &[Span]) -> String {
+    spans.iter().map(|span| span.text.as_str()).collect()
+}
+
+#[derive(Default)]
+struct Parser {
+    blocks: Vec<Block>,
+    spans: Vec<Span>,
+    bold: usize,
+    italic: usize,
+    link: Option<String>,
+    // the number of the next item of every open list, None for unordered lists
+    lists: Vec<Option<u64>>,
+    // the text of the current item has not been added as a block yet
+    item: bool,
+    quote: usize,
+    code: Option<String>,
+    rows: Vec<Vec<Vec<Span>>>,
+    row: Vec<Vec<Span>>,
+}
+
+impl Parser {
+    fn push(&mut self, text: &str, code: bool) {
+        let span = Span {
+            text: text.to_string(),
+            bold: self.bold > 0,
+            italic: self.italic > 0,
+            code,
+            link: self.link.clone(),
+        };
+        match self.spans.last_mut() {
+            Some(last)
+                if (last.bold, last.italic, last.code, &last.link)
+                    == (span.bold, span.italic, span.code, &span.link) =>
+            {
+                last.text.push_str(&span.text)
+            }
+            _ => self.spans.push(span),
+        }
+    }
+
+    // adds the text so far as the block it belongs to
+    fn flush(&mut self) {
+        if self.spans.is_empty() {
+            return;
+        }
+        let spans = std::mem::take(&mut self.spans);
+        let block = if self.item && !self.lists.is_empty() {
+            self.item = false;
+            Block::Item(self.lists.len() - 1, *self.lists.last().unwrap(), spans)
+        } else if self.quote > 0 {
+            Block::Quote(spans)
+        } else {
+            Block::Paragraph(spans)
+        };
+        self.blocks.push(block);
+    }
+
+    fn event(&mut self, event: Event) {
+        match event {
+            Event::Start(Tag::Heading { .. })
+            | Event::Start(Tag::Item)
+            | Event::Start(Tag::CodeBlock(_))
+            | Event::Rule => self.flush(),
+            _ => {}
+        }
+        match event {
+            Event::End(TagEnd::Heading(level)) => {
+                let spans = std::mem::take(&mut self.spans);
+                self.blocks.push(Block::Heading(level as usize, spans));
+            }
+            Event::End(TagEnd::Paragraph) => self.flush(),
+            Event::Start(Tag::List(start)) => {
+                self.flush();
+                self.lists.push(start);
+            }
+            Event::End(TagEnd::List(_)) => {
+                self.flush();
+                self.lists.pop();
+            }
+            Event::Start(Tag::Item) => self.item = true,
+            Event::End(TagEnd::Item) => {
+                self.flush();
+                self.item = false;
+                if let Some(Some(number)) = self.lists.last_mut() {
+                    *number += 1;
+                }
+            }
+            Event::Start(Tag::BlockQuote(_)) => {
+                self.flush();
+                self.quote += 1;
+            }
+            Event::End(TagEnd::BlockQuote(_)) => {
+                self.flush();
+                self.quote = self.quote.saturating_sub(1);
+            }
+            Event::Start(Tag::CodeBlock(_)) => self.code = Some(String::new()),
+            Event::End(TagEnd::CodeBlock) => {
+                let code = self.code.take().unwrap_or_default();
+                self.blocks
+                    .push(Block::Code(code.trim_end_matches('\n').to_string()));
+            }
+            Event::Start(Tag::Table(_)) => self.rows.clear(),
+            Event::End(TagEnd::Table) => {
+                let rows = std::mem::take(&mut self.rows);
+                self.blocks.push(Block::Table(rows));
+            }
+            Event::End(TagEnd::TableHead) | Event::End(TagEnd::TableRow) => {
+                let row = std::mem::take(&mut self.row);
+                self.rows.push(row);
+            }
+            Event::End(TagEnd::TableCell) => {
+                let cell = std::mem::take(&mut self.spans);
+                self.row.push(cell);
+            }
+            Event::Start(Tag::Strong) => self.bold += 1,
+            Event::End(TagEnd::Strong) => self.bold = self.bold.saturating_sub(1),
+            Event::Start(Tag::Emphasis) => self.italic += 1,
+            Event::End(TagEnd::Emphasis) => self.italic = self.italic.saturating_sub(1),
+            Event::Start(Tag::Link { dest_url, .. }) => self.link = Some(dest_url.to_string()),
+            Event::End(TagEnd::Link) => self.link = None,
+            Event::Text(text) => match &mut self.code {
+                Some(code) => code.push_str(&text),
+                None => self.push(&text, false),
+            },
+            Event::Code(text) => self.push(&text, true),
+            Event::SoftBreak | Event::HardBreak => self.push(" ", false),
+            Event::Rule => self.blocks.push(Block::Rule),
+            _ => {}
+        }
+    }
+}
+
+// the blocks of the report, and its title if it starts with a top level heading
+fn parse(report: &str) -> (Option<String>, Vec<Block>) {
+    let mut parser = Parser::default();
+    for event in pulldown_cmark::Parser::new_ext(report, Options::ENABLE_TABLES) {
+        parser.event(event);
+    }
+    parser.flush();
+
+    let mut blocks = parser.blocks;
+    let title = match blocks.first() {
+        Some(Block::Heading(1, spans)) => Some(text(spans)),
+        _ => None,
+    };
+    if title.is_some() {
+        blocks.remove(0);
+    }
+    (title, blocks)
+}
+
+// the report as a Word document; the table of contents is filled in by the word processor when
+// the document is opened
+fn docx(title: &str, blocks: &[Block], bibliography: &[String], template: &Template) -> Docx {
+    let runs = |paragraph: Paragraph, spans: &[Span]| {
+        spans.iter().fold(paragraph, |paragraph, span| {
+            let mut run = Run::new().add_text(&span.text);
+            if span.bold {
+                run = run.bold();
+            }
+            if span.italic {
+                run = run.italic();
+            }
+            if span.code {
+                run = run.fonts(RunFonts::new().ascii(MONOSPACE_FONT));
+            }
+            match &span.link {
+                Some(url) => paragraph.add_hyperlink(
+                    Hyperlink::new(url, HyperlinkType::External)
+                        .add_run(run.color(LINK_COLOR).underline("single")),
+                ),
+                None => paragraph.add_run(run),
+            }
+        })
+    };
+
+    let mut doc = Docx::new()
+        .default_size(template.font_size as usize * 2)
+        .add_style(
+            Style::new("Title", StyleType::Paragraph)
+                .name("Title")
+                .size(template.font_size as usize * 5)
+                .bold(),
+        );
+    if let Some(font) = &template.font {
+        doc = doc.default_fonts(RunFonts::new().ascii(font).hi_ansi(font));
+    }
+    for level in 1..=6 {
+        doc = doc.add_style(
+            Style::new(format!("Heading{}", level), StyleType::Paragraph)
+                .name(format!("Heading {}", level))
+                .size(template.font_size as usize * 2 + (7 - level.min(4)) * 3)
+                .bold()
+                .outline_lvl(level - 1),
+        );
+    }
+
+    if template.title_page {
+        doc = doc.add_paragraph(
+            Paragraph::new()
+                .add_run(Run::new().add_text(title))
+                .style("Title")
+                .align(AlignmentType::Center),
+        );
+        for line in [&template.subtitle, &template.author, &Some(date(template))]
+            .into_iter()
+            .flatten()
+        {
+            doc = doc.add_paragraph(
+                Paragraph::new()
+                    .add_run(Run::new().add_text(line))
+                    .align(AlignmentType::Center),
+            );
+        }
+    } else {
+        doc = doc.add_paragraph(
+            Paragraph::new()
+                .add_run(Run::new().add_text(title))
+                .style("Title"),
+        );
+    }
+    if template.toc {
+        doc = doc
+            .add_paragraph(
+                Paragraph::new()
+                    .add_run(Run::new().add_text(&template.toc_title).bold())
+                    .page_break_before(template.title_page),
+            )
+            .add_table_of_contents(
+                TableOfContents::new()
+                    .heading_styles_range(1, 3)
+                    .alias(&template.toc_title)
+                    .auto(),
+            )
+            .add_paragraph(Paragraph::new().add_run(Run::new().add_break(BreakType::Page)));
+    } else if template.title_page {
+        doc = doc.add_paragraph(Paragraph::new().add_run(Run::new().add_break(BreakType::Page)));
+    }
+
+    for block in blocks {
+        doc = match block {
+            Block::Heading(level, spans) => {
+                doc.add_paragraph(runs(Paragraph::new(), spans).style(&format!("Heading{}", level)))
+            }
+            Block::Paragraph(spans) => doc.add_paragraph(runs(Paragraph::new(), spans)),
+            Block::Item(level, number, spans) => {
+                let marker = match number {
+                    Some(number) => format!("{}.", number),
+                    None => "•".to_string(),
+                };
+                doc.add_paragraph(
+                    runs(
+                        Paragraph::new().add_run(Run::new().add_text(marker).add_tab()),
+                        spans,
+                    )
+                    .indent(
+                        Some(INDENT * (*level as i32 + 1)),
+                        Some(SpecialIndentType::Hanging(INDENT)),
+                        None,
+                        None,
+                    ),
+                )
+            }
+            Block::Quote(spans) => doc.add_paragraph(
+                runs(Paragraph::new(), spans)
+                    .italic()
+                    .indent(Some(INDENT), None, None, None),
+            ),
+            Block::Code(code) => code.lines().fold(doc, |doc, line| {
+                doc.add_paragraph(
+                    Paragraph::new().add_run(
+                        Run::new()
+                            .add_text(line)
+                            .fonts(RunFonts::new().ascii(MONOSPACE_FONT)),
+                    ),
+                )
+            }),
+            Block::Table(rows) => doc.add_table(Table::new(
+                rows.iter()
+                    .enumerate()
+                    .map(|(i, row)| {
+                        TableRow::new(
+                            row.iter()
+                                .map(|cell| {
+                                    let paragraph = runs(Paragraph::new(), cell);
+                                    TableCell::new().add_paragraph(match i {
+                                        0 => paragraph.bold(),
+                                        _ => paragraph,
+                                    })
+                                })
+                                .collect(),
+                        )
+                    })
+                    .collect(),
+            )),
+            Block::Rule => doc.add_paragraph(
+                Paragraph::new()
+                    .add_run(Run::new().add_text("⁂"))
+                    .align(AlignmentType::Center),
+            ),
+        };
+    }
+
+    if !bibliography.is_empty() {
+        doc = doc.add_paragraph(
+            Paragraph::new()
+                .add_run(Run::new().add_text(&template.bibliography_title))
+                .style("Heading1"),
+        );
+        for (i, url) in bibliography.iter().enumerate() {
+            doc = doc.add_paragraph(
+                Paragraph::new()
+                    .add_run(Run::new().add_text(format!("[{}]", i + 1)).add_tab())
+                    .add_hyperlink(
+                        Hyperlink::new(url, HyperlinkType::External)
+                            .add_run(Run::new().add_text(url).color(LINK_COLOR)),
+                    )
+                    .indent(
+                        Some(INDENT * 2),
+                        Some(SpecialIndentType::Hanging(INDENT * 2)),
+                        None,
+                        None,
+                    ),
+            );
+        }
+    }
+    doc
+}
+
+fn date(template: &Template) -> String {
+    template
+        .date
+        .clone()
+        .unwrap_or_else(|| chrono::Local::now().format("%B %-d, %Y").to_string())
+}
+
+// escapes the characters with a meaning in typst markup
+fn escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        if matches!(
+            c,
+            '\\' | '*'
+                | '_'
+                | '`'
+                | '$'
+                | '#'
+                | '['
+                | ']'
+                | '<'
+                | '>'
+                | '@'
+                | '='
+                | '-'
+                | '+'
+                | '/'
+                | '~'
+        ) {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    escaped
+}
+
+// a typst string literal
+fn string(text: &str) -> String {
+    format!("\"{}\"", text.replace('\\', "\\\\").replace('"', "\\\""))
+}
+
+// embedded expressions end with a semicolon, so that text after them such as .com is not read as
+// a field access
+fn markup(spans: &[Span]) -> String {
+    spans
+        .iter()
+        .map(|span| {
+            let mut markup = match span.code {
+                true => format!("#raw({});", string(&span.text)),
+                false => escape(&span.text),
+            };
+            if span.bold {
+                markup = format!("#strong[{}];", markup);
+            }
+            if span.italic {
+                markup = format!("#emph[{}];", markup);
+            }
+            if let Some(url) = &span.link {
+                markup = format!("#link({})[{}];", string(url), markup);
+            }
+            markup
+        })
+        .collect()
+}
+
+// the report as typst source
+fn typst(title: &str, blocks: &[Block], bibliography: &[String], template: &Template) -> String {
+    let mut source = format!(
+        "#set document(title: {})\n#set page(numbering: \"1\")\n#set text(size: {}pt{})\n#show link: set text(fill: rgb(\"#{}\"))\n\n",
+        string(title),
+        template.font_size,
+        template
+            .font
+            .as_ref()
+            .map(|font| format!(", font: {}", string(font)))
+            .unwrap_or_default(),
+        LINK_COLOR
+    );
+    if template.title_page {
+        source.push_str(&format!(
+            "#align(center + horizon)[\n#text(size: 2.5em, weight: \"bold\")[{}]\n",
+            escape(title)
+        ));
+        for line in [&template.subtitle, &template.author, &Some(date(template))]
+            .into_iter()
+            .flatten()
+        {
+            source.push_str(&format!("\n#v(1em)\n{}\n", escape(line)));
+        }
+        source.push_str("]\n#pagebreak()\n\n");
+    } else {
+        source.push_str(&format!(
+            "#text(size: 2em, weight: \"bold\")[{}]\n\n",
+            escape(title)
+        ));
+    }
+    if template.toc {
+        source.push_str(&format!(
+            "#outline(title: {}, depth: 3)\n#pagebreak()\n\n",
+            string(&template.toc_title)
+        ));
+    }
+
+    for (i, block) in blocks.iter().enumerate() {
+        // a list ends at the first block after it that is no item
+        if i > 0 && matches!(blocks[i - 1], Block::Item(..)) && !matches!(block, Block::Item(..)) {
+            source.push('\n');
+        }
+        match block {
+            Block::Heading(level, spans) => {
+                source.push_str(&format!("{} {}\n\n", "=".repeat(*level), markup(spans)))
+            }
+            Block::Paragraph(spans) => source.push_str(&format!("{}\n\n", markup(spans))),
+            Block::Item(level, number, spans) => source.push_str(&format!(
+                "{}{} {}\n",
+                "  ".repeat(*level),
+                number.map_or("-".to_string(), |number| format!("{}.", number)),
+                markup(spans)
+            )),
+            Block::Quote(spans) => {
+                source.push_str(&format!("#quote(block: true)[{}]\n\n", markup(spans)))
+            }
+            Block::Code(code) => {
+                source.push_str(&format!("#raw(block: true, {})\n\n", string(code)))
+            }
+            Block::Table(rows) => {
+                let columns = rows.iter().map(|row| row.len()).max().unwrap_or(1);
+                source.push_str(&format!("#table(\n  columns: {},\n", columns));
+                for (i, row) in rows.iter().enumerate() {
+                    let cells = row
+                        .iter()
+                        .map(|cell| match i {
+                            0 => format!("[#strong[{}];]", markup(cell)),
+                            _ => format!("[{}]", markup(cell)),
+                        })
+                        .collect::<Vec<_>>();
+                    source.push_str(&format!("  {},\n", cells.join(", ")));
+                }
+                source.push_str(")\n\n");
+            }
+            Block::Rule => source.push_str("#line(length: 100%)\n\n"),
+        }
+    }
+
+    if !bibliography.is_empty() {
+        source.push_str(&format!(
+            "#heading(numbering: none)[{}]\n\n",
+            escape(&template.bibliography_title)
+        ));
+        for (i, url) in bibliography.iter().enumerate() {
+            source.push_str(&format!("+ [{}] #link({})\n", i + 1, string(url)));
+        }
+    }
+    source
+}
+
+// headings of the lists of sources reports may end with
+const REFERENCE_HEADINGS: &[&str] = &["references", "sources", "bibliography", "works cited"];
+
+// whether the report has its own list of sources, such as the references of a citation style
+fn has_references(blocks: &[Block], template: &Template) -> bool {
+    blocks.iter().any(|block| match block {
+        Block::Heading(_, spans) => {
+            let heading = text(spans).trim().to_lowercase();
+            REFERENCE_HEADINGS.contains(&heading.as_str())
+                || heading == template.bibliography_title.trim().to_lowercase()
+        }
+        _ => false,
+    })
+}
+
+/// Writes the Markdown report to path in the format, with the layout of the template. For PDF
+/// the typst source is written next to it with the extension typ. The bibliography is left out
+/// of reports that end with their own references.
+pub fn export(report: &str, format: ExportFormat, template: &Template, path: &Path) -> Result<()> {
+    let (heading, blocks) = parse(report);
+    let title = template
+        .title
+        .clone()
+        .or(heading)
+        .unwrap_or_else(|| "Research report".to_string());
+    let bibliography = match template.bibliography && !has_references(&blocks, template) {
+        true => sources::cited_urls(report),
+        false => Vec::new(),
+    };
+
+    match format {
+        ExportFormat::Docx => {
+            let file = std::fs::File::create(path)?;
+            docx(&title, &blocks, &bibliography, template)
+                .build()
+                .pack(file)
+                .map_err(|e| {
+                    Error::IOError(std::io::Error::other(format!(
+                        "failed to write {}: {}",
+                        path.display(),
+                        e
+                    )))
+                })?;
+        }
+        ExportFormat::Pdf => {
+            let source = path.with_extension("typ");
+            std::fs::write(&source, typst(&title, &blocks, &bibliography, template))?;
+            let output = std::process::Command::new("typst")
+                .arg("compile")
+                .arg(&source)
+                .arg(path)
+                .output()
+                .map_err(|e| {
+                    Error::Unsupported(format!(
+                        "exporting to pdf needs the typst command line tool: {}",
+                        e
+                    ))
+                })?;
+            if !output.status.success() {
+                return Err(Error::IOError(std::io::Error::other(format!(
+                    "typst failed to compile {}: {}",
+                    source.display(),
+                    String::from_utf8_lossy(&output.stderr).trim()
+                ))));
+            }
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        Block, ExportFormat, Span, Template, escape, export, has_references, markup, parse, typst,
+    };
+
+    const REPORT: &str = "# EV market\n\nSales **grew** in [2023](https://example.com/ev).\n\n## Sales\n\n1. Germany\n   - Berlin\n2. France\n\n| Country | Sales |\n|---|---|\n| Germany | 0.5 million |\n\n> Estimates vary.\n";
+
+    #[test]
+    fn test_parse() {
+        let (title, blocks) = parse(REPORT);
+        assert_eq!(title.as_deref(), Some("EV market"));
+        assert_eq!(
+            blocks[0],
+            Block::Paragraph(vec![
+                Span {
+                    text: "Sales ".to_string(),
+                    ..Default::default()
+                },
+                Span {
+                    text: "grew".to_string(),
+                    bold: true,
+                    ..Default::default()
+                },
+                Span {
+                    text: " in ".to_string(),
+                    ..Default::default()
+                },
+                Span {
+                    text: "2023".to_string(),
+                    link: Some("https://example.com/ev".to_string()),
+                    ..Default::default()
+                },
+                Span {
+                    text: ".".to_string(),
+                    ..Default::default()
+                },
+            ])
+        );
+        let outline = blocks
+            .iter()
+            .skip(1)
+            .map(|block| match block {
+                Block::Heading(level, _) => format!("heading {}", level),
+                Block::Item(level, number, spans) => {
+                    format!("item {} {:?} {}", level, number, super::text(spans))
+                }
+                Block::Table(rows) => format!("table {}x{}", rows.len(), rows[0].len()),
+                Block::Quote(spans) => format!("quote {}", super::text(spans)),
+                _ => "other".to_string(),
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(
+            outline,
+            [
+                "heading 2",
+                "item 0 Some(1) Germany",
+                "item 1 None Berlin",
+                "item 0 Some(2) France",
+                "table 2x2",
+                "quote Estimates vary."
+            ]
+        );
+    }
+
+    #[test]
+    fn test_export() {
+        assert_eq!(escape("a_b #1 [x]"), "a\\_b \\#1 \\[x\\]");
+        let (title, blocks) = parse(REPORT);
+        let template = Template {
+            date: Some("May 1, 2024".to_string()),
+            ..Default::default()
+        };
+        let source = typst(
+            &title.unwrap(),
+            &blocks,
+            &["https://example.com/ev".to_string()],
+            &template,
+        );
+        assert!(source.contains("#text(size: 2.5em, weight: \"bold\")[EV market]"));
+        assert!(source.contains("#outline(title: \"Contents\", depth: 3)"));
+        assert!(source.contains(
+            "Sales #strong[grew]; in #link(\"https://example.com/ev\")[2023];.\n\n== Sales\n\n1. Germany\n  - Berlin\n2. France\n"
+        ));
+        assert!(source.ends_with("+ [1] #link(\"https://example.com/ev\")\n"));
+
+        let (_, blocks) = parse("Made by **Tesla**.com and `cargo`.build");
+        let Block::Paragraph(spans) = &blocks[0] else {
+            panic!("expected a paragraph");
+        };
+        assert_eq!(
+            markup(spans),
+            "Made by #strong[Tesla];.com and #raw(\"cargo\");.build"
+        );
+        assert!(!has_references(&parse(REPORT).1, &template));
+        assert!(has_references(
+            &parse("# EV\n\nGrew [1].\n\n## References\n\n[1] example.com").1,
+            &template
+        ));
+
+        let path = std::env::temp_dir().join(format!("export-{}.docx", std::process::id()));
+        export(REPORT, ExportFormat::Docx, &template, &path).unwrap();
+        let docx = std::fs::read(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert!(docx.starts_with(b"PK"));
+    }
+}
@@ -0,0 +1,176 @@
+use crate::subagents::{SubAgentRecord, SubAgentStatus};
+use agent::{Result, llm};
+use serde::Serialize;
+use std::path::Path;
+
+const ORCHESTRATOR: &str = "orchestrator";
+
+// longer tasks are cut in the dot graph to keep the nodes readable
+const MAX_LABEL_TASK: usize = 80;
+
+#[derive(Serialize)]
+pub struct AgentNode {
+    pub name: String,
+    // the agent that started this one, None for the orchestrator
+    pub parent: Option<String>,
+    // the failed attempt this agent retries
+    pub retry_of: Option<String>,
+    pub task: String,
+    pub status: String,
+    pub duration_secs: Option<f64>,
+    pub usage: llm::TokenUsage,
+}
+
+#[derive(Serialize)]
+struct AgentTree<'a> {
+    run_id: &'a str,
+    agents: &'a [AgentNode],
+}
+
+pub fn nodes(
+    task: &str,
+    status: &str,
+    duration_secs: f64,
+    usage: llm::TokenUsage,
+    subagents: Vec<SubAgentRecord>,
+) -> Vec<AgentNode> {
+    let mut nodes = vec![AgentNode {
+        name: ORCHESTRATOR.to_string(),
+        parent: None,
+        retry_of: None,
+        task: task.to_string(),
+        status: status.to_string(),
+        duration_secs: Some(duration_secs),
+        usage,
+    }];
+    nodes.extend(subagents.into_iter().map(|record| {
+        AgentNode {
+            parent: Some(ORCHESTRATOR.to_string()),
+            status: match record.status {
+                SubAgentStatus::Running => "running",
+                SubAgentStatus::Completed => "completed",
+                SubAgentStatus::Retried => "retried",
+                SubAgentStatus::Failed => "failed",
+            }
+            .to_string(),
+            name: record.name,
+            retry_of: record.retry_of,
+            task: record.task,
+            duration_secs: record.duration_secs,
+            usage: record.usage,
+        }
+    }));
+    nodes
+}
+
+fn escape(text: &str) -> String {
+    text.replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', " ")
+}
+
+fn color(status: &str) -> &'static str {
+    match status {
+        "completed" => "palegreen",
+        "retried" => "khaki",
+        "running" => "lightblue",
+        _ => "lightcoral",
+    }
+}
+
+pub fn to_dot(nodes: &[AgentNode]) -> String {
+    let mut dot = String::from("digraph agents {\n    node [shape=box, style=filled];\n");
+    for node in nodes {
+        let mut task = node.task.chars().take(MAX_LABEL_TASK).collect::<String>();
+        if task.len() < node.task.len() {
+            task.push_str("...");
+        }
+        let duration = node
+            .duration_secs
+            .map(|secs| format!("{:.0}s", secs))
+            .unwrap_or_else(|| "-".to_string());
+        dot.push_str(&format!(
+            "    \"{}\" [label=\"{}\\n{}\\n{} | {} | {} tokens\", fillcolor={}];\n",
+            escape(&node.name),
+            escape(&node.name),
+            escape(&task),
+            node.status,
+            duration,
+            node.usage.total_tokens(),
+            color(&node.status),
+        ));
+    }
+    for node in nodes {
+        if let Some(parent) = &node.parent {
+            dot.push_str(&format!(
+                "    \"{}\" -> \"{}\";\n",
+                escape(parent),
+                escape(&node.name)
+            ));
+        }
+        if let Some(retry_of) = &node.retry_of {
+            dot.push_str(&format!(
+                "    \"{}\" -> \"{}\" [style=dashed, label=\"retry\"];\n",
+                escape(retry_of),
+                escape(&node.name)
+            ));
+        }
+    }
+    dot.push_str("}\n");
+    dot
+}
+
+// writes the tree of agents of a run as agent_tree.json and as agent_tree.dot for graphviz
+pub fn write(log_dir: &Path, run_id: &str, nodes: &[AgentNode]) -> Result<()> {
+    let tree = AgentTree {
+        run_id,
+        agents: nodes,
+    };
+    std::fs::write(
+        log_dir.join("agent_tree.json"),
+        serde_json::to_string_pretty(&tree)?,
+    )?;
+    std::fs::write(log_dir.join("agent_tree.dot"), to_dot(nodes))?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{AgentNode, to_dot};
+    use agent::llm::TokenUsage;
+
+    fn node(name: &str, parent: Option<&str>, retry_of: Option<&str>) -> AgentNode {
+        AgentNode {
+            name: name.to_string(),
+            parent: parent.map(String::from),
+            retry_of: retry_of.map(String::from),
+            task: "say \"hi\"".to_string(),
+            status: "completed".to_string(),
+            duration_secs: Some(12.4),
+            usage: TokenUsage {
+                requests: 1,
+                prompt_tokens: 100,
+                completion_tokens: 20,
+            },
+        }
+    }
+
+    #[test]
+    fn test_to_dot() {
+        let dot = to_dot(&[
+            node("orchestrator", None, None),
+            node("subagent_0", Some("orchestrator"), None),
+            node("subagent_0_retry", Some("orchestrator"), Some("subagent_0")),
+        ]);
+
+        assert!(dot.starts_with("digraph agents {"));
+        assert!(dot.contains(
+            "\"subagent_0\" [label=\"subagent_0\\nsay \\\"hi\\\"\\ncompleted | 12s | 120 tokens\", fillcolor=palegreen];"
+        ));
+        assert!(dot.contains("\"orchestrator\" -> \"subagent_0\";"));
+        assert!(
+            dot.contains("\"subagent_0\" -> \"subagent_0_retry\" [style=dashed, label=\"retry\"];")
+        );
+        assert_eq!(dot.matches("->").count(), 3);
+    }
+}
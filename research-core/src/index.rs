@@ -0,0 +1,195 @@
+use crate::inputs;
+use agent::llm;
+use agent::tools::VectorMemory;
+use agent::{Error, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::path::Path;
+use std::sync::Arc;
+
+const ENTRIES_FILE: &str = "entries.json";
+const FILES_FILE: &str = "files.json";
+
+// a file is re-indexed when its size or modification time changes
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+struct Fingerprint {
+    size: u64,
+    modified: u64,
+}
+
+impl Fingerprint {
+    fn of(path: &Path) -> Result<Self> {
+        let metadata = std::fs::metadata(path)?;
+        Ok(Self {
+            size: metadata.len(),
+            modified: metadata
+                .modified()?
+                .duration_since(std::time::UNIX_EPOCH)
+                .map(|d| d.as_secs())
+                .unwrap_or_default(),
+        })
+    }
+}
+
+#[derive(Default, Serialize, Deserialize)]
+struct Files {
+    embedding_model: String,
+    files: BTreeMap<String, Fingerprint>,
+}
+
+#[derive(Debug, Default, PartialEq)]
+pub struct IndexStats {
+    pub added: usize,
+    pub updated: usize,
+    pub removed: usize,
+    pub unchanged: usize,
+}
+
+pub fn is_index(dir: &Path) -> bool {
+    dir.join(ENTRIES_FILE).is_file() && dir.join(FILES_FILE).is_file()
+}
+
+pub fn load(
+    dir: &Path,
+    embeddings: Arc<dyn llm::Embeddings + Send + Sync>,
+    embedding_model: &str,
+) -> Result<Arc<VectorMemory>> {
+    let files: Files = serde_json::from_slice(&std::fs::read(dir.join(FILES_FILE))?)?;
+    if files.embedding_model != embedding_model {
+        return Err(Error::Unsupported(format!(
+            "the index in {} was built with the embedding model {}, not {}",
+            dir.display(),
+            files.embedding_model,
+            embedding_model
+        )));
+    }
+    VectorMemory::load(embeddings, &dir.join(ENTRIES_FILE))
+}
+
+// indexes the documents below dir into out, only files that changed since the last run are
+// embedded again
+pub async fn build(
+    dir: &Path,
+    out: &Path,
+    embeddings: Arc<dyn llm::Embeddings + Send + Sync>,
+    embedding_model: &str,
+) -> Result<IndexStats> {
+    std::fs::create_dir_all(out)?;
+
+    let mut previous = match is_index(out) {
+        true => serde_json::from_slice::<Files>(&std::fs::read(out.join(FILES_FILE))?)?,
+        false => Files::default(),
+    };
+    // embeddings of different models cannot be compared, so a model change rebuilds everything
+    let memory = if is_index(out) && previous.embedding_model == embedding_model {
+        VectorMemory::load(embeddings, &out.join(ENTRIES_FILE))?
+    } else {
+        previous.files.clear();
+        VectorMemory::new(embeddings)
+    };
+
+    let mut paths = Vec::new();
+    inputs::collect_files(dir, &mut paths)?;
+
+    let mut files = Files {
+        embedding_model: embedding_model.to_string(),
+        files: BTreeMap::new(),
+    };
+    let mut stats = IndexStats::default();
+    for path in paths {
+        let name = path
+            .strip_prefix(dir)
+            .unwrap_or(&path)
+            .display()
+            .to_string();
+        let fingerprint = Fingerprint::of(&path)?;
+
+        match previous.files.remove(&name) {
+            Some(old) if old == fingerprint => stats.unchanged += 1,
+            old => {
+                memory.remove_source(&name);
+                memory
+                    .add(&name, inputs::chunk(&inputs::read(&path)?))
+                    .await?;
+                match old {
+                    Some(_) => stats.updated += 1,
+                    None => stats.added += 1,
+                }
+            }
+        }
+        files.files.insert(name, fingerprint);
+    }
+
+    for name in previous.files.keys() {
+        memory.remove_source(name);
+        stats.removed += 1;
+    }
+
+    // the entries are written before the file list so that an interrupted run re-indexes
+    // the files it did not record
+    memory.save(&out.join(ENTRIES_FILE))?;
+    std::fs::write(out.join(FILES_FILE), serde_json::to_string_pretty(&files)?)?;
+
+    Ok(stats)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{IndexStats, build, load};
+    use agent::Result;
+    use agent::llm::{EmbeddingResponse, Embeddings};
+    use async_trait::async_trait;
+    use std::sync::Arc;
+
+    struct LengthEmbeddings;
+
+    #[async_trait]
+    impl Embeddings for LengthEmbeddings {
+        async fn embed(&self, inputs: &[String]) -> Result<EmbeddingResponse> {
+            Ok(EmbeddingResponse {
+                embeddings: inputs.iter().map(|i| vec![i.len() as f32, 1.0]).collect(),
+                usage: Default::default(),
+            })
+        }
+    }
+
+    #[tokio::test]
+    async fn test_incremental_build() -> Result<()> {
+        let root = std::env::temp_dir().join(format!("index-test-{}", std::process::id()));
+        let (docs, out) = (root.join("docs"), root.join("index"));
+        std::fs::create_dir_all(docs.join("sub"))?;
+        std::fs::write(docs.join("a.md"), "alpha")?;
+        std::fs::write(docs.join("sub/b.txt"), "beta")?;
+        std::fs::write(docs.join("ignored.bin"), "binary")?;
+
+        let stats = build(&docs, &out, Arc::new(LengthEmbeddings), "m").await?;
+        assert_eq!(
+            stats,
+            IndexStats {
+                added: 2,
+                ..Default::default()
+            }
+        );
+
+        std::fs::write(docs.join("a.md"), "alpha, longer now")?;
+        std::fs::remove_file(docs.join("sub/b.txt"))?;
+        std::fs::write(docs.join("c.md"), "gamma")?;
+        let stats = build(&docs, &out, Arc::new(LengthEmbeddings), "m").await?;
+        assert_eq!(
+            stats,
+            IndexStats {
+                added: 1,
+                updated: 1,
+                removed: 1,
+                unchanged: 0,
+            }
+        );
+
+        let memory = load(&out, Arc::new(LengthEmbeddings), "m")?;
+        assert_eq!(memory.len(), 2);
+        assert!(load(&out, Arc::new(LengthEmbeddings), "other").is_err());
+
+        std::fs::remove_dir_all(root)?;
+        Ok(())
+    }
+}
@@ -0,0 +1,175 @@
+use crate::config::Config;
+use crate::prompts;
+use agent::llm::{self, CompletionRequest, Message};
+use agent::tools::VectorMemory;
+use agent::{Error, Result};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+const CHUNK_WORDS: usize = 300;
+const CHUNK_OVERLAP: usize = 50;
+
+// only the beginning of very long documents is summarized, the rest remains searchable
+const MAX_SUMMARY_INPUT: usize = 48 * 1024;
+
+// documents provided by the user, embedded for search_documents and summarized for the task
+pub struct Documents {
+    pub memory: Arc<VectorMemory>,
+    pub summaries: Vec<(String, String)>,
+}
+
+impl Documents {
+    pub fn task_prompt(&self, task: &str) -> String {
+        if self.summaries.is_empty() {
+            return task.to_string();
+        }
+
+        let mut prompt = format!(
+            "{}\n\n<input_documents>\nThe user provided the following documents as background material. Build the research around them, and use the search_documents tool to look up passages from them.\n",
+            task
+        );
+        for (name, summary) in &self.summaries {
+            prompt.push_str(&format!(
+                "<document name=\"{}\">\n{}\n</document>\n",
+                name, summary
+            ));
+        }
+        prompt.push_str("</input_documents>");
+        prompt
+    }
+}
+
+// extensions of the files picked up when indexing a corpus directory
+const CORPUS_EXTENSIONS: &[&str] = &["pdf", "md", "markdown", "txt"];
+
+pub fn read(path: &Path) -> Result<String> {
+    match path.extension().and_then(|e| e.to_str()) {
+        Some(ext) if ext.eq_ignore_ascii_case("pdf") => {
+            pdf_extract::extract_text(path).map_err(|e| {
+                Error::IOError(std::io::Error::other(format!(
+                    "failed to extract text from {}: {}",
+                    path.display(),
+                    e
+                )))
+            })
+        }
+        _ => Ok(std::fs::read_to_string(path)?),
+    }
+}
+
+pub fn chunk(text: &str) -> Vec<String> {
+    let words = text.split_whitespace().collect::<Vec<_>>();
+    let mut chunks = Vec::new();
+    let mut start = 0;
+    while start < words.len() {
+        let end = (start + CHUNK_WORDS).min(words.len());
+        chunks.push(words[start..end].join(" "));
+        if end == words.len() {
+            break;
+        }
+        start = end - CHUNK_OVERLAP;
+    }
+    chunks
+}
+
+pub fn truncate(text: &str, len: usize) -> &str {
+    match text.char_indices().nth(len) {
+        Some((i, _)) => &text[..i],
+        None => text,
+    }
+}
+
+async fn summarize(
+    llm: &Arc<dyn llm::LLM + Send + Sync>,
+    config: &Config,
+    text: &str,
+) -> Result<String> {
+    let res = llm
+        .completion(CompletionRequest {
+            messages: &vec![
+                Arc::new(Message::System(prompts::document(config))),
+                Arc::new(Message::User(truncate(text, MAX_SUMMARY_INPUT).to_string())),
+            ],
+            tools: &[],
+            web_search_tool: false,
+            prefill: None,
+        })
+        .await?;
+    Ok(res.content)
+}
+
+pub fn collect_files(dir: &Path, files: &mut Vec<PathBuf>) -> Result<()> {
+    for entry in std::fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.is_dir() {
+            collect_files(&path, files)?;
+        } else if path
+            .extension()
+            .and_then(|e| e.to_str())
+            .is_some_and(|e| CORPUS_EXTENSIONS.iter().any(|c| e.eq_ignore_ascii_case(c)))
+        {
+            files.push(path);
+        }
+    }
+    Ok(())
+}
+
+// embeds every supported document below dir without summarizing it, sources are named by
+// their path relative to dir
+pub async fn index_corpus(memory: &VectorMemory, dir: &Path) -> Result<usize> {
+    let mut files = Vec::new();
+    collect_files(dir, &mut files)?;
+    files.sort();
+
+    for path in &files {
+        let name = path.strip_prefix(dir).unwrap_or(path).display().to_string();
+        memory.add(&name, chunk(&read(path)?)).await?;
+    }
+    Ok(files.len())
+}
+
+pub async fn ingest(
+    llm: Arc<dyn llm::LLM + Send + Sync>,
+    memory: Arc<VectorMemory>,
+    config: &Config,
+    paths: &[PathBuf],
+) -> Result<Documents> {
+    let mut summaries = Vec::with_capacity(paths.len());
+
+    for path in paths {
+        let name = path
+            .file_name()
+            .map(|name| name.to_string_lossy().into_owned())
+            .unwrap_or_else(|| path.display().to_string());
+        let text = read(path)?;
+
+        memory.add(&name, chunk(&text)).await?;
+        summaries.push((name, summarize(&llm, config, &text).await?));
+    }
+
+    Ok(Documents { memory, summaries })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{CHUNK_OVERLAP, CHUNK_WORDS, chunk, truncate};
+
+    #[test]
+    fn test_chunk() {
+        assert!(chunk("").is_empty());
+        assert_eq!(chunk("a b  c"), vec!["a b c".to_string()]);
+
+        let text = (0..700)
+            .map(|i| i.to_string())
+            .collect::<Vec<_>>()
+            .join(" ");
+        let chunks = chunk(&text);
+        assert_eq!(chunks.len(), 3);
+        assert!(chunks[0].starts_with("0 1 "));
+        assert!(chunks[1].starts_with(&format!("{} ", CHUNK_WORDS - CHUNK_OVERLAP)));
+        assert!(chunks[2].ends_with(" 699"));
+
+        assert_eq!(truncate("héllo", 2), "hé");
+        assert_eq!(truncate("hi", 5), "hi");
+    }
+}
@@ -0,0 +1,222 @@
+use crate::config::Config;
+use crate::prompts;
+use crate::warnings::warn;
+use agent::llm::{self, CompletionRequest, Message};
+use agent::{Error, Result};
+use async_trait::async_trait;
+use std::io::Write;
+use std::sync::Arc;
+use tokio::io::AsyncBufReadExt;
+use tokio_util::sync::CancellationToken;
+
+// more questions than this are not asked, the user would rather start the research
+const MAX_QUESTIONS: usize = 5;
+// the model is asked again once when its questions cannot be parsed
+const QUESTION_ATTEMPTS: usize = 2;
+
+// answers the clarification questions before the research starts
+#[async_trait]
+pub trait Respondent {
+    // an empty answer skips the question
+    async fn answer(&mut self, question: &str) -> Result<String>;
+}
+
+// asks the questions in the terminal and reads one line per answer
+pub struct StdinRespondent {
+    lines: tokio::io::Lines<tokio::io::BufReader<tokio::io::Stdin>>,
+}
+
+impl StdinRespondent {
+    pub fn new() -> Box<Self> {
+        Box::new(Self {
+            lines: tokio::io::BufReader::new(tokio::io::stdin()).lines(),
+        })
+    }
+}
+
+#[async_trait]
+impl Respondent for StdinRespondent {
+    async fn answer(&mut self, question: &str) -> Result<String> {
+        print!("{}\n> ", question);
+        std::io::stdout().flush()?;
+        Ok(self.lines.next_line().await?.unwrap_or_default())
+    }
+}
+
+fn parse_questions(content: &str) -> Result<Vec<String>> {
+    let content = content.trim();
+    let content = content
+        .strip_prefix("```json")
+        .or_else(|| content.strip_prefix("```"))
+        .and_then(|c| c.strip_suffix("```"))
+        .unwrap_or(content);
+
+    let questions: Vec<String> = serde_json::from_str(content.trim()).map_err(|e| {
+        Error::AgentWorkflowError(format!("unexpected clarification questions: {}", e))
+    })?;
+    Ok(questions
+        .into_iter()
+        .map(|q| q.trim().to_string())
+        .filter(|q| !q.is_empty())
+        .take(MAX_QUESTIONS)
+        .collect())
+}
+
+fn with_answers(task: &str, answers: &[(String, String)]) -> String {
+    if answers.is_empty() {
+        return task.to_string();
+    }
+
+    let mut task = format!(
+        "{}\n\n<clarifications>\nBefore the research started, the user answered these questions about the task. Follow the answers wherever they narrow or change the task.\n",
+        task
+    );
+    for (question, answer) in answers {
+        task.push_str(&format!("Q: {}\nA: {}\n", question, answer));
+    }
+    task.push_str("</clarifications>");
+    task
+}
+
+// asks the user a few questions about the scope, time horizon, and output format of the task and
+// returns the task with the answers, unchanged if the task needs no clarification or the model
+// did not come up with questions that could be parsed
+pub async fn clarify(
+    llm: Arc<dyn llm::LLM + Send + Sync>,
+    log_dir: &std::path::Path,
+    config: &Config,
+    task: &str,
+    respondent: &mut dyn Respondent,
+    cancel: &CancellationToken,
+) -> Result<String> {
+    let mut messages = vec![
+        Arc::new(Message::System(prompts::interview(config))),
+        Arc::new(Message::User(format!("<task>\n{}\n</task>", task))),
+    ];
+    let mut attempt = 1;
+    let questions = loop {
+        let res = tokio::select! {
+            _ = cancel.cancelled() => {
+                return Err(Error::Cancelled("the interview was cancelled".to_string()));
+            }
+            res = llm.completion(CompletionRequest {
+                messages: &messages,
+                tools: &[],
+                web_search_tool: false,
+                prefill: None,
+            }) => res?,
+        };
+        match parse_questions(&res.content) {
+            Ok(questions) => break questions,
+            Err(e) if attempt < QUESTION_ATTEMPTS => {
+                attempt += 1;
+                messages.push(Arc::new(Message::Assistant(res.content, Vec::new())));
+                messages.push(Arc::new(Message::User(format!(
+                    "Your reply could not be parsed: {}\nRespond with ONLY a JSON array of strings.",
+                    e
+                ))));
+            }
+            Err(e) => {
+                warn(
+                    log_dir,
+                    &format!("the research starts without clarification questions: {}", e),
+                );
+                return Ok(task.to_string());
+            }
+        }
+    };
+
+    let mut answers = Vec::new();
+    for question in questions {
+        let answer = tokio::select! {
+            _ = cancel.cancelled() => {
+                return Err(Error::Cancelled("the interview was cancelled".to_string()));
+            }
+            answer = respondent.answer(&question) => answer?,
+        };
+        if !answer.trim().is_empty() {
+            answers.push((question, answer.trim().to_string()));
+        }
+    }
+    Ok(with_answers(task, &answers))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{MAX_QUESTIONS, Respondent, clarify, parse_questions, with_answers};
+    use crate::config::Config;
+    use agent::Result;
+    use agent::llm::{CompletionRequest, CompletionResponse, LLM};
+    use async_trait::async_trait;
+    use std::sync::{Arc, Mutex};
+    use tokio_util::sync::CancellationToken;
+
+    // replies with the scripted contents in order
+    struct Scripted(Mutex<Vec<&'static str>>);
+
+    #[async_trait]
+    impl LLM for Scripted {
+        async fn completion<'a>(&self, _: CompletionRequest<'a>) -> Result<CompletionResponse> {
+            Ok(CompletionResponse {
+                content: self.0.lock().unwrap().remove(0).to_string(),
+                ..Default::default()
+            })
+        }
+    }
+
+    struct Europe;
+
+    #[async_trait]
+    impl Respondent for Europe {
+        async fn answer(&mut self, _: &str) -> Result<String> {
+            Ok("Europe only".to_string())
+        }
+    }
+
+    #[test]
+    fn test_parse_questions() {
+        assert_eq!(
+            parse_questions("```json\n[\"Which regions?\", \" \"]\n```").unwrap(),
+            vec!["Which regions?".to_string()]
+        );
+        let many = serde_json::to_string(&vec!["question"; 8]).unwrap();
+        assert_eq!(parse_questions(&many).unwrap().len(), MAX_QUESTIONS);
+        assert!(parse_questions("[]").unwrap().is_empty());
+        assert!(parse_questions("What is the scope?").is_err());
+    }
+
+    #[tokio::test]
+    async fn test_clarify_malformed_questions() {
+        let log_dir = std::env::temp_dir().join(format!("interview-test-{}", std::process::id()));
+        std::fs::create_dir_all(&log_dir).unwrap();
+        let config = Config::default();
+        let cancel = CancellationToken::new();
+
+        let llm = Arc::new(Scripted(Mutex::new(vec![
+            "Which regions?",
+            "[\"Which regions?\"]",
+        ])));
+        let task = clarify(llm, &log_dir, &config, "EV market", &mut Europe, &cancel)
+            .await
+            .unwrap();
+        assert!(task.ends_with("Q: Which regions?\nA: Europe only\n</clarifications>"));
+
+        let llm = Arc::new(Scripted(Mutex::new(vec!["Which regions?", "Which years?"])));
+        let task = clarify(llm, &log_dir, &config, "EV market", &mut Europe, &cancel)
+            .await
+            .unwrap();
+        assert_eq!(task, "EV market");
+        std::fs::remove_dir_all(log_dir).unwrap();
+    }
+
+    #[test]
+    fn test_with_answers() {
+        assert_eq!(with_answers("EV market", &[]), "EV market");
+        let task = with_answers(
+            "EV market",
+            &[("Which regions?".to_string(), "Europe only".to_string())],
+        );
+        assert!(task.starts_with("EV market\n\n<clarifications>\n"));
+        assert!(task.ends_with("Q: Which regions?\nA: Europe only\n</clarifications>"));
+    }
+}
@@ -0,0 +1,58 @@
+//! Deep research with an orchestrator agent that plans the research, delegates sub-questions to
+//! parallel sub-agents, and writes a cited report from their findings.
+//!
+//! Build an [`Orchestrator`] with an [`OrchestratorBuilder`] and run it on a task:
+//!
+//! ```no_run
+//! # async fn example(llm: std::sync::Arc<dyn agent::llm::LLM + Send + Sync>) -> agent::Result<()> {
+//! let report = research_core::OrchestratorBuilder::new()
+//!     .llm(llm)
+//!     .log_dir("./agent_logs")
+//!     .config(research_core::Config {
+//!         model: "gpt-4.1".to_string(),
+//!         ..Default::default()
+//!     })
+//!     .build()?
+//!     .run(
+//!         "How fast is the EV market in Europe growing?".to_string(),
+//!         &tokio_util::sync::CancellationToken::new(),
+//!     )
+//!     .await?;
+//! # Ok(())
+//! # }
+//! ```
+pub mod budget;
+pub mod citations;
+pub mod compare;
+mod concurrency;
+pub mod config;
+mod conflicts;
+pub mod contract;
+pub mod debate;
+pub mod diff;
+mod entities;
+pub mod export;
+mod graph;
+pub mod index;
+pub mod inputs;
+pub mod interview;
+mod manifest;
+mod outline;
+mod partial;
+mod plan;
+pub mod presets;
+mod progress;
+mod prompts;
+pub mod research;
+pub mod resume;
+mod sources;
+pub mod state;
+mod status;
+pub mod subagents;
+mod summary;
+mod synthesis;
+mod verification;
+mod warnings;
+
+pub use config::Config;
+pub use research::{Orchestrator, OrchestratorBuilder};
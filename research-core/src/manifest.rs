@@ -0,0 +1,132 @@
+use crate::config::Config;
+use crate::subagents::{SubAgentPool, SubAgentRecord};
+use agent::artifacts::ArtifactStore;
+use agent::{Error, History, Result, callbacks, llm};
+use async_trait::async_trait;
+use serde::Serialize;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+#[derive(Serialize)]
+struct Manifest<'a> {
+    run_id: &'a str,
+    task: String,
+    models: Vec<&'a str>,
+    started_at: &'a str,
+    finished_at: Option<String>,
+    status: &'a str,
+    usage: llm::TokenUsage,
+    total_tokens: u64,
+    cost: Option<f64>,
+    subagents: Vec<SubAgentRecord>,
+    artifacts: Vec<String>,
+    #[serde(rename = "abstract", skip_serializing_if = "Option::is_none")]
+    run_abstract: Option<String>,
+    // the file of the final report in the log directory
+    #[serde(skip_serializing_if = "Option::is_none")]
+    report: Option<String>,
+}
+
+// machine readable summary of a run, rewritten after every orchestrator step and once more
+// with the final status when the run ends
+pub struct RunManifest {
+    path: PathBuf,
+    config: Arc<Config>,
+    pool: Arc<SubAgentPool>,
+    usage: Arc<llm::Usage>,
+    artifacts: Arc<ArtifactStore>,
+    started_at: Mutex<String>,
+    task: Mutex<String>,
+    run_abstract: Mutex<Option<String>>,
+    report: Mutex<Option<String>>,
+}
+
+impl RunManifest {
+    pub fn new(
+        log_dir: &Path,
+        config: Arc<Config>,
+        pool: Arc<SubAgentPool>,
+        usage: Arc<llm::Usage>,
+        artifacts: Arc<ArtifactStore>,
+    ) -> Arc<Self> {
+        Arc::new(Self {
+            path: log_dir.join("manifest.json"),
+            config,
+            pool,
+            usage,
+            artifacts,
+            started_at: Mutex::new(now()),
+            task: Mutex::new(String::new()),
+            run_abstract: Mutex::new(None),
+            report: Mutex::new(None),
+        })
+    }
+
+    pub fn start(&self, task: &str) -> Result<()> {
+        *self.started_at.lock().unwrap() = now();
+        *self.task.lock().unwrap() = task.to_string();
+        self.write("running", false)
+    }
+
+    pub fn set_abstract(&self, run_abstract: String) {
+        *self.run_abstract.lock().unwrap() = Some(run_abstract);
+    }
+
+    pub fn set_report(&self, file: &str) {
+        *self.report.lock().unwrap() = Some(file.to_string());
+    }
+
+    pub fn finish<T>(&self, res: &Result<T>) -> Result<()> {
+        self.write(&outcome(res), true)
+    }
+
+    fn write(&self, status: &str, finished: bool) -> Result<()> {
+        let usage = self.usage.total();
+        let started_at = self.started_at.lock().unwrap().clone();
+        let manifest = Manifest {
+            run_id: &self.config.run_id,
+            task: self.task.lock().unwrap().clone(),
+            models: std::iter::once(&self.config.model)
+                .chain(&self.config.cheap_model)
+                .map(String::as_str)
+                .collect(),
+            started_at: &started_at,
+            finished_at: finished.then(now),
+            status,
+            usage,
+            total_tokens: usage.total_tokens(),
+            cost: self.config.pricing.map(|pricing| usage.cost(&pricing)),
+            subagents: self.pool.records(),
+            artifacts: self.artifacts.list()?,
+            run_abstract: self.run_abstract.lock().unwrap().clone(),
+            report: self.report.lock().unwrap().clone(),
+        };
+
+        // written to a temporary file first so that readers never see a partial manifest
+        let tmp = self.path.with_extension("json.tmp");
+        std::fs::write(&tmp, serde_json::to_string_pretty(&manifest)?)?;
+        std::fs::rename(tmp, &self.path)?;
+        Ok(())
+    }
+}
+
+pub fn outcome<T>(res: &Result<T>) -> String {
+    match res {
+        Ok(_) => "completed".to_string(),
+        Err(Error::Cancelled(_)) => "cancelled".to_string(),
+        Err(e) => format!("failed: {}", e),
+    }
+}
+
+fn now() -> String {
+    chrono::Local::now().to_rfc3339()
+}
+
+pub struct UpdateManifest(pub Arc<RunManifest>);
+
+#[async_trait]
+impl callbacks::Callback for UpdateManifest {
+    async fn call(&mut self, _: &mut dyn History) -> Result<()> {
+        self.0.write("running", false)
+    }
+}
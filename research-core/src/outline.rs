@@ -0,0 +1,330 @@
+use crate::config::Config;
+use crate::inputs::truncate;
+use crate::prompts;
+use crate::synthesis::{Material, complete, fact_lines};
+use agent::llm::{self, TokenUsage};
+use agent::{Error, Result};
+use serde::Deserialize;
+use std::sync::Arc;
+use tokio::sync::Semaphore;
+use tokio::task::JoinSet;
+use tokio_util::sync::CancellationToken;
+
+// the outline only sees the start of every finding, and a section only the findings it draws on,
+// cut, so that every request stays bounded however long the research ran
+const MAX_OUTLINED_FINDING: usize = 500;
+const MAX_SECTION_FINDING: usize = 6000;
+const MAX_SECTION_FACTS: usize = 100;
+// passages of the documents recalled for every section
+const SECTION_PASSAGES: usize = 5;
+// sections written at the same time, and attempts at every section
+const MAX_PARALLEL_SECTIONS: usize = 4;
+const SECTION_ATTEMPTS: usize = 2;
+// a smoothed report with fewer words than this share of the stitched sections was shortened
+// rather than edited, and the stitched sections are delivered instead
+const MIN_SMOOTHED_WORDS: f64 = 0.8;
+
+#[derive(Debug, Deserialize)]
+struct Outline {
+    title: String,
+    sections: Vec<Section>,
+}
+
+#[derive(Debug, Deserialize)]
+struct Section {
+    title: String,
+    #[serde(default)]
+    brief: String,
+    // the numbers of the findings the section draws on, from one
+    #[serde(default)]
+    findings: Vec<usize>,
+    #[serde(default)]
+    entities: Vec<String>,
+}
+
+fn parse_outline(content: &str) -> Result<Outline> {
+    let content = content.trim();
+    let content = content
+        .strip_prefix("```json")
+        .or_else(|| content.strip_prefix("```"))
+        .and_then(|c| c.strip_suffix("```"))
+        .unwrap_or(content);
+
+    let outline: Outline = serde_json::from_str(content.trim())
+        .map_err(|e| Error::AgentWorkflowError(format!("unexpected report outline: {}", e)))?;
+    if outline.sections.is_empty() {
+        return Err(Error::AgentWorkflowError(
+            "the report outline has no sections".to_string(),
+        ));
+    }
+    Ok(outline)
+}
+
+fn outline_request(material: &Material) -> String {
+    let mut request = format!("<task>\n{}\n</task>\n<findings>\n", material.task);
+    for (i, finding) in material.findings.iter().enumerate() {
+        request.push_str(&format!(
+            "{}. {}: {}\n",
+            i + 1,
+            finding.task,
+            truncate(&finding.result, MAX_OUTLINED_FINDING)
+        ));
+    }
+    request.push_str("</findings>\n");
+    let entities = material.facts.entities();
+    if !entities.is_empty() {
+        request.push_str(&format!(
+            "<entities>\n{}\n</entities>\n",
+            entities.join("\n")
+        ));
+    }
+    request.push_str(&format!("<draft>\n{}\n</draft>\n", material.draft));
+    request
+}
+
+// the outline with the section to write, and only the material that section draws on; a section
+// that names no findings gets all of them
+fn section_request(
+    material: &Material,
+    outline: &Outline,
+    index: usize,
+    passages: &[(String, String)],
+) -> String {
+    let section = &outline.sections[index];
+    let mut request = format!(
+        "<task>\n{}\n</task>\n<outline title=\"{}\">\n",
+        material.task, outline.title
+    );
+    for (i, other) in outline.sections.iter().enumerate() {
+        request.push_str(&format!("{}. {}: {}\n", i + 1, other.title, other.brief));
+    }
+    request.push_str(&format!(
+        "</outline>\n<section number=\"{}\">\n{}\n{}\n</section>\n",
+        index + 1,
+        section.title,
+        section.brief
+    ));
+
+    let findings = material
+        .findings
+        .iter()
+        .enumerate()
+        .filter(|(i, _)| section.findings.is_empty() || section.findings.contains(&(i + 1)));
+    for (_, finding) in findings {
+        request.push_str(&format!(
+            "<finding subagent=\"{}\">\n<task>\n{}\n</task>\n{}\n</finding>\n",
+            finding.subagent,
+            finding.task,
+            truncate(&finding.handoff(), MAX_SECTION_FINDING)
+        ));
+    }
+
+    let mut facts = Vec::new();
+    for entity in &section.entities {
+        for fact in material.facts.query(&Some(entity.clone()), &None) {
+            if !facts.contains(&fact) {
+                facts.push(fact);
+            }
+        }
+    }
+    facts.truncate(MAX_SECTION_FACTS);
+    if !facts.is_empty() {
+        request.push_str(&format!("<facts>\n{}</facts>\n", fact_lines(&facts)));
+    }
+
+    for (source, text) in passages {
+        request.push_str(&format!(
+            "<passage source=\"{}\">\n{}\n</passage>\n",
+            source, text
+        ));
+    }
+    request
+}
+
+fn stitch(outline: &Outline, sections: &[String]) -> String {
+    format!("# {}\n\n{}\n", outline.title, sections.join("\n\n"))
+}
+
+fn words(text: &str) -> usize {
+    text.split_whitespace().count()
+}
+
+// writes the report in three steps: an outline of the sections with the findings and entities
+// each draws on, one bounded completion per section, all in parallel, and an edit of the stitched
+// sections that adds the summary and smooths the transitions. A section that cannot be written is
+// left as a note in the report rather than failing it
+pub(crate) async fn write(
+    llm: &Arc<dyn llm::LLM + Send + Sync>,
+    config: &Config,
+    material: &Material<'_>,
+    cancel: &CancellationToken,
+) -> Result<(String, TokenUsage)> {
+    let res = complete(
+        llm,
+        prompts::outline(config),
+        outline_request(material),
+        None,
+        cancel,
+    )
+    .await?;
+    let mut usage = res.usage;
+    let outline = parse_outline(&res.content)?;
+
+    let embeddings = llm::Usage::new();
+    let permits = Arc::new(Semaphore::new(MAX_PARALLEL_SECTIONS));
+    let mut handles = JoinSet::new();
+    for (i, section) in outline.sections.iter().enumerate() {
+        // a section is written without passages when the documents cannot be searched
+        let passages = match material.documents {
+            Some(documents) => documents
+                .search(
+                    &format!("{} {}", section.title, section.brief),
+                    SECTION_PASSAGES,
+                    &embeddings,
+                )
+                .await
+                .unwrap_or_default(),
+            None => Vec::new(),
+        };
+        let request = section_request(material, &outline, i, &passages);
+        let system = prompts::report_section(config);
+        let prefill = format!("## {}\n\n", section.title);
+        let llm = llm.clone();
+        let cancel = cancel.clone();
+        let permits = permits.clone();
+        handles.spawn(async move {
+            let _permit = permits.acquire().await;
+            let mut attempt = 1;
+            let res = loop {
+                match complete(
+                    &llm,
+                    system.clone(),
+                    request.clone(),
+                    Some(&prefill),
+                    &cancel,
+                )
+                .await
+                {
+                    Err(Error::Cancelled(e)) => break Err(Error::Cancelled(e)),
+                    Err(_) if attempt < SECTION_ATTEMPTS => attempt += 1,
+                    res => break res,
+                }
+            };
+            (i, res)
+        });
+    }
+    let mut sections = outline
+        .sections
+        .iter()
+        .map(|section| {
+            format!(
+                "## {}\n\n_This section could not be written._",
+                section.title
+            )
+        })
+        .collect::<Vec<_>>();
+    while let Some(res) = handles.join_next().await {
+        // a section whose task panicked keeps its note
+        let Ok((i, res)) = res else { continue };
+        match res {
+            Ok(res) => {
+                usage += res.usage;
+                sections[i] = res.content.trim().to_string();
+            }
+            Err(Error::Cancelled(e)) => return Err(Error::Cancelled(e)),
+            Err(_) => {}
+        }
+    }
+    usage += embeddings.total();
+    let stitched = stitch(&outline, &sections);
+
+    match complete(
+        llm,
+        prompts::smooth(config),
+        format!(
+            "<task>\n{}\n</task>\n<report>\n{}</report>\n",
+            material.task, stitched
+        ),
+        Some("# "),
+        cancel,
+    )
+    .await
+    {
+        Ok(res) => {
+            usage += res.usage;
+            if words(&res.content) as f64 >= words(&stitched) as f64 * MIN_SMOOTHED_WORDS {
+                return Ok((res.content, usage));
+            }
+        }
+        Err(Error::Cancelled(e)) => return Err(Error::Cancelled(e)),
+        // the stitched sections are a complete report without the edit
+        Err(_) => {}
+    }
+    Ok((stitched, usage))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Material, parse_outline, section_request, stitch};
+    use crate::state::Finding;
+    use agent::tools::{Fact, FactStore};
+
+    #[test]
+    fn test_section_request() {
+        let outline = parse_outline(
+            "```json\n{\"title\": \"EV market\", \"sections\": [{\"title\": \"Sales\", \"brief\": \"units sold\", \"findings\": [2], \"entities\": [\"Europe\"]}, {\"title\": \"Prices\"}]}\n```",
+        )
+        .unwrap();
+        assert!(parse_outline("{\"title\": \"EV market\", \"sections\": []}").is_err());
+
+        let finding = |task: &str, result: &str| Finding {
+            subagent: "subagent_0".to_string(),
+            task: task.to_string(),
+            result: result.to_string(),
+            provenance: Vec::new(),
+            trust: Default::default(),
+            unverified: Default::default(),
+        };
+        let findings = vec![
+            finding("EV prices", "the average price fell"),
+            finding("EV sales in Europe", "2.1 million in 2023"),
+        ];
+        let facts = FactStore::new();
+        for entity in ["Europe", "China"] {
+            facts.add(Fact {
+                entity: entity.to_string(),
+                attribute: "EV sales".to_string(),
+                value: "2.1 million".to_string(),
+                source_id: "https://example.com/ev".to_string(),
+                date: None,
+                agent: "subagent_0".to_string(),
+            });
+        }
+        let material = Material {
+            task: "EV market",
+            findings: &findings,
+            facts: &facts,
+            sources: &[],
+            documents: None,
+            draft: "",
+        };
+
+        // only the findings and facts the section draws on
+        let request = section_request(&material, &outline, 0, &[]);
+        assert!(request.contains("<section number=\"1\">\nSales\nunits sold\n</section>"));
+        assert!(request.contains("2.1 million in 2023") && !request.contains("price fell"));
+        assert!(request.contains("- Europe, EV sales") && !request.contains("- China"));
+
+        // all findings for a section that names none
+        let request = section_request(&material, &outline, 1, &[]);
+        assert!(request.contains("2.1 million in 2023") && request.contains("price fell"));
+
+        assert_eq!(
+            stitch(
+                &outline,
+                &["## Sales\n\na".to_string(), "## Prices\n\nb".to_string()]
+            ),
+            "# EV market\n\n## Sales\n\na\n\n## Prices\n\nb\n"
+        );
+    }
+}
@@ -0,0 +1,164 @@
+use crate::config::Config;
+use crate::inputs::truncate;
+use crate::prompts;
+use crate::research::SharedReport;
+use crate::state::{Finding, SharedState};
+use agent::llm::{self, CompletionRequest, Message};
+use agent::{History, Result, callbacks};
+use async_trait::async_trait;
+use std::path::PathBuf;
+use std::sync::Arc;
+
+const PARTIAL_REPORT_FILE: &str = "partial_report.md";
+// findings are cut so that the request stays small for long runs
+const MAX_FINDING: usize = 4000;
+const PREFILL: &str = "# ";
+
+fn request(task: &str, findings: &[Finding], draft: Option<&str>) -> String {
+    let mut request = format!("<task>\n{}\n</task>\n", task);
+    for finding in findings {
+        request.push_str(&format!(
+            "<finding subagent=\"{}\">\n<task>\n{}\n</task>\n{}\n</finding>\n",
+            finding.subagent,
+            finding.task,
+            truncate(&finding.handoff(), MAX_FINDING)
+        ));
+    }
+    if let Some(draft) = draft {
+        request.push_str(&format!("<draft>\n{}\n</draft>\n", draft));
+    }
+    request
+}
+
+// the findings as they are, written when the interim report cannot be synthesized
+fn assemble(task: &str, findings: &[Finding], draft: Option<&str>) -> String {
+    if let Some(draft) = draft {
+        return draft.to_string();
+    }
+    let mut report = format!("# Findings so far\n\n{}\n", task);
+    for finding in findings {
+        report.push_str(&format!("\n## {}\n\n{}\n", finding.task, finding.result));
+    }
+    report
+}
+
+// every few completed sub-agent tasks, writes a draft of the report from the findings so far to
+// the log directory, so that a run that crashes or is cancelled still leaves a usable report
+pub struct PartialReport {
+    llm: Arc<dyn llm::LLM + Send + Sync>,
+    config: Arc<Config>,
+    state: SharedState,
+    report: SharedReport,
+    usage: Arc<llm::Usage>,
+    path: PathBuf,
+    every: usize,
+    // the number of findings the last partial report was written from
+    written: usize,
+}
+
+impl PartialReport {
+    pub fn new(
+        llm: Arc<dyn llm::LLM + Send + Sync>,
+        config: Arc<Config>,
+        state: SharedState,
+        report: SharedReport,
+        usage: Arc<llm::Usage>,
+        log_dir: &std::path::Path,
+        every: usize,
+    ) -> Box<Self> {
+        Box::new(Self {
+            llm,
+            config,
+            state,
+            report,
+            usage,
+            path: log_dir.join(PARTIAL_REPORT_FILE),
+            every: every.max(1),
+            written: 0,
+        })
+    }
+
+    async fn write(&self, task: &str, findings: &[Finding]) -> Result<()> {
+        let draft = self.report.lock().unwrap().draft().map(String::from);
+        let messages = vec![
+            Arc::new(Message::System(prompts::partial_report(&self.config))),
+            Arc::new(Message::User(request(task, findings, draft.as_deref()))),
+        ];
+        let report = match self
+            .llm
+            .completion(CompletionRequest {
+                messages: &messages,
+                tools: &[],
+                web_search_tool: false,
+                // the draft starts with its title rather than with a preamble
+                prefill: Some(PREFILL),
+            })
+            .await
+        {
+            Ok(res) => {
+                self.usage.record(res.usage);
+                res.content
+            }
+            Err(_) => assemble(task, findings, draft.as_deref()),
+        };
+
+        std::fs::write(
+            &self.path,
+            format!(
+                "<!-- partial report from {} completed sub-agent tasks, {} -->\n\n{}",
+                findings.len(),
+                chrono::Local::now().to_rfc3339(),
+                report
+            ),
+        )?;
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl callbacks::Callback for PartialReport {
+    async fn call(&mut self, history: &mut dyn History) -> Result<()> {
+        let findings = self.state.lock().unwrap().findings.clone();
+        if findings.len() < self.written + self.every {
+            return Ok(());
+        }
+
+        let task = history
+            .iter()
+            .find_map(|m| match m.as_ref() {
+                Message::User(task) => Some(task.clone()),
+                _ => None,
+            })
+            .unwrap_or_default();
+        self.write(&task, &findings).await?;
+        self.written = findings.len();
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{assemble, request};
+    use crate::state::Finding;
+
+    #[test]
+    fn test_partial_report() {
+        let findings = vec![Finding {
+            subagent: "subagent_0".to_string(),
+            task: "EV sales in Europe".to_string(),
+            result: "2.1 million in 2023".to_string(),
+            provenance: Vec::new(),
+            trust: Default::default(),
+            unverified: Default::default(),
+        }];
+        assert_eq!(
+            assemble("EV market", &findings, None),
+            "# Findings so far\n\nEV market\n\n## EV sales in Europe\n\n2.1 million in 2023\n"
+        );
+        assert_eq!(assemble("EV market", &findings, Some("draft")), "draft");
+        assert!(
+            request("EV market", &findings, Some("draft"))
+                .ends_with("2.1 million in 2023\n</finding>\n<draft>\ndraft\n</draft>\n")
+        );
+    }
+}
@@ -0,0 +1,89 @@
+use crate::state::SharedState;
+use crate::subagents::SubAgentPool;
+use agent::Result;
+use agent::llm::Message;
+use agent::tools;
+use async_trait::async_trait;
+use std::sync::Arc;
+
+#[derive(serde::Deserialize, schemars::JsonSchema)]
+struct SubQuestionArgs {
+    /// the hierarchical id of the sub-question, e.g. "1" for a top level sub-question or "1.2" for the second sub-question of sub-question "1"
+    id: String,
+    /// the sub-question that must be answered
+    question: String,
+}
+
+#[derive(serde::Deserialize, schemars::JsonSchema)]
+struct DecomposeQuestionArgs {
+    /// the tree of sub-questions as a flat list, where the parent of each sub-question is given by its id
+    sub_questions: Vec<SubQuestionArgs>,
+}
+
+pub struct DecomposeQuestion(pub SharedState, pub Arc<SubAgentPool>);
+
+#[async_trait]
+impl tools::FunctionalTool for DecomposeQuestion {
+    fn definition(&self) -> Result<tools::ToolDefinition> {
+        tools::ToolDefinition::new::<DecomposeQuestionArgs>(
+            "decompose_question",
+            "This tool records a tree of sub-questions that must be answered to complete the research task. Use it while planning to break the task down, and again later to add new sub-questions; sub-questions with an existing id are updated. When the run has a budget, the result says whether the plan fits it. Pass the ids of the sub-questions a sub-agent is responsible for when starting the sub-agent, so that coverage can be tracked.",
+        )
+    }
+
+    async fn invoke_fn(
+        &mut self,
+        call: &tools::ToolCall,
+        _: &tools::ToolContext,
+    ) -> Result<Message> {
+        let args: DecomposeQuestionArgs = call.args()?;
+        let budget = self.1.budget().await;
+
+        let mut state = self.0.lock().unwrap();
+        let result = match state.add_questions(
+            args.sub_questions
+                .into_iter()
+                .map(|q| (q.id, q.question))
+                .collect(),
+        ) {
+            Ok(()) => {
+                let mut report = state.coverage_report();
+                if let Some(advice) = budget.and_then(|b| b.plan_advice(state.open_leaves())) {
+                    report.push_str(&format!("\n{}\n", advice));
+                }
+                report
+            }
+            Err(e) => format!("sub-questions were not recorded: {}", e),
+        };
+
+        Ok(Message::Tool {
+            id: call.id.clone(),
+            name: "decompose_question".to_string(),
+            result: result.into(),
+        })
+    }
+}
+
+pub struct CoverageReport(pub SharedState);
+
+#[async_trait]
+impl tools::FunctionalTool for CoverageReport {
+    fn definition(&self) -> Result<tools::ToolDefinition> {
+        Ok(tools::ToolDefinition::no_args(
+            "coverage_report",
+            "This tool shows the tree of sub-questions for the research task, and whether each one has been answered by a sub-agent, is in progress, or is still unanswered. Use it to make sure that no part of the task is left unexplored before writing the final report.",
+        ))
+    }
+
+    async fn invoke_fn(
+        &mut self,
+        call: &tools::ToolCall,
+        _: &tools::ToolContext,
+    ) -> Result<Message> {
+        Ok(Message::Tool {
+            id: call.id.clone(),
+            name: "coverage_report".to_string(),
+            result: self.0.lock().unwrap().coverage_report().into(),
+        })
+    }
+}
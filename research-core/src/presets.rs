@@ -0,0 +1,315 @@
+use crate::config::Config;
+use agent::{AgentBuilder, Result, tools};
+use std::sync::Arc;
+
+#[derive(clap::ValueEnum, Clone, Copy, Debug)]
+pub enum Persona {
+    AcademicReviewer,
+    MarketAnalyst,
+    DueDiligence,
+    TechnicalEvaluator,
+}
+
+#[derive(clap::ValueEnum, Clone, Copy, Debug, PartialEq)]
+pub enum NewsSource {
+    /// The GDELT project, free and without an api key, covering the last three months
+    Gdelt,
+    /// NewsAPI, with the key of the NEWSAPI_API_KEY credential
+    Newsapi,
+}
+
+impl NewsSource {
+    pub fn provider(
+        &self,
+        http: &tools::HttpClientConfig,
+        secrets: &agent::secrets::Secrets,
+    ) -> Result<Arc<dyn tools::NewsProvider + Send + Sync>> {
+        Ok(match self {
+            NewsSource::Gdelt => tools::GdeltNews::with_http(http)?,
+            NewsSource::Newsapi => tools::NewsApi::with_http(http, secrets)?,
+        })
+    }
+}
+
+// how the best of several candidates of a synthesis step is picked
+#[derive(clap::ValueEnum, Clone, Copy, Debug, PartialEq)]
+pub enum Selection {
+    /// The candidate that cites the most distinct sources
+    Sources,
+    /// The candidate a judge model picks
+    Judge,
+}
+
+impl Selection {
+    pub fn selector(
+        &self,
+        llm: &Arc<dyn agent::llm::LLM + Send + Sync>,
+    ) -> Box<dyn agent::llm::Selector + Send + Sync> {
+        match self {
+            Selection::Sources => agent::llm::Heuristic::new(|candidate| {
+                let text = candidate.text();
+                // the longer of two equally sourced candidates wins
+                crate::sources::cited_urls(&text).len() as f64
+                    + (text.split_whitespace().count() as f64 / 1e6).min(0.5)
+            }),
+            Selection::Judge => agent::llm::Judge::new(llm.clone()),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Role {
+    Orchestrator,
+    SubAgent,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ToolSelection {
+    pub web_search: bool,
+    pub memory: bool,
+    pub delegate: bool,
+}
+
+// the tools of a selection as they are named on the command line
+#[derive(clap::ValueEnum, Clone, Copy, Debug, PartialEq)]
+pub enum ToolGroup {
+    /// Web search, page fetching, and the optional web tools
+    Web,
+    /// Research memory and the document tools
+    Memory,
+    /// Starting sub-agents
+    Delegate,
+}
+
+impl ToolSelection {
+    pub fn of(groups: &[ToolGroup]) -> Self {
+        Self {
+            web_search: groups.contains(&ToolGroup::Web),
+            memory: groups.contains(&ToolGroup::Memory),
+            delegate: groups.contains(&ToolGroup::Delegate),
+        }
+    }
+
+    // adds the web, memory, and calculator tools, the sub-agent tools are wired by the orchestrator since
+    // they need its sub-agent pool
+    pub fn apply(
+        &self,
+        builder: AgentBuilder,
+        web: &Arc<tools::WebAccess>,
+        config: &Config,
+    ) -> Result<AgentBuilder> {
+        self.apply_with_memory(builder, web, config, &tools::KVMemoryTool::new())
+    }
+
+    // like apply, with the memory tools of a memory the caller reads once the agent ran
+    pub fn apply_with_memory(
+        &self,
+        mut builder: AgentBuilder,
+        web: &Arc<tools::WebAccess>,
+        config: &Config,
+        memory: &tools::KVMemoryTool,
+    ) -> Result<AgentBuilder> {
+        if self.web_search {
+            // searches run by the provider cannot be restricted to the allowed domains
+            let since = config.since.map(|since| since.to_string());
+            if let Some(search) = &config.search {
+                let mut search = match &since {
+                    Some(since) => search.clone().since(since.clone()),
+                    None => search.clone(),
+                };
+                if let Some(region) = &config.region {
+                    search = search.region(region.clone());
+                }
+                builder = builder.tool(tools::WebSearchTool::new(search, web.clone()));
+            } else if !web.policy().restricts_domains() {
+                builder = builder.llm_websearch();
+            }
+            if let Some(sanitizer) = web.sanitizer() {
+                builder = builder.sanitize_tool_results(sanitizer);
+            }
+            builder = builder
+                .tool(tools::WebFetchTool::new(web.clone())?)
+                .tools(tools::ArchiveTool::new(web.clone())?.tools()?);
+            if let Some(news) = config.news() {
+                builder = builder.tool(tools::NewsTool::with_scope(
+                    news.provider(&web.policy().http, &config.secrets)?,
+                    web.clone(),
+                    since.clone(),
+                    config.region.clone(),
+                ));
+            }
+            if config.finance {
+                builder =
+                    builder.tools(tools::FinanceTool::new(web.clone(), &config.secrets)?.tools()?);
+            }
+            if config.scholar() {
+                builder =
+                    builder.tools(tools::ScholarTool::new(web.clone(), &config.secrets)?.tools()?);
+            }
+            if config.youtube {
+                builder = builder.tools(tools::YoutubeTool::new(web.clone())?.tools()?);
+            }
+            for api in &config.openapi {
+                builder =
+                    builder.tools(tools::OpenApiTool::new(api.clone(), web.clone())?.tools()?);
+            }
+            for api in &config.graphql {
+                builder =
+                    builder.tools(tools::GraphQLTool::new(api.clone(), web.clone())?.tools()?);
+            }
+            #[cfg(feature = "browser")]
+            if config.browser {
+                let browser = match &config.vision {
+                    Some(vision) => tools::BrowserTool::with_vision(web.clone(), vision.clone()),
+                    None => tools::BrowserTool::new(web.clone()),
+                };
+                builder = builder.tools(browser.tools()?);
+            }
+            // the agents that gather data can analyze what they downloaded into the working
+            // directory of the run
+            if let Some(sandbox) = &config.sandbox {
+                builder = builder.tools(tools::SandboxTool::new(sandbox.clone()).tools()?);
+            }
+            if let Some(translator) = &config.translator {
+                builder = builder.tool(tools::TranslateTool::new(
+                    translator.clone(),
+                    &config.language,
+                    web.clone(),
+                ));
+            }
+        }
+        if self.memory {
+            builder = builder.tools(memory.tools()?);
+        } else {
+            // the memory tools bring their own document tools that also read memory keys
+            builder = builder
+                .tool(tools::OutlineTool::new())
+                .tools(tools::ChunkReader::new().tools()?);
+        }
+        // the calculator has no side effects, so every agent gets it
+        builder = builder.tools(tools::CalcTool::new().tools()?);
+        Ok(builder)
+    }
+}
+
+// the tools each agent role may use, by default the orchestrator only plans and delegates while
+// the sub-agents browse without starting further sub-agents
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ToolPolicy {
+    pub orchestrator: ToolSelection,
+    pub subagent: ToolSelection,
+}
+
+impl Default for ToolPolicy {
+    fn default() -> Self {
+        Self {
+            orchestrator: ToolSelection {
+                web_search: false,
+                memory: true,
+                delegate: true,
+            },
+            subagent: ToolSelection {
+                web_search: true,
+                memory: true,
+                delegate: false,
+            },
+        }
+    }
+}
+
+impl ToolPolicy {
+    pub fn role(&self, role: Role) -> ToolSelection {
+        match role {
+            Role::Orchestrator => self.orchestrator,
+            Role::SubAgent => self.subagent,
+        }
+    }
+}
+
+// the optional tools a preset gives the agents with web access in addition to those of the
+// config, only tools that need no credentials so that a preset never fails a run
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct PresetTools {
+    pub scholar: bool,
+    pub news: bool,
+}
+
+pub struct Preset {
+    pub system_prompt: &'static str,
+    pub output_format: &'static str,
+    pub tools: PresetTools,
+}
+
+impl Persona {
+    pub fn preset(&self) -> Preset {
+        match self {
+            Persona::AcademicReviewer => Preset {
+                system_prompt: "You are conducting this research as an academic reviewer. Prioritize peer-reviewed literature, systematic reviews, and meta-analyses over news coverage or blog posts. Pay close attention to study design, sample sizes, effect sizes, replication status, and conflicts of interest, and clearly distinguish established consensus from preliminary or contested findings.",
+                output_format: "Structure the report as a literature review with the sections: Abstract, Background, Methodology of the Reviewed Work, Findings, Limitations and Open Questions, and Conclusion. Use a formal academic register.",
+                tools: PresetTools {
+                    scholar: true,
+                    news: false,
+                },
+            },
+            Persona::MarketAnalyst => Preset {
+                system_prompt: "You are conducting this research as a market analyst. Focus on market size, growth rates, competitive landscape, pricing, customer segments, and key trends. Prefer primary sources such as company filings, earnings calls, and industry reports, and always state the date and source of quantitative estimates since they change quickly.",
+                output_format: "Structure the report with the sections: Executive Summary, Market Overview, Key Players, Trends and Drivers, Risks, and Outlook. Present quantitative comparisons in Markdown tables where possible.",
+                tools: PresetTools {
+                    scholar: false,
+                    news: true,
+                },
+            },
+            Persona::DueDiligence => Preset {
+                system_prompt: "You are conducting this research as a due-diligence investigator. Verify claims about the subject against independent sources, look for litigation, regulatory actions, sanctions, negative press, and inconsistencies in public statements, and clearly flag anything that could not be verified. Treat marketing material and self-reported figures with skepticism.",
+                output_format: "Structure the report with the sections: Summary of Findings, Background, Verified Facts, Red Flags, Unverified Claims, and Recommended Follow-up. Rate the severity of each red flag as low, medium, or high.",
+                tools: PresetTools {
+                    scholar: false,
+                    news: true,
+                },
+            },
+            Persona::TechnicalEvaluator => Preset {
+                system_prompt: "You are conducting this research as a technical evaluator. Focus on architecture, capabilities, performance characteristics, maturity, ecosystem, documentation quality, and operational concerns. Prefer official documentation, source repositories, benchmarks with published methodology, and practitioner reports over vendor marketing.",
+                output_format: "Structure the report with the sections: Summary and Recommendation, Evaluation Criteria, Detailed Assessment, Comparison Table, Risks and Trade-offs, and Conclusion.",
+                tools: PresetTools::default(),
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Persona, Role, ToolGroup, ToolPolicy, ToolSelection};
+
+    #[test]
+    fn test_tool_policy() {
+        let policy = ToolPolicy::default();
+        let orchestrator = policy.role(Role::Orchestrator);
+        assert!(orchestrator.delegate && !orchestrator.web_search);
+        let subagent = policy.role(Role::SubAgent);
+        assert!(subagent.web_search && !subagent.delegate);
+        assert_eq!(
+            ToolSelection::of(&[ToolGroup::Web, ToolGroup::Memory]),
+            subagent
+        );
+    }
+
+    #[test]
+    fn test_preset_tools() {
+        let config = |persona| crate::Config {
+            persona: Some(persona),
+            ..Default::default()
+        };
+        let academic = config(Persona::AcademicReviewer);
+        assert!(academic.scholar() && academic.news().is_none());
+        let analyst = config(Persona::MarketAnalyst);
+        assert!(!analyst.scholar());
+        assert_eq!(analyst.news(), Some(super::NewsSource::Gdelt));
+
+        // a news api the config names is kept
+        let config = crate::Config {
+            news: Some(super::NewsSource::Newsapi),
+            ..config(Persona::DueDiligence)
+        };
+        assert_eq!(config.news(), Some(super::NewsSource::Newsapi));
+    }
+}
@@ -0,0 +1,148 @@
+use crate::config::Config;
+use crate::state::SharedState;
+use crate::subagents::SubAgentPool;
+use agent::{History, Result, callbacks, llm};
+use async_trait::async_trait;
+use std::io::Write;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+// minimum time between two progress reports
+const REPORT_INTERVAL: Duration = Duration::from_secs(60);
+
+pub struct Progress<W: Write + Send> {
+    writer: W,
+    pool: Arc<SubAgentPool>,
+    state: SharedState,
+    usage: Arc<llm::Usage>,
+    config: Arc<Config>,
+    started: Instant,
+    last_step: Instant,
+    last_report: Option<Instant>,
+    step_durations: Vec<Duration>,
+}
+
+impl<W: Write + Send> Progress<W> {
+    pub fn new(
+        writer: W,
+        pool: Arc<SubAgentPool>,
+        state: SharedState,
+        usage: Arc<llm::Usage>,
+        config: Arc<Config>,
+    ) -> Box<Self> {
+        let now = Instant::now();
+        Box::new(Self {
+            writer,
+            pool,
+            state,
+            usage,
+            config,
+            started: now,
+            last_step: now,
+            last_report: None,
+            step_durations: Vec::new(),
+        })
+    }
+
+    async fn report(&mut self) -> Result<()> {
+        let elapsed = self.started.elapsed();
+        let (answered, total) = self.state.lock().unwrap().coverage();
+        let average =
+            self.step_durations.iter().sum::<Duration>() / self.step_durations.len().max(1) as u32;
+
+        let mut line = format!(
+            "[{}] step {} (avg {}s), sub-agents: {} started, {} running, coverage: {}/{} answered",
+            format_duration(elapsed),
+            self.step_durations.len(),
+            average.as_secs(),
+            self.pool.started(),
+            self.pool.running().await,
+            answered,
+            total,
+        );
+
+        let remaining = estimate_remaining(elapsed, answered, total);
+        match remaining {
+            Some(remaining) => {
+                line.push_str(&format!(", remaining: ~{}", format_duration(remaining)))
+            }
+            None => line.push_str(", remaining: unknown"),
+        }
+
+        if let Some(pricing) = &self.config.pricing {
+            let cost = self.usage.total().cost(pricing);
+            line.push_str(&format!(", cost: ${:.2}", cost));
+            if let Some(remaining) = remaining {
+                let total_cost =
+                    cost * (elapsed + remaining).as_secs_f64() / elapsed.as_secs_f64().max(1.0);
+                line.push_str(&format!(" (estimated total ${:.2})", total_cost));
+            }
+        }
+
+        writeln!(self.writer, "{}", line)?;
+        self.writer.flush()?;
+        Ok(())
+    }
+}
+
+// extrapolates the time needed for the unanswered sub-questions from the pace so far, there is
+// no estimate before the plan exists and the first sub-question is answered
+fn estimate_remaining(elapsed: Duration, answered: usize, total: usize) -> Option<Duration> {
+    if answered == 0 || total == 0 {
+        return None;
+    }
+    Some(elapsed.mul_f64((total - answered.min(total)) as f64 / answered as f64))
+}
+
+pub fn format_duration(duration: Duration) -> String {
+    let secs = duration.as_secs();
+    format!("{:02}:{:02}:{:02}", secs / 3600, secs / 60 % 60, secs % 60)
+}
+
+#[async_trait]
+impl<W: Write + Send> callbacks::Callback for Progress<W> {
+    async fn call(&mut self, _: &mut dyn History) -> Result<()> {
+        self.step_durations.push(self.last_step.elapsed());
+        self.last_step = Instant::now();
+
+        if self
+            .last_report
+            .is_none_or(|last| last.elapsed() >= REPORT_INTERVAL)
+        {
+            self.last_report = Some(Instant::now());
+            self.report().await?;
+        }
+
+        Ok(())
+    }
+
+    async fn on_agent_start(&mut self) -> Result<()> {
+        self.started = Instant::now();
+        self.last_step = self.started;
+        self.last_report = None;
+        self.step_durations.clear();
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{estimate_remaining, format_duration};
+    use std::time::Duration;
+
+    #[test]
+    fn test_estimate_remaining() {
+        let elapsed = Duration::from_secs(600);
+        assert_eq!(estimate_remaining(elapsed, 0, 4), None);
+        assert_eq!(estimate_remaining(elapsed, 0, 0), None);
+        assert_eq!(
+            estimate_remaining(elapsed, 1, 4),
+            Some(Duration::from_secs(1800))
+        );
+        assert_eq!(
+            estimate_remaining(elapsed, 4, 4),
+            Some(Duration::from_secs(0))
+        );
+        assert_eq!(format_duration(Duration::from_secs(3725)), "01:02:05");
+    }
+}
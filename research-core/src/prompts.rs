@@ -0,0 +1,235 @@
+use crate::config::Config;
+use crate::contract;
+use agent::{Result, SystemPrompt};
+use std::path::PathBuf;
+use std::sync::Arc;
+
+const ORCHESTRATOR_PROMPT: &str = include_str!("prompts/orchestrator.md");
+const SUBAGENT_PROMPT: &str = include_str!("prompts/subagent.md");
+const CLAIMS_PROMPT: &str = include_str!("prompts/claims.md");
+const VERIFIER_PROMPT: &str = include_str!("prompts/verifier.md");
+const CONFLICTS_PROMPT: &str = include_str!("prompts/conflicts.md");
+const COMPARE_PROMPT: &str = include_str!("prompts/compare.md");
+const DOCUMENT_PROMPT: &str = include_str!("prompts/document.md");
+const ABSTRACT_PROMPT: &str = include_str!("prompts/abstract.md");
+const ENTITIES_PROMPT: &str = include_str!("prompts/entities.md");
+const DEBATER_PROMPT: &str = include_str!("prompts/debater.md");
+const MODERATOR_PROMPT: &str = include_str!("prompts/moderator.md");
+const JUDGE_PROMPT: &str = include_str!("prompts/judge.md");
+const INTERVIEW_PROMPT: &str = include_str!("prompts/interview.md");
+const PARTIAL_REPORT_PROMPT: &str = include_str!("prompts/partial_report.md");
+const DIFF_PROMPT: &str = include_str!("prompts/diff.md");
+const SYNTHESIS_PROMPT: &str = include_str!("prompts/synthesis.md");
+const OUTLINE_PROMPT: &str = include_str!("prompts/outline.md");
+const SECTION_PROMPT: &str = include_str!("prompts/section.md");
+const SMOOTH_PROMPT: &str = include_str!("prompts/smooth.md");
+
+const OFFLINE_SECTION: &str = "\n<offline_corpus>\nThis research runs in offline mode. There is no web access, web_search and web_fetch are not available, and the only source of information is the local document collection that you can query with the search_documents tool. Base every statement on passages returned by search_documents and name the document each statement comes from. If the collection does not contain the information needed for part of the task, say so explicitly instead of filling the gap from your own knowledge.\n</offline_corpus>\n";
+
+const FRESHNESS_SECTION: &str = "Only information published on or after {{.Since}} is acceptable for this research. Check the publication or update date of every source before relying on it, and prefer the most recent sources. Treat older sources as background at most: use them only when no recent source covers a point, and then say that they predate {{.Since}}.";
+
+const REGION_SECTION: &str = "This research is scoped to {{.Country}}. Prefer sources from and about {{.Country}}, such as its regulators, official statistics, courts, and local press, and say so when a source describes another jurisdiction or market.";
+
+fn section(tag: &str, content: &str) -> String {
+    format!("\n<{tag}>\n{content}\n</{tag}>\n")
+}
+
+fn render(template: &str, config: &Config) -> String {
+    let preset = config.preset();
+
+    template
+        .replace(
+            "{{.CurrentDate}}",
+            &chrono::Local::now().format("%B %-d, %Y").to_string(),
+        )
+        .replace("{{.Language}}", &config.language)
+        .replace(
+            "{{.Persona}}",
+            &preset
+                .as_ref()
+                .map(|p| section("persona", p.system_prompt))
+                .unwrap_or_default(),
+        )
+        .replace(
+            "{{.OutputFormat}}",
+            &preset
+                .as_ref()
+                .map(|p| section("output_format", p.output_format))
+                .unwrap_or_default(),
+        )
+        .replace(
+            "{{.Offline}}",
+            if config.offline { OFFLINE_SECTION } else { "" },
+        )
+        .replace(
+            "{{.Freshness}}",
+            &config
+                .since
+                .map(|since| {
+                    section(
+                        "freshness",
+                        &FRESHNESS_SECTION
+                            .replace("{{.Since}}", &since.format("%B %-d, %Y").to_string()),
+                    )
+                })
+                .unwrap_or_default(),
+        )
+        .replace(
+            "{{.Region}}",
+            &config
+                .region
+                .as_ref()
+                .map(|region| {
+                    let country = region
+                        .country_name()
+                        .map(|name| name.to_string())
+                        .unwrap_or_else(|| region.country.to_ascii_uppercase());
+                    section("region", &REGION_SECTION.replace("{{.Country}}", &country))
+                })
+                .unwrap_or_default(),
+        )
+        .replace(
+            "{{.Citations}}",
+            &if config.contract.citation_style.is_some() {
+                section("citations", contract::CITATION_INSTRUCTION)
+            } else {
+                String::new()
+            },
+        )
+        .replace(
+            "{{.CitationRule}}",
+            if config.contract.citation_style.is_some() {
+                "Cite the sources of the report with citation markers as the <output_contract> describes. Never include a list of references or sources at the end of the report, it is added from the markers."
+            } else {
+                "Do not include ANY Markdown citations, a separate agent will be responsible for citations. Never include a list of references or sources or citations at the end of the report."
+            },
+        )
+        .replace(
+            "{{.OutputContract}}",
+            &if config.contract.is_empty() {
+                String::new()
+            } else {
+                section("output_contract", &config.contract.instructions())
+            },
+        )
+}
+
+// a prompt template that is read from the prompt directory at every step, so that prompts can be
+// tuned during a run. The built-in template is used while the file does not exist, and the last
+// version that was read is kept while the file cannot be read, e.g. while an editor saves it
+pub struct PromptFile {
+    path: PathBuf,
+    builtin: &'static str,
+    template: String,
+    config: Arc<Config>,
+}
+
+impl PromptFile {
+    fn new(config: &Arc<Config>, file: &str, builtin: &'static str) -> Option<Box<Self>> {
+        config.prompt_dir.as_ref().map(|dir| {
+            Box::new(Self {
+                path: dir.join(file),
+                builtin,
+                template: builtin.to_string(),
+                config: config.clone(),
+            })
+        })
+    }
+
+    pub fn orchestrator(config: &Arc<Config>) -> Option<Box<Self>> {
+        Self::new(config, "orchestrator.md", ORCHESTRATOR_PROMPT)
+    }
+
+    pub fn subagent(config: &Arc<Config>) -> Option<Box<Self>> {
+        Self::new(config, "subagent.md", SUBAGENT_PROMPT)
+    }
+}
+
+impl SystemPrompt for PromptFile {
+    fn system_prompt(&mut self) -> Result<String> {
+        match std::fs::read_to_string(&self.path) {
+            Ok(template) => self.template = template,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+                self.template = self.builtin.to_string()
+            }
+            Err(_) => {}
+        }
+        Ok(render(&self.template, &self.config))
+    }
+}
+
+pub fn orchestrator(config: &Config) -> String {
+    render(ORCHESTRATOR_PROMPT, config)
+}
+
+pub fn subagent(config: &Config) -> String {
+    render(SUBAGENT_PROMPT, config)
+}
+
+pub fn claims(config: &Config, max_claims: usize) -> String {
+    render(CLAIMS_PROMPT, config).replace("{{.MaxClaims}}", &max_claims.to_string())
+}
+
+pub fn verifier(config: &Config) -> String {
+    render(VERIFIER_PROMPT, config)
+}
+
+pub fn run_abstract(config: &Config) -> String {
+    render(ABSTRACT_PROMPT, config)
+}
+
+pub fn conflicts(config: &Config) -> String {
+    render(CONFLICTS_PROMPT, config)
+}
+
+pub fn compare(config: &Config) -> String {
+    render(COMPARE_PROMPT, config)
+}
+
+pub fn document(config: &Config) -> String {
+    render(DOCUMENT_PROMPT, config)
+}
+
+pub fn entities(config: &Config) -> String {
+    render(ENTITIES_PROMPT, config)
+}
+
+pub fn debater(config: &Config, position: &str) -> String {
+    render(DEBATER_PROMPT, config).replace("{{.Position}}", position)
+}
+
+pub fn moderator(config: &Config) -> String {
+    render(MODERATOR_PROMPT, config)
+}
+
+pub fn judge(config: &Config) -> String {
+    render(JUDGE_PROMPT, config)
+}
+
+pub fn interview(config: &Config) -> String {
+    render(INTERVIEW_PROMPT, config)
+}
+
+pub fn partial_report(config: &Config) -> String {
+    render(PARTIAL_REPORT_PROMPT, config)
+}
+
+pub fn diff(config: &Config) -> String {
+    render(DIFF_PROMPT, config)
+}
+
+pub fn synthesis(config: &Config) -> String {
+    render(SYNTHESIS_PROMPT, config)
+}
+
+pub fn outline(config: &Config) -> String {
+    render(OUTLINE_PROMPT, config)
+}
+
+pub fn report_section(config: &Config) -> String {
+    render(SECTION_PROMPT, config)
+}
+
+pub fn smooth(config: &Config) -> String {
+    render(SMOOTH_PROMPT, config)
+}
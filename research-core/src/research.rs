@@ -0,0 +1,885 @@
+use crate::budget;
+use crate::citations;
+use crate::config::{self, Config};
+use crate::conflicts::FindConflicts;
+use crate::contract::OutputContract;
+use crate::entities::{EntityResolution, EntityResolver, ResolveEntities};
+use crate::graph;
+use crate::manifest::{self, RunManifest, UpdateManifest};
+use crate::partial::PartialReport;
+use crate::plan::{CoverageReport, DecomposeQuestion};
+use crate::presets::Role;
+use crate::progress::Progress;
+use crate::prompts;
+use crate::resume::{CHECKPOINT_FILE, RunState, SaveRunState};
+use crate::sources::{self, ListSources};
+use crate::state::SharedState;
+use crate::status::{RunStatus, Todo};
+use crate::subagents::{
+    ListSubAgents, SPILL_THRESHOLD, StartSubAgent, StreamSubAgentResults, SubAgentPool,
+    WaitForSubAgent,
+};
+use crate::summary;
+use crate::synthesis::{Material, Synthesizer};
+use crate::verification::Verifier;
+use crate::warnings::warn;
+use agent::artifacts::ArtifactStore;
+use agent::checkpoint::Checkpoint;
+use agent::llm::Message;
+use agent::tools;
+use agent::workdir::RunContext;
+use agent::{Agent, AgentBuilder, History, StopCondition};
+use agent::{Error, Result};
+use agent::{callbacks, llm};
+use async_trait::async_trait;
+use chrono::Datelike;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use tokio_util::sync::CancellationToken;
+
+pub struct TaskCompleted;
+
+impl StopCondition for TaskCompleted {
+    fn done(&self, history: &dyn History) -> bool {
+        if let Some(llm::Message::Tool { name, .. }) = history.last().map(|m| m.as_ref()) {
+            return name == "complete_task";
+        }
+        false
+    }
+}
+
+// the orchestrator's report goes through two phases, complete_task records a draft that the
+// orchestrator reviews in its next turn and only finalize ends the run
+#[derive(Clone, Default, serde::Serialize, serde::Deserialize)]
+pub struct Report {
+    draft: Option<String>,
+    // output contract requirements the current draft does not meet
+    violations: Vec<String>,
+    revisions: usize,
+    // drafts submitted with complete_task so far
+    #[serde(default)]
+    drafts: usize,
+    finalized: bool,
+}
+
+// after this many revision requests a draft that still violates the output contract is accepted
+// rather than blocking the run forever
+const MAX_CONTRACT_REVISIONS: usize = 2;
+// the draft of the last review round is delivered as it is, so that an orchestrator that never
+// calls finalize cannot keep revising until the step limit
+const MAX_DRAFT_ROUNDS: usize = 5;
+const REPORT_FILE: &str = "report.md";
+
+impl Report {
+    // the most recent draft submitted with complete_task, finalized or not
+    pub fn draft(&self) -> Option<&str> {
+        self.draft.as_deref()
+    }
+}
+
+pub type SharedReport = Arc<Mutex<Report>>;
+
+pub struct ReportFinalized(pub SharedReport);
+
+impl StopCondition for ReportFinalized {
+    fn done(&self, _: &dyn History) -> bool {
+        self.0.lock().unwrap().finalized
+    }
+}
+
+/// An orchestrator agent that plans the research of a task, delegates it to sub-agents, and
+/// writes the report from their findings.
+pub struct Orchestrator {
+    agent: Agent,
+    llm: Arc<dyn llm::LLM + Send + Sync>,
+    log_dir: std::path::PathBuf,
+    config: Arc<Config>,
+    manifest: Arc<RunManifest>,
+    report: SharedReport,
+    subagents: Arc<SubAgentPool>,
+    state: SharedState,
+    documents: Option<Arc<tools::VectorMemory>>,
+    // usage of the orchestrator alone, without its sub-agents
+    usage: Arc<llm::Usage>,
+    work_dir: Arc<RunContext>,
+}
+
+/// Configures and builds an [`Orchestrator`].
+pub struct OrchestratorBuilder {
+    llm: Option<Arc<dyn llm::LLM + Send + Sync>>,
+    log_dir: PathBuf,
+    config: Config,
+    documents: Option<Arc<tools::VectorMemory>>,
+    resumed: bool,
+}
+
+impl Default for OrchestratorBuilder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl OrchestratorBuilder {
+    /// Creates a builder with the default [`Config`] that logs to `./agent_logs`.
+    pub fn new() -> Self {
+        Self {
+            llm: None,
+            log_dir: PathBuf::from("./agent_logs"),
+            config: Config::default(),
+            documents: None,
+            resumed: false,
+        }
+    }
+
+    /// Sets the model of the orchestrator and its sub-agents, required.
+    pub fn llm(mut self, llm: Arc<dyn llm::LLM + Send + Sync>) -> Self {
+        self.llm = Some(llm);
+        self
+    }
+
+    /// Sets the existing directory the logs, artifacts, and manifest of the run are written to.
+    pub fn log_dir(mut self, log_dir: impl AsRef<Path>) -> Self {
+        self.log_dir = log_dir.as_ref().to_path_buf();
+        self
+    }
+
+    /// Sets the options of the run. A config without a run id gets one from the current time.
+    pub fn config(mut self, config: Config) -> Self {
+        self.config = config;
+        self
+    }
+
+    /// Sets documents the agents can search, such as an index loaded with [`crate::index::load`].
+    pub fn documents(mut self, documents: Arc<tools::VectorMemory>) -> Self {
+        self.documents = Some(documents);
+        self
+    }
+
+    /// Appends to the logs in the log directory instead of replacing them, for continuing the
+    /// run logged there with [`Orchestrator::resume`].
+    pub fn resumed(mut self) -> Self {
+        self.resumed = true;
+        self
+    }
+
+    /// Builds the orchestrator together with its sub-agent pool and tools.
+    pub fn build(self) -> Result<Orchestrator> {
+        let Self {
+            llm,
+            log_dir,
+            mut config,
+            documents,
+            resumed,
+        } = self;
+        let llm = llm.ok_or(Error::MissingArg(
+            "llm is required for the orchestrator".to_string(),
+        ))?;
+        let log_dir = log_dir.as_path();
+        if config.run_id.is_empty() {
+            config.run_id = config::new_run_id();
+        }
+        let config = Arc::new(config);
+        let state = SharedState::default();
+        let log_file = |name: &str| {
+            callbacks::RotatingFile::open(&log_dir.join(name), config.log_rotation, resumed)
+        };
+
+        let file = log_file("orchestrator.md")?;
+        let artifacts = ArtifactStore::new(&log_dir.join("artifacts"))?;
+        let work_dir = config.run_context(resumed)?;
+        let usage = llm::Usage::new();
+        let subagents = SubAgentPool::new(
+            llm.clone(),
+            log_dir,
+            config.clone(),
+            state.clone(),
+            artifacts.clone(),
+            work_dir.clone(),
+            usage.clone(),
+            documents.clone(),
+        );
+
+        let manifest = RunManifest::new(
+            log_dir,
+            config.clone(),
+            subagents.clone(),
+            usage.clone(),
+            artifacts.clone(),
+        );
+
+        let report = SharedReport::default();
+        let orchestrator_usage = usage.child();
+        let tool_selection = config.tools(Role::Orchestrator);
+        // sub-agents are started by the orchestrator only, they have no pool of their own
+        if config.tools(Role::SubAgent).delegate {
+            return Err(Error::Unsupported(
+                "sub-agents cannot start further sub-agents".to_string(),
+            ));
+        }
+
+        let entities = EntityResolver::new(
+            llm.clone(),
+            config.clone(),
+            state.clone(),
+            subagents.facts(),
+            orchestrator_usage.clone(),
+        );
+
+        let mut builder = AgentBuilder::new()
+            .name("orchestrator")
+            .usage(orchestrator_usage.clone())
+            // .system_prompt(ORCHESTRATOR_PROMPT.to_string())
+            // .user_prompt(task_desc)
+            .llm(llm.clone())
+            .tool(SubmitDraft::new(
+                report.clone(),
+                config.contract.clone(),
+                config.since.map(|since| (subagents.web(), since)),
+            ))
+            .tool(Box::new(Finalize(report.clone())))
+            .tool(tools::SummarizeHistory::new(llm.clone(), 2))
+            .tool(Box::new(DecomposeQuestion(
+                state.clone(),
+                subagents.clone(),
+            )))
+            .tool(Box::new(CoverageReport(state.clone())))
+            .tools(tools::FactsTool::new(subagents.facts()).tools()?)
+            .tools(tools::BlackboardTool::new(subagents.blackboard()).tools()?)
+            .context_provider(tools::BlackboardNotifications::new(
+                subagents.blackboard(),
+                "orchestrator",
+            ))
+            .tool(Box::new(ResolveEntities(entities.clone())))
+            .callback(Box::new(EntityResolution::new(entities)))
+            .tool(FindConflicts::new(
+                state.clone(),
+                llm.clone(),
+                config.clone(),
+            ))
+            .spill_tool_results(artifacts, SPILL_THRESHOLD)
+            .run_context(work_dir.clone())
+            .recover_context_overflow();
+        if tool_selection.delegate {
+            builder = builder
+                .tool(Box::new(StartSubAgent(subagents.clone())))
+                .tool(Box::new(WaitForSubAgent(subagents.clone())))
+                .tool(Box::new(ListSubAgents(subagents.clone())))
+                .tool(Box::new(ListSources(subagents.clone())));
+        }
+
+        let mut builder = tool_selection
+            .apply(builder, &subagents.web(), &config)?
+            .callback(tools::SummarizeHistory::new(llm.clone(), 2));
+
+        if let Some(documents) = &documents {
+            builder = builder.tool(documents.search_tool());
+        }
+        builder = config.context_budget(builder, documents.as_ref(), &orchestrator_usage);
+        if config.context_budget.is_some() {
+            builder = builder.context_provider(Todo::new(state.clone()));
+        }
+        if let Some(prompt) = prompts::PromptFile::orchestrator(&config) {
+            builder = builder.reload_system_prompt(prompt);
+        }
+        if config.profile {
+            builder = builder.profiler(callbacks::Profiler::new(
+                &log_dir.join("orchestrator.profile.folded"),
+            ));
+        }
+
+        if tool_selection.delegate
+            && let Some(every) = config.partial_report_every
+        {
+            builder = builder.callback(PartialReport::new(
+                config.synthesis(&llm),
+                config.clone(),
+                state.clone(),
+                report.clone(),
+                orchestrator_usage.clone(),
+                log_dir,
+                every,
+            ));
+        }
+
+        if tool_selection.delegate && config.stream_subagent_results {
+            builder = builder.callback(StreamSubAgentResults::new(
+                subagents.clone(),
+                Box::new(ReportFinalized(report.clone())),
+            ));
+        }
+
+        Ok(Orchestrator {
+            agent: builder
+                .callback(callbacks::TeeLogger::new(vec![
+                    callbacks::MessageLogger::new("orchestrator", file)?,
+                    callbacks::EventLogger::new(
+                        "orchestrator",
+                        log_file("orchestrator.events.jsonl")?,
+                    ),
+                ]))
+                .callback(Progress::new(
+                    log_file("progress.log")?,
+                    subagents.clone(),
+                    state.clone(),
+                    usage.clone(),
+                    config.clone(),
+                ))
+                .callback(Box::new(UpdateManifest(manifest.clone())))
+                .callback(SaveRunState::new(
+                    log_dir,
+                    &config,
+                    resumed,
+                    orchestrator_usage.clone(),
+                    state.clone(),
+                    report.clone(),
+                    subagents.clone(),
+                )?)
+                .checkpoint(&log_dir.join(CHECKPOINT_FILE))
+                .context_provider(RunStatus::new(
+                    subagents.clone(),
+                    state.clone(),
+                    usage,
+                    config.clone(),
+                ))
+                .stop_condition(Box::new(ReportFinalized(report.clone())))
+                .build()?,
+            llm,
+            log_dir: log_dir.to_path_buf(),
+            config,
+            manifest,
+            report,
+            subagents,
+            state,
+            documents,
+            usage: orchestrator_usage,
+            work_dir,
+        })
+    }
+}
+
+impl Orchestrator {
+    /// Researches the task and returns the final report, which is also written to report.md in
+    /// the log directory together with the sources, facts, and a graph of the run.
+    pub async fn run(mut self, task_desc: String, cancel: &CancellationToken) -> Result<String> {
+        self.manifest.start(&task_desc)?;
+        let started = std::time::Instant::now();
+        let res = self.research(task_desc.clone(), cancel).await;
+        self.finish(&task_desc, started, res).await
+    }
+
+    /// Continues a crashed run from the orchestrator checkpoint in the log directory with the
+    /// findings, facts, notes, and sources it had gathered, sub-agents that had not finished are
+    /// started again. The orchestrator must be built with [`OrchestratorBuilder::resumed`].
+    pub async fn resume(mut self, run: RunState, cancel: &CancellationToken) -> Result<String> {
+        let checkpoint = Checkpoint::load(&self.log_dir.join(CHECKPOINT_FILE))?;
+        self.usage.record(run.orchestrator_usage);
+        {
+            let mut state = self.state.lock().unwrap();
+            state.findings = run.findings;
+            state.questions = run.questions;
+        }
+        *self.report.lock().unwrap() = run.report;
+        let facts = self.subagents.facts();
+        for fact in run.facts {
+            facts.add(fact);
+        }
+        for (alias, canonical) in run.aliases {
+            facts.merge(&canonical, &[alias]);
+        }
+        self.subagents.blackboard().restore(run.notes);
+        self.subagents.web().restore_fetched(run.sources);
+        self.subagents.web().restore_trust(run.trust);
+        self.subagents.web().restore_published(run.published);
+        self.subagents.web().restore_dois(run.dois);
+        self.subagents
+            .restore(run.subagents, run.pending, run.next_id, cancel)
+            .await?;
+
+        self.manifest.start(&run.task)?;
+        let started = std::time::Instant::now();
+        let res = match self.agent.resume(checkpoint, cancel).await {
+            Ok(_) => self.deliver(&run.task, cancel).await,
+            Err(e) => Err(e),
+        };
+        self.finish(&run.task, started, res).await
+    }
+
+    async fn finish(
+        &mut self,
+        task_desc: &str,
+        started: std::time::Instant,
+        res: Result<String>,
+    ) -> Result<String> {
+        let outcome = manifest::outcome(&res);
+        if !matches!(res, Err(Error::Cancelled(_))) {
+            self.write_abstract(task_desc, &outcome, res.as_deref().ok())
+                .await;
+        }
+        if let Ok(report) = &res {
+            match std::fs::write(self.log_dir.join(REPORT_FILE), report) {
+                Ok(()) => self.manifest.set_report(REPORT_FILE),
+                Err(e) => warn(
+                    &self.log_dir,
+                    &format!("{} could not be written: {}", REPORT_FILE, e),
+                ),
+            }
+        }
+        // like the working directory below, a manifest that could not be written does not hide
+        // the report or the error of the research
+        if let Err(e) = self.manifest.finish(&res) {
+            warn(
+                &self.log_dir,
+                &format!("the manifest could not be written: {}", e),
+            );
+        }
+
+        let nodes = graph::nodes(
+            task_desc,
+            &outcome,
+            started.elapsed().as_secs_f64(),
+            self.usage.total(),
+            self.subagents.records(),
+        );
+        graph::write(&self.log_dir, &self.config.run_id, &nodes)?;
+        // the history only improves the estimates of later runs
+        if let Err(e) = budget::record_history(&self.log_dir, &self.subagents.records()) {
+            warn(
+                &self.log_dir,
+                &format!("the usage of the sub-agents could not be recorded: {}", e),
+            );
+        }
+        let web = self.subagents.web();
+        let mut clusters = sources::cluster(&web.fetched(), &web.dois());
+        sources::mark_translated(&mut clusters, &web.translations());
+        sources::mark_trust(&mut clusters, &web.trust_levels());
+        sources::write(&self.log_dir, &clusters)?;
+        if self.config.flag_injections
+            && let Some(sanitizer) = web.sanitizer()
+        {
+            std::fs::write(
+                self.log_dir.join("injections.json"),
+                serde_json::to_string_pretty(&sanitizer.injections())?,
+            )?;
+        }
+        std::fs::write(
+            self.log_dir.join("facts.json"),
+            serde_json::to_string_pretty(&self.subagents.facts().facts())?,
+        )?;
+        std::fs::write(
+            self.log_dir.join("blackboard.json"),
+            serde_json::to_string_pretty(&self.subagents.blackboard().notes())?,
+        )?;
+        // the report or the error of the research is what the run returns, a working directory
+        // that could not be deleted does not change it
+        if let Err(e) = self.work_dir.finish(res.is_ok()) {
+            warn(
+                &self.log_dir,
+                &format!("the working directory could not be cleaned up: {}", e),
+            );
+        }
+        res
+    }
+
+    // the abstract is best effort, a run is not failed because its abstract could not be written
+    async fn write_abstract(&mut self, task_desc: &str, outcome: &str, report: Option<&str>) {
+        let findings = self.state.lock().unwrap().findings.clone();
+        let res = summary::run_abstract(
+            &self.llm,
+            &self.config,
+            task_desc,
+            outcome,
+            &self.subagents.records(),
+            &findings,
+            report,
+        )
+        .await;
+        let run_abstract = match res {
+            Ok(run_abstract) => run_abstract,
+            Err(e) => {
+                warn(
+                    &self.log_dir,
+                    &format!("the abstract could not be written: {}", e),
+                );
+                return;
+            }
+        };
+        // the manifest, which is written next, still gets the abstract
+        if let Err(e) = std::fs::write(self.log_dir.join("abstract.md"), &run_abstract) {
+            warn(
+                &self.log_dir,
+                &format!("abstract.md could not be written: {}", e),
+            );
+        }
+        self.manifest.set_abstract(run_abstract);
+    }
+
+    async fn research(&mut self, task_desc: String, cancel: &CancellationToken) -> Result<String> {
+        self.agent
+            .run(
+                vec![
+                    Arc::new(Message::System(prompts::orchestrator(&self.config))),
+                    Arc::new(Message::User(task_desc.clone())),
+                ],
+                cancel,
+            )
+            .await?;
+        self.deliver(&task_desc, cancel).await
+    }
+
+    // the finalized report, written up by the synthesizer when there is one or from an outline,
+    // and verified when verification is on
+    async fn deliver(&mut self, task_desc: &str, cancel: &CancellationToken) -> Result<String> {
+        let report = match &*self.report.lock().unwrap() {
+            Report {
+                draft: Some(draft),
+                finalized: true,
+                ..
+            } => draft.clone(),
+            _ => {
+                return Err(Error::AgentWorkflowError(
+                    "orchestrator terminated without finalizing a report".to_string(),
+                ));
+            }
+        };
+
+        let synthesizer = match &self.config.synthesizer {
+            Some(synthesizer) => Some(synthesizer.clone()),
+            None if self.config.outline_report => Some(self.llm.clone()),
+            None => None,
+        };
+        let report = match synthesizer {
+            Some(synthesizer) => {
+                let findings = self.state.lock().unwrap().findings.clone();
+                let mut sources = self
+                    .subagents
+                    .web()
+                    .fetched()
+                    .into_iter()
+                    .map(|(url, _)| url)
+                    .collect::<Vec<_>>();
+                sources.sort();
+                sources.dedup();
+                let material = Material {
+                    task: task_desc,
+                    findings: &findings,
+                    facts: &self.subagents.facts(),
+                    sources: &sources,
+                    documents: self.documents.as_deref(),
+                    draft: &report,
+                };
+                let (report, usage) =
+                    Synthesizer::new(synthesizer, &self.log_dir, self.config.clone())
+                        .synthesize(&material, cancel)
+                        .await?;
+                self.usage.record(usage);
+                report
+            }
+            None => report,
+        };
+        let report = match self.config.contract.citation_style {
+            Some(style) => citations::format(&report, style, &self.subagents.web().published()),
+            None => report,
+        };
+
+        if self.config.verify {
+            return Verifier::new(
+                self.llm.clone(),
+                &self.log_dir,
+                self.config.clone(),
+                self.subagents.web(),
+            )
+            .verify(report, cancel)
+            .await;
+        }
+
+        Ok(report)
+    }
+}
+
+pub struct CompleteTask;
+
+#[async_trait]
+impl tools::FunctionalTool for CompleteTask {
+    fn definition(&self) -> Result<tools::ToolDefinition> {
+        tools::ToolDefinition::new::<String>(
+            "complete_task",
+            "This tool will mark your task as complete and return the result. You must use this tool when you have completed your task.",
+        )
+    }
+
+    async fn invoke_fn(
+        &mut self,
+        call: &tools::ToolCall,
+        _: &tools::ToolContext,
+    ) -> Result<Message> {
+        let result: String = call.args()?;
+
+        Ok(Message::Tool {
+            id: call.id.clone(),
+            name: "complete_task".to_string(),
+            result: result.into(),
+        })
+    }
+}
+
+#[derive(serde::Deserialize, schemars::JsonSchema)]
+struct SubmitDraftArgs {
+    /// the complete research report in Markdown
+    report: String,
+}
+
+pub struct SubmitDraft {
+    report: SharedReport,
+    contract: OutputContract,
+    // the pages read during the run and the earliest date of the sources the research accepts
+    freshness: Option<(Arc<tools::WebAccess>, chrono::NaiveDate)>,
+}
+
+impl SubmitDraft {
+    pub fn new(
+        report: SharedReport,
+        contract: OutputContract,
+        freshness: Option<(Arc<tools::WebAccess>, chrono::NaiveDate)>,
+    ) -> Box<Self> {
+        Box::new(Self {
+            report,
+            contract,
+            freshness,
+        })
+    }
+
+    // a warning about cited sources that seem to predate the cutoff, they do not block the
+    // draft since the year a page mentions last is only a guess at its date
+    fn freshness_warning(&self, report: &str) -> Option<String> {
+        let (web, since) = self.freshness.as_ref()?;
+        let older = sources::older_than(report, &web.fetched(), since.year() as i64);
+        if older.is_empty() {
+            return None;
+        }
+        let list = older
+            .iter()
+            .map(|(url, year)| format!("- {} (latest year mentioned {})", url, year))
+            .collect::<Vec<_>>()
+            .join("\n");
+        Some(format!(
+            "\n\nWarning: this research only accepts sources published since {}, but these cited sources seem to be older:\n{}\nReplace them with recent sources, or say in the report that they predate the cutoff and are only background.",
+            since, list
+        ))
+    }
+}
+
+#[async_trait]
+impl tools::FunctionalTool for SubmitDraft {
+    fn definition(&self) -> Result<tools::ToolDefinition> {
+        tools::ToolDefinition::new::<SubmitDraftArgs>(
+            "complete_task",
+            "This tool submits a draft of your final research report for review. After submitting, check the draft against the task and either call finalize to deliver it, or continue researching and submit a revised draft.",
+        )
+    }
+
+    async fn invoke_fn(
+        &mut self,
+        call: &tools::ToolCall,
+        _: &tools::ToolContext,
+    ) -> Result<Message> {
+        let args: SubmitDraftArgs = call.args()?;
+        let violations = self.contract.validate(&args.report);
+        let warning = self.freshness_warning(&args.report).unwrap_or_default();
+
+        let mut report = self.report.lock().unwrap();
+        report.draft = Some(args.report);
+        report.drafts += 1;
+        if report.drafts >= MAX_DRAFT_ROUNDS {
+            report.violations.clear();
+            report.finalized = true;
+            return Ok(Message::Tool {
+                id: call.id.clone(),
+                name: "complete_task".to_string(),
+                result: format!(
+                    "This was the last of {} drafts, it was delivered as the final report.",
+                    MAX_DRAFT_ROUNDS
+                )
+                .into(),
+            });
+        }
+        if violations.is_empty() || report.revisions >= MAX_CONTRACT_REVISIONS {
+            report.violations.clear();
+        } else {
+            report.revisions += 1;
+            report.violations = violations;
+            return Ok(Message::Tool {
+                id: call.id.clone(),
+                name: "complete_task".to_string(),
+                result: format!(
+                    "Your draft report does not meet the required output contract:\n- {}\nRevise the report to fix these problems and submit it again with complete_task.{}",
+                    report.violations.join("\n- "),
+                    warning
+                )
+                .into(),
+            });
+        }
+
+        Ok(Message::Tool {
+            id: call.id.clone(),
+            name: "complete_task".to_string(),
+            result: format!("Your draft report was recorded but not yet delivered. Review it critically: does it fully answer every part of the task, are all sub-questions covered, and is every important claim supported by the research results? If the draft is complete, call the finalize tool to deliver it. Otherwise continue the research or revise the report and call complete_task again with the improved draft. This was draft {} of at most {}, the last one is delivered as it is.{}", report.drafts, MAX_DRAFT_ROUNDS, warning).into(),
+        })
+    }
+}
+
+pub struct Finalize(pub SharedReport);
+
+#[async_trait]
+impl tools::FunctionalTool for Finalize {
+    fn definition(&self) -> Result<tools::ToolDefinition> {
+        Ok(tools::ToolDefinition::no_args(
+            "finalize",
+            "This tool delivers the most recent draft submitted with complete_task as the final research report and ends the research. Only use it after you have reviewed the draft and are sure it fully answers the task.",
+        ))
+    }
+
+    async fn invoke_fn(
+        &mut self,
+        call: &tools::ToolCall,
+        _: &tools::ToolContext,
+    ) -> Result<Message> {
+        let mut report = self.0.lock().unwrap();
+        let result = if report.draft.is_none() {
+            "There is no draft to finalize, submit your report with the complete_task tool first."
+                .to_string()
+        } else if !report.violations.is_empty() {
+            format!(
+                "The draft cannot be finalized because it does not meet the required output contract:\n- {}\nSubmit a revised report with complete_task first.",
+                report.violations.join("\n- ")
+            )
+        } else {
+            report.finalized = true;
+            "The report was delivered.".to_string()
+        };
+
+        Ok(Message::Tool {
+            id: call.id.clone(),
+            name: "finalize".to_string(),
+            result: result.into(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        Finalize, MAX_DRAFT_ROUNDS, OrchestratorBuilder, REPORT_FILE, SharedReport, SubmitDraft,
+    };
+    use crate::contract::OutputContract;
+    use agent::Result;
+    use agent::llm::{CompletionRequest, CompletionResponse, LLM, Message};
+    use agent::tools::{FunctionalTool, ToolCall, ToolContext};
+    use async_trait::async_trait;
+    use std::sync::Arc;
+
+    struct Idle;
+
+    #[async_trait]
+    impl LLM for Idle {
+        async fn completion<'a>(&self, _: CompletionRequest<'a>) -> Result<CompletionResponse> {
+            Ok(CompletionResponse::default())
+        }
+    }
+
+    // embedders build with the default config, which has no run id
+    #[tokio::test]
+    async fn test_build_default_config() {
+        let log_dir = std::env::temp_dir().join(format!("build-test-{}", std::process::id()));
+        std::fs::create_dir_all(&log_dir).unwrap();
+
+        let first = OrchestratorBuilder::new()
+            .llm(Arc::new(Idle))
+            .log_dir(&log_dir)
+            .build()
+            .unwrap();
+        let second = OrchestratorBuilder::new()
+            .llm(Arc::new(Idle))
+            .log_dir(&log_dir)
+            .build()
+            .unwrap();
+        assert!(!first.config.run_id.is_empty());
+        assert_ne!(first.config.run_id, second.config.run_id);
+        assert!(first.work_dir.dir().exists() && second.work_dir.dir().exists());
+
+        for orchestrator in [first, second] {
+            orchestrator.work_dir.finish(true).unwrap();
+        }
+        std::fs::remove_dir_all(log_dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_finish_writes_report() -> Result<()> {
+        let log_dir = std::env::temp_dir().join(format!("finish-test-{}", std::process::id()));
+        std::fs::create_dir_all(&log_dir)?;
+        let mut orchestrator = OrchestratorBuilder::new()
+            .llm(Arc::new(Idle))
+            .log_dir(&log_dir)
+            .build()?;
+
+        let report = orchestrator
+            .finish(
+                "task",
+                std::time::Instant::now(),
+                Ok("# Report".to_string()),
+            )
+            .await?;
+        assert_eq!(report, "# Report");
+        assert_eq!(
+            std::fs::read_to_string(log_dir.join(REPORT_FILE))?,
+            "# Report"
+        );
+        let manifest: serde_json::Value =
+            serde_json::from_str(&std::fs::read_to_string(log_dir.join("manifest.json"))?)?;
+        assert_eq!(manifest["report"], REPORT_FILE);
+
+        std::fs::remove_dir_all(log_dir)?;
+        Ok(())
+    }
+
+    fn result(message: Message) -> String {
+        match message {
+            Message::Tool { result, .. } => result.to_string(),
+            message => panic!("not a tool result: {:?}", message),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_draft_rounds() -> Result<()> {
+        let report = SharedReport::default();
+        let mut submit = SubmitDraft::new(report.clone(), OutputContract::default(), None);
+        let mut finalize = Finalize(report.clone());
+        let call = |name: &str, args: &str| ToolCall {
+            id: "1".to_string(),
+            name: name.to_string(),
+            args: args.to_string(),
+        };
+        let ctx = ToolContext::default();
+
+        let reply = finalize.invoke_fn(&call("finalize", ""), &ctx).await?;
+        assert!(result(reply).contains("There is no draft"));
+
+        // drafts are reviewed until the orchestrator finalizes one
+        let draft = |n: usize| call("complete_task", &format!("{{\"report\": \"draft {}\"}}", n));
+        for n in 1..MAX_DRAFT_ROUNDS {
+            let reply = submit.invoke_fn(&draft(n), &ctx).await?;
+            assert!(result(reply).contains("not yet delivered"));
+            assert!(!report.lock().unwrap().finalized);
+        }
+
+        // the last round is delivered without finalize
+        let reply = submit.invoke_fn(&draft(MAX_DRAFT_ROUNDS), &ctx).await?;
+        assert!(result(reply).contains("delivered as the final report"));
+        let report = report.lock().unwrap();
+        assert!(report.finalized);
+        assert_eq!(
+            report.draft(),
+            Some(format!("draft {}", MAX_DRAFT_ROUNDS).as_str())
+        );
+        Ok(())
+    }
+}
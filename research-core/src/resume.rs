@@ -0,0 +1,275 @@
+use crate::config::Config;
+use crate::research::{Report, SharedReport};
+use crate::state::{Finding, SharedState, SubQuestion};
+use crate::subagents::{PendingSubAgent, SubAgentPool, SubAgentRecord};
+use agent::llm::{self, Message};
+use agent::tools::{Fact, Note, Trust};
+use agent::{History, Result, callbacks};
+use async_trait::async_trait;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+// the orchestrator history, written by the agent after every step
+pub const CHECKPOINT_FILE: &str = "orchestrator.checkpoint.json";
+const STATE_FILE: &str = "run_state.json";
+// the pages read by the run, one json line per page, appended to as they are read because they
+// make up most of the state
+const SOURCES_FILE: &str = "run_sources.jsonl";
+
+// everything of a run that lives outside the orchestrator history, saved after every
+// orchestrator step so a crashed run can be resumed from its log directory
+#[derive(serde::Serialize, serde::Deserialize)]
+pub struct RunState {
+    pub run_id: String,
+    // command line the run was started with, a resumed run is built from it again
+    #[serde(default)]
+    pub args: Vec<String>,
+    pub task: String,
+    pub orchestrator_usage: llm::TokenUsage,
+    pub findings: Vec<Finding>,
+    pub questions: Vec<SubQuestion>,
+    pub report: Report,
+    // sub-agents that had finished, their results are already in the findings
+    pub subagents: Vec<SubAgentRecord>,
+    // sub-agents that were running or queued, they are started again
+    pub pending: Vec<PendingSubAgent>,
+    pub next_id: u32,
+    pub facts: Vec<Fact>,
+    pub aliases: Vec<(String, String)>,
+    pub notes: Vec<Note>,
+    // the url and text of every page read so far, the sources of the report, which are kept in
+    // their own file and only read from the state of runs saved before they were
+    #[serde(default, skip_serializing)]
+    pub sources: Vec<(String, String)>,
+    #[serde(default)]
+    pub trust: Vec<(String, Trust)>,
+    // the publication years of the pages read so far that have one
+    #[serde(default)]
+    pub published: Vec<(String, i64)>,
+    // the dois the metadata of the pages read so far names
+    #[serde(default)]
+    pub dois: Vec<(String, String)>,
+}
+
+impl RunState {
+    pub fn load(log_dir: &Path) -> Result<Self> {
+        let file = std::io::BufReader::new(std::fs::File::open(log_dir.join(STATE_FILE))?);
+        let mut run: Self = serde_json::from_reader(file)?;
+        let path = log_dir.join(SOURCES_FILE);
+        if path.exists() {
+            saved_sources(&path)?;
+            for line in std::fs::read_to_string(&path)?.lines() {
+                run.sources.push(serde_json::from_str(line)?);
+            }
+        }
+        Ok(run)
+    }
+
+    fn save(&self, log_dir: &Path) -> Result<()> {
+        let path = log_dir.join(STATE_FILE);
+        let tmp = path.with_extension("tmp");
+        std::fs::write(&tmp, serde_json::to_vec(self)?)?;
+        std::fs::rename(&tmp, &path)?;
+        Ok(())
+    }
+}
+
+// the number of pages in the sources file, a line a crash cut short is removed so that the pages
+// appended next start on a line of their own
+fn saved_sources(path: &Path) -> Result<usize> {
+    let text = std::fs::read(path)?;
+    let complete = text.iter().rposition(|&b| b == b'\n').map_or(0, |i| i + 1);
+    if complete < text.len() {
+        std::fs::OpenOptions::new()
+            .write(true)
+            .open(path)?
+            .set_len(complete as u64)?;
+    }
+    Ok(text[..complete].iter().filter(|&&b| b == b'\n').count())
+}
+
+fn append_sources(path: &Path, pages: &[(String, String)]) -> Result<()> {
+    let mut lines = Vec::new();
+    for page in pages {
+        serde_json::to_writer(&mut lines, page)?;
+        lines.push(b'\n');
+    }
+    let mut file = std::fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)?;
+    std::io::Write::write_all(&mut file, &lines)?;
+    Ok(())
+}
+
+pub struct SaveRunState {
+    log_dir: PathBuf,
+    run_id: String,
+    args: Vec<String>,
+    // the number of pages already in the sources file
+    saved_sources: usize,
+    usage: Arc<llm::Usage>,
+    state: SharedState,
+    report: SharedReport,
+    pool: Arc<SubAgentPool>,
+}
+
+impl SaveRunState {
+    // a resumed run keeps appending to the sources file of the run, a new one starts it over
+    pub fn new(
+        log_dir: &Path,
+        config: &Config,
+        resumed: bool,
+        usage: Arc<llm::Usage>,
+        state: SharedState,
+        report: SharedReport,
+        pool: Arc<SubAgentPool>,
+    ) -> Result<Box<Self>> {
+        let path = log_dir.join(SOURCES_FILE);
+        let saved_sources = match resumed && path.exists() {
+            true => saved_sources(&path)?,
+            false => {
+                std::fs::write(&path, b"")?;
+                0
+            }
+        };
+        Ok(Box::new(Self {
+            log_dir: log_dir.to_path_buf(),
+            run_id: config.run_id.clone(),
+            args: config.args.clone(),
+            saved_sources,
+            usage,
+            state,
+            report,
+            pool,
+        }))
+    }
+}
+
+#[async_trait]
+impl callbacks::Callback for SaveRunState {
+    async fn call(&mut self, history: &mut dyn History) -> Result<()> {
+        let task = history
+            .iter()
+            .find_map(|m| match m.as_ref() {
+                Message::User(task) => Some(task.clone()),
+                _ => None,
+            })
+            .unwrap_or_default();
+        let (findings, questions) = {
+            let state = self.state.lock().unwrap();
+            (state.findings.clone(), state.questions.clone())
+        };
+        let facts = self.pool.facts();
+        let sources = self.pool.web().fetched();
+        if let Some(new) = sources
+            .get(self.saved_sources..)
+            .filter(|new| !new.is_empty())
+        {
+            append_sources(&self.log_dir.join(SOURCES_FILE), new)?;
+            self.saved_sources = sources.len();
+        }
+        RunState {
+            run_id: self.run_id.clone(),
+            args: self.args.clone(),
+            task,
+            orchestrator_usage: self.usage.total(),
+            findings,
+            questions,
+            report: self.report.lock().unwrap().clone(),
+            subagents: self.pool.records(),
+            pending: self.pool.pending(),
+            next_id: self.pool.started(),
+            facts: facts.facts(),
+            aliases: facts.aliases(),
+            notes: self.pool.blackboard().notes(),
+            sources: Vec::new(),
+            trust: self.pool.web().trust_levels(),
+            published: self.pool.web().published(),
+            dois: self.pool.web().dois(),
+        }
+        .save(&self.log_dir)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{RunState, SOURCES_FILE, append_sources};
+    use crate::state::Finding;
+    use crate::subagents::{PendingSubAgent, Priority};
+    use agent::tools::Trust;
+
+    #[test]
+    fn test_run_state() {
+        let dir = std::env::temp_dir().join(format!("run-state-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        RunState {
+            run_id: "run-1".to_string(),
+            args: vec!["research".to_string(), "--model=gpt-4o".to_string()],
+            task: "EV market".to_string(),
+            orchestrator_usage: Default::default(),
+            findings: vec![Finding {
+                subagent: "subagent_0".to_string(),
+                task: "EV sales in Europe".to_string(),
+                result: "2.1 million in 2023".to_string(),
+                provenance: Vec::new(),
+                trust: Default::default(),
+                unverified: Default::default(),
+            }],
+            questions: Vec::new(),
+            report: Default::default(),
+            subagents: Vec::new(),
+            pending: vec![PendingSubAgent {
+                name: "subagent_1".to_string(),
+                task: "EV sales in China".to_string(),
+                retry_of: None,
+                priority: Priority::High,
+            }],
+            next_id: 2,
+            facts: Vec::new(),
+            aliases: vec![("googl".to_string(), "Alphabet".to_string())],
+            notes: Vec::new(),
+            sources: Vec::new(),
+            trust: vec![("https://example.com".to_string(), Trust::Verified)],
+            published: vec![("https://example.com".to_string(), 2021)],
+            dois: vec![("https://example.com".to_string(), "10.1000/abc".to_string())],
+        }
+        .save(&dir)
+        .unwrap();
+        let sources = dir.join(SOURCES_FILE);
+        std::fs::write(&sources, b"").unwrap();
+        append_sources(
+            &sources,
+            &[("https://example.com".to_string(), "text".to_string())],
+        )
+        .unwrap();
+        // a page a crash cut short is dropped and the next pages are appended after it
+        let mut file = std::fs::OpenOptions::new()
+            .append(true)
+            .open(&sources)
+            .unwrap();
+        std::io::Write::write_all(&mut file, b"[\"https://cut.com\",\"te").unwrap();
+        assert_eq!(super::saved_sources(&sources).unwrap(), 1);
+        append_sources(&sources, &[("https://b.com".to_string(), "b".to_string())]).unwrap();
+
+        let loaded = RunState::load(&dir).unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(loaded.run_id, "run-1");
+        assert_eq!(loaded.args[1], "--model=gpt-4o");
+        assert_eq!(
+            loaded.sources,
+            vec![
+                ("https://example.com".to_string(), "text".to_string()),
+                ("https://b.com".to_string(), "b".to_string())
+            ]
+        );
+        assert_eq!(loaded.findings[0].result, "2.1 million in 2023");
+        assert_eq!(loaded.pending[0].name, "subagent_1");
+        assert_eq!(loaded.pending[0].priority, Priority::High);
+        assert_eq!(loaded.next_id, 2);
+        assert_eq!(loaded.aliases.len(), 1);
+        assert_eq!(loaded.trust[0].1, Trust::Verified);
+        assert_eq!(loaded.published[0].1, 2021);
+        assert_eq!(loaded.dois[0].1, "10.1000/abc");
+    }
+}
@@ -0,0 +1,487 @@
+use crate::subagents::SubAgentPool;
+use agent::Result;
+use agent::llm::{Message, ToolResult};
+use agent::tools::{self, Trust};
+use async_trait::async_trait;
+use serde::Serialize;
+use std::collections::HashSet;
+use std::hash::{Hash, Hasher};
+use std::path::Path;
+use std::sync::Arc;
+
+// query parameters that identify the visitor rather than the page
+const TRACKING_PARAMS: &[&str] = &["fbclid", "gclid", "mc_cid", "mc_eid"];
+// pages whose texts share this fraction of their word shingles are the same article
+const DUPLICATE_SIMILARITY: f64 = 0.8;
+const SHINGLE_WORDS: usize = 5;
+// only the start of a page is compared, which is enough to recognize a republished article
+const COMPARED_WORDS: usize = 2000;
+
+// the url of a page without the parts that differ between copies of it, such as the scheme,
+// www. and mobile subdomains, amp suffixes, fragments, and tracking parameters
+pub fn canonical_url(url: &str) -> String {
+    let Ok(mut parsed) = reqwest::Url::parse(url.trim()) else {
+        return url.trim().to_string();
+    };
+    parsed.set_fragment(None);
+    let query = parsed
+        .query_pairs()
+        .filter(|(key, _)| !key.starts_with("utm_") && !TRACKING_PARAMS.contains(&key.as_ref()))
+        .map(|(key, value)| (key.into_owned(), value.into_owned()))
+        .collect::<Vec<_>>();
+    if query.is_empty() {
+        parsed.set_query(None);
+    } else {
+        parsed.query_pairs_mut().clear().extend_pairs(query);
+    }
+
+    let host = parsed.host_str().unwrap_or_default().to_ascii_lowercase();
+    let host = ["www.", "m.", "amp."]
+        .iter()
+        .find_map(|prefix| host.strip_prefix(prefix))
+        .unwrap_or(&host)
+        .to_string();
+
+    let mut path = parsed.path().trim_end_matches('/').to_string();
+    for suffix in ["/amp", "/index.html", ".amp"] {
+        if let Some(stripped) = path.strip_suffix(suffix) {
+            path = stripped.to_string();
+        }
+    }
+    // arxiv serves the same paper as an abstract page and as a pdf
+    if host == "arxiv.org"
+        && let Some(id) = path.strip_prefix("/pdf/")
+    {
+        path = format!("/abs/{}", id.trim_end_matches(".pdf"));
+    }
+
+    match parsed.query() {
+        Some(query) => format!("{}{}?{}", host, path, query),
+        None => format!("{}{}", host, path),
+    }
+}
+
+// finds a doi such as 10.1038/nature12373 in a url or in the text of a page
+pub fn extract_doi(text: &str) -> Option<String> {
+    let start = text.find("10.")?;
+    let candidate = &text[start..];
+    let slash = candidate.find('/')?;
+    let prefix = &candidate[3..slash];
+    if prefix.len() < 4 || !prefix.chars().all(|c| c.is_ascii_digit() || c == '.') {
+        return extract_doi(&text[start + 3..]);
+    }
+    let end = candidate[slash + 1..]
+        .find(|c: char| c.is_whitespace() || matches!(c, '"' | '<' | '>' | '?' | '#' | '&'))
+        .map_or(candidate.len(), |i| slash + 1 + i);
+    let doi = candidate[..end].trim_end_matches(['.', ',', ';', ')', ']']);
+    if doi.len() <= slash + 1 {
+        return None;
+    }
+    Some(doi.to_ascii_lowercase())
+}
+
+fn shingles(text: &str) -> HashSet<u64> {
+    let words = text
+        .split_whitespace()
+        .take(COMPARED_WORDS)
+        .map(|w| w.to_lowercase())
+        .collect::<Vec<_>>();
+    words
+        .windows(SHINGLE_WORDS)
+        .map(|window| {
+            let mut hasher = std::hash::DefaultHasher::new();
+            window.hash(&mut hasher);
+            hasher.finish()
+        })
+        .collect()
+}
+
+fn similarity(a: &HashSet<u64>, b: &HashSet<u64>) -> f64 {
+    if a.is_empty() || b.is_empty() {
+        return 0.0;
+    }
+    a.intersection(b).count() as f64 / a.union(b).count() as f64
+}
+
+// one independent source and every url it was read from
+#[derive(Serialize, Debug, PartialEq)]
+pub struct SourceCluster {
+    pub canonical: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub doi: Option<String>,
+    pub urls: Vec<String>,
+    // the language of the source if it was read through a translation
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub translated_from: Option<String>,
+    // the most trusted of the tools the source was read with
+    pub trust: Trust,
+}
+
+struct Source {
+    url: String,
+    canonical: String,
+    doi: Option<String>,
+    shingles: HashSet<u64>,
+}
+
+impl Source {
+    fn same_as(&self, other: &Source) -> bool {
+        self.canonical == other.canonical
+            || (self.doi.is_some() && self.doi == other.doi)
+            || similarity(&self.shingles, &other.shingles) >= DUPLICATE_SIMILARITY
+    }
+}
+
+fn find(parents: &mut [usize], i: usize) -> usize {
+    let mut root = i;
+    while parents[root] != root {
+        root = parents[root];
+    }
+    parents[i] = root;
+    root
+}
+
+// groups pages that are the same article, by canonical url, doi, or nearly identical text. The
+// doi of a page is the one in its url or its metadata, dois in its text may be ones it cites
+pub fn cluster(pages: &[(String, String)], dois: &[(String, String)]) -> Vec<SourceCluster> {
+    let sources = pages
+        .iter()
+        .map(|(url, text)| {
+            let canonical = canonical_url(url);
+            let doi = extract_doi(url).or_else(|| {
+                dois.iter()
+                    .find(|(page, _)| canonical_url(page) == canonical)
+                    .and_then(|(_, doi)| extract_doi(doi))
+            });
+            Source {
+                url: url.clone(),
+                canonical,
+                doi,
+                shingles: shingles(text),
+            }
+        })
+        .collect::<Vec<_>>();
+
+    let mut parents = (0..sources.len()).collect::<Vec<_>>();
+    for i in 0..sources.len() {
+        for j in 0..i {
+            if sources[i].same_as(&sources[j]) {
+                let (a, b) = (find(&mut parents, i), find(&mut parents, j));
+                parents[a.max(b)] = a.min(b);
+            }
+        }
+    }
+
+    let mut clusters: Vec<(usize, SourceCluster)> = Vec::new();
+    for (i, source) in sources.iter().enumerate() {
+        let root = find(&mut parents, i);
+        match clusters.iter_mut().find(|(r, _)| *r == root) {
+            Some((_, cluster)) => {
+                if !cluster.urls.contains(&source.url) {
+                    cluster.urls.push(source.url.clone());
+                }
+                if cluster.doi.is_none() {
+                    cluster.doi = source.doi.clone();
+                }
+            }
+            None => clusters.push((
+                root,
+                SourceCluster {
+                    canonical: source.canonical.clone(),
+                    doi: source.doi.clone(),
+                    urls: vec![source.url.clone()],
+                    translated_from: None,
+                    trust: Trust::default(),
+                },
+            )),
+        }
+    }
+    clusters.into_iter().map(|(_, cluster)| cluster).collect()
+}
+
+// marks the clusters of the translated urls with their source language
+pub fn mark_translated(clusters: &mut [SourceCluster], translations: &[(String, String)]) {
+    for (url, language) in translations {
+        let canonical = canonical_url(url);
+        if let Some(cluster) = clusters.iter_mut().find(|cluster| {
+            cluster.canonical == canonical
+                || cluster.urls.iter().any(|u| canonical_url(u) == canonical)
+        }) {
+            cluster.translated_from = Some(language.clone());
+        }
+    }
+}
+
+// sets the trust of each cluster to the most trusted of its urls, urls that are not in the list
+// keep the default
+pub fn mark_trust(clusters: &mut [SourceCluster], levels: &[(String, Trust)]) {
+    for cluster in clusters.iter_mut() {
+        if let Some(trust) = levels
+            .iter()
+            .filter(|(url, _)| cluster.urls.contains(url))
+            .map(|(_, trust)| *trust)
+            .max()
+        {
+            cluster.trust = trust;
+        }
+    }
+}
+
+// the number of independent sources among urls, urls that were never fetched count as their own
+// source unless they share a canonical url or doi with another
+pub fn independent(clusters: &[SourceCluster], urls: &[String]) -> usize {
+    let mut seen = HashSet::new();
+    for url in urls {
+        let canonical = canonical_url(url);
+        let doi = extract_doi(url);
+        let key = clusters
+            .iter()
+            .position(|cluster| {
+                cluster.canonical == canonical
+                    || (doi.is_some() && cluster.doi == doi)
+                    || cluster.urls.iter().any(|u| canonical_url(u) == canonical)
+            })
+            .map(|i| format!("cluster {}", i))
+            .or(doi)
+            .unwrap_or(canonical);
+        seen.insert(key);
+    }
+    seen.len()
+}
+
+// the urls a report cites, in the order they first appear
+pub(crate) fn cited_urls(report: &str) -> Vec<String> {
+    let mut urls: Vec<String> = Vec::new();
+    let mut rest = report;
+    while let Some(start) = rest.find("http") {
+        rest = &rest[start..];
+        let end = rest
+            .find(|c: char| c.is_whitespace() || matches!(c, ')' | ']' | '>' | '<' | '"'))
+            .unwrap_or(rest.len());
+        let url = rest[..end].trim_end_matches(['.', ',', ';', ':']);
+        if (url.starts_with("http://") || url.starts_with("https://"))
+            && !urls.iter().any(|cited| cited == url)
+        {
+            urls.push(url.to_string());
+        }
+        rest = &rest[end.max(4)..];
+    }
+    urls
+}
+
+// the pages cited by the report whose text mentions no year since the cutoff, with the latest
+// year they mention. Pages that were not read or mention no year at all cannot be judged
+pub fn older_than(report: &str, pages: &[(String, String)], since_year: i64) -> Vec<(String, i64)> {
+    cited_urls(report)
+        .into_iter()
+        .filter_map(|url| {
+            let canonical = canonical_url(&url);
+            let (_, text) = pages
+                .iter()
+                .find(|(page, _)| canonical_url(page) == canonical)?;
+            let year = tools::latest_year(text)?;
+            (year < since_year).then_some((url, year))
+        })
+        .collect()
+}
+
+pub fn write(log_dir: &Path, clusters: &[SourceCluster]) -> Result<()> {
+    std::fs::write(
+        log_dir.join("sources.json"),
+        serde_json::to_string_pretty(clusters)?,
+    )?;
+    Ok(())
+}
+
+#[derive(serde::Deserialize, schemars::JsonSchema)]
+struct ListSourcesArgs {
+    /// urls cited for a statement, to count how many independent sources they are; leave empty to list all sources
+    #[serde(default)]
+    urls: Vec<String>,
+}
+
+#[derive(Serialize)]
+struct ListSourcesResult {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    independent_sources: Option<usize>,
+    sources: Vec<SourceCluster>,
+}
+
+pub struct ListSources(pub Arc<SubAgentPool>);
+
+#[async_trait]
+impl tools::FunctionalTool for ListSources {
+    fn definition(&self) -> Result<tools::ToolDefinition> {
+        tools::ToolDefinition::new::<ListSourcesArgs>(
+            "list_sources",
+            "This tool lists the distinct sources the sub-agents have read so far, grouping the different urls of the same article. Pass the urls cited for a statement to learn how many independent sources support it. Each source has a trust level: verified for authoritative apis such as SEC filings, web for pages anyone could have published, and generated for model output. Cite each source once in the final report, using its canonical url.",
+        )
+    }
+
+    async fn invoke_fn(
+        &mut self,
+        call: &tools::ToolCall,
+        _: &tools::ToolContext,
+    ) -> Result<Message> {
+        let args: ListSourcesArgs = call.args()?;
+        let mut sources = cluster(&self.0.web().fetched(), &self.0.web().dois());
+        mark_translated(&mut sources, &self.0.web().translations());
+        mark_trust(&mut sources, &self.0.web().trust_levels());
+        let result = ListSourcesResult {
+            independent_sources: (!args.urls.is_empty()).then(|| independent(&sources, &args.urls)),
+            sources,
+        };
+
+        Ok(Message::Tool {
+            id: call.id.clone(),
+            name: "list_sources".to_string(),
+            result: ToolResult::json(&result)?,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        canonical_url, cluster, extract_doi, independent, mark_translated, mark_trust, older_than,
+    };
+    use agent::tools::Trust;
+
+    #[test]
+    fn test_canonical_url() {
+        assert_eq!(
+            canonical_url("https://www.Example.com/news/story/?utm_source=x&id=3#top"),
+            "example.com/news/story?id=3"
+        );
+        assert_eq!(
+            canonical_url("http://m.example.com/news/story/amp"),
+            "example.com/news/story"
+        );
+        assert_eq!(
+            canonical_url("https://arxiv.org/pdf/2301.00001v2.pdf"),
+            canonical_url("https://arxiv.org/abs/2301.00001v2")
+        );
+        assert_eq!(
+            canonical_url("https://github.com/o/r/blob/x.md?ref=main&fbclid=1"),
+            "github.com/o/r/blob/x.md?ref=main"
+        );
+        assert_eq!(canonical_url("not a url"), "not a url");
+    }
+
+    #[test]
+    fn test_extract_doi() {
+        assert_eq!(
+            extract_doi("https://doi.org/10.1038/Nature12373").as_deref(),
+            Some("10.1038/nature12373")
+        );
+        assert_eq!(
+            extract_doi("version 10.2 of the paper, doi: 10.1126/science.169.3946.635.").as_deref(),
+            Some("10.1126/science.169.3946.635")
+        );
+        assert_eq!(extract_doi("released on 10.05/2024"), None);
+    }
+
+    #[test]
+    fn test_older_than() {
+        let pages = vec![
+            (
+                "https://www.example.com/old".to_string(),
+                "Published 2019. Prices fell.".to_string(),
+            ),
+            (
+                "https://example.com/new".to_string(),
+                "Updated 2021, revised in 2024".to_string(),
+            ),
+            (
+                "https://example.com/undated".to_string(),
+                "no date".to_string(),
+            ),
+        ];
+        let report = "Prices fell [1](https://example.com/old/). They rose (see <https://example.com/new>), https://example.com/undated and https://notread.org.\n1. https://example.com/old/";
+        assert_eq!(
+            older_than(report, &pages, 2024),
+            vec![("https://example.com/old/".to_string(), 2019)]
+        );
+        assert!(older_than(report, &pages, 2019).is_empty());
+    }
+
+    #[test]
+    fn test_cluster() {
+        let article = "the city council voted on tuesday to approve the new budget which raises spending on public transport by ten percent over the next two years";
+        let pages = vec![
+            (
+                "https://news.example.com/budget".to_string(),
+                article.to_string(),
+            ),
+            (
+                "https://www.news.example.com/budget/?utm_medium=social".to_string(),
+                "different layout".to_string(),
+            ),
+            (
+                "https://aggregator.org/story/123".to_string(),
+                format!("{} read more", article),
+            ),
+            (
+                "https://doi.org/10.1000/abc123".to_string(),
+                "a paper".to_string(),
+            ),
+            (
+                "https://journal.org/article".to_string(),
+                "the same paper".to_string(),
+            ),
+            (
+                "https://blog.org/review".to_string(),
+                "a review citing doi: 10.1000/abc123".to_string(),
+            ),
+            ("https://other.org".to_string(), "unrelated".to_string()),
+        ];
+        let dois = vec![(
+            "https://journal.org/article".to_string(),
+            "doi:10.1000/ABC123".to_string(),
+        )];
+        let mut clusters = cluster(&pages, &dois);
+        mark_translated(
+            &mut clusters,
+            &[("http://journal.org/article/".to_string(), "de".to_string())],
+        );
+
+        assert_eq!(clusters.len(), 4);
+        assert_eq!(clusters[0].canonical, "news.example.com/budget");
+        assert_eq!(clusters[0].urls.len(), 3);
+        assert_eq!(clusters[1].doi.as_deref(), Some("10.1000/abc123"));
+        assert_eq!(clusters[1].urls.len(), 2);
+        assert_eq!(clusters[1].translated_from.as_deref(), Some("de"));
+        assert_eq!(clusters[0].translated_from, None);
+
+        mark_trust(
+            &mut clusters,
+            &[
+                ("https://aggregator.org/story/123".to_string(), Trust::Web),
+                ("https://journal.org/article".to_string(), Trust::Verified),
+                ("https://doi.org/10.1000/abc123".to_string(), Trust::Web),
+            ],
+        );
+        assert_eq!(clusters[0].trust, Trust::Web);
+        assert_eq!(clusters[1].trust, Trust::Verified);
+
+        let urls = |urls: &[&str]| urls.iter().map(|u| u.to_string()).collect::<Vec<_>>();
+        assert_eq!(
+            independent(
+                &clusters,
+                &urls(&[
+                    "https://news.example.com/budget",
+                    "https://aggregator.org/story/123",
+                    "https://journal.org/article",
+                ])
+            ),
+            2
+        );
+        assert_eq!(
+            independent(
+                &clusters,
+                &urls(&["https://unknown.org/a", "https://unknown.org/a/#b"])
+            ),
+            1
+        );
+    }
+}
@@ -0,0 +1,352 @@
+use agent::tools::Trust;
+use std::collections::{BTreeMap, BTreeSet};
+use std::sync::{Arc, Mutex};
+
+// a claim of a sub-agent's report with the sources and memory keys that back it
+#[derive(Clone, Debug, PartialEq, serde::Serialize, serde::Deserialize, schemars::JsonSchema)]
+pub struct Provenance {
+    /// a single factual claim from your report
+    pub claim: String,
+    /// the urls or artifact ids of the sources that support the claim
+    #[serde(default)]
+    pub sources: Vec<String>,
+    /// the memory keys under which the evidence for the claim is stored, if any
+    #[serde(default)]
+    pub memory_keys: Vec<String>,
+}
+
+#[derive(Clone, serde::Serialize, serde::Deserialize)]
+pub struct Finding {
+    pub subagent: String,
+    pub task: String,
+    pub result: String,
+    #[serde(default)]
+    pub provenance: Vec<Provenance>,
+    // the trust of the provenance sources that were read during the run, by url
+    #[serde(default)]
+    pub trust: BTreeMap<String, Trust>,
+    // the provenance sources the run never read and memory keys the sub-agent never stored
+    #[serde(default)]
+    pub unverified: BTreeSet<String>,
+}
+
+impl Finding {
+    // the result as the orchestrator sees it, followed by what backs each claim so that the
+    // report can be limited to supported claims
+    pub fn handoff(&self) -> String {
+        if self.provenance.is_empty() {
+            return self.result.clone();
+        }
+
+        let mut handoff = format!("{}\n\n<provenance>\n", self.result);
+        for p in &self.provenance {
+            let mut backing = Vec::new();
+            if !p.sources.is_empty() {
+                let sources = p
+                    .sources
+                    .iter()
+                    .map(|source| match self.trust.get(source) {
+                        _ if self.unverified.contains(source) => {
+                            format!("{} (not read in this run)", source)
+                        }
+                        Some(trust) => format!("{} ({})", source, trust),
+                        None => source.clone(),
+                    })
+                    .collect::<Vec<_>>();
+                backing.push(format!("sources: {}", sources.join(", ")));
+            }
+            if !p.memory_keys.is_empty() {
+                let keys = p
+                    .memory_keys
+                    .iter()
+                    .map(|key| match self.unverified.contains(key) {
+                        true => format!("{} (not in memory)", key),
+                        false => key.clone(),
+                    })
+                    .collect::<Vec<_>>();
+                backing.push(format!("memory: {}", keys.join(", ")));
+            }
+            if backing.is_empty() {
+                backing.push("unsupported".to_string());
+            }
+            handoff.push_str(&format!("- {} [{}]\n", p.claim, backing.join("; ")));
+        }
+        handoff.push_str("</provenance>");
+        handoff
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, serde::Serialize, serde::Deserialize)]
+pub enum QuestionStatus {
+    Open,
+    InProgress(String),
+    Answered(String),
+}
+
+#[derive(Clone, serde::Serialize, serde::Deserialize)]
+pub struct SubQuestion {
+    pub id: String,
+    pub question: String,
+    pub status: QuestionStatus,
+}
+
+#[derive(Default)]
+pub struct WorkflowState {
+    pub findings: Vec<Finding>,
+    pub questions: Vec<SubQuestion>,
+}
+
+pub type SharedState = Arc<Mutex<WorkflowState>>;
+
+fn parse_id(id: &str) -> Option<Vec<u32>> {
+    id.split('.').map(|s| s.parse().ok()).collect()
+}
+
+impl WorkflowState {
+    pub fn add_questions(&mut self, questions: Vec<(String, String)>) -> Result<(), String> {
+        for (id, _) in &questions {
+            if parse_id(id).is_none() {
+                return Err(format!(
+                    "invalid sub-question id '{}', ids must be dot separated numbers such as '1' or '1.2'",
+                    id
+                ));
+            }
+
+            if let Some(parent) = id.rsplit_once('.').map(|(parent, _)| parent) {
+                let exists = self.questions.iter().any(|q| q.id == parent)
+                    || questions.iter().any(|(other, _)| other == parent);
+                if !exists {
+                    return Err(format!(
+                        "sub-question '{}' has no parent sub-question '{}'",
+                        id, parent
+                    ));
+                }
+            }
+        }
+
+        for (id, question) in questions {
+            match self.questions.iter_mut().find(|q| q.id == id) {
+                Some(existing) => existing.question = question,
+                None => self.questions.push(SubQuestion {
+                    id,
+                    question,
+                    status: QuestionStatus::Open,
+                }),
+            }
+        }
+
+        self.questions.sort_by_key(|q| parse_id(&q.id));
+
+        Ok(())
+    }
+
+    pub fn assign_questions(&mut self, ids: &[String], subagent: &str) -> Vec<String> {
+        let mut unknown = Vec::new();
+        for id in ids {
+            match self.questions.iter_mut().find(|q| &q.id == id) {
+                Some(q) => q.status = QuestionStatus::InProgress(subagent.to_string()),
+                None => unknown.push(id.clone()),
+            }
+        }
+        unknown
+    }
+
+    pub fn complete_subagent(&mut self, subagent: &str) {
+        for q in &mut self.questions {
+            if q.status == QuestionStatus::InProgress(subagent.to_string()) {
+                q.status = QuestionStatus::Answered(subagent.to_string());
+            }
+        }
+    }
+
+    pub fn reassign_subagent(&mut self, from: &str, to: &str) {
+        for q in &mut self.questions {
+            if q.status == QuestionStatus::InProgress(from.to_string()) {
+                q.status = QuestionStatus::InProgress(to.to_string());
+            }
+        }
+    }
+
+    pub fn release_subagent(&mut self, subagent: &str) {
+        for q in &mut self.questions {
+            if q.status == QuestionStatus::InProgress(subagent.to_string()) {
+                q.status = QuestionStatus::Open;
+            }
+        }
+    }
+
+    // the number of answered sub-questions and the total number of sub-questions
+    pub fn coverage(&self) -> (usize, usize) {
+        let answered = self
+            .questions
+            .iter()
+            .filter(|q| matches!(q.status, QuestionStatus::Answered(_)))
+            .count();
+        (answered, self.questions.len())
+    }
+
+    // unanswered sub-questions without sub-questions of their own, each needs a sub-agent
+    pub fn open_leaves(&self) -> usize {
+        self.questions
+            .iter()
+            .filter(|q| q.status == QuestionStatus::Open)
+            .filter(|q| {
+                let prefix = format!("{}.", q.id);
+                !self
+                    .questions
+                    .iter()
+                    .any(|other| other.id.starts_with(&prefix))
+            })
+            .count()
+    }
+
+    // the sub-questions that are not answered yet, None once there are none
+    pub fn todo(&self) -> Option<String> {
+        let todo = self
+            .questions
+            .iter()
+            .filter_map(|q| match &q.status {
+                QuestionStatus::Open => Some(format!("- {} {}\n", q.id, q.question)),
+                QuestionStatus::InProgress(subagent) => Some(format!(
+                    "- {} {} [in progress by {}]\n",
+                    q.id, q.question, subagent
+                )),
+                QuestionStatus::Answered(_) => None,
+            })
+            .collect::<String>();
+        (!todo.is_empty()).then_some(todo)
+    }
+
+    pub fn coverage_report(&self) -> String {
+        if self.questions.is_empty() {
+            return "no sub-questions have been recorded, use the decompose_question tool to break down the task".to_string();
+        }
+
+        let count =
+            |f: fn(&QuestionStatus) -> bool| self.questions.iter().filter(|q| f(&q.status)).count();
+
+        let mut report = format!(
+            "Coverage: {} of {} sub-questions answered, {} in progress, {} unanswered\n",
+            count(|s| matches!(s, QuestionStatus::Answered(_))),
+            self.questions.len(),
+            count(|s| matches!(s, QuestionStatus::InProgress(_))),
+            count(|s| matches!(s, QuestionStatus::Open)),
+        );
+
+        for q in &self.questions {
+            let status = match &q.status {
+                QuestionStatus::Open => "unanswered".to_string(),
+                QuestionStatus::InProgress(subagent) => format!("in progress by {}", subagent),
+                QuestionStatus::Answered(subagent) => format!("answered by {}", subagent),
+            };
+            report.push_str(&format!(
+                "{}- {} {} [{}]\n",
+                "  ".repeat(q.id.matches('.').count()),
+                q.id,
+                q.question,
+                status
+            ));
+        }
+
+        report
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Finding, Provenance, WorkflowState};
+    use agent::tools::Trust;
+
+    fn q(id: &str, question: &str) -> (String, String) {
+        (id.to_string(), question.to_string())
+    }
+
+    #[test]
+    fn test_question_coverage() {
+        let mut state = WorkflowState::default();
+
+        assert!(state.add_questions(vec![q("1.1", "orphan")]).is_err());
+        assert!(state.add_questions(vec![q("a", "bad id")]).is_err());
+
+        state
+            .add_questions(vec![q("2", "second"), q("1", "first"), q("1.1", "child")])
+            .unwrap();
+        state
+            .add_questions(vec![q("1.2", "another child")])
+            .unwrap();
+
+        assert_eq!(
+            state.assign_questions(&["1.1".to_string(), "9".to_string()], "subagent_0"),
+            vec!["9".to_string()]
+        );
+        state.assign_questions(&["2".to_string()], "subagent_1");
+        state.complete_subagent("subagent_0");
+
+        state.assign_questions(&["1.2".to_string()], "subagent_2");
+        state.reassign_subagent("subagent_2", "subagent_2_retry");
+        state.release_subagent("subagent_2_retry");
+
+        assert_eq!(state.open_leaves(), 1);
+        assert_eq!(
+            state.coverage_report(),
+            "Coverage: 1 of 4 sub-questions answered, 1 in progress, 2 unanswered
+- 1 first [unanswered]
+  - 1.1 child [answered by subagent_0]
+  - 1.2 another child [unanswered]
+- 2 second [in progress by subagent_1]
+"
+        );
+        assert_eq!(
+            state.todo().unwrap(),
+            "- 1 first\n- 1.2 another child\n- 2 second [in progress by subagent_1]\n"
+        );
+        assert_eq!(WorkflowState::default().todo(), None);
+    }
+
+    #[test]
+    fn test_handoff() {
+        let mut finding = Finding {
+            subagent: "subagent_0".to_string(),
+            task: "EV sales".to_string(),
+            result: "Sales grew.".to_string(),
+            provenance: Vec::new(),
+            trust: Default::default(),
+            unverified: Default::default(),
+        };
+        assert_eq!(finding.handoff(), "Sales grew.");
+        finding
+            .trust
+            .insert("https://acea.auto".to_string(), Trust::Verified);
+
+        finding.provenance = vec![
+            Provenance {
+                claim: "2.1 million EVs were sold in Europe in 2023".to_string(),
+                sources: vec!["https://acea.auto".to_string(), "artifact_3".to_string()],
+                memory_keys: vec!["ev_sales_2023".to_string()],
+            },
+            Provenance {
+                claim: "sales will double by 2026".to_string(),
+                sources: Vec::new(),
+                memory_keys: Vec::new(),
+            },
+            Provenance {
+                claim: "prices fell by 10%".to_string(),
+                sources: vec!["https://example.com/prices".to_string()],
+                memory_keys: vec!["ev_prices".to_string()],
+            },
+        ];
+        finding.unverified = ["https://example.com/prices", "ev_prices"]
+            .map(String::from)
+            .into();
+        assert_eq!(
+            finding.handoff(),
+            "Sales grew.
+
+<provenance>
+- 2.1 million EVs were sold in Europe in 2023 [sources: https://acea.auto (verified API), artifact_3; memory: ev_sales_2023]
+- sales will double by 2026 [unsupported]
+- prices fell by 10% [sources: https://example.com/prices (not read in this run); memory: ev_prices (not in memory)]
+</provenance>"
+        );
+    }
+}
@@ -0,0 +1,119 @@
+use crate::config::Config;
+use crate::progress::format_duration;
+use crate::state::SharedState;
+use crate::subagents::SubAgentPool;
+use agent::llm::{self, Message};
+use agent::{ContextProvider, History, Priority, Result};
+use async_trait::async_trait;
+use std::sync::Arc;
+use std::time::Instant;
+
+// gives the orchestrator the current state of the run before every request, without adding a
+// status message to its history at every step
+pub struct RunStatus {
+    pool: Arc<SubAgentPool>,
+    state: SharedState,
+    usage: Arc<llm::Usage>,
+    config: Arc<Config>,
+    started: Instant,
+}
+
+impl RunStatus {
+    pub fn new(
+        pool: Arc<SubAgentPool>,
+        state: SharedState,
+        usage: Arc<llm::Usage>,
+        config: Arc<Config>,
+    ) -> Box<Self> {
+        Box::new(Self {
+            pool,
+            state,
+            usage,
+            config,
+            started: Instant::now(),
+        })
+    }
+
+    async fn render(&self) -> String {
+        let (answered, total) = self.state.lock().unwrap().coverage();
+        let mut status = format!(
+            "<run_status>\nCurrent time: {}\nElapsed: {}\nSub-agents: {} started, {} running, {} queued\nSub-questions: {} of {} answered\n",
+            chrono::Local::now().format("%B %-d, %Y %H:%M"),
+            format_duration(self.started.elapsed()),
+            self.pool.started(),
+            self.pool.running().await,
+            self.pool.queued(),
+            answered,
+            total,
+        );
+        let usage = self.usage.total();
+        match &self.config.pricing {
+            Some(pricing) => status.push_str(&format!(
+                "Cost so far: ${:.2} for {} tokens\n",
+                usage.cost(pricing),
+                usage.total_tokens()
+            )),
+            None => status.push_str(&format!("Tokens used: {}\n", usage.total_tokens())),
+        }
+        if let Some(budget) = self.pool.budget().await {
+            status.push_str(&format!("{}\n", budget.describe()));
+        }
+        let aliases = self.pool.facts().aliases();
+        if !aliases.is_empty() {
+            status.push_str(&format!(
+                "Entity aliases, use the canonical names: {}\n",
+                aliases
+                    .iter()
+                    .map(|(alias, canonical)| format!("{} = {}", alias, canonical))
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            ));
+        }
+        status.push_str("</run_status>");
+        status
+    }
+}
+
+#[async_trait]
+impl ContextProvider for RunStatus {
+    async fn context(&mut self, _: &dyn History) -> Result<Vec<Arc<Message>>> {
+        Ok(vec![Arc::new(Message::Developer(self.render().await))])
+    }
+
+    fn priority(&self) -> Priority {
+        Priority::High
+    }
+}
+
+// the sub-questions that are left, for orchestrators with a context budget that may no longer
+// see the coverage reports and decompositions of their earlier steps
+pub struct Todo(SharedState);
+
+impl Todo {
+    pub fn new(state: SharedState) -> Box<Self> {
+        Box::new(Self(state))
+    }
+}
+
+#[async_trait]
+impl ContextProvider for Todo {
+    async fn context(&mut self, _: &dyn History) -> Result<Vec<Arc<Message>>> {
+        Ok(self
+            .0
+            .lock()
+            .unwrap()
+            .todo()
+            .map(|todo| {
+                Arc::new(Message::Developer(format!(
+                    "<todo>\nSub-questions that are not answered yet:\n{}</todo>",
+                    todo
+                )))
+            })
+            .into_iter()
+            .collect())
+    }
+
+    fn priority(&self) -> Priority {
+        Priority::High
+    }
+}
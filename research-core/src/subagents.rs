@@ -0,0 +1,1027 @@
+use crate::budget::{self, BudgetEstimate};
+use crate::concurrency::{self, AdaptiveConcurrency};
+use crate::config::Config;
+use crate::presets::Role;
+use crate::prompts;
+use crate::research::TaskCompleted;
+use crate::sources::canonical_url;
+use crate::state::{Finding, Provenance, SharedState};
+use agent::artifacts::ArtifactStore;
+use agent::llm::Message;
+use agent::tools;
+use agent::workdir::RunContext;
+use agent::{AgentBuilder, History, StopCondition};
+use agent::{Error, ErrorKind, Result};
+use agent::{callbacks, llm};
+use async_trait::async_trait;
+use std::collections::HashSet;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU32, Ordering};
+use tokio::sync::Mutex;
+use tokio_util::sync::CancellationToken;
+
+pub const SPILL_THRESHOLD: usize = 16 * 1024;
+
+#[derive(Clone)]
+pub struct SubAgentTask {
+    name: String,
+    task: String,
+    // name of the failed attempt this task retries
+    retry_of: Option<String>,
+    priority: Priority,
+    // child of the orchestrator's token so that cancelling the orchestrator stops its sub-agents
+    cancel: CancellationToken,
+    // started again by a resumed run, its logs are appended to those of the interrupted attempt
+    restarted: bool,
+}
+
+// queued sub-agents start in this order once one of the running sub-agents finishes
+#[derive(
+    Clone,
+    Copy,
+    Debug,
+    Default,
+    PartialEq,
+    Eq,
+    PartialOrd,
+    Ord,
+    serde::Serialize,
+    serde::Deserialize,
+    schemars::JsonSchema,
+)]
+#[serde(rename_all = "snake_case")]
+pub enum Priority {
+    Critical,
+    High,
+    #[default]
+    Normal,
+    Low,
+}
+
+impl std::fmt::Display for Priority {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Priority::Critical => "critical",
+            Priority::High => "high",
+            Priority::Normal => "normal",
+            Priority::Low => "low",
+        })
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SubAgentStatus {
+    Running,
+    Completed,
+    Retried,
+    Failed,
+}
+
+#[derive(Clone, serde::Serialize, serde::Deserialize)]
+pub struct SubAgentRecord {
+    pub name: String,
+    pub task: String,
+    pub status: SubAgentStatus,
+    #[serde(default)]
+    pub priority: Priority,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub retry_of: Option<String>,
+    pub started_at: String,
+    // set once the sub-agent has finished
+    pub duration_secs: Option<f64>,
+    pub usage: llm::TokenUsage,
+}
+
+struct TrackedSubAgent {
+    record: SubAgentRecord,
+    started: std::time::Instant,
+    usage: Arc<llm::Usage>,
+}
+
+// a sub-agent task that was running or queued when the run state was saved, it is started again
+// when the run is resumed
+#[derive(Clone, serde::Serialize, serde::Deserialize)]
+pub struct PendingSubAgent {
+    pub name: String,
+    pub task: String,
+    pub retry_of: Option<String>,
+    pub priority: Priority,
+}
+
+struct QueuedSubAgent {
+    subagent: SubAgentTask,
+    previous_failure: Option<String>,
+    queued: std::time::Instant,
+}
+
+// inserts behind the queued sub-agents of the same or a higher priority, so each priority is
+// started first in first out, and returns the position in the queue
+fn enqueue(queue: &mut Vec<QueuedSubAgent>, queued: QueuedSubAgent) -> usize {
+    let position = queue
+        .iter()
+        .position(|q| q.subagent.priority > queued.subagent.priority)
+        .unwrap_or(queue.len());
+    queue.insert(position, queued);
+    position
+}
+
+// the history of a sub-agent that ran and the keys it stored in its memory
+type Ran = (Vec<Arc<Message>>, Vec<String>);
+
+type Joined = (SubAgentTask, Result<Ran>);
+
+type Handles = Mutex<tokio::task::JoinSet<Joined>>;
+
+pub struct SubAgentPool {
+    handles: Handles,
+    // the tasks of the running sub-agents, so that a sub-agent that panicked still yields a
+    // failed finding
+    running: std::sync::Mutex<std::collections::HashMap<tokio::task::Id, SubAgentTask>>,
+    records: std::sync::Mutex<Vec<TrackedSubAgent>>,
+    // sub-agents waiting for a free slot when the number of concurrent sub-agents is capped
+    queue: std::sync::Mutex<Vec<QueuedSubAgent>>,
+    next_id: AtomicU32,
+    llm: Arc<dyn llm::LLM + Send + Sync>,
+    log_dir: std::path::PathBuf,
+    config: Arc<Config>,
+    state: SharedState,
+    artifacts: Arc<ArtifactStore>,
+    work_dir: Arc<RunContext>,
+    usage: Arc<llm::Usage>,
+    documents: Option<Arc<tools::VectorMemory>>,
+    web: Arc<tools::WebAccess>,
+    facts: Arc<tools::FactStore>,
+    blackboard: Arc<tools::Blackboard>,
+    // replaces the static cap on concurrent sub-agents when adaptive concurrency is on
+    concurrency: Option<Arc<AdaptiveConcurrency>>,
+    // usage of the sub-agents of earlier runs, for estimating the cost of new ones
+    usage_history: Vec<llm::TokenUsage>,
+}
+
+impl SubAgentPool {
+    #[allow(clippy::too_many_arguments)]
+    pub fn new(
+        llm: Arc<dyn llm::LLM + Send + Sync>,
+        log_dir: &std::path::Path,
+        config: Arc<Config>,
+        state: SharedState,
+        artifacts: Arc<ArtifactStore>,
+        work_dir: Arc<RunContext>,
+        usage: Arc<llm::Usage>,
+        documents: Option<Arc<tools::VectorMemory>>,
+    ) -> Arc<Self> {
+        let concurrency = config.adaptive_concurrency.then(|| {
+            AdaptiveConcurrency::new(
+                config
+                    .max_concurrent_subagents
+                    .unwrap_or(concurrency::DEFAULT_MAX_CONCURRENCY),
+            )
+        });
+        let llm: Arc<dyn llm::LLM + Send + Sync> = match &concurrency {
+            Some(concurrency) => concurrency.monitor(llm),
+            None => llm,
+        };
+        Arc::new(Self {
+            handles: Mutex::new(tokio::task::JoinSet::new()),
+            running: std::sync::Mutex::new(std::collections::HashMap::new()),
+            records: std::sync::Mutex::new(Vec::new()),
+            queue: std::sync::Mutex::new(Vec::new()),
+            next_id: AtomicU32::new(0),
+            llm,
+            log_dir: log_dir.to_path_buf(),
+            web: tools::WebAccess::new(config.web_policy.clone()),
+            facts: tools::FactStore::new(),
+            blackboard: tools::Blackboard::new(),
+            concurrency,
+            usage_history: budget::load_history(log_dir),
+            config,
+            state,
+            artifacts,
+            work_dir,
+            usage,
+            documents,
+        })
+    }
+
+    fn next_name(&self) -> String {
+        format!("subagent_{}", self.next_id.fetch_add(1, Ordering::SeqCst))
+    }
+
+    // the caller holds the lock of the handles from checking the cap until the sub-agent is
+    // started, so that parallel calls cannot both take the last slot
+    fn spawn(
+        &self,
+        handles: &mut tokio::task::JoinSet<Joined>,
+        subagent: SubAgentTask,
+        previous_failure: Option<String>,
+    ) -> Result<()> {
+        let task_prompt = match previous_failure {
+            Some(failure) => format!(
+                "{}\n\n<previous_attempt>\nA previous attempt at this task failed because: {}\nAdjust your approach to avoid this failure, for instance by using fewer tool calls, narrowing the scope of your searches, or making sure to finish with the complete_task tool.\n</previous_attempt>",
+                subagent.task, failure
+            ),
+            None => subagent.task.clone(),
+        };
+
+        let llm = self.llm.clone();
+        let system_prompt = prompts::subagent(&self.config);
+        let prompt_file = prompts::PromptFile::subagent(&self.config);
+        let tool_selection = self.config.tools(Role::SubAgent);
+        let memory = tools::KVMemoryTool::new();
+        let artifacts = self.artifacts.clone();
+        let work_dir = self.work_dir.clone();
+        let timeout = self.config.subagent_timeout;
+        let step_timeout = self.config.step_timeout;
+        let config = self.config.clone();
+        let usage = self.usage.child();
+        let documents = self.documents.clone();
+        let web = self.web.clone();
+        let facts = self.facts.clone();
+        let blackboard = self.blackboard.clone();
+        let profile = self.config.profile.then(|| {
+            self.log_dir
+                .join(format!("{}.profile.folded", subagent.name))
+        });
+        let log_file = |extension: &str| {
+            callbacks::RotatingFile::open(
+                &self
+                    .log_dir
+                    .join(format!("{}.{}", subagent.name, extension)),
+                self.config.log_rotation,
+                subagent.restarted,
+            )
+        };
+        let file = log_file("md")?;
+        let events = log_file("events.jsonl")?;
+        let cancel = subagent.cancel.clone();
+        self.records.lock().unwrap().push(TrackedSubAgent {
+            record: SubAgentRecord {
+                name: subagent.name.clone(),
+                task: subagent.task.clone(),
+                status: SubAgentStatus::Running,
+                priority: subagent.priority,
+                retry_of: subagent.retry_of.clone(),
+                started_at: chrono::Local::now().to_rfc3339(),
+                duration_secs: None,
+                usage: llm::TokenUsage::default(),
+            },
+            started: std::time::Instant::now(),
+            usage: usage.clone(),
+        });
+
+        let running = subagent.clone();
+        let handle = handles.spawn(async move {
+            let run = async {
+                let mut builder = AgentBuilder::new()
+                    .name(&subagent.name)
+                    .usage(usage.clone())
+                    .llm(llm.clone())
+                    .tool(Box::new(CompleteSubAgentTask))
+                    .tool(tools::SummarizeHistory::new(llm.clone(), 2))
+                    .tools(tools::FactsTool::new(facts).tools()?)
+                    .tools(tools::BlackboardTool::new(blackboard.clone()).tools()?)
+                    .context_provider(tools::BlackboardNotifications::new(
+                        blackboard,
+                        &subagent.name,
+                    ))
+                    .spill_tool_results(artifacts, SPILL_THRESHOLD)
+                    .run_context(work_dir)
+                    .recover_context_overflow();
+                if let Some(step_timeout) = step_timeout {
+                    builder = builder.step_timeout(step_timeout);
+                }
+                if let Some(profile) = &profile {
+                    builder = builder.profiler(callbacks::Profiler::new(profile));
+                }
+                if let Some(documents) = &documents {
+                    builder = builder.tool(documents.search_tool());
+                }
+                builder = config.context_budget(builder, documents.as_ref(), &usage);
+                if let Some(prompt_file) = prompt_file {
+                    builder = builder.reload_system_prompt(prompt_file);
+                }
+
+                let mut agent = tool_selection
+                    .apply_with_memory(builder, &web, &config, &memory)?
+                    .callback(tools::SummarizeHistory::new(llm.clone(), 2))
+                    .callback(callbacks::TeeLogger::new(vec![
+                        callbacks::MessageLogger::new(&subagent.name, file)?,
+                        callbacks::EventLogger::new(&subagent.name, events),
+                    ]))
+                    .stop_condition(Box::new(TaskCompleted))
+                    .build()?;
+
+                agent
+                    .run(
+                        vec![
+                            Arc::new(Message::System(system_prompt)),
+                            Arc::new(Message::User(task_prompt)),
+                        ],
+                        &cancel,
+                    )
+                    .await
+            };
+
+            let res = match timeout {
+                Some(timeout) => tokio::time::timeout(timeout, run)
+                    .await
+                    .unwrap_or_else(|_| {
+                        Err(Error::Timeout(format!(
+                            "the sub-agent did not finish within {} seconds",
+                            timeout.as_secs()
+                        )))
+                    }),
+                None => run.await,
+            };
+
+            (subagent, res.map(|history| (history, memory.keys())))
+        });
+        self.running.lock().unwrap().insert(handle.id(), running);
+
+        Ok(())
+    }
+
+    // the number of sub-agents that may run at once, None if it is not limited
+    fn cap(&self) -> Option<usize> {
+        match &self.concurrency {
+            Some(concurrency) => Some(concurrency.limit()),
+            None => self.config.max_concurrent_subagents,
+        }
+    }
+
+    // starts the sub-agent, or queues it while the cap on concurrent sub-agents is reached and
+    // returns its position in the queue
+    async fn schedule(
+        &self,
+        subagent: SubAgentTask,
+        previous_failure: Option<String>,
+    ) -> Result<Option<usize>> {
+        if subagent.cancel.is_cancelled() {
+            return Err(Error::Cancelled(format!(
+                "{} was cancelled before it started",
+                subagent.name
+            )));
+        }
+        let mut handles = self.handles.lock().await;
+        if let Some(cap) = self.cap()
+            && handles.len() >= cap
+        {
+            let queued = QueuedSubAgent {
+                subagent,
+                previous_failure,
+                queued: std::time::Instant::now(),
+            };
+            return Ok(Some(enqueue(&mut self.queue.lock().unwrap(), queued)));
+        }
+        self.spawn(&mut handles, subagent, previous_failure)?;
+        Ok(None)
+    }
+
+    // fills the slots freed by finished sub-agents with the queued ones of the highest priority,
+    // queued sub-agents whose run was cancelled meanwhile are dropped instead of started
+    async fn start_queued(&self) -> Result<()> {
+        let mut handles = self.handles.lock().await;
+        while let Some(cap) = self.cap()
+            && handles.len() < cap
+        {
+            let next = {
+                let mut queue = self.queue.lock().unwrap();
+                (!queue.is_empty()).then(|| queue.remove(0))
+            };
+            let Some(next) = next else {
+                break;
+            };
+            if next.subagent.cancel.is_cancelled() {
+                continue;
+            }
+            self.spawn(&mut handles, next.subagent, next.previous_failure)?;
+        }
+        Ok(())
+    }
+
+    pub fn queued(&self) -> usize {
+        self.queue.lock().unwrap().len()
+    }
+
+    fn describe(&self) -> String {
+        let mut result = String::new();
+        let records = self.records.lock().unwrap();
+        if records.is_empty() {
+            result.push_str("No sub-agents have been started yet.\n");
+        } else {
+            result.push_str("Sub-agents:\n");
+        }
+        for tracked in records.iter() {
+            let seconds = tracked
+                .record
+                .duration_secs
+                .unwrap_or_else(|| tracked.started.elapsed().as_secs_f64());
+            result.push_str(&format!(
+                "- {} [{}] {:?} for {:.0}s: {}\n",
+                tracked.record.name,
+                tracked.record.priority,
+                tracked.record.status,
+                seconds,
+                tracked.record.task
+            ));
+        }
+        drop(records);
+
+        let queue = self.queue.lock().unwrap();
+        if !queue.is_empty() {
+            result.push_str(&format!(
+                "Queued, in the order they will start when one of the at most {} concurrent sub-agents finishes:\n",
+                self.cap().unwrap_or_default()
+            ));
+        }
+        for (i, queued) in queue.iter().enumerate() {
+            result.push_str(&format!(
+                "{}. {} [{}] waiting for {:.0}s: {}\n",
+                i + 1,
+                queued.subagent.name,
+                queued.subagent.priority,
+                queued.queued.elapsed().as_secs_f64(),
+                queued.subagent.task
+            ));
+        }
+        result
+    }
+
+    // web access shared by the orchestrator and its sub-agents
+    pub fn web(&self) -> Arc<tools::WebAccess> {
+        self.web.clone()
+    }
+
+    // how many more sub-agents the remaining budget covers, None without a budget
+    pub async fn budget(&self) -> Option<BudgetEstimate> {
+        budget::estimate(
+            &self.config.budget,
+            self.config.pricing.as_ref(),
+            self.usage.total(),
+            budget::expected_usage(&self.records(), &self.usage_history),
+            self.running().await + self.queued(),
+        )
+    }
+
+    // fact table shared by the orchestrator and its sub-agents
+    pub fn facts(&self) -> Arc<tools::FactStore> {
+        self.facts.clone()
+    }
+
+    // blackboard shared by the orchestrator and its sub-agents
+    pub fn blackboard(&self) -> Arc<tools::Blackboard> {
+        self.blackboard.clone()
+    }
+
+    pub fn records(&self) -> Vec<SubAgentRecord> {
+        self.records
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|tracked| SubAgentRecord {
+                usage: tracked.usage.total(),
+                ..tracked.record.clone()
+            })
+            .collect()
+    }
+
+    fn set_status(&self, subagent: &str, status: SubAgentStatus) {
+        if let Some(tracked) = self
+            .records
+            .lock()
+            .unwrap()
+            .iter_mut()
+            .find(|tracked| tracked.record.name == subagent)
+        {
+            tracked.record.status = status;
+            if status != SubAgentStatus::Running {
+                tracked.record.duration_secs = Some(tracked.started.elapsed().as_secs_f64());
+            }
+        }
+    }
+
+    pub fn pending(&self) -> Vec<PendingSubAgent> {
+        let running = self
+            .records
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|tracked| tracked.record.status == SubAgentStatus::Running)
+            .map(|tracked| PendingSubAgent {
+                name: tracked.record.name.clone(),
+                task: tracked.record.task.clone(),
+                retry_of: tracked.record.retry_of.clone(),
+                priority: tracked.record.priority,
+            })
+            .collect::<Vec<_>>();
+        let queue = self.queue.lock().unwrap();
+        let queued = queue.iter().map(|queued| PendingSubAgent {
+            name: queued.subagent.name.clone(),
+            task: queued.subagent.task.clone(),
+            retry_of: queued.subagent.retry_of.clone(),
+            priority: queued.subagent.priority,
+        });
+        running.into_iter().chain(queued).collect()
+    }
+
+    // puts back the finished sub-agents of a resumed run and starts its pending ones again under
+    // their old names, so their sub-questions stay assigned to them
+    pub async fn restore(
+        &self,
+        records: Vec<SubAgentRecord>,
+        pending: Vec<PendingSubAgent>,
+        next_id: u32,
+        cancel: &CancellationToken,
+    ) -> Result<()> {
+        self.next_id.fetch_max(next_id, Ordering::SeqCst);
+        for record in records
+            .into_iter()
+            .filter(|record| record.status != SubAgentStatus::Running)
+        {
+            let usage = self.usage.child();
+            usage.record(record.usage);
+            self.records.lock().unwrap().push(TrackedSubAgent {
+                record,
+                started: std::time::Instant::now(),
+                usage,
+            });
+        }
+        for subagent in pending {
+            self.schedule(
+                SubAgentTask {
+                    name: subagent.name,
+                    task: subagent.task,
+                    retry_of: subagent.retry_of,
+                    priority: subagent.priority,
+                    cancel: cancel.child_token(),
+                    restarted: true,
+                },
+                None,
+            )
+            .await?;
+        }
+        Ok(())
+    }
+
+    pub fn started(&self) -> u32 {
+        self.next_id.load(Ordering::SeqCst)
+    }
+
+    // includes retries of failed sub-agents
+    pub async fn running(&self) -> usize {
+        self.handles.lock().await.len()
+    }
+
+    async fn shutdown(&self) {
+        self.queue.lock().unwrap().clear();
+        self.handles.lock().await.shutdown().await;
+        self.running.lock().unwrap().clear();
+        self.records.lock().unwrap().clear();
+    }
+
+    fn collect_result(
+        &self,
+        subagent: &SubAgentTask,
+        (mut history, memory_keys): Ran,
+    ) -> Result<String> {
+        if let Some(Message::Tool { name, result, .. }) = history.pop().as_deref()
+            && name == "complete_task"
+        {
+            // results of a plain report, e.g. from a checkpoint of an older run, carry no provenance
+            let args = result
+                .parse::<CompleteSubAgentTaskArgs>()
+                .unwrap_or_else(|_| CompleteSubAgentTaskArgs {
+                    report: result.to_string(),
+                    findings: Vec::new(),
+                });
+            let web = self.web();
+            let trust = args
+                .findings
+                .iter()
+                .flat_map(|p| &p.sources)
+                .filter_map(|source| Some((source.clone(), web.trust(source)?)))
+                .collect();
+            // claims may only rest on pages that were read, artifacts that exist, and values the
+            // sub-agent stored, anything else is flagged to the orchestrator
+            let fetched = web
+                .fetched()
+                .iter()
+                .map(|(url, _)| canonical_url(url))
+                .collect::<HashSet<_>>();
+            let artifacts = self.artifacts.list().unwrap_or_default();
+            let sources = args
+                .findings
+                .iter()
+                .flat_map(|p| &p.sources)
+                .filter(|source| {
+                    !fetched.contains(&canonical_url(source)) && !artifacts.contains(source)
+                });
+            let keys = args
+                .findings
+                .iter()
+                .flat_map(|p| &p.memory_keys)
+                .filter(|key| !memory_keys.contains(key));
+            let unverified = sources.chain(keys).cloned().collect();
+            let finding = Finding {
+                subagent: subagent.name.clone(),
+                task: subagent.task.clone(),
+                result: args.report,
+                provenance: args.findings,
+                trust,
+                unverified,
+            };
+            let handoff = finding.handoff();
+
+            let mut state = self.state.lock().unwrap();
+            state.complete_subagent(&subagent.name);
+            state.findings.push(finding);
+
+            return Ok(handoff);
+        }
+
+        Err(Error::AgentWorkflowError(
+            "sub agent terminated without correct tool call".to_string(),
+        ))
+    }
+
+    // returns None if the sub-agent failed and was restarted, otherwise the result that should
+    // be reported to the orchestrator
+    async fn resolve(&self, subagent: SubAgentTask, res: Result<Ran>) -> Result<Option<String>> {
+        let failure = match res.and_then(|ran| self.collect_result(&subagent, ran)) {
+            Ok(result) => {
+                self.set_status(&subagent.name, SubAgentStatus::Completed);
+                return Ok(Some(result));
+            }
+            // neither a retry nor the orchestrator can fix bad credentials or an exhausted quota
+            Err(e) if e.kind() == ErrorKind::UserActionable || matches!(e, Error::Cancelled(_)) => {
+                self.set_status(&subagent.name, SubAgentStatus::Failed);
+                return Err(e);
+            }
+            Err(e) => e.to_string(),
+        };
+
+        if subagent.retry_of.is_none() {
+            self.set_status(&subagent.name, SubAgentStatus::Retried);
+            let retry = SubAgentTask {
+                name: format!("{}_retry", subagent.name),
+                task: subagent.task,
+                retry_of: Some(subagent.name.clone()),
+                priority: subagent.priority,
+                cancel: subagent.cancel,
+                restarted: false,
+            };
+            self.state
+                .lock()
+                .unwrap()
+                .reassign_subagent(&subagent.name, &retry.name);
+            self.schedule(retry, Some(failure)).await?;
+            return Ok(None);
+        }
+
+        self.set_status(&subagent.name, SubAgentStatus::Failed);
+        self.state.lock().unwrap().release_subagent(&subagent.name);
+
+        Ok(Some(format!(
+            "The sub-agent for the following task failed twice and produced no result:\n{}\nThe last attempt failed because: {}",
+            subagent.task, failure
+        )))
+    }
+
+    // the task and outcome of a finished sub-agent, a panic is a failure like any other
+    fn joined(
+        &self,
+        joined: std::result::Result<(tokio::task::Id, Joined), tokio::task::JoinError>,
+    ) -> Result<Joined> {
+        let (id, res) = match joined {
+            Ok((id, joined)) => (id, Ok(joined)),
+            Err(e) => (e.id(), Err(e)),
+        };
+        let subagent = self.running.lock().unwrap().remove(&id);
+        match (res, subagent) {
+            (Ok(joined), _) => Ok(joined),
+            (Err(e), Some(subagent)) => Ok((
+                subagent,
+                Err(Error::AgentWorkflowError(format!(
+                    "the sub-agent stopped unexpectedly: {}",
+                    e
+                ))),
+            )),
+            (Err(e), None) => Err(Error::AgentWorkflowError(e.to_string())),
+        }
+    }
+
+    async fn wait_next(&self) -> Result<Option<String>> {
+        loop {
+            self.start_queued().await?;
+            let joined = self.handles.lock().await.join_next_with_id().await;
+            let Some(joined) = joined else {
+                return Ok(None);
+            };
+
+            let (subagent, res) = self.joined(joined)?;
+            let result = self.resolve(subagent, res).await?;
+            self.start_queued().await?;
+            if let Some(result) = result {
+                return Ok(Some(result));
+            }
+        }
+    }
+
+    async fn try_next(&self) -> Result<Option<String>> {
+        loop {
+            self.start_queued().await?;
+            let joined = self.handles.lock().await.try_join_next_with_id();
+            let Some(joined) = joined else {
+                return Ok(None);
+            };
+
+            let (subagent, res) = self.joined(joined)?;
+            let result = self.resolve(subagent, res).await?;
+            self.start_queued().await?;
+            if let Some(result) = result {
+                return Ok(Some(result));
+            }
+        }
+    }
+}
+
+#[derive(serde::Serialize, serde::Deserialize, schemars::JsonSchema)]
+struct CompleteSubAgentTaskArgs {
+    /// your detailed, condensed, complete, and accurate report for the lead researcher
+    report: String,
+    /// each key claim of the report with the sources and memory keys that back it
+    #[serde(default)]
+    findings: Vec<Provenance>,
+}
+
+// the sub-agent's complete_task, which hands the report over together with the provenance of
+// its claims
+struct CompleteSubAgentTask;
+
+#[async_trait]
+impl tools::FunctionalTool for CompleteSubAgentTask {
+    fn definition(&self) -> Result<tools::ToolDefinition> {
+        tools::ToolDefinition::new::<CompleteSubAgentTaskArgs>(
+            "complete_task",
+            "This tool will mark your task as complete and return your report to the lead researcher. You must use this tool when you have completed your task. List every key claim of the report in findings together with the urls or artifact ids of the sources and the memory keys that back it.",
+        )
+    }
+
+    async fn invoke_fn(
+        &mut self,
+        call: &tools::ToolCall,
+        _: &tools::ToolContext,
+    ) -> Result<Message> {
+        let args: CompleteSubAgentTaskArgs = call.args()?;
+
+        Ok(Message::Tool {
+            id: call.id.clone(),
+            name: "complete_task".to_string(),
+            result: llm::ToolResult::json(&args)?,
+        })
+    }
+}
+
+pub struct StartSubAgent(pub Arc<SubAgentPool>);
+
+#[derive(serde::Deserialize, schemars::JsonSchema)]
+struct StartSubAgentArgs {
+    /// this is the description of the task that the sub-agent should complete
+    task_desc: String,
+    /// the ids of the sub-questions from decompose_question that the sub-agent is responsible for answering
+    #[serde(default)]
+    question_ids: Vec<String>,
+    /// critical, high, normal, or low; when the number of concurrent sub-agents is capped, queued sub-agents start in order of priority, so mark blocking sub-tasks critical and background investigations low
+    #[serde(default)]
+    priority: Priority,
+}
+
+#[async_trait]
+impl tools::Tool for StartSubAgent {
+    fn definition(&self) -> Result<tools::ToolDefinition> {
+        tools::ToolDefinition::new::<StartSubAgentArgs>(
+            "start_subagent",
+            "This tool allows you to create a research sub-agent to investigate a specific research task. You must use this tool to delegate parts of your research task to sub-agents. Make sure to provide clear instructions to the sub-agent as to what it should research.",
+        )
+    }
+
+    async fn invoke(
+        &mut self,
+        call: &tools::ToolCall,
+        history: &mut dyn History,
+        ctx: &tools::ToolContext,
+    ) -> Result<()> {
+        let args: StartSubAgentArgs = call.args()?;
+
+        if let Some(budget) = self.0.budget().await
+            && budget.affordable == 0
+        {
+            history.append(Arc::new(Message::Tool {
+                id: call.id.clone(),
+                name: "start_subagent".to_string(),
+                result: format!(
+                    "The sub-agent was not started because the remaining budget does not cover it. {}. Wait for the running sub-agents and write the report from the findings so far.",
+                    budget.describe()
+                )
+                .into(),
+            }));
+            return Ok(());
+        }
+
+        let name = self.0.next_name();
+
+        let unknown_ids = self
+            .0
+            .state
+            .lock()
+            .unwrap()
+            .assign_questions(&args.question_ids, &name);
+
+        let queued = self
+            .0
+            .schedule(
+                SubAgentTask {
+                    name,
+                    task: args.task_desc.clone(),
+                    retry_of: None,
+                    priority: args.priority,
+                    cancel: ctx.cancel.child_token(),
+                    restarted: false,
+                },
+                None,
+            )
+            .await?;
+
+        let mut result = match queued {
+            Some(position) => format!(
+                "Research sub-agent queued with {} priority at position {} for task: {}\nIt starts when one of the running sub-agents finishes, use list_subagents to see the queue.",
+                args.priority,
+                position + 1,
+                args.task_desc
+            ),
+            None => format!("Research sub-agent started for task: {}", args.task_desc),
+        };
+        if !unknown_ids.is_empty() {
+            result.push_str(&format!(
+                "\nWarning: unknown sub-question ids {}",
+                unknown_ids.join(", ")
+            ));
+        }
+        history.append(Arc::new(Message::Tool {
+            id: call.id.clone(),
+            name: "start_subagent".to_string(),
+            result: result.into(),
+        }));
+        Ok(())
+    }
+
+    async fn on_agent_start(&mut self) -> Result<()> {
+        self.0.shutdown().await;
+        Ok(())
+    }
+}
+
+pub struct WaitForSubAgent(pub Arc<SubAgentPool>);
+
+#[async_trait]
+impl tools::FunctionalTool for WaitForSubAgent {
+    fn definition(&self) -> Result<tools::ToolDefinition> {
+        Ok(tools::ToolDefinition::no_args(
+            "wait_for_subagent",
+            "This tool will wait for any of the active sub-agents to complete, and return the result they provide for their completed task.",
+        ))
+    }
+
+    async fn invoke_fn(
+        &mut self,
+        call: &tools::ToolCall,
+        _: &tools::ToolContext,
+    ) -> Result<Message> {
+        let result = self.0.wait_next().await?.unwrap_or_else(|| {
+            "no sub-agents are currently active, create a new sub-agent to wait for a task"
+                .to_string()
+        });
+
+        Ok(Message::Tool {
+            id: call.id.clone(),
+            name: "wait_for_subagent".to_string(),
+            result: result.into(),
+        })
+    }
+}
+
+pub struct ListSubAgents(pub Arc<SubAgentPool>);
+
+#[async_trait]
+impl tools::FunctionalTool for ListSubAgents {
+    fn definition(&self) -> Result<tools::ToolDefinition> {
+        Ok(tools::ToolDefinition::no_args(
+            "list_subagents",
+            "This tool lists the sub-agents with their priority, status, and run time, and the queued sub-agents in the order they will start.",
+        ))
+    }
+
+    async fn invoke_fn(
+        &mut self,
+        call: &tools::ToolCall,
+        _: &tools::ToolContext,
+    ) -> Result<Message> {
+        Ok(Message::Tool {
+            id: call.id.clone(),
+            name: "list_subagents".to_string(),
+            result: self.0.describe().into(),
+        })
+    }
+}
+
+pub struct StreamSubAgentResults {
+    pool: Arc<SubAgentPool>,
+    stop_condition: Box<dyn StopCondition + Send>,
+    delivered: u32,
+}
+
+impl StreamSubAgentResults {
+    pub fn new(
+        pool: Arc<SubAgentPool>,
+        stop_condition: Box<dyn StopCondition + Send>,
+    ) -> Box<Self> {
+        Box::new(Self {
+            pool,
+            stop_condition,
+            delivered: 0,
+        })
+    }
+}
+
+#[async_trait]
+impl callbacks::Callback for StreamSubAgentResults {
+    async fn call(&mut self, history: &mut dyn History) -> Result<()> {
+        if self.stop_condition.done(history) {
+            return Ok(());
+        }
+
+        while let Some(result) = self.pool.try_next().await? {
+            // tool results must follow the assistant message that requested them, so the
+            // delivery is recorded as a wait_for_subagent call made on the model's behalf
+            let call = tools::ToolCall {
+                id: format!("auto_wait_for_subagent_{}", self.delivered),
+                name: "wait_for_subagent".to_string(),
+                args: "{}".to_string(),
+            };
+            self.delivered += 1;
+
+            history.append(Arc::new(Message::Assistant(
+                String::new(),
+                vec![call.clone()],
+            )));
+            history.append(Arc::new(Message::Tool {
+                id: call.id,
+                name: call.name,
+                result: format!(
+                    "A sub-agent finished and its result was delivered automatically:\n{}",
+                    result
+                )
+                .into(),
+            }));
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Priority, QueuedSubAgent, SubAgentTask, enqueue};
+    use tokio_util::sync::CancellationToken;
+
+    fn queued(name: &str, priority: Priority) -> QueuedSubAgent {
+        QueuedSubAgent {
+            subagent: SubAgentTask {
+                name: name.to_string(),
+                task: String::new(),
+                retry_of: None,
+                priority,
+                cancel: CancellationToken::new(),
+                restarted: false,
+            },
+            previous_failure: None,
+            queued: std::time::Instant::now(),
+        }
+    }
+
+    #[test]
+    fn test_enqueue() {
+        let mut queue = Vec::new();
+        assert_eq!(enqueue(&mut queue, queued("a", Priority::Low)), 0);
+        assert_eq!(enqueue(&mut queue, queued("b", Priority::Normal)), 0);
+        assert_eq!(enqueue(&mut queue, queued("c", Priority::Critical)), 0);
+        assert_eq!(enqueue(&mut queue, queued("d", Priority::Normal)), 2);
+        assert_eq!(enqueue(&mut queue, queued("e", Priority::Low)), 4);
+        let names = queue
+            .iter()
+            .map(|q| q.subagent.name.as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(names, vec!["c", "b", "d", "a", "e"]);
+    }
+}
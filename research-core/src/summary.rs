@@ -0,0 +1,126 @@
+use crate::config::Config;
+use crate::inputs::truncate;
+use crate::prompts;
+use crate::state::Finding;
+use crate::subagents::SubAgentRecord;
+use agent::Result;
+use agent::llm::{self, CompletionRequest, Message};
+use std::sync::Arc;
+
+// findings and the report are cut so that the request stays small for long runs
+const MAX_FINDING: usize = 1500;
+const MAX_REPORT: usize = 4000;
+
+fn request(
+    task: &str,
+    outcome: &str,
+    subagents: &[SubAgentRecord],
+    findings: &[Finding],
+    report: Option<&str>,
+) -> String {
+    let mut request = format!(
+        "<task>\n{}\n</task>\n<outcome>\n{}\n</outcome>\n",
+        task, outcome
+    );
+    for subagent in subagents {
+        let status = serde_json::to_value(subagent.status)
+            .ok()
+            .and_then(|status| status.as_str().map(String::from))
+            .unwrap_or_default();
+        request.push_str(&format!(
+            "<subagent name=\"{}\" status=\"{}\">\n<task>\n{}\n</task>\n",
+            subagent.name, status, subagent.task
+        ));
+        if let Some(finding) = findings.iter().find(|f| f.subagent == subagent.name) {
+            request.push_str(&format!(
+                "<finding>\n{}\n</finding>\n",
+                truncate(&finding.result, MAX_FINDING)
+            ));
+        }
+        request.push_str("</subagent>\n");
+    }
+    if let Some(report) = report {
+        request.push_str(&format!(
+            "<report>\n{}\n</report>\n",
+            truncate(report, MAX_REPORT)
+        ));
+    }
+    request
+}
+
+// writes a two paragraph abstract of what the run did and found, for browsing past runs
+pub async fn run_abstract(
+    llm: &Arc<dyn llm::LLM + Send + Sync>,
+    config: &Config,
+    task: &str,
+    outcome: &str,
+    subagents: &[SubAgentRecord],
+    findings: &[Finding],
+    report: Option<&str>,
+) -> Result<String> {
+    let res = llm
+        .completion(CompletionRequest {
+            messages: &vec![
+                Arc::new(Message::System(prompts::run_abstract(config))),
+                Arc::new(Message::User(request(
+                    task, outcome, subagents, findings, report,
+                ))),
+            ],
+            tools: &[],
+            web_search_tool: false,
+            prefill: None,
+        })
+        .await?;
+    Ok(res.content.trim().to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{MAX_FINDING, request};
+    use crate::state::Finding;
+    use crate::subagents::{SubAgentRecord, SubAgentStatus};
+
+    #[test]
+    fn test_request() {
+        let record = |name: &str, status| SubAgentRecord {
+            name: name.to_string(),
+            task: format!("task of {}", name),
+            status,
+            priority: Default::default(),
+            retry_of: None,
+            started_at: String::new(),
+            duration_secs: None,
+            usage: Default::default(),
+        };
+        let request = request(
+            "the task",
+            "completed",
+            &[
+                record("subagent_0", SubAgentStatus::Completed),
+                record("subagent_1", SubAgentStatus::Failed),
+            ],
+            &[Finding {
+                subagent: "subagent_0".to_string(),
+                task: "task of subagent_0".to_string(),
+                result: "x".repeat(2 * MAX_FINDING),
+                provenance: Vec::new(),
+                trust: Default::default(),
+                unverified: Default::default(),
+            }],
+            None,
+        );
+
+        assert!(
+            request.starts_with("<task>\nthe task\n</task>\n<outcome>\ncompleted\n</outcome>\n")
+        );
+        assert!(request.contains("<subagent name=\"subagent_0\" status=\"completed\">"));
+        assert!(request.contains(&format!(
+            "<finding>\n{}\n</finding>",
+            "x".repeat(MAX_FINDING)
+        )));
+        assert!(request.contains(
+            "<subagent name=\"subagent_1\" status=\"failed\">\n<task>\ntask of subagent_1\n</task>\n</subagent>"
+        ));
+        assert!(!request.contains("<report>"));
+    }
+}
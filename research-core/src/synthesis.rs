@@ -0,0 +1,239 @@
+use crate::config::Config;
+use crate::outline;
+use crate::prompts;
+use crate::state::Finding;
+use crate::warnings;
+use agent::llm::{self, CompletionRequest, CompletionResponse, Message, TokenUsage};
+use agent::tools;
+use agent::{Error, Result};
+use std::path::Path;
+use std::sync::Arc;
+use tokio_util::sync::CancellationToken;
+
+const SYNTHESIS_FILE: &str = "synthesis.md";
+const PREFILL: &str = "# ";
+// the words of the uncut request, about 200k tokens, research that gathered more is written
+// section by section from an outline, where every request holds only what its section draws on
+const MAX_REQUEST_WORDS: usize = 150_000;
+
+// what the research gathered, for the write-up of the final report
+pub struct Material<'a> {
+    pub task: &'a str,
+    pub findings: &'a [Finding],
+    pub facts: &'a tools::FactStore,
+    pub sources: &'a [String],
+    pub documents: Option<&'a tools::VectorMemory>,
+    // the finalized draft of the orchestrator
+    pub draft: &'a str,
+}
+
+// the facts as list items with their date and source
+pub(crate) fn fact_lines(facts: &[tools::Fact]) -> String {
+    facts
+        .iter()
+        .map(|fact| {
+            let date = fact
+                .date
+                .as_deref()
+                .map(|date| format!(" ({})", date))
+                .unwrap_or_default();
+            format!(
+                "- {}, {}: {}{} [{}]\n",
+                fact.entity, fact.attribute, fact.value, date, fact.source_id
+            )
+        })
+        .collect()
+}
+
+// a completion without tools that is given up when the run is cancelled
+pub(crate) async fn complete(
+    llm: &Arc<dyn llm::LLM + Send + Sync>,
+    system: String,
+    user: String,
+    prefill: Option<&str>,
+    cancel: &CancellationToken,
+) -> Result<CompletionResponse> {
+    let messages = vec![
+        Arc::new(Message::System(system)),
+        Arc::new(Message::User(user)),
+    ];
+    tokio::select! {
+        _ = cancel.cancelled() => {
+            Err(Error::Cancelled("synthesis was cancelled".to_string()))
+        }
+        res = llm.completion(CompletionRequest {
+            messages: &messages,
+            tools: &[],
+            web_search_tool: false,
+            prefill,
+        }) => res,
+    }
+}
+
+// everything the research gathered, uncut, since the synthesis model has a long context, None if
+// it is too long for one request
+fn uncut(material: &Material) -> Option<String> {
+    let request = request(material);
+    (request.split_whitespace().count() <= MAX_REQUEST_WORDS).then_some(request)
+}
+
+fn request(material: &Material) -> String {
+    let mut request = format!("<task>\n{}\n</task>\n", material.task);
+    for finding in material.findings {
+        request.push_str(&format!(
+            "<finding subagent=\"{}\">\n<task>\n{}\n</task>\n{}\n</finding>\n",
+            finding.subagent,
+            finding.task,
+            finding.handoff()
+        ));
+    }
+    let facts = material.facts.facts();
+    if !facts.is_empty() {
+        request.push_str(&format!("<facts>\n{}</facts>\n", fact_lines(&facts)));
+    }
+    if !material.sources.is_empty() {
+        request.push_str(&format!(
+            "<sources>\n{}\n</sources>\n",
+            material.sources.join("\n")
+        ));
+    }
+    request.push_str(&format!("<draft>\n{}\n</draft>\n", material.draft));
+    request
+}
+
+// writes the final report with a separate long-context model from all findings, facts, and
+// sources of the research, with the finalized draft of the orchestrator as its outline, so that
+// the quality of the write-up does not depend on the model of the research loop. With
+// outline_report the report is planned first and every section is drafted on its own
+pub struct Synthesizer {
+    llm: Arc<dyn llm::LLM + Send + Sync>,
+    config: Arc<Config>,
+    log_dir: std::path::PathBuf,
+}
+
+impl Synthesizer {
+    pub fn new(llm: Arc<dyn llm::LLM + Send + Sync>, log_dir: &Path, config: Arc<Config>) -> Self {
+        Self {
+            llm: config.synthesis(&llm),
+            config,
+            log_dir: log_dir.to_path_buf(),
+        }
+    }
+
+    // the write-up and the tokens it used. The draft is kept when the model fails or its report
+    // does not meet the output contract, the write-up is still saved to the log directory then
+    pub async fn synthesize(
+        &self,
+        material: &Material<'_>,
+        cancel: &CancellationToken,
+    ) -> Result<(String, TokenUsage)> {
+        let request = match self.config.outline_report {
+            true => None,
+            false => {
+                let request = uncut(material);
+                if request.is_none() {
+                    warnings::warn(
+                        &self.log_dir,
+                        &format!(
+                            "the research is longer than the {} words of a synthesis request, the report is written from an outline instead",
+                            MAX_REQUEST_WORDS
+                        ),
+                    );
+                }
+                request
+            }
+        };
+        let res = match request {
+            Some(request) => complete(
+                &self.llm,
+                prompts::synthesis(&self.config),
+                request,
+                Some(PREFILL),
+                cancel,
+            )
+            .await
+            .map(|res| (res.content, res.usage)),
+            None => outline::write(&self.llm, &self.config, material, cancel).await,
+        };
+        let (report, usage) = match res {
+            Ok(res) => res,
+            Err(Error::Cancelled(e)) => return Err(Error::Cancelled(e)),
+            Err(e) => {
+                warnings::warn(
+                    &self.log_dir,
+                    &format!("the synthesis failed, the draft is kept: {}", e),
+                );
+                return Ok((material.draft.to_string(), TokenUsage::default()));
+            }
+        };
+
+        std::fs::write(self.log_dir.join(SYNTHESIS_FILE), &report)?;
+        let violations = self.config.contract.validate(&report);
+        if !violations.is_empty() {
+            warnings::warn(
+                &self.log_dir,
+                &format!(
+                    "the synthesized report does not meet the output contract, the draft is kept: {}",
+                    violations.join("; ")
+                ),
+            );
+            return Ok((material.draft.to_string(), usage));
+        }
+        Ok((report, usage))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{MAX_REQUEST_WORDS, Material, request, uncut};
+    use crate::state::Finding;
+    use agent::tools::{Fact, FactStore};
+
+    #[test]
+    fn test_request() {
+        let findings = vec![Finding {
+            subagent: "subagent_0".to_string(),
+            task: "EV sales in Europe".to_string(),
+            result: "2.1 million in 2023".to_string(),
+            provenance: Vec::new(),
+            trust: Default::default(),
+            unverified: Default::default(),
+        }];
+        let facts = FactStore::new();
+        facts.add(Fact {
+            entity: "Europe".to_string(),
+            attribute: "EV sales".to_string(),
+            value: "2.1 million".to_string(),
+            source_id: "https://example.com/ev".to_string(),
+            date: Some("2023".to_string()),
+            agent: "subagent_0".to_string(),
+        });
+        let material = Material {
+            task: "EV market",
+            findings: &findings,
+            facts: &facts,
+            sources: &["https://example.com/ev".to_string()],
+            documents: None,
+            draft: "# EV market",
+        };
+        let request = request(&material);
+        assert_eq!(uncut(&material).as_deref(), Some(request.as_str()));
+        let long = "word ".repeat(MAX_REQUEST_WORDS);
+        assert!(
+            uncut(&Material {
+                draft: &long,
+                ..material
+            })
+            .is_none()
+        );
+        assert!(
+            request.starts_with("<task>\nEV market\n</task>\n<finding subagent=\"subagent_0\">")
+        );
+        assert!(request.contains(
+            "<facts>\n- Europe, EV sales: 2.1 million (2023) [https://example.com/ev]\n</facts>\n"
+        ));
+        assert!(request.ends_with(
+            "<sources>\nhttps://example.com/ev\n</sources>\n<draft>\n# EV market\n</draft>\n"
+        ));
+    }
+}
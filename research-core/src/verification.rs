@@ -0,0 +1,383 @@
+use crate::config::Config;
+use crate::presets::ToolSelection;
+use crate::prompts;
+use agent::llm::{self, CompletionRequest, Message};
+use agent::tools;
+use agent::{AgentBuilder, Error, History, Result, StopCondition, callbacks};
+use async_trait::async_trait;
+use std::sync::Arc;
+use tokio::sync::Semaphore;
+use tokio_util::sync::CancellationToken;
+
+const MAX_CLAIMS: usize = 20;
+// claims checked at the same time
+const MAX_PARALLEL_CHECKS: usize = 4;
+
+// a claim of the report with the urls of the sources the report cites for it
+#[derive(serde::Deserialize, Clone, Debug, PartialEq)]
+struct Claim {
+    claim: String,
+    #[serde(default)]
+    sources: Vec<String>,
+}
+
+impl Claim {
+    fn prompt(&self) -> String {
+        let mut prompt = format!("<claim>\n{}\n</claim>", self.claim);
+        if !self.sources.is_empty() {
+            prompt.push_str(&format!(
+                "\n\n<cited_sources>\n{}\n</cited_sources>",
+                self.sources.join("\n")
+            ));
+        }
+        prompt
+    }
+}
+
+#[derive(
+    serde::Deserialize, serde::Serialize, schemars::JsonSchema, Clone, Copy, Debug, PartialEq,
+)]
+#[serde(rename_all = "snake_case")]
+pub enum Status {
+    Supported,
+    Contradicted,
+    Unverified,
+}
+
+#[derive(serde::Deserialize, serde::Serialize, schemars::JsonSchema, Debug)]
+pub struct Verdict {
+    /// whether the claim is supported by, contradicted by, or could not be verified against reliable sources
+    pub status: Status,
+    /// confidence between 0 and 1 that the claim is true as stated
+    pub confidence: f64,
+    /// the urls of the sources that were used to check the claim
+    pub sources: Vec<String>,
+    /// a brief explanation of the verdict, including the correct information if the claim is contradicted
+    pub explanation: String,
+}
+
+impl Verdict {
+    fn failed(reason: String) -> Self {
+        Self {
+            status: Status::Unverified,
+            confidence: 0.0,
+            sources: Vec::new(),
+            explanation: format!("verification failed: {}", reason),
+        }
+    }
+
+    // models do not always keep to the range they are asked for
+    fn clamped(mut self) -> Self {
+        self.confidence = if self.confidence.is_nan() {
+            0.0
+        } else {
+            self.confidence.clamp(0.0, 1.0)
+        };
+        self
+    }
+
+    fn label(&self) -> String {
+        match self.status {
+            Status::Supported => format!("confidence {:.2}", self.confidence),
+            Status::Contradicted => format!("disputed, confidence {:.2}", self.confidence),
+            Status::Unverified => "unverified".to_string(),
+        }
+    }
+}
+
+pub struct Verifier {
+    llm: Arc<dyn llm::LLM + Send + Sync>,
+    log_dir: std::path::PathBuf,
+    config: Arc<Config>,
+    // the web access of the sub-agents, so that claims are checked against pages already fetched
+    web: Arc<tools::WebAccess>,
+}
+
+impl Verifier {
+    pub fn new(
+        llm: Arc<dyn llm::LLM + Send + Sync>,
+        log_dir: &std::path::Path,
+        config: Arc<Config>,
+        web: Arc<tools::WebAccess>,
+    ) -> Self {
+        Self {
+            llm,
+            log_dir: log_dir.to_path_buf(),
+            config,
+            web,
+        }
+    }
+
+    pub async fn verify(&self, report: String, cancel: &CancellationToken) -> Result<String> {
+        let claims = self.extract_claims(&report).await?;
+
+        let permits = Arc::new(Semaphore::new(MAX_PARALLEL_CHECKS));
+        let mut handles = tokio::task::JoinSet::new();
+        for (i, claim) in claims.iter().enumerate() {
+            let file = std::fs::File::create(self.log_dir.join(format!("verifier_{}.md", i)))?;
+            let llm = self.llm.clone();
+            let system_prompt = prompts::verifier(&self.config);
+            let claim = claim.clone();
+            let cancel = cancel.child_token();
+            let web = self.web.clone();
+            let config = self.config.clone();
+            let permits = permits.clone();
+
+            handles.spawn(async move {
+                let _permit = permits.acquire().await;
+                (
+                    i,
+                    check_claim(llm, system_prompt, claim, file, web, &config, cancel).await,
+                )
+            });
+        }
+
+        let mut verdicts = Vec::with_capacity(claims.len());
+        while let Some(res) = handles.join_next().await {
+            let (i, verdict) = res?;
+            verdicts.push((
+                i,
+                verdict.unwrap_or_else(|e| Verdict::failed(e.to_string())),
+            ));
+        }
+        // cancelled checks would otherwise show up as failed verdicts in the report
+        if cancel.is_cancelled() {
+            return Err(Error::Cancelled("verification was cancelled".to_string()));
+        }
+        verdicts.sort_by_key(|(i, _)| *i);
+
+        let results = claims
+            .into_iter()
+            .map(|claim| claim.claim)
+            .zip(verdicts.into_iter().map(|(_, verdict)| verdict))
+            .collect::<Vec<_>>();
+
+        Ok(annotate(&report, &results))
+    }
+
+    async fn extract_claims(&self, report: &str) -> Result<Vec<Claim>> {
+        let res = self
+            .llm
+            .completion(CompletionRequest {
+                messages: &vec![
+                    Arc::new(Message::System(prompts::claims(&self.config, MAX_CLAIMS))),
+                    Arc::new(Message::User(report.to_string())),
+                ],
+                tools: &[],
+                web_search_tool: false,
+                prefill: None,
+            })
+            .await?;
+
+        parse_claims(&res.content)
+    }
+}
+
+fn parse_claims(content: &str) -> Result<Vec<Claim>> {
+    let content = content.trim();
+    let content = content
+        .strip_prefix("```json")
+        .or_else(|| content.strip_prefix("```"))
+        .and_then(|c| c.strip_suffix("```"))
+        .unwrap_or(content);
+
+    let mut claims: Vec<Claim> = serde_json::from_str(content.trim())?;
+    claims.truncate(MAX_CLAIMS);
+    Ok(claims)
+}
+
+async fn check_claim(
+    llm: Arc<dyn llm::LLM + Send + Sync>,
+    system_prompt: String,
+    claim: Claim,
+    file: std::fs::File,
+    web: Arc<tools::WebAccess>,
+    config: &Config,
+    cancel: CancellationToken,
+) -> Result<Verdict> {
+    let builder = AgentBuilder::new().llm(llm).tool(Box::new(SubmitVerdict));
+    let mut agent = ToolSelection {
+        web_search: true,
+        memory: false,
+        delegate: false,
+    }
+    .apply(builder, &web, config)?
+    .callback(callbacks::MessageLogger::new("verifier", file)?)
+    .stop_condition(Box::new(VerdictSubmitted))
+    .build()?;
+
+    let mut history = agent
+        .run(
+            vec![
+                Arc::new(Message::System(system_prompt)),
+                Arc::new(Message::User(claim.prompt())),
+            ],
+            &cancel,
+        )
+        .await?;
+
+    match history.pop().as_deref() {
+        Some(Message::Tool { name, result, .. }) if name == "submit_verdict" => {
+            result.parse().map(Verdict::clamped)
+        }
+        _ => Err(Error::AgentWorkflowError(
+            "verifier terminated without submitting a verdict".to_string(),
+        )),
+    }
+}
+
+fn annotate(report: &str, results: &[(String, Verdict)]) -> String {
+    let mut annotated = report.to_string();
+
+    for (i, (claim, verdict)) in results.iter().enumerate() {
+        if let Some(pos) = annotated.find(claim.as_str()) {
+            let marker = format!(" [claim {}: {}]", i + 1, verdict.label());
+            annotated.insert_str(pos + claim.len(), &marker);
+        }
+    }
+
+    annotated.push_str("\n\n## Claim Verification\n\n");
+    annotated.push_str("| # | Claim | Status | Confidence | Sources | Notes |\n");
+    annotated.push_str("|---|---|---|---|---|---|\n");
+    for (i, (claim, verdict)) in results.iter().enumerate() {
+        annotated.push_str(&format!(
+            "| {} | {} | {} | {:.2} | {} | {} |\n",
+            i + 1,
+            escape_cell(claim),
+            match verdict.status {
+                Status::Supported => "supported",
+                Status::Contradicted => "**contradicted**",
+                Status::Unverified => "**unverified**",
+            },
+            verdict.confidence,
+            escape_cell(&verdict.sources.join(", ")),
+            escape_cell(&verdict.explanation),
+        ));
+    }
+
+    annotated
+}
+
+fn escape_cell(s: &str) -> String {
+    s.replace('|', "\\|").replace('\n', " ")
+}
+
+struct VerdictSubmitted;
+
+impl StopCondition for VerdictSubmitted {
+    fn done(&self, history: &dyn History) -> bool {
+        if let Some(Message::Tool { name, .. }) = history.last().map(|m| m.as_ref()) {
+            return name == "submit_verdict";
+        }
+        false
+    }
+}
+
+struct SubmitVerdict;
+
+#[async_trait]
+impl tools::FunctionalTool for SubmitVerdict {
+    fn definition(&self) -> Result<tools::ToolDefinition> {
+        tools::ToolDefinition::new::<Verdict>(
+            "submit_verdict",
+            "This tool submits your verdict for the claim you were asked to verify. You must use this tool once you have finished checking the claim.",
+        )
+    }
+
+    async fn invoke_fn(
+        &mut self,
+        call: &tools::ToolCall,
+        _: &tools::ToolContext,
+    ) -> Result<Message> {
+        let verdict: Verdict = call.args()?;
+
+        Ok(Message::Tool {
+            id: call.id.clone(),
+            name: "submit_verdict".to_string(),
+            result: llm::ToolResult::json(&verdict)?,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Claim, Status, Verdict, annotate, parse_claims};
+
+    #[test]
+    fn test_parse_claims() {
+        let claims = parse_claims(
+            "```json\n[{\"claim\": \"a\", \"sources\": [\"https://example.com\"]}, {\"claim\": \"b\"}]\n```",
+        )
+        .unwrap();
+        assert_eq!(
+            claims,
+            vec![
+                Claim {
+                    claim: "a".to_string(),
+                    sources: vec!["https://example.com".to_string()],
+                },
+                Claim {
+                    claim: "b".to_string(),
+                    sources: vec![],
+                },
+            ]
+        );
+        assert_eq!(
+            claims[0].prompt(),
+            "<claim>\na\n</claim>\n\n<cited_sources>\nhttps://example.com\n</cited_sources>"
+        );
+        assert_eq!(claims[1].prompt(), "<claim>\nb\n</claim>");
+        assert!(parse_claims("not json").is_err());
+    }
+
+    #[test]
+    fn test_clamped() {
+        let verdict = |confidence| Verdict {
+            status: Status::Supported,
+            confidence,
+            sources: vec![],
+            explanation: String::new(),
+        };
+        assert_eq!(verdict(1.7).clamped().confidence, 1.0);
+        assert_eq!(verdict(-0.2).clamped().confidence, 0.0);
+        assert_eq!(verdict(f64::NAN).clamped().confidence, 0.0);
+        assert_eq!(verdict(0.4).clamped().confidence, 0.4);
+    }
+
+    #[test]
+    fn test_annotate() {
+        let report = "Paris is the capital of France. The Seine is 2000 km long.";
+        let results = vec![
+            (
+                "Paris is the capital of France.".to_string(),
+                Verdict {
+                    status: Status::Supported,
+                    confidence: 0.99,
+                    sources: vec!["https://example.com".to_string()],
+                    explanation: "well known".to_string(),
+                },
+            ),
+            (
+                "The Seine is 2000 km long.".to_string(),
+                Verdict {
+                    status: Status::Unverified,
+                    confidence: 0.2,
+                    sources: vec![],
+                    explanation: "no | sources".to_string(),
+                },
+            ),
+        ];
+
+        let annotated = annotate(report, &results);
+
+        assert!(annotated.starts_with(
+            "Paris is the capital of France. [claim 1: confidence 0.99] The Seine is 2000 km long. [claim 2: unverified]"
+        ));
+        assert!(annotated.contains(
+            "| 1 | Paris is the capital of France. | supported | 0.99 | https://example.com | well known |"
+        ));
+        assert!(annotated.contains(
+            "| 2 | The Seine is 2000 km long. | **unverified** | 0.20 |  | no \\| sources |"
+        ));
+    }
+}
@@ -0,0 +1,17 @@
+use std::io::Write;
+use std::path::Path;
+
+const WARNINGS_FILE: &str = "warnings.log";
+
+// reports a problem that does not fail the run, such as a fallback to a weaker path or a cleanup
+// that did not work, on stderr and in the warnings log of the log directory
+pub(crate) fn warn(log_dir: &Path, message: &str) {
+    eprintln!("warning: {}", message);
+    let file = std::fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(log_dir.join(WARNINGS_FILE));
+    if let Ok(mut file) = file {
+        let _ = writeln!(file, "{} {}", chrono::Local::now().to_rfc3339(), message);
+    }
+}
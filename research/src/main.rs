@@ -1,33 +1,1071 @@
-mod research;
-use agent::Result;
+mod tail;
+use agent::callbacks;
+use agent::llm::{
+    Coalescing, GenerationParams, LLM, OpenAICompatible, OpenAIEmbeddings, Pricing, RoutingLLM,
+    StepKind, TokenUsage,
+};
+use agent::secrets::{self, Secrets, SecretsConfig};
+use agent::tools::{
+    GraphQL, GraphQLConfig, HttpClientConfig, OpenApi, OpenApiConfig, Reranker, SandboxBackend,
+    SandboxConfig, SearchApi, SearchApiKind, SearchProvider, SearchQuotas, VectorMemory, WebPolicy,
+    WebSearch,
+};
+use agent::workdir::Cleanup;
+use agent::{Error, Result};
+use research_core::{
+    OrchestratorBuilder, budget, compare, config, contract, debate, diff, export, index, inputs,
+    interview, presets, resume,
+};
 
-use clap::Parser;
+use clap::{CommandFactory, Parser};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio_util::sync::CancellationToken;
+
+// openai rejects requests with more stop sequences
+const MAX_STOP_SEQUENCES: usize = 4;
 
 #[derive(Parser, Debug)]
-#[command(author, version, about, long_about = None)]
+#[command(author, version, about, long_about = None, subcommand_negates_reqs = true)]
 struct Args {
+    #[command(subcommand)]
+    command: Option<Command>,
+
     /// The research task
-    #[arg(short, long)]
-    task: String,
+    #[arg(short, long, required = true)]
+    task: Option<String>,
 
     /// Name of the model to use
-    #[arg(short, long)]
-    model: String,
+    #[arg(short, long, required = true)]
+    model: Option<String>,
+
+    /// Cheaper model of the same provider for summaries and extraction, the main model still plans and uses the tools
+    #[arg(long)]
+    cheap_model: Option<String>,
+
+    /// Long-context model of the same provider that writes the final report from all findings, facts, and sources once the orchestrator has finalized its draft
+    #[arg(long)]
+    synthesis_model: Option<String>,
+
+    /// Write the final report from an outline, drafting every section in its own request with only the findings, facts, and document passages it draws on, then smoothing the stitched sections; uses --synthesis-model when given
+    #[arg(long)]
+    outline_report: bool,
+
+    /// Provider serving the model
+    #[arg(long, value_enum, default_value = "openai")]
+    provider: Provider,
 
     /// Directory to store logs in
     #[arg(short, long, default_value = "./agent_logs")]
     log_dir: String,
+
+    /// Language the research report should be written in, e.g. "en" or "de"
+    #[arg(long, default_value = "en")]
+    language: String,
+
+    /// Tools the orchestrator may use, comma separated; by default it only plans and delegates with memory
+    #[arg(long, value_enum, value_delimiter = ',')]
+    orchestrator_tools: Option<Vec<presets::ToolGroup>>,
+
+    /// Tools the sub-agents may use, comma separated; by default they browse with memory, they can never delegate
+    #[arg(long, value_enum, value_delimiter = ',')]
+    subagent_tools: Option<Vec<presets::ToolGroup>>,
+
+    /// Preset bundling a research persona, report format, and the tools it needs: academic-reviewer adds the paper search, market-analyst and due-diligence the news search
+    #[arg(long, value_enum)]
+    persona: Option<presets::Persona>,
+
+    /// Ask a few questions about the scope, time horizon, and output format of the task on the terminal before the research starts
+    #[arg(long)]
+    interview: bool,
+
+    /// Verify the claims in the final report and annotate them with confidence scores
+    #[arg(long)]
+    verify: bool,
+
+    /// Deliver sub-agent results to the orchestrator as soon as they finish instead of only via wait_for_subagent
+    #[arg(long)]
+    stream_results: bool,
+
+    /// Maximum number of seconds a sub-agent may run before it is retried or reported as failed
+    #[arg(long)]
+    subagent_timeout_secs: Option<u64>,
+
+    /// Write a draft of the report from the findings so far to partial_report.md after every this many completed sub-agent tasks
+    #[arg(long, value_parser = clap::value_parser!(u64).range(1..))]
+    partial_report_every: Option<u64>,
+
+    /// Maximum number of sub-agents running at once, further sub-agents wait in a queue ordered by their priority
+    #[arg(long, value_parser = clap::value_parser!(u64).range(1..))]
+    max_concurrent_subagents: Option<u64>,
+
+    /// Raise and lower the number of concurrent sub-agents with the rate limit errors and latency of the model, up to --max-concurrent-subagents
+    #[arg(long)]
+    adaptive_concurrency: bool,
+
+    /// Write a per-agent breakdown of step time into model, tool, callback, and checkpoint time to <agent>.profile.folded in the log directory, in the folded stack format flamegraph tools read
+    #[arg(long)]
+    profile: bool,
+
+    /// Rotate the log files of the orchestrator and the sub-agents once they grow past this many megabytes
+    #[arg(long, value_parser = clap::value_parser!(u64).range(1..))]
+    log_max_mb: Option<u64>,
+
+    /// Number of rotated segments kept of each log file, older ones are deleted
+    #[arg(long, default_value_t = 5)]
+    log_max_files: usize,
+
+    /// Compress rotated log segments with gzip
+    #[arg(long, requires = "log_max_mb")]
+    log_gzip: bool,
+
+    /// Directory the working directory of the run is created in, for downloads and other files the tools create; defaults to the system temp directory
+    #[arg(long)]
+    work_root: Option<std::path::PathBuf>,
+
+    /// When to keep the working directory of the run instead of deleting it at the end, a failed run keeps it by default so that it can be resumed
+    #[arg(long, value_enum, default_value = "on-failure")]
+    keep_work_dir: KeepWorkDir,
+
+    /// Maximum number of seconds a single request to the model may take
+    #[arg(long)]
+    request_timeout_secs: Option<u64>,
+
+    /// Maximum number of seconds a single sub-agent step, a model request plus its tool calls, may take
+    #[arg(long)]
+    step_timeout_secs: Option<u64>,
+
+    /// Bias added to a token of the model before sampling, as TOKEN_ID=BIAS with a bias from -100 to 100, e.g. -100 to ban the first token of boilerplate phrases, in every request of every model of the run, the steps of the agents included; may be repeated
+    #[arg(long = "logit-bias", value_parser = logit_bias)]
+    logit_biases: Vec<(u32, i32)>,
+
+    /// Sequence at which the model stops generating, in every request of every model of the run, the steps of the agents included; may be given up to 4 times
+    #[arg(long = "stop")]
+    stop_sequences: Vec<String>,
+
+    /// Price of the model in USD per million prompt tokens, used to estimate the cost of the run
+    #[arg(long)]
+    prompt_price: Option<f64>,
+
+    /// Price of the model in USD per million completion tokens, used to estimate the cost of the run
+    #[arg(long)]
+    completion_price: Option<f64>,
+
+    /// Maximum number of tokens the whole run may use, the plan is sized to fit and no sub-agents are started once it is spent
+    #[arg(long)]
+    max_tokens: Option<u64>,
+
+    /// Maximum cost of the whole run in USD, requires --prompt-price or --completion-price
+    #[arg(long)]
+    max_cost: Option<f64>,
+
+    /// Approximate number of words the final report should have
+    #[arg(long)]
+    target_words: Option<usize>,
+
+    /// Section heading the final report must contain, can be repeated
+    #[arg(long = "section")]
+    sections: Vec<String>,
+
+    /// How the final report cites its sources
+    #[arg(long, value_enum)]
+    citation_style: Option<contract::CitationStyle>,
+
+    /// Also write the final report as report.docx or report.pdf to the log directory next to report.md instead of printing it, can be repeated; pdf needs the typst command line tool
+    #[arg(long, value_enum)]
+    export: Vec<export::ExportFormat>,
+
+    /// JSON file with the layout of the exported reports: title, subtitle, author, date, title_page, toc, toc_title, bibliography, bibliography_title, font, and font_size
+    #[arg(long)]
+    export_template: Option<std::path::PathBuf>,
+
+    /// Document the research should build on, such as a PDF or Markdown file, can be repeated
+    #[arg(long = "input")]
+    inputs: Vec<std::path::PathBuf>,
+
+    /// Directory of PDF, Markdown, and text files, or an index built with `research index`, to answer from; disables all web tools
+    #[arg(long, conflicts_with = "verify")]
+    corpus: Option<std::path::PathBuf>,
+
+    /// Directory with orchestrator.md and subagent.md prompt templates that replace the built-in prompts and are re-read at every agent step, so edits apply to the running research
+    #[arg(long)]
+    prompt_dir: Option<std::path::PathBuf>,
+
+    /// Provider of the embedding model used to search the input documents and rank search results; defaults to mistral with --provider mistral and to openai otherwise, since the other providers serve no embeddings
+    #[arg(long, value_enum)]
+    embedding_provider: Option<EmbeddingProvider>,
+
+    /// Name of the embedding model, defaults to text-embedding-3-small for openai and mistral-embed for mistral
+    #[arg(long)]
+    embedding_model: Option<String>,
+
+    /// Domain the web tools may access, can be repeated; when set, only these domains and their subdomains are accessed and the provider's web search is disabled
+    #[arg(long = "allow-domain")]
+    allowed_domains: Vec<String>,
+
+    /// Domain the web tools may never access, can be repeated; disables the provider's web search
+    #[arg(long = "block-domain")]
+    blocked_domains: Vec<String>,
+
+    /// Maximum number of pages fetched from a single domain during the run
+    #[arg(long)]
+    max_requests_per_domain: Option<usize>,
+
+    /// News API that agents with web access can search by publication date and outlet; GDELT only covers the last three months, and NewsAPI falls back to GDELT when NEWSAPI_API_KEY is not set
+    #[arg(long, value_enum)]
+    news: Option<presets::NewsSource>,
+
+    /// Give agents with web access tools for stock quotes, company fundamentals, and SEC EDGAR filings; EDGAR requires a contact, so SEC_USER_AGENT must hold a name and an email address, e.g. "Jane Doe jane@example.org"
+    #[arg(long)]
+    finance: bool,
+
+    /// Give agents with web access tools to look up papers and their citations and references, always on for the academic-reviewer persona
+    #[arg(long)]
+    scholar: bool,
+
+    /// Give agents with web access a tool that translates non-English sources into the report language, using the cheap model when one is set
+    #[arg(long)]
+    translate: bool,
+
+    /// Give agents with web access tools that read the metadata and transcripts of YouTube videos
+    #[arg(long)]
+    youtube: bool,
+
+    /// JSON file naming an OpenAPI spec whose operations agents with web access can call as tools, with the operations to expose and the credential they authenticate with; can be repeated
+    #[arg(long = "openapi")]
+    openapi: Vec<std::path::PathBuf>,
+
+    /// JSON file naming a GraphQL endpoint that agents with web access can query, with the credential it authenticates with; the schema is read by introspection at startup; can be repeated
+    #[arg(long = "graphql")]
+    graphql: Vec<std::path::PathBuf>,
+
+    /// JSON file mapping the names of the credentials of --openapi, --graphql, and --search-api, and NEWSAPI_API_KEY, SEMANTIC_SCHOLAR_API_KEY, and SEC_USER_AGENT, to environment variables, keyring entries, or entries of an encrypted secrets file; names it does not list are read from the environment variable of the same name
+    #[arg(long)]
+    secrets: Option<std::path::PathBuf>,
+
+    /// Give agents with web access a headless browser for pages that need javascript, requires the browser feature and node.js with playwright
+    #[arg(long)]
+    browser: bool,
+
+    /// Vision capable model that describes screenshots of pages whose content is in charts or images, gives the browser a browser_describe tool
+    #[arg(long, requires = "browser")]
+    vision_model: Option<String>,
+
+    /// Give agents with web access run_shell and run_python tools that run code in a sandbox with the working directory of the run
+    #[arg(long)]
+    sandbox: bool,
+
+    /// How the sandbox isolates code: a container, which needs docker or a compatible runtime, firejail, or a process restricted with resource limits, landlock, and a seccomp filter, which needs neither but linux 5.13 or later and can only read the system directories
+    #[arg(long, value_enum, default_value = "container", requires = "sandbox")]
+    sandbox_backend: SandboxKind,
+
+    /// Backend of a single tool as TOOL=BACKEND, e.g. run_python=process, can be repeated
+    #[arg(long = "sandbox-tool-backend", value_parser = tool_backend, requires = "sandbox")]
+    sandbox_tool_backends: Vec<(String, SandboxKind)>,
+
+    /// Directory besides the system ones that code of the process backend may read and run programs from, such as a python installation in a home directory, can be repeated
+    #[arg(long = "sandbox-read-path", requires = "sandbox")]
+    sandbox_read_paths: Vec<std::path::PathBuf>,
+
+    /// Container runtime the sandbox runs with, such as docker or podman
+    #[arg(long, default_value = "docker", requires = "sandbox")]
+    sandbox_runtime: String,
+
+    /// Image the sandbox runs code in, run_python needs python3 in it
+    #[arg(long, default_value = "python:3.12-slim", requires = "sandbox")]
+    sandbox_image: String,
+
+    /// Let the code in the sandbox access the network
+    #[arg(long, requires = "sandbox")]
+    sandbox_network: bool,
+
+    /// Number of cpus a command in the sandbox may use
+    #[arg(long, default_value_t = 1.0, requires = "sandbox")]
+    sandbox_cpus: f64,
+
+    /// Megabytes of memory a command in the sandbox may use
+    #[arg(long, default_value_t = 512, requires = "sandbox")]
+    sandbox_memory_mb: u64,
+
+    /// Megabytes the files in the working directory may take before commands in the sandbox can no longer write files
+    #[arg(long, default_value_t = 1024, requires = "sandbox")]
+    sandbox_disk_mb: u64,
+
+    /// Maximum number of seconds a command in the sandbox may run
+    #[arg(long, default_value_t = 60, requires = "sandbox")]
+    sandbox_timeout_secs: u64,
+
+    /// Search api of the web_search tool, which then replaces the search of the model provider; the key is read from the credential TAVILY_API_KEY, BRAVE_API_KEY, or SERPER_API_KEY. Can be repeated, the tool rotates among the apis and falls back to the others when one fails
+    #[arg(long = "search", value_enum)]
+    search_apis: Vec<SearchKind>,
+
+    /// Searches a search api may be used for per day as PROVIDER=N, e.g. brave=100, can be repeated; defaults to about a thirtieth of the monthly free plan
+    #[arg(long = "search-quota", value_parser = search_quota)]
+    search_quotas: Vec<(SearchKind, u64)>,
+
+    /// File the searches of the day are counted in across runs, defaults to search_quota.json in the log directory
+    #[arg(long)]
+    search_quota_file: Option<std::path::PathBuf>,
+
+    /// Rank the results of web_search by their relevance to the task of the agent before the model sees them, by the similarity of their embeddings with --embedding-model or by ratings of the cheap model
+    #[arg(long, value_enum)]
+    search_rerank: Option<RerankKind>,
+
+    /// Number of results web_search keeps after ranking them
+    #[arg(long, default_value_t = 5, requires = "search_rerank")]
+    search_rerank_keep: usize,
+
+    /// Only accept sources published on or after this date, e.g. 2024-01-01: searches of the --search apis and --news are restricted to it, the agents are told to discard older sources, and cited sources that seem older are flagged; the search of the model provider cannot be restricted
+    #[arg(long)]
+    since: Option<chrono::NaiveDate>,
+
+    /// Fit every request of the agents into this many tokens instead of sending the whole history, with 4096 tokens kept for the reply and room for the tool definitions: the system prompt, the task, and the latest turn are always sent, then the run status and todo list, the recent turns, notes of other agents, older turns, and passages recalled from the documents
+    #[arg(long)]
+    context_budget: Option<usize>,
+
+    /// Number of passages of the --corpus or --input documents recalled for the latest step of every agent with --context-budget, 0 to recall none
+    #[arg(long, default_value_t = 3, requires = "context_budget")]
+    recall_passages: usize,
+
+    /// Sample this many candidates of the synthesis steps, the partial reports, the judgement of the debate command and the table of the compare command, and keep the best of them
+    #[arg(long)]
+    best_of: Option<usize>,
+
+    /// How the best of the --best-of candidates is picked
+    #[arg(long, value_enum, default_value = "sources", requires = "best_of")]
+    best_of_select: presets::Selection,
+
+    /// Scope web and news searches to a country, by its two letter code such as de or gb: providers are asked for results from the country, results from its domains are listed first, and the agents are told to prefer its sources
+    #[arg(long, value_parser = two_letter_code)]
+    region: Option<String>,
+
+    /// Language of the search results for --region, by its two letter code such as de
+    #[arg(long, requires = "region", value_parser = two_letter_code)]
+    region_language: Option<String>,
+
+    /// Top level domain whose results are listed first for --region instead of the domain of the country, e.g. eu; may be repeated
+    #[arg(long = "region-tld", requires = "region")]
+    region_tlds: Vec<String>,
+
+    /// Skip pages that are marked as available to subscribers only
+    #[arg(long)]
+    respect_paywalls: bool,
+
+    /// Wrap fetched web content in untrusted blocks and remove text from it that tries to instruct the agents
+    #[arg(long)]
+    sanitize_web_content: bool,
+
+    /// Write the suspected prompt injections found in web content to injections.json in the log directory for review
+    #[arg(long, requires = "sanitize_web_content")]
+    flag_injections: bool,
+
+    /// User agent the web tools send with their requests
+    #[arg(long)]
+    user_agent: Option<String>,
+
+    /// Proxy for the requests of the web tools, e.g. http://proxy.example.com:8080; defaults to the HTTP_PROXY and HTTPS_PROXY environment variables
+    #[arg(long)]
+    proxy: Option<String>,
+
+    /// Time limit of a single request of the web tools, replacing the limits of the individual tools
+    #[arg(long)]
+    http_timeout_secs: Option<u64>,
+
+    /// Time limit of establishing a connection for a request of the web tools
+    #[arg(long)]
+    connect_timeout_secs: Option<u64>,
+
+    /// How often the web tools retry requests that failed to connect, timed out, or were rate limited
+    #[arg(long, default_value_t = 0)]
+    http_retries: u32,
+
+    /// PEM file with additional root certificates for the web tools, for proxies that inspect TLS traffic
+    #[arg(long)]
+    ca_cert: Option<std::path::PathBuf>,
+
+    /// Directory of a disk cache for the responses of the web tools, shared by later runs
+    #[arg(long)]
+    http_cache: Option<std::path::PathBuf>,
+
+    /// How long responses in the HTTP cache are served before they are fetched again
+    #[arg(long, default_value_t = 24 * 60 * 60, requires = "http_cache")]
+    http_cache_ttl_secs: u64,
+
+    /// Size limit of the HTTP cache in megabytes, the least recently used responses are removed beyond it
+    #[arg(long, default_value_t = 1024, requires = "http_cache")]
+    http_cache_max_mb: u64,
+}
+
+#[derive(clap::Subcommand, Debug)]
+enum Command {
+    /// Research several items in parallel and synthesize a comparison table
+    Compare {
+        /// Item to compare, can be repeated
+        #[arg(long = "item", required = true, num_args = 1)]
+        items: Vec<String>,
+
+        /// Aspect the items should be compared on, can be repeated
+        #[arg(long = "criterion")]
+        criteria: Vec<String>,
+    },
+    /// Let two agents debate the task question for several rounds and a judge synthesize the debate
+    Debate {
+        /// Position one of the debaters argues for, give it twice for the two sides; without it the moderator frames the positions
+        #[arg(long = "position")]
+        positions: Vec<String>,
+
+        /// Number of rounds of arguments and rebuttals
+        #[arg(long, default_value = "3", value_parser = clap::value_parser!(u64).range(1..))]
+        rounds: u64,
+    },
+    /// Align two saved runs of the same task step by step, show where they diverged, and explain why their outcomes differ
+    Diff {
+        /// Log directory of the first run
+        run_a: std::path::PathBuf,
+
+        /// Log directory of the second run
+        run_b: std::path::PathBuf,
+    },
+    /// Build or update a search index of a directory of documents for use with --corpus
+    Index {
+        /// Directory containing the PDF, Markdown, and text files to index
+        #[arg(long)]
+        dir: std::path::PathBuf,
+
+        /// Directory the index is written to, an existing index is updated incrementally
+        #[arg(long)]
+        out: std::path::PathBuf,
+    },
+    /// Continue a crashed or cancelled research run from its log directory, with the same model and options as the original run
+    Resume {
+        /// Log directory of the run to resume
+        #[arg(long)]
+        dir: std::path::PathBuf,
+    },
+    /// Store a credential in an encrypted secrets file, reading its value from stdin and the passphrase of the file from an environment variable
+    Secret {
+        /// Name of the credential
+        name: String,
+
+        /// Secrets file to add the credential to, created if it does not exist
+        #[arg(long)]
+        file: std::path::PathBuf,
+
+        /// Environment variable holding the passphrase of the file
+        #[arg(long, default_value = secrets::DEFAULT_KEY_ENV)]
+        key_env: String,
+    },
+    /// Follow the agents of a run in the terminal while it is in progress
+    Tail {
+        /// Log directory of the run to follow
+        #[arg(long, default_value = "./agent_logs")]
+        dir: std::path::PathBuf,
+    },
+}
+
+#[derive(clap::ValueEnum, Clone, Copy, Debug)]
+enum Provider {
+    Openai,
+    Xai,
+    Mistral,
+    Deepseek,
+    Groq,
+}
+
+#[derive(clap::ValueEnum, Clone, Copy, Debug)]
+enum EmbeddingProvider {
+    Openai,
+    Mistral,
+}
+
+impl Args {
+    // the embeddings of the documents, the index, and the ranking of search results, and the name
+    // of their model that indexes are built with
+    fn embeddings(&self) -> (Arc<OpenAIEmbeddings>, String) {
+        let provider = self.embedding_provider.unwrap_or(match self.provider {
+            Provider::Mistral => EmbeddingProvider::Mistral,
+            _ => EmbeddingProvider::Openai,
+        });
+        match provider {
+            EmbeddingProvider::Openai => {
+                let model = self
+                    .embedding_model
+                    .clone()
+                    .unwrap_or_else(|| "text-embedding-3-small".to_string());
+                (OpenAIEmbeddings::new(model.clone()), model)
+            }
+            EmbeddingProvider::Mistral => {
+                let model = self
+                    .embedding_model
+                    .clone()
+                    .unwrap_or_else(|| "mistral-embed".to_string());
+                (OpenAIEmbeddings::mistral(model.clone()), model)
+            }
+        }
+    }
+}
+
+#[derive(clap::ValueEnum, Clone, Copy, Debug)]
+enum KeepWorkDir {
+    Never,
+    Always,
+    OnFailure,
+}
+
+#[derive(clap::ValueEnum, Clone, Copy, Debug)]
+enum SandboxKind {
+    Container,
+    Firejail,
+    Process,
+}
+
+impl SandboxKind {
+    fn backend(self) -> SandboxBackend {
+        match self {
+            SandboxKind::Container => SandboxBackend::Container,
+            SandboxKind::Firejail => SandboxBackend::Firejail,
+            SandboxKind::Process => SandboxBackend::Process,
+        }
+    }
+}
+
+#[derive(clap::ValueEnum, Clone, Copy, Debug, PartialEq)]
+enum SearchKind {
+    Tavily,
+    Brave,
+    Serper,
+}
+
+impl SearchKind {
+    fn api(self) -> SearchApiKind {
+        match self {
+            SearchKind::Tavily => SearchApiKind::Tavily,
+            SearchKind::Brave => SearchApiKind::Brave,
+            SearchKind::Serper => SearchApiKind::Serper,
+        }
+    }
+}
+
+#[derive(clap::ValueEnum, Clone, Copy, Debug)]
+enum RerankKind {
+    Embeddings,
+    Model,
+}
+
+fn search_quota(arg: &str) -> std::result::Result<(SearchKind, u64), String> {
+    let (provider, quota) = arg
+        .split_once('=')
+        .ok_or_else(|| format!("expected PROVIDER=N, got {}", arg))?;
+    Ok((
+        <SearchKind as clap::ValueEnum>::from_str(provider, true)?,
+        quota
+            .parse()
+            .map_err(|_| format!("{} is not a number of searches", quota))?,
+    ))
+}
+
+// ISO 3166-1 alpha-2 country and ISO 639-1 language codes, in the lowercase the search apis take
+fn two_letter_code(arg: &str) -> std::result::Result<String, String> {
+    if arg.len() == 2 && arg.chars().all(|c| c.is_ascii_alphabetic()) {
+        Ok(arg.to_ascii_lowercase())
+    } else {
+        Err(format!("{} is not a two letter code such as de", arg))
+    }
+}
+
+fn logit_bias(arg: &str) -> std::result::Result<(u32, i32), String> {
+    let (token, bias) = arg
+        .split_once('=')
+        .ok_or_else(|| format!("expected TOKEN_ID=BIAS, got {}", arg))?;
+    let token = token
+        .parse()
+        .map_err(|_| format!("{} is not a token id", token))?;
+    match bias.parse() {
+        Ok(bias) if (-100..=100).contains(&bias) => Ok((token, bias)),
+        _ => Err(format!("{} is not a bias from -100 to 100", bias)),
+    }
+}
+
+fn tool_backend(arg: &str) -> std::result::Result<(String, SandboxKind), String> {
+    let (tool, backend) = arg
+        .split_once('=')
+        .ok_or_else(|| format!("expected TOOL=BACKEND, got {}", arg))?;
+    Ok((
+        tool.to_string(),
+        <SandboxKind as clap::ValueEnum>::from_str(backend, true)?,
+    ))
+}
+
+// the commands without a manifest that would record their usage report it on stderr
+fn print_usage(command: &str, usage: TokenUsage, pricing: Option<Pricing>) {
+    match pricing {
+        Some(pricing) => eprintln!(
+            "{} used {} tokens, about ${:.2}",
+            command,
+            usage.total_tokens(),
+            usage.cost(&pricing)
+        ),
+        None => eprintln!("{} used {} tokens", command, usage.total_tokens()),
+    }
 }
 
 #[tokio::main]
 async fn main() -> Result<()> {
-    let args = Args::parse();
+    let mut args = Args::parse();
+    let mut command_line = std::env::args().collect::<Vec<_>>();
+    // a resumed run is built from the command line it was started with rather than the options
+    // given to resume, runs saved without one use those
+    if let Some(Command::Resume { dir }) = &args.command {
+        let run = resume::RunState::load(dir)?;
+        if !run.args.is_empty() {
+            let dir = dir.clone();
+            args = Args::parse_from(&run.args);
+            args.command = Some(Command::Resume { dir });
+            command_line = run.args;
+        }
+    }
+    if args.stop_sequences.len() > MAX_STOP_SEQUENCES {
+        Args::command()
+            .error(
+                clap::error::ErrorKind::TooManyValues,
+                format!(
+                    "--stop may be given at most {} times, it was given {} times",
+                    MAX_STOP_SEQUENCES,
+                    args.stop_sequences.len()
+                ),
+            )
+            .exit();
+    }
+
+    // ctrl-c stops the orchestrator and every sub-agent it started instead of killing the
+    // process in the middle of writing the logs
+    let cancel = CancellationToken::new();
+    let on_interrupt = cancel.clone();
+    tokio::spawn(async move {
+        if tokio::signal::ctrl_c().await.is_ok() {
+            on_interrupt.cancel();
+        }
+    });
+
+    if let Some(Command::Tail { dir }) = &args.command {
+        return tail::tail(dir, &cancel).await;
+    }
+    if let Some(Command::Secret {
+        name,
+        file,
+        key_env,
+    }) = &args.command
+    {
+        let passphrase = std::env::var(key_env).map_err(|_| {
+            Error::MissingArg(format!(
+                "set {} to the passphrase of the secrets file",
+                key_env
+            ))
+        })?;
+        let mut entries = if file.exists() {
+            secrets::decrypt(file, &passphrase)?
+        } else {
+            Default::default()
+        };
+        let mut value = String::new();
+        std::io::stdin().read_line(&mut value)?;
+        entries.insert(
+            name.clone(),
+            value.trim_end_matches(['\r', '\n']).to_string(),
+        );
+        secrets::encrypt(file, &passphrase, &entries)?;
+        println!("stored {} in {}", name, file.display());
+        return Ok(());
+    }
+    let (embeddings, embedding_model) = args.embeddings();
+    if let Some(Command::Index { dir, out }) = &args.command {
+        let stats = index::build(dir, out, embeddings.clone(), &embedding_model).await?;
+        println!(
+            "indexed {}: {} added, {} updated, {} removed, {} unchanged",
+            dir.display(),
+            stats.added,
+            stats.updated,
+            stats.removed,
+            stats.unchanged
+        );
+        return Ok(());
+    }
+
+    let model = args
+        .model
+        .ok_or(Error::MissingArg("--model is required".to_string()))?;
+    for format in &args.export {
+        format.check()?;
+    }
+    let template = match &args.export_template {
+        Some(path) => export::Template::load(path)?,
+        None => export::Template::default(),
+    };
+    let provider = |model: &str| {
+        let mut provider = match args.provider {
+            Provider::Openai => OpenAICompatible::openai(model.to_string()),
+            Provider::Xai => OpenAICompatible::xai(model.to_string()),
+            Provider::Mistral => OpenAICompatible::mistral(model.to_string()),
+            Provider::Deepseek => OpenAICompatible::deepseek(model.to_string()),
+            Provider::Groq => OpenAICompatible::groq(model.to_string()),
+        };
+        if let Some(secs) = args.request_timeout_secs {
+            provider = provider.timeout(Duration::from_secs(secs));
+        }
+        provider
+            .params(GenerationParams {
+                logit_bias: args.logit_biases.iter().copied().collect(),
+                stop: args.stop_sequences.clone(),
+            })
+            .build()
+    };
+    let llm: Arc<dyn LLM + Send + Sync> = match &args.cheap_model {
+        // requests without tools are summaries and extractions, which a cheaper model handles
+        Some(cheap_model) => RoutingLLM::builder(provider(&model))
+            .route(
+                |profile| profile.kind == StepKind::Extract,
+                provider(cheap_model),
+            )
+            .build(),
+        None => provider(&model),
+    };
+    let llm = Coalescing::new(llm);
+    // translations never see the research history, so they go to the cheap model when there is one
+    let translator = args.translate.then(|| -> Arc<dyn LLM + Send + Sync> {
+        provider(args.cheap_model.as_deref().unwrap_or(&model))
+    });
+    let synthesizer = args
+        .synthesis_model
+        .as_deref()
+        .map(|model| -> Arc<dyn LLM + Send + Sync> { provider(model) });
+    let vision = args
+        .vision_model
+        .as_deref()
+        .map(|model| -> Arc<dyn LLM + Send + Sync> { provider(model) });
+
+    let pricing =
+        (args.prompt_price.is_some() || args.completion_price.is_some()).then(|| Pricing {
+            prompt: args.prompt_price.unwrap_or_default(),
+            completion: args.completion_price.unwrap_or_default(),
+        });
+    let http = HttpClientConfig {
+        user_agent: args.user_agent,
+        proxy: args.proxy,
+        timeout: args.http_timeout_secs.map(Duration::from_secs),
+        connect_timeout: args.connect_timeout_secs.map(Duration::from_secs),
+        retries: args.http_retries,
+        ca_cert: args.ca_cert,
+        cache_dir: args.http_cache,
+        cache_ttl: Some(Duration::from_secs(args.http_cache_ttl_secs)),
+        cache_max_bytes: Some(args.http_cache_max_mb * 1024 * 1024),
+    };
+    // bad proxies and certificates are reported before the run starts instead of by the first
+    // agent that builds its tools
+    http.client(Duration::from_secs(30))?;
+    let secrets = match &args.secrets {
+        Some(path) => Secrets::load(SecretsConfig::read(path)?)?,
+        None => Secrets::new(),
+    };
+    let search = if args.search_apis.is_empty() {
+        None
+    } else {
+        let quotas = match &args.search_quota_file {
+            Some(path) => SearchQuotas::open(path)?,
+            None => {
+                std::fs::create_dir_all(&args.log_dir)?;
+                SearchQuotas::open(&std::path::Path::new(&args.log_dir).join("search_quota.json"))?
+            }
+        };
+        let mut providers: Vec<(Arc<dyn SearchProvider + Send + Sync>, u64)> = Vec::new();
+        for kind in &args.search_apis {
+            let api = kind.api();
+            let quota = args
+                .search_quotas
+                .iter()
+                .rev()
+                .find(|(provider, _)| provider == kind)
+                .map(|(_, quota)| *quota)
+                .unwrap_or(api.default_quota());
+            let key = secrets.get(api.credential())?;
+            providers.push((SearchApi::new(api, key, &http)?, quota));
+        }
+        let search = WebSearch::new(providers, quotas);
+        Some(match args.search_rerank {
+            None => search,
+            Some(RerankKind::Embeddings) => search.rerank(
+                Reranker::Embeddings(embeddings.clone()),
+                args.search_rerank_keep,
+            ),
+            // rating snippets needs no research history, so it goes to the cheap model too
+            Some(RerankKind::Model) => search.rerank(
+                Reranker::Model(provider(args.cheap_model.as_deref().unwrap_or(&model))),
+                args.search_rerank_keep,
+            ),
+        })
+    };
+    if args.since.is_some() && search.is_none() && args.corpus.is_none() {
+        eprintln!(
+            "warning: --since cannot restrict the search of the model provider, only the agents are told to discard older sources; pass --search to restrict the searches"
+        );
+    }
+    // without the key news searches go to GDELT instead of failing every agent that builds its
+    // tools
+    let news = match args.news {
+        Some(presets::NewsSource::Newsapi) if secrets.get("NEWSAPI_API_KEY").is_err() => {
+            eprintln!(
+                "warning: the credential NEWSAPI_API_KEY is not set, news searches use GDELT instead of NewsAPI"
+            );
+            Some(presets::NewsSource::Gdelt)
+        }
+        news => news,
+    };
+    let mut openapi = Vec::new();
+    for path in &args.openapi {
+        openapi.push(OpenApi::load(OpenApiConfig::read(path)?, &http, &secrets).await?);
+    }
+    let mut graphql = Vec::new();
+    for path in &args.graphql {
+        graphql.push(GraphQL::load(GraphQLConfig::read(path)?, &http, &secrets).await?);
+    }
+
+    if args.browser && !cfg!(feature = "browser") {
+        return Err(Error::MissingArg(
+            "--browser requires building with the browser feature".to_string(),
+        ));
+    }
+    if args.max_cost.is_some() && pricing.is_none() {
+        return Err(Error::MissingArg(
+            "--max-cost requires --prompt-price or --completion-price".to_string(),
+        ));
+    }
+    let mut tool_policy = presets::ToolPolicy::default();
+    if let Some(tools) = &args.orchestrator_tools {
+        tool_policy.orchestrator = presets::ToolSelection::of(tools);
+    }
+    if let Some(tools) = &args.subagent_tools {
+        if tools.contains(&presets::ToolGroup::Delegate) {
+            return Err(Error::MissingArg(
+                "--subagent-tools cannot include delegate, sub-agents cannot start further sub-agents"
+                    .to_string(),
+            ));
+        }
+        tool_policy.subagent = presets::ToolSelection::of(tools);
+    }
+
+    let mut config = config::Config {
+        run_id: config::new_run_id(),
+        args: command_line,
+        model,
+        cheap_model: args.cheap_model,
+        language: args.language,
+        persona: args.persona,
+        verify: args.verify,
+        stream_subagent_results: args.stream_results,
+        subagent_timeout: args.subagent_timeout_secs.map(Duration::from_secs),
+        partial_report_every: args.partial_report_every.map(|every| every as usize),
+        max_concurrent_subagents: args.max_concurrent_subagents.map(|cap| cap as usize),
+        adaptive_concurrency: args.adaptive_concurrency,
+        step_timeout: args.step_timeout_secs.map(Duration::from_secs),
+        profile: args.profile,
+        log_rotation: args.log_max_mb.map(|mb| callbacks::Rotation {
+            max_bytes: mb * 1024 * 1024,
+            max_files: args.log_max_files,
+            compress: args.log_gzip,
+        }),
+        work_root: args.work_root,
+        keep_work_dir: match args.keep_work_dir {
+            KeepWorkDir::Never => Cleanup::Delete,
+            KeepWorkDir::Always => Cleanup::Keep,
+            KeepWorkDir::OnFailure => Cleanup::KeepOnFailure,
+        },
+        pricing,
+        budget: budget::Budget {
+            max_tokens: args.max_tokens,
+            max_cost: args.max_cost,
+        },
+        contract: contract::OutputContract {
+            target_words: args.target_words,
+            sections: args.sections,
+            citation_style: args.citation_style,
+        },
+        web_policy: WebPolicy {
+            allowed_domains: args.allowed_domains,
+            blocked_domains: args.blocked_domains,
+            max_requests_per_domain: args.max_requests_per_domain,
+            respect_paywalls: args.respect_paywalls,
+            sanitize_content: args.sanitize_web_content,
+            http,
+        },
+        flag_injections: args.flag_injections,
+        news,
+        finance: args.finance,
+        scholar: args.scholar,
+        youtube: args.youtube,
+        secrets,
+        openapi,
+        graphql,
+        browser: args.browser,
+        search,
+        sandbox: args.sandbox.then(|| SandboxConfig {
+            backend: args.sandbox_backend.backend(),
+            tool_backends: args
+                .sandbox_tool_backends
+                .iter()
+                .map(|(tool, backend)| (tool.clone(), backend.backend()))
+                .collect(),
+            runtime: args.sandbox_runtime,
+            image: args.sandbox_image,
+            network: args.sandbox_network,
+            cpus: args.sandbox_cpus,
+            memory_mb: args.sandbox_memory_mb,
+            disk_mb: args.sandbox_disk_mb,
+            timeout: Duration::from_secs(args.sandbox_timeout_secs),
+            read_paths: args.sandbox_read_paths,
+            ..Default::default()
+        }),
+        vision,
+        translator,
+        synthesizer,
+        outline_report: args.outline_report,
+        since: args.since,
+        region: args.region.map(|country| agent::tools::Region {
+            language: args.region_language,
+            tlds: args.region_tlds,
+            ..agent::tools::Region::new(&country)
+        }),
+        context_budget: args.context_budget,
+        recall_passages: args.recall_passages,
+        best_of: args.best_of.map(|n| (n, args.best_of_select)),
+        offline: args.corpus.is_some(),
+        tool_policy,
+        prompt_dir: args.prompt_dir,
+    };
 
-    let llm = agent::llm::OpenAI::new(args.model);
+    let documents = if args.inputs.is_empty() && args.corpus.is_none() {
+        None
+    } else {
+        let memory = match &args.corpus {
+            Some(corpus) if index::is_index(corpus) => {
+                index::load(corpus, embeddings, &embedding_model)?
+            }
+            Some(corpus) => {
+                let memory = VectorMemory::new(embeddings);
+                inputs::index_corpus(&memory, corpus).await?;
+                memory
+            }
+            None => VectorMemory::new(embeddings),
+        };
+        Some(inputs::ingest(llm.clone(), memory, &config, &args.inputs).await?)
+    };
 
-    let orchestrator = research::Orchestrator::new(llm, &std::path::Path::new(&args.log_dir))?;
+    let log_dir = std::path::Path::new(&args.log_dir);
+    // the report of the run and the directory it is exported to
+    let report = match args.command {
+        Some(Command::Index { .. } | Command::Secret { .. } | Command::Tail { .. }) => None,
+        Some(Command::Compare { items, criteria }) => {
+            let comparison = compare::compare(
+                llm,
+                log_dir,
+                config,
+                items,
+                criteria,
+                documents.as_ref(),
+                &cancel,
+            )
+            .await?;
+            Some((comparison, log_dir.to_path_buf()))
+        }
+        Some(Command::Debate { positions, rounds }) => {
+            let mut question = args
+                .task
+                .ok_or(Error::MissingArg("--task is required".to_string()))?;
+            if args.interview {
+                question = interview::clarify(
+                    llm.clone(),
+                    log_dir,
+                    &config,
+                    &question,
+                    interview::StdinRespondent::new().as_mut(),
+                    &cancel,
+                )
+                .await?;
+            }
+            let (synthesis, usage) = debate::debate(
+                llm,
+                log_dir,
+                config,
+                debate::Motion {
+                    question,
+                    positions,
+                },
+                rounds as usize,
+                documents.as_ref(),
+                &cancel,
+            )
+            .await?;
+            print_usage("the debate", usage, pricing);
+            Some((synthesis, log_dir.to_path_buf()))
+        }
+        None => {
+            let mut task = args
+                .task
+                .ok_or(Error::MissingArg("--task is required".to_string()))?;
+            if args.interview {
+                task = interview::clarify(
+                    llm.clone(),
+                    log_dir,
+                    &config,
+                    &task,
+                    interview::StdinRespondent::new().as_mut(),
+                    &cancel,
+                )
+                .await?;
+            }
+            if let Some(documents) = &documents {
+                task = documents.task_prompt(&task);
+            }
+            let mut builder = OrchestratorBuilder::new()
+                .llm(llm)
+                .log_dir(log_dir)
+                .config(config);
+            if let Some(documents) = documents {
+                builder = builder.documents(documents.memory);
+            }
+            Some((
+                builder.build()?.run(task, &cancel).await?,
+                log_dir.to_path_buf(),
+            ))
+        }
+        Some(Command::Diff { run_a, run_b }) => {
+            let (diff, usage) = diff::diff(llm, &config, &run_a, &run_b, &cancel).await?;
+            println!("{}", diff);
+            print_usage("the diff", usage, pricing);
+            None
+        }
+        Some(Command::Resume { dir }) => {
+            let run = resume::RunState::load(&dir)?;
+            config.run_id = run.run_id.clone();
+            let mut builder = OrchestratorBuilder::new()
+                .llm(llm)
+                .log_dir(&dir)
+                .config(config)
+                .resumed();
+            if let Some(documents) = documents {
+                builder = builder.documents(documents.memory);
+            }
+            Some((builder.build()?.resume(run, &cancel).await?, dir))
+        }
+    };
 
-    orchestrator.run(args.task).await?;
+    if let Some((report, dir)) = report {
+        if args.export.is_empty() {
+            println!("{}", report);
+        }
+        for format in &args.export {
+            let path = dir.join(format!("report.{}", format.extension()));
+            export::export(&report, *format, &template, &path)?;
+        }
+    }
 
     Ok(())
 }
@@ -0,0 +1,179 @@
+use agent::Result;
+use agent::callbacks::{Event, EventKind};
+use std::collections::{BTreeMap, HashMap};
+use std::io::{IsTerminal, Read, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use tokio_util::sync::CancellationToken;
+
+const EVENTS_SUFFIX: &str = ".events.jsonl";
+const POLL_INTERVAL: Duration = Duration::from_millis(500);
+
+// every event is rendered on a single line, longer texts are cut
+const MAX_TEXT: usize = 160;
+const AGENT_WIDTH: usize = 20;
+
+const COLORS: &[&str] = &[
+    "\x1b[36m", "\x1b[33m", "\x1b[35m", "\x1b[32m", "\x1b[34m", "\x1b[31m",
+];
+const RESET: &str = "\x1b[0m";
+const DIM: &str = "\x1b[2m";
+
+#[derive(Default)]
+struct EventFile {
+    offset: u64,
+    // the last line of the file if the agent has not finished writing it yet
+    partial: String,
+}
+
+impl EventFile {
+    fn read_new(&mut self, path: &Path) -> Result<Vec<Event>> {
+        let mut file = std::fs::File::open(path)?;
+        // a file that shrank was recreated by a new run in the same directory
+        if file.metadata()?.len() < self.offset {
+            *self = Self::default();
+        }
+        file.seek(SeekFrom::Start(self.offset))?;
+        let mut buf = String::new();
+        self.offset += file.read_to_string(&mut buf)? as u64;
+
+        self.partial.push_str(&buf);
+        let Some(end) = self.partial.rfind('\n') else {
+            return Ok(Vec::new());
+        };
+        let complete = self.partial[..end].to_string();
+        self.partial.drain(..=end);
+
+        Ok(complete
+            .lines()
+            .filter_map(|line| serde_json::from_str(line).ok())
+            .collect())
+    }
+}
+
+fn collect_event_files(dir: &Path, files: &mut Vec<PathBuf>) -> Result<()> {
+    for entry in std::fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.is_dir() {
+            collect_event_files(&path, files)?;
+        } else if path
+            .file_name()
+            .is_some_and(|name| name.to_string_lossy().ends_with(EVENTS_SUFFIX))
+        {
+            files.push(path);
+        }
+    }
+    Ok(())
+}
+
+fn one_line(text: &str) -> String {
+    let text = text.split_whitespace().collect::<Vec<_>>().join(" ");
+    match text.char_indices().nth(MAX_TEXT) {
+        Some((i, _)) => format!("{}...", &text[..i]),
+        None => text,
+    }
+}
+
+fn render(event: &Event, color: Option<&str>) -> String {
+    let time = chrono::DateTime::from_timestamp_millis(event.time_ms as i64)
+        .map(|time| {
+            time.with_timezone(&chrono::Local)
+                .format("%H:%M:%S")
+                .to_string()
+        })
+        .unwrap_or_default();
+    let tool = event.tool.as_deref().unwrap_or_default();
+    let text = match event.kind {
+        EventKind::System => format!("system: {}", one_line(&event.text)),
+        EventKind::User => format!("user: {}", one_line(&event.text)),
+        EventKind::Developer => format!("developer: {}", one_line(&event.text)),
+        EventKind::Assistant => one_line(&event.text),
+        EventKind::ToolCall => format!("-> {} {}", tool, one_line(&event.text)),
+        EventKind::ToolResult => format!("<- {} {}", tool, one_line(&event.text)),
+        EventKind::HistoryCleared if event.text.is_empty() => "[history cleared]".to_string(),
+        EventKind::HistoryCleared => format!("[history cleared: {}]", event.text),
+    };
+    let agent = format!("{:<width$}", event.agent, width = AGENT_WIDTH);
+
+    match color {
+        Some(color) => {
+            let text = match event.kind {
+                EventKind::ToolResult | EventKind::System | EventKind::Developer => {
+                    format!("{}{}{}", DIM, text, RESET)
+                }
+                _ => text,
+            };
+            format!("{} {}{}{} {}", time, color, agent, RESET, text)
+        }
+        None => format!("{} {} {}", time, agent, text),
+    }
+}
+
+// follows the event files of all agents below dir, including agents that start later, and
+// prints their events interleaved by time until cancelled
+pub async fn tail(dir: &Path, cancel: &CancellationToken) -> Result<()> {
+    let colorize = std::io::stdout().is_terminal();
+    let mut files: BTreeMap<PathBuf, EventFile> = BTreeMap::new();
+    let mut colors: HashMap<String, &str> = HashMap::new();
+
+    loop {
+        let mut paths = Vec::new();
+        if dir.is_dir() {
+            collect_event_files(dir, &mut paths)?;
+        }
+
+        let mut events = Vec::new();
+        for path in paths {
+            events.extend(files.entry(path.clone()).or_default().read_new(&path)?);
+        }
+        events.sort_by_key(|event| event.time_ms);
+
+        for event in &events {
+            let next = COLORS[colors.len() % COLORS.len()];
+            let color = *colors.entry(event.agent.clone()).or_insert(next);
+            println!("{}", render(event, colorize.then_some(color)));
+        }
+
+        tokio::select! {
+            _ = cancel.cancelled() => return Ok(()),
+            _ = tokio::time::sleep(POLL_INTERVAL) => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{EventFile, render};
+    use agent::callbacks::{Event, EventKind};
+    use std::io::Write;
+
+    #[test]
+    fn test_read_new_events() -> agent::Result<()> {
+        let path = std::env::temp_dir().join(format!("tail-{}.events.jsonl", std::process::id()));
+        let event = Event {
+            time_ms: 0,
+            agent: "subagent_0".to_string(),
+            step: 0,
+            kind: EventKind::ToolCall,
+            tool: Some("web_fetch".to_string()),
+            text: "{\"url\":\n\"https://example.com\"}".to_string(),
+        };
+        let line = serde_json::to_string(&event)?;
+
+        let mut file = std::fs::File::create(&path)?;
+        let mut events = EventFile::default();
+        write!(file, "{}\n{}", line, &line[..10])?;
+        assert_eq!(events.read_new(&path)?, vec![event.clone()]);
+        assert!(events.read_new(&path)?.is_empty());
+
+        writeln!(file, "{}", &line[10..])?;
+        assert_eq!(events.read_new(&path)?, vec![event.clone()]);
+        std::fs::remove_file(&path)?;
+
+        assert!(
+            render(&event, None)
+                .ends_with("subagent_0           -> web_fetch {\"url\": \"https://example.com\"}")
+        );
+        Ok(())
+    }
+}